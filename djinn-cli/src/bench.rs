@@ -0,0 +1,295 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use djinn_core::{
+    config::default_model_config,
+    lm::{
+        config::{ModelConfig, RunConfig},
+        mistral::create_new_context,
+        model::ModelContext,
+        validate::validate_model_config,
+    },
+};
+use futures::{pin_mut, StreamExt as _};
+use serde::Serialize;
+
+const DEFAULT_BENCH_SAMPLE_LEN: usize = 128;
+const DEFAULT_PROMPTS: &[&str] = &[
+    "Write a short story about a lighthouse keeper.",
+    "Explain how a hash map works to a new programmer.",
+    "fn fibonacci(n: u64) -> u64 {",
+    "List five facts about the moon.",
+];
+
+/// Measure how fast a model loads and generates
+#[derive(Parser, Clone, Debug)]
+pub struct BenchArgs {
+    /// Path to the model config to benchmark
+    #[arg(long, default_value_os_t = default_model_config())]
+    model_config: PathBuf,
+    /// A file with one prompt per line. A built in prompt set is used if none is given
+    #[arg(long)]
+    prompts: Option<PathBuf>,
+    /// The number of tokens to generate for each prompt
+    #[arg(long, short = 'n', default_value_t = DEFAULT_BENCH_SAMPLE_LEN)]
+    sample_len: usize,
+    /// How many times to run each prompt
+    #[arg(long, default_value_t = 1)]
+    repeat: usize,
+    /// The report format
+    #[arg(long, value_enum, default_value_t)]
+    format: ReportFormat,
+    /// Write the report to a file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    model_config: PathBuf,
+    config: ModelConfig,
+    load_ms: u64,
+    /// The peak resident memory of the process, if the platform reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_memory_bytes: Option<u64>,
+    runs: Vec<RunReport>,
+    /// The mean prompt evaluation speed across runs
+    prompt_tokens_per_second: f64,
+    /// The mean generation speed across runs
+    generated_tokens_per_second: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct RunReport {
+    prompt: String,
+    /// Prompt tokens that were run through the model, not counting cached tokens
+    prompt_tokens: usize,
+    generated_tokens: usize,
+    time_to_first_token_ms: u64,
+    total_ms: u64,
+    prompt_tokens_per_second: f64,
+    generated_tokens_per_second: f64,
+}
+
+fn per_second(tokens: usize, duration: Duration) -> f64 {
+    let seconds = duration.as_secs_f64();
+    if seconds > 0. {
+        tokens as f64 / seconds
+    } else {
+        0.
+    }
+}
+
+fn mean(values: impl ExactSizeIterator<Item = f64>) -> f64 {
+    let len = values.len();
+    if len == 0 {
+        0.
+    } else {
+        values.sum::<f64>() / len as f64
+    }
+}
+
+fn read_prompts(path: Option<&Path>) -> anyhow::Result<Vec<String>> {
+    let Some(path) = path else {
+        return Ok(DEFAULT_PROMPTS.iter().map(|s| s.to_string()).collect());
+    };
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("can't read prompts from {path:?}: {error}"))?;
+    let prompts: Vec<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+    if prompts.is_empty() {
+        anyhow::bail!("no prompts in {path:?}");
+    }
+    Ok(prompts)
+}
+
+async fn bench_prompt(
+    context: &mut ModelContext,
+    prompt: &str,
+    sample_len: usize,
+) -> anyhow::Result<RunReport> {
+    let run_config = RunConfig {
+        sample_len,
+        echo_prompt: false,
+        ..Default::default()
+    };
+
+    let start = Instant::now();
+    let mut first_token = None;
+    {
+        let stream = context.run(prompt.to_string(), run_config);
+        pin_mut!(stream);
+        while let Some(token) = stream.next().await {
+            token?;
+            first_token.get_or_insert_with(|| start.elapsed());
+        }
+    }
+    let total = start.elapsed();
+    let first_token = first_token.unwrap_or(total);
+
+    let stats = context.stats();
+    let prompt_tokens = stats.prompt_tokens - stats.cached_tokens;
+    Ok(RunReport {
+        prompt: prompt.to_string(),
+        prompt_tokens,
+        generated_tokens: stats.generated_tokens,
+        time_to_first_token_ms: first_token.as_millis() as u64,
+        total_ms: total.as_millis() as u64,
+        // the first token is sampled from the prompt's logits
+        prompt_tokens_per_second: per_second(prompt_tokens, first_token),
+        generated_tokens_per_second: per_second(
+            stats.generated_tokens.saturating_sub(1),
+            total - first_token,
+        ),
+    })
+}
+
+pub async fn run(args: BenchArgs) -> anyhow::Result<()> {
+    let prompts = read_prompts(args.prompts.as_deref())?;
+    let contents = tokio::fs::read_to_string(&args.model_config).await?;
+    let config = validate_model_config(&contents)?;
+
+    let start = Instant::now();
+    let mut context = create_new_context(&config).await?;
+    let load_time = start.elapsed();
+    tracing::info!(?load_time, "loaded model");
+
+    let mut runs = Vec::with_capacity(prompts.len() * args.repeat);
+    for _ in 0..args.repeat {
+        for prompt in &prompts {
+            let report = bench_prompt(&mut context, prompt, args.sample_len).await?;
+            tracing::info!(
+                prompt_tokens_per_second = report.prompt_tokens_per_second,
+                generated_tokens_per_second = report.generated_tokens_per_second,
+                "finished run {}",
+                runs.len() + 1
+            );
+            runs.push(report);
+        }
+    }
+
+    let report = BenchReport {
+        model_config: args.model_config,
+        config,
+        load_ms: load_time.as_millis() as u64,
+        peak_memory_bytes: peak_memory_bytes(),
+        prompt_tokens_per_second: mean(runs.iter().map(|run| run.prompt_tokens_per_second)),
+        generated_tokens_per_second: mean(runs.iter().map(|run| run.generated_tokens_per_second)),
+        runs,
+    };
+
+    let contents = match args.format {
+        ReportFormat::Markdown => report.markdown(),
+        ReportFormat::Json => serde_json::to_string_pretty(&report)? + "\n",
+    };
+    match args.output {
+        Some(path) => std::fs::write(path, contents)?,
+        None => print!("{contents}"),
+    }
+
+    Ok(())
+}
+
+impl BenchReport {
+    fn markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# {}\n", self.model_config.display());
+        let _ = writeln!(out, "- variant: {:?}", self.config.variant);
+        let _ = writeln!(out, "- device: {:?}", self.config.device);
+        let _ = writeln!(out, "- load time: {} ms", self.load_ms);
+        if let Some(bytes) = self.peak_memory_bytes {
+            let _ = writeln!(
+                out,
+                "- peak memory: {:.1} MiB",
+                bytes as f64 / (1024. * 1024.)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "- prompt eval: {:.1} tokens/s",
+            self.prompt_tokens_per_second
+        );
+        let _ = writeln!(
+            out,
+            "- generation: {:.1} tokens/s\n",
+            self.generated_tokens_per_second
+        );
+
+        let _ = writeln!(
+            out,
+            "| prompt | prompt tokens | generated tokens | first token (ms) | total (ms) | prompt tokens/s | generated tokens/s |"
+        );
+        let _ = writeln!(out, "|---|---:|---:|---:|---:|---:|---:|");
+        for run in &self.runs {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {:.1} | {:.1} |",
+                table_cell(&run.prompt),
+                run.prompt_tokens,
+                run.generated_tokens,
+                run.time_to_first_token_ms,
+                run.total_ms,
+                run.prompt_tokens_per_second,
+                run.generated_tokens_per_second,
+            );
+        }
+        out
+    }
+}
+
+/// Shorten a prompt and escape it for a markdown table
+fn table_cell(prompt: &str) -> String {
+    const MAX_CHARS: usize = 40;
+    let mut cell: String = prompt.chars().take(MAX_CHARS).collect();
+    if prompt.chars().count() > MAX_CHARS {
+        cell.push('…');
+    }
+    cell.replace('|', "\\|")
+}
+
+/// The peak resident set size of this process.
+/// Only reported on Linux
+fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_peak_rss(&status)
+}
+
+fn parse_peak_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_peak_rss() {
+        let status =
+            "Name:\tdjinn\nVmPeak:\t  123456 kB\nVmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\n";
+        assert_eq!(parse_peak_rss(status), Some(2048 * 1024));
+        assert_eq!(parse_peak_rss("Name:\tdjinn\n"), None);
+    }
+}
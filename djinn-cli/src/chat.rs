@@ -0,0 +1,333 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use djinn_client::{ChatRequest, Client};
+use djinn_core::{
+    config::default_model_config,
+    lm::{
+        chat::ChatTemplate,
+        config::{RunConfig, DEFAULT_SAMPLE_LEN},
+        mistral::create_new_context,
+        model::ModelContext,
+        validate::validate_model_config,
+    },
+};
+use futures::{pin_mut, StreamExt as _};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use serde::{Deserialize, Serialize};
+
+const PROMPT: &str = ">>> ";
+const HELP: &str = "\
+/reset           clear the conversation
+/system [prompt] set the system prompt and clear the conversation, or show it
+/save <path>     save the conversation to a TOML file
+/model <model>   switch to another model config, or server model
+/help            show this message
+/quit            leave the chat";
+
+/// Chat with a model in an interactive loop
+#[derive(Parser, Clone, Debug)]
+pub struct ChatArgs {
+    /// Path to the model config to load
+    #[arg(long, default_value_os_t = default_model_config())]
+    model_config: PathBuf,
+    /// Chat through a running djinn server instead of loading a model,
+    /// e.g. `http://[::1]:8080`
+    #[arg(long, alias = "server")]
+    remote: Option<String>,
+    /// The server model to chat with. The server's default model is used if none is given
+    #[arg(long, requires = "remote")]
+    model: Option<String>,
+    /// A system prompt for the conversation
+    #[arg(long)]
+    system: Option<String>,
+    /// The maximum length of each reply (in tokens)
+    #[arg(long, short = 'n', default_value_t = DEFAULT_SAMPLE_LEN)]
+    sample_len: usize,
+}
+
+/// A conversation saved with `/save`
+#[derive(Default, Debug, Serialize, Deserialize)]
+struct Conversation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(default)]
+    turns: Vec<Turn>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Turn {
+    user: String,
+    reply: String,
+}
+
+impl Conversation {
+    /// The prompt for the next reply, formatted with the model's chat template
+    fn prompt(&self, template: &ChatTemplate, message: &str) -> String {
+        let mut prompt = self
+            .system
+            .as_deref()
+            .map(|system| template.system(system))
+            .unwrap_or_default();
+        for turn in &self.turns {
+            prompt += &template.user_turn(&turn.user);
+            prompt += &template.reply(&turn.reply);
+        }
+        prompt + &template.user_turn(message)
+    }
+}
+
+enum Backend {
+    Local {
+        config_path: PathBuf,
+        context: Box<ModelContext>,
+    },
+    Remote {
+        client: Client,
+        model: Option<String>,
+        /// The server session, started by the first message
+        session: Option<String>,
+    },
+}
+
+impl Backend {
+    async fn load(config_path: PathBuf) -> anyhow::Result<Backend> {
+        let context = load_context(&config_path).await?;
+        Ok(Backend::Local {
+            config_path,
+            context: Box::new(context),
+        })
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Backend::Local { config_path, .. } => config_path.display().to_string(),
+            Backend::Remote { client, model, .. } => match model {
+                Some(model) => format!("{model} on {}", client.url()),
+                None => format!("default model on {}", client.url()),
+            },
+        }
+    }
+
+    /// Switch to another model.
+    /// Returns false if the conversation can't be kept
+    async fn switch(&mut self, model: &str) -> anyhow::Result<bool> {
+        match self {
+            Backend::Local {
+                config_path,
+                context,
+            } => {
+                let path = PathBuf::from(model);
+                **context = load_context(&path).await?;
+                *config_path = path;
+                Ok(true)
+            }
+            Backend::Remote { .. } => {
+                // server sessions are bound to a model
+                self.reset().await?;
+                if let Backend::Remote { model: current, .. } = self {
+                    *current = Some(model.to_string());
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Forget the conversation
+    async fn reset(&mut self) -> anyhow::Result<()> {
+        if let Backend::Remote {
+            client, session, ..
+        } = self
+        {
+            if let Some(session) = session.take() {
+                client.end_chat(&session).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a message and print the reply as it is generated
+    async fn send(
+        &mut self,
+        conversation: &Conversation,
+        message: &str,
+        sample_len: usize,
+    ) -> anyhow::Result<String> {
+        let mut stdout = std::io::stdout();
+        let reply = match self {
+            Backend::Local { context, .. } => {
+                let prompt = conversation.prompt(&context.chat_template(), message);
+                let run_config = RunConfig {
+                    sample_len,
+                    echo_prompt: false,
+                    ..Default::default()
+                };
+
+                let stream = context.run(prompt, run_config);
+                pin_mut!(stream);
+
+                let mut reply = String::new();
+                while let Some(token) = stream.next().await {
+                    let token = token?;
+                    stdout.write_all(token.as_bytes())?;
+                    stdout.flush()?;
+                    reply += &token;
+                }
+                reply
+            }
+            Backend::Remote {
+                client,
+                model,
+                session,
+            } => {
+                let mut request = ChatRequest::new(message);
+                request.system = match session {
+                    Some(_) => None,
+                    None => conversation.system.clone(),
+                };
+                request.session = session.clone();
+                request.model = model.clone();
+                request.config.sample_len = sample_len;
+                let response = client.chat(&request).await?;
+                *session = Some(response.session);
+                stdout.write_all(response.reply.as_bytes())?;
+                response.reply
+            }
+        };
+        writeln!(stdout)?;
+        Ok(reply)
+    }
+}
+
+async fn load_context(path: &Path) -> anyhow::Result<ModelContext> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|error| anyhow::anyhow!("can't read model config at {path:?}: {error}"))?;
+    let model_config = validate_model_config(&contents)?;
+    create_new_context(&model_config).await
+}
+
+pub async fn run(args: ChatArgs) -> anyhow::Result<()> {
+    let mut backend = match args.remote {
+        Some(url) => Backend::Remote {
+            client: Client::new(url),
+            model: args.model,
+            session: None,
+        },
+        None => Backend::load(args.model_config).await?,
+    };
+    let mut conversation = Conversation {
+        system: args.system,
+        turns: Vec::new(),
+    };
+
+    let mut editor = DefaultEditor::new()?;
+    println!("chatting with {}, /help for commands", backend.name());
+
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            // ctrl-c clears the line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        let Some(command) = line.strip_prefix('/') else {
+            match backend.send(&conversation, line, args.sample_len).await {
+                Ok(reply) => conversation.turns.push(Turn {
+                    user: line.to_string(),
+                    reply,
+                }),
+                Err(error) => eprintln!("error: {error:#}"),
+            }
+            continue;
+        };
+
+        let (command, argument) = command
+            .split_once(char::is_whitespace)
+            .map(|(command, argument)| (command, argument.trim()))
+            .unwrap_or((command, ""));
+        let result = match command {
+            "reset" => backend.reset().await.map(|()| {
+                conversation.turns.clear();
+                println!("cleared the conversation");
+            }),
+            "system" if argument.is_empty() => {
+                match &conversation.system {
+                    Some(system) => println!("{system}"),
+                    None => println!("no system prompt set"),
+                }
+                Ok(())
+            }
+            "system" => backend.reset().await.map(|()| {
+                conversation.system = Some(argument.to_string());
+                conversation.turns.clear();
+                println!("set the system prompt and cleared the conversation");
+            }),
+            "save" if argument.is_empty() => Err(anyhow::anyhow!("usage: /save <path>")),
+            "save" => save(&conversation, Path::new(argument)),
+            "model" if argument.is_empty() => {
+                println!("{}", backend.name());
+                Ok(())
+            }
+            "model" => backend.switch(argument).await.map(|kept| {
+                if !kept {
+                    conversation.turns.clear();
+                    println!("started a new conversation");
+                }
+                println!("switched to {}", backend.name());
+            }),
+            "help" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            "quit" | "exit" => break,
+            _ => Err(anyhow::anyhow!(
+                "unknown command /{command}, /help for commands"
+            )),
+        };
+        if let Err(error) = result {
+            eprintln!("error: {error:#}");
+        }
+    }
+
+    backend.reset().await
+}
+
+fn save(conversation: &Conversation, path: &Path) -> anyhow::Result<()> {
+    std::fs::write(path, toml::to_string_pretty(conversation)?)?;
+    println!("saved the conversation to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_includes_history() {
+        let conversation = Conversation {
+            system: Some("be brief".to_string()),
+            turns: vec![Turn {
+                user: "hi".to_string(),
+                reply: "hello".to_string(),
+            }],
+        };
+
+        let prompt = conversation.prompt(&ChatTemplate::MISTRAL, "how are you?");
+
+        assert_eq!(
+            prompt,
+            "be brief\n\n[INST] hi [/INST]hello</s>[INST] how are you? [/INST]"
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::{io::Read as _, path::PathBuf};
+
+use clap::{Args, Subcommand};
+use djinn_client::{Client, InfillRequest};
+use djinn_core::{
+    config::default_model_config,
+    lm::{
+        config::RunConfig, mistral::create_new_context, model::RunStats,
+        validate::validate_model_config,
+    },
+};
+use futures::{pin_mut, StreamExt as _};
+
+const DEFAULT_CODE_SAMPLE_LEN: usize = 128;
+/// How much of the file around the cursor is put in the prompt by default
+const DEFAULT_CONTEXT_CHARS: usize = 8192;
+/// Passed to `--file` to read the file's contents from stdin
+const STDIN_FILE: &str = "-";
+
+#[derive(Subcommand)]
+pub enum CodeCommand {
+    /// Complete the code at a position in a file and print only the completion,
+    /// so that an editor can insert it at the cursor
+    Complete(CompleteArgs),
+}
+
+#[derive(Args)]
+pub struct CompleteArgs {
+    /// The file to complete. `-` reads it from stdin, e.g. an editor's unsaved buffer
+    #[arg(long)]
+    file: PathBuf,
+    /// The line of the cursor, starting at 1
+    #[arg(long)]
+    line: usize,
+    /// The column of the cursor in characters, starting at 1.
+    /// Columns past the end of the line are at its end
+    #[arg(long)]
+    col: usize,
+    /// The config of a model that supports fill-in-the-middle, like Starcoder
+    #[arg(long, default_value_os_t = default_model_config())]
+    model_config: PathBuf,
+    /// Complete on a running djinn server instead of loading the model,
+    /// e.g. `http://[::1]:8080`
+    #[arg(long, conflicts_with = "model_config")]
+    remote: Option<String>,
+    /// The server model to use. The server's default model is used if none is given
+    #[arg(long, requires = "remote")]
+    model: Option<String>,
+    /// The most tokens to generate
+    #[arg(long, short = 'n', default_value_t = DEFAULT_CODE_SAMPLE_LEN)]
+    sample_len: usize,
+    /// Stop at the end of the current line
+    #[arg(long)]
+    single_line: bool,
+    /// The most characters of the file before and after the cursor used as context
+    #[arg(long, default_value_t = DEFAULT_CONTEXT_CHARS)]
+    context_chars: usize,
+    /// Print JSON with the completion and the run stats
+    #[arg(long)]
+    json: bool,
+}
+
+impl CompleteArgs {
+    fn read_file(&self) -> anyhow::Result<String> {
+        if self.file.as_os_str() == STDIN_FILE {
+            let mut contents = String::new();
+            std::io::stdin().read_to_string(&mut contents)?;
+            return Ok(contents);
+        }
+        std::fs::read_to_string(&self.file)
+            .map_err(|error| anyhow::anyhow!("can't read {:?}: {error}", self.file))
+    }
+
+    fn run_config(&self) -> RunConfig {
+        RunConfig {
+            sample_len: self.sample_len,
+            seed: None,
+            stop: if self.single_line {
+                vec!["\n".to_string()]
+            } else {
+                Vec::new()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+pub async fn run(command: CodeCommand) -> anyhow::Result<()> {
+    match command {
+        CodeCommand::Complete(args) => complete(args).await,
+    }
+}
+
+async fn complete(args: CompleteArgs) -> anyhow::Result<()> {
+    let contents = args.read_file()?;
+    let (prefix, suffix) = split_at_cursor(&contents, args.line, args.col)?;
+    let prefix = last_chars(prefix, args.context_chars);
+    let suffix = first_chars(suffix, args.context_chars);
+
+    let (completion, stats) = match &args.remote {
+        Some(url) => {
+            let request = InfillRequest {
+                prefix: prefix.to_string(),
+                suffix: suffix.to_string(),
+                model: args.model.clone(),
+                config: args.run_config(),
+            };
+            let response = Client::new(url).infill(&request).await?;
+            (response.output, response.stats)
+        }
+        None => local_infill(&args, prefix, suffix).await?,
+    };
+
+    if args.json {
+        let output = serde_json::json!({
+            "completion": completion,
+            "stats": stats,
+        });
+        println!("{output}");
+    } else {
+        print!("{completion}");
+    }
+    Ok(())
+}
+
+async fn local_infill(
+    args: &CompleteArgs,
+    prefix: &str,
+    suffix: &str,
+) -> anyhow::Result<(String, RunStats)> {
+    let contents = tokio::fs::read_to_string(&args.model_config).await?;
+    let model_config = validate_model_config(&contents)?;
+    let mut model_context = create_new_context(&model_config).await?;
+
+    let mut completion = String::new();
+    {
+        let stream = model_context.infill(prefix, suffix, args.run_config())?;
+        pin_mut!(stream);
+        while let Some(token) = stream.next().await {
+            completion.push_str(&token?);
+        }
+    }
+    Ok((completion, model_context.stats()))
+}
+
+/// The text before and after a 1-based line and column
+fn split_at_cursor(text: &str, line: usize, col: usize) -> anyhow::Result<(&str, &str)> {
+    if line == 0 || col == 0 {
+        anyhow::bail!("lines and columns start at 1");
+    }
+    let mut line_start = 0;
+    for _ in 1..line {
+        match text[line_start..].find('\n') {
+            Some(end) => line_start += end + 1,
+            None => anyhow::bail!("the file has fewer than {line} lines"),
+        }
+    }
+    let line_text = text[line_start..].split('\n').next().unwrap_or_default();
+    let offset = line_text
+        .char_indices()
+        .nth(col - 1)
+        .map_or(line_text.len(), |(offset, _)| offset);
+    Ok(text.split_at(line_start + offset))
+}
+
+/// At most the last `max` characters of `text`
+fn last_chars(text: &str, max: usize) -> &str {
+    let skip = text.chars().count().saturating_sub(max);
+    match text.char_indices().nth(skip) {
+        Some((start, _)) => &text[start..],
+        None => "",
+    }
+}
+
+/// At most the first `max` characters of `text`
+fn first_chars(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "fn main() {\n    println!(\"hi\");\n}\n";
+
+    #[test]
+    fn splits_at_the_cursor() {
+        assert_eq!(split_at_cursor(FILE, 1, 1).unwrap(), ("", FILE));
+        assert_eq!(
+            split_at_cursor(FILE, 2, 5).unwrap(),
+            ("fn main() {\n    ", "println!(\"hi\");\n}\n")
+        );
+        // past the end of the line
+        assert_eq!(
+            split_at_cursor(FILE, 1, 80).unwrap(),
+            ("fn main() {", "\n    println!(\"hi\");\n}\n")
+        );
+        assert_eq!(split_at_cursor(FILE, 4, 1).unwrap(), (FILE, ""));
+        assert!(split_at_cursor(FILE, 5, 1).is_err());
+        assert!(split_at_cursor(FILE, 0, 1).is_err());
+    }
+
+    #[test]
+    fn limits_context() {
+        assert_eq!(last_chars("héllo", 3), "llo");
+        assert_eq!(last_chars("héllo", 10), "héllo");
+        assert_eq!(last_chars("héllo", 0), "");
+        assert_eq!(first_chars("héllo", 2), "hé");
+        assert_eq!(first_chars("héllo", 10), "héllo");
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    io::Write as _,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::{Args, Parser, Subcommand};
+use djinn_core::{
+    config::default_config_dir,
+    lm::{
+        config::{ModelConfig, ModelRun},
+        validate::{validate_model_config, validate_model_run, ConfigErrors},
+    },
+};
+
+use crate::output::OutputArgs;
+
+/// The editor used by `config edit` when `$VISUAL` and `$EDITOR` aren't set
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Run a saved model config, or manage configs with a subcommand
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: Option<ConfigCommand>,
+    #[arg(long, required = true)]
+    model_name: Option<String>,
+    #[arg(long, required = true)]
+    config_name: Option<String>,
+    #[command(flatten)]
+    config_dir: ConfigDir,
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct ConfigDir {
+    #[arg(long, default_value_os_t = default_config_dir())]
+    config_dir: PathBuf,
+}
+
+impl ConfigDir {
+    /// The path of a config named `<model>/<config>`
+    fn path(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let Some((model_name, config_name)) = name.split_once('/') else {
+            anyhow::bail!("config names look like `<model>/<config>`, got {name:?}");
+        };
+        Ok(self.file(model_name, config_name))
+    }
+
+    fn file(&self, model_name: &str, config_name: &str) -> PathBuf {
+        self.config_dir
+            .join(model_name)
+            .join(format!("{config_name}.toml"))
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// List the configs in the config directory by model
+    List {
+        #[command(flatten)]
+        config_dir: ConfigDir,
+    },
+    /// Print a config with all defaults filled in
+    Show {
+        /// The config to show, as `<model>/<config>`
+        name: String,
+        #[command(flatten)]
+        config_dir: ConfigDir,
+    },
+    /// Open a config in `$EDITOR`, and only save it if it's valid
+    Edit {
+        /// The config to edit, as `<model>/<config>`
+        name: String,
+        #[command(flatten)]
+        config_dir: ConfigDir,
+    },
+    /// Check a model run or model config file and report every problem found
+    Validate {
+        /// The TOML file to check
+        path: PathBuf,
+    },
+}
+
+impl ConfigArgs {
+    /// The CPU threads set by the config to run, if it can be read
+    pub fn threads(&self) -> Option<NonZeroUsize> {
+        let (Some(model_name), Some(config_name)) = (&self.model_name, &self.config_name) else {
+            return None;
+        };
+        let contents =
+            std::fs::read_to_string(self.config_dir.file(model_name, config_name)).ok()?;
+        validate_model_run(&contents).ok()?.model_config.threads
+    }
+}
+
+impl TryFrom<ConfigArgs> for ModelRun {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ConfigArgs) -> anyhow::Result<ModelRun> {
+        let (Some(model_name), Some(config_name)) = (value.model_name, value.config_name) else {
+            anyhow::bail!("--model-name and --config-name are required to run a config");
+        };
+        let path = value.config_dir.file(&model_name, &config_name);
+        if !path.exists() {
+            return Err(anyhow::Error::msg(format!(
+                "config does not exist at {path:?}"
+            )));
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let data = validate_model_run(&contents)?;
+
+        Ok(data)
+    }
+}
+
+pub fn run(command: ConfigCommand) -> anyhow::Result<()> {
+    match command {
+        ConfigCommand::List { config_dir } => list(&config_dir.config_dir),
+        ConfigCommand::Show { name, config_dir } => show(&config_dir.path(&name)?),
+        ConfigCommand::Edit { name, config_dir } => edit(&config_dir.path(&name)?),
+        ConfigCommand::Validate { path } => validate(&path),
+    }
+}
+
+/// The kinds of TOML files kept in the config directory
+enum ConfigKind {
+    ModelRun,
+    ModelConfig,
+    Other,
+}
+
+impl ConfigKind {
+    fn detect(contents: &str) -> ConfigKind {
+        let Ok(table) = toml::from_str::<toml::Table>(contents) else {
+            return ConfigKind::Other;
+        };
+        // model runs nest the model config under `model_config`
+        if table.contains_key("model_config") {
+            ConfigKind::ModelRun
+        } else if table.contains_key("variant") {
+            ConfigKind::ModelConfig
+        } else {
+            ConfigKind::Other
+        }
+    }
+
+    fn validate(&self, contents: &str) -> Result<(), ConfigErrors> {
+        match self {
+            ConfigKind::ModelRun => validate_model_run(contents).map(|_run| ()),
+            ConfigKind::ModelConfig | ConfigKind::Other => {
+                validate_model_config(contents).map(|_config| ())
+            }
+        }
+    }
+}
+
+fn list(config_dir: &Path) -> anyhow::Result<()> {
+    let mut models = std::fs::read_dir(config_dir)
+        .map_err(|error| anyhow::anyhow!("can't read config directory {config_dir:?}: {error}"))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    models.sort();
+
+    for model in models {
+        let mut configs = std::fs::read_dir(&model)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .collect::<Vec<_>>();
+        if configs.is_empty() {
+            continue;
+        }
+        configs.sort();
+
+        let model = model.file_name().unwrap_or_default().to_string_lossy();
+        println!("{model}");
+        for config in configs {
+            println!("  {model}/{config}");
+        }
+    }
+
+    Ok(())
+}
+
+fn show(path: &Path) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("can't read config at {path:?}: {error}"))?;
+    // round trip through the config types to fill in defaults
+    let resolved = match ConfigKind::detect(&contents) {
+        ConfigKind::ModelRun => toml::to_string_pretty(&toml::from_str::<ModelRun>(&contents)?)?,
+        ConfigKind::ModelConfig => {
+            toml::to_string_pretty(&toml::from_str::<ModelConfig>(&contents)?)?
+        }
+        ConfigKind::Other => contents,
+    };
+
+    println!("# {}", path.display());
+    print!("{resolved}");
+    Ok(())
+}
+
+fn edit(path: &Path) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| anyhow::anyhow!("can't read config at {path:?}: {error}"))?;
+    let kind = ConfigKind::detect(&contents);
+
+    // edit a copy so the config is left alone if the edit is abandoned
+    let draft = path.with_extension("toml.edit");
+    std::fs::write(&draft, &contents)?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+
+    loop {
+        let status = Command::new(&editor)
+            .arg(&draft)
+            .status()
+            .map_err(|error| {
+                anyhow::anyhow!("can't start editor {editor:?}: {error}, set $EDITOR to change it")
+            })?;
+        if !status.success() {
+            std::fs::remove_file(&draft)?;
+            anyhow::bail!("editor exited with {status}, {path:?} was not changed");
+        }
+
+        let edited = std::fs::read_to_string(&draft)?;
+        match kind.validate(&edited) {
+            Ok(()) => {
+                std::fs::rename(&draft, path)?;
+                println!("saved {}", path.display());
+                return Ok(());
+            }
+            Err(errors) => {
+                eprint!("{errors}");
+                if !confirm("edit again?", true)? {
+                    std::fs::remove_file(&draft)?;
+                    anyhow::bail!("{path:?} was not changed");
+                }
+            }
+        }
+    }
+}
+
+/// Ask a yes or no question on stderr.
+/// An empty answer picks `default`
+pub fn confirm(question: &str, default: bool) -> anyhow::Result<bool> {
+    let options = if default { "[Y/n]" } else { "[y/N]" };
+    eprint!("{question} {options} ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+fn validate(path: &Path) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    match ConfigKind::detect(&contents).validate(&contents) {
+        Ok(()) => {
+            println!("{} is valid", path.display());
+            Ok(())
+        }
+        Err(errors) => Err(anyhow::Error::msg(format!("{}\n{errors}", path.display()))),
+    }
+}
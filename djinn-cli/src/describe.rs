@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use djinn_core::{
+    device::Device,
+    llava::{DescribeOptions, Describer, DescriberConfig, LlavaArchitecture},
+};
+
+#[derive(Parser)]
+pub struct DescribeArgs {
+    /// The image to ask about
+    image: PathBuf,
+    /// The question about the image
+    #[arg(long, default_value = "Describe this image.")]
+    prompt: String,
+    /// The LLaVA model to use
+    #[arg(long, value_enum, default_value_t)]
+    variant: LlavaArchitecture,
+    /// The revision of the model on the Hugging Face Hub
+    #[arg(long, default_value = "main")]
+    revision: String,
+    /// The most tokens to generate
+    #[arg(long, short = 'n', default_value_t = DescribeOptions::default().max_tokens)]
+    max_tokens: usize,
+    /// Sample the answer instead of picking the most likely tokens
+    #[arg(long)]
+    temperature: Option<f64>,
+    #[arg(long, default_value_t)]
+    seed: u64,
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+    /// Print JSON with the answer and the number of generated tokens
+    #[arg(long)]
+    json: bool,
+}
+
+pub async fn run(args: DescribeArgs) -> anyhow::Result<()> {
+    let image = image::open(&args.image)
+        .map_err(|error| anyhow::anyhow!("can't open {:?}: {error}", args.image))?;
+    let config = DescriberConfig {
+        variant: args.variant,
+        device: args.device.unwrap_or_default(),
+        revision: args.revision,
+    };
+    let describer = Describer::from_config(&config).await?;
+    let options = DescribeOptions {
+        max_tokens: args.max_tokens,
+        temperature: args.temperature,
+        seed: args.seed,
+    };
+
+    // inference is CPU or GPU bound, so keep it off of the async runtime
+    let prompt = args.prompt;
+    let description =
+        tokio::task::spawn_blocking(move || describer.describe(&image, &prompt, &options))
+            .await??;
+
+    if args.json {
+        println!("{}", serde_json::to_string(&description)?);
+    } else {
+        println!("{}", description.text);
+    }
+    Ok(())
+}
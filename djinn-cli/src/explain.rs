@@ -0,0 +1,141 @@
+use std::{
+    io::{IsTerminal as _, Read as _, Write as _},
+    path::PathBuf,
+};
+
+use clap::{Parser, ValueEnum};
+use djinn_core::{
+    config::default_model_config,
+    lm::{config::RunConfig, mistral::create_new_context, validate::validate_model_config},
+};
+use futures::{pin_mut, StreamExt as _};
+
+use crate::shell;
+
+const DEFAULT_EXPLAIN_SAMPLE_LEN: usize = 512;
+
+/// A context profile that tailors the system prompt
+/// to the tool that produced the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Agent {
+    Shell,
+    Nix,
+    Git,
+    Cargo,
+}
+
+impl Agent {
+    pub fn system_prompt(&self) -> &'static str {
+        match self {
+            Agent::Shell => include_str!("../../prompts/explain/shell.txt"),
+            Agent::Nix => include_str!("../../prompts/explain/nix.txt"),
+            Agent::Git => include_str!("../../prompts/explain/git.txt"),
+            Agent::Cargo => include_str!("../../prompts/explain/cargo.txt"),
+        }
+    }
+}
+
+/// Explain the output of a previous shell command.
+/// The output of the command is read from stdin, e.g.:
+/// `cargo build 2>&1 | djinn explain cargo --command "cargo build"`
+/// Without piped output the last command captured by `djinn watch-shell` is explained.
+#[derive(Parser, Clone, Debug)]
+pub struct ExplainArgs {
+    /// The context profile used to explain the output
+    #[arg(value_enum)]
+    agent: Agent,
+    /// The command that produced the output
+    #[arg(long)]
+    command: Option<String>,
+    /// Path to the model config used to generate the explanation
+    #[arg(long, default_value_os_t = default_model_config())]
+    model_config: PathBuf,
+    /// The length of the explanation to generate (in tokens).
+    #[arg(long, short = 'n', default_value_t = DEFAULT_EXPLAIN_SAMPLE_LEN)]
+    sample_len: usize,
+}
+
+impl ExplainArgs {
+    fn prompt(&self, output: &str) -> String {
+        let system_prompt = self.agent.system_prompt().trim();
+        let command = self
+            .command
+            .as_deref()
+            .map(|command| format!("Command:\n$ {command}\n\n"))
+            .unwrap_or_default();
+
+        format!("{system_prompt}\n\n{command}Output:\n{output}\n\nExplanation:\n")
+    }
+}
+
+/// The piped output, or the last captured command and its output
+fn read_command_output() -> anyhow::Result<(Option<String>, String)> {
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        let Some(capture) = shell::last_capture()? else {
+            anyhow::bail!(
+                "no command output to explain. pipe the output of a command into `explain` \
+                 or capture commands with `djinn watch-shell install`"
+            );
+        };
+        let output = format!("{}\n(exit code {})", capture.output, capture.exit_code);
+        return Ok((Some(capture.command), output));
+    }
+
+    let mut output = String::new();
+    stdin.read_to_string(&mut output)?;
+
+    Ok((None, output))
+}
+
+pub async fn run(mut args: ExplainArgs) -> anyhow::Result<()> {
+    let (command, output) = read_command_output()?;
+    if args.command.is_none() {
+        args.command = command;
+    }
+    let prompt = args.prompt(&output);
+
+    let contents = tokio::fs::read_to_string(&args.model_config).await?;
+    let model_config = validate_model_config(&contents)?;
+    let mut model_context = create_new_context(&model_config).await?;
+
+    let run_config = RunConfig {
+        sample_len: args.sample_len,
+        echo_prompt: false,
+        ..Default::default()
+    };
+
+    let stream = model_context.run(prompt, run_config);
+    pin_mut!(stream);
+
+    let mut stdout = std::io::stdout();
+    while let Some(token) = stream.next().await {
+        stdout.write_all(token?.as_bytes())?;
+        stdout.flush()?;
+    }
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_includes_command_and_output() {
+        let args = ExplainArgs {
+            agent: Agent::Cargo,
+            command: Some("cargo build".to_string()),
+            model_config: default_model_config(),
+            sample_len: DEFAULT_EXPLAIN_SAMPLE_LEN,
+        };
+
+        let prompt = args.prompt("error[E0425]: cannot find value `x` in this scope");
+
+        assert!(prompt.starts_with(Agent::Cargo.system_prompt().trim()));
+        assert!(prompt.contains("$ cargo build"));
+        assert!(prompt.contains("error[E0425]"));
+        assert!(prompt.ends_with("Explanation:\n"));
+    }
+}
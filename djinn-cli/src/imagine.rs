@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use djinn_core::{
+    device::Device,
+    diffusion::{
+        DiffusionArchitecture, DiffusionScheduler, ImageGenerator, ImageGeneratorConfig,
+        ImageOptions,
+    },
+};
+
+#[derive(Parser)]
+pub struct ImagineArgs {
+    /// What to draw
+    prompt: String,
+    /// What the image shouldn't look like
+    #[arg(long, default_value = "")]
+    negative_prompt: String,
+    /// Where to write the PNG
+    #[arg(long, short, default_value = "imagine.png")]
+    output: PathBuf,
+    /// The Stable Diffusion version to use
+    #[arg(long, value_enum, default_value_t)]
+    variant: DiffusionArchitecture,
+    /// The revision of the model on the Hugging Face Hub
+    #[arg(long, default_value = "main")]
+    revision: String,
+    #[arg(long, value_enum, default_value_t)]
+    scheduler: DiffusionScheduler,
+    /// More steps take longer and add detail
+    #[arg(long, default_value_t = ImageOptions::default().steps)]
+    steps: usize,
+    /// How closely the image follows the prompt. Guidance is off at 1 or less
+    #[arg(long, default_value_t = ImageOptions::default().guidance_scale)]
+    guidance_scale: f64,
+    /// A multiple of 8
+    #[arg(long, default_value_t = ImageOptions::default().width)]
+    width: usize,
+    /// A multiple of 8
+    #[arg(long, default_value_t = ImageOptions::default().height)]
+    height: usize,
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Load the half precision weights, which halves memory use on GPUs
+    #[arg(long)]
+    f16: bool,
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+}
+
+pub async fn run(args: ImagineArgs) -> anyhow::Result<()> {
+    let options = ImageOptions {
+        scheduler: args.scheduler,
+        steps: args.steps,
+        guidance_scale: args.guidance_scale,
+        width: args.width,
+        height: args.height,
+        seed: args.seed,
+    };
+    let config = ImageGeneratorConfig {
+        variant: args.variant,
+        device: args.device.unwrap_or_default(),
+        revision: args.revision,
+        f16: args.f16,
+        defaults: options.clone(),
+    };
+    let generator = ImageGenerator::from_config(&config).await?;
+
+    // generation is CPU or GPU bound, so keep it off of the async runtime
+    let (prompt, negative_prompt) = (args.prompt, args.negative_prompt);
+    let image = tokio::task::spawn_blocking(move || {
+        generator.generate(&prompt, &negative_prompt, &options)
+    })
+    .await??;
+
+    image.save(&args.output)?;
+    println!("{}", args.output.display());
+    Ok(())
+}
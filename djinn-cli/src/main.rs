@@ -4,23 +4,58 @@ use std::{
     fmt::{Debug, Display},
     fs::File,
     io::Write,
+    num::NonZeroUsize,
     path::PathBuf,
     sync::Arc,
 };
 
+use bench::BenchArgs;
+use chat::ChatArgs;
 use clap::{Parser, Subcommand, ValueEnum};
+use code::CodeCommand;
+use config::ConfigArgs;
+use describe::DescribeArgs;
+use djinn_client::{Client, CompleteRequest, StreamEvent};
 use djinn_core::{
-    config::DEFAULT_CONFIG_DIR,
+    config::{default_config_dir, migrate_legacy_configs, LEGACY_CONFIG_DIR},
+    device::set_cpu_threads,
     lm::config::ModelRun,
-    lm::mistral::{run, run_model},
+    lm::mistral::{run_beam_search, run_model},
 };
+use djinn_server::{SetLogFilter, Watch};
+use explain::ExplainArgs;
+use futures::{pin_mut, StreamExt as _};
+use imagine::ImagineArgs;
+use models::ModelsCommand;
+use output::{OutputArgs, TokenWriter};
+use search::{IndexArgs, SearchArgs};
 use server::ServerArgs;
+use shell::WatchShellCommand;
+use tokens::TokensCommand;
 use tracing::Instrument;
 use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+use transcribe::TranscribeArgs;
+use yolo::YoloArgs;
 
+mod bench;
+mod chat;
+mod code;
+mod config;
+mod describe;
+mod explain;
+mod imagine;
 mod mistral;
+mod models;
+mod output;
+mod search;
 mod server;
+mod shell;
+mod tokens;
+mod transcribe;
+mod yolo;
 
 const DEFAULT_LOG_ENV: &str = "warn,djinn_server=debug,djinn_core=debug,axum=debug,axum::rejection=trace,candle_core=info,tower_http=debug";
 
@@ -29,6 +64,10 @@ struct Cli {
     /// Enable tracing (generates a trace-timestamp.json file).
     #[arg(long, default_value_t)]
     pub tracing: TracingArgs,
+    /// The number of threads the CPU kernels use.
+    /// Overrides the `threads` of the model configs, which default to the number of CPUs.
+    #[arg(long, global = true)]
+    pub threads: Option<NonZeroUsize>,
     #[command(subcommand)]
     runner: Runner,
 }
@@ -41,33 +80,72 @@ enum Runner {
     ServerConfig {
         #[arg(long)]
         name: Arc<str>,
-        #[arg(long, default_value = DEFAULT_CONFIG_DIR)]
+        #[arg(long, default_value_os_t = default_config_dir())]
         config_dir: PathBuf,
     },
     SingleRun(SingleRunArgs),
     Config(ConfigArgs),
+    /// Chat with a model in an interactive loop
+    Chat(ChatArgs),
+    /// Benchmark loading and generation speed of a model config
+    Bench(BenchArgs),
+    /// Manage downloaded models
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Count, encode, and decode tokens with a model's tokenizer,
+    /// e.g. to check that a prompt fits the context
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
+    },
+    /// Complete code with a fill-in-the-middle model like Starcoder
+    Code {
+        #[command(subcommand)]
+        command: CodeCommand,
+    },
+    /// Explain the output of a shell command piped into stdin,
+    /// or of the last command captured with `watch-shell`
+    Explain(ExplainArgs),
+    /// Capture shell commands and their output for `explain` and the chat TUI
+    WatchShell {
+        #[command(subcommand)]
+        command: WatchShellCommand,
+    },
+    /// Run YOLOv8 object detection or pose estimation on images
+    Yolo(YoloArgs),
+    /// Transcribe or translate speech in an audio file with Whisper
+    Transcribe(TranscribeArgs),
+    /// Chunk and embed text files into an index for `search`
+    Index(IndexArgs),
+    /// Find the chunks of an index most similar to a query
+    Search(SearchArgs),
+    /// Generate an image of a prompt with Stable Diffusion
+    Imagine(ImagineArgs),
+    /// Answer a question about an image with LLaVA
+    Describe(DescribeArgs),
 }
 
 #[derive(Parser)]
 struct SingleRunArgs {
     /// Pass the name of the config to save
-    #[arg(long)]
+    #[arg(long, conflicts_with = "remote")]
     save_config: Option<String>,
+    /// Run the prompt on a running djinn server instead of loading the model,
+    /// e.g. `http://[::1]:8080`. Only the prompt and sampling flags are used
+    #[arg(long)]
+    remote: Option<String>,
+    /// The server model to run. The server's default model is used if none is given
+    #[arg(long, requires = "remote")]
+    model: Option<String>,
+    #[command(flatten)]
+    output: OutputArgs,
     /// The model architecture used
     #[command(subcommand)]
     architecture: Architecture,
 }
 
-#[derive(Parser)]
-struct ConfigArgs {
-    #[arg(long)]
-    model_name: String,
-    #[arg(long)]
-    config_name: String,
-    #[arg(long, default_value=PathBuf::from(DEFAULT_CONFIG_DIR).into_os_string())]
-    config_dir: PathBuf,
-}
-
 #[derive(Subcommand)]
 enum Architecture {
     Mistral(mistral::Args),
@@ -76,12 +154,27 @@ enum Architecture {
 async fn single_run(args: SingleRunArgs) -> anyhow::Result<()> {
     let save_config = args.save_config.clone();
     let run: ModelRun = match args.architecture {
-        Architecture::Mistral(mistral_args) => run(mistral_args.try_into()?).await?,
+        Architecture::Mistral(mistral_args) => mistral_args.try_into()?,
     };
+    let writer = args.output.writer()?;
+    match args.remote {
+        Some(url) => {
+            let request = CompleteRequest {
+                prompt: run.prompt.clone(),
+                model: args.model,
+                config: run.run_config.clone(),
+            };
+            remote_run(&Client::new(url), &request, writer).await?
+        }
+        None => local_run(&run, writer).await?,
+    }
 
     if let Some(name) = save_config {
         let contents = toml::to_string(&run)?;
-        let path = PathBuf::from(format!("./configs/mistral/{name}.toml"));
+        let path = default_config_dir().join(format!("mistral/{name}.toml"));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
         let mut file = File::create(path)?;
         let _ = file.write_all(contents.as_bytes());
     }
@@ -89,25 +182,36 @@ async fn single_run(args: SingleRunArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
-impl TryFrom<ConfigArgs> for ModelRun {
-    type Error = anyhow::Error;
-
-    fn try_from(value: ConfigArgs) -> anyhow::Result<ModelRun> {
-        let path = value
-            .config_dir
-            .join(value.model_name)
-            .join(format!("{}.toml", value.config_name));
-        if !path.exists() {
-            return Err(anyhow::Error::msg(format!(
-                "config does not exist at {path:?}"
-            )));
-        }
-
-        let contents = std::fs::read_to_string(path)?;
-        let data: ModelRun = toml::from_str(&contents)?;
+/// Load the model and write its completion to `writer`
+async fn local_run(run: &ModelRun, mut writer: TokenWriter) -> anyhow::Result<()> {
+    if run.run_config.num_beams().is_some() {
+        let (candidates, stats) = run_beam_search(run).await?;
+        return writer.candidates(&candidates, stats);
+    }
+    let stats = run_model(run, |token| writer.token(token)).await?;
+    writer.finish(stats)
+}
 
-        Ok(data)
+/// Stream a completion from a server into `writer`.
+/// Beam search results can't be streamed, so they're requested all at once
+async fn remote_run(
+    client: &Client,
+    request: &CompleteRequest,
+    mut writer: TokenWriter,
+) -> anyhow::Result<()> {
+    if request.config.num_beams().is_some() {
+        let response = client.complete(request).await?;
+        return writer.candidates(&response.candidates, response.stats);
+    }
+    let stream = client.complete_stream(request).await?;
+    pin_mut!(stream);
+    while let Some(event) = stream.next().await {
+        match event? {
+            StreamEvent::Token(token) => writer.token(&token)?,
+            StreamEvent::End(end) => return writer.finish(end.stats),
+        }
     }
+    anyhow::bail!("the server ended the stream before the run finished")
 }
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -128,49 +232,131 @@ impl Display for TracingArgs {
     }
 }
 
-fn setup_tracing(tracing_args: TracingArgs) -> anyhow::Result<Option<FlushGuard>> {
+/// Keeps tracing running and changes its filter
+#[derive(Default)]
+struct Tracing {
+    _guard: Option<FlushGuard>,
+    filter: Option<reload::Handle<EnvFilter, Registry>>,
+}
+
+impl Tracing {
+    /// Lets a server change the log filter when its config changes
+    fn set_log_filter(&self) -> Option<SetLogFilter> {
+        let handle = self.filter.clone()?;
+        Some(Box::new(move |filter| {
+            handle.reload(EnvFilter::try_new(filter)?)?;
+            Ok(())
+        }))
+    }
+}
+
+fn setup_tracing(tracing_args: TracingArgs) -> anyhow::Result<Tracing> {
     match tracing_args {
         TracingArgs::Chrome => {
             let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
             tracing_subscriber::registry().with(chrome_layer).init();
-            Ok(Some(guard))
+            Ok(Tracing {
+                _guard: Some(guard),
+                filter: None,
+            })
         }
         TracingArgs::Stdout => {
+            let (filter, handle) = reload::Layer::new(
+                tracing_subscriber::EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| DEFAULT_LOG_ENV.into()),
+            );
             tracing_subscriber::registry()
-                .with(
-                    tracing_subscriber::EnvFilter::try_from_default_env()
-                        .unwrap_or_else(|_| DEFAULT_LOG_ENV.into()),
-                )
+                .with(filter)
                 .with(tracing_subscriber::fmt::layer().pretty())
                 .with(EnvFilter::from_default_env())
                 .init();
 
             tracing::info!("tracing started");
 
-            Ok(None)
+            Ok(Tracing {
+                _guard: None,
+                filter: Some(handle),
+            })
         }
-        TracingArgs::None => Ok(None),
+        TracingArgs::None => Ok(Tracing::default()),
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
-    let _guard = setup_tracing(args.tracing)?;
+    // the environment is only safe to write before the runtime starts its threads
+    if let Some(threads) = args.threads.or_else(|| config_threads(&args.runner)) {
+        set_cpu_threads(threads.get());
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args))
+}
+
+/// The CPU threads set by the model configs the command loads.
+/// Errors are left for the command to report when it reads the configs
+fn config_threads(runner: &Runner) -> Option<NonZeroUsize> {
+    let config = match runner {
+        Runner::Server(args) => djinn_server::Config::try_from(args.clone()).ok()?,
+        Runner::ServerConfig { name, config_dir } => {
+            let contents = std::fs::read_to_string(server::config_path(config_dir, name)).ok()?;
+            toml::from_str(&contents).ok()?
+        }
+        Runner::Config(args) => return args.threads(),
+        _ => return None,
+    };
+    djinn_server::cpu_threads(&config)
+}
+
+async fn run(args: Cli) -> anyhow::Result<()> {
+    let logging = setup_tracing(args.tracing)?;
+    if let Err(error) = migrate_legacy_configs() {
+        tracing::warn!(%error, "unable to copy configs from {LEGACY_CONFIG_DIR}");
+    }
+    // servers report downloads in their logs
+    djinn_core::hub::set_progress_bars(!matches!(
+        args.runner,
+        Runner::Server(_) | Runner::ServerConfig { .. }
+    ));
     match args.runner {
         Runner::Server(args) => server::run(args).await,
         Runner::ServerConfig { name, config_dir } => {
-            let filename = format!("server/{name}.toml");
-            let path = config_dir.join(filename);
-            let config = server::load_config(path).await?;
+            let path = server::config_path(&config_dir, &name);
+            let config = server::load_config(&path).await?;
             let span = tracing::info_span!("run_server span");
-            djinn_server::run_server(config).instrument(span).await
+            let watch = Watch {
+                path,
+                set_log_filter: logging.set_log_filter(),
+            };
+            djinn_server::run_server_watching(config, watch)
+                .instrument(span)
+                .await
         }
         Runner::SingleRun(args) => single_run(args).await,
+        Runner::Config(ConfigArgs {
+            command: Some(command),
+            ..
+        }) => config::run(command),
         Runner::Config(args) => {
+            let writer = args.output.writer()?;
             let config: ModelRun = args.try_into()?;
             //TODO only Mistral is supported for now
-            run_model(config).await
+            local_run(&config, writer).await
         }
+        Runner::Chat(args) => chat::run(args).await,
+        Runner::Bench(args) => bench::run(args).await,
+        Runner::Models { command } => models::run(command).await,
+        Runner::Tokens { command } => tokens::run(command).await,
+        Runner::Code { command } => code::run(command).await,
+        Runner::Explain(args) => explain::run(args).await,
+        Runner::WatchShell { command } => shell::run(command),
+        Runner::Yolo(args) => yolo::run(args).await,
+        Runner::Transcribe(args) => transcribe::run(args).await,
+        Runner::Index(args) => search::index(args).await,
+        Runner::Search(args) => search::search(args).await,
+        Runner::Imagine(args) => imagine::run(args).await,
+        Runner::Describe(args) => describe::run(args).await,
     }
 }
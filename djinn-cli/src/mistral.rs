@@ -1,20 +1,38 @@
+use std::io::Read as _;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
 use clap::Parser;
-use djinn_core::device::Device;
+use djinn_core::device::{Device, DeviceSpec};
 use djinn_core::lm::config::RunConfig;
 use djinn_core::lm::config::{
-    ModelConfig, ModelRun, DEFAULT_REPEAT_LAST_N, DEFAULT_REPEAT_PENALTY, DEFAULT_SAMPLE_LEN,
-    DEFAULT_SEED, DEFAULT_TEMPERATURE,
+    ContextOverflow, ModelConfig, ModelRun, OutputFormat, WeightDType, WeightLoading,
+    DEFAULT_ECHO_PROMPT, DEFAULT_REPEAT_LAST_N, DEFAULT_REPEAT_PENALTY, DEFAULT_SAMPLE_LEN,
+    DEFAULT_SEED, DEFAULT_SPECULATIVE, DEFAULT_TEMPERATURE,
 };
 use djinn_core::lm::model::ModelArchitecture;
+use djinn_core::lm::prefix_cache::DEFAULT_MAX_CACHED_TOKENS;
 use djinn_core::lm::ModelSource;
 
+const DEFAULT_REVISION: &str = "main";
+/// Passed to `--prompt` to read the prompt from stdin
+const STDIN_PROMPT: &str = "-";
+
 #[derive(Parser, Clone)]
 pub struct Args {
-    /// Run on CPU rather than on GPU.
-    #[arg(long, value_enum)]
+    /// The device to run on. `auto` picks CUDA, then Metal, then the CPU.
+    #[arg(long, value_enum, default_value_t)]
     device: Device,
-    #[arg(long)]
-    prompt: String,
+    /// Split the model across several devices, e.g. `cuda:0,cuda:1`.
+    /// Overrides `--device`.
+    #[arg(long, value_delimiter = ',')]
+    devices: Vec<DeviceSpec>,
+    /// The prompt to complete. `-` reads the prompt from stdin
+    #[arg(long, required_unless_present = "prompt_file")]
+    prompt: Option<String>,
+    /// Read the prompt from a file
+    #[arg(long, conflicts_with = "prompt")]
+    prompt_file: Option<PathBuf>,
     #[arg(long)]
     model_id: Option<String>,
     /// Penalty to be applied for repeating tokens, 1. means no penalty.
@@ -37,24 +55,92 @@ pub struct Args {
     /// Nucleus sampling probability cutoff.
     #[arg(long)]
     top_p: Option<f64>,
+    /// Only sample from the k most likely tokens.
+    #[arg(long)]
+    top_k: Option<usize>,
+    /// Minimum probability of a token relative to the most likely token.
+    #[arg(long)]
+    min_p: Option<f64>,
+    /// Locally typical sampling probability mass.
+    #[arg(long)]
+    typical_p: Option<f64>,
+    /// Stop generating when this string is generated. Can be repeated.
+    #[arg(long)]
+    stop: Vec<String>,
+    /// How to handle prompts that don't fit in the model's context.
+    #[arg(long, value_enum, default_value_t)]
+    context_overflow: ContextOverflow,
+    /// Constrain the output to a format, e.g. `json`.
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+    /// Constrain the output to a GBNF grammar file
+    #[arg(long, conflicts_with = "format")]
+    grammar_file: Option<PathBuf>,
+    /// Search this many beams instead of sampling
+    #[arg(long)]
+    num_beams: Option<usize>,
+    /// Print this many completions found by beam search, most likely first
+    #[arg(long = "n")]
+    n_best: Option<usize>,
     /// Only compatible with [`Device::Cuda`]
     #[arg(long)]
     use_flash_attn: bool,
+    /// Memory map the weights or read them into memory up front
+    #[arg(long, value_enum, default_value_t)]
+    weight_loading: WeightLoading,
+    /// Convert the weights to this type instead of the device's default
+    #[arg(long, value_enum)]
+    dtype: Option<WeightDType>,
     #[arg(value_enum)]
     variant: ModelArchitecture,
+    /// Merge a LoRA adapter directory (PEFT format) into the weights.
+    #[arg(long)]
+    adapter: Option<PathBuf>,
 
+    /// Load the tokenizer from a local file rather than the HuggingFace Hub
     #[arg(long)]
-    tokenizer_file: Option<String>,
+    tokenizer_file: Option<PathBuf>,
+    /// Load the weights from local safetensors files or a GGUF file
+    #[arg(long, num_args = 1..)]
+    weight_files: Vec<PathBuf>,
+    /// The model's `config.json` when loading from local files
     #[arg(long)]
-    weight_files: Option<String>,
+    config_file: Option<PathBuf>,
+}
+
+impl Args {
+    fn read_prompt(&self) -> anyhow::Result<String> {
+        match (&self.prompt, &self.prompt_file) {
+            (Some(prompt), _) if prompt == STDIN_PROMPT => {
+                let mut prompt = String::new();
+                std::io::stdin().read_to_string(&mut prompt)?;
+                Ok(prompt)
+            }
+            (Some(prompt), _) => Ok(prompt.clone()),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map_err(|error| anyhow!("can't read prompt file {path:?}: {error}")),
+            (None, None) => Err(anyhow!("either --prompt or --prompt-file is required")),
+        }
+    }
+
+    fn read_grammar(&self) -> anyhow::Result<Option<String>> {
+        self.grammar_file
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .map_err(|error| anyhow!("can't read grammar file {path:?}: {error}"))
+            })
+            .transpose()
+    }
 }
 
 impl TryFrom<Args> for ModelRun {
     type Error = anyhow::Error;
 
     fn try_from(args: Args) -> Result<Self, Self::Error> {
-        let prompt = args.prompt.to_owned();
-        let run_config: RunConfig = args.clone().into();
+        let prompt = args.read_prompt()?;
+        let mut run_config: RunConfig = args.clone().into();
+        run_config.grammar = args.read_grammar()?;
         let model_config: ModelConfig = args.try_into()?;
 
         Ok(ModelRun {
@@ -71,18 +157,39 @@ impl From<Args> for RunConfig {
             seed,
             temperature,
             top_p,
+            top_k,
+            min_p,
+            typical_p,
+            stop,
+            context_overflow,
             sample_len,
             repeat_penalty,
             repeat_last_n,
+            format,
+            num_beams,
+            n_best,
             ..
         } = value;
         RunConfig {
-            seed,
+            seed: Some(seed),
             temperature,
             top_p,
+            top_k,
+            min_p,
+            typical_p,
+            stop,
+            context_overflow,
             sample_len,
             repeat_penalty,
             repeat_last_n,
+            echo_prompt: DEFAULT_ECHO_PROMPT,
+            format,
+            grammar: None,
+            speculative: DEFAULT_SPECULATIVE,
+            logprobs: None,
+            deterministic: false,
+            num_beams,
+            n_best,
         }
     }
 }
@@ -93,29 +200,47 @@ impl TryFrom<Args> for ModelConfig {
         let Args {
             variant,
             device,
+            devices,
             use_flash_attn,
             revision,
             weight_files,
             tokenizer_file,
+            config_file,
+            adapter,
+            weight_loading,
+            dtype,
             ..
         } = value;
 
-        let model_source = if let Some(revision) = revision {
-            ModelSource::HuggingFaceHub { revision }
-        } else if let Some((weight_files, tokenizer_file)) = weight_files.zip(tokenizer_file) {
-            ModelSource::Files {
-                weight_files: vec![weight_files.into()],
-                tokenizer_file: tokenizer_file.into(),
+        let model_source = match (revision, tokenizer_file) {
+            (Some(revision), _) => ModelSource::HuggingFaceHub { revision },
+            (None, Some(tokenizer_file)) if !weight_files.is_empty() => ModelSource::Files {
+                weight_files,
+                tokenizer_file,
+                config_file,
+            },
+            (None, None) if weight_files.is_empty() => ModelSource::HuggingFaceHub {
+                revision: DEFAULT_REVISION.to_string(),
+            },
+            _ => {
+                return Err(anyhow!(
+                    "both --weight-files and --tokenizer-file are required to load local files"
+                ))
             }
-        } else {
-            panic!("unable to find files");
         };
 
         Ok(ModelConfig {
             variant,
             device,
+            devices,
             flash_attn: use_flash_attn,
+            prefix_cache_tokens: DEFAULT_MAX_CACHED_TOKENS,
             model_source,
+            adapter,
+            draft: None,
+            threads: None,
+            weight_loading,
+            dtype,
         })
     }
 }
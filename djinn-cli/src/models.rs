@@ -0,0 +1,446 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use clap::{Subcommand, ValueEnum as _};
+use djinn_core::{
+    config::default_config_dir,
+    hub::{
+        cache::{cache_dir, cached_repo, cached_repos, remove_repo, remove_revision, CachedRepo},
+        verify_weights, HubRepo,
+    },
+    lm::{
+        config::{ModelConfig, ModelRun},
+        model::ModelArchitecture,
+        ModelSource,
+    },
+};
+use djinn_gguf::{Header, Value};
+
+use crate::config::confirm;
+
+const DEFAULT_REVISION: &str = "main";
+
+/// File extensions that are downloaded when pulling a repo by ID
+const MODEL_FILE_EXTENSIONS: &[&str] = &["safetensors", "gguf", "json", "model"];
+
+#[derive(Subcommand)]
+pub enum ModelsCommand {
+    /// Download a model into the HuggingFace cache and verify its weights.
+    /// Files that are already cached are skipped,
+    /// so an interrupted pull picks up where it stopped.
+    Pull {
+        /// A model variant, e.g. `mistral`,
+        /// or a HuggingFace repo ID, e.g. `mistralai/Mistral-7B-v0.1`
+        repo: String,
+        #[arg(long, default_value = DEFAULT_REVISION)]
+        revision: String,
+    },
+    /// List cached models and their revisions,
+    /// and the local weight files used by model configs
+    List {
+        #[arg(long, default_value_os_t = default_config_dir())]
+        config_dir: PathBuf,
+    },
+    /// Remove a cached model, or some of its revisions
+    Rm {
+        /// A model variant or HuggingFace repo ID
+        repo: String,
+        /// Only remove this revision, a branch name or commit hash
+        #[arg(long, conflicts_with = "detached")]
+        revision: Option<String>,
+        /// Only remove revisions that no branch points to anymore
+        #[arg(long)]
+        detached: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Print the cache directory of a model revision
+    Path {
+        /// A model variant or HuggingFace repo ID
+        repo: String,
+        #[arg(long, default_value = DEFAULT_REVISION)]
+        revision: String,
+    },
+    /// Show how much disk space models use, largest first
+    Du {
+        #[arg(long, default_value_os_t = default_config_dir())]
+        config_dir: PathBuf,
+    },
+    /// Show the architecture, quantization, context length,
+    /// and tokenizer of a GGUF file without loading its weights
+    Inspect {
+        file: PathBuf,
+        /// Print every metadata key instead of a summary
+        #[arg(long)]
+        all: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+pub async fn run(command: ModelsCommand) -> anyhow::Result<()> {
+    match command {
+        ModelsCommand::Pull { repo, revision } => pull(&repo, &revision).await,
+        ModelsCommand::List { config_dir } => list(&config_dir),
+        ModelsCommand::Rm {
+            repo,
+            revision,
+            detached,
+            yes,
+        } => rm(&repo, revision.as_deref(), detached, yes),
+        ModelsCommand::Path { repo, revision } => path(&repo, &revision),
+        ModelsCommand::Du { config_dir } => du(&config_dir),
+        ModelsCommand::Inspect { file, all, json } => inspect(&file, all, json),
+    }
+}
+
+/// Resolve a model variant name to its repo ID
+fn repo_id(repo: &str) -> String {
+    match ModelArchitecture::from_str(repo, true) {
+        Ok(variant) => variant.hf_repo_id(),
+        Err(_) => repo.to_string(),
+    }
+}
+
+async fn pull(repo: &str, revision: &str) -> anyhow::Result<()> {
+    let files = match ModelArchitecture::from_str(repo, true) {
+        Ok(variant) => pull_variant(variant, revision).await?,
+        Err(_) => pull_repo(repo, revision).await?,
+    };
+
+    for file in &files {
+        verify_weights(file)?;
+    }
+    println!("pulled {} files", files.len());
+    Ok(())
+}
+
+/// Download the files needed to load a model variant
+async fn pull_variant(variant: ModelArchitecture, revision: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let repo = HubRepo::model(variant.hf_repo_id(), revision)?;
+    println!("pulling {variant:?} from {}", repo.id());
+
+    let mut files = variant.hf_files(&repo).await?;
+    files.push(repo.get("tokenizer.json").await?);
+    if variant.needs_config_file() {
+        files.push(repo.get("config.json").await?);
+    }
+    Ok(files)
+}
+
+/// Download the weights, tokenizer and config files in a repo
+async fn pull_repo(repo_id: &str, revision: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let repo = HubRepo::model(repo_id, revision)?;
+    println!("pulling {}", repo.id());
+
+    let mut files = Vec::new();
+    for filename in repo.files().await? {
+        let is_model_file = filename
+            .rsplit_once('.')
+            .is_some_and(|(_name, extension)| MODEL_FILE_EXTENSIONS.contains(&extension));
+        if is_model_file {
+            files.push(repo.get(&filename).await?);
+        }
+    }
+    if files.is_empty() {
+        anyhow::bail!("{repo_id} has no model files");
+    }
+    Ok(files)
+}
+
+fn find_repo(repo: &str) -> anyhow::Result<CachedRepo> {
+    let repo_id = repo_id(repo);
+    cached_repo(&cache_dir(), &repo_id)?
+        .ok_or_else(|| anyhow::anyhow!("{repo_id} is not in the cache at {:?}", cache_dir()))
+}
+
+fn list(config_dir: &Path) -> anyhow::Result<()> {
+    let cache_dir = cache_dir();
+    println!("{}", cache_dir.display());
+    for repo in cached_repos(&cache_dir)? {
+        println!("  {} {}", repo.id, format_bytes(repo.size));
+        for revision in &repo.revisions {
+            let refs = if revision.refs.is_empty() {
+                "detached".to_string()
+            } else {
+                revision.refs.join(", ")
+            };
+            println!(
+                "    {} ({refs}) {} files, {}",
+                short_commit(&revision.commit),
+                revision.files.len(),
+                format_bytes(revision.size)
+            );
+        }
+    }
+
+    let local = local_weights(config_dir)?;
+    if !local.is_empty() {
+        println!("{}", config_dir.display());
+        for (config, files) in local {
+            println!("  {}", config.display());
+            for file in files {
+                match std::fs::metadata(&file) {
+                    Ok(metadata) => {
+                        println!("    {} {}", file.display(), format_bytes(metadata.len()))
+                    }
+                    Err(_) => println!("    {} (missing)", file.display()),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rm(repo: &str, revision: Option<&str>, detached: bool, yes: bool) -> anyhow::Result<()> {
+    let repo = find_repo(repo)?;
+
+    let revisions: Vec<_> = match revision {
+        Some(name) => vec![repo
+            .revision(name)
+            .ok_or_else(|| anyhow::anyhow!("{} has no cached revision {name}", repo.id))?],
+        None if detached => repo.detached_revisions().collect(),
+        None => Vec::new(),
+    };
+
+    let freed = if revision.is_none() && !detached {
+        let question = format!("remove {} ({})?", repo.id, format_bytes(repo.size));
+        if !yes && !confirm(&question, false)? {
+            return Ok(());
+        }
+        remove_repo(&repo)?
+    } else {
+        if revisions.is_empty() {
+            println!("{} has no detached revisions", repo.id);
+            return Ok(());
+        }
+        let commits: Vec<_> = revisions
+            .iter()
+            .map(|revision| short_commit(&revision.commit))
+            .collect();
+        let question = format!("remove {} revisions {}?", repo.id, commits.join(", "));
+        if !yes && !confirm(&question, false)? {
+            return Ok(());
+        }
+        let mut freed = 0;
+        for revision in revisions {
+            freed += remove_revision(&repo, revision)?;
+        }
+        freed
+    };
+
+    println!("freed {}", format_bytes(freed));
+    Ok(())
+}
+
+fn path(repo: &str, revision: &str) -> anyhow::Result<()> {
+    let repo = find_repo(repo)?;
+    let revision = repo
+        .revision(revision)
+        .ok_or_else(|| anyhow::anyhow!("{} has no cached revision {revision}", repo.id))?;
+    println!("{}", revision.path.display());
+    Ok(())
+}
+
+fn du(config_dir: &Path) -> anyhow::Result<()> {
+    let mut usage: Vec<(String, u64)> = cached_repos(&cache_dir())?
+        .into_iter()
+        .map(|repo| (repo.id, repo.size))
+        .collect();
+    for (config, files) in local_weights(config_dir)? {
+        let size = files
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        usage.push((config.display().to_string(), size));
+    }
+    usage.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let total: u64 = usage.iter().map(|(_name, size)| size).sum();
+    for (name, size) in usage {
+        println!("{:>10}  {name}", format_bytes(size));
+    }
+    println!("{:>10}  total", format_bytes(total));
+    Ok(())
+}
+
+fn inspect(file: &Path, all: bool, json: bool) -> anyhow::Result<()> {
+    let header = Header::open(file).with_context(|| format!("unable to read {file:?}"))?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&inspect_json(&header, all))?
+        );
+        return Ok(());
+    }
+
+    let unknown = || "unknown".to_string();
+    println!("{}", file.display());
+    println!("  GGUF version    {}", header.version);
+    if let Some(name) = header.name() {
+        println!("  name            {name}");
+    }
+    if let Some(file_type) = header.file_type() {
+        println!("  type            {file_type}");
+    }
+    println!(
+        "  architecture    {}",
+        header.architecture().map_or_else(unknown, str::to_string)
+    );
+    println!(
+        "  quantization    {}",
+        header.quantization().unwrap_or_else(unknown)
+    );
+    println!("  parameters      {}", format_count(header.parameters()));
+    println!("  tensors         {}", header.tensors.len());
+    println!(
+        "  context length  {}",
+        header
+            .context_length()
+            .map_or_else(unknown, |len| len.to_string())
+    );
+    match header.tokenizer() {
+        Some(tokenizer) => {
+            println!("  tokenizer");
+            println!(
+                "    model         {}",
+                tokenizer.model.unwrap_or_else(unknown)
+            );
+            let id = |id: Option<u64>| id.map_or_else(unknown, |id| id.to_string());
+            println!("    tokens        {}", id(tokenizer.tokens));
+            println!("    bos token id  {}", id(tokenizer.bos_token_id));
+            println!("    eos token id  {}", id(tokenizer.eos_token_id));
+        }
+        None => println!("  tokenizer       none"),
+    }
+
+    if all {
+        println!("  metadata");
+        for (key, value) in &header.metadata {
+            println!("    {key} = {value}");
+        }
+    }
+    Ok(())
+}
+
+fn inspect_json(header: &Header, all: bool) -> serde_json::Value {
+    let tokenizer = header.tokenizer().map(|tokenizer| {
+        serde_json::json!({
+            "model": tokenizer.model,
+            "tokens": tokenizer.tokens,
+            "bos_token_id": tokenizer.bos_token_id,
+            "eos_token_id": tokenizer.eos_token_id,
+        })
+    });
+    let mut report = serde_json::json!({
+        "version": header.version,
+        "name": header.name(),
+        "type": header.file_type(),
+        "architecture": header.architecture(),
+        "quantization": header.quantization(),
+        "parameters": header.parameters(),
+        "tensors": header.tensors.len(),
+        "context_length": header.context_length(),
+        "tokenizer": tokenizer,
+    });
+    if all {
+        let metadata: serde_json::Map<String, serde_json::Value> = header
+            .metadata
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value.clone().into(),
+                    Value::Bool(value) => (*value).into(),
+                    value => value.to_string().into(),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+        report["metadata"] = metadata.into();
+    }
+    report
+}
+
+/// The local weight files of each model config under `config_dir`
+fn local_weights(config_dir: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+    let mut configs = Vec::new();
+    let mut dirs = vec![config_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !path.extension().is_some_and(|ext| ext == "toml") {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path)?;
+            let model_config = toml::from_str::<ModelConfig>(&contents)
+                .or_else(|_| toml::from_str::<ModelRun>(&contents).map(|run| run.model_config));
+            if let Ok(ModelConfig {
+                model_source: ModelSource::Files { weight_files, .. },
+                ..
+            }) = model_config
+            {
+                configs.push((path, weight_files));
+            }
+        }
+    }
+    configs.sort();
+    Ok(configs)
+}
+
+fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(8)]
+}
+
+/// A parameter count like `7.2B`
+fn format_count(count: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[(1_000_000_000, "B"), (1_000_000, "M"), (1_000, "K")];
+    UNITS
+        .iter()
+        .find(|(size, _unit)| count >= *size)
+        .map(|(size, unit)| format!("{:.1}{unit}", count as f64 / *size as f64))
+        .unwrap_or_else(|| count.to_string())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(14 * 1024 * 1024 * 1024), "14.0 GiB");
+    }
+
+    #[test]
+    fn formats_counts() {
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(7_241_732_096), "7.2B");
+        assert_eq!(format_count(135_000_000), "135.0M");
+    }
+}
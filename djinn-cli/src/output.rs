@@ -0,0 +1,165 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use clap::Args;
+use djinn_core::lm::{beam::Candidate, model::RunStats};
+use serde::Serialize;
+
+/// Where and how generated text is written
+#[derive(Args, Clone, Debug, Default)]
+pub struct OutputArgs {
+    /// Write the output to a file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Write a JSON event per line for each token, with timings, and one when the run is done
+    #[arg(long, conflicts_with = "quiet")]
+    json: bool,
+    /// Only write the final text when generation is done
+    #[arg(long)]
+    quiet: bool,
+}
+
+impl OutputArgs {
+    pub fn writer(&self) -> anyhow::Result<TokenWriter> {
+        let out: Box<dyn Write> = match &self.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path).map_err(|error| {
+                anyhow::anyhow!("can't create output file {path:?}: {error}")
+            })?)),
+            None => Box::new(std::io::stdout()),
+        };
+        let format = if self.json {
+            Format::Json
+        } else if self.quiet {
+            Format::Quiet
+        } else {
+            Format::Text
+        };
+
+        Ok(TokenWriter {
+            out,
+            format,
+            start: Instant::now(),
+            text: String::new(),
+            tokens: 0,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Stream tokens as they are generated
+    Text,
+    /// JSON lines of [`Event`]s
+    Json,
+    /// Only the final text
+    Quiet,
+}
+
+/// Structured output for `--json`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Token {
+        index: usize,
+        text: &'a str,
+        /// Milliseconds since the run started
+        elapsed_ms: u128,
+    },
+    Done {
+        text: &'a str,
+        elapsed_ms: u128,
+        #[serde(flatten)]
+        stats: RunStats,
+        /// The completions of a beam search, best first
+        #[serde(skip_serializing_if = "<[Candidate]>::is_empty")]
+        candidates: &'a [Candidate],
+    },
+}
+
+/// Writes tokens as they are generated
+pub struct TokenWriter {
+    out: Box<dyn Write>,
+    format: Format,
+    start: Instant,
+    text: String,
+    tokens: usize,
+}
+
+impl TokenWriter {
+    pub fn token(&mut self, token: &str) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => {
+                self.out.write_all(token.as_bytes())?;
+                self.out.flush()?;
+            }
+            Format::Json => {
+                let event = Event::Token {
+                    index: self.tokens,
+                    text: token,
+                    elapsed_ms: self.start.elapsed().as_millis(),
+                };
+                self.write_event(&event)?;
+            }
+            Format::Quiet => {}
+        }
+        self.text.push_str(token);
+        self.tokens += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self, stats: RunStats) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.out)?,
+            Format::Json => {
+                let event = Event::Done {
+                    text: &self.text,
+                    elapsed_ms: self.start.elapsed().as_millis(),
+                    stats,
+                    candidates: &[],
+                };
+                let line = serde_json::to_string(&event)?;
+                writeln!(self.out, "{line}")?;
+            }
+            Format::Quiet => writeln!(self.out, "{}", self.text)?,
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Write the completions of a beam search, which all arrive at once
+    pub fn candidates(mut self, candidates: &[Candidate], stats: RunStats) -> anyhow::Result<()> {
+        match self.format {
+            Format::Text | Format::Quiet => {
+                for (rank, candidate) in candidates.iter().enumerate() {
+                    if rank > 0 {
+                        writeln!(self.out)?;
+                    }
+                    writeln!(self.out, "[{}] score {:.3}", rank + 1, candidate.score)?;
+                    writeln!(self.out, "{}", candidate.text)?;
+                }
+            }
+            Format::Json => {
+                let event = Event::Done {
+                    text: candidates.first().map_or("", |best| best.text.as_str()),
+                    elapsed_ms: self.start.elapsed().as_millis(),
+                    stats,
+                    candidates,
+                };
+                self.write_event(&event)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.out, event)?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use djinn_core::{
+    device::Device,
+    embed::{EmbeddingArchitecture, EmbeddingConfig, EmbeddingContext},
+    lm::ModelSource,
+    search::{SearchHit, SearchIndex, DEFAULT_CHUNK_CHARS},
+};
+
+const DEFAULT_TOP_K: usize = 5;
+/// How much of a hit's text is shown in the table
+const SNIPPET_CHARS: usize = 80;
+
+#[derive(Parser)]
+pub struct IndexArgs {
+    /// Text files to index. Directories are indexed recursively
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// The index file to write.
+    /// If it exists, the files are added to it, replacing their old chunks
+    #[arg(long)]
+    index: PathBuf,
+    /// The embedding model of a new index
+    #[arg(long, value_enum)]
+    variant: Option<EmbeddingArchitecture>,
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+    /// About how many characters each chunk has
+    #[arg(long, default_value_t = DEFAULT_CHUNK_CHARS)]
+    chunk_chars: usize,
+}
+
+#[derive(Parser)]
+pub struct SearchArgs {
+    /// The text to search for
+    query: String,
+    /// An index built with `djinn index`
+    #[arg(long)]
+    index: PathBuf,
+    /// How many chunks to print
+    #[arg(long, short = 'k', default_value_t = DEFAULT_TOP_K)]
+    top_k: usize,
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+    /// Print the hits as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+pub async fn index(args: IndexArgs) -> anyhow::Result<()> {
+    let mut index = if args.index.exists() {
+        let index = SearchIndex::load(&args.index)?;
+        if let Some(variant) = args.variant.filter(|&v| v != index.embedder.variant) {
+            anyhow::bail!(
+                "{:?} was built with {:?}, not {variant:?}",
+                args.index,
+                index.embedder.variant
+            );
+        }
+        index
+    } else {
+        SearchIndex::new(EmbeddingConfig {
+            variant: args.variant.unwrap_or_default(),
+            device: Device::default(),
+            model_source: ModelSource::HuggingFaceHub {
+                revision: "main".to_string(),
+            },
+            normalize: true,
+        })
+    };
+    let embedder = load_embedder(&index, args.device).await?;
+
+    let mut files = Vec::new();
+    for path in &args.paths {
+        collect_files(path, &mut files)?;
+    }
+    for file in &files {
+        let Ok(text) = std::fs::read_to_string(file) else {
+            tracing::warn!(?file, "skipping file that isn't UTF-8 text");
+            continue;
+        };
+        let chunks = index.add(&embedder, file, &text, args.chunk_chars)?;
+        println!("{} chunks from {}", chunks, file.display());
+    }
+
+    index.save(&args.index)?;
+    println!("{} chunks in {}", index.chunks.len(), args.index.display());
+    Ok(())
+}
+
+pub async fn search(args: SearchArgs) -> anyhow::Result<()> {
+    let index = SearchIndex::load(&args.index)?;
+    let embedder = load_embedder(&index, args.device).await?;
+    let query = embedder.embed(&args.query)?;
+    let hits = index.search(&query.vector, args.top_k);
+
+    if args.json {
+        println!("{}", serde_json::to_string(&hits)?);
+    } else {
+        print_table(&hits);
+    }
+    Ok(())
+}
+
+/// Load the model the index was built with, on the given device
+async fn load_embedder(
+    index: &SearchIndex,
+    device: Option<Device>,
+) -> anyhow::Result<EmbeddingContext> {
+    let mut config = index.embedder.clone();
+    config.device = device.unwrap_or(config.device);
+    EmbeddingContext::from_config(&config).await
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn print_table(hits: &[SearchHit]) {
+    let locations: Vec<String> = hits
+        .iter()
+        .map(|hit| {
+            format!(
+                "{}:{}-{}",
+                hit.source.display(),
+                hit.span.start_line,
+                hit.span.end_line
+            )
+        })
+        .collect();
+    let width = locations.iter().map(String::len).max().unwrap_or_default();
+    for (hit, location) in hits.iter().zip(&locations) {
+        println!(
+            "{:.3}  {location:width$}  {}",
+            hit.score,
+            snippet(&hit.text)
+        );
+    }
+}
+
+/// The start of a text on one line
+fn snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippets_fit_on_a_line() {
+        assert_eq!(
+            snippet("fn main() {\n    run();\n}\n"),
+            "fn main() { run(); }"
+        );
+        let long = "word ".repeat(40);
+        assert_eq!(snippet(&long).chars().count(), SNIPPET_CHARS + 3);
+    }
+}
@@ -4,7 +4,7 @@ use std::{
 };
 
 use djinn_core::{
-    config::DEFAULT_CONFIG_DIR,
+    config::default_config_dir,
     lm::config::ModelRun,
     lm::{mistral::create_new_context, model::ModelContext},
 };
@@ -24,7 +24,7 @@ pub struct ServerArgs {
     #[arg(long, default_value_t = DEFAULT_HOST_PORT)]
     port: u16,
     /// Where server configs are stored
-    #[arg(long, default_value = DEFAULT_CONFIG_DIR)]
+    #[arg(long, default_value_os_t = default_config_dir())]
     config_dir: PathBuf,
     /// An optional name of this config to save to [`ServerArgs::config_dir`]
     #[arg(long)]
@@ -38,7 +38,7 @@ impl Default for ServerArgs {
         Self {
             ip: DEFAULT_HOST_ADDR.to_string(),
             port: DEFAULT_HOST_PORT,
-            config_dir: PathBuf::from(DEFAULT_CONFIG_DIR),
+            config_dir: default_config_dir(),
             save_config: None,
             model_config: DEFAULT_MODEL_CONFIG.to_string(),
         }
@@ -52,12 +52,13 @@ impl TryFrom<ServerArgs> for Config {
         let ServerArgs {
             ip,
             port,
+            config_dir,
             model_config,
             ..
         } = value;
 
         let filename = format!("{model_config}.toml");
-        let path = PathBuf::from("./configs/").join(filename);
+        let path = config_dir.join(filename);
 
         let address = IpAddr::parse_ascii(ip.as_bytes())?;
         let full_address = SocketAddr::new(address, port);
@@ -93,8 +94,7 @@ async fn save_config(args: ServerArgs) -> anyhow::Result<()> {
         std::fs::create_dir_all(&args.config_dir)?;
     }
     let name = args.save_config.clone().expect("no config name given!");
-    let filename = format!("server/{name}.toml");
-    let path = args.config_dir.join(filename);
+    let path = config_path(&args.config_dir, &name);
 
     let config: Config = args.clone().try_into()?;
 
@@ -104,6 +104,11 @@ async fn save_config(args: ServerArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The path of the server config named `name`
+pub fn config_path(config_dir: &Path, name: &str) -> PathBuf {
+    config_dir.join(format!("server/{name}.toml"))
+}
+
 pub async fn load_config(path: impl AsRef<Path>) -> anyhow::Result<Config> {
     let path = path.as_ref();
     tracing::info!(?path, "loading config");
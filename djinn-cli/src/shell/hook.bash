@@ -0,0 +1,41 @@
+# djinn watch-shell: save each command, its exit code and its output
+# for `djinn explain` and the chat TUI. Needs bash 5.1 or newer.
+# Output is copied with tee, so commands in this list keep the terminal to themselves.
+__djinn_no_capture=(vim nvim vi nano less more man ssh top htop tmux screen fzf)
+__djinn_output="${TMPDIR:-/tmp}/djinn-shell-$$.out"
+
+__djinn_preexec() {
+  # the DEBUG trap runs before every simple command, only the first one after the prompt is new
+  [[ -z "$__djinn_at_prompt" || -n "$COMP_LINE" ]] && return
+  unset __djinn_at_prompt
+  __djinn_command="$(HISTTIMEFORMAT= builtin history 1 | sed 's/^ *[0-9]* *//')"
+  : >| "$__djinn_output"
+  local program="${__djinn_command%% *}"
+  local ignored
+  for ignored in "${__djinn_no_capture[@]}"; do
+    [[ "$program" == "$ignored" ]] && return
+  done
+  exec {__djinn_stdout}>&1 {__djinn_stderr}>&2
+  exec > >(tee -- "$__djinn_output") 2>&1
+  __djinn_tee=$!
+}
+
+__djinn_precmd() {
+  local exit_code=$?
+  if [[ -n "$__djinn_tee" ]]; then
+    exec 1>&$__djinn_stdout 2>&$__djinn_stderr {__djinn_stdout}>&- {__djinn_stderr}>&-
+    wait "$__djinn_tee" 2>/dev/null
+    unset __djinn_tee
+  fi
+  if [[ -n "$__djinn_command" ]]; then
+    __DJINN_WATCH_SHELL__ record --exit-code "$exit_code" --output-file "$__djinn_output" -- "$__djinn_command"
+    unset __djinn_command
+  fi
+}
+
+trap '__djinn_preexec' DEBUG
+# first to see the command's exit code, the flag is set last
+# so the rest of PROMPT_COMMAND isn't mistaken for a command
+PROMPT_COMMAND="__djinn_precmd
+${PROMPT_COMMAND:+$PROMPT_COMMAND
+}__djinn_at_prompt=1"
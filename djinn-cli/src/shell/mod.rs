@@ -0,0 +1,281 @@
+//! Capture shell commands with a hook so `explain` and the chat TUI
+//! can use the last command and its output as context.
+//!
+//! Captures are kept in a JSON lines file that's cut down to the newest
+//! few commands each time one is recorded.
+//! The ollama-cli TUI reads the same file.
+
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand, ValueEnum};
+use djinn_dirs::Dirs;
+use serde::{Deserialize, Serialize};
+
+/// Overrides where captures are saved
+const CAPTURES_PATH_VAR: &str = "DJINN_SHELL_CAPTURES";
+/// In the state directory
+const CAPTURES_FILE: &str = "shell.jsonl";
+const DEFAULT_KEEP: usize = 20;
+/// Only the end of long output is kept, that's where errors usually are
+const MAX_OUTPUT_BYTES: usize = 32 * 1024;
+/// Replaced with the command that records a capture
+const HOOK_PLACEHOLDER: &str = "__DJINN_WATCH_SHELL__";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+}
+
+impl Shell {
+    fn hook_template(&self) -> &'static str {
+        match self {
+            Shell::Bash => include_str!("hook.bash"),
+            Shell::Zsh => include_str!("hook.zsh"),
+        }
+    }
+
+    fn rc_file(&self) -> anyhow::Result<PathBuf> {
+        let home = std::env::var_os("HOME").ok_or_else(|| anyhow::anyhow!("$HOME isn't set"))?;
+        let name = match self {
+            Shell::Bash => ".bashrc",
+            Shell::Zsh => ".zshrc",
+        };
+        Ok(PathBuf::from(home).join(name))
+    }
+
+    /// The hook with the path of this executable filled in
+    fn hook(&self) -> anyhow::Result<String> {
+        let exe = std::env::current_exe()?;
+        let command = format!("{} watch-shell", quote(&exe.to_string_lossy()));
+        Ok(self.hook_template().replace(HOOK_PLACEHOLDER, &command))
+    }
+
+    /// The line that loads the hook in the rc file
+    fn source_line(&self) -> anyhow::Result<String> {
+        let exe = std::env::current_exe()?;
+        let shell = match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+        };
+        Ok(format!(
+            "eval \"$({} watch-shell hook {shell})\"",
+            quote(&exe.to_string_lossy())
+        ))
+    }
+}
+
+/// Quote a word for POSIX shells
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[derive(Subcommand)]
+pub enum WatchShellCommand {
+    /// Print the hook for a shell, e.g. `eval "$(djinn watch-shell hook zsh)"`
+    Hook {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Load the hook from the shell's rc file
+    Install {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Save a command and its output. Called by the hook
+    Record(RecordArgs),
+    /// Print the newest captured commands
+    Show {
+        #[arg(long, short = 'n', default_value_t = 1)]
+        count: usize,
+    },
+}
+
+#[derive(Args)]
+pub struct RecordArgs {
+    #[arg(long)]
+    exit_code: i32,
+    /// A file with the output of the command
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+    /// The number of commands to keep
+    #[arg(long, default_value_t = DEFAULT_KEEP)]
+    keep: usize,
+    /// The command as it was typed
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+/// A command run in a shell with the hook
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    pub command: String,
+    pub exit_code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// The end of the output, stdout and stderr together
+    #[serde(default)]
+    pub output: String,
+}
+
+impl Capture {
+    pub fn failed(&self) -> bool {
+        self.exit_code != 0
+    }
+}
+
+/// The file the captures are saved in
+pub fn captures_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(CAPTURES_PATH_VAR) {
+        return Ok(path.into());
+    }
+    Ok(Dirs::djinn()?.state().join(CAPTURES_FILE))
+}
+
+/// Captures oldest first, skipping lines that can't be parsed
+pub fn load_captures(path: &Path) -> anyhow::Result<Vec<Capture>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The newest captured command
+pub fn last_capture() -> anyhow::Result<Option<Capture>> {
+    Ok(load_captures(&captures_path()?)?.pop())
+}
+
+/// Add a capture and keep only the newest `keep`
+fn push_capture(path: &Path, capture: Capture, keep: usize) -> anyhow::Result<()> {
+    let mut captures = load_captures(path)?;
+    captures.push(capture);
+    let start = captures.len().saturating_sub(keep);
+
+    let mut contents = String::new();
+    for capture in &captures[start..] {
+        contents += &serde_json::to_string(capture)?;
+        contents.push('\n');
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// The last `max_bytes` of `output`, cut at a character boundary
+fn tail(output: &str, max_bytes: usize) -> &str {
+    let mut start = output.len().saturating_sub(max_bytes);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    &output[start..]
+}
+
+fn record(args: RecordArgs) -> anyhow::Result<()> {
+    let output = match &args.output_file {
+        Some(path) => {
+            let bytes = std::fs::read(path).unwrap_or_default();
+            // the next command reuses the file
+            let _ = std::fs::remove_file(path);
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        None => String::new(),
+    };
+    let capture = Capture {
+        command: args.command.join(" "),
+        exit_code: args.exit_code,
+        cwd: std::env::current_dir().ok(),
+        output: tail(output.trim_end(), MAX_OUTPUT_BYTES).to_string(),
+    };
+    push_capture(&captures_path()?, capture, args.keep)
+}
+
+fn install(shell: Shell) -> anyhow::Result<()> {
+    let rc_file = shell.rc_file()?;
+    let line = shell.source_line()?;
+    let contents = std::fs::read_to_string(&rc_file).unwrap_or_default();
+    if contents.contains("watch-shell hook") {
+        println!("the hook is already loaded in {}", rc_file.display());
+        return Ok(());
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&rc_file)?;
+    writeln!(file, "\n# capture commands for djinn\n{line}")?;
+    println!(
+        "added the hook to {}, it's loaded in new shells",
+        rc_file.display()
+    );
+    Ok(())
+}
+
+fn show(count: usize) -> anyhow::Result<()> {
+    let captures = load_captures(&captures_path()?)?;
+    let start = captures.len().saturating_sub(count);
+    for capture in &captures[start..] {
+        println!("$ {} (exit code {})", capture.command, capture.exit_code);
+        if !capture.output.is_empty() {
+            println!("{}", capture.output);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(command: WatchShellCommand) -> anyhow::Result<()> {
+    match command {
+        WatchShellCommand::Hook { shell } => {
+            print!("{}", shell.hook()?);
+            Ok(())
+        }
+        WatchShellCommand::Install { shell } => install(shell),
+        WatchShellCommand::Record(args) => record(args),
+        WatchShellCommand::Show { count } => show(count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_are_a_ring_buffer() {
+        let dir = std::env::temp_dir().join(format!("djinn-shell-test-{}", std::process::id()));
+        let path = dir.join("shell.jsonl");
+        let capture = |exit_code| Capture {
+            command: "cargo build".to_string(),
+            exit_code,
+            cwd: None,
+            output: String::new(),
+        };
+
+        for exit_code in 0..5 {
+            push_capture(&path, capture(exit_code), 3).unwrap();
+        }
+        let captures = load_captures(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(captures, vec![capture(2), capture(3), capture(4)]);
+    }
+
+    #[test]
+    fn output_keeps_the_end() {
+        assert_eq!(tail("héllo", 4), "llo");
+        assert_eq!(tail("hi", 4), "hi");
+    }
+
+    #[test]
+    fn quotes_survive_the_shell() {
+        assert_eq!(quote("/it's/djinn"), r"'/it'\''s/djinn'");
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    io::{IsTerminal as _, Read as _},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand};
+use djinn_client::{Client, TokenizeRequest};
+use djinn_core::{
+    config::default_model_config,
+    lm::{
+        tokens::{decode, encode, load_tokenizer, Tokenizer},
+        validate::validate_model_config,
+    },
+};
+use serde::Serialize;
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// Print how many tokens a prompt uses
+    Count {
+        #[command(flatten)]
+        input: TextInput,
+        #[command(flatten)]
+        tokenizer: TokenizerArgs,
+    },
+    /// Print the token IDs of a prompt
+    Encode {
+        #[command(flatten)]
+        input: TextInput,
+        #[command(flatten)]
+        tokenizer: TokenizerArgs,
+    },
+    /// Print the text of token IDs
+    Decode {
+        /// Token IDs separated by spaces or commas.
+        /// They're read from stdin if none are given
+        #[arg(value_delimiter = ',')]
+        ids: Vec<u32>,
+        /// The model config whose tokenizer is used
+        #[arg(long, default_value_os_t = default_model_config())]
+        model_config: PathBuf,
+        /// Leave out special tokens like BOS
+        #[arg(long)]
+        skip_special_tokens: bool,
+    },
+}
+
+/// The text to tokenize
+#[derive(Args)]
+pub struct TextInput {
+    /// The text is read from stdin if none is given
+    text: Option<String>,
+    /// Read the text from a file
+    #[arg(long, conflicts_with = "text")]
+    file: Option<PathBuf>,
+}
+
+impl TextInput {
+    fn read(&self) -> anyhow::Result<String> {
+        match (&self.text, &self.file) {
+            (Some(text), _) => Ok(text.clone()),
+            (None, Some(path)) => Ok(std::fs::read_to_string(path)?),
+            (None, None) => read_stdin(),
+        }
+    }
+}
+
+/// Which tokenizer to use
+#[derive(Args)]
+pub struct TokenizerArgs {
+    /// The model config whose tokenizer is used.
+    /// Only the tokenizer is loaded, not the weights
+    #[arg(long, default_value_os_t = default_model_config())]
+    model_config: PathBuf,
+    /// Use the tokenizer of a model on a running djinn server,
+    /// e.g. `http://[::1]:8080`, which also reports its context length
+    #[arg(long, conflicts_with = "model_config")]
+    remote: Option<String>,
+    /// The server model to use. The server's default model is used if none is given
+    #[arg(long, requires = "remote")]
+    model: Option<String>,
+    /// Print JSON with the token IDs, their count, and the context length if it's known
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Tokens {
+    tokens: Vec<u32>,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_context_len: Option<usize>,
+}
+
+impl TokenizerArgs {
+    async fn tokenize(&self, text: String) -> anyhow::Result<Tokens> {
+        match &self.remote {
+            Some(url) => {
+                let request = TokenizeRequest {
+                    text,
+                    model: self.model.clone(),
+                };
+                let response = Client::new(url).tokenize(&request).await?;
+                Ok(Tokens {
+                    tokens: response.tokens,
+                    count: response.count,
+                    max_context_len: Some(response.max_context_len),
+                })
+            }
+            None => {
+                let tokenizer = tokenizer(&self.model_config).await?;
+                let tokens = encode(&tokenizer, &text)?;
+                Ok(Tokens {
+                    count: tokens.len(),
+                    tokens,
+                    max_context_len: None,
+                })
+            }
+        }
+    }
+}
+
+pub async fn run(command: TokensCommand) -> anyhow::Result<()> {
+    match command {
+        TokensCommand::Count { input, tokenizer } => {
+            let tokens = tokenizer.tokenize(input.read()?).await?;
+            if tokenizer.json {
+                println!("{}", serde_json::to_string(&tokens)?);
+                return Ok(());
+            }
+            match tokens.max_context_len {
+                Some(max_context_len) => println!("{} / {max_context_len}", tokens.count),
+                None => println!("{}", tokens.count),
+            }
+        }
+        TokensCommand::Encode { input, tokenizer } => {
+            let tokens = tokenizer.tokenize(input.read()?).await?;
+            if tokenizer.json {
+                println!("{}", serde_json::to_string(&tokens)?);
+                return Ok(());
+            }
+            let ids: Vec<String> = tokens.tokens.iter().map(u32::to_string).collect();
+            println!("{}", ids.join(" "));
+        }
+        TokensCommand::Decode {
+            ids,
+            model_config,
+            skip_special_tokens,
+        } => {
+            let ids = if ids.is_empty() {
+                parse_ids(&read_stdin()?)?
+            } else {
+                ids
+            };
+            let tokenizer = tokenizer(&model_config).await?;
+            println!("{}", decode(&tokenizer, &ids, skip_special_tokens)?);
+        }
+    }
+    Ok(())
+}
+
+async fn tokenizer(model_config: &Path) -> anyhow::Result<Tokenizer> {
+    let contents = tokio::fs::read_to_string(model_config).await?;
+    load_tokenizer(&validate_model_config(&contents)?).await
+}
+
+fn read_stdin() -> anyhow::Result<String> {
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        anyhow::bail!("nothing to read, pass the input as an argument or pipe it into stdin");
+    }
+    let mut input = String::new();
+    stdin.read_to_string(&mut input)?;
+    Ok(input)
+}
+
+/// Token IDs separated by whitespace or commas, e.g. the output of `encode`
+fn parse_ids(text: &str) -> anyhow::Result<Vec<u32>> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| anyhow::anyhow!("{id:?} is not a token ID"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_encode_output() {
+        assert_eq!(parse_ids("1 415 2936\n").unwrap(), vec![1, 415, 2936]);
+        assert_eq!(parse_ids("1,415, 2936").unwrap(), vec![1, 415, 2936]);
+        assert!(parse_ids("1 fox").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use djinn_core::{
+    device::Device,
+    lm::ModelSource,
+    whisper::{
+        read_audio, TranscribeOptions, TranscriberConfig, TranscriptionContext, WhisperArchitecture,
+    },
+};
+
+#[derive(Parser)]
+pub struct TranscribeArgs {
+    /// The audio file to transcribe, e.g. a WAV, MP3, or FLAC file
+    audio: PathBuf,
+    /// The Whisper model to use
+    #[arg(long, value_enum, default_value_t)]
+    variant: WhisperArchitecture,
+    /// The revision of the model on the Hugging Face Hub
+    #[arg(long, default_value = "main")]
+    revision: String,
+    /// The spoken language as a code like `en` or `de`.
+    /// Multilingual models detect it if it isn't given
+    #[arg(long)]
+    language: Option<String>,
+    /// Translate the speech to English instead of transcribing it
+    #[arg(long)]
+    translate: bool,
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+    /// Print the start and end time of each 30 second segment
+    #[arg(long, conflicts_with = "json")]
+    timestamps: bool,
+    /// Print JSON with the text, the language, and the segments
+    #[arg(long)]
+    json: bool,
+}
+
+pub async fn run(args: TranscribeArgs) -> anyhow::Result<()> {
+    let config = TranscriberConfig {
+        variant: args.variant,
+        device: args.device.unwrap_or_default(),
+        model_source: ModelSource::HuggingFaceHub {
+            revision: args.revision,
+        },
+    };
+    let mut context = TranscriptionContext::from_config(&config).await?;
+    let options = TranscribeOptions {
+        language: args.language,
+        translate: args.translate,
+    };
+    context.check_options(&options)?;
+
+    // decoding and inference are CPU bound, so keep them off of the async runtime
+    let audio = args.audio;
+    let transcription = tokio::task::spawn_blocking(move || {
+        let samples = read_audio(&audio)?;
+        context.transcribe(&samples, &options)
+    })
+    .await??;
+
+    if args.json {
+        println!("{}", serde_json::to_string(&transcription)?);
+    } else if args.timestamps {
+        for segment in &transcription.segments {
+            println!(
+                "[{} --> {}] {}",
+                format_time(segment.start),
+                format_time(segment.end),
+                segment.text
+            );
+        }
+    } else {
+        println!("{}", transcription.text);
+    }
+    Ok(())
+}
+
+/// Seconds as `mm:ss.mmm`, or `hh:mm:ss.mmm` past an hour
+fn format_time(secs: f64) -> String {
+    let millis = (secs * 1000.).round() as u64;
+    let (hours, minutes) = (millis / 3_600_000, millis / 60_000 % 60);
+    let (seconds, millis) = (millis / 1000 % 60, millis % 1000);
+    if hours > 0 {
+        format!("{hours:02}:{minutes:02}:{seconds:02}.{millis:03}")
+    } else {
+        format!("{minutes:02}:{seconds:02}.{millis:03}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_time(0.), "00:00.000");
+        assert_eq!(format_time(30.), "00:30.000");
+        assert_eq!(format_time(61.5), "01:01.500");
+        assert_eq!(format_time(3723.25), "01:02:03.250");
+    }
+}
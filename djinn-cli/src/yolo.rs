@@ -0,0 +1,38 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use clap::Parser;
+use djinn_core::{device::Device, yolov8};
+
+#[derive(Parser)]
+pub struct YoloArgs {
+    /// The device to run the model on.
+    /// Defaults to the first available GPU, falling back to the CPU.
+    #[arg(long, value_enum)]
+    device: Option<Device>,
+    #[command(flatten)]
+    args: yolov8::args::Args,
+}
+
+pub async fn run(args: YoloArgs) -> anyhow::Result<()> {
+    let YoloArgs { device, args } = args;
+    let device = device.unwrap_or_default().try_into()?;
+
+    // ctrl-c stops a video or camera and finishes writing its output,
+    // images are left to the default handler
+    let stop = Arc::new(AtomicBool::new(false));
+    if args.video.is_some() || args.camera.is_some() {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                tracing::info!("received ctrl-c");
+                stop.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    // inference is CPU bound, so keep it off of the async runtime
+    tokio::task::spawn_blocking(move || yolov8::run(device, args, &stop)).await?
+}
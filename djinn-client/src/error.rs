@@ -0,0 +1,31 @@
+pub use djinn_core::api::{ErrorCode, ErrorResponse};
+use reqwest::StatusCode;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// An error response from the server
+    #[error("{0}")]
+    Server(ErrorResponse),
+    /// An error status without a body the client understands, e.g. from a proxy
+    #[error("the server responded with {0}")]
+    Status(StatusCode),
+    /// An `error` event that isn't JSON, from an older server
+    #[error("error in stream: {0}")]
+    Stream(String),
+}
+
+impl Error {
+    /// The server's error code, if the server sent one
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Server(response) => Some(response.code),
+            _ => None,
+        }
+    }
+}
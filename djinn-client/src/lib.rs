@@ -0,0 +1,211 @@
+//! A typed async client for djinn-server
+//!
+//! ```no_run
+//! # async fn run() -> djinn_client::Result<()> {
+//! let client = djinn_client::Client::new("http://[::1]:8080");
+//! let response = client
+//!     .complete(&djinn_client::CompleteRequest::new("The capital of France is"))
+//!     .await?;
+//! println!("{}", response.output);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::{pin_mut, Stream, StreamExt as _};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use error::{Error, ErrorCode, ErrorResponse, Result};
+pub use types::*;
+
+use crate::sse::{lines, EventParser};
+
+mod error;
+mod sse;
+mod types;
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server-sent event names used by `/complete/stream`
+const EVENT_TOKEN: &str = "token";
+const EVENT_ERROR: &str = "error";
+const EVENT_EOS: &str = "eos";
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl Client {
+    /// A client for the server at `url`, e.g. `http://[::1]:8080`
+    pub fn new(url: impl Into<String>) -> Self {
+        Client::with_http(reqwest::Client::new(), url)
+    }
+
+    /// A client that sends requests with `http`, e.g. to set timeouts or headers
+    pub fn with_http(http: reqwest::Client, url: impl Into<String>) -> Self {
+        let url = url.into().trim_end_matches('/').to_string();
+        Client { http, url }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Check that the server is up
+    pub async fn health(&self) -> Result<()> {
+        let response = self
+            .http
+            .get(self.route("/health-check"))
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// The models the server can run
+    pub async fn models(&self) -> Result<Vec<ModelStatus>> {
+        let response = self.http.get(self.route("/models")).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Run a prompt and wait for the whole output
+    pub async fn complete(&self, request: &CompleteRequest) -> Result<CompleteResponse> {
+        self.post("/complete", request).await
+    }
+
+    /// Run several prompts with the same model and parameters
+    pub async fn complete_batch(
+        &self,
+        request: &BatchCompleteRequest,
+    ) -> Result<BatchCompleteResponse> {
+        self.post("/complete/batch", request).await
+    }
+
+    /// Run a prompt and stream the tokens as they are generated.
+    /// The stream ends with [`StreamEvent::End`] unless the run fails.
+    pub async fn complete_stream(
+        &self,
+        request: &CompleteRequest,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>>> {
+        let response = self
+            .http
+            .post(self.route("/complete/stream"))
+            .json(request)
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+
+        Ok(async_stream::try_stream! {
+            let lines = lines(response);
+            pin_mut!(lines);
+            let mut parser = EventParser::default();
+
+            while let Some(line) = lines.next().await {
+                let Some(event) = parser.line(&line?) else {
+                    continue;
+                };
+                match event.name.as_str() {
+                    EVENT_TOKEN => yield StreamEvent::Token(event.data),
+                    EVENT_ERROR => Err(event_error(event.data))?,
+                    EVENT_EOS => {
+                        yield StreamEvent::End(serde_json::from_str(&event.data)?);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+
+    /// Send a chat message, starting a new session if the request has none
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        self.post("/chat", request).await
+    }
+
+    /// End a chat session, freeing it on the server
+    pub async fn end_chat(&self, session: &str) -> Result<()> {
+        let response = self
+            .http
+            .delete(self.route(&format!("/chat/{session}")))
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Compute embeddings for one or more texts.
+    /// Needs the server's `embedder` config.
+    pub async fn embed(&self, input: impl Into<EmbedInput>) -> Result<EmbedResponse> {
+        let request = EmbedRequest {
+            input: input.into(),
+        };
+        self.post("/embed", &request).await
+    }
+
+    /// Encode text with a model's tokenizer, e.g. to check that a prompt fits its context
+    pub async fn tokenize(&self, request: &TokenizeRequest) -> Result<TokenizeResponse> {
+        self.post("/tokenize", request).await
+    }
+
+    /// Generate the text between a prefix and a suffix, e.g. code at an editor's cursor
+    pub async fn infill(&self, request: &InfillRequest) -> Result<InfillResponse> {
+        self.post("/infill", request).await
+    }
+
+    fn route(&self, path: &str) -> String {
+        format!("{}{path}", self.url)
+    }
+
+    async fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        let response = self.http.post(self.route(path)).json(body).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+}
+
+/// Turn an error status into an error with the server's code and message
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ErrorResponse>().await {
+        Ok(error) => Err(Error::Server(error)),
+        Err(_) => Err(Error::Status(status)),
+    }
+}
+
+/// The data of an `error` event, plain text on older servers
+fn event_error(data: String) -> Error {
+    match serde_json::from_str::<ErrorResponse>(&data) {
+        Ok(error) => Error::Server(error),
+        Err(_) => Error::Stream(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_use_the_server_defaults() {
+        let request = serde_json::to_value(CompleteRequest::new("hi")).unwrap();
+
+        assert_eq!(request["prompt"], "hi");
+        assert!(request.get("model").is_none());
+        assert!(request.get("seed").is_none());
+        assert!(request.get("sample_len").is_some());
+    }
+
+    #[test]
+    fn error_events_have_codes() {
+        let error = event_error(r#"{"code": "queue_full", "message": "busy"}"#.to_string());
+        assert_eq!(error.code(), Some(ErrorCode::QueueFull));
+
+        let error = event_error("unable to load model".to_string());
+        assert!(matches!(error, Error::Stream(_)));
+    }
+}
@@ -0,0 +1,99 @@
+//! Parsing for server-sent events
+
+use futures::{Stream, StreamExt as _};
+
+use crate::error::Result;
+
+/// Split a response body into lines as they arrive
+pub(crate) fn lines(response: reqwest::Response) -> impl Stream<Item = Result<String>> {
+    async_stream::try_stream! {
+        let mut bytes = response.bytes_stream();
+        let mut buffer: Vec<u8> = Vec::new();
+
+        while let Some(chunk) = bytes.next().await {
+            buffer.extend_from_slice(&chunk?);
+
+            while let Some(newline) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                yield String::from_utf8_lossy(&line).into_owned();
+            }
+        }
+    }
+}
+
+/// A server-sent event
+#[derive(Debug, PartialEq)]
+pub(crate) struct Event {
+    pub name: String,
+    pub data: String,
+}
+
+/// Collects the fields of server-sent events one line at a time
+#[derive(Debug, Default)]
+pub(crate) struct EventParser {
+    name: Option<String>,
+    data: Vec<String>,
+}
+
+impl EventParser {
+    /// Returns the event once the blank line that ends it is read
+    pub fn line(&mut self, line: &str) -> Option<Event> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line.is_empty() {
+            if self.name.is_none() && self.data.is_empty() {
+                return None;
+            }
+            return Some(Event {
+                name: self.name.take().unwrap_or_else(|| "message".into()),
+                data: std::mem::take(&mut self.data).join("\n"),
+            });
+        }
+
+        // lines starting with a colon are comments, like keep alive messages
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.name = Some(value.into()),
+            "data" => self.data.push(value.into()),
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Vec<Event> {
+        let mut parser = EventParser::default();
+        text.split_inclusive('\n')
+            .filter_map(|line| parser.line(line))
+            .collect()
+    }
+
+    #[test]
+    fn parses_events() {
+        let events = parse("event: token\ndata:  world\n\n:\n\nevent: eos\ndata: {}\n\n");
+
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    name: "token".into(),
+                    data: " world".into(),
+                },
+                Event {
+                    name: "eos".into(),
+                    data: "{}".into(),
+                },
+            ]
+        );
+    }
+}
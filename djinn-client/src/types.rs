@@ -0,0 +1,268 @@
+//! Requests and responses of djinn-server's routes
+
+use std::path::PathBuf;
+
+use djinn_core::lm::{
+    beam::Candidate, config::RunConfig, model::RunStats, prefix_cache::PrefixCacheStats,
+    sampling::TokenLogprobs,
+};
+use serde::{Deserialize, Serialize};
+
+/// The run config of a request that doesn't set any parameters.
+/// Unlike [`RunConfig::default`], a random seed is picked for each run.
+pub fn default_run_config() -> RunConfig {
+    RunConfig {
+        seed: None,
+        ..Default::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompleteRequest {
+    pub prompt: String,
+    /// The name of the model to run.
+    /// The server's default model is used if none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub config: RunConfig,
+}
+
+impl CompleteRequest {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        CompleteRequest {
+            prompt: prompt.into(),
+            model: None,
+            config: default_run_config(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompleteResponse {
+    pub prompt: String,
+    pub output: String,
+    #[serde(flatten)]
+    pub stats: RunStats,
+    /// One entry per generated token, if `logprobs` was requested
+    #[serde(default)]
+    pub logprobs: Vec<TokenLogprobs>,
+    /// The completions found by beam search, best first
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+}
+
+/// The end of a streamed completion
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StreamEnd {
+    #[serde(flatten)]
+    pub stats: RunStats,
+    #[serde(default)]
+    pub logprobs: Vec<TokenLogprobs>,
+}
+
+/// An item of a streamed completion
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    Token(String),
+    /// The last event of the stream
+    End(StreamEnd),
+}
+
+/// Several prompts that are run with the same model and parameters
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchCompleteRequest {
+    pub prompts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub config: RunConfig,
+}
+
+impl BatchCompleteRequest {
+    pub fn new(prompts: Vec<String>) -> Self {
+        BatchCompleteRequest {
+            prompts,
+            model: None,
+            config: default_run_config(),
+        }
+    }
+}
+
+/// Results in the same order as [`BatchCompleteRequest::prompts`]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchCompleteResponse {
+    pub results: Vec<CompleteResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatRequest {
+    /// The session to continue.
+    /// A new session is started if none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub message: String,
+    /// A system prompt for a new session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// The model to start a new session with.
+    /// The server's default model is used if none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub config: RunConfig,
+}
+
+impl ChatRequest {
+    pub fn new(message: impl Into<String>) -> Self {
+        ChatRequest {
+            session: None,
+            message: message.into(),
+            system: None,
+            model: None,
+            config: default_run_config(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatResponse {
+    /// Pass it back in [`ChatRequest::session`] to continue the chat
+    pub session: String,
+    pub reply: String,
+    #[serde(flatten)]
+    pub stats: RunStats,
+}
+
+/// One text or a batch of texts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum EmbedInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<String> for EmbedInput {
+    fn from(text: String) -> Self {
+        EmbedInput::One(text)
+    }
+}
+
+impl From<&str> for EmbedInput {
+    fn from(text: &str) -> Self {
+        EmbedInput::One(text.to_string())
+    }
+}
+
+impl From<Vec<String>> for EmbedInput {
+    fn from(texts: Vec<String>) -> Self {
+        EmbedInput::Many(texts)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EmbedRequest {
+    pub input: EmbedInput,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EmbedResponse {
+    /// One embedding per input text, in order
+    pub embeddings: Vec<Vec<f32>>,
+    /// The total number of tokens embedded
+    pub tokens: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenizeRequest {
+    pub text: String,
+    /// The model whose tokenizer is used.
+    /// The server's default model is used if none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl TokenizeRequest {
+    pub fn new(text: impl Into<String>) -> Self {
+        TokenizeRequest {
+            text: text.into(),
+            model: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenizeResponse {
+    /// The token IDs of the text as it's encoded in a prompt
+    pub tokens: Vec<u32>,
+    pub count: usize,
+    /// The most tokens the model can attend to, prompt and output together
+    pub max_context_len: usize,
+}
+
+/// Generate the text between `prefix` and `suffix`.
+/// The model has to support fill-in-the-middle, like Starcoder
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InfillRequest {
+    /// The text before the cursor
+    pub prefix: String,
+    /// The text after the cursor
+    #[serde(default)]
+    pub suffix: String,
+    /// The server's default model is used if none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub config: RunConfig,
+}
+
+impl InfillRequest {
+    pub fn new(prefix: impl Into<String>, suffix: impl Into<String>) -> Self {
+        InfillRequest {
+            prefix: prefix.into(),
+            suffix: suffix.into(),
+            model: None,
+            config: default_run_config(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InfillResponse {
+    /// The text that goes between the prefix and the suffix
+    pub output: String,
+    #[serde(flatten)]
+    pub stats: RunStats,
+    /// The completions found by beam search, best first
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+}
+
+/// A model the server can run
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModelStatus {
+    pub name: String,
+    pub config: PathBuf,
+    pub loaded: bool,
+    pub default: bool,
+    /// Only set for loaded models
+    #[serde(default)]
+    pub prefix_cache: Option<PrefixCacheStats>,
+    /// How long the model stays loaded when it isn't used, forever if `None`
+    #[serde(default)]
+    pub keep_alive_mins: Option<u64>,
+    /// Seconds since the last request, only set for loaded models
+    #[serde(default)]
+    pub idle_secs: Option<u64>,
+    #[serde(default)]
+    pub evictions: Evictions,
+}
+
+/// How often a model was unloaded to free memory
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Evictions {
+    /// Unloaded after being idle for its keep alive
+    pub idle: u64,
+    /// Unloaded to make room for another model
+    pub capacity: u64,
+}
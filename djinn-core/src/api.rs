@@ -0,0 +1,89 @@
+//! Types shared by djinn-server and its clients
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// The kind of an error djinn-server responded with,
+/// sent with its message so clients can handle each kind differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The model isn't configured or couldn't be loaded
+    ModelNotLoaded,
+    /// The prompt and sample length don't fit in the model's context
+    ContextOverflow,
+    /// The request couldn't be parsed or has invalid parameters
+    InvalidParams,
+    /// Too many requests are waiting for the models
+    QueueFull,
+    /// The model or another part of the server failed
+    BackendError,
+    /// A chat session or an endpoint that isn't configured
+    NotFound,
+    /// A code from a newer server
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// What a user can do about the error, if anything
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ErrorCode::ModelNotLoaded => {
+                Some("check the model name and the server's model configs")
+            }
+            ErrorCode::ContextOverflow => Some("shorten the prompt or ask for fewer tokens"),
+            ErrorCode::QueueFull => Some("the server is busy, try again later"),
+            ErrorCode::BackendError => Some("the server's logs have the details"),
+            ErrorCode::InvalidParams | ErrorCode::NotFound | ErrorCode::Unknown => None,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::ModelNotLoaded => write!(f, "model_not_loaded"),
+            ErrorCode::ContextOverflow => write!(f, "context_overflow"),
+            ErrorCode::InvalidParams => write!(f, "invalid_params"),
+            ErrorCode::QueueFull => write!(f, "queue_full"),
+            ErrorCode::BackendError => write!(f, "backend_error"),
+            ErrorCode::NotFound => write!(f, "not_found"),
+            ErrorCode::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The JSON body of djinn-server's error responses
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)?;
+        if let Some(hint) = self.code.hint() {
+            write!(f, ", {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_codes_are_parsed() {
+        let response: ErrorResponse =
+            serde_json::from_str(r#"{"code": "rate_limited", "message": "slow down"}"#).unwrap();
+        assert_eq!(response.code, ErrorCode::Unknown);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::QueueFull).unwrap(),
+            "queue_full"
+        );
+    }
+}
@@ -1 +1,34 @@
-pub const DEFAULT_CONFIG_DIR: &str = "./configs";
+//! Where model and server configs are kept
+
+use std::path::{Path, PathBuf};
+
+use djinn_dirs::Dirs;
+
+/// Where configs were kept before the config directory, relative to the working directory
+pub const LEGACY_CONFIG_DIR: &str = "./configs";
+
+/// The config directory, e.g. `~/.config/djinn`,
+/// falling back to [`LEGACY_CONFIG_DIR`] without a home directory
+pub fn default_config_dir() -> PathBuf {
+    Dirs::djinn()
+        .map(|dirs| dirs.config().to_path_buf())
+        .unwrap_or_else(|_| PathBuf::from(LEGACY_CONFIG_DIR))
+}
+
+/// Copy the configs in [`LEGACY_CONFIG_DIR`] to the config directory
+/// if it doesn't exist yet
+pub fn migrate_legacy_configs() -> std::io::Result<()> {
+    let config_dir = default_config_dir();
+    if djinn_dirs::migrate(Path::new(LEGACY_CONFIG_DIR), &config_dir)? {
+        tracing::info!(?config_dir, "copied configs from {LEGACY_CONFIG_DIR}");
+    }
+    Ok(())
+}
+
+/// The model config used when none is given, relative to the config directory
+pub const DEFAULT_MODEL_CONFIG: &str = "model/q_mistral.toml";
+
+/// The path of [`DEFAULT_MODEL_CONFIG`] in the config directory
+pub fn default_model_config() -> PathBuf {
+    default_config_dir().join(DEFAULT_MODEL_CONFIG)
+}
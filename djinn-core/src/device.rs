@@ -1,38 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
 use candle_core::Device as CandleDevice;
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 /// A enum of supported devices to run models on
-#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize,
+)]
 pub enum Device {
+    /// Use the first available device, trying CUDA, then Metal, then the CPU
+    #[default]
+    Auto,
     Cpu,
     Cuda,
     Metal,
 }
 
-#[allow(clippy::derivable_impls)]
-impl Default for Device {
-    fn default() -> Self {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "cuda")] {
-                Device::Cuda
-            } else if #[cfg(feature = "mac")] {
-                Device::Metal
-            } else {
-                Device::Cpu
+impl Device {
+    /// Find the best available device.
+    /// Devices that fail to initialize are skipped.
+    pub fn probe() -> CandleDevice {
+        log_cpu_capabilities();
+
+        if candle_core::utils::cuda_is_available() {
+            match CandleDevice::new_cuda(0) {
+                Ok(device) => {
+                    tracing::info!("using CUDA device 0");
+                    return device;
+                }
+                Err(error) => tracing::warn!(%error, "CUDA is available but failed to initialize"),
+            }
+        } else {
+            tracing::debug!("CUDA support is not available");
+        }
+
+        if candle_core::utils::metal_is_available() {
+            match CandleDevice::new_metal(0) {
+                Ok(device) => {
+                    tracing::info!("using Metal device 0");
+                    return device;
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "Metal is available but failed to initialize")
+                }
             }
+        } else {
+            tracing::debug!("Metal support is not available");
         }
+
+        tracing::info!("falling back to the CPU");
+        CandleDevice::Cpu
     }
 }
 
+/// Set the number of threads the CPU kernels use for the whole process.
+/// Candle reads `RAYON_NUM_THREADS` for its matrix multiplications,
+/// and the thread pool of its other kernels is sized by it when it starts.
+///
+/// This writes to the environment, which isn't synchronized with other threads reading it,
+/// so it has to be called at startup before any threads are spawned,
+/// e.g. before the async runtime is built.
+pub fn set_cpu_threads(threads: usize) {
+    std::env::set_var("RAYON_NUM_THREADS", threads.to_string());
+}
+
+fn log_cpu_capabilities() {
+    tracing::info!(
+        avx = candle_core::utils::with_avx(),
+        neon = candle_core::utils::with_neon(),
+        simd128 = candle_core::utils::with_simd128(),
+        f16c = candle_core::utils::with_f16c(),
+        threads = candle_core::utils::get_num_threads(),
+        "CPU capabilities"
+    );
+}
+
 impl TryFrom<Device> for CandleDevice {
     type Error = candle_core::Error;
 
     fn try_from(value: Device) -> Result<Self, Self::Error> {
         match value {
+            Device::Auto => Ok(Device::probe()),
             Device::Cpu => Ok(CandleDevice::Cpu),
             Device::Cuda => CandleDevice::new_cuda(0),
             Device::Metal => CandleDevice::new_metal(0),
         }
     }
 }
+
+/// A specific device, written as `cpu`, `cuda:1`, or `metal:0`.
+/// The ordinal defaults to 0.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DeviceSpec {
+    pub device: Device,
+    pub ordinal: usize,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, ordinal) = match s.split_once(':') {
+            Some((name, ordinal)) => (
+                name,
+                ordinal
+                    .parse()
+                    .map_err(|_| anyhow!("invalid device ordinal in {s:?}"))?,
+            ),
+            None => (s, 0),
+        };
+        let device = match name.to_lowercase().as_str() {
+            "cpu" => Device::Cpu,
+            "cuda" => Device::Cuda,
+            "metal" => Device::Metal,
+            _ => {
+                return Err(anyhow!(
+                    "unknown device {s:?}, expected cpu, cuda:N, or metal:N"
+                ))
+            }
+        };
+        Ok(DeviceSpec { device, ordinal })
+    }
+}
+
+impl TryFrom<String> for DeviceSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.device {
+            Device::Cpu => write!(f, "cpu"),
+            Device::Cuda => write!(f, "cuda:{}", self.ordinal),
+            Device::Metal => write!(f, "metal:{}", self.ordinal),
+            Device::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl From<DeviceSpec> for String {
+    fn from(value: DeviceSpec) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<DeviceSpec> for CandleDevice {
+    type Error = candle_core::Error;
+
+    fn try_from(value: DeviceSpec) -> Result<Self, Self::Error> {
+        match value.device {
+            Device::Cuda => CandleDevice::new_cuda(value.ordinal),
+            Device::Metal => CandleDevice::new_metal(value.ordinal),
+            Device::Cpu | Device::Auto => value.device.try_into(),
+        }
+    }
+}
+
+/// The devices a model is loaded on.
+/// Layers are split into contiguous, evenly sized groups, one per device.
+#[derive(Clone, Debug)]
+pub struct DeviceMap {
+    devices: Vec<CandleDevice>,
+}
+
+impl DeviceMap {
+    pub fn new(devices: Vec<CandleDevice>) -> anyhow::Result<Self> {
+        if devices.is_empty() {
+            return Err(anyhow!("a device map needs at least one device"));
+        }
+        Ok(DeviceMap { devices })
+    }
+
+    /// Open each device in `specs`
+    pub fn open(specs: &[DeviceSpec]) -> anyhow::Result<Self> {
+        let devices = specs
+            .iter()
+            .map(|&spec| CandleDevice::try_from(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        DeviceMap::new(devices)
+    }
+
+    /// The device that holds the embeddings and receives the input
+    pub fn main(&self) -> &CandleDevice {
+        &self.devices[0]
+    }
+
+    /// The device that holds the output head
+    pub fn last(&self) -> &CandleDevice {
+        &self.devices[self.devices.len() - 1]
+    }
+
+    /// True if the model is split across more than one device
+    pub fn is_sharded(&self) -> bool {
+        self.devices.len() > 1
+    }
+
+    pub fn devices(&self) -> &[CandleDevice] {
+        &self.devices
+    }
+
+    /// The index of the device that holds `layer` out of `num_layers`
+    pub fn layer_shard(&self, layer: usize, num_layers: usize) -> usize {
+        shard_index(layer, num_layers, self.devices.len())
+    }
+}
+
+impl From<CandleDevice> for DeviceMap {
+    fn from(device: CandleDevice) -> Self {
+        DeviceMap {
+            devices: vec![device],
+        }
+    }
+}
+
+/// The index of the shard that holds `layer`
+fn shard_index(layer: usize, num_layers: usize, num_shards: usize) -> usize {
+    (layer * num_shards / num_layers.max(1)).min(num_shards - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_device_specs() {
+        let spec: DeviceSpec = "cuda:1".parse().unwrap();
+        assert_eq!(
+            spec,
+            DeviceSpec {
+                device: Device::Cuda,
+                ordinal: 1
+            }
+        );
+        assert_eq!(spec.to_string(), "cuda:1");
+        assert_eq!("cpu".parse::<DeviceSpec>().unwrap().device, Device::Cpu);
+        assert_eq!("metal".parse::<DeviceSpec>().unwrap().ordinal, 0);
+        assert!("cuda:x".parse::<DeviceSpec>().is_err());
+        assert!("tpu:0".parse::<DeviceSpec>().is_err());
+    }
+
+    #[test]
+    fn splits_layers_evenly() {
+        let shards: Vec<usize> = (0..6).map(|layer| shard_index(layer, 6, 2)).collect();
+        assert_eq!(shards, [0, 0, 0, 1, 1, 1]);
+        let shards: Vec<usize> = (0..5).map(|layer| shard_index(layer, 5, 3)).collect();
+        assert_eq!(shards, [0, 0, 1, 1, 2]);
+    }
+}
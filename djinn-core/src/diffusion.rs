@@ -0,0 +1,393 @@
+//! Text to image generation with Stable Diffusion
+
+use anyhow::{anyhow, Context as _};
+use candle_core::{DType, Device, IndexOp, Module, Tensor};
+use candle_transformers::models::stable_diffusion::{
+    build_clip_transformer,
+    clip::ClipTextTransformer,
+    ddim::DDIMSchedulerConfig,
+    euler_ancestral_discrete::EulerAncestralDiscreteSchedulerConfig,
+    schedulers::{PredictionType, Scheduler, SchedulerConfig},
+    unet_2d::UNet2DConditionModel,
+    vae::AutoEncoderKL,
+    StableDiffusionConfig,
+};
+use clap::ValueEnum;
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use crate::hub::HubRepo;
+
+const DEFAULT_REVISION: &str = "main";
+/// The CLIP text encoders read prompts of exactly this many tokens
+const PROMPT_TOKENS: usize = 77;
+/// Latents are scaled by this before they're decoded by the VAE
+const VAE_SCALE: f64 = 0.18215;
+/// Latents are this many times smaller than the image on each side
+const LATENT_SCALE: usize = 8;
+const LATENT_CHANNELS: usize = 4;
+
+/// The Stable Diffusion version used to generate images
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffusionArchitecture {
+    #[default]
+    V1_5,
+    V2_1,
+}
+
+impl DiffusionArchitecture {
+    pub fn hf_repo_id(&self) -> &'static str {
+        match self {
+            DiffusionArchitecture::V1_5 => "stable-diffusion-v1-5/stable-diffusion-v1-5",
+            DiffusionArchitecture::V2_1 => "stabilityai/stable-diffusion-2-1",
+        }
+    }
+
+    /// The repo of the CLIP tokenizer, which isn't in the model repo
+    fn tokenizer_repo_id(&self) -> &'static str {
+        match self {
+            DiffusionArchitecture::V1_5 => "openai/clip-vit-base-patch32",
+            DiffusionArchitecture::V2_1 => "laion/CLIP-ViT-H-14-laion2B-s32B-b79K",
+        }
+    }
+
+    /// The token prompts are padded with
+    fn pad_token(&self) -> &'static str {
+        match self {
+            DiffusionArchitecture::V1_5 => "<|endoftext|>",
+            DiffusionArchitecture::V2_1 => "!",
+        }
+    }
+
+    /// 2.1 predicts velocities rather than noise
+    fn prediction_type(&self) -> PredictionType {
+        match self {
+            DiffusionArchitecture::V1_5 => PredictionType::Epsilon,
+            DiffusionArchitecture::V2_1 => PredictionType::VPrediction,
+        }
+    }
+
+    fn sd_config(&self, width: usize, height: usize) -> StableDiffusionConfig {
+        match self {
+            DiffusionArchitecture::V1_5 => {
+                StableDiffusionConfig::v1_5(None, Some(height), Some(width))
+            }
+            DiffusionArchitecture::V2_1 => {
+                StableDiffusionConfig::v2_1(None, Some(height), Some(width))
+            }
+        }
+    }
+}
+
+/// How noise is removed from the latents at each step
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffusionScheduler {
+    #[default]
+    Ddim,
+    /// Adds noise back at each step, so images vary more between step counts
+    EulerAncestral,
+}
+
+const fn default_steps() -> usize {
+    30
+}
+
+const fn default_guidance_scale() -> f64 {
+    7.5
+}
+
+const fn default_size() -> usize {
+    512
+}
+
+/// How an image is generated.
+/// The server's config has the defaults that requests can override
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageOptions {
+    #[serde(default)]
+    pub scheduler: DiffusionScheduler,
+    /// More steps take longer and add detail
+    #[serde(default = "default_steps")]
+    pub steps: usize,
+    /// How closely the image follows the prompt.
+    /// Guidance is off at 1 or less, which is twice as fast
+    #[serde(default = "default_guidance_scale")]
+    pub guidance_scale: f64,
+    /// A multiple of 8
+    #[serde(default = "default_size")]
+    pub width: usize,
+    /// A multiple of 8
+    #[serde(default = "default_size")]
+    pub height: usize,
+    /// A random seed is used if none is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            scheduler: DiffusionScheduler::default(),
+            steps: default_steps(),
+            guidance_scale: default_guidance_scale(),
+            width: default_size(),
+            height: default_size(),
+            seed: None,
+        }
+    }
+}
+
+impl ImageOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.steps == 0 {
+            anyhow::bail!("steps must be at least 1");
+        }
+        for (name, size) in [("width", self.width), ("height", self.height)] {
+            if size == 0 || size % LATENT_SCALE != 0 {
+                anyhow::bail!("{name} must be a positive multiple of {LATENT_SCALE}, not {size}");
+            }
+        }
+        Ok(())
+    }
+
+    fn uses_guidance(&self) -> bool {
+        self.guidance_scale > 1.
+    }
+}
+
+fn default_revision() -> String {
+    DEFAULT_REVISION.to_string()
+}
+
+/// Configuration for loading an [`ImageGenerator`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageGeneratorConfig {
+    #[serde(default)]
+    pub variant: DiffusionArchitecture,
+    #[serde(default)]
+    pub device: crate::device::Device,
+    #[serde(default = "default_revision")]
+    pub revision: String,
+    /// Load the half precision weights, which halves memory use on GPUs
+    #[serde(default)]
+    pub f16: bool,
+    #[serde(default)]
+    pub defaults: ImageOptions,
+}
+
+/// The loaded text encoder, denoiser, and decoder of a Stable Diffusion model
+pub struct ImageGenerator {
+    variant: DiffusionArchitecture,
+    tokenizer: Tokenizer,
+    clip: ClipTextTransformer,
+    unet: UNet2DConditionModel,
+    vae: AutoEncoderKL,
+    device: Device,
+    dtype: DType,
+    defaults: ImageOptions,
+}
+
+impl ImageGenerator {
+    pub async fn from_config(config: &ImageGeneratorConfig) -> anyhow::Result<Self> {
+        config.defaults.validate()?;
+        let variant = config.variant;
+        let repo = HubRepo::model(variant.hf_repo_id(), &config.revision)?;
+        let weights = |name: &str| {
+            if config.f16 {
+                format!("{name}/diffusion_pytorch_model.fp16.safetensors")
+            } else {
+                format!("{name}/diffusion_pytorch_model.safetensors")
+            }
+        };
+        let clip_file = if config.f16 {
+            "text_encoder/model.fp16.safetensors"
+        } else {
+            "text_encoder/model.safetensors"
+        };
+        let clip_weights = repo.get(clip_file).await?;
+        let unet_weights = repo.get(&weights("unet")).await?;
+        let vae_weights = repo.get(&weights("vae")).await?;
+        let tokenizer_file = HubRepo::model(variant.tokenizer_repo_id(), DEFAULT_REVISION)?
+            .get("tokenizer.json")
+            .await?;
+
+        let device: Device = config.device.try_into()?;
+        let dtype = if config.f16 { DType::F16 } else { DType::F32 };
+        let defaults = &config.defaults;
+        let sd_config = variant.sd_config(defaults.width, defaults.height);
+        // the text encoder runs once per image, so it's kept in full precision
+        let clip = build_clip_transformer(&sd_config.clip, clip_weights, &device, DType::F32)?;
+        let unet = sd_config.build_unet(unet_weights, &device, LATENT_CHANNELS, false, dtype)?;
+        let vae = sd_config.build_vae(vae_weights, &device, dtype)?;
+        let tokenizer = Tokenizer::from_file(&tokenizer_file).map_err(anyhow::Error::msg)?;
+
+        tracing::info!(?variant, "loaded stable diffusion model");
+
+        Ok(ImageGenerator {
+            variant,
+            tokenizer,
+            clip,
+            unet,
+            vae,
+            device,
+            dtype,
+            defaults: config.defaults.clone(),
+        })
+    }
+
+    /// The options images are generated with unless a request overrides them
+    pub fn defaults(&self) -> &ImageOptions {
+        &self.defaults
+    }
+
+    /// Change the defaults of the next images, they should be validated first
+    pub fn set_defaults(&mut self, defaults: ImageOptions) {
+        self.defaults = defaults;
+    }
+
+    /// Generate an image of a prompt, avoiding what's in the negative prompt
+    pub fn generate(
+        &self,
+        prompt: &str,
+        negative_prompt: &str,
+        options: &ImageOptions,
+    ) -> anyhow::Result<RgbImage> {
+        options.validate()?;
+        if let Some(seed) = options.seed {
+            self.device.set_seed(seed)?;
+        }
+
+        let text_embeddings = if options.uses_guidance() {
+            let negative = self.encode_prompt(negative_prompt)?;
+            let positive = self.encode_prompt(prompt)?;
+            Tensor::cat(&[negative, positive], 0)?
+        } else {
+            self.encode_prompt(prompt)?
+        }
+        .to_dtype(self.dtype)?;
+
+        let mut scheduler = self.scheduler(options)?;
+        let timesteps = scheduler.timesteps().to_vec();
+        let latent_shape = (
+            1,
+            LATENT_CHANNELS,
+            options.height / LATENT_SCALE,
+            options.width / LATENT_SCALE,
+        );
+        let mut latents = (Tensor::randn(0f32, 1f32, latent_shape, &self.device)?
+            * scheduler.init_noise_sigma())?
+        .to_dtype(self.dtype)?;
+
+        for (step, &timestep) in timesteps.iter().enumerate() {
+            let input = if options.uses_guidance() {
+                Tensor::cat(&[&latents, &latents], 0)?
+            } else {
+                latents.clone()
+            };
+            let input = scheduler.scale_model_input(input, timestep)?;
+            let noise = self
+                .unet
+                .forward(&input, timestep as f64, &text_embeddings)?;
+            let noise = if options.uses_guidance() {
+                let noise = noise.chunk(2, 0)?;
+                let (unconditioned, conditioned) = (&noise[0], &noise[1]);
+                (unconditioned + ((conditioned - unconditioned)? * options.guidance_scale)?)?
+            } else {
+                noise
+            };
+            latents = scheduler.step(&noise, timestep, &latents)?;
+            tracing::debug!(step = step + 1, steps = timesteps.len(), "denoised");
+        }
+
+        let image = self.vae.decode(&(&latents / VAE_SCALE)?)?;
+        to_rgb_image(&image)
+    }
+
+    /// The CLIP embedding of a prompt, padded to the encoder's length
+    fn encode_prompt(&self, prompt: &str) -> anyhow::Result<Tensor> {
+        let pad_token = self.variant.pad_token();
+        let pad_id = self
+            .tokenizer
+            .token_to_id(pad_token)
+            .ok_or(anyhow!("no token {pad_token:?} in the tokenizer"))?;
+        let mut tokens = self
+            .tokenizer
+            .encode(prompt, true)
+            .map_err(anyhow::Error::msg)?
+            .get_ids()
+            .to_vec();
+        if tokens.len() > PROMPT_TOKENS {
+            tracing::warn!(tokens = tokens.len(), "truncating a long prompt");
+        }
+        tokens.resize(PROMPT_TOKENS, pad_id);
+
+        let tokens = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        Ok(self.clip.forward(&tokens)?)
+    }
+
+    fn scheduler(&self, options: &ImageOptions) -> anyhow::Result<Box<dyn Scheduler>> {
+        let prediction_type = self.variant.prediction_type();
+        let scheduler = match options.scheduler {
+            DiffusionScheduler::Ddim => DDIMSchedulerConfig {
+                prediction_type,
+                ..Default::default()
+            }
+            .build(options.steps)?,
+            DiffusionScheduler::EulerAncestral => EulerAncestralDiscreteSchedulerConfig {
+                prediction_type,
+                ..Default::default()
+            }
+            .build(options.steps)?,
+        };
+        Ok(scheduler)
+    }
+}
+
+/// Convert a decoded image in `[-1, 1]` with shape `(1, 3, height, width)`
+fn to_rgb_image(image: &Tensor) -> anyhow::Result<RgbImage> {
+    let image = ((image.to_dtype(DType::F32)? / 2.)? + 0.5)?
+        .to_device(&Device::Cpu)?
+        .clamp(0f32, 1f32)?;
+    let image = (image * 255.)?.to_dtype(DType::U8)?.i(0)?;
+    let (channels, height, width) = image.dims3()?;
+    if channels != 3 {
+        anyhow::bail!("expected an RGB image, got {channels} channels");
+    }
+    let pixels = image.permute((1, 2, 0))?.flatten_all()?.to_vec1::<u8>()?;
+    RgbImage::from_raw(width as u32, height as u32, pixels)
+        .context("image buffer doesn't match its size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_options() {
+        assert!(ImageOptions::default().validate().is_ok());
+        let odd_width = ImageOptions {
+            width: 500,
+            ..Default::default()
+        };
+        assert!(odd_width.validate().is_err());
+        let no_steps = ImageOptions {
+            steps: 0,
+            ..Default::default()
+        };
+        assert!(no_steps.validate().is_err());
+    }
+
+    #[test]
+    fn converts_tensors_to_images() {
+        // a 1x2 image, one black and one white pixel
+        let data = [-1f32, 1., -1., 1., -1., 1.];
+        let tensor = Tensor::from_slice(&data, (1, 3, 1, 2), &Device::Cpu).unwrap();
+        let image = to_rgb_image(&tensor).unwrap();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 255, 255]);
+    }
+}
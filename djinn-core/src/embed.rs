@@ -0,0 +1,247 @@
+//! Sentence embeddings from BERT style encoder models
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::{
+    bert::{BertModel, Config as BertConfig, DTYPE},
+    distilbert::{Config as DistilBertConfig, DistilBertModel},
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokenizers::{Tokenizer, TruncationParams};
+
+use crate::hub::HubRepo;
+use crate::lm::ModelSource;
+
+/// The maximum number of tokens embedded, longer texts are truncated
+const MAX_TOKENS: usize = 512;
+const DEFAULT_REVISION: &str = "main";
+
+/// The encoder model used to compute embeddings
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingArchitecture {
+    #[default]
+    Bert,
+    DistilBert,
+}
+
+impl EmbeddingArchitecture {
+    pub fn hf_repo_id(&self) -> &'static str {
+        match self {
+            EmbeddingArchitecture::Bert => "sentence-transformers/all-MiniLM-L6-v2",
+            EmbeddingArchitecture::DistilBert => "sentence-transformers/multi-qa-distilbert-cos-v1",
+        }
+    }
+
+    /// Whether `name` refers to this model,
+    /// by its variant, e.g. `distil_bert`, or its Hugging Face repo,
+    /// e.g. `sentence-transformers/all-MiniLM-L6-v2` or `all-MiniLM-L6-v2`
+    pub fn is_named(&self, name: &str) -> bool {
+        let repo = self.hf_repo_id();
+        let repo_name = repo.rsplit('/').next().unwrap_or(repo);
+        self.to_possible_value()
+            .is_some_and(|value| value.matches(&name.replace('_', "-"), true))
+            || name.eq_ignore_ascii_case(repo)
+            || name.eq_ignore_ascii_case(repo_name)
+    }
+}
+
+fn default_model_source() -> ModelSource {
+    ModelSource::HuggingFaceHub {
+        revision: DEFAULT_REVISION.to_string(),
+    }
+}
+
+const fn default_normalize() -> bool {
+    true
+}
+
+/// Configuration for loading an [`EmbeddingContext`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmbeddingConfig {
+    #[serde(default)]
+    pub variant: EmbeddingArchitecture,
+    #[serde(default)]
+    pub device: crate::device::Device,
+    #[serde(default = "default_model_source")]
+    pub model_source: ModelSource,
+    /// Scale embeddings to unit length,
+    /// so the dot product of two embeddings is their cosine similarity
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+}
+
+enum EmbeddingModel {
+    Bert(BertModel),
+    DistilBert(DistilBertModel),
+}
+
+/// The embedding of a single text
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Embedding {
+    pub vector: Vec<f32>,
+    /// The number of tokens that were embedded
+    pub tokens: usize,
+}
+
+/// A loaded embedding model that can be reused across texts
+pub struct EmbeddingContext {
+    variant: EmbeddingArchitecture,
+    model: EmbeddingModel,
+    tokenizer: Tokenizer,
+    device: Device,
+    normalize: bool,
+}
+
+impl EmbeddingContext {
+    pub async fn from_config(config: &EmbeddingConfig) -> anyhow::Result<Self> {
+        let (weight_files, config_file, tokenizer_file) = match &config.model_source {
+            ModelSource::HuggingFaceHub { revision } => {
+                let repo = HubRepo::model(config.variant.hf_repo_id(), revision)?;
+                (
+                    vec![repo.get("model.safetensors").await?],
+                    repo.get("config.json").await?,
+                    repo.get("tokenizer.json").await?,
+                )
+            }
+            ModelSource::Files {
+                weight_files,
+                tokenizer_file,
+                config_file,
+            } => {
+                let config_file = match config_file {
+                    Some(config_file) => config_file.clone(),
+                    None => weight_files
+                        .first()
+                        .and_then(|file| file.parent())
+                        .map(|dir| dir.join("config.json"))
+                        .ok_or(anyhow!("no weight files given"))?,
+                };
+                (weight_files.clone(), config_file, tokenizer_file.clone())
+            }
+        };
+
+        let device: Device = config.device.try_into()?;
+        let model = load_model(config.variant, &weight_files, &config_file, &device)?;
+
+        let mut tokenizer = Tokenizer::from_file(&tokenizer_file).map_err(anyhow::Error::msg)?;
+        // texts are embedded one at a time, so they are never padded
+        tokenizer
+            .with_padding(None)
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+
+        tracing::info!(variant = ?config.variant, "loaded embedding model");
+
+        Ok(EmbeddingContext {
+            variant: config.variant,
+            model,
+            tokenizer,
+            device,
+            normalize: config.normalize,
+        })
+    }
+
+    /// Embed a text as the mean of its token embeddings
+    pub fn variant(&self) -> EmbeddingArchitecture {
+        self.variant
+    }
+
+    pub fn embed(&self, text: &str) -> anyhow::Result<Embedding> {
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(anyhow::Error::msg)?;
+        let tokens = encoding.get_ids().len();
+        let input_ids = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+
+        let hidden_states = match &self.model {
+            EmbeddingModel::Bert(model) => {
+                let token_type_ids = input_ids.zeros_like()?;
+                model.forward(&input_ids, &token_type_ids, None)?
+            }
+            EmbeddingModel::DistilBert(model) => {
+                // positions where the mask is set are ignored
+                let mask = Tensor::zeros((1, tokens), DType::U8, &self.device)?;
+                model.forward(&input_ids, &mask)?
+            }
+        };
+
+        let mut vector = hidden_states
+            .mean(1)?
+            .squeeze(0)?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>()?;
+        if self.normalize {
+            normalize(&mut vector);
+        }
+
+        Ok(Embedding { vector, tokens })
+    }
+}
+
+fn load_model(
+    variant: EmbeddingArchitecture,
+    weight_files: &[PathBuf],
+    config_file: &Path,
+    device: &Device,
+) -> anyhow::Result<EmbeddingModel> {
+    let config = std::fs::read_to_string(config_file)
+        .with_context(|| format!("unable to read model config {config_file:?}"))?;
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors(weight_files, DTYPE, device)? };
+
+    match variant {
+        EmbeddingArchitecture::Bert => {
+            let config: BertConfig = serde_json::from_str(&config)?;
+            Ok(EmbeddingModel::Bert(BertModel::load(vb, &config)?))
+        }
+        EmbeddingArchitecture::DistilBert => {
+            let config: DistilBertConfig = serde_json::from_str(&config)?;
+            Ok(EmbeddingModel::DistilBert(DistilBertModel::load(
+                vb, &config,
+            )?))
+        }
+    }
+}
+
+/// Scale a vector to unit length
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0. {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_to_unit_length() {
+        let mut vector = [3., 4.];
+        normalize(&mut vector);
+        assert_eq!(vector, [0.6, 0.8]);
+
+        let mut zeros = [0., 0.];
+        normalize(&mut zeros);
+        assert_eq!(zeros, [0., 0.]);
+    }
+
+    #[test]
+    fn matches_model_names() {
+        let variant = EmbeddingArchitecture::DistilBert;
+        assert!(variant.is_named("distil_bert"));
+        assert!(variant.is_named("distil-bert"));
+        assert!(variant.is_named("sentence-transformers/multi-qa-distilbert-cos-v1"));
+        assert!(variant.is_named("multi-qa-distilbert-cos-v1"));
+        assert!(!variant.is_named("bert"));
+        assert!(!EmbeddingArchitecture::Bert.is_named("mistral"));
+    }
+}
@@ -12,6 +12,19 @@ pub enum Error {
     Candle(#[from] candle_core::Error),
     #[error(transparent)]
     Tokenizer(#[from] tokenizers::Error),
+    #[error(
+        "prompt of {prompt_tokens} tokens and sample length of {sample_len} \
+        exceed the context length of {max_context_len}"
+    )]
+    ContextOverflow {
+        prompt_tokens: usize,
+        sample_len: usize,
+        max_context_len: usize,
+    },
+    #[error("the model doesn't support fill-in-the-middle completion")]
+    InfillUnsupported,
+    #[error(transparent)]
+    Grammar(#[from] crate::lm::grammar::GrammarError),
     #[error(transparent)]
     Anyhow(#[from] anyhow::Error),
 }
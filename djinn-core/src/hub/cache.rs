@@ -0,0 +1,241 @@
+//! Inspect and clean up the HuggingFace Hub cache
+//!
+//! The cache keeps each repo in a `models--{org}--{name}` directory with
+//! `blobs/` holding file contents by hash,
+//! `snapshots/{commit}/` holding links into `blobs/` for each revision,
+//! and `refs/` mapping branch names to commits.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use hf_hub::Cache;
+
+const MODEL_FOLDER_PREFIX: &str = "models--";
+
+/// The directory the Hub cache is kept in
+pub fn cache_dir() -> PathBuf {
+    Cache::default().path().clone()
+}
+
+/// A model repo in the cache
+#[derive(Clone, Debug)]
+pub struct CachedRepo {
+    /// The repo ID, e.g. `mistralai/Mistral-7B-v0.1`
+    pub id: String,
+    pub path: PathBuf,
+    pub revisions: Vec<CachedRevision>,
+    /// The size of all blobs, shared between revisions
+    pub size: u64,
+}
+
+/// A downloaded revision of a repo
+#[derive(Clone, Debug)]
+pub struct CachedRevision {
+    pub commit: String,
+    /// Branch or tag names that point to this commit
+    pub refs: Vec<String>,
+    pub path: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub size: u64,
+}
+
+impl CachedRepo {
+    /// Find a revision by ref name or commit hash prefix
+    pub fn revision(&self, name: &str) -> Option<&CachedRevision> {
+        self.revisions
+            .iter()
+            .find(|revision| revision.refs.iter().any(|r| r == name))
+            .or_else(|| {
+                self.revisions
+                    .iter()
+                    .find(|revision| revision.commit.starts_with(name))
+            })
+    }
+
+    /// Revisions that no ref points to, which are left behind when a branch moves
+    pub fn detached_revisions(&self) -> impl Iterator<Item = &CachedRevision> {
+        self.revisions
+            .iter()
+            .filter(|revision| revision.refs.is_empty())
+    }
+}
+
+/// The cache folder name of a model repo
+pub fn repo_folder_name(repo_id: &str) -> String {
+    format!("{MODEL_FOLDER_PREFIX}{}", repo_id.replace('/', "--"))
+}
+
+/// The model repo ID of a cache folder, if it is a model folder
+pub fn repo_id(folder_name: &str) -> Option<String> {
+    folder_name
+        .strip_prefix(MODEL_FOLDER_PREFIX)
+        .map(|name| name.replace("--", "/"))
+}
+
+/// All model repos in the cache, sorted by ID
+pub fn cached_repos(cache_dir: &Path) -> io::Result<Vec<CachedRepo>> {
+    let entries = match std::fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    let mut repos = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(id) = repo_id(&entry.file_name().to_string_lossy()) else {
+            continue;
+        };
+        repos.push(read_repo(id, entry.path())?);
+    }
+    repos.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(repos)
+}
+
+/// Get a model repo from the cache
+pub fn cached_repo(cache_dir: &Path, repo_id: &str) -> io::Result<Option<CachedRepo>> {
+    let path = cache_dir.join(repo_folder_name(repo_id));
+    if !path.is_dir() {
+        return Ok(None);
+    }
+    read_repo(repo_id.to_string(), path).map(Some)
+}
+
+fn read_repo(id: String, path: PathBuf) -> io::Result<CachedRepo> {
+    let refs_dir = path.join("refs");
+    let mut refs = Vec::new();
+    for file in files_in(&refs_dir)? {
+        let name = file
+            .strip_prefix(&refs_dir)
+            .unwrap_or(&file)
+            .to_string_lossy()
+            .into_owned();
+        let commit = std::fs::read_to_string(&file)?.trim().to_string();
+        refs.push((name, commit));
+    }
+
+    let mut revisions = Vec::new();
+    let snapshots = path.join("snapshots");
+    if snapshots.is_dir() {
+        for entry in std::fs::read_dir(&snapshots)? {
+            let entry = entry?;
+            let commit = entry.file_name().to_string_lossy().into_owned();
+            let files = files_in(&entry.path())?;
+            // the files link into blobs/, so follow the links for their size
+            let size = files
+                .iter()
+                .filter_map(|file| std::fs::metadata(file).ok())
+                .map(|metadata| metadata.len())
+                .sum();
+            revisions.push(CachedRevision {
+                refs: refs
+                    .iter()
+                    .filter(|(_name, target)| *target == commit)
+                    .map(|(name, _target)| name.clone())
+                    .collect(),
+                commit,
+                path: entry.path(),
+                files,
+                size,
+            });
+        }
+    }
+    revisions.sort_by(|a, b| a.commit.cmp(&b.commit));
+
+    let size = files_in(&path.join("blobs"))?
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    Ok(CachedRepo {
+        id,
+        path,
+        revisions,
+        size,
+    })
+}
+
+/// All files under `dir`, or none if it doesn't exist
+fn files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Remove a whole repo from the cache.
+/// Returns the number of bytes freed.
+pub fn remove_repo(repo: &CachedRepo) -> io::Result<u64> {
+    std::fs::remove_dir_all(&repo.path)?;
+    Ok(repo.size)
+}
+
+/// Remove a revision and any blobs that no other revision uses.
+/// Returns the number of bytes freed.
+pub fn remove_revision(repo: &CachedRepo, revision: &CachedRevision) -> io::Result<u64> {
+    let kept: HashSet<PathBuf> = repo
+        .revisions
+        .iter()
+        .filter(|other| other.commit != revision.commit)
+        .flat_map(|other| other.files.iter())
+        .filter_map(|file| std::fs::canonicalize(file).ok())
+        .collect();
+    let blobs: HashSet<PathBuf> = revision
+        .files
+        .iter()
+        .filter_map(|file| std::fs::canonicalize(file).ok())
+        .collect();
+
+    let mut freed = 0;
+    for blob in blobs.difference(&kept) {
+        freed += std::fs::metadata(blob)?.len();
+        std::fs::remove_file(blob)?;
+    }
+    std::fs::remove_dir_all(&revision.path)?;
+    for name in &revision.refs {
+        std::fs::remove_file(repo.path.join("refs").join(name))?;
+    }
+
+    tracing::info!(
+        repo = repo.id,
+        commit = revision.commit,
+        freed,
+        "removed cached revision"
+    );
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_repo_folder_names() {
+        let folder = repo_folder_name("mistralai/Mistral-7B-v0.1");
+        assert_eq!(folder, "models--mistralai--Mistral-7B-v0.1");
+        assert_eq!(
+            repo_id(&folder).as_deref(),
+            Some("mistralai/Mistral-7B-v0.1")
+        );
+        assert_eq!(repo_id("datasets--squad"), None);
+    }
+}
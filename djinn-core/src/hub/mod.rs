@@ -0,0 +1,211 @@
+//! Download model files from the HuggingFace Hub into the local cache
+//!
+//! Every download is reported with tracing events.
+//! Command line tools can also draw progress bars with [`set_progress_bars`].
+
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use anyhow::Context as _;
+use hf_hub::{
+    api::tokio::{Api, ApiBuilder, ApiError},
+    Cache, Repo, RepoType,
+};
+use tokio_stream::StreamExt;
+
+use crate::error::Error;
+
+pub mod cache;
+
+static PROGRESS_BARS: AtomicBool = AtomicBool::new(false);
+
+/// Draw a progress bar on stderr for each download.
+/// Off by default so servers only report downloads with tracing events.
+pub fn set_progress_bars(enabled: bool) {
+    PROGRESS_BARS.store(enabled, Ordering::Relaxed);
+}
+
+/// A repo on the HuggingFace Hub and its files in the local cache
+pub struct HubRepo {
+    repo: Repo,
+    api: Api,
+    cache: Cache,
+}
+
+impl HubRepo {
+    pub fn new(repo: Repo) -> Result<Self, ApiError> {
+        let api = ApiBuilder::new()
+            .with_progress(PROGRESS_BARS.load(Ordering::Relaxed))
+            .build()?;
+        Ok(HubRepo {
+            repo,
+            api,
+            cache: Cache::default(),
+        })
+    }
+
+    /// A model repo at a revision, e.g. a branch name or commit hash
+    pub fn model(
+        repo_id: impl Into<String>,
+        revision: impl Into<String>,
+    ) -> Result<Self, ApiError> {
+        HubRepo::new(Repo::with_revision(
+            repo_id.into(),
+            RepoType::Model,
+            revision.into(),
+        ))
+    }
+
+    /// The repo ID, e.g. `mistralai/Mistral-7B-v0.1`
+    pub fn id(&self) -> String {
+        self.repo.url()
+    }
+
+    /// The path of a file in the cache, if it has been downloaded
+    pub fn cached(&self, filename: &str) -> Option<PathBuf> {
+        self.cache.repo(self.repo.clone()).get(filename)
+    }
+
+    /// Get a file from the cache, downloading it first if needed
+    pub async fn get(&self, filename: &str) -> Result<PathBuf, ApiError> {
+        if let Some(path) = self.cached(filename) {
+            tracing::debug!(repo = self.id(), filename, "using cached file");
+            return Ok(path);
+        }
+
+        tracing::info!(repo = self.id(), filename, "downloading");
+        let start = Instant::now();
+        let path = self.api.repo(self.repo.clone()).download(filename).await?;
+        let bytes = std::fs::metadata(&path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        tracing::info!(
+            repo = self.id(),
+            filename,
+            bytes,
+            elapsed = ?start.elapsed(),
+            "downloaded"
+        );
+        Ok(path)
+    }
+
+    /// The names of all files in the repo
+    pub async fn files(&self) -> Result<Vec<String>, ApiError> {
+        let info = self.api.repo(self.repo.clone()).info().await?;
+        Ok(info
+            .siblings
+            .into_iter()
+            .map(|sibling| sibling.rfilename)
+            .collect())
+    }
+}
+
+/// Loads the safetensors files for a model from the hub based on a json index file.
+pub async fn hub_load_safetensors(repo: &HubRepo, json_file: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let json_file = repo.get(json_file).await?;
+    let json_fh = std::fs::File::open(json_file.clone())?;
+    let json: serde_json::Value =
+        serde_json::from_reader(&json_fh).map_err(candle_core::Error::wrap)?;
+    let weight_map = match json.get("weight_map") {
+        None => Err(Error::ParameterFileParse {
+            path: json_file.to_owned(),
+            message: String::from("no weight map found"),
+        }),
+        Some(serde_json::Value::Object(map)) => Ok(map),
+        Some(_) => Err(Error::ParameterFileParse {
+            path: json_file,
+            message: String::from("weight map in file is not a map"),
+        }),
+    }?;
+    let mut safetensors_files = HashSet::new();
+    for value in weight_map.values() {
+        if let Some(file) = value.as_str() {
+            safetensors_files.insert(file.to_string());
+        }
+    }
+
+    let files: Vec<Result<PathBuf, ApiError>> =
+        tokio_stream::iter(std::iter::repeat(repo).zip(&safetensors_files))
+            .then(|(repo, file)| repo.get(file))
+            .collect::<Vec<Result<PathBuf, ApiError>>>()
+            .await;
+
+    let files: Vec<PathBuf> = files.into_iter().collect::<Result<Vec<_>, ApiError>>()?;
+
+    Ok(files)
+}
+
+/// Check that a downloaded weight file is complete.
+/// safetensors files must be as long as their header says,
+/// and GGUF files must start with the GGUF magic number.
+/// Other files are not checked.
+pub fn verify_weights(path: &Path) -> anyhow::Result<()> {
+    let Some(extension) = path.extension() else {
+        return Ok(());
+    };
+    let mut file = std::fs::File::open(path).with_context(|| format!("can't open {path:?}"))?;
+    let len = file.metadata()?.len();
+
+    if extension == "safetensors" {
+        let mut header_len = [0u8; 8];
+        file.read_exact(&mut header_len)
+            .with_context(|| format!("{path:?} is too short to be a safetensors file"))?;
+        let header_len = u64::from_le_bytes(header_len);
+        let mut header = vec![0u8; header_len as usize];
+        file.read_exact(&mut header)
+            .with_context(|| format!("{path:?} has a truncated safetensors header"))?;
+
+        let expected = 8 + header_len + safetensors_data_len(&header)?;
+        if len != expected {
+            anyhow::bail!("{path:?} is {len} bytes but should be {expected}, download it again");
+        }
+    } else if extension == "gguf" {
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .with_context(|| format!("{path:?} is too short to be a GGUF file"))?;
+        if &magic != b"GGUF" {
+            anyhow::bail!("{path:?} is not a GGUF file");
+        }
+    }
+    Ok(())
+}
+
+/// The length of the tensor data described by a safetensors JSON header
+fn safetensors_data_len(header: &[u8]) -> anyhow::Result<u64> {
+    let header: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(header).context("invalid safetensors header")?;
+    let mut len = 0;
+    for (name, tensor) in header {
+        if name == "__metadata__" {
+            continue;
+        }
+        let end = tensor
+            .get("data_offsets")
+            .and_then(|offsets| offsets.get(1))
+            .and_then(|end| end.as_u64())
+            .with_context(|| format!("tensor {name} has no data offsets"))?;
+        len = len.max(end);
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_safetensors_data_len() {
+        let header = br#"{
+            "__metadata__": {"format": "pt"},
+            "a": {"dtype": "F32", "shape": [2], "data_offsets": [0, 8]},
+            "b": {"dtype": "F32", "shape": [4], "data_offsets": [8, 24]}
+        }"#;
+        assert_eq!(safetensors_data_len(header).unwrap(), 24);
+        assert!(safetensors_data_len(br#"{"a": {}}"#).is_err());
+    }
+}
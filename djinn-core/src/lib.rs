@@ -1,14 +1,20 @@
 #[cfg(feature = "mac")]
 extern crate accelerate_src;
 
+pub mod api;
 mod coco_classes;
 pub mod config;
 pub mod device;
+pub mod diffusion;
+pub mod embed;
 mod error;
 mod font;
-mod hf_hub_ext;
+pub mod hub;
+pub mod llava;
 pub mod lm;
+pub mod search;
 mod token_output_stream;
+pub mod whisper;
 pub mod yolov8;
 
 pub use error::Error;
@@ -0,0 +1,243 @@
+//! Image question answering with LLaVA-NeXT,
+//! a CLIP vision tower whose features are projected into a language model's input
+
+pub mod preprocess;
+
+use anyhow::{anyhow, Context as _};
+use candle_core::{DType, Device, IndexOp, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::{
+    generation::LogitsProcessor,
+    models::{
+        llama::Cache,
+        llava::{
+            config::{HFGenerationConfig, HFLLaVAConfig, HFPreProcessorConfig, LLaVAConfig},
+            LLaVA,
+        },
+    },
+};
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImageView as _};
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use crate::hub::{hub_load_safetensors, HubRepo};
+
+use self::preprocess::ImagePreprocessor;
+
+const DEFAULT_REVISION: &str = "main";
+/// Where the image goes in a prompt
+const IMAGE_PLACEHOLDER: &str = "<image>";
+
+/// The LLaVA model used to describe images
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LlavaArchitecture {
+    #[default]
+    Vicuna7b,
+    Vicuna13b,
+}
+
+impl LlavaArchitecture {
+    pub fn hf_repo_id(&self) -> &'static str {
+        match self {
+            LlavaArchitecture::Vicuna7b => "llava-hf/llava-v1.6-vicuna-7b-hf",
+            LlavaArchitecture::Vicuna13b => "llava-hf/llava-v1.6-vicuna-13b-hf",
+        }
+    }
+
+    /// The prompt in the model's conversation format, with the image before the question
+    fn prompt(&self, question: &str) -> String {
+        match self {
+            LlavaArchitecture::Vicuna7b | LlavaArchitecture::Vicuna13b => format!(
+                "A chat between a curious human and an artificial intelligence assistant. \
+                The assistant gives helpful, detailed, and polite answers to the human's questions. \
+                USER: {IMAGE_PLACEHOLDER}\n{question} ASSISTANT:"
+            ),
+        }
+    }
+}
+
+fn default_revision() -> String {
+    DEFAULT_REVISION.to_string()
+}
+
+/// Configuration for loading a [`Describer`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DescriberConfig {
+    #[serde(default)]
+    pub variant: LlavaArchitecture,
+    #[serde(default)]
+    pub device: crate::device::Device,
+    #[serde(default = "default_revision")]
+    pub revision: String,
+}
+
+const fn default_max_tokens() -> usize {
+    256
+}
+
+/// How an answer is generated
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DescribeOptions {
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    /// The answer is greedy if this isn't set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Default for DescribeOptions {
+    fn default() -> Self {
+        DescribeOptions {
+            max_tokens: default_max_tokens(),
+            temperature: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Description {
+    pub text: String,
+    /// The number of generated tokens
+    pub tokens: usize,
+}
+
+/// A loaded LLaVA model that answers questions about images
+pub struct Describer {
+    model: LLaVA,
+    config: LLaVAConfig,
+    tokenizer: Tokenizer,
+    preprocessor: ImagePreprocessor,
+    variant: LlavaArchitecture,
+    device: Device,
+    dtype: DType,
+}
+
+impl Describer {
+    pub async fn from_config(config: &DescriberConfig) -> anyhow::Result<Self> {
+        let repo = HubRepo::model(config.variant.hf_repo_id(), &config.revision)?;
+        let read_json = |file: std::path::PathBuf| -> anyhow::Result<Vec<u8>> {
+            std::fs::read(&file).with_context(|| format!("unable to read {file:?}"))
+        };
+        let hf_config: HFLLaVAConfig =
+            serde_json::from_slice(&read_json(repo.get("config.json").await?)?)?;
+        let generation_config: HFGenerationConfig =
+            serde_json::from_slice(&read_json(repo.get("generation_config.json").await?)?)?;
+        let preprocessor_json = read_json(repo.get("preprocessor_config.json").await?)?;
+        let hf_preprocessor: HFPreProcessorConfig = serde_json::from_slice(&preprocessor_json)?;
+        let preprocessor: ImagePreprocessor = serde_json::from_slice(&preprocessor_json)?;
+        let tokenizer =
+            Tokenizer::from_file(repo.get("tokenizer.json").await?).map_err(anyhow::Error::msg)?;
+        let weight_files = hub_load_safetensors(&repo, "model.safetensors.index.json").await?;
+
+        let llava_config = hf_config.to_llava_config(&generation_config, &hf_preprocessor);
+        let device: Device = config.device.try_into()?;
+        // half precision is slow on CPUs
+        let dtype = if device.is_cpu() {
+            DType::F32
+        } else {
+            DType::F16
+        };
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&weight_files, dtype, &device)? };
+        let model = LLaVA::load(vb, &llava_config, Some(hf_config.to_clip_vision_config()))?;
+
+        tracing::info!(variant = ?config.variant, "loaded llava model");
+
+        Ok(Describer {
+            model,
+            config: llava_config,
+            tokenizer,
+            preprocessor,
+            variant: config.variant,
+            device,
+            dtype,
+        })
+    }
+
+    /// Answer a question about an image
+    pub fn describe(
+        &self,
+        image: &DynamicImage,
+        question: &str,
+        options: &DescribeOptions,
+    ) -> anyhow::Result<Description> {
+        let pixels = self
+            .preprocessor
+            .preprocess_anyres(image, &self.config.image_grid_pinpoints)?
+            .to_dtype(self.dtype)?
+            .to_device(&self.device)?;
+        let input_ids = self.input_ids(&self.variant.prompt(question))?;
+        let mut embeddings = self.model.prepare_inputs_labels_for_multimodal(
+            &input_ids,
+            &[pixels],
+            &[image.dimensions()],
+        )?;
+
+        let mut cache = Cache::new(
+            true,
+            self.dtype,
+            &self.config.to_llama_config(),
+            &self.device,
+        )?;
+        let mut logits_processor = LogitsProcessor::new(options.seed, options.temperature, None);
+        let eos_token = self.config.eos_token_id as u32;
+
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        for _ in 0..options.max_tokens {
+            // the whole prompt is run first, then only the newest token
+            let (_, len, _) = embeddings.dims3()?;
+            let context = if tokens.is_empty() { len } else { 1 };
+            let input = embeddings.i((.., len - context.., ..))?;
+            let logits = self
+                .model
+                .forward(&input, position, &mut cache)?
+                .squeeze(0)?;
+            position += context;
+
+            let token = logits_processor.sample(&logits.to_dtype(DType::F32)?)?;
+            if token == eos_token {
+                break;
+            }
+            tokens.push(token);
+
+            let token = Tensor::new(&[token], &self.device)?;
+            let next = self.model.llama.embed(&token)?.unsqueeze(0)?;
+            embeddings = Tensor::cat(&[embeddings, next], 1)?;
+        }
+
+        let text = self
+            .tokenizer
+            .decode(&tokens, true)
+            .map_err(anyhow::Error::msg)?;
+        Ok(Description {
+            text: text.trim().to_string(),
+            tokens: tokens.len(),
+        })
+    }
+
+    /// The prompt's token IDs with the image token where the placeholder is
+    fn input_ids(&self, prompt: &str) -> anyhow::Result<Tensor> {
+        let (before, after) = prompt
+            .split_once(IMAGE_PLACEHOLDER)
+            .ok_or(anyhow!("the prompt has no {IMAGE_PLACEHOLDER} placeholder"))?;
+        let encode = |text: &str| -> anyhow::Result<Vec<i64>> {
+            let encoding = self
+                .tokenizer
+                .encode(text, false)
+                .map_err(anyhow::Error::msg)?;
+            Ok(encoding.get_ids().iter().map(|&id| id as i64).collect())
+        };
+
+        let mut ids = vec![self.config.bos_token_id as i64];
+        ids.extend(encode(before)?);
+        ids.push(self.config.image_token_index as i64);
+        ids.extend(encode(after)?);
+        let len = ids.len();
+        Ok(Tensor::from_vec(ids, (1, len), &Device::Cpu)?)
+    }
+}
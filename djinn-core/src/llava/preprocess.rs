@@ -0,0 +1,174 @@
+//! Image preprocessing for LLaVA-NeXT's "anyres" vision tower input:
+//! the whole image at the tower's resolution,
+//! followed by crops of the image at a higher resolution that fits its aspect ratio
+
+use candle_core::{DType, Device, Tensor};
+use image::{imageops::FilterType, DynamicImage, GenericImageView as _, Rgb, RgbImage};
+use serde::Deserialize;
+
+/// The parts of a HuggingFace `preprocessor_config.json` used for CLIP images
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImagePreprocessor {
+    /// The shortest side of an image after it's resized
+    pub size: ShortestEdge,
+    /// The size of the square cropped from the center of the resized image
+    pub crop_size: CropSize,
+    pub image_mean: [f32; 3],
+    pub image_std: [f32; 3],
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShortestEdge {
+    pub shortest_edge: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CropSize {
+    pub height: u32,
+    pub width: u32,
+}
+
+impl ImagePreprocessor {
+    /// Resize, center crop, and normalize an image into a `(3, height, width)` tensor
+    pub fn preprocess(&self, image: &DynamicImage) -> candle_core::Result<Tensor> {
+        let (width, height) = image.dimensions();
+        let shortest_edge = self.size.shortest_edge;
+        let (new_width, new_height) = if width < height {
+            let scaled = (height as f64 * shortest_edge as f64 / width as f64).round() as u32;
+            (shortest_edge, scaled)
+        } else {
+            let scaled = (width as f64 * shortest_edge as f64 / height as f64).round() as u32;
+            (scaled, shortest_edge)
+        };
+        let resized = image.resize_exact(new_width, new_height, FilterType::CatmullRom);
+
+        let (crop_width, crop_height) = (self.crop_size.width, self.crop_size.height);
+        let cropped = resized
+            .crop_imm(
+                new_width.saturating_sub(crop_width) / 2,
+                new_height.saturating_sub(crop_height) / 2,
+                crop_width,
+                crop_height,
+            )
+            .to_rgb8();
+
+        let pixels = Tensor::from_vec(
+            cropped.into_raw(),
+            (crop_height as usize, crop_width as usize, 3),
+            &Device::Cpu,
+        )?
+        .permute((2, 0, 1))?
+        .to_dtype(DType::F32)?;
+        let mean = Tensor::new(&self.image_mean, &Device::Cpu)?.reshape((3, 1, 1))?;
+        let std = Tensor::new(&self.image_std, &Device::Cpu)?.reshape((3, 1, 1))?;
+        (pixels / 255.)?.broadcast_sub(&mean)?.broadcast_div(&std)
+    }
+
+    /// Preprocess the whole image and its patches at the best of the `resolutions`
+    /// into a `(patches + 1, 3, height, width)` tensor
+    pub fn preprocess_anyres(
+        &self,
+        image: &DynamicImage,
+        resolutions: &[(u32, u32)],
+    ) -> candle_core::Result<Tensor> {
+        let resolution = best_resolution(image.dimensions(), resolutions);
+        let padded = resize_and_pad(image, resolution);
+
+        let mut patches = vec![self.preprocess(image)?];
+        for (x, y) in patch_origins(resolution, self.crop_size.width) {
+            let patch = padded.crop_imm(x, y, self.crop_size.width, self.crop_size.width);
+            patches.push(self.preprocess(&patch)?);
+        }
+        Tensor::stack(&patches, 0)
+    }
+}
+
+/// The resolution that keeps the most of the image's detail when it's scaled to fit,
+/// and wastes the least space on padding when that's a tie
+fn best_resolution(size: (u32, u32), resolutions: &[(u32, u32)]) -> (u32, u32) {
+    let (width, height) = (size.0 as f64, size.1 as f64);
+    let mut best = size;
+    let mut best_effective = 0.;
+    let mut least_wasted = f64::INFINITY;
+    for &(target_width, target_height) in resolutions {
+        let scale = f64::min(target_width as f64 / width, target_height as f64 / height);
+        let downscaled = (width * scale).floor() * (height * scale).floor();
+        let effective = downscaled.min(width * height);
+        let wasted = target_width as f64 * target_height as f64 - effective;
+        if effective > best_effective || (effective == best_effective && wasted < least_wasted) {
+            best = (target_width, target_height);
+            best_effective = effective;
+            least_wasted = wasted;
+        }
+    }
+    best
+}
+
+/// Scale an image to fit the target size and center it on black padding
+fn resize_and_pad(image: &DynamicImage, target: (u32, u32)) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let (target_width, target_height) = target;
+    let scale_width = target_width as f64 / width as f64;
+    let scale_height = target_height as f64 / height as f64;
+    let (new_width, new_height) = if scale_width < scale_height {
+        let scaled = (height as f64 * scale_width).ceil() as u32;
+        (target_width, scaled.min(target_height))
+    } else {
+        let scaled = (width as f64 * scale_height).ceil() as u32;
+        (scaled.min(target_width), target_height)
+    };
+    let resized = image
+        .resize_exact(new_width, new_height, FilterType::CatmullRom)
+        .to_rgb8();
+
+    let mut padded = RgbImage::from_pixel(target_width, target_height, Rgb([0, 0, 0]));
+    image::imageops::overlay(
+        &mut padded,
+        &resized,
+        ((target_width - new_width) / 2).into(),
+        ((target_height - new_height) / 2).into(),
+    );
+    DynamicImage::ImageRgb8(padded)
+}
+
+/// The top left corners of the square patches that tile a size, row by row
+fn patch_origins(size: (u32, u32), patch_size: u32) -> Vec<(u32, u32)> {
+    let (width, height) = size;
+    (0..height)
+        .step_by(patch_size as usize)
+        .flat_map(|y| (0..width).step_by(patch_size as usize).map(move |x| (x, y)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTIONS: [(u32, u32); 5] =
+        [(336, 672), (672, 336), (672, 672), (1008, 336), (336, 1008)];
+
+    #[test]
+    fn picks_the_resolution_that_fits_the_aspect_ratio() {
+        assert_eq!(best_resolution((1000, 500), &RESOLUTIONS), (672, 336));
+        assert_eq!(best_resolution((400, 1200), &RESOLUTIONS), (336, 1008));
+        assert_eq!(best_resolution((2000, 2000), &RESOLUTIONS), (672, 672));
+        // small images fit in all of them, so the least padding wins
+        assert_eq!(best_resolution((300, 300), &RESOLUTIONS), (336, 672));
+    }
+
+    #[test]
+    fn tiles_patches_row_by_row() {
+        assert_eq!(patch_origins((672, 336), 336), [(0, 0), (336, 0)]);
+        assert_eq!(patch_origins((672, 672), 336).len(), 4);
+        assert_eq!(patch_origins((672, 672), 336)[2], (0, 336));
+    }
+
+    #[test]
+    fn pads_to_the_target_size() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(100, 50, Rgb([255, 255, 255])));
+        let padded = resize_and_pad(&image, (336, 336)).to_rgb8();
+        assert_eq!(padded.dimensions(), (336, 336));
+        assert_eq!(padded.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(padded.get_pixel(168, 168).0, [255, 255, 255]);
+    }
+}
@@ -0,0 +1,170 @@
+//! Beam search keeps the most likely sequences at each step instead of sampling one,
+//! so several ranked completions can be returned.
+//! See [`ModelContext::beam_search`](super::model::ModelContext::beam_search).
+
+use serde::{Deserialize, Serialize};
+
+/// A completion found by beam search
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    pub text: String,
+    /// The sum of the log probabilities of the generated tokens
+    pub logprob: f32,
+    /// The log probability per generated token, which candidates are ranked by
+    pub score: f32,
+    /// The number of generated tokens
+    pub tokens: usize,
+}
+
+/// Generated tokens and their summed log probability
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Beam {
+    pub tokens: Vec<u32>,
+    pub logprob: f32,
+}
+
+impl Beam {
+    /// The log probability per token, so that longer beams aren't ranked lower for their length
+    pub fn score(&self) -> f32 {
+        if self.tokens.is_empty() {
+            0.
+        } else {
+            self.logprob / self.tokens.len() as f32
+        }
+    }
+}
+
+/// A token that the beam at index `beam` could be extended with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Extension {
+    pub beam: usize,
+    pub token: u32,
+    /// The log probability of the token
+    pub logprob: f32,
+}
+
+/// The beams that are being extended and the ones that finished
+#[derive(Clone, Debug)]
+pub struct BeamSearch {
+    num_beams: usize,
+    beams: Vec<Beam>,
+    finished: Vec<Beam>,
+}
+
+impl BeamSearch {
+    pub fn new(num_beams: usize) -> Self {
+        BeamSearch {
+            num_beams: num_beams.max(1),
+            beams: vec![Beam::default()],
+            finished: Vec::new(),
+        }
+    }
+
+    /// The beams to extend in the next step
+    pub fn beams(&self) -> &[Beam] {
+        &self.beams
+    }
+
+    /// Keep the `num_beams` most likely extensions of the current beams.
+    /// A beam finishes when `is_end` returns true for it, e.g. after an EOS token,
+    /// if it's among the `num_beams` most likely extensions.
+    pub fn step(&mut self, mut extensions: Vec<Extension>, is_end: impl Fn(&Beam) -> bool) {
+        extensions.sort_by(|a, b| {
+            let a = self.beams[a.beam].logprob + a.logprob;
+            let b = self.beams[b.beam].logprob + b.logprob;
+            b.total_cmp(&a)
+        });
+
+        let mut beams = Vec::with_capacity(self.num_beams);
+        for (rank, extension) in extensions.into_iter().enumerate() {
+            if beams.len() == self.num_beams {
+                break;
+            }
+            let parent = &self.beams[extension.beam];
+            let mut tokens = parent.tokens.clone();
+            tokens.push(extension.token);
+            let beam = Beam {
+                tokens,
+                logprob: parent.logprob + extension.logprob,
+            };
+            if !is_end(&beam) {
+                beams.push(beam);
+            } else if rank < self.num_beams {
+                self.finished.push(beam);
+            }
+        }
+        self.beams = beams;
+    }
+
+    /// True once `num_beams` beams finished or none are left to extend
+    pub fn is_done(&self) -> bool {
+        self.finished.len() >= self.num_beams || self.beams.is_empty()
+    }
+
+    /// The finished beams and the ones that were cut off, best first
+    pub fn finish(self) -> Vec<Beam> {
+        let mut beams = self.finished;
+        beams.extend(self.beams);
+        beams.sort_by(|a, b| b.score().total_cmp(&a.score()));
+        beams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EOS: u32 = 0;
+
+    fn extend(beam: usize, tokens: &[(u32, f32)]) -> Vec<Extension> {
+        tokens
+            .iter()
+            .map(|&(token, logprob)| Extension {
+                beam,
+                token,
+                logprob,
+            })
+            .collect()
+    }
+
+    fn ends(beam: &Beam) -> bool {
+        beam.tokens.last() == Some(&EOS)
+    }
+
+    #[test]
+    fn keeps_the_most_likely_beams() {
+        let mut search = BeamSearch::new(2);
+        search.step(extend(0, &[(1, -0.5), (2, -1.0), (3, -2.0)]), ends);
+        assert_eq!(search.beams().len(), 2);
+        assert_eq!(search.beams()[0].tokens, [1]);
+        assert_eq!(search.beams()[1].tokens, [2]);
+
+        // the second beam's continuation is more likely overall
+        let mut extensions = extend(0, &[(4, -3.0), (5, -3.5)]);
+        extensions.extend(extend(1, &[(6, -0.1), (EOS, -0.2)]));
+        search.step(extensions, ends);
+        assert_eq!(search.beams()[0].tokens, [2, 6]);
+        assert_eq!(search.beams()[1].tokens, [1, 4]);
+        assert!(!search.is_done());
+
+        let beams = search.finish();
+        assert_eq!(beams.len(), 3);
+        assert_eq!(beams[0].tokens, [2, 6]);
+        assert_eq!(beams[1].tokens, [2, EOS]);
+        assert!((beams[1].score() + 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stops_when_enough_beams_finished() {
+        let mut search = BeamSearch::new(1);
+        search.step(extend(0, &[(EOS, -0.1), (1, -0.2)]), ends);
+        assert!(search.is_done());
+        assert_eq!(search.finish()[0].tokens, [EOS]);
+
+        // unlikely ends are dropped
+        let mut search = BeamSearch::new(1);
+        search.step(extend(0, &[(1, -0.1), (EOS, -0.2)]), ends);
+        assert!(!search.is_done());
+        assert_eq!(search.finish().len(), 1);
+    }
+}
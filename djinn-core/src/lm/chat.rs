@@ -0,0 +1,96 @@
+//! Prompt formats for multi-turn chat
+
+/// How a model expects the turns of a conversation to be formatted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChatTemplate {
+    /// Written before the system prompt
+    pub system_prefix: &'static str,
+    /// Written after the system prompt.
+    /// Models without a system role get the system prompt as plain text
+    /// before the first user turn.
+    pub system_suffix: &'static str,
+    /// Written before each user message
+    pub user_prefix: &'static str,
+    /// Written after each user message, starting the model's reply
+    pub user_suffix: &'static str,
+    /// Written after each reply
+    pub reply_suffix: &'static str,
+}
+
+impl ChatTemplate {
+    pub const MISTRAL: ChatTemplate = ChatTemplate {
+        system_prefix: "",
+        system_suffix: "\n\n",
+        user_prefix: "[INST] ",
+        user_suffix: " [/INST]",
+        reply_suffix: "</s>",
+    };
+
+    pub const LLAMA3: ChatTemplate = ChatTemplate {
+        system_prefix: "<|start_header_id|>system<|end_header_id|>\n\n",
+        system_suffix: "<|eot_id|>",
+        user_prefix: "<|start_header_id|>user<|end_header_id|>\n\n",
+        user_suffix: "<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n",
+        reply_suffix: "<|eot_id|>",
+    };
+
+    pub const PHI3: ChatTemplate = ChatTemplate {
+        system_prefix: "<|system|>\n",
+        system_suffix: "<|end|>\n",
+        user_prefix: "<|user|>\n",
+        user_suffix: "<|end|>\n<|assistant|>\n",
+        reply_suffix: "<|end|>\n",
+    };
+
+    pub const GEMMA: ChatTemplate = ChatTemplate {
+        system_prefix: "",
+        system_suffix: "\n\n",
+        user_prefix: "<start_of_turn>user\n",
+        user_suffix: "<end_of_turn>\n<start_of_turn>model\n",
+        reply_suffix: "<end_of_turn>\n",
+    };
+
+    /// For models without a chat format
+    pub const PLAIN: ChatTemplate = ChatTemplate {
+        system_prefix: "",
+        system_suffix: "\n\n",
+        user_prefix: "",
+        user_suffix: "\n",
+        reply_suffix: "\n",
+    };
+
+    /// The text for a system prompt, written at the start of the conversation
+    pub fn system(&self, prompt: &str) -> String {
+        format!("{}{prompt}{}", self.system_prefix, self.system_suffix)
+    }
+
+    /// The text for a user turn, ending where the model's reply starts
+    pub fn user_turn(&self, message: &str) -> String {
+        format!("{}{message}{}", self.user_prefix, self.user_suffix)
+    }
+
+    /// The text for a finished reply
+    pub fn reply(&self, reply: &str) -> String {
+        format!("{reply}{}", self.reply_suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_mistral_turns() {
+        let template = ChatTemplate::MISTRAL;
+        let transcript = [
+            template.user_turn("hi"),
+            template.reply("hello"),
+            template.user_turn("how are you?"),
+        ]
+        .concat();
+        assert_eq!(
+            transcript,
+            "[INST] hi [/INST]hello</s>[INST] how are you? [/INST]"
+        );
+    }
+}
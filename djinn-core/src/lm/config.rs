@@ -1,12 +1,16 @@
-use std::path::Path;
+use std::{
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
+use clap::ValueEnum;
 #[cfg(not(feature = "fixed-seed"))]
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    device::Device,
-    lm::{model::ModelArchitecture, ModelSource},
+    device::{Device, DeviceSpec},
+    lm::{model::ModelArchitecture, prefix_cache::DEFAULT_MAX_CACHED_TOKENS, ModelSource},
 };
 
 pub const DEFAULT_SAMPLE_LEN: usize = 100;
@@ -15,11 +19,19 @@ pub const DEFAULT_REPEAT_LAST_N: usize = 64;
 pub const DEFAULT_REPEAT_PENALTY: f32 = 1.1;
 pub const DEFAULT_TEMPERATURE: f64 = 1e-7;
 pub const DEFAULT_TOP_P: Option<f64> = None;
+pub const DEFAULT_ECHO_PROMPT: bool = true;
+pub const DEFAULT_SPECULATIVE: bool = true;
+pub const DEFAULT_DRAFT_TOKENS: usize = 4;
+/// The most alternatives that can be reported for each generated token
+pub const MAX_LOGPROBS: usize = 20;
+/// The most beams a beam search can keep
+pub const MAX_BEAMS: usize = 16;
 
 const fn default_sample_len() -> usize {
     DEFAULT_SAMPLE_LEN
 }
 
+/// A random seed, or [`DEFAULT_SEED`] with the `fixed-seed` feature
 fn default_seed() -> u64 {
     cfg_if::cfg_if! {
         if #[cfg(feature = "fixed-seed")] {
@@ -47,6 +59,19 @@ const fn default_temperature() -> f64 {
 const fn default_top_p() -> Option<f64> {
     DEFAULT_TOP_P
 }
+const fn default_echo_prompt() -> bool {
+    DEFAULT_ECHO_PROMPT
+}
+const fn default_speculative() -> bool {
+    DEFAULT_SPECULATIVE
+}
+const fn default_draft_tokens() -> usize {
+    DEFAULT_DRAFT_TOKENS
+}
+
+const fn default_prefix_cache_tokens() -> usize {
+    DEFAULT_MAX_CACHED_TOKENS
+}
 
 /// The results of a model run
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -61,8 +86,12 @@ pub struct ModelRun {
 pub struct RunConfig {
     #[serde(default = "default_sample_len")]
     pub sample_len: usize,
-    #[serde(default = "default_seed")]
-    pub seed: u64,
+    /// The seed used to sample tokens.
+    /// A random seed is picked for each run if none is given,
+    /// unless the run is `deterministic`.
+    /// The seed that was used is reported in [`RunStats`](super::model::RunStats)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
     #[serde(default = "default_repeat_last_n")]
     pub repeat_last_n: usize,
     #[serde(default = "default_repeat_penalty")]
@@ -71,17 +100,126 @@ pub struct RunConfig {
     pub temperature: f64,
     #[serde(default = "default_top_p", skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
+    /// Only sample from the `top_k` most likely tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<usize>,
+    /// Only sample tokens with a probability of at least `min_p`
+    /// relative to the most likely token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    /// Locally typical sampling probability mass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typical_p: Option<f64>,
+    /// Generation stops when any of these strings are generated.
+    /// The stop sequence itself is not included in the output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Set false to only stream generated tokens and not the prompt
+    #[serde(default = "default_echo_prompt")]
+    pub echo_prompt: bool,
+    /// What to do when the prompt and generation don't fit in the model's context
+    #[serde(default)]
+    pub context_overflow: ContextOverflow,
+    /// Constrain the generated text to a format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    /// A GBNF grammar the generated text must match, starting at its `root` rule.
+    /// Can't be combined with `format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// Use the draft model for speculative decoding, if one is loaded
+    #[serde(default = "default_speculative")]
+    pub speculative: bool,
+    /// Report the log probability of each generated token
+    /// and of this many of the most likely alternatives.
+    /// Speculative decoding is disabled when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<usize>,
+    /// Make the output reproducible.
+    /// [`DEFAULT_SEED`] is used if no seed is given,
+    /// and speculative decoding and reuse of the KV cache between runs are disabled,
+    /// so that every run computes the same logits from scratch.
+    ///
+    /// The same prompt, config, and seed give the same output
+    /// with the same model weights, device, and build of djinn.
+    /// Different hardware, GPU drivers, or candle versions can change
+    /// floating point results and so the output.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deterministic: bool,
+    /// Search this many beams instead of sampling.
+    /// Beam search ignores the sampling options and returns whole completions,
+    /// see [`ModelContext::beam_search`](super::model::ModelContext::beam_search)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_beams: Option<usize>,
+    /// Return this many completions from a beam search, most likely first.
+    /// At least this many beams are searched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n_best: Option<usize>,
+}
+
+/// Formats that generation can be constrained to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// A single JSON value. Generation stops when the value is complete.
+    Json,
+}
+
+/// Strategies for prompts and generations that are longer than the model's context length
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflow {
+    /// Return an error if the prompt and `sample_len` don't fit
+    Error,
+    /// Drop the oldest prompt tokens so that the prompt and `sample_len` fit.
+    /// Generation stops when the context is full.
+    #[default]
+    Truncate,
+    /// Drop the oldest tokens and keep generating when the context is full
+    SlidingWindow,
 }
 
 impl Default for RunConfig {
     fn default() -> Self {
         RunConfig {
             sample_len: DEFAULT_SAMPLE_LEN,
-            seed: DEFAULT_SEED,
+            seed: Some(DEFAULT_SEED),
             repeat_last_n: DEFAULT_REPEAT_LAST_N,
             repeat_penalty: DEFAULT_REPEAT_PENALTY,
             temperature: DEFAULT_TEMPERATURE,
             top_p: DEFAULT_TOP_P,
+            top_k: None,
+            min_p: None,
+            typical_p: None,
+            stop: Vec::new(),
+            echo_prompt: DEFAULT_ECHO_PROMPT,
+            context_overflow: ContextOverflow::default(),
+            format: None,
+            grammar: None,
+            speculative: DEFAULT_SPECULATIVE,
+            logprobs: None,
+            deterministic: false,
+            num_beams: None,
+            n_best: None,
+        }
+    }
+}
+
+impl RunConfig {
+    /// The number of beams to search, if beam search is used instead of sampling
+    pub fn num_beams(&self) -> Option<usize> {
+        match (self.num_beams, self.n_best) {
+            (None, None) => None,
+            (num_beams, n_best) => Some(num_beams.unwrap_or(1).max(n_best.unwrap_or(1))),
+        }
+    }
+
+    /// The seed to use for a run
+    pub fn resolve_seed(&self) -> u64 {
+        match self.seed {
+            Some(seed) => seed,
+            None if self.deterministic => DEFAULT_SEED,
+            None => default_seed(),
         }
     }
 }
@@ -97,7 +235,79 @@ pub struct ModelConfig {
     pub variant: ModelArchitecture,
     #[serde(default)]
     pub device: Device,
+    /// Split the model's layers across these devices, e.g. `["cuda:0", "cuda:1"]`.
+    /// Overrides `device` when set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceSpec>,
     /// Set true to use flash attention. Only supported on CUDA
     pub flash_attn: bool,
     pub model_source: ModelSource,
+    /// The maximum number of tokens kept in the KV cache between runs,
+    /// so prompts that extend the previous one skip recomputing it.
+    /// 0 disables prefix caching
+    #[serde(default = "default_prefix_cache_tokens")]
+    pub prefix_cache_tokens: usize,
+    /// A LoRA adapter directory (PEFT format) to merge into the weights.
+    /// Not supported for quantized or Starcoder models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapter: Option<PathBuf>,
+    /// A smaller model that proposes tokens for speculative decoding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<DraftConfig>,
+    /// The number of threads the CPU kernels use.
+    /// This is process wide, so it's read from the configs when djinn starts
+    /// and `djinn --threads` overrides it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<NonZeroUsize>,
+    /// Memory map the safetensors weights or read them into memory up front
+    #[serde(default)]
+    pub weight_loading: WeightLoading,
+    /// Convert the weights to this type instead of the device's default,
+    /// BF16 on CUDA and F32 elsewhere. Not supported for quantized models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtype: Option<WeightDType>,
+}
+
+/// How safetensors weights are read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightLoading {
+    /// Map the files into memory so pages are read as they're used
+    /// and shared with other processes that load the same files
+    #[default]
+    Mmap,
+    /// Read the whole files into memory while the model loads,
+    /// so the first requests don't wait on disk reads
+    Full,
+}
+
+/// Floating point types the weights can be converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightDType {
+    F16,
+    Bf16,
+    F32,
+}
+
+impl From<WeightDType> for candle_core::DType {
+    fn from(dtype: WeightDType) -> Self {
+        match dtype {
+            WeightDType::F16 => candle_core::DType::F16,
+            WeightDType::Bf16 => candle_core::DType::BF16,
+            WeightDType::F32 => candle_core::DType::F32,
+        }
+    }
+}
+
+/// The draft model for speculative decoding.
+/// It must use the same tokenizer as the main model,
+/// and both models must support speculation, see [`ModelArchitecture::supports_speculation`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DraftConfig {
+    pub variant: ModelArchitecture,
+    pub model_source: ModelSource,
+    /// The number of tokens proposed per step
+    #[serde(default = "default_draft_tokens")]
+    pub tokens: usize,
 }
@@ -0,0 +1,68 @@
+//! Constraints on the generated text that are checked before each token is sampled
+
+use anyhow::anyhow;
+
+use crate::error::Result;
+
+use super::{config::OutputFormat, grammar::Grammar, json::JsonValidator};
+
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    Json(JsonValidator),
+    Grammar(Grammar),
+}
+
+impl Constraint {
+    /// The constraint of a run, if it has one
+    pub fn new(format: Option<OutputFormat>, grammar: Option<&str>) -> Result<Option<Self>> {
+        match (format, grammar) {
+            (Some(_), Some(_)) => Err(anyhow!("`format` and `grammar` can't both be set").into()),
+            (Some(OutputFormat::Json), None) => {
+                Ok(Some(Constraint::Json(JsonValidator::default())))
+            }
+            (None, Some(grammar)) => Ok(Some(Constraint::Grammar(grammar.parse()?))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// True if `text` would keep the output valid
+    pub fn accepts(&self, text: &str) -> bool {
+        match self {
+            Constraint::Json(validator) => validator.accepts(text),
+            Constraint::Grammar(grammar) => grammar.accepts(text),
+        }
+    }
+
+    /// True if a token that is only part of a multi-byte character may be valid
+    pub fn accepts_partial_char(&self) -> bool {
+        match self {
+            Constraint::Json(validator) => validator.in_string(),
+            Constraint::Grammar(grammar) => grammar.allows_non_ascii(),
+        }
+    }
+
+    pub fn push_str(&mut self, text: &str) -> bool {
+        match self {
+            Constraint::Json(validator) => validator.push_str(text),
+            Constraint::Grammar(grammar) => grammar.push_str(text),
+        }
+    }
+
+    /// True if the output is valid as it is, so generation may end
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Constraint::Json(validator) => validator.is_complete(),
+            Constraint::Grammar(grammar) => grammar.is_complete(),
+        }
+    }
+
+    /// True if generation should stop because the output is complete.
+    /// A JSON value ends when it's complete,
+    /// while a grammar may allow more text after a complete match.
+    pub fn is_finished(&self) -> bool {
+        match self {
+            Constraint::Json(validator) => validator.is_complete(),
+            Constraint::Grammar(grammar) => !grammar.can_continue(),
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! Constrain generation to a GBNF grammar
+//!
+//! The grammar format is llama.cpp's GBNF:
+//!
+//! ```text
+//! root   ::= answer ("," ws answer)*
+//! answer ::= "yes" | "no" | [0-9]+
+//! ws     ::= [ \t\n]*
+//! ```
+//!
+//! Rules are made of string literals, character classes like `[a-z]` or `[^"]`,
+//! `.` for any character, references to other rules, groups in parentheses,
+//! and the repetitions `*`, `+`, `?`, `{m}`, `{m,}`, and `{m,n}`.
+//! Generation starts at the `root` rule.
+//!
+//! [`Grammar`] tracks every way the text so far can be parsed,
+//! one stack of rule positions for each, and accepts text one character at a time.
+//! Candidate tokens that would make the output invalid are masked out before sampling.
+
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+const ROOT: &str = "root";
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum GrammarError {
+    #[error("grammar syntax error on line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("the grammar has no `root` rule")]
+    MissingRoot,
+    #[error("rule `{0}` is used but never defined")]
+    UndefinedRule(String),
+    #[error("rule `{0}` is defined more than once")]
+    DuplicateRule(String),
+    #[error("rule `{0}` is left recursive, which can't be matched one character at a time")]
+    LeftRecursion(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Element {
+    /// One character in any of the ranges, or in none of them if `negated`
+    Char {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Rule(usize),
+}
+
+impl Element {
+    fn literal(c: char) -> Self {
+        Element::Char {
+            ranges: vec![(c, c)],
+            negated: false,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Element::Char { ranges, negated } => {
+                ranges
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&c))
+                    != *negated
+            }
+            Element::Rule(_) => false,
+        }
+    }
+}
+
+/// Alternatives, each a sequence of elements
+type Rule = Vec<Vec<Element>>;
+
+/// The next element of an alternative of a rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    rule: usize,
+    alternative: usize,
+    index: usize,
+}
+
+/// Positions in nested rules, innermost last
+type Stack = Vec<Position>;
+
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    rules: Arc<Vec<Rule>>,
+    /// The ways the text so far can be parsed.
+    /// The last position of each stack is a character,
+    /// and an empty stack means the text matches the root rule.
+    stacks: Vec<Stack>,
+}
+
+impl FromStr for Grammar {
+    type Err = GrammarError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (rules, root) = Parser::new(source).parse()?;
+        let mut grammar = Grammar {
+            rules: Arc::new(rules),
+            stacks: Vec::new(),
+        };
+        let mut stacks = Vec::new();
+        for alternative in 0..grammar.rules[root].len() {
+            grammar.expand(
+                vec![Position {
+                    rule: root,
+                    alternative,
+                    index: 0,
+                }],
+                &mut stacks,
+            );
+        }
+        stacks.sort();
+        stacks.dedup();
+        grammar.stacks = stacks;
+        Ok(grammar)
+    }
+}
+
+impl Grammar {
+    /// True if `text` would keep the output valid
+    pub fn accepts(&self, text: &str) -> bool {
+        let mut grammar = self.clone();
+        grammar.push_str(text)
+    }
+
+    /// Advance past `text`.
+    /// Returns false if the text is invalid, in which case no more text is accepted.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.push(c))
+    }
+
+    /// True if the text so far matches the whole grammar
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(Vec::is_empty)
+    }
+
+    /// True if more text can be added
+    pub fn can_continue(&self) -> bool {
+        self.stacks.iter().any(|stack| !stack.is_empty())
+    }
+
+    /// True if the next character can be outside of ASCII,
+    /// so a token that is part of a multi-byte character may be valid
+    pub fn allows_non_ascii(&self) -> bool {
+        self.stacks
+            .iter()
+            .filter_map(|stack| self.next_element(stack))
+            .any(|element| match element {
+                Element::Char { negated: true, .. } => true,
+                Element::Char { ranges, .. } => ranges.iter().any(|(_start, end)| !end.is_ascii()),
+                Element::Rule(_) => false,
+            })
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        let mut stacks = Vec::new();
+        for stack in &self.stacks {
+            if !self
+                .next_element(stack)
+                .is_some_and(|element| element.matches(c))
+            {
+                continue;
+            }
+            let mut stack = stack.clone();
+            if let Some(top) = stack.last_mut() {
+                top.index += 1;
+            }
+            self.expand(stack, &mut stacks);
+        }
+        stacks.sort();
+        stacks.dedup();
+        self.stacks = stacks;
+        !self.stacks.is_empty()
+    }
+
+    fn next_element(&self, stack: &Stack) -> Option<&Element> {
+        let top = stack.last()?;
+        self.rules[top.rule][top.alternative].get(top.index)
+    }
+
+    /// Add the stacks that `stack` can become
+    /// when rules are entered or finished without reading a character
+    fn expand(&self, mut stack: Stack, stacks: &mut Vec<Stack>) {
+        // leave finished alternatives
+        while let Some(top) = stack.last() {
+            if top.index < self.rules[top.rule][top.alternative].len() {
+                break;
+            }
+            stack.pop();
+        }
+
+        match self.next_element(&stack) {
+            Some(Element::Rule(rule)) => {
+                let rule = *rule;
+                if let Some(top) = stack.last_mut() {
+                    top.index += 1;
+                }
+                for alternative in 0..self.rules[rule].len() {
+                    let mut stack = stack.clone();
+                    stack.push(Position {
+                        rule,
+                        alternative,
+                        index: 0,
+                    });
+                    self.expand(stack, stacks);
+                }
+            }
+            Some(Element::Char { .. }) | None => stacks.push(stack),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    rules: Vec<Option<Rule>>,
+}
+
+impl Parser {
+    fn new(source: &str) -> Self {
+        Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+            names: Vec::new(),
+            ids: HashMap::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// The rules and the index of the root rule
+    fn parse(mut self) -> Result<(Vec<Rule>, usize), GrammarError> {
+        loop {
+            self.skip_space(true);
+            if self.peek().is_none() {
+                break;
+            }
+            let name = self.parse_name()?;
+            self.skip_space(false);
+            if !self.eat_str("::=") {
+                return Err(self.error("expected `::=` after the rule name"));
+            }
+            self.skip_space(true);
+            let alternatives = self.parse_alternatives(&name, false)?;
+            let id = self.rule_id(&name);
+            if self.rules[id].is_some() {
+                return Err(GrammarError::DuplicateRule(name));
+            }
+            self.rules[id] = Some(alternatives);
+        }
+
+        let root = *self.ids.get(ROOT).ok_or(GrammarError::MissingRoot)?;
+        let rules = self
+            .rules
+            .into_iter()
+            .zip(&self.names)
+            .map(|(rule, name)| rule.ok_or_else(|| GrammarError::UndefinedRule(name.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        check_left_recursion(&rules, &self.names)?;
+        Ok((rules, root))
+    }
+
+    fn parse_alternatives(&mut self, name: &str, nested: bool) -> Result<Rule, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence(name, nested)?];
+        while self.eat('|') {
+            self.skip_space(true);
+            alternatives.push(self.parse_sequence(name, nested)?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self, name: &str, nested: bool) -> Result<Vec<Element>, GrammarError> {
+        let mut sequence = Vec::new();
+        // where the last item starts, which a repetition applies to
+        let mut item_start = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    item_start = sequence.len();
+                    while !self.eat('"') {
+                        let c = self.parse_char()?;
+                        sequence.push(Element::literal(c));
+                    }
+                }
+                '[' => {
+                    self.pos += 1;
+                    item_start = sequence.len();
+                    sequence.push(self.parse_class()?);
+                }
+                '.' => {
+                    self.pos += 1;
+                    item_start = sequence.len();
+                    sequence.push(Element::Char {
+                        ranges: Vec::new(),
+                        negated: true,
+                    });
+                }
+                '(' => {
+                    self.pos += 1;
+                    self.skip_space(true);
+                    let group = self.parse_alternatives(name, true)?;
+                    if !self.eat(')') {
+                        return Err(self.error("expected `)`"));
+                    }
+                    item_start = sequence.len();
+                    sequence.push(Element::Rule(self.add_rule(name, group)));
+                }
+                '*' | '+' | '?' | '{' => {
+                    if item_start == sequence.len() {
+                        return Err(self.error("a repetition must follow an item"));
+                    }
+                    self.pos += 1;
+                    let (min, max) = match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        '?' => (0, Some(1)),
+                        _ => self.parse_bounds()?,
+                    };
+                    let item = sequence.split_off(item_start);
+                    sequence.extend(self.repeat(name, item, min, max));
+                    item_start = sequence.len();
+                }
+                c if is_name_char(c) => {
+                    let reference = self.parse_name()?;
+                    item_start = sequence.len();
+                    sequence.push(Element::Rule(self.rule_id(&reference)));
+                }
+                _ => break,
+            }
+            self.skip_space(nested);
+        }
+        Ok(sequence)
+    }
+
+    /// `item` repeated between `min` and `max` times
+    fn repeat(
+        &mut self,
+        name: &str,
+        item: Vec<Element>,
+        min: usize,
+        max: Option<usize>,
+    ) -> Vec<Element> {
+        let mut sequence: Vec<Element> = (0..min).flat_map(|_| item.clone()).collect();
+        match max {
+            // rest ::= item rest |
+            None => {
+                let id = self.add_rule(name, Vec::new());
+                let mut repeated = item;
+                repeated.push(Element::Rule(id));
+                self.rules[id] = Some(vec![repeated, Vec::new()]);
+                sequence.push(Element::Rule(id));
+            }
+            // rest ::= item (item (...)?)? |
+            Some(max) => {
+                let mut optional: Option<usize> = None;
+                for _ in min..max {
+                    let mut repeated = item.clone();
+                    repeated.extend(optional.map(Element::Rule));
+                    optional = Some(self.add_rule(name, vec![repeated, Vec::new()]));
+                }
+                sequence.extend(optional.map(Element::Rule));
+            }
+        }
+        sequence
+    }
+
+    /// `{m}`, `{m,}`, or `{m,n}` after the `{`
+    fn parse_bounds(&mut self) -> Result<(usize, Option<usize>), GrammarError> {
+        self.skip_space(true);
+        let min = self.parse_number()?;
+        self.skip_space(true);
+        let max = if self.eat(',') {
+            self.skip_space(true);
+            match self.peek() {
+                Some(c) if c.is_ascii_digit() => Some(self.parse_number()?),
+                _ => None,
+            }
+        } else {
+            Some(min)
+        };
+        self.skip_space(true);
+        if !self.eat('}') {
+            return Err(self.error("expected `}`"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("the maximum repetition is less than the minimum"));
+        }
+        Ok((min, max))
+    }
+
+    fn parse_number(&mut self) -> Result<usize, GrammarError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| self.error("expected a number"))
+    }
+
+    /// A character class after the `[`
+    fn parse_class(&mut self) -> Result<Element, GrammarError> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        while !self.eat(']') {
+            let start = self.parse_char()?;
+            let end = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                self.parse_char()?
+            } else {
+                start
+            };
+            ranges.push((start, end));
+        }
+        Ok(Element::Char { ranges, negated })
+    }
+
+    /// A character in a literal or class, escapes included
+    fn parse_char(&mut self) -> Result<char, GrammarError> {
+        let c = self
+            .next()
+            .ok_or_else(|| self.error("unterminated literal or character class"))?;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = self
+            .next()
+            .ok_or_else(|| self.error("unterminated escape"))?;
+        let digits = match escaped {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            c => return Ok(c),
+        };
+        let start = self.pos;
+        self.pos = (self.pos + digits).min(self.chars.len());
+        let hex: String = self.chars[start..self.pos].iter().collect();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == digits)
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid character escape"))
+    }
+
+    fn parse_name(&mut self) -> Result<String, GrammarError> {
+        let start = self.pos;
+        while self.peek().is_some_and(is_name_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a rule name"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// The ID of a named rule, which may be defined later
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.rules.len();
+        self.rules.push(None);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// A rule for a group or repetition inside the rule `parent`
+    fn add_rule(&mut self, parent: &str, rule: Rule) -> usize {
+        let id = self.rules.len();
+        self.rules.push(Some(rule));
+        self.names.push(format!("{parent}-{id}"));
+        id
+    }
+
+    /// Skip spaces and comments, and newlines if `newlines` is set.
+    /// A newline ends a rule unless it's inside parentheses or after `|`.
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                ' ' | '\t' => self.pos += 1,
+                '\r' | '\n' if newlines => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_str(&mut self, text: &str) -> bool {
+        let found = text
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c));
+        if found {
+            self.pos += text.chars().count();
+        }
+        found
+    }
+
+    fn error(&self, message: &str) -> GrammarError {
+        let end = self.pos.min(self.chars.len());
+        let line = self.chars[..end].iter().filter(|c| **c == '\n').count() + 1;
+        GrammarError::Syntax {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Rules that can be entered again without reading a character would expand forever
+fn check_left_recursion(rules: &[Rule], names: &[String]) -> Result<(), GrammarError> {
+    // rules that can match empty text
+    let mut nullable = vec![false; rules.len()];
+    let is_nullable = |element: &Element, nullable: &[bool]| match element {
+        Element::Rule(rule) => nullable[*rule],
+        Element::Char { .. } => false,
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (id, rule) in rules.iter().enumerate() {
+            if !nullable[id]
+                && rule.iter().any(|alternative| {
+                    alternative
+                        .iter()
+                        .all(|element| is_nullable(element, &nullable))
+                })
+            {
+                nullable[id] = true;
+                changed = true;
+            }
+        }
+    }
+
+    // the rules each rule can enter before reading a character
+    let first: Vec<Vec<usize>> = rules
+        .iter()
+        .map(|rule| {
+            let mut first = Vec::new();
+            for alternative in rule {
+                for element in alternative {
+                    if let Element::Rule(rule) = element {
+                        first.push(*rule);
+                    }
+                    if !is_nullable(element, &nullable) {
+                        break;
+                    }
+                }
+            }
+            first
+        })
+        .collect();
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        New,
+        Active,
+        Done,
+    }
+    fn visit(id: usize, first: &[Vec<usize>], visits: &mut [Visit]) -> Option<usize> {
+        visits[id] = Visit::Active;
+        for &next in &first[id] {
+            match visits[next] {
+                Visit::Active => return Some(next),
+                Visit::New => {
+                    if let Some(cycle) = visit(next, first, visits) {
+                        return Some(cycle);
+                    }
+                }
+                Visit::Done => {}
+            }
+        }
+        visits[id] = Visit::Done;
+        None
+    }
+
+    let mut visits = vec![Visit::New; rules.len()];
+    for id in 0..rules.len() {
+        if visits[id] == Visit::New {
+            if let Some(cycle) = visit(id, &first, &mut visits) {
+                return Err(GrammarError::LeftRecursion(names[cycle].clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grammar(source: &str) -> Grammar {
+        source.parse().unwrap()
+    }
+
+    /// Whether `text` is valid and whether it's complete
+    fn check(grammar: &Grammar, text: &str) -> (bool, bool) {
+        let mut grammar = grammar.clone();
+        let valid = grammar.push_str(text);
+        (valid, valid && grammar.is_complete())
+    }
+
+    #[test]
+    fn matches_alternatives_and_repetitions() {
+        let grammar = grammar(
+            r#"
+            # a list of answers
+            root   ::= answer ("," ws answer)*
+            answer ::= "yes" | "no" | [0-9]+
+            ws     ::= [ \t\n]*
+            "#,
+        );
+
+        assert_eq!(check(&grammar, "yes"), (true, true));
+        assert_eq!(check(&grammar, "yes, no,\n42"), (true, true));
+        assert_eq!(check(&grammar, "ye"), (true, false));
+        assert_eq!(check(&grammar, "yes,"), (true, false));
+        assert_eq!(check(&grammar, "maybe"), (false, false));
+        assert_eq!(check(&grammar, "yes no"), (false, false));
+    }
+
+    #[test]
+    fn matches_classes_and_bounds() {
+        let grammar = grammar(
+            r#"root ::= "\"" [^"\n]{1,3} "\"" id? .
+id ::= [a-zA-Z_] [a-zA-Z0-9_]{2}"#,
+        );
+
+        assert_eq!(check(&grammar, r#""é"!"#), (true, true));
+        assert_eq!(check(&grammar, r#""abc"x_1!"#), (true, true));
+        assert_eq!(check(&grammar, r#""""#), (false, false));
+        assert_eq!(check(&grammar, r#""abcd""#), (false, false));
+        assert_eq!(check(&grammar, r#""a"x1"#), (true, false));
+    }
+
+    #[test]
+    fn tracks_what_can_follow() {
+        let grammar = grammar(r#"root ::= [a-z]+ "😀"?"#);
+        assert!(grammar.can_continue());
+        assert!(!grammar.allows_non_ascii());
+        assert!(!grammar.accepts("1"));
+
+        let mut word = grammar.clone();
+        assert!(word.push_str("hi"));
+        assert!(word.is_complete());
+        assert!(word.can_continue());
+        assert!(word.allows_non_ascii());
+
+        assert!(word.push_str("😀"));
+        assert!(word.is_complete());
+        assert!(!word.can_continue());
+    }
+
+    #[test]
+    fn rejects_invalid_grammars() {
+        assert_eq!(
+            "answer ::= \"yes\"".parse::<Grammar>().unwrap_err(),
+            GrammarError::MissingRoot
+        );
+        assert_eq!(
+            "root ::= item".parse::<Grammar>().unwrap_err(),
+            GrammarError::UndefinedRule("item".to_string())
+        );
+        assert_eq!(
+            "root ::= list\nlist ::= list \",\" [0-9] | [0-9]"
+                .parse::<Grammar>()
+                .unwrap_err(),
+            GrammarError::LeftRecursion("list".to_string())
+        );
+        assert!(matches!(
+            "root ::= \"unterminated".parse::<Grammar>(),
+            Err(GrammarError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            "root ::= \"a\"\n  * \"b\"".parse::<Grammar>(),
+            Err(GrammarError::Syntax { line: 2, .. })
+        ));
+    }
+}
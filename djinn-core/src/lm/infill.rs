@@ -0,0 +1,43 @@
+//! Prompt formats for fill-in-the-middle (FIM) completion,
+//! where the model generates the text between a prefix and a suffix
+
+/// The special tokens a model was trained to fill in the middle with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FimTemplate {
+    /// Written before the text before the cursor
+    pub prefix: &'static str,
+    /// Written before the text after the cursor
+    pub suffix: &'static str,
+    /// Written last, the model's output starts here
+    pub middle: &'static str,
+}
+
+impl FimTemplate {
+    pub const STARCODER: FimTemplate = FimTemplate {
+        prefix: "<fim_prefix>",
+        suffix: "<fim_suffix>",
+        middle: "<fim_middle>",
+    };
+
+    /// The prompt for the text between `prefix` and `suffix`,
+    /// in prefix-suffix-middle order
+    pub fn prompt(&self, prefix: &str, suffix: &str) -> String {
+        format!(
+            "{}{prefix}{}{suffix}{}",
+            self.prefix, self.suffix, self.middle
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_starcoder_prompts() {
+        assert_eq!(
+            FimTemplate::STARCODER.prompt("fn add(a: u32, b: u32) -> u32 {\n", "\n}\n"),
+            "<fim_prefix>fn add(a: u32, b: u32) -> u32 {\n<fim_suffix>\n}\n<fim_middle>"
+        );
+    }
+}
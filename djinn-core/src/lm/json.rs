@@ -0,0 +1,331 @@
+//! Constrain generation to valid JSON
+//!
+//! [`JsonValidator`] is a pushdown automaton that accepts prefixes of a JSON document
+//! one character at a time.
+//! Candidate tokens that would make the output invalid are masked out before sampling.
+
+/// Containers that are open at the current position
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// What the next structural character can be
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Expect {
+    Value,
+    /// After `[`
+    ValueOrEnd,
+    /// After `{`
+    KeyOrEnd,
+    /// After `,` in an object
+    Key,
+    Colon,
+    CommaOrEnd,
+    /// The top level value is finished
+    Done,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Escape {
+    None,
+    Backslash,
+    /// The number of hex digits left in a `\u` escape
+    Unicode(u8),
+}
+
+/// States of the JSON number grammar
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Minus,
+    Zero,
+    Int,
+    Dot,
+    Fraction,
+    E,
+    ExponentSign,
+    Exponent,
+}
+
+impl Number {
+    fn next(self, c: char) -> Option<Number> {
+        match (self, c) {
+            (Number::Minus, '0') => Some(Number::Zero),
+            (Number::Minus, '1'..='9') => Some(Number::Int),
+            (Number::Int, '0'..='9') => Some(Number::Int),
+            (Number::Zero | Number::Int, '.') => Some(Number::Dot),
+            (Number::Dot | Number::Fraction, '0'..='9') => Some(Number::Fraction),
+            (Number::Zero | Number::Int | Number::Fraction, 'e' | 'E') => Some(Number::E),
+            (Number::E, '+' | '-') => Some(Number::ExponentSign),
+            (Number::E | Number::ExponentSign | Number::Exponent, '0'..='9') => {
+                Some(Number::Exponent)
+            }
+            _ => None,
+        }
+    }
+
+    /// True if the number can end here
+    fn is_complete(self) -> bool {
+        matches!(
+            self,
+            Number::Zero | Number::Int | Number::Fraction | Number::Exponent
+        )
+    }
+}
+
+/// The token being read, if any
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Lexeme {
+    None,
+    String { key: bool, escape: Escape },
+    Number(Number),
+    Literal { rest: &'static str },
+}
+
+#[derive(Clone, Debug)]
+pub struct JsonValidator {
+    stack: Vec<Container>,
+    expect: Expect,
+    lexeme: Lexeme,
+}
+
+impl Default for JsonValidator {
+    fn default() -> Self {
+        JsonValidator {
+            stack: Vec::new(),
+            expect: Expect::Value,
+            lexeme: Lexeme::None,
+        }
+    }
+}
+
+impl JsonValidator {
+    /// True if `text` would keep the document valid
+    pub fn accepts(&self, text: &str) -> bool {
+        let mut validator = self.clone();
+        text.chars().all(|c| validator.push(c))
+    }
+
+    /// Advance past `text`.
+    /// Returns false if the text is invalid, in which case the state is undefined.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        text.chars().all(|c| self.push(c))
+    }
+
+    /// True if the document is a complete JSON value
+    pub fn is_complete(&self) -> bool {
+        match self.lexeme {
+            Lexeme::None => self.expect == Expect::Done,
+            Lexeme::Number(number) => self.stack.is_empty() && number.is_complete(),
+            Lexeme::String { .. } | Lexeme::Literal { .. } => false,
+        }
+    }
+
+    /// True if the document is inside a string,
+    /// where any character other than a control character is valid
+    pub fn in_string(&self) -> bool {
+        matches!(
+            self.lexeme,
+            Lexeme::String {
+                escape: Escape::None,
+                ..
+            }
+        )
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        match self.lexeme {
+            Lexeme::String { key, escape } => self.push_string(c, key, escape),
+            Lexeme::Number(number) => match number.next(c) {
+                Some(number) => {
+                    self.lexeme = Lexeme::Number(number);
+                    true
+                }
+                None if number.is_complete() => {
+                    self.lexeme = Lexeme::None;
+                    self.end_value();
+                    self.push_structural(c)
+                }
+                None => false,
+            },
+            Lexeme::Literal { rest } => {
+                let mut chars = rest.chars();
+                if chars.next() != Some(c) {
+                    return false;
+                }
+                let rest = chars.as_str();
+                if rest.is_empty() {
+                    self.lexeme = Lexeme::None;
+                    self.end_value();
+                } else {
+                    self.lexeme = Lexeme::Literal { rest };
+                }
+                true
+            }
+            Lexeme::None => self.push_structural(c),
+        }
+    }
+
+    fn push_string(&mut self, c: char, key: bool, escape: Escape) -> bool {
+        let escape = match (escape, c) {
+            (Escape::None, '"') => {
+                self.lexeme = Lexeme::None;
+                if key {
+                    self.expect = Expect::Colon;
+                } else {
+                    self.end_value();
+                }
+                return true;
+            }
+            (Escape::None, '\\') => Escape::Backslash,
+            (Escape::None, c) if c.is_control() => return false,
+            (Escape::None, _) => Escape::None,
+            (Escape::Backslash, '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => Escape::None,
+            (Escape::Backslash, 'u') => Escape::Unicode(4),
+            (Escape::Backslash, _) => return false,
+            (Escape::Unicode(left), c) if c.is_ascii_hexdigit() => {
+                if left == 1 {
+                    Escape::None
+                } else {
+                    Escape::Unicode(left - 1)
+                }
+            }
+            (Escape::Unicode(_), _) => return false,
+        };
+        self.lexeme = Lexeme::String { key, escape };
+        true
+    }
+
+    fn push_structural(&mut self, c: char) -> bool {
+        if matches!(c, ' ' | '\t' | '\n' | '\r') {
+            return true;
+        }
+
+        match (self.expect, c) {
+            (Expect::Value | Expect::ValueOrEnd, _) if self.start_value(c) => true,
+            (Expect::ValueOrEnd, ']') => self.close(Container::Array),
+            (Expect::KeyOrEnd | Expect::Key, '"') => {
+                self.lexeme = Lexeme::String {
+                    key: true,
+                    escape: Escape::None,
+                };
+                true
+            }
+            (Expect::KeyOrEnd, '}') => self.close(Container::Object),
+            (Expect::Colon, ':') => {
+                self.expect = Expect::Value;
+                true
+            }
+            (Expect::CommaOrEnd, ',') => {
+                self.expect = match self.stack.last() {
+                    Some(Container::Object) => Expect::Key,
+                    _ => Expect::Value,
+                };
+                true
+            }
+            (Expect::CommaOrEnd, '}') => self.close(Container::Object),
+            (Expect::CommaOrEnd, ']') => self.close(Container::Array),
+            _ => false,
+        }
+    }
+
+    /// Start reading a value. Returns false if `c` can't start a value.
+    fn start_value(&mut self, c: char) -> bool {
+        match c {
+            '{' => {
+                self.stack.push(Container::Object);
+                self.expect = Expect::KeyOrEnd;
+            }
+            '[' => {
+                self.stack.push(Container::Array);
+                self.expect = Expect::ValueOrEnd;
+            }
+            '"' => {
+                self.lexeme = Lexeme::String {
+                    key: false,
+                    escape: Escape::None,
+                }
+            }
+            '-' => self.lexeme = Lexeme::Number(Number::Minus),
+            '0' => self.lexeme = Lexeme::Number(Number::Zero),
+            '1'..='9' => self.lexeme = Lexeme::Number(Number::Int),
+            't' => self.lexeme = Lexeme::Literal { rest: "rue" },
+            'f' => self.lexeme = Lexeme::Literal { rest: "alse" },
+            'n' => self.lexeme = Lexeme::Literal { rest: "ull" },
+            _ => return false,
+        }
+        true
+    }
+
+    fn close(&mut self, container: Container) -> bool {
+        if self.stack.pop() != Some(container) {
+            return false;
+        }
+        self.end_value();
+        true
+    }
+
+    fn end_value(&mut self) {
+        self.expect = if self.stack.is_empty() {
+            Expect::Done
+        } else {
+            Expect::CommaOrEnd
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(text: &str) -> (bool, bool) {
+        let mut validator = JsonValidator::default();
+        let valid = validator.push_str(text);
+        (valid, validator.is_complete())
+    }
+
+    #[test]
+    fn accepts_complete_documents() {
+        for document in [
+            r#"{"a": [1, -2.5e3, true, null], "b": {"c": "\u00e9\n"}}"#,
+            "[]",
+            "{}",
+            " \"text\" ",
+            "0",
+        ] {
+            assert_eq!(validate(document), (true, true), "{document}");
+        }
+    }
+
+    #[test]
+    fn prefixes_are_valid_but_incomplete() {
+        for prefix in [r#"{"a": [1, 2"#, r#"{"key"#, "[tr", "-", "1."] {
+            assert_eq!(validate(prefix), (true, false), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for document in [
+            "{a: 1}",
+            "[1,]",
+            "{\"a\" 1}",
+            "01",
+            "[1}",
+            "\"\\x\"",
+            "{} {}",
+            "tru e",
+        ] {
+            assert!(!validate(document).0, "{document}");
+        }
+    }
+
+    #[test]
+    fn accepts_does_not_advance() {
+        let validator = JsonValidator::default();
+        assert!(validator.accepts("{\"a\""));
+        assert!(!validator.accepts("}"));
+        assert!(validator.accepts("["));
+    }
+}
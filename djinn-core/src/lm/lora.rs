@@ -0,0 +1,133 @@
+//! Apply LoRA adapters to model weights
+//!
+//! Adapters are read from a PEFT style directory containing
+//! `adapter_config.json` and `adapter_model.safetensors`.
+//! The low rank update is merged into the base weights at load time,
+//! so the model runs at the same speed as the base model.
+//! QLoRA adapters are merged the same way, on top of the unquantized base weights.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use candle_core::{DType, Device, Tensor};
+use serde::Deserialize;
+
+const ADAPTER_CONFIG_FILE: &str = "adapter_config.json";
+const ADAPTER_WEIGHTS_FILE: &str = "adapter_model.safetensors";
+
+/// The prefix PEFT adds to the names of the base model's tensors
+const PEFT_PREFIX: &str = "base_model.model.";
+
+/// The subset of `adapter_config.json` needed to merge an adapter
+#[derive(Debug, Deserialize)]
+struct AdapterConfig {
+    /// The rank of the update matrices
+    r: usize,
+    lora_alpha: f64,
+}
+
+#[derive(Debug)]
+pub struct LoraAdapter {
+    tensors: HashMap<String, Tensor>,
+    /// `lora_alpha / r`
+    scale: f64,
+}
+
+impl LoraAdapter {
+    /// Load an adapter from a directory,
+    /// or from an `adapter_model.safetensors` file with the config next to it
+    pub fn load(path: &Path, device: &Device) -> anyhow::Result<Self> {
+        let (dir, weights_file) = if path.is_dir() {
+            (path, path.join(ADAPTER_WEIGHTS_FILE))
+        } else {
+            let dir = path
+                .parent()
+                .ok_or(anyhow!("adapter file has no parent directory: {path:?}"))?;
+            (dir, path.to_path_buf())
+        };
+
+        let config_file = dir.join(ADAPTER_CONFIG_FILE);
+        let config: AdapterConfig = serde_json::from_str(
+            &std::fs::read_to_string(&config_file)
+                .with_context(|| format!("unable to read {config_file:?}"))?,
+        )
+        .with_context(|| format!("unable to parse {config_file:?}"))?;
+        if config.r == 0 {
+            return Err(anyhow!("adapter rank must be greater than 0"));
+        }
+
+        let tensors = candle_core::safetensors::load(&weights_file, device)
+            .with_context(|| format!("unable to load adapter weights {weights_file:?}"))?;
+
+        Ok(LoraAdapter {
+            tensors,
+            scale: config.lora_alpha / config.r as f64,
+        })
+    }
+
+    /// Add the adapter's update to `weights`.
+    /// Returns the number of weights that were updated.
+    pub fn merge(&self, weights: &mut HashMap<String, Tensor>) -> anyhow::Result<usize> {
+        let mut merged = 0;
+        for (name, lora_a) in &self.tensors {
+            let Some(base_name) = base_weight_name(name) else {
+                continue;
+            };
+            let lora_b = self
+                .tensors
+                .get(&name.replace(".lora_A.", ".lora_B."))
+                .ok_or(anyhow!("adapter is missing the lora_B matrix for {name}"))?;
+            let weight = weights
+                .get_mut(&base_name)
+                .ok_or(anyhow!("adapter targets unknown weight {base_name}"))?;
+
+            // W' = W + scale * B A, computed in F32 to avoid losing the update to rounding
+            let dtype = weight.dtype();
+            let delta = lora_b
+                .to_dtype(DType::F32)?
+                .matmul(&lora_a.to_dtype(DType::F32)?)?
+                .affine(self.scale, 0.0)?;
+            *weight = (weight.to_dtype(DType::F32)? + delta)?.to_dtype(dtype)?;
+            merged += 1;
+        }
+
+        if merged == 0 {
+            return Err(anyhow!("adapter contains no LoRA weights"));
+        }
+        Ok(merged)
+    }
+}
+
+/// The name of the base weight that a `lora_A` tensor updates,
+/// e.g. `base_model.model.model.layers.0.self_attn.q_proj.lora_A.weight`
+/// updates `model.layers.0.self_attn.q_proj.weight`.
+/// Returns `None` for tensors that aren't `lora_A` matrices.
+fn base_weight_name(lora_a_name: &str) -> Option<String> {
+    let name = lora_a_name.strip_prefix(PEFT_PREFIX).unwrap_or(lora_a_name);
+    let module = name
+        .strip_suffix(".lora_A.weight")
+        .or_else(|| name.strip_suffix(".lora_A.default.weight"))?;
+    Some(format!("{module}.weight"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_peft_names_to_base_weights() {
+        assert_eq!(
+            base_weight_name("base_model.model.model.layers.0.self_attn.q_proj.lora_A.weight"),
+            Some("model.layers.0.self_attn.q_proj.weight".to_string())
+        );
+        assert_eq!(
+            base_weight_name("model.layers.3.mlp.down_proj.lora_A.default.weight"),
+            Some("model.layers.3.mlp.down_proj.weight".to_string())
+        );
+        assert_eq!(
+            base_weight_name("base_model.model.model.layers.0.self_attn.q_proj.lora_B.weight"),
+            None
+        );
+    }
+}
@@ -1,44 +1,92 @@
+use std::path::PathBuf;
+
 use candle_core::{self as candle};
 use futures::pin_mut;
 use futures::StreamExt;
-use hf_hub::{api::tokio::Api, Repo, RepoType};
 use tokenizers::Tokenizer;
 
+use crate::device::DeviceMap;
+use crate::hub::HubRepo;
 use crate::lm::ModelSource;
 
+use super::beam::Candidate;
 use super::config::ModelConfig;
 use super::config::ModelRun;
+use super::model::Draft;
+use super::model::LoadOptions;
+use super::model::Model;
+use super::model::ModelArchitecture;
 use super::model::ModelContext;
 use super::model::ModelContextBuilder;
+use super::model::RunStats;
+use super::prefix_cache::PrefixCache;
+
+pub mod sharded;
 
 pub async fn create_new_context(model_config: &ModelConfig) -> anyhow::Result<ModelContext> {
     // prep files
     let start = std::time::Instant::now();
+    if let Some(threads) = model_config.threads {
+        let current = candle::utils::get_num_threads();
+        if threads.get() != current {
+            tracing::warn!(
+                threads = threads.get(),
+                current,
+                "the CPU threads are set when djinn starts, restart it to change them"
+            );
+        }
+    }
 
-    let device = model_config.device.try_into()?;
-
-    let revision = match &model_config.model_source {
-        ModelSource::HuggingFaceHub { revision } => revision,
-        ModelSource::Files {
-            weight_files: _,
-            tokenizer_file: _,
-        } => todo!(),
+    let devices = if model_config.devices.is_empty() {
+        DeviceMap::from(candle::Device::try_from(model_config.device)?)
+    } else {
+        DeviceMap::open(&model_config.devices)?
     };
 
-    let variant = model_config.variant;
-    let api = Api::new()?;
-    let repo_id = variant.hf_repo_id();
-    let repo = api.repo(Repo::with_revision(
-        repo_id,
-        RepoType::Model,
-        revision.to_owned(),
-    ));
-
-    let weights = variant
-        .load_weights(&repo, &device, model_config.flash_attn)
-        .await?;
+    let options = LoadOptions {
+        use_flash_attn: model_config.flash_attn,
+        adapter: model_config.adapter.as_deref(),
+        speculative: model_config.draft.is_some(),
+        dtype: model_config.dtype.map(Into::into),
+        weight_loading: model_config.weight_loading,
+    };
+    let (weights, tokenizer_file) = load_weights(
+        model_config.variant,
+        &model_config.model_source,
+        &devices,
+        options,
+    )
+    .await?;
+
+    let draft = match &model_config.draft {
+        Some(draft_config) => {
+            tracing::info!(variant = ?draft_config.variant, "loading draft model");
+            let options = LoadOptions {
+                use_flash_attn: model_config.flash_attn,
+                adapter: None,
+                speculative: true,
+                // the draft model proposes tokens the main model checks, so it uses the same type
+                dtype: model_config.dtype.map(Into::into),
+                weight_loading: model_config.weight_loading,
+            };
+            // the draft model is small enough to keep on one device
+            let draft_devices = DeviceMap::from(devices.main().clone());
+            let (weights, _tokenizer_file) = load_weights(
+                draft_config.variant,
+                &draft_config.model_source,
+                &draft_devices,
+                options,
+            )
+            .await?;
+            Some(Draft::new(
+                weights,
+                draft_config.tokens,
+                model_config.prefix_cache_tokens,
+            ))
+        }
+        None => None,
+    };
 
-    let tokenizer_file = repo.get("tokenizer.json").await?;
     let tokenizer = Tokenizer::from_file(tokenizer_file).map_err(anyhow::Error::msg)?;
 
     tracing::info!("loaded the model in {:?}", start.elapsed());
@@ -46,39 +94,62 @@ pub async fn create_new_context(model_config: &ModelConfig) -> anyhow::Result<Mo
     Ok(ModelContextBuilder::default()
         .model(weights)
         .tokenizer(tokenizer)
-        .device(device)
+        .device(devices.main().clone())
+        .prefix_cache(PrefixCache::new(model_config.prefix_cache_tokens))
+        .draft(draft)
         .build()?)
 }
 
-pub async fn run(run: ModelRun) -> anyhow::Result<ModelRun> {
-    tracing::info!(
-        "avx: {}, neon: {}, simd128: {}, f16c: {}",
-        candle::utils::with_avx(),
-        candle::utils::with_neon(),
-        candle::utils::with_simd128(),
-        candle::utils::with_f16c()
-    );
-    tracing::info!(
-        "temp: {:.2} repeat-penalty: {:.2} repeat-last-n: {}",
-        run.run_config.temperature,
-        run.run_config.repeat_penalty,
-        run.run_config.repeat_last_n
-    );
-    let mut model_context = create_new_context(&run.model_config).await?;
-    let stream = model_context.run(run.prompt.clone(), run.run_config.clone());
-
-    pin_mut!(stream);
-
-    while let Some(value) = stream.next().await {
-        if let Ok(string_token) = value {
-            tracing::info!("{string_token}");
+/// Load a model's weights, downloading them if needed.
+/// Returns the weights and the tokenizer file.
+async fn load_weights(
+    variant: ModelArchitecture,
+    model_source: &ModelSource,
+    devices: &DeviceMap,
+    options: LoadOptions<'_>,
+) -> anyhow::Result<(Model, PathBuf)> {
+    match model_source {
+        ModelSource::HuggingFaceHub { revision } => {
+            let repo = HubRepo::model(variant.hf_repo_id(), revision)?;
+
+            let weights = variant.load_weights(&repo, devices, options).await?;
+
+            let tokenizer_file = repo.get("tokenizer.json").await?;
+            Ok((weights, tokenizer_file))
+        }
+        ModelSource::Files {
+            weight_files,
+            tokenizer_file,
+            config_file,
+        } => {
+            tracing::info!(?weight_files, "loading model from local files");
+            let weights = variant.load_local_weights(
+                weight_files,
+                config_file.as_deref(),
+                devices,
+                options,
+            )?;
+            Ok((weights, tokenizer_file.clone()))
         }
     }
+}
 
+/// Run a model and return the run, e.g. to save it as a config.
+/// `on_token` is called with each token as it is generated
+pub async fn run(
+    run: ModelRun,
+    on_token: impl FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<ModelRun> {
+    run_model(&run, on_token).await?;
     Ok(run)
 }
 
-pub async fn run_model(run: ModelRun) -> anyhow::Result<()> {
+/// Load the model for `run` and generate a completion of its prompt.
+/// `on_token` is called with each token as it is generated
+pub async fn run_model(
+    run: &ModelRun,
+    mut on_token: impl FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<RunStats> {
     tracing::info!(
         "avx: {}, neon: {}, simd128: {}, f16c: {}",
         candle::utils::with_avx(),
@@ -93,15 +164,31 @@ pub async fn run_model(run: ModelRun) -> anyhow::Result<()> {
         run.run_config.repeat_last_n,
     );
     let mut model_context = create_new_context(&run.model_config).await?;
-    let stream = model_context.run(run.prompt.clone(), run.run_config.clone());
+    {
+        let stream = model_context.run(run.prompt.clone(), run.run_config.clone());
 
-    pin_mut!(stream);
+        pin_mut!(stream);
 
-    while let Some(value) = stream.next().await {
-        if let Ok(string_token) = value {
-            tracing::info!("{string_token}");
+        while let Some(value) = stream.next().await {
+            on_token(&value?)?;
         }
     }
 
-    Ok(())
+    let stats = model_context.stats();
+    tracing::info!(
+        "prompt tokens: {} (truncated: {}, cached: {}), generated tokens: {}",
+        stats.prompt_tokens,
+        stats.truncated_tokens,
+        stats.cached_tokens,
+        stats.generated_tokens,
+    );
+
+    Ok(stats)
+}
+
+/// Load the model for `run` and return the completions found by a beam search, best first
+pub async fn run_beam_search(run: &ModelRun) -> anyhow::Result<(Vec<Candidate>, RunStats)> {
+    let mut model_context = create_new_context(&run.model_config).await?;
+    let candidates = model_context.beam_search(&run.prompt, &run.run_config)?;
+    Ok((candidates, model_context.stats()))
 }
@@ -0,0 +1,393 @@
+//! Mistral split layer-wise across multiple devices
+//!
+//! This follows candle's Mistral implementation,
+//! except that each decoder layer lives on the device chosen by the [`DeviceMap`].
+//! The hidden state is moved to the next device when crossing a shard boundary.
+//! The embeddings live on the first device and the output head on the last.
+//!
+//! Unlike candle's implementation, it can return the logits for every position
+//! and roll back its KV cache, which speculative decoding needs.
+//! It is used on a single device when a draft model is configured.
+
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Module, Result, Tensor, D};
+use candle_nn::{embedding, linear_no_bias, rms_norm, Embedding, Linear, RmsNorm, VarBuilder};
+use candle_transformers::models::mistral::Config;
+use candle_transformers::utils::repeat_kv;
+
+use crate::device::DeviceMap;
+
+fn head_dim(config: &Config) -> usize {
+    config.hidden_size / config.num_attention_heads
+}
+
+#[derive(Debug)]
+struct RotaryEmbedding {
+    sin: Tensor,
+    cos: Tensor,
+}
+
+impl RotaryEmbedding {
+    fn new(dtype: DType, config: &Config, device: &Device) -> Result<Self> {
+        let dim = head_dim(config);
+        let max_seq_len = config.max_position_embeddings;
+        let rope_theta = config.rope_theta as f32;
+        let inv_freq: Vec<f32> = (0..dim)
+            .step_by(2)
+            .map(|i| 1f32 / rope_theta.powf(i as f32 / dim as f32))
+            .collect();
+        let inv_freq_len = inv_freq.len();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), device)?.to_dtype(dtype)?;
+        let t = Tensor::arange(0u32, max_seq_len as u32, device)?
+            .to_dtype(dtype)?
+            .reshape((max_seq_len, 1))?;
+        let freqs = t.matmul(&inv_freq)?;
+        Ok(RotaryEmbedding {
+            sin: freqs.sin()?,
+            cos: freqs.cos()?,
+        })
+    }
+
+    fn apply(&self, q: &Tensor, k: &Tensor, seqlen_offset: usize) -> Result<(Tensor, Tensor)> {
+        let (_b, _h, seq_len, _d) = q.dims4()?;
+        let cos = self.cos.narrow(0, seqlen_offset, seq_len)?;
+        let sin = self.sin.narrow(0, seqlen_offset, seq_len)?;
+        let q = candle_nn::rotary_emb::rope(&q.contiguous()?, &cos, &sin)?;
+        let k = candle_nn::rotary_emb::rope(&k.contiguous()?, &cos, &sin)?;
+        Ok((q, k))
+    }
+}
+
+#[derive(Debug)]
+struct Mlp {
+    gate_proj: Linear,
+    up_proj: Linear,
+    down_proj: Linear,
+    act_fn: candle_nn::Activation,
+}
+
+impl Mlp {
+    fn new(config: &Config, vb: VarBuilder) -> Result<Self> {
+        let hidden = config.hidden_size;
+        let intermediate = config.intermediate_size;
+        Ok(Mlp {
+            gate_proj: linear_no_bias(hidden, intermediate, vb.pp("gate_proj"))?,
+            up_proj: linear_no_bias(hidden, intermediate, vb.pp("up_proj"))?,
+            down_proj: linear_no_bias(intermediate, hidden, vb.pp("down_proj"))?,
+            act_fn: config.hidden_act,
+        })
+    }
+}
+
+impl Module for Mlp {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let lhs = xs.apply(&self.gate_proj)?.apply(&self.act_fn)?;
+        let rhs = xs.apply(&self.up_proj)?;
+        (lhs * rhs)?.apply(&self.down_proj)
+    }
+}
+
+#[derive(Debug)]
+struct Attention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    o_proj: Linear,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+    rotary_emb: Arc<RotaryEmbedding>,
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl Attention {
+    fn new(rotary_emb: Arc<RotaryEmbedding>, config: &Config, vb: VarBuilder) -> Result<Self> {
+        let hidden = config.hidden_size;
+        let num_heads = config.num_attention_heads;
+        let num_kv_heads = config.num_key_value_heads;
+        let head_dim = head_dim(config);
+        Ok(Attention {
+            q_proj: linear_no_bias(hidden, num_heads * head_dim, vb.pp("q_proj"))?,
+            k_proj: linear_no_bias(hidden, num_kv_heads * head_dim, vb.pp("k_proj"))?,
+            v_proj: linear_no_bias(hidden, num_kv_heads * head_dim, vb.pp("v_proj"))?,
+            o_proj: linear_no_bias(num_heads * head_dim, hidden, vb.pp("o_proj"))?,
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            rotary_emb,
+            kv_cache: None,
+        })
+    }
+
+    fn forward(
+        &mut self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let (b_size, q_len, _) = xs.dims3()?;
+
+        let q = xs
+            .apply(&self.q_proj)?
+            .reshape((b_size, q_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let k = xs
+            .apply(&self.k_proj)?
+            .reshape((b_size, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let v = xs
+            .apply(&self.v_proj)?
+            .reshape((b_size, q_len, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+
+        let (q, k) = self.rotary_emb.apply(&q, &k, seqlen_offset)?;
+
+        let (k, v) = match &self.kv_cache {
+            None => (k, v),
+            Some((prev_k, prev_v)) => (
+                Tensor::cat(&[prev_k, &k], 2)?,
+                Tensor::cat(&[prev_v, &v], 2)?,
+            ),
+        };
+        self.kv_cache = Some((k.clone(), v.clone()));
+
+        let n_rep = self.num_heads / self.num_kv_heads;
+        let k = repeat_kv(k, n_rep)?.contiguous()?;
+        let v = repeat_kv(v, n_rep)?.contiguous()?;
+
+        let scale = 1f64 / f64::sqrt(self.head_dim as f64);
+        let attn_weights = (q.contiguous()?.matmul(&k.t()?)? * scale)?;
+        let attn_weights = match attention_mask {
+            None => attn_weights,
+            Some(mask) => attn_weights.broadcast_add(mask)?,
+        };
+        let attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
+        attn_weights
+            .matmul(&v)?
+            .transpose(1, 2)?
+            .reshape((b_size, q_len, self.num_heads * self.head_dim))?
+            .apply(&self.o_proj)
+    }
+}
+
+#[derive(Debug)]
+struct DecoderLayer {
+    self_attn: Attention,
+    mlp: Mlp,
+    input_layernorm: RmsNorm,
+    post_attention_layernorm: RmsNorm,
+    device: Device,
+}
+
+impl DecoderLayer {
+    fn new(
+        rotary_emb: Arc<RotaryEmbedding>,
+        config: &Config,
+        device: &Device,
+        vb: VarBuilder,
+    ) -> Result<Self> {
+        let eps = config.rms_norm_eps;
+        Ok(DecoderLayer {
+            self_attn: Attention::new(rotary_emb, config, vb.pp("self_attn"))?,
+            mlp: Mlp::new(config, vb.pp("mlp"))?,
+            input_layernorm: rms_norm(config.hidden_size, eps, vb.pp("input_layernorm"))?,
+            post_attention_layernorm: rms_norm(
+                config.hidden_size,
+                eps,
+                vb.pp("post_attention_layernorm"),
+            )?,
+            device: device.clone(),
+        })
+    }
+
+    fn forward(
+        &mut self,
+        xs: &Tensor,
+        attention_mask: Option<&Tensor>,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let residual = xs;
+        let xs = xs.apply(&self.input_layernorm)?;
+        let xs = self.self_attn.forward(&xs, attention_mask, seqlen_offset)?;
+        let xs = (xs + residual)?;
+        let residual = &xs;
+        let xs = xs.apply(&self.post_attention_layernorm)?.apply(&self.mlp)?;
+        residual + xs
+    }
+}
+
+#[derive(Debug)]
+pub struct ShardedMistral {
+    embed_tokens: Embedding,
+    layers: Vec<DecoderLayer>,
+    norm: RmsNorm,
+    lm_head: Linear,
+    sliding_window: Option<usize>,
+    dtype: DType,
+    main_device: Device,
+    last_device: Device,
+}
+
+impl ShardedMistral {
+    /// Load the model with one [`VarBuilder`] per device in `devices`
+    pub fn new(config: &Config, devices: &DeviceMap, vbs: &[VarBuilder]) -> Result<Self> {
+        let num_layers = config.num_hidden_layers;
+        let (Some(main_vb), Some(last_vb)) = (vbs.first(), vbs.last()) else {
+            candle_core::bail!("no var builders given");
+        };
+        if vbs.len() != devices.devices().len() {
+            candle_core::bail!("expected one var builder per device");
+        }
+
+        let embed_tokens = embedding(
+            config.vocab_size,
+            config.hidden_size,
+            main_vb.pp("model.embed_tokens"),
+        )?;
+
+        let dtype = main_vb.dtype();
+        let rotary_embs = devices
+            .devices()
+            .iter()
+            .map(|device| RotaryEmbedding::new(dtype, config, device).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut layers = Vec::with_capacity(num_layers);
+        for index in 0..num_layers {
+            let shard = devices.layer_shard(index, num_layers);
+            let vb = vbs[shard].pp("model.layers").pp(index);
+            layers.push(DecoderLayer::new(
+                rotary_embs[shard].clone(),
+                config,
+                &devices.devices()[shard],
+                vb,
+            )?);
+        }
+
+        let norm = rms_norm(
+            config.hidden_size,
+            config.rms_norm_eps,
+            last_vb.pp("model.norm"),
+        )?;
+        let lm_head = linear_no_bias(config.hidden_size, config.vocab_size, last_vb.pp("lm_head"))?;
+
+        Ok(ShardedMistral {
+            embed_tokens,
+            layers,
+            norm,
+            lm_head,
+            sliding_window: config.sliding_window,
+            dtype,
+            main_device: devices.main().clone(),
+            last_device: devices.last().clone(),
+        })
+    }
+
+    fn attention_mask(
+        &self,
+        b_size: usize,
+        tgt_len: usize,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let sliding_window = self.sliding_window.unwrap_or(tgt_len + 1);
+        let mask: Vec<f32> = (0..tgt_len)
+            .flat_map(|i| {
+                (0..tgt_len).map(move |j| {
+                    if i < j || j + sliding_window < i {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.main_device)?;
+        let mask = if seqlen_offset > 0 {
+            let prefix = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.main_device)?;
+            Tensor::cat(&[&prefix, &mask], D::Minus1)?
+        } else {
+            mask
+        };
+        mask.expand((b_size, 1, tgt_len, tgt_len + seqlen_offset))?
+            .to_dtype(self.dtype)
+    }
+
+    /// The logits for the last position of `input_ids`
+    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let xs = self.hidden_states(input_ids, seqlen_offset)?;
+        let seq_len = xs.dim(1)?;
+        xs.narrow(1, seq_len - 1, 1)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)
+    }
+
+    /// The logits for every position of `input_ids`,
+    /// used to verify several draft tokens at once
+    pub fn forward_all(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        self.hidden_states(input_ids, seqlen_offset)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)
+    }
+
+    /// The output of the last decoder layer, on the last device
+    fn hidden_states(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let (b_size, seq_len) = input_ids.dims2()?;
+        let mut mask = if seq_len <= 1 {
+            None
+        } else {
+            Some(self.attention_mask(b_size, seq_len, seqlen_offset)?)
+        };
+
+        let mut xs = input_ids
+            .to_device(&self.main_device)?
+            .apply(&self.embed_tokens)?;
+        for layer in self.layers.iter_mut() {
+            if !xs.device().same_device(&layer.device) {
+                xs = xs.to_device(&layer.device)?;
+                mask = mask.map(|mask| mask.to_device(&layer.device)).transpose()?;
+            }
+            xs = layer.forward(&xs, mask.as_ref(), seqlen_offset)?;
+        }
+
+        xs.to_device(&self.last_device)
+    }
+
+    pub fn clear_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.self_attn.kv_cache = None;
+        }
+    }
+
+    /// The KV cache of every layer, e.g. to restore it with [`ShardedMistral::set_kv_cache`]
+    pub fn kv_cache(&self) -> Vec<Option<(Tensor, Tensor)>> {
+        self.layers
+            .iter()
+            .map(|layer| layer.self_attn.kv_cache.clone())
+            .collect()
+    }
+
+    /// Replace the KV cache with one from [`ShardedMistral::kv_cache`]
+    pub fn set_kv_cache(&mut self, cache: Vec<Option<(Tensor, Tensor)>>) {
+        for (layer, cache) in self.layers.iter_mut().zip(cache) {
+            layer.self_attn.kv_cache = cache;
+        }
+    }
+
+    /// Drop everything after the first `len` positions from the KV cache
+    pub fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        if len == 0 {
+            self.clear_kv_cache();
+            return Ok(());
+        }
+        for layer in self.layers.iter_mut() {
+            let cache = &mut layer.self_attn.kv_cache;
+            if let Some((k, v)) = cache {
+                if k.dim(2)? > len {
+                    *cache = Some((k.narrow(2, 0, len)?, v.narrow(2, 0, len)?));
+                }
+            }
+        }
+        Ok(())
+    }
+}
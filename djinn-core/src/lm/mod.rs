@@ -7,9 +7,21 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::Result;
 
+pub mod beam;
+pub mod chat;
 pub mod config;
+pub mod constraint;
+pub mod grammar;
+pub mod infill;
+pub mod json;
+pub mod lora;
 pub mod mistral;
 pub mod model;
+pub mod prefix_cache;
+pub mod sampling;
+pub mod stop;
+pub mod tokens;
+pub mod validate;
 
 pub trait Lm {
     // type Config;
@@ -32,7 +44,12 @@ pub enum ModelSource {
         revision: String,
     },
     Files {
+        /// safetensors files, or a single GGUF file for quantized architectures
         weight_files: Vec<PathBuf>,
         tokenizer_file: PathBuf,
+        /// The model's `config.json`.
+        /// Defaults to a `config.json` in the same directory as the weights.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config_file: Option<PathBuf>,
     },
 }
@@ -1,27 +1,52 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
 
 use anyhow::{anyhow, Context};
 use async_stream::stream;
-use candle_core::{DType, Device, Tensor};
+use candle_core::{DType, Device, Tensor, D};
 use candle_nn::VarBuilder;
-use candle_transformers::generation::LogitsProcessor;
 use candle_transformers::models::{
+    gemma::{Config as GemmaConfig, Model as Gemma},
+    llama::{
+        Cache as LlamaCache, Config as LlamaConfig, Llama, LlamaConfig as LlamaJsonConfig,
+        MAX_SEQ_LEN as LLAMA_MAX_SEQ_LEN,
+    },
     mistral::{Config as MistralConfig, Model as Mistral},
+    phi3::{Config as Phi3Config, Model as Phi3},
     quantized_mistral::Model as QMistral,
     starcoder2::{Config as StarcoderConfig, Model as Starcoder},
 };
 use clap::ValueEnum;
 use derive_builder::Builder;
-use hf_hub::api::tokio::ApiRepo;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokenizers::Tokenizer;
 use tokio_stream::Stream;
 use tracing::instrument;
 
-use crate::error::Result;
-use crate::hf_hub_ext::hub_load_safetensors;
+use crate::device::DeviceMap;
+use crate::error::{Error, Result};
+use crate::hub::{hub_load_safetensors, HubRepo};
 use crate::token_output_stream::TokenOutputStream;
 
-use super::config::RunConfig;
+use super::beam::{BeamSearch, Candidate, Extension};
+use super::chat::ChatTemplate;
+use super::config::{ContextOverflow, RunConfig, WeightLoading};
+use super::constraint::Constraint;
+use super::infill::FimTemplate;
+use super::lora::LoraAdapter;
+use super::mistral::sharded::ShardedMistral;
+use super::prefix_cache::{PrefixCache, PrefixCacheStats};
+use super::sampling::{logprobs, top_logprobs, Sampler, TokenLogprob, TokenLogprobs};
+use super::stop::{first_stop, StopOutput, StopSequences};
+use super::tokens::{decode, encode};
+
+/// The context length of Starcoder2 models,
+/// which isn't exposed by the candle config
+const STARCODER_MAX_CONTEXT_LEN: usize = 16_384;
+
+/// DistilBert is an encoder, so it can only be used through [`crate::embed`]
+const EMBEDDING_ONLY: &str = "DistilBert can't generate text, load it as an embedding model";
 
 /// The variant of the model to be loaded
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
@@ -33,6 +58,12 @@ pub enum ModelArchitecture {
     QMistral,
     DistilBert,
     Starcoder,
+    /// Llama family models
+    Llama,
+    /// Microsoft Phi-3
+    Phi3,
+    /// Google Gemma
+    Gemma,
 }
 
 #[derive(Debug)]
@@ -49,29 +80,139 @@ pub enum Model {
         weights: Starcoder,
         config: StarcoderConfig,
     },
+    /// Mistral split across multiple devices
+    ShardedMistral {
+        weights: ShardedMistral,
+        config: MistralConfig,
+    },
+    Llama {
+        weights: Llama,
+        config: LlamaConfig,
+        cache: LlamaCache,
+        /// A fresh cache used to clear `cache`
+        empty_cache: LlamaCache,
+    },
+    Phi3 {
+        weights: Phi3,
+        config: Phi3Config,
+    },
+    Gemma {
+        weights: Gemma,
+        config: GemmaConfig,
+    },
 }
 
 impl Model {
-    /// Get the End of Sequence token for a given model
-    pub fn eos_token(&self) -> &'static str {
+    /// Get the possible End of Sequence tokens for a given model.
+    /// Only the tokens that are in the tokenizer's vocabulary are used.
+    pub fn eos_tokens(&self) -> &'static [&'static str] {
+        match self {
+            Model::Mistral { .. } => &["</s>"],
+            Model::QMistral { .. } => &["</s>"],
+            Model::ShardedMistral { .. } => &["</s>"],
+            Model::Starcoder { .. } => &["<|endoftext|>"],
+            // Llama 2 and Llama 3 respectively
+            Model::Llama { .. } => &["</s>", "<|end_of_text|>", "<|eot_id|>"],
+            Model::Phi3 { .. } => &["<|endoftext|>", "<|end|>"],
+            Model::Gemma { .. } => &["<eos>", "<end_of_turn>"],
+        }
+    }
+
+    /// The prompt format for multi-turn chat
+    pub fn chat_template(&self) -> ChatTemplate {
+        match self {
+            Model::Mistral { .. } | Model::QMistral { .. } | Model::ShardedMistral { .. } => {
+                ChatTemplate::MISTRAL
+            }
+            Model::Starcoder { .. } => ChatTemplate::PLAIN,
+            Model::Llama { .. } => ChatTemplate::LLAMA3,
+            Model::Phi3 { .. } => ChatTemplate::PHI3,
+            Model::Gemma { .. } => ChatTemplate::GEMMA,
+        }
+    }
+
+    /// The prompt format for fill-in-the-middle completion, if the model was trained for it
+    pub fn fim_template(&self) -> Option<FimTemplate> {
+        match self {
+            Model::Starcoder { .. } => Some(FimTemplate::STARCODER),
+            Model::Mistral { .. }
+            | Model::QMistral { .. }
+            | Model::ShardedMistral { .. }
+            | Model::Llama { .. }
+            | Model::Phi3 { .. }
+            | Model::Gemma { .. } => None,
+        }
+    }
+
+    /// The maximum number of tokens the model can attend to
+    pub fn max_context_len(&self) -> usize {
         match self {
-            Model::Mistral { .. } => "</s>",
-            Model::QMistral { .. } => "</s>",
-            Model::Starcoder { .. } => "<|endoftext|>",
+            Model::Mistral { config, .. } => config.max_position_embeddings,
+            Model::QMistral { config, .. } => config.max_position_embeddings,
+            Model::ShardedMistral { config, .. } => config.max_position_embeddings,
+            Model::Starcoder { .. } => STARCODER_MAX_CONTEXT_LEN,
+            Model::Llama { .. } => LLAMA_MAX_SEQ_LEN,
+            Model::Phi3 { config, .. } => config.max_position_embeddings,
+            Model::Gemma { config, .. } => config.max_position_embeddings,
         }
     }
 }
 
+/// Options for loading a model
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions<'a> {
+    /// Use flash attention. Only supported on CUDA
+    pub use_flash_attn: bool,
+    /// A LoRA adapter to merge into the weights
+    pub adapter: Option<&'a Path>,
+    /// Load a model that can verify and roll back draft tokens
+    pub speculative: bool,
+    /// Use this type for the weights instead of the device's default
+    pub dtype: Option<DType>,
+    pub weight_loading: WeightLoading,
+}
+
 impl ModelArchitecture {
+    /// Download the model files from the HuggingFace Hub and load the weights
     pub async fn load_weights(
         &self,
-        repo: &ApiRepo,
-        device: &Device,
-        use_flash_attn: bool,
+        repo: &HubRepo,
+        devices: &DeviceMap,
+        options: LoadOptions,
     ) -> anyhow::Result<Model> {
         let files = self.hf_files(repo).await?;
+        let config_file = if self.needs_config_file() {
+            Some(repo.get("config.json").await?)
+        } else {
+            None
+        };
 
-        self.load_model(&files, repo, device, use_flash_attn).await
+        self.load_model(&files, config_file.as_deref(), devices, options)
+    }
+
+    /// Load the weights from files on the local file system.
+    /// If no `config_file` is given, a `config.json` next to the weights is used.
+    pub fn load_local_weights(
+        &self,
+        weight_files: &[PathBuf],
+        config_file: Option<&Path>,
+        devices: &DeviceMap,
+        options: LoadOptions,
+    ) -> anyhow::Result<Model> {
+        if let Some(missing) = weight_files.iter().find(|file| !file.exists()) {
+            return Err(anyhow!("weight file does not exist: {missing:?}"));
+        }
+
+        let config_file: Option<PathBuf> = match config_file {
+            Some(config_file) => Some(config_file.to_path_buf()),
+            None if self.needs_config_file() => weight_files
+                .first()
+                .and_then(|file| file.parent())
+                .map(|dir| dir.join("config.json")),
+            None => None,
+        };
+
+        self.load_model(weight_files, config_file.as_deref(), devices, options)
     }
 
     pub fn hf_repo_id(&self) -> String {
@@ -80,93 +221,271 @@ impl ModelArchitecture {
             ModelArchitecture::QMistral => "lmz/candle-mistral",
             ModelArchitecture::DistilBert => "distilbert/distilbert-base-cased-distilled-squad",
             ModelArchitecture::Starcoder => "bigcode/starcoder2-3b",
+            ModelArchitecture::Llama => "meta-llama/Meta-Llama-3-8B",
+            ModelArchitecture::Phi3 => "microsoft/Phi-3-mini-4k-instruct",
+            ModelArchitecture::Gemma => "google/gemma-2b-it",
         }
         .to_string()
     }
 
-    pub async fn hf_files(&self, repo: &ApiRepo) -> anyhow::Result<Vec<PathBuf>> {
+    /// True if the architecture is loaded from quantized GGUF weights
+    pub fn is_quantized(&self) -> bool {
+        matches!(self, ModelArchitecture::QMistral)
+    }
+
+    /// True if LoRA adapters can be merged into the architecture's weights
+    pub fn supports_adapters(&self) -> bool {
+        matches!(
+            self,
+            ModelArchitecture::Mistral
+                | ModelArchitecture::Llama
+                | ModelArchitecture::Phi3
+                | ModelArchitecture::Gemma
+        )
+    }
+
+    /// True if the architecture's layers can be split across multiple devices
+    pub fn supports_sharding(&self) -> bool {
+        matches!(self, ModelArchitecture::Mistral)
+    }
+
+    /// True if the architecture can be used for speculative decoding,
+    /// either as the main model or as the draft model
+    pub fn supports_speculation(&self) -> bool {
+        matches!(self, ModelArchitecture::Mistral)
+    }
+
+    /// True if the architecture needs a `config.json` to be loaded
+    pub fn needs_config_file(&self) -> bool {
+        match self {
+            ModelArchitecture::Mistral
+            | ModelArchitecture::Starcoder
+            | ModelArchitecture::Llama
+            | ModelArchitecture::Phi3
+            | ModelArchitecture::Gemma => true,
+            ModelArchitecture::QMistral | ModelArchitecture::DistilBert => false,
+        }
+    }
+
+    pub async fn hf_files(&self, repo: &HubRepo) -> anyhow::Result<Vec<PathBuf>> {
         match self {
             ModelArchitecture::Mistral => {
                 hub_load_safetensors(repo, "model.safetensors.index.json").await
             }
             ModelArchitecture::QMistral => Ok(vec![repo.get("model-q4k.gguf").await?]),
-            ModelArchitecture::DistilBert => todo!(),
+            ModelArchitecture::DistilBert => Err(anyhow!(EMBEDDING_ONLY)),
             ModelArchitecture::Starcoder => {
                 hub_load_safetensors(repo, "model.safetensors.index.json").await
             }
+            ModelArchitecture::Llama | ModelArchitecture::Phi3 | ModelArchitecture::Gemma => {
+                hub_load_safetensors(repo, "model.safetensors.index.json").await
+            }
         }
     }
 
-    pub async fn load_model<P: AsRef<Path>>(
+    /// Load the model from weight files.
+    /// If `devices` has more than one device, the layers are split across them.
+    pub fn load_model<P: AsRef<Path>>(
         &self,
         files: &[P],
-        repo: &ApiRepo,
-        device: &Device,
-        use_flash_attn: bool,
+        config_file: Option<&Path>,
+        devices: &DeviceMap,
+        options: LoadOptions,
     ) -> anyhow::Result<Model> {
+        let is_gguf = files
+            .iter()
+            .any(|file| file.as_ref().extension().is_some_and(|ext| ext == "gguf"));
+        if is_gguf != self.is_quantized() {
+            return Err(anyhow!(
+                "{self:?} expects {} weights",
+                if self.is_quantized() {
+                    "GGUF"
+                } else {
+                    "safetensors"
+                }
+            ));
+        }
+        let LoadOptions {
+            use_flash_attn,
+            adapter,
+            speculative,
+            dtype,
+            weight_loading,
+        } = options;
+        if adapter.is_some() && !self.supports_adapters() {
+            return Err(anyhow!("{self:?} does not support LoRA adapters"));
+        }
+        if devices.is_sharded() && !self.supports_sharding() {
+            return Err(anyhow!("{self:?} can't be split across multiple devices"));
+        }
+        if speculative && !self.supports_speculation() {
+            return Err(anyhow!("{self:?} doesn't support speculative decoding"));
+        }
+        if dtype.is_some() && self.is_quantized() {
+            return Err(anyhow!("{self:?} uses the types of its GGUF file"));
+        }
+        let device = devices.main();
+        // BF16 on CUDA and F32 elsewhere, unless overridden
+        let default_dtype = |cuda: DType, other: DType| {
+            dtype.unwrap_or(if device.is_cuda() { cuda } else { other })
+        };
+
         match self {
             ModelArchitecture::Mistral => {
-                let file = repo.get("config.json").await?;
-                let config = serde_json::from_slice(&std::fs::read(file)?)
-                    .context("unable to load Mistral config")?;
-                let dtype = if device.is_cuda() {
-                    DType::BF16
-                } else {
-                    DType::F32
-                };
-                let vb = unsafe { VarBuilder::from_mmaped_safetensors(files, dtype, device)? };
+                let config: MistralConfig =
+                    read_config(config_file).context("unable to load Mistral config")?;
+                let dtype = default_dtype(DType::BF16, DType::F32);
+                if devices.is_sharded() || speculative {
+                    let vbs =
+                        var_builders(files, dtype, devices.devices(), adapter, weight_loading)?;
+                    let weights = ShardedMistral::new(&config, devices, &vbs)?;
+                    return Ok(Model::ShardedMistral { weights, config });
+                }
+                let vb = var_builder(files, dtype, device, adapter, weight_loading)?;
                 let weights = Mistral::new(&config, vb)?;
                 Ok(Model::Mistral { weights, config })
             }
             ModelArchitecture::QMistral => {
                 let config = MistralConfig::config_7b_v0_1(use_flash_attn);
-                let filename = &files[0];
+                let filename = files.first().ok_or(anyhow!("no GGUF weight file given"))?;
                 let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
                     filename, device,
                 )?;
                 let weights = QMistral::new(&config, vb)?;
                 Ok(Model::QMistral { weights, config })
             }
-            ModelArchitecture::DistilBert => todo!(),
+            ModelArchitecture::DistilBert => Err(anyhow!(EMBEDDING_ONLY)),
             ModelArchitecture::Starcoder => {
-                let file = repo.get("config.json").await?;
-                let config = serde_json::from_slice(&std::fs::read(file)?)
-                    .context("unable to load Starcoder config")?;
-                let dtype = if device.is_cuda() {
-                    DType::BF16
-                } else {
-                    // DType::F32
-                    DType::F16
-                };
-                let vb = unsafe { VarBuilder::from_mmaped_safetensors(files, dtype, device)? };
+                let config: StarcoderConfig =
+                    read_config(config_file).context("unable to load Starcoder config")?;
+                let dtype = default_dtype(DType::BF16, DType::F16);
+                let vb = var_builder(files, dtype, device, None, weight_loading)?;
                 let weights = Starcoder::new(&config, vb)?;
                 Ok(Model::Starcoder { weights, config })
             }
+            ModelArchitecture::Llama => {
+                let config: LlamaJsonConfig =
+                    read_config(config_file).context("unable to load Llama config")?;
+                let config = config.into_config(use_flash_attn);
+                let dtype = default_dtype(DType::BF16, DType::F32);
+                let vb = var_builder(files, dtype, device, adapter, weight_loading)?;
+                let weights = Llama::load(vb, &config)?;
+                let empty_cache = LlamaCache::new(true, dtype, &config, device)?;
+                Ok(Model::Llama {
+                    weights,
+                    config,
+                    cache: empty_cache.clone(),
+                    empty_cache,
+                })
+            }
+            ModelArchitecture::Phi3 => {
+                let config: Phi3Config =
+                    read_config(config_file).context("unable to load Phi-3 config")?;
+                let dtype = default_dtype(DType::BF16, DType::F32);
+                let vb = var_builder(files, dtype, device, adapter, weight_loading)?;
+                let weights = Phi3::new(&config, vb)?;
+                Ok(Model::Phi3 { weights, config })
+            }
+            ModelArchitecture::Gemma => {
+                let config: GemmaConfig =
+                    read_config(config_file).context("unable to load Gemma config")?;
+                let dtype = default_dtype(DType::BF16, DType::F32);
+                let vb = var_builder(files, dtype, device, adapter, weight_loading)?;
+                let weights = Gemma::new(use_flash_attn, &config, vb)?;
+                Ok(Model::Gemma { weights, config })
+            }
         }
     }
 }
 
+/// Build a [`VarBuilder`] over safetensors weight files.
+/// The files are memory mapped unless [`WeightLoading::Full`] is asked for
+/// or there's an adapter to merge in.
+fn var_builder<P: AsRef<Path>>(
+    files: &[P],
+    dtype: DType,
+    device: &Device,
+    adapter: Option<&Path>,
+    weight_loading: WeightLoading,
+) -> anyhow::Result<VarBuilder<'static>> {
+    let mut vbs = var_builders(
+        files,
+        dtype,
+        std::slice::from_ref(device),
+        adapter,
+        weight_loading,
+    )?;
+    Ok(vbs.remove(0))
+}
+
+/// Build a [`VarBuilder`] for each device in `devices`,
+/// see [`var_builder`].
+/// The adapter is merged once on the first device.
+fn var_builders<P: AsRef<Path>>(
+    files: &[P],
+    dtype: DType,
+    devices: &[Device],
+    adapter: Option<&Path>,
+    weight_loading: WeightLoading,
+) -> anyhow::Result<Vec<VarBuilder<'static>>> {
+    if adapter.is_none() && weight_loading == WeightLoading::Mmap {
+        let mut vbs = Vec::with_capacity(devices.len());
+        for device in devices {
+            vbs.push(unsafe { VarBuilder::from_mmaped_safetensors(files, dtype, device)? });
+        }
+        return Ok(vbs);
+    }
+    let device = devices.first().ok_or(anyhow!("no devices given"))?;
+
+    let mut weights = HashMap::new();
+    for file in files {
+        weights.extend(candle_core::safetensors::load(file, device)?);
+    }
+    if let Some(adapter) = adapter {
+        let merged = LoraAdapter::load(adapter, device)?.merge(&mut weights)?;
+        tracing::info!(?adapter, merged, "merged LoRA adapter");
+    }
+
+    // tensors are moved to each builder's device as they are read
+    Ok(devices
+        .iter()
+        .map(|device| VarBuilder::from_tensors(weights.clone(), dtype, device))
+        .collect())
+}
+
+/// Parse a model's `config.json`
+fn read_config<T: DeserializeOwned>(config_file: Option<&Path>) -> anyhow::Result<T> {
+    let config_file = config_file.ok_or(anyhow!("no config file given"))?;
+    let contents = std::fs::read(config_file)
+        .with_context(|| format!("unable to read config file {config_file:?}"))?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
 impl Model {
+    /// Run `tokens[start_pos..]` through the model.
+    /// The tokens before `start_pos` must already be in the KV cache.
     #[instrument(skip(self))]
     fn forward(
         &mut self,
-        index: usize,
         tokens: &[u32],
+        start_pos: usize,
         device: &Device,
         repeat_penalty: f32,
         repeat_last_n: usize,
     ) -> Result<Tensor> {
-        tracing::trace!("forward pass on index {index}");
+        tracing::trace!("forward pass at position {start_pos}");
         tracing::debug!("tokens {:?}", &tokens);
-        let context_size = if index > 0 { 1 } else { tokens.len() };
-        let start_pos = tokens.len().saturating_sub(context_size);
         let context = &tokens[start_pos..];
         let input = Tensor::new(context, device)?.unsqueeze(0)?;
-        tracing::debug!(input_shape = ?input.shape(), start_pos, context_size);
+        tracing::debug!(input_shape = ?input.shape(), start_pos, context_size = context.len());
         let logits = match self {
             Model::Mistral { weights, config: _ } => weights.forward(&input, start_pos),
             Model::QMistral { weights, config: _ } => weights.forward(&input, start_pos),
+            Model::ShardedMistral { weights, config: _ } => weights.forward(&input, start_pos),
             Model::Starcoder { weights, config: _ } => weights.forward(&input, start_pos),
+            Model::Llama { weights, cache, .. } => weights.forward(&input, start_pos, cache),
+            Model::Phi3 { weights, config: _ } => weights.forward(&input, start_pos),
+            Model::Gemma { weights, config: _ } => weights.forward(&input, start_pos),
         }
         .inspect_err(|error| {
             tracing::error!(model = ?self, ?error);
@@ -190,11 +509,135 @@ impl Model {
         match self {
             Model::Mistral { weights, config: _ } => weights.clear_kv_cache(),
             Model::QMistral { weights, config: _ } => weights.clear_kv_cache(),
+            Model::ShardedMistral { weights, config: _ } => weights.clear_kv_cache(),
             Model::Starcoder { weights, config: _ } => weights.clear_kv_cache(),
+            Model::Llama {
+                cache, empty_cache, ..
+            } => *cache = empty_cache.clone(),
+            Model::Phi3 { weights, config: _ } => weights.clear_kv_cache(),
+            Model::Gemma { weights, config: _ } => weights.clear_kv_cache(),
+        }
+    }
+
+    /// A copy of the KV cache, if the model exposes it.
+    /// Tensors are reference counted, so their data isn't copied.
+    fn kv_cache(&self) -> Option<KvCache> {
+        match self {
+            Model::Llama { cache, .. } => Some(KvCache::Llama(cache.clone())),
+            Model::ShardedMistral { weights, config: _ } => {
+                Some(KvCache::ShardedMistral(weights.kv_cache()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the KV cache with one from [`Model::kv_cache`].
+    /// Returns false if it's from another kind of model.
+    fn set_kv_cache(&mut self, kv_cache: KvCache) -> bool {
+        match (self, kv_cache) {
+            (Model::Llama { cache, .. }, KvCache::Llama(saved)) => {
+                *cache = saved;
+                true
+            }
+            (Model::ShardedMistral { weights, config: _ }, KvCache::ShardedMistral(saved)) => {
+                weights.set_kv_cache(saved);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The logits for every position of `tokens[start_pos..]`, with shape `(len, vocab)`.
+    /// Only supported by models loaded for speculative decoding.
+    fn forward_all(&mut self, tokens: &[u32], start_pos: usize, device: &Device) -> Result<Tensor> {
+        let input = Tensor::new(&tokens[start_pos..], device)?.unsqueeze(0)?;
+        let logits = match self {
+            Model::ShardedMistral { weights, config: _ } => {
+                weights.forward_all(&input, start_pos)?
+            }
+            _ => return Err(anyhow!("model doesn't support speculative decoding").into()),
+        };
+        Ok(logits.squeeze(0)?.to_dtype(DType::F32)?)
+    }
+
+    /// The most tokens that can be run in one pass after positions already in the KV cache.
+    /// Candle's llama attention mask only covers the new tokens,
+    /// so llama has to run longer inputs from an empty KV cache.
+    pub fn max_cached_suffix(&self) -> usize {
+        match self {
+            Model::Llama { .. } => 1,
+            _ => usize::MAX,
+        }
+    }
+
+    /// Whether [`Model::truncate_kv_cache`] is supported
+    fn can_truncate_kv_cache(&self) -> bool {
+        matches!(self, Model::ShardedMistral { .. })
+    }
+
+    /// Drop everything after the first `len` positions from the KV cache.
+    /// Only supported by models loaded for speculative decoding.
+    fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        match self {
+            Model::ShardedMistral { weights, config: _ } => Ok(weights.truncate_kv_cache(len)?),
+            _ => Err(anyhow!("model doesn't support speculative decoding").into()),
         }
     }
 }
 
+/// The KV cache of the models that expose it
+#[derive(Clone, Debug)]
+enum KvCache {
+    Llama(LlamaCache),
+    ShardedMistral(Vec<Option<(Tensor, Tensor)>>),
+}
+
+/// A saved KV cache and the tokens it holds,
+/// so a conversation can continue from it after other runs used the model
+#[derive(Clone, Debug)]
+pub struct KvSnapshot {
+    /// The context it was saved from
+    owner: Weak<()>,
+    cache: KvCache,
+    tokens: Vec<u32>,
+}
+
+/// A small model that proposes tokens for the main model to verify
+pub struct Draft {
+    model: Model,
+    /// Tracks the draft model's KV cache
+    cache: PrefixCache,
+    /// The number of tokens proposed per step
+    tokens: usize,
+}
+
+impl Draft {
+    pub fn new(model: Model, tokens: usize, max_cached_tokens: usize) -> Self {
+        Draft {
+            model,
+            cache: PrefixCache::new(max_cached_tokens),
+            tokens,
+        }
+    }
+}
+
+/// Token counts and the seed from a model run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// The number of prompt tokens passed to the model
+    pub prompt_tokens: usize,
+    /// The number of prompt tokens dropped to fit the context
+    pub truncated_tokens: usize,
+    /// The number of prompt tokens reused from the prefix cache
+    #[serde(default)]
+    pub cached_tokens: usize,
+    pub generated_tokens: usize,
+    /// The seed tokens were sampled with,
+    /// pass it back in [`RunConfig::seed`] to reproduce the run
+    #[serde(default)]
+    pub seed: u64,
+}
+
 #[derive(Builder)]
 #[builder(pattern = "owned")]
 pub struct ModelContext {
@@ -202,9 +645,442 @@ pub struct ModelContext {
     #[builder(setter(into))]
     tokenizer: TokenOutputStream,
     device: Device,
+    /// Stats from the most recent call to [`ModelContext::run`]
+    #[builder(setter(skip))]
+    stats: RunStats,
+    /// Log probabilities from the most recent run, if they were requested
+    #[builder(setter(skip))]
+    logprobs: Vec<TokenLogprobs>,
+    /// Tracks the KV cache so it can be reused by the next run
+    #[builder(default)]
+    prefix_cache: PrefixCache,
+    /// Enables speculative decoding
+    #[builder(default)]
+    draft: Option<Draft>,
+    /// Identifies this context in the [`KvSnapshot`]s saved from it
+    #[builder(setter(skip))]
+    kv_owner: Arc<()>,
 }
 
 impl ModelContext {
+    pub fn max_context_len(&self) -> usize {
+        self.model.max_context_len()
+    }
+
+    /// Token counts from the most recent run.
+    /// These are updated as the run stream is consumed.
+    pub fn stats(&self) -> RunStats {
+        self.stats
+    }
+
+    /// Log probabilities of the tokens generated by the most recent run,
+    /// if [`RunConfig::logprobs`] was set.
+    /// These are updated as the run stream is consumed.
+    pub fn logprobs(&self) -> &[TokenLogprobs] {
+        &self.logprobs
+    }
+
+    pub fn chat_template(&self) -> ChatTemplate {
+        self.model.chat_template()
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        self.tokenizer.tokenizer()
+    }
+
+    /// The prompt for generating the text between `prefix` and `suffix`.
+    /// Fails if the model doesn't support fill-in-the-middle completion
+    pub fn infill_prompt(&self, prefix: &str, suffix: &str) -> Result<String> {
+        let template = self.model.fim_template().ok_or(Error::InfillUnsupported)?;
+        Ok(template.prompt(prefix, suffix))
+    }
+
+    /// Generate the text between `prefix` and `suffix`, e.g. code at an editor's cursor.
+    /// Only the generated text is streamed.
+    pub fn infill(
+        &mut self,
+        prefix: &str,
+        suffix: &str,
+        config: RunConfig,
+    ) -> Result<impl Stream<Item = Result<String>> + '_> {
+        let prompt = self.infill_prompt(prefix, suffix)?;
+        Ok(self.run(
+            prompt,
+            RunConfig {
+                echo_prompt: false,
+                ..config
+            },
+        ))
+    }
+
+    /// How often the KV cache was reused between runs
+    pub fn prefix_cache_stats(&self) -> PrefixCacheStats {
+        self.prefix_cache.stats()
+    }
+
+    /// Save the KV cache after a run, e.g. at the end of a chat turn.
+    /// `None` if it's empty or the model doesn't expose it.
+    pub fn save_kv_cache(&self) -> Option<KvSnapshot> {
+        if self.prefix_cache.is_empty() {
+            return None;
+        }
+        Some(KvSnapshot {
+            owner: Arc::downgrade(&self.kv_owner),
+            cache: self.model.kv_cache()?,
+            tokens: self.prefix_cache.tokens().to_vec(),
+        })
+    }
+
+    /// Continue from a saved KV cache, so the next run that extends its tokens
+    /// only runs the new ones, whatever ran in between.
+    /// Snapshots saved from another context are ignored.
+    pub fn restore_kv_cache(&mut self, snapshot: KvSnapshot) {
+        let KvSnapshot {
+            owner,
+            cache,
+            tokens,
+        } = snapshot;
+        if !owner
+            .upgrade()
+            .is_some_and(|owner| Arc::ptr_eq(&owner, &self.kv_owner))
+        {
+            return;
+        }
+        if self.model.set_kv_cache(cache) {
+            self.prefix_cache.restore(tokens);
+        }
+    }
+
+    /// Propose tokens with the draft model and verify them with one pass of the model.
+    /// Returns the accepted draft tokens followed by one token sampled by the model.
+    /// Every token is sampled from the model's logits,
+    /// so the output is the same as it would be without a draft.
+    fn speculate(
+        &mut self,
+        tokens: &[u32],
+        sampler: &mut Sampler,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        max_tokens: usize,
+    ) -> Result<Vec<u32>> {
+        let draft = self
+            .draft
+            .as_mut()
+            .ok_or(anyhow!("no draft model is loaded"))?;
+
+        // greedily propose tokens with the draft model
+        let mut proposal = tokens.to_vec();
+        for _ in 0..draft.tokens.min(max_tokens.saturating_sub(1)) {
+            let mut start_pos = draft.cache.len();
+            if proposal.len() - start_pos > draft.model.max_cached_suffix() {
+                draft.model.clear_kv_cache();
+                draft.cache.clear();
+                start_pos = 0;
+            }
+            let logits = draft
+                .model
+                .forward(&proposal, start_pos, &self.device, 1., repeat_last_n)
+                .inspect_err(|_| draft.cache.clear())?;
+            draft.cache.extend(&proposal, start_pos);
+            proposal.push(logits.argmax(D::Minus1)?.to_scalar::<u32>()?);
+        }
+        let drafted = &proposal[tokens.len()..];
+
+        // score every drafted position at once
+        let start_pos = self.prefix_cache.len();
+        let logits = self
+            .model
+            .forward_all(&proposal, start_pos, &self.device)
+            .inspect_err(|_| self.prefix_cache.clear())?;
+        self.prefix_cache.extend(&proposal, start_pos);
+
+        // the row predicting the token after `tokens`
+        let first_row = tokens.len() - 1 - start_pos;
+        let mut context = tokens.to_vec();
+        let mut accepted = Vec::with_capacity(drafted.len() + 1);
+        for index in 0..=drafted.len() {
+            let logits = logits.get(first_row + index)?;
+            let logits = if repeat_penalty == 1. {
+                logits
+            } else {
+                let start_at = context.len().saturating_sub(repeat_last_n);
+                candle_transformers::utils::apply_repeat_penalty(
+                    &logits,
+                    repeat_penalty,
+                    &context[start_at..],
+                )?
+            };
+            let token = sampler.sample(&logits)?;
+            accepted.push(token);
+            context.push(token);
+            if drafted.get(index) != Some(&token) {
+                break;
+            }
+        }
+        tracing::trace!(
+            drafted = drafted.len(),
+            accepted = accepted.len() - 1,
+            "verified draft tokens"
+        );
+
+        // roll back the rejected draft tokens,
+        // the last sampled token hasn't been run yet
+        let kept = tokens.len() + accepted.len() - 1;
+        if self.prefix_cache.len() > kept {
+            self.model.truncate_kv_cache(kept)?;
+            self.prefix_cache.truncate(kept);
+        }
+        if draft.cache.len() > kept {
+            draft.model.truncate_kv_cache(kept)?;
+            draft.cache.truncate(kept);
+        }
+
+        Ok(accepted)
+    }
+
+    fn token_logprobs(&self, logits: &Tensor, token: u32, top: usize) -> Result<TokenLogprobs> {
+        let decode = |id: u32| self.tokenizer.tokenizer().decode(&[id], false);
+        let (logprob, top) = logprobs(logits, token, top)?;
+        let top = top
+            .into_iter()
+            .map(|(id, logprob)| {
+                Ok(TokenLogprob {
+                    id,
+                    token: decode(id)?,
+                    logprob,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(TokenLogprobs {
+            token: TokenLogprob {
+                id: token,
+                token: decode(token)?,
+                logprob,
+            },
+            top,
+        })
+    }
+
+    /// Drop prompt tokens according to `context_overflow`
+    /// so that the prompt and the generated tokens fit in the context,
+    /// and record the prompt's stats
+    fn fit_prompt(
+        &mut self,
+        tokens: &mut Vec<u32>,
+        sample_len: usize,
+        context_overflow: ContextOverflow,
+    ) -> Result<()> {
+        let max_context_len = self.max_context_len();
+        let prompt_budget = match context_overflow {
+            ContextOverflow::Error => {
+                if tokens.len() + sample_len > max_context_len {
+                    return Err(Error::ContextOverflow {
+                        prompt_tokens: tokens.len(),
+                        sample_len,
+                        max_context_len,
+                    });
+                }
+                max_context_len
+            }
+            // leave at least half of the context for the prompt
+            ContextOverflow::Truncate => max_context_len
+                .saturating_sub(sample_len)
+                .max(max_context_len / 2),
+            // leave room for at least one generated token
+            ContextOverflow::SlidingWindow => max_context_len - 1,
+        };
+        let truncated_tokens = truncate_tokens(tokens, prompt_budget);
+        if truncated_tokens > 0 {
+            tracing::warn!(truncated_tokens, max_context_len, "truncated prompt");
+        }
+        self.stats.prompt_tokens = tokens.len();
+        self.stats.truncated_tokens = truncated_tokens;
+        Ok(())
+    }
+
+    fn eos_tokens(&self) -> Result<Vec<u32>> {
+        let eos_tokens: Vec<u32> = self
+            .model
+            .eos_tokens()
+            .iter()
+            .filter_map(|token| self.tokenizer.get_token(token))
+            .collect();
+        if eos_tokens.is_empty() {
+            return Err(anyhow!("no EOS token found").into());
+        }
+        Ok(eos_tokens)
+    }
+
+    /// Start a run with `tokens`, reusing the KV cache if it holds a prefix of them
+    fn reuse_kv_cache(&mut self, tokens: &[u32], deterministic: bool) {
+        if deterministic {
+            self.prefix_cache.clear();
+        }
+        let cached_tokens = self
+            .prefix_cache
+            .reuse(tokens, self.model.max_cached_suffix());
+        if cached_tokens == 0 {
+            self.model.clear_kv_cache();
+        } else {
+            tracing::debug!(cached_tokens, "reusing the KV cache");
+        }
+        self.stats.cached_tokens = cached_tokens;
+    }
+
+    /// The logits after `tokens`, only running the tokens that aren't in the KV cache.
+    /// If the KV cache holds tokens that diverge from `tokens`, like another beam's,
+    /// it's rolled back if the model supports it and cleared otherwise.
+    fn forward_cached(
+        &mut self,
+        tokens: &[u32],
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+    ) -> Result<Tensor> {
+        // at least one token has to be run to get the next logits
+        let mut start_pos = self
+            .prefix_cache
+            .common_prefix_len(tokens)
+            .min(tokens.len() - 1);
+        if tokens.len() - start_pos > self.model.max_cached_suffix() {
+            start_pos = 0;
+        } else if start_pos < self.prefix_cache.len() {
+            if start_pos > 0 && self.model.can_truncate_kv_cache() {
+                self.model.truncate_kv_cache(start_pos)?;
+                self.prefix_cache.truncate(start_pos);
+            } else {
+                start_pos = 0;
+            }
+        }
+        if start_pos == 0 {
+            self.model.clear_kv_cache();
+            self.prefix_cache.clear();
+        }
+
+        let logits = self
+            .model
+            .forward(
+                tokens,
+                start_pos,
+                &self.device,
+                repeat_penalty,
+                repeat_last_n,
+            )
+            // the KV cache may be partially updated
+            .inspect_err(|_| self.prefix_cache.clear())?;
+        self.prefix_cache.extend(tokens, start_pos);
+        Ok(logits)
+    }
+
+    /// Search [`RunConfig::num_beams`] beams
+    /// and return the [`RunConfig::n_best`] most likely completions, best first.
+    /// Beam search is deterministic, so the sampling options are ignored.
+    /// It can't be combined with `format`, `grammar`, or `logprobs`.
+    ///
+    /// The model keeps a single KV cache, so each beam is run from the point
+    /// where it diverges from the previous one if the model can roll back its KV cache,
+    /// and from the start of the prompt otherwise.
+    pub fn beam_search(&mut self, prompt: &str, config: &RunConfig) -> Result<Vec<Candidate>> {
+        let num_beams = config
+            .num_beams()
+            .ok_or(anyhow!("beam search needs `num_beams` or `n_best`"))?;
+        if config.format.is_some() || config.grammar.is_some() || config.logprobs.is_some() {
+            return Err(anyhow!(
+                "beam search can't be combined with `format`, `grammar`, or `logprobs`"
+            )
+            .into());
+        }
+        let RunConfig {
+            sample_len,
+            repeat_penalty,
+            repeat_last_n,
+            context_overflow,
+            deterministic,
+            ..
+        } = *config;
+
+        self.stats = RunStats::default();
+        self.logprobs.clear();
+
+        let mut prompt_tokens = encode(self.tokenizer.tokenizer(), prompt)?;
+        self.fit_prompt(&mut prompt_tokens, sample_len, context_overflow)?;
+        self.reuse_kv_cache(&prompt_tokens, deterministic);
+        let eos_tokens = self.eos_tokens()?;
+        let prompt_text = decode(self.tokenizer.tokenizer(), &prompt_tokens, true)?;
+        // the text generated by `tokens`, decoded with the prompt so that spacing is kept
+        let generated_text = |tokenizer: &Tokenizer, tokens: &[u32]| -> Result<String> {
+            let text = decode(
+                tokenizer,
+                &[prompt_tokens.as_slice(), tokens].concat(),
+                true,
+            )?;
+            match text.strip_prefix(&prompt_text) {
+                Some(generated) => Ok(generated.to_string()),
+                None => decode(tokenizer, tokens, true),
+            }
+        };
+
+        let start_gen = std::time::Instant::now();
+        tracing::info!(num_beams, "starting beam search");
+        let mut search = BeamSearch::new(num_beams);
+        let max_len = sample_len.min(self.max_context_len().saturating_sub(prompt_tokens.len()));
+        for _ in 0..max_len {
+            let mut extensions = Vec::with_capacity(search.beams().len() * num_beams * 2);
+            for (index, beam) in search.beams().iter().enumerate() {
+                let tokens = [prompt_tokens.as_slice(), &beam.tokens].concat();
+                let logits = self.forward_cached(&tokens, repeat_penalty, repeat_last_n)?;
+                // extra candidates in case some of them end their beam
+                extensions.extend(top_logprobs(&logits, num_beams * 2)?.into_iter().map(
+                    |(token, logprob)| Extension {
+                        beam: index,
+                        token,
+                        logprob,
+                    },
+                ));
+            }
+            let tokenizer = self.tokenizer.tokenizer();
+            search.step(extensions, |beam| {
+                beam.tokens
+                    .last()
+                    .is_some_and(|token| eos_tokens.contains(token))
+                    || (!config.stop.is_empty()
+                        && generated_text(tokenizer, &beam.tokens)
+                            .is_ok_and(|text| first_stop(&text, &config.stop).is_some()))
+            });
+            if search.is_done() {
+                break;
+            }
+        }
+
+        let candidates = search
+            .finish()
+            .into_iter()
+            .take(config.n_best.unwrap_or(1))
+            .map(|beam| {
+                let mut text = generated_text(self.tokenizer.tokenizer(), &beam.tokens)?;
+                if let Some(index) = first_stop(&text, &config.stop) {
+                    text.truncate(index);
+                }
+                Ok(Candidate {
+                    text,
+                    logprob: beam.logprob,
+                    score: beam.score(),
+                    tokens: beam.tokens.len(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.stats.generated_tokens = candidates.first().map_or(0, |best| best.tokens);
+
+        if self.prefix_cache.finish() {
+            self.model.clear_kv_cache();
+        }
+        tracing::info!(
+            candidates = candidates.len(),
+            "finished beam search in {:.2}s",
+            start_gen.elapsed().as_secs_f64(),
+        );
+        Ok(candidates)
+    }
+
     pub fn run(
         &mut self,
         prompt: String,
@@ -212,71 +1088,223 @@ impl ModelContext {
     ) -> impl Stream<Item = Result<String>> + '_ {
         let prompt = prompt.to_string();
         stream! {
+            let seed = config.resolve_seed();
+            let mut sampler = Sampler::new(&config, seed);
+
             let RunConfig {
-                seed,
-                temperature,
-                top_p,
                 sample_len,
                 repeat_penalty,
                 repeat_last_n,
+                echo_prompt,
+                stop,
+                context_overflow,
+                format,
+                grammar,
+                speculative,
+                logprobs: top_logprobs,
+                deterministic,
                 ..
             } = config;
 
+            let mut stop_sequences = StopSequences::new(stop);
+            let mut constraint = match Constraint::new(format, grammar.as_deref()) {
+                Ok(constraint) => constraint,
+                Err(error) => {
+                    yield Err(error);
+                    return;
+                }
+            };
+
             self.tokenizer.clear();
+            self.stats = RunStats {
+                seed,
+                ..Default::default()
+            };
+            self.logprobs.clear();
 
             tracing::debug!("initializing tokenizer");
 
-            let mut tokens = self
-                .tokenizer
-                .tokenizer()
-                .encode(prompt, true)?
-                .get_ids()
-                .to_vec();
+            let mut tokens = encode(self.tokenizer.tokenizer(), &prompt)?;
+
+            let max_context_len = self.max_context_len();
+            if let Err(error) = self.fit_prompt(&mut tokens, sample_len, context_overflow) {
+                yield Err(error);
+                return;
+            }
+
+            self.reuse_kv_cache(&tokens, deterministic);
+
+            // constrained output has to be checked one token at a time,
+            // and logprobs are only computed for tokens sampled one at a time
+            let draft_tokens = match &mut self.draft {
+                Some(draft)
+                    if speculative
+                        && !deterministic
+                        && constraint.is_none()
+                        && top_logprobs.is_none() =>
+                {
+                    if draft.cache.reuse(&tokens, draft.model.max_cached_suffix()) == 0 {
+                        draft.model.clear_kv_cache();
+                    }
+                    Some(draft.tokens)
+                }
+                _ => None,
+            };
 
             for &t in tokens.iter() {
                 if let Some(t) = self.tokenizer.next_token(t)? {
-                    yield Ok(t);
+                    if echo_prompt {
+                        yield Ok(t);
+                    }
                 }
             }
 
-            let eos_token = self
-                .tokenizer
-                .get_token(self.model.eos_token())
-                // startcoder -> "<|endoftext|>"
-                .ok_or(anyhow!("no EOS token found"))?;
+            let eos_tokens = match self.eos_tokens() {
+                Ok(eos_tokens) => eos_tokens,
+                Err(error) => {
+                    yield Err(error);
+                    return;
+                }
+            };
 
             let mut generated_tokens = 0usize;
 
-            let mut logits_processor = LogitsProcessor::new(seed, Some(temperature), top_p);
-
-            tracing::debug!("initialized logits");
-
             let start_gen = std::time::Instant::now();
             tracing::info!("starting generation");
-            for index in 0..sample_len {
-                let logits =
-                    self.model.forward(index, &tokens, &self.device, repeat_penalty, repeat_last_n)?;
+            'generate: while generated_tokens < sample_len {
+                if tokens.len() >= max_context_len {
+                    match context_overflow {
+                        ContextOverflow::SlidingWindow => {
+                            let dropped = truncate_tokens(&mut tokens, max_context_len / 2);
+                            tracing::debug!(dropped, "sliding the context window");
+                            self.model.clear_kv_cache();
+                            self.prefix_cache.clear();
+                            if let Some(draft) = &mut self.draft {
+                                draft.model.clear_kv_cache();
+                                draft.cache.clear();
+                            }
+                        }
+                        ContextOverflow::Error | ContextOverflow::Truncate => {
+                            tracing::warn!(max_context_len, "context is full");
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(draft_tokens) = draft_tokens {
+                    if tokens.len() + draft_tokens < max_context_len {
+                        let new_tokens = self.speculate(
+                            &tokens,
+                            &mut sampler,
+                            repeat_penalty,
+                            repeat_last_n,
+                            sample_len - generated_tokens,
+                        )?;
+                        for next_token in new_tokens {
+                            tokens.push(next_token);
+                            generated_tokens += 1;
+                            self.stats.generated_tokens = generated_tokens;
 
-                let next_token = logits_processor.sample(&logits)?;
+                            if eos_tokens.contains(&next_token) {
+                                break 'generate;
+                            }
+                            if let Some(t) = self.tokenizer.next_token(next_token)? {
+                                let StopOutput { text, stopped } = stop_sequences.push(&t);
+                                if !text.is_empty() {
+                                    yield Ok(text);
+                                }
+                                if stopped {
+                                    tracing::debug!("stop sequence found");
+                                    break 'generate;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                }
+
+                let start_pos = self.prefix_cache.len();
+                let logits = self
+                    .model
+                    .forward(
+                        &tokens,
+                        start_pos,
+                        &self.device,
+                        repeat_penalty,
+                        repeat_last_n,
+                    )
+                    // the KV cache may be partially updated
+                    .inspect_err(|_| self.prefix_cache.clear())?;
+                self.prefix_cache.extend(&tokens, start_pos);
+
+                let next_token = match &constraint {
+                    Some(constraint) => sampler.sample_constrained(&logits, |token| {
+                        if eos_tokens.contains(&token) {
+                            return constraint.is_complete();
+                        }
+                        match self.tokenizer.peek_token(token) {
+                            Ok(Some(text)) => constraint.accepts(&text),
+                            // part of a multi-byte character
+                            Ok(None) => constraint.accepts_partial_char(),
+                            Err(_) => false,
+                        }
+                    })?,
+                    None => sampler.sample(&logits)?,
+                };
+                if let Some(top) = top_logprobs {
+                    let token_logprobs = self.token_logprobs(&logits, next_token, top)?;
+                    self.logprobs.push(token_logprobs);
+                }
                 tokens.push(next_token);
                 generated_tokens += 1;
+                self.stats.generated_tokens = generated_tokens;
 
-                if next_token == eos_token {
+                if eos_tokens.contains(&next_token) {
                     break;
                 }
 
                 if let Some(t) = self.tokenizer.next_token(next_token)? {
-                    yield Ok(t);
+                    let StopOutput { text, stopped } = stop_sequences.push(&t);
+                    if !text.is_empty() {
+                        yield Ok(text);
+                    }
+                    if stopped {
+                        tracing::debug!("stop sequence found");
+                        break;
+                    }
+                    if let Some(constraint) = &mut constraint {
+                        constraint.push_str(&t);
+                        if constraint.is_finished() {
+                            tracing::debug!("constrained output is complete");
+                            break;
+                        }
+                    }
                 }
             }
 
             let dt = start_gen.elapsed();
             tracing::info!("finished generation");
-            if let Some(rest) = self.tokenizer.decode_rest()? {
-                yield Ok(rest);
+            if !stop_sequences.is_stopped() {
+                if let Some(rest) = self.tokenizer.decode_rest()? {
+                    let StopOutput { text, .. } = stop_sequences.push(&rest);
+                    if !text.is_empty() {
+                        yield Ok(text);
+                    }
+                }
+                let rest = stop_sequences.flush();
+                if !rest.is_empty() {
+                    yield Ok(rest);
+                }
             }
 
-            self.model.clear_kv_cache();
+            if self.prefix_cache.finish() {
+                self.model.clear_kv_cache();
+            }
+            if let Some(draft) = &mut self.draft {
+                if draft.cache.finish() {
+                    draft.model.clear_kv_cache();
+                }
+            }
             tracing::info!(
                 "\n{generated_tokens} tokens generated ({:.2} tokens/s)",
                 generated_tokens as f64 / dt.as_secs_f64(),
@@ -284,3 +1312,14 @@ impl ModelContext {
         }
     }
 }
+
+/// Drop the oldest tokens so that at most `max_len` remain,
+/// keeping the first token which is usually the BOS token.
+/// Returns the number of tokens dropped.
+fn truncate_tokens(tokens: &mut Vec<u32>, max_len: usize) -> usize {
+    let excess = tokens.len().saturating_sub(max_len.max(1));
+    if excess > 0 {
+        tokens.drain(1..=excess);
+    }
+    excess
+}
@@ -0,0 +1,208 @@
+//! Reuse the KV cache between runs that share a prompt prefix
+//!
+//! The models keep a single KV cache,
+//! so [`PrefixCache`] tracks which tokens it holds.
+//! A run whose prompt starts with those tokens, like the next turn of a chat,
+//! only has to run the model on the new tokens.
+//! Models that expose their KV cache can also save it with its tokens
+//! and restore it later, see [`ModelContext::save_kv_cache`](super::model::ModelContext::save_kv_cache).
+
+use serde::{Deserialize, Serialize};
+
+/// The default maximum number of tokens kept in the KV cache between runs
+pub const DEFAULT_MAX_CACHED_TOKENS: usize = 4096;
+
+/// Counters for how often the prefix cache was used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixCacheStats {
+    /// Runs that reused the KV cache
+    pub hits: u64,
+    /// Runs that had to start from an empty KV cache
+    pub misses: u64,
+    /// The total number of prompt tokens that didn't need to be recomputed
+    pub reused_tokens: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PrefixCache {
+    /// The tokens held by the model's KV cache, in order
+    tokens: Vec<u32>,
+    /// The cache is cleared after a run that leaves more tokens than this.
+    /// 0 disables reuse.
+    max_tokens: usize,
+    stats: PrefixCacheStats,
+}
+
+impl Default for PrefixCache {
+    fn default() -> Self {
+        PrefixCache::new(DEFAULT_MAX_CACHED_TOKENS)
+    }
+}
+
+impl PrefixCache {
+    pub fn new(max_tokens: usize) -> Self {
+        PrefixCache {
+            tokens: Vec::new(),
+            max_tokens,
+            stats: PrefixCacheStats::default(),
+        }
+    }
+
+    /// The number of tokens in the KV cache,
+    /// which is the position of the next token to run
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn stats(&self) -> PrefixCacheStats {
+        self.stats
+    }
+
+    /// The tokens held by the KV cache
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// Track a KV cache that was restored from a snapshot holding `tokens`
+    pub fn restore(&mut self, tokens: Vec<u32>) {
+        self.tokens = tokens;
+    }
+
+    /// Start a run with `prompt`.
+    /// Returns the number of prompt tokens that are already in the KV cache.
+    /// If this is 0 the KV cache must be cleared before running the model.
+    ///
+    /// The cache is only reused if at most `max_suffix` new tokens have to be run,
+    /// see [`Model::max_cached_suffix`](super::model::Model::max_cached_suffix).
+    pub fn reuse(&mut self, prompt: &[u32], max_suffix: usize) -> usize {
+        let cached = self.tokens.len();
+        // at least one token has to be run to get the next logits
+        if cached > 0
+            && cached < prompt.len()
+            && prompt.len() - cached <= max_suffix
+            && prompt.starts_with(&self.tokens)
+        {
+            self.stats.hits += 1;
+            self.stats.reused_tokens += cached as u64;
+            cached
+        } else {
+            self.stats.misses += 1;
+            self.tokens.clear();
+            0
+        }
+    }
+
+    /// The number of leading `tokens` that are in the KV cache
+    pub fn common_prefix_len(&self, tokens: &[u32]) -> usize {
+        self.tokens
+            .iter()
+            .zip(tokens)
+            .take_while(|(cached, token)| cached == token)
+            .count()
+    }
+
+    /// Record that `tokens[start..]` were run through the model
+    pub fn extend(&mut self, tokens: &[u32], start: usize) {
+        self.tokens.truncate(start);
+        self.tokens.extend_from_slice(&tokens[start..]);
+    }
+
+    /// Keep the first `len` tokens, after the KV cache was rolled back
+    pub fn truncate(&mut self, len: usize) {
+        self.tokens.truncate(len);
+    }
+
+    /// Forget the cached tokens, e.g. after the KV cache was cleared
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+
+    /// End a run.
+    /// Returns true if the cache is over its limit and the KV cache should be cleared.
+    pub fn finish(&mut self) -> bool {
+        if self.tokens.len() > self.max_tokens {
+            self.tokens.clear();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_extended_prompts() {
+        let mut cache = PrefixCache::new(100);
+        assert_eq!(cache.reuse(&[1, 2, 3], usize::MAX), 0);
+        cache.extend(&[1, 2, 3], 0);
+        cache.extend(&[1, 2, 3, 4], 3);
+        assert!(!cache.finish());
+
+        assert_eq!(cache.reuse(&[1, 2, 3, 4, 5, 6], usize::MAX), 4);
+        // the same prompt can't be reused since no tokens would be run
+        cache.extend(&[1, 2, 3, 4, 5, 6], 4);
+        assert_eq!(cache.reuse(&[1, 2, 3, 4, 5, 6], usize::MAX), 0);
+        assert!(cache.is_empty());
+
+        assert_eq!(
+            cache.stats(),
+            PrefixCacheStats {
+                hits: 1,
+                misses: 2,
+                reused_tokens: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn clears_diverging_prompts() {
+        let mut cache = PrefixCache::new(100);
+        cache.extend(&[1, 2, 3], 0);
+        assert_eq!(cache.common_prefix_len(&[1, 9, 3, 4]), 1);
+        assert_eq!(cache.reuse(&[1, 9, 3, 4], usize::MAX), 0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn reruns_long_suffixes() {
+        // e.g. llama, which can only run one token after cached positions
+        let mut cache = PrefixCache::new(100);
+        cache.extend(&[1, 2, 3], 0);
+        assert_eq!(cache.reuse(&[1, 2, 3, 4, 5], 1), 0);
+        assert!(cache.is_empty());
+
+        cache.extend(&[1, 2, 3], 0);
+        assert_eq!(cache.reuse(&[1, 2, 3, 4], 1), 3);
+    }
+
+    #[test]
+    fn limits_cached_tokens() {
+        let mut cache = PrefixCache::new(2);
+        cache.extend(&[1, 2, 3], 0);
+        assert!(cache.finish());
+        assert!(cache.is_empty());
+
+        let mut disabled = PrefixCache::new(0);
+        disabled.extend(&[1], 0);
+        assert!(disabled.finish());
+    }
+
+    #[test]
+    fn reuses_restored_tokens() {
+        let mut cache = PrefixCache::new(100);
+        cache.extend(&[1, 2, 3], 0);
+        let saved = cache.tokens().to_vec();
+
+        // another prompt ran in between
+        cache.extend(&[7, 8], 0);
+        cache.restore(saved);
+        assert_eq!(cache.reuse(&[1, 2, 3, 4], usize::MAX), 3);
+    }
+}
@@ -0,0 +1,324 @@
+//! Token sampling strategies applied on top of the model logits
+use candle_core::{DType, Tensor};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+use super::config::RunConfig;
+
+/// The number of most likely tokens that are checked first by [`Sampler::sample_constrained`],
+/// the rest of the vocabulary is only checked if none of them are allowed
+const MAX_CONSTRAINED_CANDIDATES: usize = 128;
+
+/// Samples the next token from a set of logits.
+/// Temperature, top-k, and top-p are handled by candle's [`LogitsProcessor`].
+/// min-p and typical-p mask out the logits of unlikely tokens before that.
+pub struct Sampler {
+    logits_processor: LogitsProcessor,
+    temperature: f64,
+    min_p: Option<f64>,
+    typical_p: Option<f64>,
+}
+
+impl Sampler {
+    pub fn new(config: &RunConfig, seed: u64) -> Self {
+        let temperature = config.temperature;
+        // matches the cutoff used by [`LogitsProcessor::new`]
+        let sampling = if temperature < 1e-7 {
+            Sampling::ArgMax
+        } else {
+            match (config.top_k, config.top_p) {
+                (None, None) => Sampling::All { temperature },
+                (Some(k), None) => Sampling::TopK { k, temperature },
+                (None, Some(p)) => Sampling::TopP { p, temperature },
+                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            }
+        };
+
+        Sampler {
+            logits_processor: LogitsProcessor::from_sampling(seed, sampling),
+            temperature,
+            min_p: config.min_p,
+            typical_p: config.typical_p,
+        }
+    }
+
+    pub fn sample(&mut self, logits: &Tensor) -> Result<u32> {
+        let logits = self.mask(logits)?;
+        Ok(self.logits_processor.sample(&logits)?)
+    }
+
+    /// Sample from the most likely tokens that pass `allowed`.
+    /// Used to constrain the output to a grammar.
+    pub fn sample_constrained(
+        &mut self,
+        logits: &Tensor,
+        allowed: impl Fn(u32) -> bool,
+    ) -> Result<u32> {
+        let logits = self.mask(logits)?;
+        let mut values: Vec<f32> = logits.to_dtype(DType::F32)?.to_vec1()?;
+
+        let keep = constrained_candidates(&values, MAX_CONSTRAINED_CANDIDATES, allowed);
+        if keep.is_empty() {
+            return Err(anyhow!("no token fits the output format").into());
+        }
+
+        let mut keep_mask = vec![false; values.len()];
+        for index in keep {
+            keep_mask[index] = true;
+        }
+        values
+            .iter_mut()
+            .zip(keep_mask)
+            .filter(|(_value, keep)| !keep)
+            .for_each(|(value, _keep)| *value = f32::NEG_INFINITY);
+
+        let logits = Tensor::from_vec(values, logits.dims(), logits.device())?;
+        Ok(self.logits_processor.sample(&logits)?)
+    }
+
+    /// Set the logits of tokens excluded by min-p and typical-p to negative infinity
+    fn mask(&self, logits: &Tensor) -> Result<Tensor> {
+        if self.min_p.is_none() && self.typical_p.is_none() {
+            return Ok(logits.clone());
+        }
+
+        let mut values: Vec<f32> = logits.to_dtype(DType::F32)?.to_vec1()?;
+        let probs = softmax(&values, self.temperature);
+
+        let mut keep = vec![true; probs.len()];
+        if let Some(min_p) = self.min_p {
+            min_p_filter(&probs, min_p, &mut keep);
+        }
+        if let Some(typical_p) = self.typical_p {
+            typical_filter(&probs, typical_p, &mut keep);
+        }
+
+        values
+            .iter_mut()
+            .zip(keep)
+            .filter(|(_value, keep)| !keep)
+            .for_each(|(value, _keep)| *value = f32::NEG_INFINITY);
+
+        Ok(Tensor::from_vec(values, logits.dims(), logits.device())?)
+    }
+}
+
+/// A token and its log probability
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub id: u32,
+    pub token: String,
+    pub logprob: f32,
+}
+
+/// The log probability of a generated token and of the most likely alternatives
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprobs {
+    #[serde(flatten)]
+    pub token: TokenLogprob,
+    /// The most likely tokens at this position, most likely first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub top: Vec<TokenLogprob>,
+}
+
+/// The log probability of `token` and the `top` most likely tokens with their log probabilities.
+/// These come from the model's logits before temperature and sampling filters are applied.
+pub fn logprobs(logits: &Tensor, token: u32, top: usize) -> Result<(f32, Vec<(u32, f32)>)> {
+    let values: Vec<f32> = logits.to_dtype(DType::F32)?.to_vec1()?;
+    let logprobs = log_softmax(&values);
+    let logprob = *logprobs
+        .get(token as usize)
+        .ok_or(anyhow!("token {token} is not in the vocabulary"))?;
+    Ok((logprob, most_likely(&logprobs, top)))
+}
+
+/// The `n` most likely tokens with their log probabilities, most likely first
+pub fn top_logprobs(logits: &Tensor, n: usize) -> Result<Vec<(u32, f32)>> {
+    let values: Vec<f32> = logits.to_dtype(DType::F32)?.to_vec1()?;
+    Ok(most_likely(&log_softmax(&values), n))
+}
+
+fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits
+        .iter()
+        .map(|logit| (logit - max).exp())
+        .sum::<f32>()
+        .ln();
+    logits.iter().map(|logit| logit - max - log_sum).collect()
+}
+
+/// The `n` largest values and their indices, largest first
+fn most_likely(values: &[f32], n: usize) -> Vec<(u32, f32)> {
+    let mut by_value: Vec<(u32, f32)> = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (index as u32, *value))
+        .collect();
+    by_value.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    by_value.truncate(n);
+    by_value
+}
+
+/// The indices of the finite logits that pass `allowed`.
+/// Only the `max_candidates` largest are checked,
+/// unless none of them pass and the whole vocabulary has to be.
+fn constrained_candidates(
+    logits: &[f32],
+    max_candidates: usize,
+    allowed: impl Fn(u32) -> bool,
+) -> Vec<usize> {
+    let mut by_logit: Vec<usize> = (0..logits.len())
+        .filter(|index| logits[*index].is_finite())
+        .collect();
+    by_logit.sort_by(|a, b| logits[*b].total_cmp(&logits[*a]));
+
+    let is_allowed = |index: &usize| {
+        u32::try_from(*index)
+            .map(|token| allowed(token))
+            .unwrap_or(false)
+    };
+    let split = max_candidates.min(by_logit.len());
+    let (likely, rest) = by_logit.split_at(split);
+    let candidates: Vec<usize> = likely.iter().copied().filter(&is_allowed).collect();
+    if !candidates.is_empty() {
+        return candidates;
+    }
+    rest.iter().copied().filter(&is_allowed).collect()
+}
+
+fn softmax(logits: &[f32], temperature: f64) -> Vec<f32> {
+    let temperature = temperature.max(f64::EPSILON) as f32;
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps: Vec<f32> = logits
+        .iter()
+        .map(|logit| ((logit - max) / temperature).exp())
+        .collect();
+    let sum: f32 = exps.iter().sum();
+    exps.into_iter().map(|exp| exp / sum).collect()
+}
+
+/// Only keep tokens with a probability of at least `min_p`
+/// times the probability of the most likely token.
+fn min_p_filter(probs: &[f32], min_p: f64, keep: &mut [bool]) {
+    let max = probs.iter().copied().fold(0., f32::max);
+    let threshold = max * min_p as f32;
+    for (keep, prob) in keep.iter_mut().zip(probs) {
+        *keep &= *prob >= threshold;
+    }
+}
+
+/// Locally typical sampling: only keep the tokens whose information content
+/// is closest to the entropy of the distribution,
+/// until their cumulative probability reaches `typical_p`.
+fn typical_filter(probs: &[f32], typical_p: f64, keep: &mut [bool]) {
+    let entropy: f32 = -probs
+        .iter()
+        .filter(|prob| **prob > 0.)
+        .map(|prob| prob * prob.ln())
+        .sum::<f32>();
+
+    let mut by_deviation: Vec<(usize, f32)> = probs
+        .iter()
+        .enumerate()
+        .filter(|(_index, prob)| **prob > 0.)
+        .map(|(index, prob)| (index, (-prob.ln() - entropy).abs()))
+        .collect();
+    by_deviation.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    let mut typical = vec![false; probs.len()];
+    let mut cumulative = 0.;
+    for (index, _deviation) in by_deviation {
+        typical[index] = true;
+        cumulative += probs[index];
+        if cumulative >= typical_p as f32 {
+            break;
+        }
+    }
+
+    for (keep, typical) in keep.iter_mut().zip(typical) {
+        *keep &= typical;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn softmax_sums_to_one() {
+        let probs = softmax(&[1.0, 2.0, 3.0], 1.0);
+        let sum: f32 = probs.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+        assert!(probs[2] > probs[1] && probs[1] > probs[0]);
+    }
+
+    #[test]
+    fn min_p_removes_unlikely_tokens() {
+        let probs = [0.5, 0.3, 0.15, 0.05];
+        let mut keep = vec![true; probs.len()];
+
+        min_p_filter(&probs, 0.2, &mut keep);
+
+        assert_eq!(keep, vec![true, true, true, false]);
+    }
+
+    #[test]
+    fn typical_p_keeps_at_least_one_token() {
+        let probs = [0.9, 0.05, 0.05];
+        let mut keep = vec![true; probs.len()];
+
+        typical_filter(&probs, 0.0, &mut keep);
+
+        assert_eq!(keep.iter().filter(|keep| **keep).count(), 1);
+    }
+
+    #[test]
+    fn constrained_candidates_are_the_most_likely_allowed_tokens() {
+        let logits = [0.1, 3.0, f32::NEG_INFINITY, 2.0, 1.0];
+
+        let candidates = constrained_candidates(&logits, 3, |token| token != 3);
+
+        assert_eq!(candidates, vec![1, 4]);
+    }
+
+    #[test]
+    fn constrained_candidates_fall_back_to_the_whole_vocabulary() {
+        let logits: Vec<f32> = (0..1000).map(|index| index as f32).collect();
+
+        let candidates = constrained_candidates(&logits, MAX_CONSTRAINED_CANDIDATES, |token| {
+            token == 3 || token == 7
+        });
+
+        assert_eq!(candidates, vec![7, 3]);
+    }
+
+    #[test]
+    fn log_softmax_matches_softmax() {
+        let logits = [1.0, 2.0, 3.0];
+        let probs = softmax(&logits, 1.0);
+        for (logprob, prob) in log_softmax(&logits).into_iter().zip(probs) {
+            assert!((logprob.exp() - prob).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn most_likely_is_sorted() {
+        let top = most_likely(&[-2.0, -0.5, -3.0, -1.0], 2);
+        assert_eq!(top, vec![(1, -0.5), (3, -1.0)]);
+    }
+
+    #[test]
+    fn typical_p_of_one_keeps_everything() {
+        let probs = [0.4, 0.3, 0.2, 0.1];
+        let mut keep = vec![true; probs.len()];
+
+        typical_filter(&probs, 1.0, &mut keep);
+
+        assert!(keep.iter().all(|keep| *keep));
+    }
+}
@@ -0,0 +1,160 @@
+//! Stop sequences that end generation early
+//!
+//! Streamed text that could be the beginning of a stop sequence
+//! is held back until it is either matched or ruled out,
+//! so partial stop sequences aren't emitted.
+
+/// The byte index of the first stop sequence in `text`
+pub fn first_stop(text: &str, sequences: &[String]) -> Option<usize> {
+    sequences
+        .iter()
+        .filter(|sequence| !sequence.is_empty())
+        .filter_map(|sequence| text.find(sequence.as_str()))
+        .min()
+}
+
+#[derive(Debug, Default)]
+pub struct StopSequences {
+    sequences: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+
+/// The result of pushing text through [`StopSequences`]
+#[derive(Debug, PartialEq)]
+pub struct StopOutput {
+    /// Text that is safe to emit
+    pub text: String,
+    /// True if a stop sequence was found and generation should end
+    pub stopped: bool,
+}
+
+impl StopSequences {
+    pub fn new(sequences: Vec<String>) -> Self {
+        StopSequences {
+            sequences: sequences
+                .into_iter()
+                .filter(|sequence| !sequence.is_empty())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn push(&mut self, text: &str) -> StopOutput {
+        if self.stopped {
+            return StopOutput {
+                text: String::new(),
+                stopped: true,
+            };
+        }
+
+        self.pending.push_str(text);
+
+        if let Some(index) = first_stop(&self.pending, &self.sequences) {
+            self.pending.truncate(index);
+            self.stopped = true;
+            return StopOutput {
+                text: std::mem::take(&mut self.pending),
+                stopped: true,
+            };
+        }
+
+        let held = self.partial_match_start();
+        let pending = self.pending.split_off(held);
+        let text = std::mem::replace(&mut self.pending, pending);
+
+        StopOutput {
+            text,
+            stopped: false,
+        }
+    }
+
+    /// Release any text that was held back
+    pub fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// The byte index of the longest suffix of the pending text
+    /// that is the beginning of a stop sequence
+    fn partial_match_start(&self) -> usize {
+        self.pending
+            .char_indices()
+            .map(|(index, _char)| index)
+            .find(|index| {
+                let suffix = &self.pending[*index..];
+                self.sequences
+                    .iter()
+                    .any(|sequence| sequence.starts_with(suffix))
+            })
+            .unwrap_or(self.pending.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(stop: &mut StopSequences, tokens: &[&str]) -> (String, bool) {
+        let mut output = String::new();
+        for token in tokens {
+            let StopOutput { text, stopped } = stop.push(token);
+            output.push_str(&text);
+            if stopped {
+                return (output, true);
+            }
+        }
+        output.push_str(&stop.flush());
+        (output, false)
+    }
+
+    #[test]
+    fn no_stop_sequences_passes_text_through() {
+        let mut stop = StopSequences::new(vec![]);
+        assert_eq!(
+            stop.push("hello"),
+            StopOutput {
+                text: "hello".to_string(),
+                stopped: false
+            }
+        );
+    }
+
+    #[test]
+    fn stops_on_sequence_split_across_tokens() {
+        let mut stop = StopSequences::new(vec!["\n\nUser:".to_string()]);
+        let (output, stopped) = push_all(&mut stop, &["Hi there", "!\n", "\nUs", "er:", " more"]);
+
+        assert!(stopped);
+        assert_eq!(output, "Hi there!");
+    }
+
+    #[test]
+    fn partial_match_is_released_when_ruled_out() {
+        let mut stop = StopSequences::new(vec!["END".to_string()]);
+
+        let first = stop.push("the EN");
+        assert_eq!(first.text, "the ");
+
+        let second = stop.push("D");
+        assert!(second.stopped);
+        assert_eq!(second.text, "");
+
+        let mut stop = StopSequences::new(vec!["END".to_string()]);
+        let (output, stopped) = push_all(&mut stop, &["the EN", "ding"]);
+        assert!(!stopped);
+        assert_eq!(output, "the ENding");
+    }
+
+    #[test]
+    fn earliest_of_multiple_sequences_wins() {
+        let mut stop = StopSequences::new(vec!["b".to_string(), "a".to_string()]);
+        let output = stop.push("xxaxxb");
+
+        assert!(output.stopped);
+        assert_eq!(output.text, "xx");
+    }
+}
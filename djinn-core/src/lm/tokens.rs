@@ -0,0 +1,33 @@
+//! Count, encode, and decode tokens the way a model sees them,
+//! without loading its weights.
+
+pub use tokenizers::Tokenizer;
+
+use crate::error::Result;
+use crate::hub::HubRepo;
+
+use super::{config::ModelConfig, ModelSource};
+
+/// Load only the tokenizer of a model, downloading it if needed
+pub async fn load_tokenizer(model_config: &ModelConfig) -> anyhow::Result<Tokenizer> {
+    let tokenizer_file = match &model_config.model_source {
+        ModelSource::HuggingFaceHub { revision } => {
+            HubRepo::model(model_config.variant.hf_repo_id(), revision)?
+                .get("tokenizer.json")
+                .await?
+        }
+        ModelSource::Files { tokenizer_file, .. } => tokenizer_file.clone(),
+    };
+    Tokenizer::from_file(tokenizer_file).map_err(anyhow::Error::msg)
+}
+
+/// The token IDs of `text` as it's encoded when it's a prompt,
+/// special tokens like BOS included
+pub fn encode(tokenizer: &Tokenizer, text: &str) -> Result<Vec<u32>> {
+    Ok(tokenizer.encode(text, true)?.get_ids().to_vec())
+}
+
+/// The text of token IDs
+pub fn decode(tokenizer: &Tokenizer, ids: &[u32], skip_special_tokens: bool) -> Result<String> {
+    Ok(tokenizer.decode(ids, skip_special_tokens)?)
+}
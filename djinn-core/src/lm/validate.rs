@@ -0,0 +1,453 @@
+//! Check model configs before loading them
+//!
+//! [`validate_model_run`] and [`validate_model_config`] parse TOML
+//! and collect every problem they find instead of stopping at the first:
+//! unknown keys, out of range sampling parameters, missing files,
+//! and options the model architecture or device doesn't support.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::device::Device;
+
+use super::config::{DraftConfig, ModelConfig, ModelRun, RunConfig, MAX_BEAMS, MAX_LOGPROBS};
+use super::grammar::Grammar;
+use super::model::ModelArchitecture;
+use super::ModelSource;
+
+/// A problem with a single key in a config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    /// The dotted path to the key, e.g. `run_config.temperature`.
+    /// Empty for problems with the whole file.
+    pub key: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Issue {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Every problem found in a config
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<Issue>);
+
+impl std::error::Error for ConfigErrors {}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "found {} problem(s) in the config:", self.0.len())?;
+        for issue in &self.0 {
+            writeln!(f, "  - {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse and check a [`ModelRun`].
+/// Relative paths are resolved from the working directory.
+pub fn validate_model_run(contents: &str) -> Result<ModelRun, ConfigErrors> {
+    let (run, mut issues) = parse::<ModelRun>(contents)?;
+    check_model_config(&run.model_config, "model_config", &mut issues);
+    check_run_config(&run.run_config, "run_config", &mut issues);
+    finish(run, issues)
+}
+
+/// Parse and check a [`ModelConfig`]
+pub fn validate_model_config(contents: &str) -> Result<ModelConfig, ConfigErrors> {
+    let (config, mut issues) = parse::<ModelConfig>(contents)?;
+    check_model_config(&config, "", &mut issues);
+    finish(config, issues)
+}
+
+fn finish<T>(value: T, issues: Vec<Issue>) -> Result<T, ConfigErrors> {
+    if issues.is_empty() {
+        Ok(value)
+    } else {
+        Err(ConfigErrors(issues))
+    }
+}
+
+/// Parse `contents` and report keys that aren't part of `T`
+fn parse<T: DeserializeOwned + Serialize>(contents: &str) -> Result<(T, Vec<Issue>), ConfigErrors> {
+    let parse_error =
+        |error: toml::de::Error| ConfigErrors(vec![Issue::new("", error.to_string().trim())]);
+    let input: toml::Value = toml::from_str(contents).map_err(parse_error)?;
+    let value: T = toml::from_str(contents).map_err(parse_error)?;
+
+    let mut issues = Vec::new();
+    // every key that was used ends up in the serialized value
+    if let Ok(known) = toml::Value::try_from(&value) {
+        unknown_keys(&input, &known, "", &mut issues);
+    }
+    Ok((value, issues))
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+fn unknown_keys(input: &toml::Value, known: &toml::Value, prefix: &str, issues: &mut Vec<Issue>) {
+    let (toml::Value::Table(input), toml::Value::Table(known)) = (input, known) else {
+        return;
+    };
+    for (key, value) in input {
+        let path = join(prefix, key);
+        match known.get(key) {
+            Some(known) => unknown_keys(value, known, &path, issues),
+            // empty values are skipped when serializing
+            None if is_empty(value) => {}
+            None => {
+                let message = match closest_key(key, known.keys()) {
+                    Some(suggestion) => format!("unknown key, did you mean `{suggestion}`?"),
+                    None => "unknown key".to_string(),
+                };
+                issues.push(Issue::new(path, message));
+            }
+        }
+    }
+}
+
+fn is_empty(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Array(array) => array.is_empty(),
+        toml::Value::Table(table) => table.is_empty(),
+        _ => false,
+    }
+}
+
+/// A known key that is a likely typo of `key`
+fn closest_key<'a>(key: &str, known: impl Iterator<Item = &'a String>) -> Option<&'a str> {
+    known
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn check_run_config(config: &RunConfig, prefix: &str, issues: &mut Vec<Issue>) {
+    let mut check = |key: &str, ok: bool, message: &str| {
+        if !ok {
+            issues.push(Issue::new(join(prefix, key), message));
+        }
+    };
+
+    check("sample_len", config.sample_len > 0, "must be at least 1");
+    check(
+        "temperature",
+        config.temperature >= 0.,
+        "must not be negative",
+    );
+    check(
+        "repeat_penalty",
+        config.repeat_penalty > 0.,
+        "must be greater than 0, 1.0 disables the penalty",
+    );
+    if let Some(top_p) = config.top_p {
+        check(
+            "top_p",
+            top_p > 0. && top_p <= 1.,
+            "must be greater than 0 and at most 1",
+        );
+    }
+    if let Some(top_k) = config.top_k {
+        check("top_k", top_k > 0, "must be at least 1");
+    }
+    if let Some(min_p) = config.min_p {
+        check(
+            "min_p",
+            (0. ..=1.).contains(&min_p),
+            "must be between 0 and 1",
+        );
+    }
+    if let Some(typical_p) = config.typical_p {
+        check(
+            "typical_p",
+            typical_p > 0. && typical_p <= 1.,
+            "must be greater than 0 and at most 1",
+        );
+    }
+    check(
+        "stop",
+        !config.stop.iter().any(String::is_empty),
+        "stop sequences must not be empty",
+    );
+    if let Some(logprobs) = config.logprobs {
+        check(
+            "logprobs",
+            logprobs <= MAX_LOGPROBS,
+            &format!("must be at most {MAX_LOGPROBS}"),
+        );
+    }
+    if let Some(grammar) = &config.grammar {
+        check(
+            "grammar",
+            config.format.is_none(),
+            "can't be combined with `format`",
+        );
+        if let Err(error) = grammar.parse::<Grammar>() {
+            check("grammar", false, &error.to_string());
+        }
+    }
+    if let Some(num_beams) = config.num_beams {
+        check(
+            "num_beams",
+            (1..=MAX_BEAMS).contains(&num_beams),
+            &format!("must be between 1 and {MAX_BEAMS}"),
+        );
+    }
+    if let Some(n_best) = config.n_best {
+        check(
+            "n_best",
+            n_best > 0 && n_best <= config.num_beams.unwrap_or(MAX_BEAMS),
+            "must be at least 1 and at most `num_beams`",
+        );
+    }
+    if config.num_beams().is_some() {
+        check(
+            "num_beams",
+            config.format.is_none() && config.grammar.is_none() && config.logprobs.is_none(),
+            "beam search can't be combined with `format`, `grammar`, or `logprobs`",
+        );
+    }
+}
+
+fn check_model_config(config: &ModelConfig, prefix: &str, issues: &mut Vec<Issue>) {
+    let variant = config.variant;
+    let key = |key: &str| join(prefix, key);
+
+    check_model_source(variant, &config.model_source, &key("model_source"), issues);
+
+    match config.device {
+        Device::Cuda if !candle_core::utils::cuda_is_available() => issues.push(Issue::new(
+            key("device"),
+            "djinn was built without CUDA support, use `cpu` or `auto` \
+            or rebuild with the `cuda` feature",
+        )),
+        Device::Metal if !candle_core::utils::metal_is_available() => issues.push(Issue::new(
+            key("device"),
+            "djinn was built without Metal support, use `cpu` or `auto` \
+            or rebuild with the `mac` feature",
+        )),
+        _ => {}
+    }
+    if config.flash_attn && matches!(config.device, Device::Cpu | Device::Metal) {
+        issues.push(Issue::new(
+            key("flash_attn"),
+            "flash attention is only supported on CUDA",
+        ));
+    }
+
+    if config.devices.len() > 1 && !variant.supports_sharding() {
+        issues.push(Issue::new(
+            key("devices"),
+            format!("{variant:?} can't be split across multiple devices"),
+        ));
+    }
+
+    if let Some(adapter) = &config.adapter {
+        if !variant.supports_adapters() {
+            issues.push(Issue::new(
+                key("adapter"),
+                format!("{variant:?} does not support LoRA adapters"),
+            ));
+        }
+        check_exists(adapter, &key("adapter"), issues);
+    }
+
+    if let Some(draft) = &config.draft {
+        check_draft(variant, draft, &key("draft"), issues);
+    }
+
+    if config.dtype.is_some() && variant.is_quantized() {
+        issues.push(Issue::new(
+            key("dtype"),
+            format!("{variant:?} uses the types of its GGUF file"),
+        ));
+    }
+}
+
+fn check_draft(
+    variant: ModelArchitecture,
+    draft: &DraftConfig,
+    prefix: &str,
+    issues: &mut Vec<Issue>,
+) {
+    if !variant.supports_speculation() {
+        issues.push(Issue::new(
+            prefix,
+            format!("{variant:?} doesn't support speculative decoding"),
+        ));
+    }
+    if !draft.variant.supports_speculation() {
+        issues.push(Issue::new(
+            join(prefix, "variant"),
+            format!("{:?} can't be used as a draft model", draft.variant),
+        ));
+    }
+    if draft.tokens == 0 {
+        issues.push(Issue::new(join(prefix, "tokens"), "must be at least 1"));
+    }
+    check_model_source(
+        draft.variant,
+        &draft.model_source,
+        &join(prefix, "model_source"),
+        issues,
+    );
+}
+
+fn check_model_source(
+    variant: ModelArchitecture,
+    source: &ModelSource,
+    prefix: &str,
+    issues: &mut Vec<Issue>,
+) {
+    let ModelSource::Files {
+        weight_files,
+        tokenizer_file,
+        config_file,
+    } = source
+    else {
+        return;
+    };
+    let key = |key: &str| join(prefix, &join("files", key));
+
+    if weight_files.is_empty() {
+        issues.push(Issue::new(key("weight_files"), "no weight files given"));
+    }
+    for file in weight_files {
+        check_exists(file, &key("weight_files"), issues);
+        let is_gguf = file.extension().is_some_and(|ext| ext == "gguf");
+        if is_gguf != variant.is_quantized() {
+            issues.push(Issue::new(
+                key("weight_files"),
+                format!(
+                    "{file:?} is not a {} file, which {variant:?} expects",
+                    if variant.is_quantized() {
+                        "GGUF"
+                    } else {
+                        "safetensors"
+                    }
+                ),
+            ));
+        }
+    }
+    check_exists(tokenizer_file, &key("tokenizer_file"), issues);
+    if let Some(config_file) = config_file {
+        check_exists(config_file, &key("config_file"), issues);
+    }
+}
+
+fn check_exists(path: &Path, key: &str, issues: &mut Vec<Issue>) {
+    if !path.exists() {
+        issues.push(Issue::new(key, format!("{path:?} does not exist")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN: &str = r#"
+prompt = "hi"
+
+[model_config]
+variant = "mistral"
+flash_atn = false
+
+[model_config.model_source.hugging_face_hub]
+revision = "main"
+
+[run_config]
+temperature = -1.0
+top_p = 0.9
+"#;
+
+    #[test]
+    fn reports_every_problem() {
+        let ConfigErrors(issues) = validate_model_run(RUN).unwrap_err();
+        // flash_attn is required, so the typo fails to parse
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("flash_attn"), "{issues:?}");
+
+        let run = RUN.replace("flash_atn = false", "flash_attn = false\nflash_atn = false");
+        let ConfigErrors(issues) = validate_model_run(&run).unwrap_err();
+        assert_eq!(
+            issues,
+            [
+                Issue::new(
+                    "model_config.flash_atn",
+                    "unknown key, did you mean `flash_attn`?"
+                ),
+                Issue::new("run_config.temperature", "must not be negative"),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_grammars() {
+        let run = RUN.replace(
+            "temperature = -1.0",
+            "grammar = 'root ::= \"yes\" | answer'",
+        );
+        let ConfigErrors(issues) = validate_model_run(&run).unwrap_err();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key, "run_config.grammar");
+
+        let run = RUN.replace(
+            "temperature = -1.0",
+            "grammar = 'root ::= \"yes\" | \"no\"'\nformat = \"json\"",
+        );
+        let ConfigErrors(issues) = validate_model_run(&run).unwrap_err();
+        assert_eq!(
+            issues,
+            [Issue::new(
+                "run_config.grammar",
+                "can't be combined with `format`"
+            )]
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("flash_atn", "flash_attn"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
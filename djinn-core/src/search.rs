@@ -0,0 +1,222 @@
+//! An index of embedded text chunks that can be searched by similarity to a query
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use crate::embed::{EmbeddingConfig, EmbeddingContext};
+
+/// The default size of a chunk.
+/// Embedders truncate long texts, so chunks are kept well below their limit
+pub const DEFAULT_CHUNK_CHARS: usize = 1000;
+
+/// Where a chunk is in its source text
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    /// The first line, starting at 1
+    pub start_line: usize,
+    /// The last line, inclusive
+    pub end_line: usize,
+    /// Byte offsets of the chunk in the source
+    pub start: usize,
+    pub end: usize,
+}
+
+/// An embedded part of a document
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Chunk {
+    pub source: PathBuf,
+    pub span: Span,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// A chunk that matched a query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// The cosine similarity of the chunk and the query
+    pub score: f32,
+    pub source: PathBuf,
+    pub span: Span,
+    pub text: String,
+}
+
+/// Embedded chunks and the embedder they were embedded with,
+/// which queries have to be embedded with too
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchIndex {
+    pub embedder: EmbeddingConfig,
+    pub chunks: Vec<Chunk>,
+}
+
+impl SearchIndex {
+    pub fn new(embedder: EmbeddingConfig) -> Self {
+        SearchIndex {
+            embedder,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read index {path:?}"))?;
+        serde_json::from_str(&contents).with_context(|| format!("invalid index {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("unable to write index {path:?}"))
+    }
+
+    /// Chunk and embed a document, replacing the chunks it had in the index.
+    /// Returns the number of chunks added
+    pub fn add(
+        &mut self,
+        embedder: &EmbeddingContext,
+        source: &Path,
+        text: &str,
+        max_chars: usize,
+    ) -> anyhow::Result<usize> {
+        self.chunks.retain(|chunk| chunk.source != source);
+        let spans = chunk_text(text, max_chars);
+        for span in &spans {
+            let text = &text[span.start..span.end];
+            self.chunks.push(Chunk {
+                source: source.to_path_buf(),
+                span: *span,
+                text: text.to_string(),
+                vector: embedder.embed(text)?.vector,
+            });
+        }
+        Ok(spans.len())
+    }
+
+    /// The `top_k` chunks most similar to an embedded query, best first
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<SearchHit> {
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (cosine_similarity(query, &chunk.vector), chunk))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        scored
+            .into_iter()
+            .take(top_k)
+            .map(|(score, chunk)| SearchHit {
+                score,
+                source: chunk.source.clone(),
+                span: chunk.span,
+                text: chunk.text.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Split text into chunks of whole lines of about `max_chars` characters,
+/// preferring to end chunks at blank lines.
+/// Lines longer than `max_chars` become their own chunk
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current: Option<(Span, usize)> = None;
+    let mut offset = 0;
+
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let line_number = index + 1;
+        let chars = line.chars().count();
+        let is_blank = line.trim().is_empty();
+
+        if let Some((span, len)) = current {
+            if len + chars > max_chars {
+                spans.push(span);
+                current = None;
+            }
+        }
+        match &mut current {
+            Some((span, len)) => {
+                span.end_line = line_number;
+                span.end = offset + line.len();
+                *len += chars;
+            }
+            // chunks don't start with blank lines
+            None if is_blank => {}
+            None => {
+                let span = Span {
+                    start_line: line_number,
+                    end_line: line_number,
+                    start: offset,
+                    end: offset + line.len(),
+                };
+                current = Some((span, chars));
+            }
+        }
+        // end the chunk at a paragraph break once it's half full
+        if let Some((span, len)) = current {
+            if is_blank && len >= max_chars / 2 {
+                spans.push(span);
+                current = None;
+            }
+        }
+        offset += line.len();
+    }
+    spans.extend(current.map(|(span, _)| span));
+    spans
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |vector: &[f32]| vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms > 0. {
+        dot / norms
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_at_paragraphs() {
+        let text = "one\ntwo\n\nthree\n\n\nfour five six\n";
+        let spans = chunk_text(text, 10);
+        let chunks: Vec<&str> = spans
+            .iter()
+            .map(|span| &text[span.start..span.end])
+            .collect();
+        assert_eq!(chunks, ["one\ntwo\n\n", "three\n\n", "four five six\n"]);
+        assert_eq!((spans[0].start_line, spans[0].end_line), (1, 3));
+        assert_eq!((spans[1].start_line, spans[1].end_line), (4, 5));
+        assert_eq!((spans[2].start_line, spans[2].end_line), (7, 7));
+    }
+
+    #[test]
+    fn ranks_by_similarity() {
+        let chunk = |name: &str, vector: Vec<f32>| Chunk {
+            source: name.into(),
+            span: Span {
+                start_line: 1,
+                end_line: 1,
+                start: 0,
+                end: 0,
+            },
+            text: name.to_string(),
+            vector,
+        };
+        let index = SearchIndex {
+            embedder: serde_json::from_str("{}").unwrap(),
+            chunks: vec![
+                chunk("far", vec![0., 1.]),
+                chunk("near", vec![1., 0.1]),
+                chunk("opposite", vec![-1., 0.]),
+            ],
+        };
+        let hits = index.search(&[1., 0.], 2);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].text, "near");
+        assert_eq!(hits[1].text, "far");
+        assert!(hits[0].score > 0.99);
+    }
+}
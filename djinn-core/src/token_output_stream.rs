@@ -30,39 +30,53 @@ impl TokenOutputStream {
     }
 
     // https://github.com/huggingface/text-generation-inference/blob/5ba53d44a18983a4de32d122f4cb46f4a17d9ef6/server/text_generation_server/models/model.py#L68
+    /// Add a token and return the text it completes, if any.
+    /// Text is held back while it ends in an incomplete character,
+    /// e.g. the first byte-fallback token of an emoji.
     pub fn next_token(&mut self, token: u32) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
-            String::new()
-        } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
-        };
         self.tokens.push(token);
-        let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
-            let text = text.split_at(prev_text.len());
+        let text = self.new_text(&self.tokens[self.prev_index..], false)?;
+        if text.is_some() {
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
-            Ok(Some(text.1.to_string()))
-        } else {
-            Ok(None)
         }
+        Ok(text)
     }
 
+    /// The text that [`Self::next_token`] would return for `token`
+    /// without adding it to the stream
+    pub fn peek_token(&self, token: u32) -> Result<Option<String>> {
+        let mut tokens = self.tokens[self.prev_index..].to_vec();
+        tokens.push(token);
+        self.new_text(&tokens, false)
+    }
+
+    /// The text that is still held back, incomplete characters included
     pub fn decode_rest(&self) -> Result<Option<String>> {
-        let prev_text = if self.tokens.is_empty() {
+        self.new_text(&self.tokens[self.prev_index..], true)
+    }
+
+    /// The text `tokens` add to the text that was already returned.
+    /// `tokens` start at `prev_index` so that the decoder sees the token before the new ones,
+    /// which keeps the leading space of the new ones for SentencePiece tokenizers.
+    fn new_text(&self, tokens: &[u32], flush: bool) -> Result<Option<String>> {
+        let prev_text = if self.prev_index == self.current_index {
             String::new()
         } else {
-            let tokens = &self.tokens[self.prev_index..self.current_index];
-            self.decode(tokens)?
+            self.decode(&self.tokens[self.prev_index..self.current_index])?
         };
-        let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() {
-            let text = text.split_at(prev_text.len());
-            Ok(Some(text.1.to_string()))
-        } else {
-            Ok(None)
+        let text = self.decode(tokens)?;
+        if !flush && text.ends_with(char::REPLACEMENT_CHARACTER) {
+            return Ok(None);
         }
+
+        let new_text = match text.strip_prefix(prev_text.as_str()) {
+            Some(new_text) => new_text,
+            // the new tokens changed how the previous ones decode,
+            // so only the text after what they have in common is new
+            None => &text[common_prefix_len(&prev_text, &text)..],
+        };
+        Ok((!new_text.is_empty()).then(|| new_text.to_string()))
     }
 
     pub fn decode_all(&self) -> Result<String> {
@@ -85,8 +99,163 @@ impl TokenOutputStream {
     }
 }
 
+/// The length in bytes of the longest common prefix, which ends at a character boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_index, a), b)| a != b)
+        .map_or(a.len().min(b.len()), |((index, _a), _b)| index)
+}
+
 impl From<Tokenizer> for TokenOutputStream {
     fn from(value: Tokenizer) -> Self {
         TokenOutputStream::new(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use super::*;
+
+    /// A SentencePiece tokenizer with byte fallback, like Mistral's and Llama 2's
+    const SENTENCEPIECE: &str = r#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [
+            {"id": 0, "content": "<unk>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true},
+            {"id": 1, "content": "<s>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true}
+        ],
+        "normalizer": null,
+        "pre_tokenizer": null,
+        "post_processor": null,
+        "decoder": {
+            "type": "Sequence",
+            "decoders": [
+                {"type": "Replace", "pattern": {"String": "▁"}, "content": " "},
+                {"type": "ByteFallback"},
+                {"type": "Fuse"},
+                {"type": "Strip", "content": " ", "start": 1, "stop": 0}
+            ]
+        },
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": "<unk>",
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": true,
+            "byte_fallback": true,
+            "vocab": {
+                "<unk>": 0, "<s>": 1, "▁Hello": 2, ",": 3, "▁world": 4, "▁café": 5, "▁": 6,
+                "<0xE4>": 7, "<0xBD>": 8, "<0xA0>": 9, "!": 10,
+                "<0xF0>": 11, "<0x9F>": 12, "<0x98>": 13, "<0x80>": 14
+            },
+            "merges": []
+        }
+    }"#;
+
+    /// A byte-level BPE tokenizer, like Llama 3's
+    const BYTE_LEVEL: &str = r#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": null,
+        "post_processor": null,
+        "decoder": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true},
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": null,
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "vocab": {"Hello": 0, "Ġworld": 1, "ä½": 2, "ł": 3, "!": 4},
+            "merges": []
+        }
+    }"#;
+
+    fn stream(tokenizer: &str, tokens: &[u32]) -> (TokenOutputStream, Vec<Option<String>>) {
+        let mut stream = TokenOutputStream::new(Tokenizer::from_str(tokenizer).unwrap());
+        let texts = tokens
+            .iter()
+            .map(|token| stream.next_token(*token).unwrap())
+            .collect();
+        (stream, texts)
+    }
+
+    fn some(text: &str) -> Option<String> {
+        Some(text.to_string())
+    }
+
+    #[test]
+    fn sentencepiece_keeps_spaces_and_joins_bytes() {
+        // <s> Hello, world café 你! 😀
+        let tokens = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 6, 11, 12, 13, 14];
+        let (stream, texts) = stream(SENTENCEPIECE, &tokens);
+
+        assert_eq!(
+            texts,
+            [
+                None,
+                some("Hello"),
+                some(","),
+                some(" world"),
+                some(" café"),
+                some(" "),
+                None,
+                None,
+                some("你"),
+                some("!"),
+                some(" "),
+                None,
+                None,
+                None,
+                some("😀"),
+            ]
+        );
+        let streamed: String = texts.into_iter().flatten().collect();
+        assert_eq!(streamed, stream.decode_all().unwrap());
+        assert_eq!(stream.decode_rest().unwrap(), None);
+    }
+
+    #[test]
+    fn byte_level_joins_split_characters() {
+        let (stream, texts) = stream(BYTE_LEVEL, &[0, 1, 2, 3, 4]);
+
+        assert_eq!(
+            texts,
+            [some("Hello"), some(" world"), None, some("你"), some("!")]
+        );
+        assert_eq!(stream.decode_all().unwrap(), "Hello world你!");
+    }
+
+    #[test]
+    fn rest_includes_incomplete_characters() {
+        let (stream, texts) = stream(SENTENCEPIECE, &[2, 11, 12]);
+
+        assert_eq!(texts, [some("Hello"), None, None]);
+        assert_eq!(
+            stream.peek_token(13).unwrap(),
+            None,
+            "the emoji is still incomplete"
+        );
+        assert_eq!(stream.peek_token(3).unwrap(), some("\u{FFFD}\u{FFFD},"));
+        assert!(stream
+            .decode_rest()
+            .unwrap()
+            .is_some_and(|rest| rest.ends_with(char::REPLACEMENT_CHARACTER)));
+    }
+
+    #[test]
+    fn common_prefixes_end_at_characters() {
+        assert_eq!(common_prefix_len("café", "cafe"), 3);
+        assert_eq!(common_prefix_len("日本", "日本語"), 6);
+        assert_eq!(common_prefix_len("abc", "xyz"), 0);
+    }
+}
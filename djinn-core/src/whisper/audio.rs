@@ -0,0 +1,194 @@
+//! Decoding audio files into the 16kHz mono samples Whisper expects
+
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{anyhow, Context as _};
+use candle_transformers::models::whisper::SAMPLE_RATE;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// Read an audio file, see [`decode_audio`]
+pub fn read_audio(path: &Path) -> anyhow::Result<Vec<f32>> {
+    let bytes = std::fs::read(path).with_context(|| format!("unable to read audio {path:?}"))?;
+    decode_audio(bytes)
+}
+
+/// Decode the first audio track of a file in any format symphonia supports,
+/// like WAV, MP3, FLAC, or Ogg Vorbis,
+/// into mono samples at Whisper's sample rate
+pub fn decode_audio(bytes: impl AsRef<[u8]> + Send + Sync + 'static) -> anyhow::Result<Vec<f32>> {
+    let source = MediaSourceStream::new(
+        Box::new(Cursor::new(bytes)),
+        MediaSourceStreamOptions::default(),
+    );
+    let probed = symphonia::default::get_probe().format(
+        &Hint::new(),
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(anyhow!("no audio track found"))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or(anyhow!("unknown sample rate"))?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(error))
+                if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break
+            }
+            Err(error) => return Err(error.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a corrupt packet only loses its own samples
+            Err(SymphoniaError::DecodeError(error)) => {
+                tracing::warn!(error, "skipping undecodable packet");
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend(mix_down(buffer.samples(), spec.channels.count()));
+    }
+
+    Ok(resample(&samples, sample_rate, SAMPLE_RATE as u32))
+}
+
+/// Average interleaved channels into one
+fn mix_down(samples: &[f32], channels: usize) -> impl Iterator<Item = f32> + '_ {
+    let channels = channels.max(1);
+    samples
+        .chunks(channels)
+        .map(move |frame| frame.iter().sum::<f32>() / channels as f32)
+}
+
+/// Linearly interpolate samples from one sample rate to another
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let len = (samples.len() as f64 / ratio).round() as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position.floor() as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index.min(samples.len() - 1)];
+            let next = samples[(index + 1).min(samples.len() - 1)];
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+/// A mel filterbank like librosa's with Slaney normalization,
+/// which is what Whisper was trained with.
+/// Returns `n_mels` rows of `n_fft / 2 + 1` weights
+pub fn mel_filters(n_mels: usize, n_fft: usize, sample_rate: usize) -> Vec<f32> {
+    let n_freqs = n_fft / 2 + 1;
+    let fft_freqs: Vec<f64> = (0..n_freqs)
+        .map(|i| i as f64 * sample_rate as f64 / n_fft as f64)
+        .collect();
+
+    let max_mel = hz_to_mel(sample_rate as f64 / 2.);
+    let mel_freqs: Vec<f64> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(max_mel * i as f64 / (n_mels + 1) as f64))
+        .collect();
+
+    let mut filters = vec![0.; n_mels * n_freqs];
+    for mel in 0..n_mels {
+        let (lower, center, upper) = (mel_freqs[mel], mel_freqs[mel + 1], mel_freqs[mel + 2]);
+        // scale each triangle to the same area
+        let norm = 2. / (upper - lower);
+        for (freq, &hz) in fft_freqs.iter().enumerate() {
+            let rising = (hz - lower) / (center - lower);
+            let falling = (upper - hz) / (upper - center);
+            let weight = rising.min(falling).max(0.);
+            filters[mel * n_freqs + freq] = (weight * norm) as f32;
+        }
+    }
+    filters
+}
+
+// The Slaney mel scale is linear below 1kHz and logarithmic above
+const MIN_LOG_HZ: f64 = 1000.;
+const HZ_PER_MEL: f64 = 200. / 3.;
+const MIN_LOG_MEL: f64 = MIN_LOG_HZ / HZ_PER_MEL;
+
+fn log_step() -> f64 {
+    6.4f64.ln() / 27.
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    if hz < MIN_LOG_HZ {
+        hz / HZ_PER_MEL
+    } else {
+        MIN_LOG_MEL + (hz / MIN_LOG_HZ).ln() / log_step()
+    }
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    if mel < MIN_LOG_MEL {
+        mel * HZ_PER_MEL
+    } else {
+        MIN_LOG_HZ * ((mel - MIN_LOG_MEL) * log_step()).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixes_and_resamples() {
+        let stereo = [1., 0., 0.5, 0.5];
+        assert_eq!(mix_down(&stereo, 2).collect::<Vec<_>>(), [0.5, 0.5]);
+
+        assert_eq!(resample(&[0., 1., 2., 3.], 32_000, 16_000), [0., 2.]);
+        assert_eq!(resample(&[0., 1.], 8_000, 16_000), [0., 0.5, 1., 1.]);
+    }
+
+    #[test]
+    fn builds_slaney_mel_filters() {
+        for hz in [0., 500., 1000., 4000., 8000.] {
+            assert!((mel_to_hz(hz_to_mel(hz)) - hz).abs() < 1e-6);
+        }
+
+        let filters = mel_filters(80, 400, 16_000);
+        assert_eq!(filters.len(), 80 * 201);
+        // the first filter peaks at the first frequency bin above 0Hz
+        let first = &filters[..201];
+        assert_eq!(first[0], 0.);
+        assert!(first[1] > 0.);
+        assert!(first[10..].iter().all(|&weight| weight == 0.));
+        assert!(filters.iter().all(|&weight| weight >= 0.));
+    }
+}
@@ -0,0 +1,376 @@
+//! Speech to text with OpenAI's Whisper models
+
+pub mod audio;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _};
+use candle_core::{Device, IndexOp, Tensor, D};
+use candle_nn::VarBuilder;
+use candle_transformers::models::whisper::{
+    audio::pcm_to_mel, model::Whisper, Config, DTYPE, EOT_TOKEN, HOP_LENGTH, NO_TIMESTAMPS_TOKEN,
+    N_FFT, N_FRAMES, SAMPLE_RATE, SOT_TOKEN, TRANSCRIBE_TOKEN, TRANSLATE_TOKEN,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use crate::hub::HubRepo;
+use crate::lm::ModelSource;
+
+pub use audio::{decode_audio, read_audio};
+
+const DEFAULT_REVISION: &str = "main";
+
+/// The Whisper model used to transcribe audio.
+/// The `*_en` variants only understand English
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhisperArchitecture {
+    Tiny,
+    TinyEn,
+    #[default]
+    Base,
+    BaseEn,
+    Small,
+    SmallEn,
+    Medium,
+    MediumEn,
+    LargeV3,
+    DistilLargeV3,
+}
+
+impl WhisperArchitecture {
+    pub fn hf_repo_id(&self) -> &'static str {
+        match self {
+            WhisperArchitecture::Tiny => "openai/whisper-tiny",
+            WhisperArchitecture::TinyEn => "openai/whisper-tiny.en",
+            WhisperArchitecture::Base => "openai/whisper-base",
+            WhisperArchitecture::BaseEn => "openai/whisper-base.en",
+            WhisperArchitecture::Small => "openai/whisper-small",
+            WhisperArchitecture::SmallEn => "openai/whisper-small.en",
+            WhisperArchitecture::Medium => "openai/whisper-medium",
+            WhisperArchitecture::MediumEn => "openai/whisper-medium.en",
+            WhisperArchitecture::LargeV3 => "openai/whisper-large-v3",
+            WhisperArchitecture::DistilLargeV3 => "distil-whisper/distil-large-v3",
+        }
+    }
+
+    /// Multilingual models detect the language and can translate to English
+    pub fn is_multilingual(&self) -> bool {
+        !matches!(
+            self,
+            WhisperArchitecture::TinyEn
+                | WhisperArchitecture::BaseEn
+                | WhisperArchitecture::SmallEn
+                | WhisperArchitecture::MediumEn
+        )
+    }
+}
+
+fn default_model_source() -> ModelSource {
+    ModelSource::HuggingFaceHub {
+        revision: DEFAULT_REVISION.to_string(),
+    }
+}
+
+/// Configuration for loading a [`TranscriptionContext`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TranscriberConfig {
+    #[serde(default)]
+    pub variant: WhisperArchitecture,
+    #[serde(default)]
+    pub device: crate::device::Device,
+    #[serde(default = "default_model_source")]
+    pub model_source: ModelSource,
+}
+
+/// Options for a single transcription
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TranscribeOptions {
+    /// The spoken language as a code like `en` or `de`.
+    /// Multilingual models detect it if it isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Translate the speech to English instead of transcribing it
+    #[serde(default)]
+    pub translate: bool,
+}
+
+/// The text of about 30 seconds of audio
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Seconds from the start of the audio
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transcription {
+    pub text: String,
+    /// The language of the speech if the model is multilingual
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    pub segments: Vec<Segment>,
+}
+
+/// A loaded Whisper model that can be reused across recordings
+pub struct TranscriptionContext {
+    model: Whisper,
+    tokenizer: Tokenizer,
+    device: Device,
+    variant: WhisperArchitecture,
+    mel_filters: Vec<f32>,
+    /// Added to the logits to keep the model from generating
+    /// the tokens listed in the config's `suppress_tokens`
+    suppress_tokens: Tensor,
+}
+
+impl TranscriptionContext {
+    pub async fn from_config(config: &TranscriberConfig) -> anyhow::Result<Self> {
+        let (weight_files, config_file, tokenizer_file) = match &config.model_source {
+            ModelSource::HuggingFaceHub { revision } => {
+                let repo = HubRepo::model(config.variant.hf_repo_id(), revision)?;
+                (
+                    vec![repo.get("model.safetensors").await?],
+                    repo.get("config.json").await?,
+                    repo.get("tokenizer.json").await?,
+                )
+            }
+            ModelSource::Files {
+                weight_files,
+                tokenizer_file,
+                config_file,
+            } => {
+                let config_file = match config_file {
+                    Some(config_file) => config_file.clone(),
+                    None => weight_files
+                        .first()
+                        .and_then(|file| file.parent())
+                        .map(|dir| dir.join("config.json"))
+                        .ok_or(anyhow!("no weight files given"))?,
+                };
+                (weight_files.clone(), config_file, tokenizer_file.clone())
+            }
+        };
+
+        let device: Device = config.device.try_into()?;
+        let model = load_model(&weight_files, &config_file, &device)?;
+        let tokenizer = Tokenizer::from_file(&tokenizer_file).map_err(anyhow::Error::msg)?;
+
+        let mel_filters = audio::mel_filters(model.config.num_mel_bins, N_FFT, SAMPLE_RATE);
+        let suppress_tokens: Vec<f32> = (0..model.config.vocab_size as u32)
+            .map(|token| {
+                if model.config.suppress_tokens.contains(&token) {
+                    f32::NEG_INFINITY
+                } else {
+                    0.
+                }
+            })
+            .collect();
+        let suppress_tokens = Tensor::new(suppress_tokens.as_slice(), &device)?;
+
+        tracing::info!(variant = ?config.variant, "loaded whisper model");
+
+        Ok(TranscriptionContext {
+            model,
+            tokenizer,
+            device,
+            variant: config.variant,
+            mel_filters,
+            suppress_tokens,
+        })
+    }
+
+    /// Transcribe mono samples at 16kHz, see [`decode_audio`].
+    /// The audio is decoded greedily in 30 second windows
+    pub fn transcribe(
+        &mut self,
+        samples: &[f32],
+        options: &TranscribeOptions,
+    ) -> anyhow::Result<Transcription> {
+        self.check_options(options)?;
+
+        let num_mel_bins = self.model.config.num_mel_bins;
+        let mel = pcm_to_mel(&self.model.config, samples, &self.mel_filters);
+        let mel_len = mel.len() / num_mel_bins;
+        let mel = Tensor::from_vec(mel, (1, num_mel_bins, mel_len), &self.device)?;
+        // the mel spectrogram is padded by a window, which isn't audio
+        let content_frames = samples.len() / HOP_LENGTH;
+
+        let mut language = None;
+        let mut segments = Vec::new();
+        let mut seek = 0;
+        while seek < content_frames {
+            let frames = usize::min(mel_len - seek, N_FRAMES);
+            let window = mel.narrow(2, seek, frames)?;
+            let features = self.model.encoder.forward(&window, true)?;
+
+            let language_token = match (&options.language, language) {
+                (Some(code), _) => Some(self.language_token(code)?),
+                (None, Some(token)) => Some(token),
+                (None, None) if self.variant.is_multilingual() => {
+                    let token = self.detect_language(&features)?;
+                    language = Some(token);
+                    Some(token)
+                }
+                (None, None) => None,
+            };
+
+            let text = self.decode(&features, language_token, options.translate)?;
+            let start = seek;
+            seek += frames;
+            segments.push(Segment {
+                start: frames_to_secs(start),
+                end: frames_to_secs(seek.min(content_frames)),
+                text: text.trim().to_string(),
+            });
+        }
+
+        let text = segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let language = match (&options.language, language) {
+            (Some(code), _) => Some(code.clone()),
+            (None, Some(token)) => self.language_code(token),
+            (None, None) => None,
+        };
+
+        Ok(Transcription {
+            text,
+            language,
+            segments,
+        })
+    }
+
+    /// Check that the model can transcribe with these options
+    pub fn check_options(&self, options: &TranscribeOptions) -> anyhow::Result<()> {
+        if options.translate && !self.variant.is_multilingual() {
+            anyhow::bail!("{:?} can't translate, it only knows English", self.variant);
+        }
+        if let Some(code) = &options.language {
+            self.language_token(code)?;
+        }
+        Ok(())
+    }
+
+    /// Greedily decode the text of one window of audio features
+    fn decode(
+        &mut self,
+        features: &Tensor,
+        language_token: Option<u32>,
+        translate: bool,
+    ) -> anyhow::Result<String> {
+        let task_token = if translate {
+            TRANSLATE_TOKEN
+        } else {
+            TRANSCRIBE_TOKEN
+        };
+        let mut tokens = vec![self.token_id(SOT_TOKEN)?];
+        tokens.extend(language_token);
+        tokens.push(self.token_id(task_token)?);
+        tokens.push(self.token_id(NO_TIMESTAMPS_TOKEN)?);
+        let prompt_len = tokens.len();
+        let eot_token = self.token_id(EOT_TOKEN)?;
+
+        let max_tokens = self.model.config.max_target_positions / 2;
+        for i in 0..max_tokens {
+            let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+            let hidden = self.model.decoder.forward(&input, features, i == 0)?;
+            let (_, seq_len, _) = hidden.dims3()?;
+            let logits = self
+                .model
+                .decoder
+                .final_linear(&hidden.i((..1, seq_len - 1..))?)?
+                .i(0)?
+                .i(0)?
+                .broadcast_add(&self.suppress_tokens)?;
+            let next_token = logits.argmax(D::Minus1)?.to_scalar::<u32>()?;
+            if next_token == eot_token {
+                break;
+            }
+            tokens.push(next_token);
+        }
+
+        self.tokenizer
+            .decode(&tokens[prompt_len..], true)
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// The most likely language token after the start of transcript token.
+    /// Language tokens come right after it in multilingual vocabularies
+    fn detect_language(&mut self, features: &Tensor) -> anyhow::Result<u32> {
+        let sot_token = self.token_id(SOT_TOKEN)?;
+        let first_language = sot_token + 1;
+        let last_language = self.token_id(TRANSLATE_TOKEN)?;
+
+        let input = Tensor::new(&[[sot_token]], &self.device)?;
+        let hidden = self.model.decoder.forward(&input, features, true)?;
+        let logits = self
+            .model
+            .decoder
+            .final_linear(&hidden.i(..1)?)?
+            .i(0)?
+            .i(0)?;
+        let language_logits = logits.narrow(
+            0,
+            first_language as usize,
+            (last_language - first_language) as usize,
+        )?;
+        let index = language_logits.argmax(D::Minus1)?.to_scalar::<u32>()?;
+        let token = first_language + index;
+        tracing::debug!(language = ?self.language_code(token), "detected language");
+        Ok(token)
+    }
+
+    fn language_token(&self, code: &str) -> anyhow::Result<u32> {
+        if !self.variant.is_multilingual() {
+            anyhow::bail!("{:?} only knows English", self.variant);
+        }
+        self.token_id(&format!("<|{code}|>"))
+            .map_err(|_| anyhow!("unsupported language {code:?}"))
+    }
+
+    /// The code of a language token, like `en` for `<|en|>`
+    fn language_code(&self, token: u32) -> Option<String> {
+        let token = self.tokenizer.id_to_token(token)?;
+        Some(token.strip_prefix("<|")?.strip_suffix("|>")?.to_string())
+    }
+
+    fn token_id(&self, token: &str) -> anyhow::Result<u32> {
+        self.tokenizer
+            .token_to_id(token)
+            .ok_or(anyhow!("no token {token:?} in the tokenizer"))
+    }
+}
+
+fn load_model(
+    weight_files: &[PathBuf],
+    config_file: &Path,
+    device: &Device,
+) -> anyhow::Result<Whisper> {
+    let config = std::fs::read_to_string(config_file)
+        .with_context(|| format!("unable to read model config {config_file:?}"))?;
+    let config: Config = serde_json::from_str(&config)?;
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors(weight_files, DTYPE, device)? };
+    Ok(Whisper::load(&vb, config)?)
+}
+
+fn frames_to_secs(frames: usize) -> f64 {
+    (frames * HOP_LENGTH) as f64 / SAMPLE_RATE as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_frames_to_seconds() {
+        assert_eq!(frames_to_secs(0), 0.);
+        assert_eq!(frames_to_secs(N_FRAMES), 30.);
+    }
+}
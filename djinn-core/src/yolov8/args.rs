@@ -1,24 +1,43 @@
+use std::path::PathBuf;
+
 use candle_core::{Module, Result, Tensor};
 use candle_nn::VarBuilder;
 use clap::{Parser, ValueEnum};
-use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 
 use super::{
+    detect_objects, detect_poses,
     model::{Multiples, YoloV8, YoloV8Pose},
-    report_detect, report_pose,
+    ClassNames, Detection, ImageScale,
 };
 
-#[derive(Clone, Copy, ValueEnum, Debug)]
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Which {
     N,
+    #[default]
     S,
     M,
     L,
     X,
 }
 
-#[derive(Clone, Copy, ValueEnum, Debug)]
+impl Which {
+    pub fn multiples(&self) -> Multiples {
+        match self {
+            Which::N => Multiples::n(),
+            Which::S => Multiples::s(),
+            Which::M => Multiples::m(),
+            Which::L => Multiples::l(),
+            Which::X => Multiples::x(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum YoloTask {
+    #[default]
     Detect,
     Pose,
 }
@@ -34,8 +53,62 @@ pub struct Args {
     #[arg(long, value_enum, default_value_t = Which::S)]
     pub which: Which,
 
+    /// The images to run the model on.
+    /// Directories are searched recursively for images,
+    /// and glob patterns like `photos/*.jpg` are expanded.
+    #[arg(required_unless_present_any = ["video", "camera"])]
     pub images: Vec<String>,
 
+    /// Run the model on each frame of a video file.
+    /// Requires `ffmpeg` and `ffprobe`.
+    #[arg(long, conflicts_with_all = ["images", "camera"])]
+    pub video: Option<PathBuf>,
+
+    /// Run the model on frames from a camera,
+    /// e.g. `/dev/video0` on Linux or `0` on macOS.
+    /// Requires `ffmpeg`.
+    #[arg(long, conflicts_with = "images")]
+    pub camera: Option<String>,
+
+    /// The frame size requested from the camera.
+    #[arg(long, default_value = "640x480")]
+    pub camera_size: FrameSize,
+
+    /// Write annotated frames from a video or camera to this video file.
+    #[arg(long)]
+    pub output_video: Option<PathBuf>,
+
+    /// Stop a video or camera after this many frames.
+    #[arg(long)]
+    pub max_frames: Option<usize>,
+
+    /// Stop a video or camera after this many seconds.
+    #[arg(long)]
+    pub duration_secs: Option<f64>,
+
+    /// Print the detections for each video frame to stdout as JSON lines.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Write annotated images to this directory
+    /// instead of next to the input images.
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Write the detections for all images to this file
+    /// as a COCO format dataset.
+    #[arg(long, conflicts_with_all = ["video", "camera"])]
+    pub coco_json: Option<PathBuf>,
+
+    /// Don't write annotated images, only the COCO results.
+    #[arg(long, requires = "coco_json")]
+    pub no_annotate: bool,
+
+    /// The number of images processed at once.
+    /// Defaults to the number of CPUs.
+    #[arg(long, short)]
+    pub jobs: Option<usize>,
+
     /// Threshold for the model confidence level.
     #[arg(long, default_value_t = 0.25)]
     pub confidence_threshold: f32,
@@ -44,6 +117,16 @@ pub struct Args {
     #[arg(long, default_value_t = 0.45)]
     pub nms_threshold: f32,
 
+    /// A file with one class name per line, in the order the model was trained on.
+    /// Defaults to the COCO classes of the pretrained models.
+    #[arg(long)]
+    pub class_names: Option<PathBuf>,
+
+    /// Only keep detections of these classes, by name or index,
+    /// e.g. `person,car`.
+    #[arg(long, value_delimiter = ',')]
+    pub classes: Vec<String>,
+
     /// The task to be run.
     #[arg(long, default_value = "detect")]
     pub task: YoloTask,
@@ -53,84 +136,126 @@ pub struct Args {
     pub legend_size: u32,
 }
 
+/// The width and height of a video frame, e.g. `640x480`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for FrameSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (width, height) = s
+            .split_once('x')
+            .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {s}"))?;
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<u32>()
+                .map_err(|error| format!("invalid frame size {s}: {error}"))
+        };
+
+        Ok(FrameSize {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+impl std::fmt::Display for FrameSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
 impl Args {
-    pub fn model(&self) -> anyhow::Result<std::path::PathBuf> {
+    pub fn model(&self) -> anyhow::Result<PathBuf> {
         let path = match &self.model {
-            Some(model) => std::path::PathBuf::from(model),
-            None => {
-                let api = hf_hub::api::sync::Api::new()?;
-                let api = api.model("lmz/candle-yolo-v8".to_string());
-                let size = match self.which {
-                    Which::N => "n",
-                    Which::S => "s",
-                    Which::M => "m",
-                    Which::L => "l",
-                    Which::X => "x",
-                };
-                let task = match self.task {
-                    YoloTask::Pose => "-pose",
-                    YoloTask::Detect => "",
-                };
-                api.get(&format!("yolov8{size}{task}.safetensors"))?
-            }
+            Some(model) => PathBuf::from(model),
+            None => hub_weights(self.which, self.task)?,
         };
         Ok(path)
     }
+
+    pub fn class_names(&self) -> anyhow::Result<ClassNames> {
+        match &self.class_names {
+            Some(path) => ClassNames::from_file(path),
+            None => Ok(ClassNames::coco()),
+        }
+    }
+}
+
+/// Download the weights for a model variant and task from the HuggingFace Hub
+pub fn hub_weights(which: Which, task: YoloTask) -> anyhow::Result<PathBuf> {
+    let api = hf_hub::api::sync::Api::new()?;
+    let api = api.model("lmz/candle-yolo-v8".to_string());
+    let size = match which {
+        Which::N => "n",
+        Which::S => "s",
+        Which::M => "m",
+        Which::L => "l",
+        Which::X => "x",
+    };
+    let task = match task {
+        YoloTask::Pose => "-pose",
+        YoloTask::Detect => "",
+    };
+    Ok(api.get(&format!("yolov8{size}{task}.safetensors"))?)
 }
 
 pub trait Task: Module + Sized {
-    fn load(vb: VarBuilder, multiples: Multiples) -> Result<Self>;
-    fn report(
+    fn load(vb: VarBuilder, multiples: Multiples, num_classes: usize) -> Result<Self>;
+    fn detections(
         pred: &Tensor,
-        img: DynamicImage,
-        w: usize,
-        h: usize,
+        scale: ImageScale,
+        class_names: &ClassNames,
         confidence_threshold: f32,
         nms_threshold: f32,
-        legend_size: u32,
-    ) -> Result<DynamicImage>;
+    ) -> Result<Vec<Detection>>;
 }
 
 impl Task for YoloV8 {
-    fn load(vb: VarBuilder, multiples: Multiples) -> Result<Self> {
-        YoloV8::load(vb, multiples, /* num_classes=*/ 80)
+    fn load(vb: VarBuilder, multiples: Multiples, num_classes: usize) -> Result<Self> {
+        YoloV8::load(vb, multiples, num_classes)
     }
 
-    fn report(
+    fn detections(
         pred: &Tensor,
-        img: DynamicImage,
-        w: usize,
-        h: usize,
+        scale: ImageScale,
+        class_names: &ClassNames,
         confidence_threshold: f32,
         nms_threshold: f32,
-        legend_size: u32,
-    ) -> Result<DynamicImage> {
-        report_detect(
+    ) -> Result<Vec<Detection>> {
+        detect_objects(
             pred,
-            img,
-            w,
-            h,
+            scale,
+            class_names,
             confidence_threshold,
             nms_threshold,
-            legend_size,
         )
     }
 }
 
 impl Task for YoloV8Pose {
-    fn load(vb: VarBuilder, multiples: Multiples) -> Result<Self> {
-        YoloV8Pose::load(vb, multiples, /* num_classes=*/ 1, (17, 3))
+    fn load(vb: VarBuilder, multiples: Multiples, num_classes: usize) -> Result<Self> {
+        YoloV8Pose::load(vb, multiples, num_classes, (17, 3))
     }
 
-    fn report(
+    fn detections(
         pred: &Tensor,
-        img: DynamicImage,
-        w: usize,
-        h: usize,
+        scale: ImageScale,
+        class_names: &ClassNames,
         confidence_threshold: f32,
         nms_threshold: f32,
-        _legend_size: u32,
-    ) -> Result<DynamicImage> {
-        report_pose(pred, img, w, h, confidence_threshold, nms_threshold)
+    ) -> Result<Vec<Detection>> {
+        detect_poses(
+            pred,
+            scale,
+            class_names,
+            confidence_threshold,
+            nms_threshold,
+        )
     }
 }
@@ -0,0 +1,192 @@
+//! Run detection on many images with a pool of worker threads
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Context as _;
+use image::GenericImageView as _;
+
+use super::{annotate, args::Args, output_path, Detection, Detector};
+
+/// The extensions of the files picked from directories and glob patterns
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "bmp", "gif", "webp", "tif", "tiff"];
+/// The suffix of annotated images, which aren't detected on again
+const ANNOTATED_SUFFIX: &str = ".pp.jpg";
+
+/// An image and what was found in it
+#[derive(Debug)]
+pub struct ImageDetections {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub detections: Vec<Detection>,
+}
+
+/// Expand directories and glob patterns into the image files they contain.
+/// Directories are searched recursively.
+/// Paths that are neither are kept as they are, whatever their extension
+pub fn expand_images(inputs: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            collect_dir(path, &mut images)?;
+        } else if is_glob(input) {
+            let mut matches = Vec::new();
+            for entry in glob::glob(input).with_context(|| format!("invalid pattern {input}"))? {
+                let entry = entry?;
+                if is_image(&entry) {
+                    matches.push(entry);
+                }
+            }
+            if matches.is_empty() {
+                tracing::warn!(pattern = input, "no images match");
+            }
+            images.extend(matches);
+        } else {
+            images.push(path.to_path_buf());
+        }
+    }
+    Ok(images)
+}
+
+fn collect_dir(dir: &Path, images: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("can't read {dir:?}"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_dir(&entry, images)?;
+        } else if is_image(&entry) {
+            images.push(entry);
+        }
+    }
+    Ok(())
+}
+
+fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+fn is_image(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    if name.ends_with(ANNOTATED_SUFFIX) {
+        return false;
+    }
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Detect objects in each image on `jobs` threads, annotating them unless `annotate` is off.
+/// Results are in the order of `images`.
+/// Images that fail are logged and returned as errors, the others are still processed
+pub fn detect_images(
+    detector: &Detector,
+    images: &[PathBuf],
+    args: &Args,
+    legend_size: u32,
+    jobs: usize,
+) -> Vec<anyhow::Result<ImageDetections>> {
+    let next = AtomicUsize::new(0);
+    let process = || {
+        let mut done = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(path) = images.get(index) else {
+                break;
+            };
+            let result = detect_image(detector, path, args, legend_size)
+                .with_context(|| format!("failed to process {path:?}"));
+            if let Err(error) = &result {
+                tracing::warn!("{error:#}");
+            }
+            done.push((index, result));
+        }
+        done
+    };
+
+    let mut results: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1)).map(|_| scope.spawn(process)).collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("detection worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn detect_image(
+    detector: &Detector,
+    path: &Path,
+    args: &Args,
+    legend_size: u32,
+) -> anyhow::Result<ImageDetections> {
+    tracing::info!("processing {path:?}");
+    let image = image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
+    let (width, height) = image.dimensions();
+    let detections = detector.detect(&image, args.confidence_threshold, args.nms_threshold)?;
+    for detection in detections.iter() {
+        tracing::info!("{}: {:?}", detection.label, detection);
+    }
+
+    if !args.no_annotate {
+        let annotated = annotate(image, &detections, legend_size);
+        let output_path = output_path(path, args.output_dir.as_deref());
+        tracing::info!("writing {output_path:?}");
+        annotated.save(output_path)?;
+    }
+
+    Ok(ImageDetections {
+        path: path.to_path_buf(),
+        width,
+        height,
+        detections,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_image_files() {
+        assert!(is_image(Path::new("photos/cat.JPG")));
+        assert!(is_image(Path::new("cat.png")));
+        assert!(!is_image(Path::new("cat.pp.jpg")));
+        assert!(!is_image(Path::new("notes.txt")));
+        assert!(!is_image(Path::new("README")));
+
+        assert!(is_glob("photos/*.jpg"));
+        assert!(!is_glob("photos/cat.jpg"));
+    }
+
+    #[test]
+    fn expands_directories() {
+        let dir = std::env::temp_dir().join(format!("djinn-yolo-batch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b.jpg", "a.png", "a.pp.jpg", "notes.txt", "nested/c.jpeg"] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+
+        let images = expand_images(&[dir.to_string_lossy().into_owned()]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = images
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                PathBuf::from("a.png"),
+                PathBuf::from("b.jpg"),
+                PathBuf::from("nested/c.jpeg")
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! The class names of a detection model,
+//! so models fine-tuned on other datasets than COCO are labeled correctly
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context as _};
+use serde::{Deserialize, Serialize};
+
+/// Class names in the order of the model's class indices
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassNames(Vec<String>);
+
+impl Default for ClassNames {
+    fn default() -> Self {
+        ClassNames::coco()
+    }
+}
+
+impl ClassNames {
+    /// The 80 COCO classes the pretrained models detect
+    pub fn coco() -> Self {
+        ClassNames(
+            crate::coco_classes::NAMES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        )
+    }
+
+    /// Read a file with one class name per line
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("unable to read {path:?}"))?;
+        text.parse()
+            .with_context(|| format!("invalid class names in {path:?}"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.0
+    }
+
+    /// The name of a class, or its index if it has none
+    pub fn label(&self, class_index: usize) -> String {
+        self.0
+            .get(class_index)
+            .cloned()
+            .unwrap_or_else(|| class_index.to_string())
+    }
+
+    /// Look up classes given by name or by index
+    pub fn resolve<S: AsRef<str>>(&self, classes: &[S]) -> anyhow::Result<Vec<usize>> {
+        classes
+            .iter()
+            .map(|class| {
+                let class = class.as_ref().trim();
+                match class.parse::<usize>() {
+                    Ok(index) if index < self.len() => Ok(index),
+                    Ok(index) => Err(anyhow!(
+                        "class index {index} is out of range, there are {} classes",
+                        self.len()
+                    )),
+                    Err(_) => self
+                        .0
+                        .iter()
+                        .position(|name| name == class)
+                        .ok_or_else(|| anyhow!("unknown class {class:?}")),
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for ClassNames {
+    type Err = anyhow::Error;
+
+    /// One name per line, blank lines are skipped
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let names: Vec<String> = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        if names.is_empty() {
+            bail!("no class names");
+        }
+        Ok(ClassNames(names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_resolves_classes() {
+        let names: ClassNames = "cat\n\n  dog \nbird\n".parse().unwrap();
+        assert_eq!(names.names(), ["cat", "dog", "bird"]);
+        assert_eq!(names.label(1), "dog");
+        assert_eq!(names.label(7), "7");
+
+        assert_eq!(names.resolve(&["bird", "0", " dog"]).unwrap(), [2, 0, 1]);
+        assert!(names.resolve(&["3"]).is_err());
+        assert!(names.resolve(&["fish"]).is_err());
+        assert!("\n \n".parse::<ClassNames>().is_err());
+
+        assert_eq!(ClassNames::coco().len(), 80);
+    }
+}
@@ -0,0 +1,157 @@
+//! Detections in the COCO dataset format,
+//! so they can be loaded by dataset and evaluation tools
+
+use serde::{Deserialize, Serialize};
+
+use super::{Detection, KEYPOINT_THRESHOLD};
+
+/// COCO keypoint visibility flags
+const KEYPOINT_NOT_LABELED: u8 = 0;
+const KEYPOINT_VISIBLE: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CocoImage {
+    pub id: usize,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CocoAnnotation {
+    pub id: usize,
+    pub image_id: usize,
+    pub category_id: usize,
+    /// `[x, y, width, height]` from the top left corner
+    pub bbox: [f32; 4],
+    pub area: f32,
+    pub score: f32,
+    pub iscrowd: u8,
+    /// `[x, y, visibility]` for each keypoint, only for pose estimation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_keypoints: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CocoCategory {
+    pub id: usize,
+    pub name: String,
+}
+
+/// Images and their detections.
+/// Category IDs are the class indices plus one, since COCO IDs start at 1
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+impl CocoDataset {
+    pub fn new<S: AsRef<str>>(class_names: &[S]) -> Self {
+        CocoDataset {
+            categories: class_names
+                .iter()
+                .enumerate()
+                .map(|(index, name)| CocoCategory {
+                    id: index + 1,
+                    name: name.as_ref().to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Add an image and its detections, returning the image's ID
+    pub fn add_image(
+        &mut self,
+        file_name: impl Into<String>,
+        width: u32,
+        height: u32,
+        detections: &[Detection],
+    ) -> usize {
+        let image_id = self.images.len() + 1;
+        self.images.push(CocoImage {
+            id: image_id,
+            file_name: file_name.into(),
+            width,
+            height,
+        });
+
+        for detection in detections {
+            let width = detection.xmax - detection.xmin;
+            let height = detection.ymax - detection.ymin;
+            let keypoints: Vec<f32> = detection
+                .keypoints
+                .iter()
+                .flat_map(|keypoint| {
+                    let visibility = if keypoint.confidence < KEYPOINT_THRESHOLD {
+                        KEYPOINT_NOT_LABELED
+                    } else {
+                        KEYPOINT_VISIBLE
+                    };
+                    [keypoint.x, keypoint.y, visibility as f32]
+                })
+                .collect();
+            let num_keypoints = (!detection.keypoints.is_empty()).then(|| {
+                detection
+                    .keypoints
+                    .iter()
+                    .filter(|keypoint| keypoint.confidence >= KEYPOINT_THRESHOLD)
+                    .count()
+            });
+
+            self.annotations.push(CocoAnnotation {
+                id: self.annotations.len() + 1,
+                image_id,
+                category_id: detection.class_index + 1,
+                bbox: [detection.xmin, detection.ymin, width, height],
+                area: width * height,
+                score: detection.confidence,
+                iscrowd: 0,
+                keypoints,
+                num_keypoints,
+            });
+        }
+        image_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_detections() {
+        let detection = Detection {
+            class_index: 1,
+            label: "bicycle".to_string(),
+            confidence: 0.9,
+            xmin: 10.,
+            ymin: 20.,
+            xmax: 40.,
+            ymax: 60.,
+            keypoints: Vec::new(),
+        };
+        let mut dataset = CocoDataset::new(&["person", "bicycle"]);
+        assert_eq!(dataset.add_image("a.jpg", 640, 480, &[]), 1);
+        assert_eq!(
+            dataset.add_image("b.jpg", 640, 480, &[detection.clone(), detection]),
+            2
+        );
+
+        assert_eq!(dataset.categories[1].id, 2);
+        assert_eq!(dataset.annotations.len(), 2);
+        let annotation = &dataset.annotations[1];
+        assert_eq!(annotation.id, 2);
+        assert_eq!(annotation.image_id, 2);
+        assert_eq!(annotation.category_id, 2);
+        assert_eq!(annotation.bbox, [10., 20., 30., 40.]);
+        assert_eq!(annotation.area, 1200.);
+
+        let json = serde_json::to_value(&dataset).unwrap();
+        assert!(json["annotations"][0].get("keypoints").is_none());
+    }
+}
@@ -0,0 +1,214 @@
+//! A loaded YOLOv8 model that can be reused across images
+
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use candle_core::{DType, Device, Module, Tensor};
+use candle_nn::VarBuilder;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    args::{hub_weights, Task, Which, YoloTask},
+    model::{YoloV8, YoloV8Pose},
+    ClassNames, Detection, ImageScale,
+};
+
+/// The length of the longest side of the image passed to the model
+const INPUT_SIZE: usize = 640;
+
+/// Configuration for loading a [`Detector`]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DetectorConfig {
+    /// Model weights in safetensors format.
+    /// The weights are downloaded from the HuggingFace Hub if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<PathBuf>,
+    #[serde(default)]
+    pub which: Which,
+    #[serde(default)]
+    pub task: YoloTask,
+    #[serde(default)]
+    pub device: crate::device::Device,
+    /// A file with one class name per line, for models trained on other classes than COCO's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_names: Option<PathBuf>,
+    /// Only keep detections of these classes, by name or index.
+    /// All classes are kept if this is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
+}
+
+#[derive(Debug)]
+enum YoloModel {
+    Detect(YoloV8),
+    Pose(YoloV8Pose),
+}
+
+#[derive(Debug)]
+pub struct Detector {
+    model: YoloModel,
+    task: YoloTask,
+    device: Device,
+    class_names: ClassNames,
+    /// The class indices to keep, or all of them if empty
+    classes: Vec<usize>,
+}
+
+impl Detector {
+    pub fn new(
+        device: Device,
+        which: Which,
+        task: YoloTask,
+        weights: PathBuf,
+        class_names: ClassNames,
+    ) -> anyhow::Result<Self> {
+        // pose models only detect people
+        let num_classes = match task {
+            YoloTask::Detect => class_names.len(),
+            YoloTask::Pose => 1,
+        };
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DType::F32, &device)? };
+        let model = match task {
+            YoloTask::Detect => {
+                <YoloV8 as Task>::load(vb, which.multiples(), num_classes).map(YoloModel::Detect)
+            }
+            YoloTask::Pose => {
+                <YoloV8Pose as Task>::load(vb, which.multiples(), num_classes).map(YoloModel::Pose)
+            }
+        }
+        .with_context(|| format!("unable to load a {task:?} model with {num_classes} classes"))?;
+        tracing::info!(?which, ?task, num_classes, "model loaded");
+
+        Ok(Detector {
+            model,
+            task,
+            device,
+            class_names,
+            classes: Vec::new(),
+        })
+    }
+
+    /// Only keep detections of these classes, given by name or index.
+    /// All classes are kept if `classes` is empty.
+    pub fn with_classes<S: AsRef<str>>(mut self, classes: &[S]) -> anyhow::Result<Self> {
+        self.classes = self.class_names.resolve(classes)?;
+        Ok(self)
+    }
+
+    pub fn from_config(config: &DetectorConfig) -> anyhow::Result<Self> {
+        let weights = match &config.model {
+            Some(path) => path.clone(),
+            None => hub_weights(config.which, config.task)?,
+        };
+
+        let class_names = match &config.class_names {
+            Some(path) => ClassNames::from_file(path)?,
+            None => ClassNames::coco(),
+        };
+
+        Detector::new(
+            config.device.try_into()?,
+            config.which,
+            config.task,
+            weights,
+            class_names,
+        )?
+        .with_classes(&config.classes)
+    }
+
+    pub fn task(&self) -> YoloTask {
+        self.task
+    }
+
+    pub fn class_names(&self) -> &ClassNames {
+        &self.class_names
+    }
+
+    /// Run the model on an image.
+    /// The coordinates of the detections are relative to the original image.
+    pub fn detect(
+        &self,
+        image: &DynamicImage,
+        confidence_threshold: f32,
+        nms_threshold: f32,
+    ) -> anyhow::Result<Vec<Detection>> {
+        let (width, height) = input_size(image);
+        let image_t = {
+            let img = image.resize_exact(
+                width as u32,
+                height as u32,
+                image::imageops::FilterType::CatmullRom,
+            );
+            let data = img.to_rgb8().into_raw();
+            Tensor::from_vec(
+                data,
+                (img.height() as usize, img.width() as usize, 3),
+                &self.device,
+            )?
+            .permute((2, 0, 1))?
+        };
+        let image_t = (image_t.unsqueeze(0)?.to_dtype(DType::F32)? * (1. / 255.))?;
+        let scale = ImageScale {
+            width: image.width() as f32 / width as f32,
+            height: image.height() as f32 / height as f32,
+        };
+
+        let mut detections = match &self.model {
+            YoloModel::Detect(model) => predict(
+                model,
+                &image_t,
+                scale,
+                &self.class_names,
+                confidence_threshold,
+                nms_threshold,
+            )?,
+            YoloModel::Pose(model) => predict(
+                model,
+                &image_t,
+                scale,
+                &self.class_names,
+                confidence_threshold,
+                nms_threshold,
+            )?,
+        };
+        if !self.classes.is_empty() {
+            detections.retain(|detection| self.classes.contains(&detection.class_index));
+        }
+
+        Ok(detections)
+    }
+}
+
+fn predict<T: Task>(
+    model: &T,
+    image: &Tensor,
+    scale: ImageScale,
+    class_names: &ClassNames,
+    confidence_threshold: f32,
+    nms_threshold: f32,
+) -> candle_core::Result<Vec<Detection>> {
+    let predictions = model.forward(image)?.squeeze(0)?;
+    tracing::debug!("generated predictions {predictions:?}");
+    T::detections(
+        &predictions,
+        scale,
+        class_names,
+        confidence_threshold,
+        nms_threshold,
+    )
+}
+
+/// Scale the image so the longest side is [`INPUT_SIZE`].
+/// Sizes have to be divisible by 32.
+fn input_size(image: &DynamicImage) -> (usize, usize) {
+    let w = image.width() as usize;
+    let h = image.height() as usize;
+    if w < h {
+        let w = w * INPUT_SIZE / h;
+        (w / 32 * 32, INPUT_SIZE)
+    } else {
+        let h = h * INPUT_SIZE / w;
+        (INPUT_SIZE, h / 32 * 32)
+    }
+}
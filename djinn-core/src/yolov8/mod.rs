@@ -1,19 +1,23 @@
 pub mod args;
+pub mod batch;
+pub mod classes;
+pub mod coco;
+pub mod detector;
 pub mod model;
+pub mod video;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 
-use candle_core::{DType, Device, Error, IndexOp, Result, Tensor};
-use candle_nn::VarBuilder;
+use candle_core::{Device, IndexOp, Result, Tensor};
 use candle_transformers::object_detection::{non_maximum_suppression, Bbox, KeyPoint};
 use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 
-use crate::yolov8::{args::Which, model::Multiples};
+use self::args::YoloTask;
 
-use self::{
-    args::{Task, YoloTask},
-    model::{YoloV8, YoloV8Pose},
-};
+pub use classes::ClassNames;
+pub use detector::{Detector, DetectorConfig};
 
 // Keypoints as reported by ChatGPT :)
 // Nose
@@ -54,89 +58,144 @@ const KP_CONNECTIONS: [(usize, usize); 16] = [
 // Model architecture from https://github.com/ultralytics/ultralytics/issues/189
 // https://github.com/tinygrad/tinygrad/blob/master/examples/yolov8.py
 
-pub fn run(device: Device, args: args::Args) -> anyhow::Result<()> {
-    match args.task {
-        YoloTask::Detect => run_task::<YoloV8>(device, args)?,
-        YoloTask::Pose => run_task::<YoloV8Pose>(device, args)?,
-    }
+/// Keypoints with a lower confidence than this are not drawn
+const KEYPOINT_THRESHOLD: f32 = 0.6;
 
-    Ok(())
+/// An object found in an image.
+/// Coordinates are in pixels of the original image.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Detection {
+    pub class_index: usize,
+    pub label: String,
+    pub confidence: f32,
+    pub xmin: f32,
+    pub ymin: f32,
+    pub xmax: f32,
+    pub ymax: f32,
+    /// Only populated for pose estimation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keypoints: Vec<Keypoint>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keypoint {
+    pub x: f32,
+    pub y: f32,
+    pub confidence: f32,
 }
 
-fn run_task<T: Task>(device: Device, args: args::Args) -> anyhow::Result<()> {
-    // Create the model and load the weights from the file.
-    let multiples = match args.which {
-        Which::N => Multiples::n(),
-        Which::S => Multiples::s(),
-        Which::M => Multiples::m(),
-        Which::L => Multiples::l(),
-        Which::X => Multiples::x(),
+/// The ratio between the original image size and the model input size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageScale {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Detection {
+    fn from_bbox(
+        class_index: usize,
+        bbox: &Bbox<Vec<KeyPoint>>,
+        scale: ImageScale,
+        class_names: &ClassNames,
+    ) -> Self {
+        Detection {
+            class_index,
+            label: class_names.label(class_index),
+            confidence: bbox.confidence,
+            xmin: bbox.xmin * scale.width,
+            ymin: bbox.ymin * scale.height,
+            xmax: bbox.xmax * scale.width,
+            ymax: bbox.ymax * scale.height,
+            keypoints: bbox
+                .data
+                .iter()
+                .map(|keypoint| Keypoint {
+                    x: keypoint.x * scale.width,
+                    y: keypoint.y * scale.height,
+                    confidence: keypoint.mask,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Run the model on the images, video, or camera in `args`.
+/// A video or camera stops early, and its output is finished, once `stop` is set,
+/// e.g. on ctrl-c.
+pub fn run(device: Device, args: args::Args, stop: &AtomicBool) -> anyhow::Result<()> {
+    let detector = Detector::new(
+        device,
+        args.which,
+        args.task,
+        args.model()?,
+        args.class_names()?,
+    )?
+    .with_classes(&args.classes)?;
+    let legend_size = match args.task {
+        YoloTask::Detect => args.legend_size,
+        YoloTask::Pose => 0,
     };
-    let model: PathBuf = args.model()?;
-    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[model], DType::F32, &device)? };
-    let model = T::load(vb, multiples)?;
-    tracing::info!("model loaded");
-    for image_name in args.images.iter() {
-        tracing::info!("processing {image_name}");
-        let mut image_name = std::path::PathBuf::from(image_name);
-        let original_image = image::io::Reader::open(&image_name)?
-            .decode()
-            .map_err(Error::wrap)?;
-        let (width, height) = {
-            let w = original_image.width() as usize;
-            let h = original_image.height() as usize;
-            if w < h {
-                let w = w * 640 / h;
-                // Sizes have to be divisible by 32.
-                (w / 32 * 32, 640)
-            } else {
-                let h = h * 640 / w;
-                (640, h / 32 * 32)
+
+    if let Some(source) = video::VideoSource::from_args(&args) {
+        return video::run(&detector, &source, &args, legend_size, stop);
+    }
+
+    if let Some(output_dir) = &args.output_dir {
+        std::fs::create_dir_all(output_dir)?;
+    }
+
+    let images = batch::expand_images(&args.images)?;
+    let jobs = args.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    });
+    tracing::info!(images = images.len(), jobs, "processing images");
+
+    let mut dataset = coco::CocoDataset::new(detector.class_names().names());
+    let mut failures = 0;
+    for result in batch::detect_images(&detector, &images, &args, legend_size, jobs) {
+        match result {
+            Ok(image) => {
+                dataset.add_image(
+                    image.path.to_string_lossy(),
+                    image.width,
+                    image.height,
+                    &image.detections,
+                );
             }
-        };
-        let image_t = {
-            let img = original_image.resize_exact(
-                width as u32,
-                height as u32,
-                image::imageops::FilterType::CatmullRom,
-            );
-            let data = img.to_rgb8().into_raw();
-            Tensor::from_vec(
-                data,
-                (img.height() as usize, img.width() as usize, 3),
-                &device,
-            )?
-            .permute((2, 0, 1))?
-        };
-        let image_t = (image_t.unsqueeze(0)?.to_dtype(DType::F32)? * (1. / 255.))?;
-        let predictions = model.forward(&image_t)?.squeeze(0)?;
-        tracing::info!("generated predictions {predictions:?}");
-        let image_t = T::report(
-            &predictions,
-            original_image,
-            width,
-            height,
-            args.confidence_threshold,
-            args.nms_threshold,
-            args.legend_size,
-        )?;
-        image_name.set_extension("pp.jpg");
-        tracing::info!("writing {image_name:?}");
-        image_t.save(image_name)?
+            Err(_) => failures += 1,
+        }
+    }
+
+    if let Some(coco_json) = &args.coco_json {
+        tracing::info!("writing {coco_json:?}");
+        let file = std::io::BufWriter::new(std::fs::File::create(coco_json)?);
+        serde_json::to_writer(file, &dataset)?;
     }
 
+    if failures > 0 {
+        anyhow::bail!("{failures} of {} images failed", images.len());
+    }
     Ok(())
 }
 
-pub fn report_detect(
+/// Annotated images are saved as `<name>.pp.jpg`
+/// either next to the input image or in `output_dir`
+fn output_path(image: &Path, output_dir: Option<&Path>) -> PathBuf {
+    let mut path = match (output_dir, image.file_name()) {
+        (Some(dir), Some(file_name)) => dir.join(file_name),
+        _ => image.to_path_buf(),
+    };
+    path.set_extension("pp.jpg");
+    path
+}
+
+pub fn detect_objects(
     pred: &Tensor,
-    img: DynamicImage,
-    w: usize,
-    h: usize,
+    scale: ImageScale,
+    class_names: &ClassNames,
     confidence_threshold: f32,
     nms_threshold: f32,
-    legend_size: u32,
-) -> Result<DynamicImage> {
+) -> Result<Vec<Detection>> {
     let (pred_size, npreds) = pred.dims2()?;
     let nclasses = pred_size - 4;
     // The bounding boxes grouped by (maximum) class index.
@@ -168,62 +227,24 @@ pub fn report_detect(
 
     non_maximum_suppression(&mut bboxes, nms_threshold);
 
-    // Annotate the original image and print boxes information.
-    let (initial_h, initial_w) = (img.height(), img.width());
-    let w_ratio = initial_w as f32 / w as f32;
-    let h_ratio = initial_h as f32 / h as f32;
-    let mut img = img.to_rgb8();
-    let font = crate::font::get_default_font();
-    for (class_index, bboxes_for_class) in bboxes.iter().enumerate() {
-        for b in bboxes_for_class.iter() {
-            tracing::info!("{}: {:?}", crate::coco_classes::NAMES[class_index], b);
-            let xmin = (b.xmin * w_ratio) as i32;
-            let ymin = (b.ymin * h_ratio) as i32;
-            let dx = (b.xmax - b.xmin) * w_ratio;
-            let dy = (b.ymax - b.ymin) * h_ratio;
-            if dx >= 0. && dy >= 0. {
-                imageproc::drawing::draw_hollow_rect_mut(
-                    &mut img,
-                    imageproc::rect::Rect::at(xmin, ymin).of_size(dx as u32, dy as u32),
-                    image::Rgb([255, 0, 0]),
-                );
-            }
-            if legend_size > 0 {
-                if let Some(font) = font.as_ref() {
-                    imageproc::drawing::draw_filled_rect_mut(
-                        &mut img,
-                        imageproc::rect::Rect::at(xmin, ymin).of_size(dx as u32, legend_size),
-                        image::Rgb([170, 0, 0]),
-                    );
-                    let legend = format!(
-                        "{}   {:.0}%",
-                        crate::coco_classes::NAMES[class_index],
-                        100. * b.confidence
-                    );
-                    imageproc::drawing::draw_text_mut(
-                        &mut img,
-                        image::Rgb([255, 255, 255]),
-                        xmin,
-                        ymin,
-                        rusttype::Scale::uniform(legend_size as f32 - 1.),
-                        font,
-                        &legend,
-                    )
-                }
-            }
-        }
-    }
-    Ok(DynamicImage::ImageRgb8(img))
+    Ok(bboxes
+        .iter()
+        .enumerate()
+        .flat_map(|(class_index, bboxes_for_class)| {
+            bboxes_for_class
+                .iter()
+                .map(move |bbox| Detection::from_bbox(class_index, bbox, scale, class_names))
+        })
+        .collect())
 }
 
-pub fn report_pose(
+pub fn detect_poses(
     pred: &Tensor,
-    img: DynamicImage,
-    w: usize,
-    h: usize,
+    scale: ImageScale,
+    class_names: &ClassNames,
     confidence_threshold: f32,
     nms_threshold: f32,
-) -> Result<DynamicImage> {
+) -> Result<Vec<Detection>> {
     let (pred_size, npreds) = pred.dims2()?;
     if pred_size != 17 * 3 + 4 + 1 {
         candle_core::bail!("unexpected pred-size {pred_size}");
@@ -255,19 +276,24 @@ pub fn report_pose(
 
     let mut bboxes = vec![bboxes];
     non_maximum_suppression(&mut bboxes, nms_threshold);
-    let bboxes = &bboxes[0];
 
-    // Annotate the original image and print boxes information.
-    let (initial_h, initial_w) = (img.height(), img.width());
-    let w_ratio = initial_w as f32 / w as f32;
-    let h_ratio = initial_h as f32 / h as f32;
+    // pose estimation only detects people, which is the first COCO class
+    Ok(bboxes[0]
+        .iter()
+        .map(|bbox| Detection::from_bbox(0, bbox, scale, class_names))
+        .collect())
+}
+
+/// Draw the bounding boxes and keypoints of `detections` on the image.
+/// A legend with the label and confidence is drawn if `legend_size` is not 0.
+pub fn annotate(img: DynamicImage, detections: &[Detection], legend_size: u32) -> DynamicImage {
     let mut img = img.to_rgb8();
-    for b in bboxes.iter() {
-        tracing::info!("{b:?}");
-        let xmin = (b.xmin * w_ratio) as i32;
-        let ymin = (b.ymin * h_ratio) as i32;
-        let dx = (b.xmax - b.xmin) * w_ratio;
-        let dy = (b.ymax - b.ymin) * h_ratio;
+    let font = crate::font::get_default_font();
+    for b in detections.iter() {
+        let xmin = b.xmin as i32;
+        let ymin = b.ymin as i32;
+        let dx = b.xmax - b.xmin;
+        let dy = b.ymax - b.ymin;
         if dx >= 0. && dy >= 0. {
             imageproc::drawing::draw_hollow_rect_mut(
                 &mut img,
@@ -275,33 +301,53 @@ pub fn report_pose(
                 image::Rgb([255, 0, 0]),
             );
         }
-        for kp in b.data.iter() {
-            if kp.mask < 0.6 {
+        if legend_size > 0 {
+            if let Some(font) = font.as_ref() {
+                imageproc::drawing::draw_filled_rect_mut(
+                    &mut img,
+                    imageproc::rect::Rect::at(xmin, ymin).of_size(dx as u32, legend_size),
+                    image::Rgb([170, 0, 0]),
+                );
+                let legend = format!("{}   {:.0}%", b.label, 100. * b.confidence);
+                imageproc::drawing::draw_text_mut(
+                    &mut img,
+                    image::Rgb([255, 255, 255]),
+                    xmin,
+                    ymin,
+                    rusttype::Scale::uniform(legend_size as f32 - 1.),
+                    font,
+                    &legend,
+                )
+            }
+        }
+
+        for kp in b.keypoints.iter() {
+            if kp.confidence < KEYPOINT_THRESHOLD {
                 continue;
             }
-            let x = (kp.x * w_ratio) as i32;
-            let y = (kp.y * h_ratio) as i32;
             imageproc::drawing::draw_filled_circle_mut(
                 &mut img,
-                (x, y),
+                (kp.x as i32, kp.y as i32),
                 2,
                 image::Rgb([0, 255, 0]),
             );
         }
 
-        for &(idx1, idx2) in KP_CONNECTIONS.iter() {
-            let kp1 = &b.data[idx1];
-            let kp2 = &b.data[idx2];
-            if kp1.mask < 0.6 || kp2.mask < 0.6 {
-                continue;
+        if b.keypoints.len() == 17 {
+            for &(idx1, idx2) in KP_CONNECTIONS.iter() {
+                let kp1 = &b.keypoints[idx1];
+                let kp2 = &b.keypoints[idx2];
+                if kp1.confidence < KEYPOINT_THRESHOLD || kp2.confidence < KEYPOINT_THRESHOLD {
+                    continue;
+                }
+                imageproc::drawing::draw_line_segment_mut(
+                    &mut img,
+                    (kp1.x, kp1.y),
+                    (kp2.x, kp2.y),
+                    image::Rgb([255, 255, 0]),
+                );
             }
-            imageproc::drawing::draw_line_segment_mut(
-                &mut img,
-                (kp1.x * w_ratio, kp1.y * h_ratio),
-                (kp2.x * w_ratio, kp2.y * h_ratio),
-                image::Rgb([255, 255, 0]),
-            );
         }
     }
-    Ok(DynamicImage::ImageRgb8(img))
+    DynamicImage::ImageRgb8(img)
 }
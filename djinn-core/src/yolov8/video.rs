@@ -0,0 +1,290 @@
+//! Run detection on video files and cameras.
+//!
+//! Frames are decoded and encoded by `ffmpeg` subprocesses
+//! as raw RGB pixels over pipes.
+
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use image::{DynamicImage, RgbImage};
+use serde::Serialize;
+
+use super::{annotate, args::Args, args::FrameSize, Detection, Detector};
+
+/// Used when the frame rate of the source is unknown
+const DEFAULT_FRAME_RATE: &str = "30";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VideoSource {
+    File(PathBuf),
+    Camera { device: String, size: FrameSize },
+}
+
+impl VideoSource {
+    pub fn from_args(args: &Args) -> Option<Self> {
+        if let Some(path) = &args.video {
+            Some(VideoSource::File(path.clone()))
+        } else {
+            args.camera.as_ref().map(|device| VideoSource::Camera {
+                device: device.clone(),
+                size: args.camera_size,
+            })
+        }
+    }
+
+    /// The `ffmpeg` arguments that select this source as the input
+    fn input_args(&self) -> Vec<String> {
+        match self {
+            VideoSource::File(path) => vec!["-i".into(), path.to_string_lossy().into_owned()],
+            VideoSource::Camera { device, size } => {
+                let format = if cfg!(target_os = "macos") {
+                    "avfoundation"
+                } else {
+                    "v4l2"
+                };
+                vec![
+                    "-f".into(),
+                    format.into(),
+                    "-framerate".into(),
+                    DEFAULT_FRAME_RATE.into(),
+                    "-video_size".into(),
+                    size.to_string(),
+                    "-i".into(),
+                    device.clone(),
+                ]
+            }
+        }
+    }
+
+    /// The frame size and frame rate of the source
+    fn probe(&self) -> anyhow::Result<(FrameSize, String)> {
+        match self {
+            VideoSource::Camera { size, .. } => Ok((*size, DEFAULT_FRAME_RATE.to_string())),
+            VideoSource::File(path) => probe_file(path),
+        }
+    }
+}
+
+/// The detections for a single frame, printed as a JSON line
+#[derive(Debug, Serialize)]
+struct FrameDetections<'a> {
+    frame: usize,
+    detections: &'a [Detection],
+}
+
+pub fn run(
+    detector: &Detector,
+    source: &VideoSource,
+    args: &Args,
+    legend_size: u32,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let duration = args
+        .duration_secs
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .context("invalid duration")?;
+    let (size, frame_rate) = source.probe()?;
+    tracing::info!(?source, %size, frame_rate, "reading video");
+
+    let mut reader = FrameReader::open(source, size)?;
+    let mut writer = args
+        .output_video
+        .as_deref()
+        .map(|path| FrameWriter::open(path, size, &frame_rate))
+        .transpose()?;
+
+    let mut stdout = std::io::stdout().lock();
+    let mut frame = 0;
+    let started = Instant::now();
+    while let Some(image) = reader.next_frame()? {
+        let image = DynamicImage::ImageRgb8(image);
+        let detections = detector.detect(&image, args.confidence_threshold, args.nms_threshold)?;
+        tracing::debug!(frame, count = detections.len(), "processed frame");
+
+        if args.json {
+            serde_json::to_writer(
+                &mut stdout,
+                &FrameDetections {
+                    frame,
+                    detections: &detections,
+                },
+            )?;
+            writeln!(stdout)?;
+            stdout.flush()?;
+        }
+
+        if let Some(writer) = writer.as_mut() {
+            let annotated = annotate(image, &detections, legend_size);
+            writer.write_frame(&annotated.to_rgb8())?;
+        }
+
+        frame += 1;
+        if stop.load(Ordering::Relaxed) {
+            tracing::info!(frame, "stopping the video");
+            break;
+        }
+        if args
+            .max_frames
+            .is_some_and(|max_frames| frame >= max_frames)
+            || duration.is_some_and(|duration| started.elapsed() >= duration)
+        {
+            tracing::info!(frame, "reached the frame or duration limit");
+            break;
+        }
+    }
+
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+    reader.finish()?;
+    tracing::info!(frames = frame, "finished video");
+
+    Ok(())
+}
+
+fn probe_file(path: &Path) -> anyhow::Result<(FrameSize, String)> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=width,height,r_frame_rate",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(path)
+        .output()
+        .context("unable to run ffprobe")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.trim().split(',');
+    let (Some(width), Some(height), frame_rate) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(anyhow!("unexpected ffprobe output: {stdout}"));
+    };
+
+    let size = FrameSize {
+        width: width.parse()?,
+        height: height.parse()?,
+    };
+    let frame_rate = frame_rate.unwrap_or(DEFAULT_FRAME_RATE).to_string();
+
+    Ok((size, frame_rate))
+}
+
+/// An `ffmpeg` command that doesn't get the terminal's ctrl-c,
+/// so the output file is only finished once the last frame has been written
+fn ffmpeg() -> Command {
+    let mut command = Command::new("ffmpeg");
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command
+}
+
+/// Decodes frames from an `ffmpeg` process
+struct FrameReader {
+    child: Child,
+    stdout: ChildStdout,
+    size: FrameSize,
+}
+
+impl FrameReader {
+    fn open(source: &VideoSource, size: FrameSize) -> anyhow::Result<Self> {
+        let mut child = ffmpeg()
+            .args(["-loglevel", "error"])
+            .args(source.input_args())
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("unable to run ffmpeg")?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("ffmpeg stdout is not available"))?;
+
+        Ok(FrameReader {
+            child,
+            stdout,
+            size,
+        })
+    }
+
+    /// Returns `None` at the end of the video
+    fn next_frame(&mut self) -> anyhow::Result<Option<RgbImage>> {
+        let FrameSize { width, height } = self.size;
+        let mut buffer = vec![0u8; width as usize * height as usize * 3];
+        match self.stdout.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+
+        RgbImage::from_raw(width, height, buffer)
+            .map(Some)
+            .ok_or_else(|| anyhow!("frame does not match size {}", self.size))
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        // the camera never ends on its own
+        let _ = self.child.kill();
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
+/// Encodes frames with an `ffmpeg` process
+struct FrameWriter {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl FrameWriter {
+    fn open(path: &Path, size: FrameSize, frame_rate: &str) -> anyhow::Result<Self> {
+        let mut child = ffmpeg()
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-video_size", &size.to_string()])
+            .args(["-framerate", frame_rate])
+            .args(["-i", "-", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("unable to run ffmpeg")?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("ffmpeg stdin is not available"))?;
+
+        Ok(FrameWriter { child, stdin })
+    }
+
+    fn write_frame(&mut self, frame: &RgbImage) -> anyhow::Result<()> {
+        self.stdin.write_all(frame.as_raw())?;
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        let FrameWriter { mut child, stdin } = self;
+        // closing stdin lets ffmpeg finish writing the file
+        drop(stdin);
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("ffmpeg exited with {status}"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! Where djinn keeps its files
+//!
+//! Config, data, cache, and state directories follow the XDG base directory spec
+//! on Unix, macOS included, and use the roaming and local app data folders on Windows.
+//! Each app has its own directory in each of them, e.g. `~/.config/djinn`.
+
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The directory name shared by djinn-cli and djinn-server
+pub const APP_NAME: &str = "djinn";
+
+/// The directories of one app
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dirs {
+    config: PathBuf,
+    data: PathBuf,
+    cache: PathBuf,
+    state: PathBuf,
+}
+
+impl Dirs {
+    /// The directories of djinn-cli and djinn-server
+    pub fn djinn() -> io::Result<Self> {
+        Dirs::new(APP_NAME)
+    }
+
+    /// The directories of `app`, from the environment
+    pub fn new(app: &str) -> io::Result<Self> {
+        Dirs::from_env(app, |name| std::env::var_os(name))
+    }
+
+    #[cfg(not(windows))]
+    fn from_env(app: &str, var: impl Fn(&str) -> Option<OsString>) -> io::Result<Self> {
+        let home = var("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+        // relative paths in the XDG variables are ignored, as the spec says
+        let base = |name: &str, default: &str| match var(name).map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => Ok(dir.join(app)),
+            _ => home
+                .as_ref()
+                .map(|home| home.join(default).join(app))
+                .ok_or_else(|| not_found(&format!("${name} and $HOME aren't set"))),
+        };
+        Ok(Dirs {
+            config: base("XDG_CONFIG_HOME", ".config")?,
+            data: base("XDG_DATA_HOME", ".local/share")?,
+            cache: base("XDG_CACHE_HOME", ".cache")?,
+            state: base("XDG_STATE_HOME", ".local/state")?,
+        })
+    }
+
+    #[cfg(windows)]
+    fn from_env(app: &str, var: impl Fn(&str) -> Option<OsString>) -> io::Result<Self> {
+        let folder = |name: &str| {
+            var(name)
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| PathBuf::from(dir).join(app))
+                .ok_or_else(|| not_found(&format!("%{name}% isn't set")))
+        };
+        let roaming = folder("APPDATA")?;
+        let local = folder("LOCALAPPDATA")?;
+        Ok(Dirs {
+            config: roaming.join("config"),
+            data: roaming.join("data"),
+            cache: local.join("cache"),
+            state: local.join("state"),
+        })
+    }
+
+    /// Settings the user edits
+    pub fn config(&self) -> &Path {
+        &self.config
+    }
+
+    /// Files the app keeps, like saved sessions
+    pub fn data(&self) -> &Path {
+        &self.data
+    }
+
+    /// Files that can be downloaded or built again
+    pub fn cache(&self) -> &Path {
+        &self.cache
+    }
+
+    /// History and logs that should outlive a restart but aren't worth backing up
+    pub fn state(&self) -> &Path {
+        &self.state
+    }
+
+    /// The path of `name` in the config directory, creating the directories it's in
+    pub fn place_config_file(&self, name: impl AsRef<Path>) -> io::Result<PathBuf> {
+        place(&self.config, name.as_ref())
+    }
+
+    /// The path of `name` in the state directory, creating the directories it's in
+    pub fn place_state_file(&self, name: impl AsRef<Path>) -> io::Result<PathBuf> {
+        place(&self.state, name.as_ref())
+    }
+
+    /// The directory `name` in the data directory, created if it doesn't exist
+    pub fn create_data_dir(&self, name: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let dir = self.data.join(name);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+}
+
+fn not_found(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
+
+fn place(dir: &Path, name: &Path) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+/// Copy files from an old location to their new one if it doesn't exist yet.
+/// Returns whether anything was copied.
+///
+/// The old files are left where they are,
+/// e.g. `./configs` in a checkout of the repo.
+pub fn migrate(from: &Path, to: &Path) -> io::Result<bool> {
+    if !from.exists() || to.exists() {
+        return Ok(false);
+    }
+    copy_all(from, to)?;
+    Ok(true)
+}
+
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn xdg_variables_override_home() {
+        let dirs = Dirs::from_env("djinn", |name| match name {
+            "HOME" => Some("/home/me".into()),
+            "XDG_CONFIG_HOME" => Some("/etc/me".into()),
+            // relative, so it's ignored
+            "XDG_STATE_HOME" => Some("state".into()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(dirs.config(), Path::new("/etc/me/djinn"));
+        assert_eq!(dirs.data(), Path::new("/home/me/.local/share/djinn"));
+        assert_eq!(dirs.cache(), Path::new("/home/me/.cache/djinn"));
+        assert_eq!(dirs.state(), Path::new("/home/me/.local/state/djinn"));
+    }
+
+    #[test]
+    fn migration_copies_once() {
+        let dir = std::env::temp_dir().join(format!("djinn-dirs-test-{}", std::process::id()));
+        let from = dir.join("configs");
+        let to = dir.join("config/djinn");
+        std::fs::create_dir_all(from.join("model")).unwrap();
+        std::fs::write(from.join("model/phi3.toml"), "old").unwrap();
+
+        let copied = migrate(&from, &to).unwrap();
+        std::fs::write(from.join("model/phi3.toml"), "changed").unwrap();
+        let copied_again = migrate(&from, &to).unwrap();
+        let contents = std::fs::read_to_string(to.join("model/phi3.toml")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(copied);
+        assert!(!copied_again);
+        assert_eq!(contents, "old");
+    }
+}
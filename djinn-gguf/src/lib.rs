@@ -0,0 +1,557 @@
+//! Read the header of a GGUF file without loading its weights
+//!
+//! The header holds the metadata key-values and the tensor infos.
+//! Array values are summarized by their item type and length
+//! since the tokenizer vocabulary alone can have hundreds of thousands of entries.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+/// Version 1 used 32 bit lengths and is no longer written by llama.cpp
+const SUPPORTED_VERSIONS: [u32; 2] = [2, 3];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NotGguf,
+    UnsupportedVersion(u32),
+    UnknownValueType(u32),
+    InvalidString,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                write!(f, "the GGUF header is truncated")
+            }
+            Error::Io(error) => error.fmt(f),
+            Error::NotGguf => write!(f, "not a GGUF file"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported GGUF version {version}")
+            }
+            Error::UnknownValueType(value_type) => {
+                write!(f, "unknown GGUF value type {value_type}")
+            }
+            Error::InvalidString => write!(f, "a GGUF string isn't UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// A metadata value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    /// The items are skipped
+    Array {
+        item_type: ValueType,
+        len: u64,
+    },
+}
+
+impl Value {
+    /// The value of any integer type that fits in a `u64`
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::U8(value) => Some(value.into()),
+            Value::U16(value) => Some(value.into()),
+            Value::U32(value) => Some(value.into()),
+            Value::U64(value) => Some(value),
+            Value::I8(value) => value.try_into().ok(),
+            Value::I16(value) => value.try_into().ok(),
+            Value::I32(value) => value.try_into().ok(),
+            Value::I64(value) => value.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::U8(value) => value.fmt(f),
+            Value::I8(value) => value.fmt(f),
+            Value::U16(value) => value.fmt(f),
+            Value::I16(value) => value.fmt(f),
+            Value::U32(value) => value.fmt(f),
+            Value::I32(value) => value.fmt(f),
+            Value::U64(value) => value.fmt(f),
+            Value::I64(value) => value.fmt(f),
+            Value::F32(value) => value.fmt(f),
+            Value::F64(value) => value.fmt(f),
+            Value::Bool(value) => value.fmt(f),
+            Value::String(value) => write!(f, "{value:?}"),
+            Value::Array { item_type, len } => write!(f, "[{item_type:?}; {len}]"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    Bool,
+    String,
+    Array,
+    U64,
+    I64,
+    F64,
+}
+
+impl TryFrom<u32> for ValueType {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self> {
+        Ok(match value {
+            0 => ValueType::U8,
+            1 => ValueType::I8,
+            2 => ValueType::U16,
+            3 => ValueType::I16,
+            4 => ValueType::U32,
+            5 => ValueType::I32,
+            6 => ValueType::F32,
+            7 => ValueType::Bool,
+            8 => ValueType::String,
+            9 => ValueType::Array,
+            10 => ValueType::U64,
+            11 => ValueType::I64,
+            12 => ValueType::F64,
+            _ => return Err(Error::UnknownValueType(value)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dims: Vec<u64>,
+    /// The ggml type of the weights, see [`ggml_type_name`]
+    pub ggml_type: u32,
+    /// Where the weights start, relative to the tensor data
+    pub offset: u64,
+}
+
+impl TensorInfo {
+    pub fn elements(&self) -> u64 {
+        self.dims.iter().product()
+    }
+}
+
+/// The tokenizer the file embeds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tokenizer {
+    /// e.g. `llama` for SentencePiece or `gpt2` for BPE
+    pub model: Option<String>,
+    pub tokens: Option<u64>,
+    pub bos_token_id: Option<u64>,
+    pub eos_token_id: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub metadata: BTreeMap<String, Value>,
+    pub tensors: Vec<TensorInfo>,
+}
+
+impl Header {
+    /// Read the header of the GGUF file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Header::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a header from the start of a GGUF file
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => Error::NotGguf,
+                _ => Error::Io(error),
+            })?;
+        if &magic != MAGIC {
+            return Err(Error::NotGguf);
+        }
+        let version = read_u32(&mut reader)?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let tensor_count = read_u64(&mut reader)?;
+        let kv_count = read_u64(&mut reader)?;
+
+        let mut metadata = BTreeMap::new();
+        for _ in 0..kv_count {
+            let key = read_string(&mut reader)?;
+            let value_type = ValueType::try_from(read_u32(&mut reader)?)?;
+            let value = read_value(&mut reader, value_type)?;
+            metadata.insert(key, value);
+        }
+
+        let mut tensors = Vec::new();
+        for _ in 0..tensor_count {
+            let name = read_string(&mut reader)?;
+            let n_dims = read_u32(&mut reader)?;
+            let dims = (0..n_dims)
+                .map(|_| read_u64(&mut reader))
+                .collect::<Result<_>>()?;
+            tensors.push(TensorInfo {
+                name,
+                dims,
+                ggml_type: read_u32(&mut reader)?,
+                offset: read_u64(&mut reader)?,
+            });
+        }
+
+        Ok(Header {
+            version,
+            metadata,
+            tensors,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.metadata.get(key)
+    }
+
+    /// `general.architecture`, e.g. `llama`
+    pub fn architecture(&self) -> Option<&str> {
+        self.get("general.architecture")?.as_str()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.get("general.name")?.as_str()
+    }
+
+    /// `general.type`, `model` or `adapter`.
+    /// Older files don't have it.
+    pub fn file_type(&self) -> Option<&str> {
+        self.get("general.type")?.as_str()
+    }
+
+    pub fn is_adapter(&self) -> bool {
+        self.file_type() == Some("adapter")
+    }
+
+    /// The context length the model was trained with
+    pub fn context_length(&self) -> Option<u64> {
+        self.arch_value("context_length")
+    }
+
+    pub fn embedding_length(&self) -> Option<u64> {
+        self.arch_value("embedding_length")
+    }
+
+    pub fn block_count(&self) -> Option<u64> {
+        self.arch_value("block_count")
+    }
+
+    /// The quantization named by `general.file_type`,
+    /// or the most common tensor type if the file doesn't say
+    pub fn quantization(&self) -> Option<String> {
+        let file_type = self
+            .get("general.file_type")
+            .and_then(Value::as_u64)
+            .and_then(file_type_name);
+        if let Some(name) = file_type {
+            return Some(name.to_string());
+        }
+
+        let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+        for tensor in &self.tensors {
+            *counts.entry(tensor.ggml_type).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(_ggml_type, count)| *count)
+            .map(|(ggml_type, _count)| ggml_type_name(ggml_type))
+    }
+
+    /// The number of weights in all tensors
+    pub fn parameters(&self) -> u64 {
+        self.tensors.iter().map(TensorInfo::elements).sum()
+    }
+
+    /// The embedded tokenizer, if the file has one
+    pub fn tokenizer(&self) -> Option<Tokenizer> {
+        let model = self
+            .get("tokenizer.ggml.model")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let tokens = match self.get("tokenizer.ggml.tokens") {
+            Some(Value::Array { len, .. }) => Some(*len),
+            _ => None,
+        };
+        if model.is_none() && tokens.is_none() {
+            return None;
+        }
+        Some(Tokenizer {
+            model,
+            tokens,
+            bos_token_id: self
+                .get("tokenizer.ggml.bos_token_id")
+                .and_then(Value::as_u64),
+            eos_token_id: self
+                .get("tokenizer.ggml.eos_token_id")
+                .and_then(Value::as_u64),
+        })
+    }
+
+    /// An integer value of the architecture, e.g. `llama.context_length`
+    fn arch_value(&self, name: &str) -> Option<u64> {
+        let architecture = self.architecture()?;
+        self.get(&format!("{architecture}.{name}"))?.as_u64()
+    }
+}
+
+/// The name of a `general.file_type`, as llama.cpp numbers them
+pub fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+/// The name of a tensor's ggml type
+pub fn ggml_type_name(ggml_type: u32) -> String {
+    let name = match ggml_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        6 => "Q5_0",
+        7 => "Q5_1",
+        8 => "Q8_0",
+        9 => "Q8_1",
+        10 => "Q2_K",
+        11 => "Q3_K",
+        12 => "Q4_K",
+        13 => "Q5_K",
+        14 => "Q6_K",
+        15 => "Q8_K",
+        30 => "BF16",
+        _ => return format!("type {ggml_type}"),
+    };
+    name.to_string()
+}
+
+fn read_value(reader: &mut impl Read, value_type: ValueType) -> Result<Value> {
+    Ok(match value_type {
+        ValueType::U8 => Value::U8(u8::from_le_bytes(read_bytes(reader)?)),
+        ValueType::I8 => Value::I8(i8::from_le_bytes(read_bytes(reader)?)),
+        ValueType::U16 => Value::U16(u16::from_le_bytes(read_bytes(reader)?)),
+        ValueType::I16 => Value::I16(i16::from_le_bytes(read_bytes(reader)?)),
+        ValueType::U32 => Value::U32(read_u32(reader)?),
+        ValueType::I32 => Value::I32(i32::from_le_bytes(read_bytes(reader)?)),
+        ValueType::U64 => Value::U64(read_u64(reader)?),
+        ValueType::I64 => Value::I64(i64::from_le_bytes(read_bytes(reader)?)),
+        ValueType::F32 => Value::F32(f32::from_le_bytes(read_bytes(reader)?)),
+        ValueType::F64 => Value::F64(f64::from_le_bytes(read_bytes(reader)?)),
+        ValueType::Bool => Value::Bool(u8::from_le_bytes(read_bytes(reader)?) != 0),
+        ValueType::String => Value::String(read_string(reader)?),
+        ValueType::Array => {
+            let item_type = ValueType::try_from(read_u32(reader)?)?;
+            let len = read_u64(reader)?;
+            for _ in 0..len {
+                skip_value(reader, item_type)?;
+            }
+            Value::Array { item_type, len }
+        }
+    })
+}
+
+fn skip_value(reader: &mut impl Read, value_type: ValueType) -> Result<()> {
+    let size = match value_type {
+        ValueType::U8 | ValueType::I8 | ValueType::Bool => 1,
+        ValueType::U16 | ValueType::I16 => 2,
+        ValueType::U32 | ValueType::I32 | ValueType::F32 => 4,
+        ValueType::U64 | ValueType::I64 | ValueType::F64 => 8,
+        ValueType::String => read_u64(reader)?,
+        ValueType::Array => {
+            read_value(reader, value_type)?;
+            return Ok(());
+        }
+    };
+    let skipped = io::copy(&mut reader.take(size), &mut io::sink())?;
+    if skipped < size {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidString)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(bytes: &mut Vec<u8>, value: &str) {
+        bytes.extend((value.len() as u64).to_le_bytes());
+        bytes.extend(value.as_bytes());
+    }
+
+    fn key(bytes: &mut Vec<u8>, name: &str, value_type: u32) {
+        string(bytes, name);
+        bytes.extend(value_type.to_le_bytes());
+    }
+
+    /// A tiny llama model with a three token vocabulary
+    fn header() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(5u64.to_le_bytes());
+
+        key(&mut bytes, "general.architecture", 8);
+        string(&mut bytes, "llama");
+        key(&mut bytes, "general.file_type", 4);
+        bytes.extend(15u32.to_le_bytes());
+        key(&mut bytes, "llama.context_length", 4);
+        bytes.extend(4096u32.to_le_bytes());
+        key(&mut bytes, "tokenizer.ggml.model", 8);
+        string(&mut bytes, "llama");
+        key(&mut bytes, "tokenizer.ggml.tokens", 9);
+        bytes.extend(8u32.to_le_bytes());
+        bytes.extend(3u64.to_le_bytes());
+        for token in ["<s>", "</s>", "hi"] {
+            string(&mut bytes, token);
+        }
+
+        for (name, dims, ggml_type) in [
+            ("token_embd.weight", [8u64, 3], 12u32),
+            ("output_norm.weight", [8, 1], 0),
+        ] {
+            string(&mut bytes, name);
+            bytes.extend(2u32.to_le_bytes());
+            for dim in dims {
+                bytes.extend(dim.to_le_bytes());
+            }
+            bytes.extend(ggml_type.to_le_bytes());
+            bytes.extend(0u64.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_header() {
+        let header = Header::read(header().as_slice()).unwrap();
+
+        assert_eq!(header.version, 3);
+        assert_eq!(header.architecture(), Some("llama"));
+        assert_eq!(header.context_length(), Some(4096));
+        assert_eq!(header.quantization().as_deref(), Some("Q4_K_M"));
+        assert_eq!(header.parameters(), 32);
+        assert_eq!(
+            header.tokenizer(),
+            Some(Tokenizer {
+                model: Some("llama".to_string()),
+                tokens: Some(3),
+                bos_token_id: None,
+                eos_token_id: None,
+            })
+        );
+        assert_eq!(header.tensors[0].name, "token_embd.weight");
+        assert!(!header.is_adapter());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Header::read(&b"PK\x03\x04"[..]),
+            Err(Error::NotGguf)
+        ));
+        assert!(matches!(Header::read(&b""[..]), Err(Error::NotGguf)));
+
+        let mut truncated = header();
+        truncated.truncate(40);
+        assert!(matches!(
+            Header::read(truncated.as_slice()),
+            Err(Error::Io(_))
+        ));
+    }
+}
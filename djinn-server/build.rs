@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // generating the gRPC code needs `protoc`,
+    // so it is only done when the service is enabled
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/djinn.proto")?;
+    Ok(())
+}
@@ -0,0 +1,608 @@
+//! An append only log of the requests made to the server.
+//!
+//! Each request is written as one line of JSON to [`AuditConfig::path`].
+//! When the file reaches [`AuditConfig::max_file_bytes`] it is rotated
+//! to `<path>.1`, `<path>.1` to `<path>.2`, and so on,
+//! keeping at most [`AuditConfig::max_files`] old files.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write as _},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header, request::Parts, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use djinn_dirs::Dirs;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::server::body::{track, Track};
+
+/// The largest request body that is read for the audit log,
+/// the same as axum's default body limit
+const MAX_REQUEST_BYTES: usize = 2 * 1024 * 1024;
+const DEFAULT_MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+const REDACTED: &str = "[redacted]";
+
+/// Request fields that hold the text sent to a model
+const PROMPT_FIELDS: &[&str] = &[
+    "prompt",
+    "prompts",
+    "negative_prompt",
+    "message",
+    "messages",
+    "system",
+    "input",
+    "prefix",
+    "suffix",
+    "text",
+];
+
+const fn default_max_file_bytes() -> u64 {
+    DEFAULT_MAX_FILE_BYTES
+}
+
+const fn default_max_files() -> usize {
+    DEFAULT_MAX_FILES
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// The JSONL file records are appended to.
+    /// Relative paths are in the state directory, e.g. `~/.local/state/djinn`
+    pub path: PathBuf,
+    /// The size at which the file is rotated
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// The number of rotated files to keep
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// A request header that identifies the client, e.g. `x-user`.
+    /// The client's IP address is always recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_header: Option<String>,
+    /// Replace prompt text with its length
+    #[serde(default)]
+    pub redact_prompts: bool,
+    /// Request fields to replace with `[redacted]`, e.g. `stop`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_fields: Vec<String>,
+}
+
+impl AuditConfig {
+    /// [`AuditConfig::path`] in the state directory unless it's absolute
+    pub fn resolved_path(&self) -> io::Result<PathBuf> {
+        if self.path.is_absolute() {
+            return Ok(self.path.clone());
+        }
+        Ok(Dirs::djinn()?.state().join(&self.path))
+    }
+}
+
+/// One request to the server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch when the request was received
+    pub timestamp_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_address: Option<IpAddr>,
+    /// The value of [`AuditConfig::client_header`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The prompt fields of a JSON request
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub prompt: Map<String, Value>,
+    /// The other fields of a JSON request
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+    pub status: u16,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+    /// Time until the whole response was sent, including streamed responses
+    pub latency_ms: u64,
+    /// False if the client went away before the response was sent
+    pub completed: bool,
+}
+
+/// Removes sensitive data from a record before it is written
+pub trait Redact: Send + Sync {
+    fn redact(&self, record: &mut AuditRecord);
+}
+
+/// Replaces prompt text with its length
+struct RedactPrompts;
+
+impl Redact for RedactPrompts {
+    fn redact(&self, record: &mut AuditRecord) {
+        record.prompt.values_mut().for_each(redact_strings);
+    }
+}
+
+/// Replace every string with its length, except the roles of chat messages
+fn redact_strings(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = format!("[redacted {} chars]", text.chars().count()),
+        Value::Array(values) => values.iter_mut().for_each(redact_strings),
+        Value::Object(map) => map
+            .iter_mut()
+            .filter(|(key, _value)| key.as_str() != "role")
+            .for_each(|(_key, value)| redact_strings(value)),
+        _ => {}
+    }
+}
+
+/// Replaces request fields by name
+struct RedactFields(Vec<String>);
+
+impl Redact for RedactFields {
+    fn redact(&self, record: &mut AuditRecord) {
+        for (key, value) in record.prompt.iter_mut().chain(record.params.iter_mut()) {
+            if self.0.contains(key) {
+                *value = Value::String(REDACTED.to_string());
+            }
+        }
+    }
+}
+
+pub struct AuditLog {
+    client_header: Option<String>,
+    redactors: Vec<Box<dyn Redact>>,
+    writer: Mutex<RotatingWriter>,
+}
+
+impl AuditLog {
+    pub fn new(config: &AuditConfig) -> io::Result<Self> {
+        let mut redactors: Vec<Box<dyn Redact>> = Vec::new();
+        if config.redact_prompts {
+            redactors.push(Box::new(RedactPrompts));
+        }
+        if !config.redact_fields.is_empty() {
+            redactors.push(Box::new(RedactFields(config.redact_fields.clone())));
+        }
+
+        let path = config.resolved_path()?;
+        tracing::info!(?path, "writing audit log");
+        Ok(AuditLog {
+            client_header: config.client_header.clone(),
+            redactors,
+            writer: Mutex::new(RotatingWriter::open(
+                path,
+                config.max_file_bytes,
+                config.max_files,
+            )?),
+        })
+    }
+
+    /// Redact and write a record.
+    /// Errors are logged rather than failing the request
+    pub fn write(&self, mut record: AuditRecord) {
+        for redactor in &self.redactors {
+            redactor.redact(&mut record);
+        }
+        let result = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                let mut writer = self
+                    .writer
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner());
+                writer.write_line(&line)
+            });
+        if let Err(error) = result {
+            tracing::error!(%error, "unable to write audit record");
+        }
+    }
+}
+
+/// Appends lines to a file and rotates it when it gets too big
+struct RotatingWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_file_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingWriter {
+    fn open(path: PathBuf, max_file_bytes: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingWriter {
+            path,
+            file,
+            size,
+            max_file_bytes,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_file_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        tracing::debug!(path = ?self.path, "rotated audit log");
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// `<path>.<index>`, e.g. `audit.jsonl.1`
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// Split the fields of a JSON request into the model name, prompt fields, and the rest
+fn split_request(request: Value) -> (Option<String>, Map<String, Value>, Map<String, Value>) {
+    let Value::Object(mut params) = request else {
+        return (None, Map::new(), Map::new());
+    };
+    let model = match params.remove("model") {
+        Some(Value::String(model)) => Some(model),
+        _ => None,
+    };
+    let mut prompt = Map::new();
+    for field in PROMPT_FIELDS {
+        if let Some(value) = params.remove(*field) {
+            prompt.insert(field.to_string(), value);
+        }
+    }
+    (model, prompt, params)
+}
+
+fn is_json(parts: &Parts) -> bool {
+    parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Writes the record when the response has been sent or dropped
+struct PendingRecord {
+    audit: Arc<AuditLog>,
+    record: Option<AuditRecord>,
+    started: Instant,
+}
+
+impl Track for PendingRecord {
+    fn data(&mut self, len: usize) {
+        if let Some(record) = &mut self.record {
+            record.response_bytes += len;
+        }
+    }
+
+    fn end(&mut self) {
+        if let Some(record) = &mut self.record {
+            record.completed = true;
+        }
+    }
+}
+
+impl Drop for PendingRecord {
+    fn drop(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            record.latency_ms = self.started.elapsed().as_millis() as u64;
+            self.audit.write(record);
+        }
+    }
+}
+
+/// Middleware that writes a record to the [`AuditLog`] for every request
+/// except `GET` requests, which don't run models
+pub async fn audit_requests(
+    State(audit): State<Arc<AuditLog>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::GET {
+        return next.run(request).await;
+    }
+
+    let started = Instant::now();
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default();
+
+    let (parts, body) = request.into_parts();
+    let client_address = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+    let client_id = audit
+        .client_header
+        .as_ref()
+        .and_then(|name| parts.headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let (body, request_bytes, json) = if is_json(&parts) {
+        let bytes = match to_bytes(body, MAX_REQUEST_BYTES).await {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                return (StatusCode::PAYLOAD_TOO_LARGE, error.to_string()).into_response();
+            }
+        };
+        let json = serde_json::from_slice(&bytes).ok();
+        (Body::from(bytes.clone()), bytes.len(), json)
+    } else {
+        let len = parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        (body, len, None)
+    };
+    let (model, prompt, params) = json.map(split_request).unwrap_or_default();
+
+    let record = AuditRecord {
+        timestamp_ms,
+        client_address,
+        client_id,
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        model,
+        prompt,
+        params,
+        status: 0,
+        request_bytes,
+        response_bytes: 0,
+        latency_ms: 0,
+        completed: false,
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let pending = PendingRecord {
+        audit,
+        record: Some(AuditRecord {
+            status: response.status().as_u16(),
+            ..record
+        }),
+        started,
+    };
+    // count the response as it is sent, so streamed responses are covered too
+    track(response, pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_paths_are_numbered() {
+        assert_eq!(
+            rotated_path(Path::new("logs/audit.jsonl"), 2),
+            PathBuf::from("logs/audit.jsonl.2")
+        );
+    }
+
+    #[test]
+    fn splits_prompts_from_params() {
+        let request = serde_json::json!({
+            "prompt": "hello",
+            "model": "mistral",
+            "temperature": 0.5,
+        });
+
+        let (model, prompt, params) = split_request(request);
+
+        assert_eq!(model.as_deref(), Some("mistral"));
+        assert_eq!(prompt.get("prompt"), Some(&Value::from("hello")));
+        assert_eq!(params.get("temperature"), Some(&Value::from(0.5)));
+        assert!(!params.contains_key("prompt"));
+    }
+
+    fn record(path: &str, request: Value) -> AuditRecord {
+        let (model, prompt, params) = split_request(request);
+        AuditRecord {
+            timestamp_ms: 0,
+            client_address: None,
+            client_id: None,
+            method: "POST".to_string(),
+            path: path.to_string(),
+            model,
+            prompt,
+            params,
+            status: 200,
+            request_bytes: 0,
+            response_bytes: 0,
+            latency_ms: 0,
+            completed: true,
+        }
+    }
+
+    /// The record of `request` with its prompts redacted
+    fn redacted(path: &str, request: Value) -> AuditRecord {
+        let mut record = record(path, request);
+        RedactPrompts.redact(&mut record);
+        record
+    }
+
+    #[test]
+    fn redacts_prompts_and_fields() {
+        let mut record = record(
+            "/complete/batch",
+            serde_json::json!({
+                "prompts": ["secret", "text"],
+                "stop": ["\n"],
+            }),
+        );
+
+        RedactPrompts.redact(&mut record);
+        RedactFields(vec!["stop".to_string()]).redact(&mut record);
+
+        assert_eq!(
+            record.prompt.get("prompts"),
+            Some(&serde_json::json!([
+                "[redacted 6 chars]",
+                "[redacted 4 chars]"
+            ]))
+        );
+        assert_eq!(record.params.get("stop"), Some(&Value::from(REDACTED)));
+    }
+
+    #[test]
+    fn redacts_completions() {
+        let record = redacted(
+            "/complete",
+            serde_json::json!({"prompt": "secret", "model": "mistral", "sample_len": 10}),
+        );
+
+        assert_eq!(
+            record.prompt.get("prompt"),
+            Some(&Value::from("[redacted 6 chars]"))
+        );
+        assert_eq!(record.params.get("sample_len"), Some(&Value::from(10)));
+    }
+
+    #[test]
+    fn redacts_chat_messages() {
+        let record = redacted(
+            "/chat",
+            serde_json::json!({"session": "abc", "message": "secret", "system": "be nice"}),
+        );
+
+        assert_eq!(
+            record.prompt.get("message"),
+            Some(&Value::from("[redacted 6 chars]"))
+        );
+        assert_eq!(
+            record.prompt.get("system"),
+            Some(&Value::from("[redacted 7 chars]"))
+        );
+        assert_eq!(record.params.get("session"), Some(&Value::from("abc")));
+    }
+
+    #[test]
+    fn redacts_infill() {
+        let record = redacted(
+            "/infill",
+            serde_json::json!({"prefix": "fn main() {", "suffix": "}"}),
+        );
+
+        assert_eq!(
+            record.prompt.get("prefix"),
+            Some(&Value::from("[redacted 11 chars]"))
+        );
+        assert_eq!(
+            record.prompt.get("suffix"),
+            Some(&Value::from("[redacted 1 chars]"))
+        );
+    }
+
+    #[test]
+    fn redacts_embeddings_and_tokens() {
+        let record = redacted("/embed", serde_json::json!({"input": ["secret", "text"]}));
+        assert_eq!(
+            record.prompt.get("input"),
+            Some(&serde_json::json!([
+                "[redacted 6 chars]",
+                "[redacted 4 chars]"
+            ]))
+        );
+
+        let record = redacted("/tokenize", serde_json::json!({"text": "secret"}));
+        assert_eq!(
+            record.prompt.get("text"),
+            Some(&Value::from("[redacted 6 chars]"))
+        );
+    }
+
+    #[test]
+    fn redacts_images() {
+        let record = redacted(
+            "/imagine",
+            serde_json::json!({"prompt": "a cat", "negative_prompt": "a dog", "steps": 30}),
+        );
+
+        assert_eq!(
+            record.prompt.get("negative_prompt"),
+            Some(&Value::from("[redacted 5 chars]"))
+        );
+        assert_eq!(record.params.get("steps"), Some(&Value::from(30)));
+    }
+
+    #[test]
+    fn redacts_ollama_requests() {
+        let record = redacted(
+            "/api/generate",
+            serde_json::json!({"model": "mistral", "prompt": "secret", "system": "be nice"}),
+        );
+        assert_eq!(
+            record.prompt.get("prompt"),
+            Some(&Value::from("[redacted 6 chars]"))
+        );
+
+        let record = redacted(
+            "/api/chat",
+            serde_json::json!({
+                "model": "mistral",
+                "messages": [
+                    {"role": "system", "content": "be nice"},
+                    {"role": "user", "content": "secret"},
+                ],
+            }),
+        );
+        assert_eq!(
+            record.prompt.get("messages"),
+            Some(&serde_json::json!([
+                {"role": "system", "content": "[redacted 7 chars]"},
+                {"role": "user", "content": "[redacted 6 chars]"},
+            ]))
+        );
+
+        let record = redacted(
+            "/api/embeddings",
+            serde_json::json!({"model": "nomic", "prompt": "secret"}),
+        );
+        assert_eq!(record.model.as_deref(), Some("nomic"));
+        assert_eq!(
+            record.prompt.get("prompt"),
+            Some(&Value::from("[redacted 6 chars]"))
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use djinn_core::lm::{
+    config::RunConfig,
+    model::{KvSnapshot, RunStats},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{instrument, Instrument};
+
+use crate::complete::generate;
+use crate::error::{Error, Result};
+use crate::registry::load_model;
+use crate::server::{Context, Json};
+
+pub const ROUTE_CHAT: &str = "/chat";
+pub const ROUTE_CHAT_SESSION: &str = "/chat/:session";
+
+/// The maximum number of sessions to keep.
+/// The least recently used session is dropped to make room for a new one.
+const MAX_SESSIONS: usize = 256;
+/// The number of most recently used sessions that keep a copy of the model's KV cache
+const MAX_KV_CACHES: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatRequest {
+    /// The session to continue.
+    /// A new session is started if none is given.
+    #[serde(default)]
+    pub(crate) session: Option<String>,
+    pub(crate) message: String,
+    /// A system prompt for a new session
+    #[serde(default)]
+    pub(crate) system: Option<String>,
+    /// The model to start a new session with.
+    /// The default model is used if none is given.
+    #[serde(default)]
+    pub(crate) model: Option<String>,
+    #[serde(default, flatten)]
+    pub(crate) config: RunConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatResponse {
+    pub(crate) session: String,
+    pub(crate) reply: String,
+    #[serde(flatten)]
+    pub(crate) stats: RunStats,
+}
+
+struct ChatSession {
+    model: Option<String>,
+    /// The conversation so far, formatted with the model's chat template
+    transcript: String,
+    /// The model's KV cache after the last turn
+    kv_cache: Option<KvSnapshot>,
+    last_used: Instant,
+}
+
+/// Conversations kept between requests.
+/// Each turn extends the previous prompt,
+/// so the model's prefix cache only has to encode the new messages.
+/// The most recent sessions keep a copy of the KV cache if the model exposes it,
+/// so this holds even when other requests used the model in between.
+/// Other sessions share the model's KV cache with every other request.
+#[derive(Default)]
+pub struct ChatSessions {
+    sessions: HashMap<String, ChatSession>,
+}
+
+impl ChatSessions {
+    /// Get a session by ID, or start a new one if no ID is given
+    fn get_or_start(
+        &mut self,
+        id: Option<String>,
+        model: Option<String>,
+    ) -> Result<(String, &mut ChatSession)> {
+        let id = match id {
+            Some(id) => {
+                let session = self
+                    .sessions
+                    .get(&id)
+                    .ok_or_else(|| Error::UnknownSession(id.as_str().into()))?;
+                if model.is_some() && model != session.model {
+                    return Err(Error::InvalidRequest(format!(
+                        "session {id} uses a different model"
+                    )));
+                }
+                id
+            }
+            None => {
+                self.make_room();
+                let id = format!("{:032x}", rand::random::<u128>());
+                tracing::info!(%id, "starting chat session");
+                self.sessions.insert(
+                    id.clone(),
+                    ChatSession {
+                        model,
+                        transcript: String::new(),
+                        kv_cache: None,
+                        last_used: Instant::now(),
+                    },
+                );
+                id
+            }
+        };
+
+        let session = self.sessions.get_mut(&id).expect("session should exist");
+        session.last_used = Instant::now();
+        Ok((id, session))
+    }
+
+    /// Returns true if the session existed
+    pub(crate) fn end(&mut self, id: &str) -> bool {
+        self.sessions.remove(id).is_some()
+    }
+
+    /// Drop the KV caches of all but the most recently used sessions
+    fn limit_kv_caches(&mut self) {
+        let mut cached: Vec<&mut ChatSession> = self
+            .sessions
+            .values_mut()
+            .filter(|session| session.kv_cache.is_some())
+            .collect();
+        cached.sort_by_key(|session| std::cmp::Reverse(session.last_used));
+        for session in cached.into_iter().skip(MAX_KV_CACHES) {
+            session.kv_cache = None;
+        }
+    }
+
+    fn make_room(&mut self) {
+        while self.sessions.len() >= MAX_SESSIONS {
+            let Some(id) = self
+                .sessions
+                .iter()
+                .min_by_key(|(_id, session)| session.last_used)
+                .map(|(id, _session)| id.clone())
+            else {
+                break;
+            };
+            tracing::info!(%id, "dropping least recently used chat session");
+            self.sessions.remove(&id);
+        }
+    }
+}
+
+/// Send a message in a chat session and get the model's reply
+#[instrument(skip(context))]
+pub async fn chat(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<ChatRequest>,
+) -> Result<Json<ChatResponse>> {
+    let span = tracing::info_span!("chat");
+
+    let mut lock = lock_chat_model(&context, &payload).instrument(span).await?;
+    tracing::info!("got model lock");
+
+    let response = chat_turn(lock.deref_mut(), payload).await?;
+
+    Ok(Json(response))
+}
+
+/// Lock the context with the model of a chat request loaded:
+/// the requested model, or the model of the session it continues
+pub(crate) async fn lock_chat_model<'a>(
+    context: &'a Mutex<Context>,
+    request: &ChatRequest,
+) -> Result<MutexGuard<'a, Context>> {
+    let lock = context.lock().await;
+    let model = match (&request.model, &request.session) {
+        (Some(model), _) => Some(model.clone()),
+        (None, Some(id)) => match lock.sessions.sessions.get(id) {
+            Some(session) => session.model.clone(),
+            // the turn fails with an unknown session error
+            None => return Ok(lock),
+        },
+        (None, None) => None,
+    };
+    load_model(context, lock, model.as_deref()).await
+}
+
+/// Run one turn of a chat session
+pub(crate) async fn chat_turn(context: &mut Context, payload: ChatRequest) -> Result<ChatResponse> {
+    let Context {
+        models, sessions, ..
+    } = context;
+
+    let ChatRequest {
+        session,
+        message,
+        system,
+        model,
+        mut config,
+    } = payload;
+
+    let (id, session) = sessions.get_or_start(session, model)?;
+    let model = models.get(session.model.as_deref())?;
+
+    let template = model.chat_template();
+    if let Some(system) = system {
+        if !session.transcript.is_empty() {
+            return Err(Error::InvalidRequest(format!(
+                "session {id} has already started, the system prompt can only be set for new sessions"
+            )));
+        }
+        session.transcript = template.system(&system);
+    }
+    let prompt = session.transcript.clone() + &template.user_turn(&message);
+    config.echo_prompt = false;
+
+    if let Some(kv_cache) = session.kv_cache.take() {
+        model.restore_kv_cache(kv_cache);
+    }
+    let (reply, stats, _candidates) = generate(model, prompt.clone(), config).await?;
+    session.transcript = prompt + &template.reply(&reply);
+    session.kv_cache = model.save_kv_cache();
+    sessions.limit_kv_caches();
+
+    Ok(ChatResponse {
+        session: id,
+        reply,
+        stats,
+    })
+}
+
+/// Drop a chat session and its history
+#[instrument(skip(context))]
+pub async fn end_chat(
+    State(context): State<Arc<Mutex<Context>>>,
+    Path(session): Path<String>,
+) -> Result<StatusCode> {
+    let mut lock = context.lock().await;
+    if lock.sessions.end(&session) {
+        tracing::info!(%session, "ended chat session");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::UnknownSession(session.into()))
+    }
+}
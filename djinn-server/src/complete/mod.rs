@@ -1,29 +1,84 @@
+use std::convert::Infallible;
 use std::ops::DerefMut;
 use std::sync::Arc;
 
+use async_stream::stream;
 use axum::extract::State;
-use djinn_core::lm::config::RunConfig;
-use futures::{pin_mut, StreamExt};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use djinn_core::lm::{
+    beam::Candidate,
+    config::{RunConfig, MAX_BEAMS, MAX_LOGPROBS},
+    grammar::Grammar,
+    model::{ModelContext, RunStats},
+    sampling::TokenLogprobs,
+};
+use futures::{pin_mut, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use tracing::{instrument, Instrument};
 
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorResponse, Result};
+use crate::registry::lock_model;
 use crate::server::{Context, Json};
 
 pub const ROUTE_COMPLETE: &str = "/complete";
+pub const ROUTE_COMPLETE_STREAM: &str = "/complete/stream";
+pub const ROUTE_COMPLETE_BATCH: &str = "/complete/batch";
+
+/// Server-sent event names used by [`complete_stream`]
+const EVENT_TOKEN: &str = "token";
+const EVENT_ERROR: &str = "error";
+const EVENT_EOS: &str = "eos";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CompleteRequest {
-    prompt: String,
+    pub(crate) prompt: String,
+    /// The name of the model to run.
+    /// The default model is used if none is given.
+    #[serde(default)]
+    pub(crate) model: Option<String>,
     #[serde(default, flatten)]
-    config: RunConfig,
+    pub(crate) config: RunConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CompleteResponse {
-    prompt: String,
-    output: String,
+    pub(crate) prompt: String,
+    pub(crate) output: String,
+    #[serde(flatten)]
+    pub(crate) stats: RunStats,
+    /// One entry per generated token, if `logprobs` was requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) logprobs: Vec<TokenLogprobs>,
+    /// The completions found by beam search, best first.
+    /// `output` is the best one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) candidates: Vec<Candidate>,
+}
+
+/// The data of the `eos` event sent by [`complete_stream`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamEnd {
+    #[serde(flatten)]
+    stats: RunStats,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    logprobs: Vec<TokenLogprobs>,
+}
+
+/// Several prompts that are run with the same model and parameters
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchCompleteRequest {
+    prompts: Vec<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default, flatten)]
+    config: RunConfig,
+}
+
+/// Results in the same order as [`BatchCompleteRequest::prompts`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchCompleteResponse {
+    results: Vec<CompleteResponse>,
 }
 
 #[instrument(skip(model_context))]
@@ -31,9 +86,12 @@ pub async fn complete(
     State(model_context): State<Arc<Mutex<Context>>>,
     Json(payload): Json<CompleteRequest>,
 ) -> Result<Json<CompleteResponse>> {
+    check_run_config(&payload.config)?;
     let span = tracing::info_span!("complete JSON");
 
-    let mut lock = model_context.lock().instrument(span).await;
+    let mut lock = lock_model(&model_context, payload.model.as_deref())
+        .instrument(span)
+        .await?;
     tracing::info!("got model lock");
 
     let context: &mut Context = lock.deref_mut();
@@ -43,32 +101,241 @@ pub async fn complete(
     Ok(Json(response))
 }
 
+/// Run a batch of prompts while holding the model lock once.
+/// The prompts are currently run one after the other.
 #[instrument(skip(model_context))]
-async fn run_model(
+pub async fn complete_batch(
+    State(model_context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<BatchCompleteRequest>,
+) -> Result<Json<BatchCompleteResponse>> {
+    check_run_config(&payload.config)?;
+    let span = tracing::info_span!("complete batch JSON");
+
+    let mut lock = lock_model(&model_context, payload.model.as_deref())
+        .instrument(span)
+        .await?;
+    tracing::info!(batch_size = payload.prompts.len(), "got model lock");
+
+    let context: &mut Context = lock.deref_mut();
+
+    let BatchCompleteRequest {
+        prompts,
+        model,
+        config,
+    } = payload;
+
+    let model = context.models.get(model.as_deref())?;
+
+    let mut results = Vec::with_capacity(prompts.len());
+    for prompt in prompts {
+        let (output, stats, candidates) = generate(model, prompt.clone(), config.clone()).await?;
+        results.push(CompleteResponse {
+            prompt,
+            output,
+            stats,
+            logprobs: model.logprobs().to_vec(),
+            candidates,
+        });
+    }
+
+    Ok(Json(BatchCompleteResponse { results }))
+}
+
+/// An `error` event with an [`ErrorResponse`] as JSON
+fn error_event(error: Error) -> Event {
+    let response = error.to_response();
+    Event::default()
+        .event(EVENT_ERROR)
+        .json_data(&response)
+        .unwrap_or_else(|_| Event::default().event(EVENT_ERROR).data(response.message))
+}
+
+/// Stream tokens back to the client as server-sent events as they are generated.
+/// Each token is sent as a `token` event and the stream is terminated with an `eos` event
+/// which contains the [`RunStats`] and any requested logprobs as JSON.
+/// If the run fails, an `error` event is sent instead of `eos` and the stream ends.
+#[instrument(skip(model_context))]
+pub async fn complete_stream(
+    State(model_context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<CompleteRequest>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    let CompleteRequest {
+        prompt,
+        model,
+        config,
+    } = payload;
+
+    let stream = stream! {
+        if let Err(error) = check_run_config(&config).and_then(|()| check_streamable(&config)) {
+            yield Ok(error_event(error));
+            return;
+        }
+
+        let span = tracing::info_span!("complete stream");
+        let mut lock = match lock_model(&model_context, model.as_deref()).instrument(span).await {
+            Ok(lock) => lock,
+            Err(error) => {
+                tracing::error!(%error, "unable to get model");
+                yield Ok(error_event(error));
+                return;
+            }
+        };
+        tracing::info!("got model lock");
+
+        let context: &mut Context = lock.deref_mut();
+        let model = match context.models.get(model.as_deref()) {
+            Ok(model) => model,
+            Err(error) => {
+                tracing::error!(%error, "unable to get model");
+                yield Ok(error_event(error));
+                return;
+            }
+        };
+        {
+            let stream = model.run(prompt, config);
+            pin_mut!(stream);
+
+            while let Some(value) = stream.next().await {
+                match value {
+                    Ok(string_token) => {
+                        tracing::trace!("{string_token}");
+                        yield Ok(Event::default().event(EVENT_TOKEN).data(string_token));
+                    }
+                    Err(error) => {
+                        tracing::error!(%error, "error while streaming completion");
+                        yield Ok(error_event(error.into()));
+                        return;
+                    }
+                }
+            }
+        }
+
+        let end = StreamEnd {
+            stats: model.stats(),
+            logprobs: model.logprobs().to_vec(),
+        };
+        let eos = Event::default()
+            .event(EVENT_EOS)
+            .json_data(end)
+            .unwrap_or_else(|_| Event::default().event(EVENT_EOS).data(""));
+        yield Ok(eos);
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Run a completion with a loaded model.
+/// The request's config is checked with [`check_run_config`] before taking the lock.
+#[instrument(skip(model_context))]
+pub(crate) async fn run_model(
     model_context: &mut Context,
     request: CompleteRequest,
 ) -> Result<CompleteResponse> {
-    let prompt = request.prompt;
-
-    let config = request.config;
+    let CompleteRequest {
+        prompt,
+        model,
+        config,
+    } = request;
 
-    // setup output stream
-    let stream = model_context.model.run(prompt.clone(), config);
+    let model = model_context.models.get(model.as_deref())?;
 
-    // consume the stream
-    pin_mut!(stream);
-    let mut output = String::new();
-    while let Some(value) = stream.next().await {
-        value
-            .map(|string_token| {
-                output.push_str(&string_token);
-                tracing::trace!("{string_token}");
-            })
-            .map_err(Error::from)?;
-    }
-    let response = CompleteResponse { prompt, output };
+    let (output, stats, candidates) = generate(model, prompt.clone(), config).await?;
+    let response = CompleteResponse {
+        prompt,
+        output,
+        stats,
+        logprobs: model.logprobs().to_vec(),
+        candidates,
+    };
 
     tracing::info!("sending response: {response:?}");
 
     Ok(response)
 }
+
+/// Reject options the model can't run with before the request is queued
+pub(crate) fn check_run_config(config: &RunConfig) -> Result<()> {
+    if let Some(logprobs) = config.logprobs {
+        if logprobs > MAX_LOGPROBS {
+            return Err(Error::InvalidRequest(format!(
+                "logprobs must be at most {MAX_LOGPROBS}"
+            )));
+        }
+    }
+    if let Some(grammar) = &config.grammar {
+        if config.format.is_some() {
+            return Err(Error::InvalidRequest(
+                "grammar can't be combined with format".to_string(),
+            ));
+        }
+        grammar
+            .parse::<Grammar>()
+            .map_err(|error| Error::InvalidRequest(format!("invalid grammar: {error}")))?;
+    }
+    if let Some(num_beams) = config.num_beams() {
+        if num_beams > MAX_BEAMS || config.num_beams == Some(0) || config.n_best == Some(0) {
+            return Err(Error::InvalidRequest(format!(
+                "num_beams and n_best must be between 1 and {MAX_BEAMS}"
+            )));
+        }
+        if let (Some(num_beams), Some(n_best)) = (config.num_beams, config.n_best) {
+            if n_best > num_beams {
+                return Err(Error::InvalidRequest(
+                    "n_best must be at most num_beams".to_string(),
+                ));
+            }
+        }
+        if config.format.is_some() || config.grammar.is_some() || config.logprobs.is_some() {
+            return Err(Error::InvalidRequest(
+                "beam search can't be combined with format, grammar, or logprobs".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Beam search only has output once every beam is done
+pub(crate) fn check_streamable(config: &RunConfig) -> Result<()> {
+    if config.num_beams().is_some() {
+        return Err(Error::InvalidRequest(format!(
+            "beam search can't be streamed, use {ROUTE_COMPLETE}"
+        )));
+    }
+    Ok(())
+}
+
+/// Run the model and collect the output.
+/// With beam search the output is the best candidate, and every candidate is returned too.
+pub(crate) async fn generate(
+    model: &mut ModelContext,
+    prompt: String,
+    config: RunConfig,
+) -> Result<(String, RunStats, Vec<Candidate>)> {
+    if config.num_beams().is_some() {
+        let candidates = model.beam_search(&prompt, &config)?;
+        let output = candidates
+            .first()
+            .map(|best| best.text.clone())
+            .unwrap_or_default();
+        return Ok((output, model.stats(), candidates));
+    }
+
+    let mut output = String::new();
+    {
+        // setup output stream
+        let stream = model.run(prompt, config);
+
+        // consume the stream
+        pin_mut!(stream);
+        while let Some(value) = stream.next().await {
+            value
+                .map(|string_token| {
+                    output.push_str(&string_token);
+                    tracing::trace!("{string_token}");
+                })
+                .map_err(Error::from)?;
+        }
+    }
+
+    Ok((output, model.stats(), Vec::new()))
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::extract::{Multipart, State};
+use djinn_core::llava::{DescribeOptions, Description};
+use image::DynamicImage;
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::detect::parse_field;
+use crate::error::{Error, Result};
+use crate::server::{Context, Json};
+
+pub const ROUTE_DESCRIBE: &str = "/describe";
+
+const DEFAULT_PROMPT: &str = "Describe this image.";
+
+/// Multipart form field names accepted by [`describe`]
+const FIELD_IMAGE: &str = "image";
+const FIELD_PROMPT: &str = "prompt";
+const FIELD_MAX_TOKENS: &str = "max_tokens";
+const FIELD_TEMPERATURE: &str = "temperature";
+const FIELD_SEED: &str = "seed";
+
+#[derive(Debug)]
+struct DescribeRequest {
+    image: DynamicImage,
+    prompt: String,
+    options: DescribeOptions,
+}
+
+/// Answer a question about an image uploaded as multipart form data.
+///
+/// The `image` field is required.
+/// `prompt`, `max_tokens`, `temperature`, and `seed` are optional.
+#[instrument(skip(context, multipart))]
+pub async fn describe(
+    State(context): State<Arc<Mutex<Context>>>,
+    multipart: Multipart,
+) -> Result<Json<Description>> {
+    let request = read_request(multipart).await?;
+
+    let span = tracing::info_span!("describe");
+    let lock = context.lock().instrument(span).await;
+    tracing::info!("got describer lock");
+
+    let describer = lock
+        .describer
+        .as_ref()
+        .ok_or(Error::DescriberNotConfigured)?;
+
+    let description = describer
+        .describe(&request.image, &request.prompt, &request.options)
+        .map_err(Error::Describe)?;
+    tracing::debug!(tokens = description.tokens, "described image");
+
+    Ok(Json(description))
+}
+
+async fn read_request(mut multipart: Multipart) -> Result<DescribeRequest> {
+    let mut image = None;
+    let mut prompt = DEFAULT_PROMPT.to_string();
+    let mut options = DescribeOptions::default();
+
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            FIELD_IMAGE => {
+                let bytes = field.bytes().await?;
+                let decoded = image::load_from_memory(&bytes).map_err(|error| {
+                    Error::InvalidRequest(format!("unable to decode image: {error}"))
+                })?;
+                image = Some(decoded);
+            }
+            FIELD_PROMPT => prompt = field.text().await?,
+            FIELD_MAX_TOKENS => options.max_tokens = parse_field(&name, &field.text().await?)?,
+            FIELD_TEMPERATURE => {
+                options.temperature = Some(parse_field(&name, &field.text().await?)?);
+            }
+            FIELD_SEED => options.seed = parse_field(&name, &field.text().await?)?,
+            _ => tracing::warn!(name, "ignoring unknown field"),
+        }
+    }
+
+    let image =
+        image.ok_or_else(|| Error::InvalidRequest(format!("missing `{FIELD_IMAGE}` field")))?;
+
+    Ok(DescribeRequest {
+        image,
+        prompt,
+        options,
+    })
+}
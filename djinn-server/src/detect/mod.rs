@@ -0,0 +1,146 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use axum::extract::{Multipart, State};
+use base64::Engine;
+use djinn_core::yolov8::{annotate, args::YoloTask, Detection};
+use image::{DynamicImage, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::error::{Error, Result};
+use crate::server::{Context, Json};
+
+pub const ROUTE_DETECT: &str = "/detect";
+
+const DEFAULT_CONFIDENCE_THRESHOLD: f32 = 0.25;
+const DEFAULT_NMS_THRESHOLD: f32 = 0.45;
+const DEFAULT_LEGEND_SIZE: u32 = 14;
+const ANNOTATED_JPEG_QUALITY: u8 = 90;
+
+/// Multipart form field names accepted by [`detect`]
+const FIELD_IMAGE: &str = "image";
+const FIELD_CONFIDENCE_THRESHOLD: &str = "confidence_threshold";
+const FIELD_NMS_THRESHOLD: &str = "nms_threshold";
+const FIELD_ANNOTATE: &str = "annotate";
+
+#[derive(Debug)]
+struct DetectRequest {
+    image: DynamicImage,
+    confidence_threshold: f32,
+    nms_threshold: f32,
+    annotate: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DetectResponse {
+    detections: Vec<Detection>,
+    /// The input image with the detections drawn on it,
+    /// as a base64 encoded JPEG
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    annotated_image: Option<String>,
+}
+
+/// Run object detection on an image uploaded as multipart form data.
+///
+/// The `image` field is required.
+/// `confidence_threshold`, `nms_threshold`, and `annotate` are optional.
+#[instrument(skip(context, multipart))]
+pub async fn detect(
+    State(context): State<Arc<Mutex<Context>>>,
+    multipart: Multipart,
+) -> Result<Json<DetectResponse>> {
+    let request = read_request(multipart).await?;
+
+    let span = tracing::info_span!("detect");
+    let lock = context.lock().instrument(span).await;
+    tracing::info!("got detector lock");
+
+    let detector = lock.detector.as_ref().ok_or(Error::DetectorNotConfigured)?;
+
+    let detections = detector
+        .detect(
+            &request.image,
+            request.confidence_threshold,
+            request.nms_threshold,
+        )
+        .map_err(Error::Detection)?;
+    tracing::debug!(count = detections.len(), "detected objects");
+
+    let legend_size = match detector.task() {
+        YoloTask::Detect => DEFAULT_LEGEND_SIZE,
+        YoloTask::Pose => 0,
+    };
+
+    let annotated_image = if request.annotate {
+        let image = annotate(request.image, &detections, legend_size);
+        Some(encode_jpeg(&image)?)
+    } else {
+        None
+    };
+
+    Ok(Json(DetectResponse {
+        detections,
+        annotated_image,
+    }))
+}
+
+async fn read_request(mut multipart: Multipart) -> Result<DetectRequest> {
+    let mut image = None;
+    let mut confidence_threshold = DEFAULT_CONFIDENCE_THRESHOLD;
+    let mut nms_threshold = DEFAULT_NMS_THRESHOLD;
+    let mut annotate = false;
+
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            FIELD_IMAGE => {
+                let bytes = field.bytes().await?;
+                let decoded = image::load_from_memory(&bytes).map_err(|error| {
+                    Error::InvalidRequest(format!("unable to decode image: {error}"))
+                })?;
+                image = Some(decoded);
+            }
+            FIELD_CONFIDENCE_THRESHOLD => {
+                confidence_threshold = parse_field(&name, &field.text().await?)?;
+            }
+            FIELD_NMS_THRESHOLD => nms_threshold = parse_field(&name, &field.text().await?)?,
+            FIELD_ANNOTATE => annotate = parse_field(&name, &field.text().await?)?,
+            _ => tracing::warn!(name, "ignoring unknown field"),
+        }
+    }
+
+    let image =
+        image.ok_or_else(|| Error::InvalidRequest(format!("missing `{FIELD_IMAGE}` field")))?;
+
+    Ok(DetectRequest {
+        image,
+        confidence_threshold,
+        nms_threshold,
+        annotate,
+    })
+}
+
+/// Parse a multipart form field's text
+pub(crate) fn parse_field<T: std::str::FromStr>(name: &str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|error| Error::InvalidRequest(format!("invalid `{name}` field: {error}")))
+}
+
+fn encode_jpeg(image: &DynamicImage) -> Result<String> {
+    let mut bytes = Vec::new();
+    image
+        .write_to(
+            &mut Cursor::new(&mut bytes),
+            ImageOutputFormat::Jpeg(ANNOTATED_JPEG_QUALITY),
+        )
+        .map_err(|error| Error::Detection(error.into()))?;
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
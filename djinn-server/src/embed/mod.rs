@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::error::{Error, Result};
+use crate::server::{Context, Json};
+
+pub const ROUTE_EMBED: &str = "/embed";
+
+/// One text or a batch of texts
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum EmbedInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl EmbedInput {
+    fn into_texts(self) -> Vec<String> {
+        match self {
+            EmbedInput::One(text) => vec![text],
+            EmbedInput::Many(texts) => texts,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmbedRequest {
+    input: EmbedInput,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmbedResponse {
+    /// One embedding per input text, in order
+    pub(crate) embeddings: Vec<Vec<f32>>,
+    /// The total number of tokens embedded
+    pub(crate) tokens: usize,
+}
+
+/// Compute embeddings for one or more texts
+#[instrument(skip(context, payload))]
+pub async fn embed(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<EmbedRequest>,
+) -> Result<Json<EmbedResponse>> {
+    let texts = payload.input.into_texts();
+    if texts.is_empty() {
+        return Err(Error::InvalidRequest("no input to embed".to_string()));
+    }
+
+    let span = tracing::info_span!("embed");
+    let lock = context.lock().instrument(span).await;
+    tracing::info!("got embedder lock");
+
+    Ok(Json(embed_texts(&lock, &texts)?))
+}
+
+/// Embed each text with the configured embedder
+pub(crate) fn embed_texts(context: &Context, texts: &[String]) -> Result<EmbedResponse> {
+    let embedder = context
+        .embedder
+        .as_ref()
+        .ok_or(Error::EmbedderNotConfigured)?;
+
+    let mut embeddings = Vec::with_capacity(texts.len());
+    let mut tokens = 0;
+    for text in texts {
+        let embedding = embedder.embed(text).map_err(Error::Embedding)?;
+        tokens += embedding.tokens;
+        embeddings.push(embedding.vector);
+    }
+
+    Ok(EmbedResponse { embeddings, tokens })
+}
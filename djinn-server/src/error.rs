@@ -1,5 +1,11 @@
-use axum::{extract::rejection::JsonRejection, http::StatusCode, response::IntoResponse};
-use serde::Serialize;
+use std::sync::Arc;
+
+use axum::{
+    extract::{multipart::MultipartError, rejection::JsonRejection},
+    http::StatusCode,
+    response::IntoResponse,
+};
+pub use djinn_core::api::{ErrorCode, ErrorResponse};
 
 use crate::server::Json;
 
@@ -11,26 +17,172 @@ pub enum Error {
     Json(#[from] JsonRejection),
     #[error(transparent)]
     Core(#[from] djinn_core::Error),
+    #[error("no model named {0} is configured")]
+    UnknownModel(Arc<str>),
+    #[error("no chat session with ID {0}")]
+    UnknownSession(Arc<str>),
+    #[error("unable to load model {name}: {source}")]
+    ModelLoad {
+        name: Arc<str>,
+        source: anyhow::Error,
+    },
+    #[error(transparent)]
+    Multipart(#[from] MultipartError),
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("object detection is not configured")]
+    DetectorNotConfigured,
+    #[error("object detection failed: {0}")]
+    Detection(anyhow::Error),
+    #[error("embeddings are not configured")]
+    EmbedderNotConfigured,
+    #[error("embedding failed: {0}")]
+    Embedding(anyhow::Error),
+    #[error("transcription is not configured")]
+    TranscriberNotConfigured,
+    #[error("transcription failed: {0}")]
+    Transcription(anyhow::Error),
+    #[error("image generation is not configured")]
+    ImageGeneratorNotConfigured,
+    #[error("image generation failed: {0}")]
+    ImageGeneration(anyhow::Error),
+    #[error("image description is not configured")]
+    DescriberNotConfigured,
+    #[error("image description failed: {0}")]
+    Describe(anyhow::Error),
+    #[error("{max} requests are already running or waiting")]
+    QueueFull { max: usize },
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        #[derive(Serialize)]
-        struct ErrorResponse {
-            message: String,
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Json(_)
+            | Error::Multipart(_)
+            | Error::InvalidRequest(_)
+            | Error::Core(djinn_core::Error::Grammar(_) | djinn_core::Error::InfillUnsupported) => {
+                ErrorCode::InvalidParams
+            }
+            Error::Core(djinn_core::Error::ContextOverflow { .. }) => ErrorCode::ContextOverflow,
+            Error::Core(_)
+            | Error::Detection(_)
+            | Error::Embedding(_)
+            | Error::Transcription(_)
+            | Error::ImageGeneration(_)
+            | Error::Describe(_) => ErrorCode::BackendError,
+            Error::UnknownModel(_) | Error::ModelLoad { .. } => ErrorCode::ModelNotLoaded,
+            Error::QueueFull { .. } => ErrorCode::QueueFull,
+            Error::UnknownSession(_)
+            | Error::DetectorNotConfigured
+            | Error::EmbedderNotConfigured
+            | Error::TranscriberNotConfigured
+            | Error::ImageGeneratorNotConfigured
+            | Error::DescriberNotConfigured => ErrorCode::NotFound,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Json(err) => err.status(),
+            Error::Multipart(err) => err.status(),
+            Error::InvalidRequest(_)
+            | Error::Core(djinn_core::Error::Grammar(_) | djinn_core::Error::InfillUnsupported) => {
+                StatusCode::BAD_REQUEST
+            }
+            Error::Core(djinn_core::Error::ContextOverflow { .. }) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Core(_)
+            | Error::Detection(_)
+            | Error::Embedding(_)
+            | Error::Transcription(_)
+            | Error::ImageGeneration(_)
+            | Error::Describe(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::UnknownModel(_) => StatusCode::NOT_FOUND,
+            Error::ModelLoad { .. } | Error::QueueFull { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Error::UnknownSession(_)
+            | Error::DetectorNotConfigured
+            | Error::EmbedderNotConfigured
+            | Error::TranscriberNotConfigured
+            | Error::ImageGeneratorNotConfigured
+            | Error::DescriberNotConfigured => StatusCode::NOT_FOUND,
         }
+    }
 
-        let (status, message) = match self {
-            Error::Json(err) => (err.status(), err.body_text()),
+    /// The error as it's sent to clients.
+    /// Details of server errors are only logged.
+    pub fn to_response(&self) -> ErrorResponse {
+        let code = self.code();
+        let message = match self {
+            Error::Json(err) => err.body_text(),
+            Error::Multipart(err) => err.body_text(),
+            Error::Core(
+                djinn_core::Error::ContextOverflow { .. }
+                | djinn_core::Error::Grammar(_)
+                | djinn_core::Error::InfillUnsupported,
+            ) => self.to_string(),
             Error::Core(err) => {
                 tracing::error!(%err, "djinn_core error");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Something went wrong D:".to_string(),
-                )
+                "Something went wrong D:".to_string()
+            }
+            err @ Error::ModelLoad { .. } => {
+                tracing::error!(%err, "model load error");
+                "unable to load model".to_string()
+            }
+            err @ Error::Detection(_) => {
+                tracing::error!(%err, "detection error");
+                "unable to run object detection".to_string()
+            }
+            err @ Error::Embedding(_) => {
+                tracing::error!(%err, "embedding error");
+                "unable to compute embeddings".to_string()
+            }
+            err @ Error::Transcription(_) => {
+                tracing::error!(%err, "transcription error");
+                "unable to transcribe audio".to_string()
             }
+            err @ Error::ImageGeneration(_) => {
+                tracing::error!(%err, "image generation error");
+                "unable to generate image".to_string()
+            }
+            err @ Error::Describe(_) => {
+                tracing::error!(%err, "image description error");
+                "unable to describe image".to_string()
+            }
+            err => err.to_string(),
         };
+        ErrorResponse { code, message }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        (self.status(), Json(self.to_response())).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_have_codes_and_statuses() {
+        let overflow = Error::Core(djinn_core::Error::ContextOverflow {
+            prompt_tokens: 4000,
+            sample_len: 200,
+            max_context_len: 4096,
+        });
+        assert_eq!(overflow.code(), ErrorCode::ContextOverflow);
+        assert_eq!(overflow.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let unknown = Error::UnknownModel("phi".into());
+        assert_eq!(unknown.code(), ErrorCode::ModelNotLoaded);
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+
+        let unsupported = Error::Core(djinn_core::Error::InfillUnsupported);
+        assert_eq!(unsupported.code(), ErrorCode::InvalidParams);
+        assert_eq!(unsupported.status(), StatusCode::BAD_REQUEST);
 
-        (status, Json(ErrorResponse { message })).into_response()
+        let full = Error::QueueFull { max: 4 };
+        assert_eq!(full.code(), ErrorCode::QueueFull);
+        assert_eq!(full.status(), StatusCode::SERVICE_UNAVAILABLE);
     }
 }
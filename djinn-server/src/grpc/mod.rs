@@ -0,0 +1,333 @@
+//! The gRPC API defined in `proto/djinn.proto`.
+//! It is served on [`Config::grpc_addr`](crate::Config::grpc_addr)
+//! next to the HTTP API and shares its models, chat sessions,
+//! request queue, and audit log.
+
+use std::{future::Future, net::SocketAddr, ops::DerefMut, pin::Pin, sync::Arc};
+
+use async_stream::stream;
+use axum::{http::StatusCode, Router};
+use djinn_core::lm::{
+    config::RunConfig,
+    model::RunStats,
+    sampling::{TokenLogprob, TokenLogprobs},
+};
+use futures::{pin_mut, Stream, StreamExt};
+use tokio::sync::Mutex;
+use tonic::{service::Routes, Code, Request, Response, Status};
+use tracing::{instrument, Instrument};
+
+use crate::chat::{chat_turn, lock_chat_model, ChatRequest};
+use crate::complete::{check_run_config, run_model, CompleteRequest};
+use crate::embed::embed_texts;
+use crate::error::Error;
+use crate::preload::Readiness;
+use crate::registry::lock_model;
+use crate::server::Context;
+
+pub mod proto {
+    tonic::include_proto!("djinn.v1");
+}
+
+use proto::{
+    complete_chunk::Chunk,
+    djinn_server::{Djinn, DjinnServer},
+};
+
+/// The gRPC API as an axum router, so it can share the HTTP API's middleware
+pub fn router(context: Arc<Mutex<Context>>, readiness: Readiness) -> Router {
+    Routes::new(DjinnServer::new(DjinnService { context, readiness })).into_axum_router()
+}
+
+/// Serve `router` until `shutdown` resolves,
+/// then wait for in flight requests to finish
+pub async fn serve(
+    addr: SocketAddr,
+    router: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    tracing::info!("starting gRPC server on {addr}");
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await?;
+    tracing::info!("gRPC server shutdown");
+    Ok(())
+}
+
+struct DjinnService {
+    context: Arc<Mutex<Context>>,
+    readiness: Readiness,
+}
+
+#[tonic::async_trait]
+impl Djinn for DjinnService {
+    type CompleteStreamStream =
+        Pin<Box<dyn Stream<Item = Result<proto::CompleteChunk, Status>> + Send>>;
+
+    #[instrument(skip(self))]
+    async fn health_check(
+        &self,
+        _request: Request<proto::HealthCheckRequest>,
+    ) -> Result<Response<proto::HealthCheckResponse>, Status> {
+        tracing::debug!("health checked");
+        if self.readiness.is_ready() {
+            Ok(Response::new(proto::HealthCheckResponse {}))
+        } else {
+            Err(Status::unavailable("loading models"))
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn complete(
+        &self,
+        request: Request<proto::CompleteRequest>,
+    ) -> Result<Response<proto::CompleteResponse>, Status> {
+        let proto::CompleteRequest {
+            prompt,
+            model,
+            options,
+        } = request.into_inner();
+        let request = CompleteRequest {
+            prompt,
+            model,
+            config: options.unwrap_or_default().into(),
+        };
+        check_run_config(&request.config)?;
+
+        let span = tracing::info_span!("complete gRPC");
+        let mut lock = lock_model(&self.context, request.model.as_deref())
+            .instrument(span)
+            .await?;
+        tracing::info!("got model lock");
+
+        let response = run_model(lock.deref_mut(), request).await?;
+
+        Ok(Response::new(proto::CompleteResponse {
+            output: response.output,
+            stats: Some(response.stats.into()),
+            logprobs: response.logprobs.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn complete_stream(
+        &self,
+        request: Request<proto::CompleteRequest>,
+    ) -> Result<Response<Self::CompleteStreamStream>, Status> {
+        let proto::CompleteRequest {
+            prompt,
+            model,
+            options,
+        } = request.into_inner();
+        let config: RunConfig = options.unwrap_or_default().into();
+        check_run_config(&config)?;
+
+        let context = self.context.clone();
+        let stream = stream! {
+            let span = tracing::info_span!("complete stream gRPC");
+            let mut lock = match lock_model(&context, model.as_deref()).instrument(span).await {
+                Ok(lock) => lock,
+                Err(error) => {
+                    yield Err(error.into());
+                    return;
+                }
+            };
+            tracing::info!("got model lock");
+
+            let context: &mut Context = lock.deref_mut();
+            let model = match context.models.get(model.as_deref()) {
+                Ok(model) => model,
+                Err(error) => {
+                    yield Err(error.into());
+                    return;
+                }
+            };
+            {
+                let stream = model.run(prompt, config);
+                pin_mut!(stream);
+
+                while let Some(value) = stream.next().await {
+                    match value {
+                        Ok(token) => yield Ok(proto::CompleteChunk {
+                            chunk: Some(Chunk::Token(token)),
+                        }),
+                        Err(error) => {
+                            tracing::error!(%error, "error while streaming completion");
+                            yield Err(Error::from(error).into());
+                            return;
+                        }
+                    }
+                }
+            }
+
+            yield Ok(proto::CompleteChunk {
+                chunk: Some(Chunk::Done(proto::CompleteDone {
+                    stats: Some(model.stats().into()),
+                    logprobs: model.logprobs().iter().cloned().map(Into::into).collect(),
+                })),
+            });
+        };
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[instrument(skip(self))]
+    async fn chat(
+        &self,
+        request: Request<proto::ChatRequest>,
+    ) -> Result<Response<proto::ChatResponse>, Status> {
+        let proto::ChatRequest {
+            session,
+            message,
+            system,
+            model,
+            options,
+        } = request.into_inner();
+        let request = ChatRequest {
+            session,
+            message,
+            system,
+            model,
+            config: options.unwrap_or_default().into(),
+        };
+
+        let span = tracing::info_span!("chat gRPC");
+        let mut lock = lock_chat_model(&self.context, &request)
+            .instrument(span)
+            .await?;
+        tracing::info!("got model lock");
+
+        let response = chat_turn(lock.deref_mut(), request).await?;
+
+        Ok(Response::new(proto::ChatResponse {
+            session: response.session,
+            reply: response.reply,
+            stats: Some(response.stats.into()),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn end_chat(
+        &self,
+        request: Request<proto::EndChatRequest>,
+    ) -> Result<Response<proto::EndChatResponse>, Status> {
+        let session = request.into_inner().session;
+        let mut lock = self.context.lock().await;
+        if lock.sessions.end(&session) {
+            tracing::info!(%session, "ended chat session");
+            Ok(Response::new(proto::EndChatResponse {}))
+        } else {
+            Err(Error::UnknownSession(session.into()).into())
+        }
+    }
+
+    #[instrument(skip(self, request))]
+    async fn embed(
+        &self,
+        request: Request<proto::EmbedRequest>,
+    ) -> Result<Response<proto::EmbedResponse>, Status> {
+        let texts = request.into_inner().input;
+        if texts.is_empty() {
+            return Err(Status::invalid_argument("no input to embed"));
+        }
+
+        let span = tracing::info_span!("embed gRPC");
+        let lock = self.context.lock().instrument(span).await;
+        tracing::info!("got embedder lock");
+
+        let response = embed_texts(&lock, &texts)?;
+
+        Ok(Response::new(proto::EmbedResponse {
+            embeddings: response
+                .embeddings
+                .into_iter()
+                .map(|vector| proto::Embedding { vector })
+                .collect(),
+            tokens: response.tokens as u64,
+        }))
+    }
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        // the response hides and logs the details of server errors
+        let response = error.to_response();
+        let message = format!("{}: {}", response.code, response.message);
+        match error.status() {
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                Status::invalid_argument(message)
+            }
+            StatusCode::NOT_FOUND => Status::not_found(message),
+            StatusCode::PAYLOAD_TOO_LARGE => Status::new(Code::ResourceExhausted, message),
+            StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+/// Unset options use the same defaults as a JSON request
+impl From<proto::RunOptions> for RunConfig {
+    fn from(options: proto::RunOptions) -> Self {
+        let defaults = RunConfig {
+            seed: None,
+            ..Default::default()
+        };
+        RunConfig {
+            sample_len: options
+                .sample_len
+                .map_or(defaults.sample_len, |len| len as usize),
+            seed: options.seed,
+            temperature: options.temperature.unwrap_or(defaults.temperature),
+            top_p: options.top_p.or(defaults.top_p),
+            top_k: options.top_k.map(|k| k as usize),
+            min_p: options.min_p,
+            typical_p: options.typical_p,
+            repeat_penalty: options.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+            repeat_last_n: options
+                .repeat_last_n
+                .map_or(defaults.repeat_last_n, |n| n as usize),
+            stop: options.stop,
+            echo_prompt: options.echo_prompt.unwrap_or(defaults.echo_prompt),
+            speculative: options.speculative.unwrap_or(defaults.speculative),
+            logprobs: options.logprobs.map(|n| n as usize),
+            deterministic: options.deterministic.unwrap_or(defaults.deterministic),
+            grammar: options.grammar,
+            ..defaults
+        }
+    }
+}
+
+impl From<RunStats> for proto::RunStats {
+    fn from(stats: RunStats) -> Self {
+        proto::RunStats {
+            prompt_tokens: stats.prompt_tokens as u64,
+            truncated_tokens: stats.truncated_tokens as u64,
+            cached_tokens: stats.cached_tokens as u64,
+            generated_tokens: stats.generated_tokens as u64,
+            seed: stats.seed,
+        }
+    }
+}
+
+impl From<TokenLogprob> for proto::TokenLogprob {
+    fn from(token: TokenLogprob) -> Self {
+        proto::TokenLogprob {
+            id: token.id,
+            token: token.token,
+            logprob: token.logprob,
+        }
+    }
+}
+
+impl From<TokenLogprobs> for proto::TokenLogprobs {
+    fn from(logprobs: TokenLogprobs) -> Self {
+        proto::TokenLogprobs {
+            token: Some(logprobs.token.into()),
+            top: logprobs.top.into_iter().map(Into::into).collect(),
+        }
+    }
+}
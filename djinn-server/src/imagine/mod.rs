@@ -0,0 +1,102 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use djinn_core::diffusion::{DiffusionScheduler, ImageOptions};
+use image::ImageOutputFormat;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::error::{Error, Result};
+use crate::server::{Context, Json};
+
+pub const ROUTE_IMAGINE: &str = "/imagine";
+
+/// A prompt and the options that override the configured defaults
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImagineRequest {
+    prompt: String,
+    /// What the image shouldn't look like
+    #[serde(default)]
+    negative_prompt: String,
+    #[serde(default)]
+    scheduler: Option<DiffusionScheduler>,
+    #[serde(default)]
+    steps: Option<usize>,
+    #[serde(default)]
+    guidance_scale: Option<f64>,
+    #[serde(default)]
+    width: Option<usize>,
+    #[serde(default)]
+    height: Option<usize>,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+impl ImagineRequest {
+    fn options(&self, defaults: &ImageOptions) -> ImageOptions {
+        ImageOptions {
+            scheduler: self.scheduler.unwrap_or(defaults.scheduler),
+            steps: self.steps.unwrap_or(defaults.steps),
+            guidance_scale: self.guidance_scale.unwrap_or(defaults.guidance_scale),
+            width: self.width.unwrap_or(defaults.width),
+            height: self.height.unwrap_or(defaults.height),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+}
+
+/// Generate an image of a prompt, responding with a PNG
+#[instrument(skip(context, payload))]
+pub async fn imagine(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<ImagineRequest>,
+) -> Result<Response> {
+    let span = tracing::info_span!("imagine");
+    let lock = context.lock().instrument(span).await;
+    tracing::info!("got image generator lock");
+
+    let generator = lock
+        .image_generator
+        .as_ref()
+        .ok_or(Error::ImageGeneratorNotConfigured)?;
+
+    let options = payload.options(generator.defaults());
+    options
+        .validate()
+        .map_err(|error| Error::InvalidRequest(error.to_string()))?;
+
+    let image = generator
+        .generate(&payload.prompt, &payload.negative_prompt, &options)
+        .map_err(Error::ImageGeneration)?;
+    drop(lock);
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(|error| Error::ImageGeneration(error.into()))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_override_defaults() {
+        let request: ImagineRequest =
+            serde_json::from_str(r#"{"prompt": "a lighthouse", "steps": 10, "seed": 7}"#).unwrap();
+        let defaults = ImageOptions::default();
+        let options = request.options(&defaults);
+        assert_eq!(options.steps, 10);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.guidance_scale, defaults.guidance_scale);
+        assert_eq!(options.width, defaults.width);
+    }
+}
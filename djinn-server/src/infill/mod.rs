@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use djinn_core::lm::{beam::Candidate, config::RunConfig, model::RunStats};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::complete::{check_run_config, generate};
+use crate::error::Result;
+use crate::registry::lock_model;
+use crate::server::{Context, Json};
+
+pub const ROUTE_INFILL: &str = "/infill";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InfillRequest {
+    /// The text before the cursor
+    prefix: String,
+    /// The text after the cursor
+    #[serde(default)]
+    suffix: String,
+    /// The name of the model to run, which has to support fill-in-the-middle.
+    /// The default model is used if none is given.
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default, flatten)]
+    config: RunConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InfillResponse {
+    /// The text that goes between the prefix and the suffix
+    output: String,
+    #[serde(flatten)]
+    stats: RunStats,
+    /// The completions found by beam search, best first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    candidates: Vec<Candidate>,
+}
+
+/// Generate the text between a prefix and a suffix, e.g. code at an editor's cursor
+#[instrument(skip(context, payload))]
+pub async fn infill(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<InfillRequest>,
+) -> Result<Json<InfillResponse>> {
+    let InfillRequest {
+        prefix,
+        suffix,
+        model,
+        config,
+    } = payload;
+    check_run_config(&config)?;
+
+    let span = tracing::info_span!("infill");
+    let mut lock = lock_model(&context, model.as_deref())
+        .instrument(span)
+        .await?;
+    let model = lock.models.get(model.as_deref())?;
+
+    let prompt = model.infill_prompt(&prefix, &suffix)?;
+    let config = RunConfig {
+        echo_prompt: false,
+        ..config
+    };
+    let (output, stats, candidates) = generate(model, prompt, config).await?;
+    Ok(Json(InfillResponse {
+        output,
+        stats,
+        candidates,
+    }))
+}
@@ -1,33 +1,131 @@
-use djinn_core::lm::config::ModelConfig;
-use djinn_core::lm::mistral::create_new_context;
+use std::sync::Arc;
+
+pub use audit::AuditConfig;
+use djinn_core::{
+    diffusion::ImageGenerator, embed::EmbeddingContext, llava::Describer,
+    whisper::TranscriptionContext, yolov8::Detector,
+};
+pub use registry::cpu_threads;
+pub use reload::{SetLogFilter, Watch};
 pub use server::{Config, HttpServer};
 use tokio::sync::Mutex;
 use tracing::instrument;
 
-use crate::server::{Context, HttpServerBuilder};
+use crate::audit::AuditLog;
+use crate::chat::ChatSessions;
+use crate::preload::Readiness;
+use crate::registry::ModelRegistry;
+use crate::server::{Context, HttpServerBuilder, RequestQueue};
 
+mod audit;
+mod chat;
 mod complete;
+mod describe;
+mod detect;
+mod embed;
 mod error;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod imagine;
+mod infill;
+mod ollama;
+mod preload;
+mod registry;
+mod reload;
 mod server;
+mod tokenize;
+mod transcribe;
 
-pub use error::{Error, Result};
+pub use error::{Error, ErrorCode, ErrorResponse, Result};
 
 #[instrument]
 pub async fn run_server(config: Config) -> anyhow::Result<()> {
-    let model_path = &config.model_config;
-    tracing::debug!("loading model config at {model_path:?}");
-    let contents = tokio::fs::read_to_string(model_path).await?;
-    let model_config = toml::from_str::<ModelConfig>(&contents)?;
-    let model = create_new_context(&model_config).await?;
+    serve(config, None).await
+}
+
+/// Run the server and apply changes to its config file while it runs
+#[instrument(skip(watch), fields(path = ?watch.path))]
+pub async fn run_server_watching(config: Config, watch: Watch) -> anyhow::Result<()> {
+    serve(config, Some(watch)).await
+}
+
+async fn serve(config: Config, watch: Option<Watch>) -> anyhow::Result<()> {
+    let mut models = ModelRegistry::new(&config);
+    preload::check_names(&config, &config.preload)?;
+    if config.preload.is_empty() {
+        // load the default model up front so the first request doesn't wait
+        models.load(None).await?;
+    }
+
+    let detector = config
+        .detector
+        .as_ref()
+        .map(Detector::from_config)
+        .transpose()?;
 
-    let context = Context { model };
+    let embedder = match &config.embedder {
+        Some(embedder) => Some(EmbeddingContext::from_config(embedder).await?),
+        None => None,
+    };
+
+    let transcriber = match &config.transcriber {
+        Some(transcriber) => Some(TranscriptionContext::from_config(transcriber).await?),
+        None => None,
+    };
+
+    let image_generator = match &config.image_generator {
+        Some(image_generator) => Some(ImageGenerator::from_config(image_generator).await?),
+        None => None,
+    };
+
+    let describer = match &config.describer {
+        Some(describer) => Some(Describer::from_config(describer).await?),
+        None => None,
+    };
+
+    let context = Arc::new(Mutex::new(Context {
+        models,
+        sessions: ChatSessions::default(),
+        detector,
+        embedder,
+        transcriber,
+        image_generator,
+        describer,
+    }));
+
+    let audit = config
+        .audit
+        .as_ref()
+        .map(AuditLog::new)
+        .transpose()?
+        .map(Arc::new);
 
     tracing::debug!("starting server with config: {config:?}");
 
+    let queue = RequestQueue::new(config.max_queued_requests);
+    let watcher =
+        watch.map(|watch| reload::spawn(watch, config.clone(), context.clone(), queue.clone()));
+    let idle_eviction = registry::spawn_idle_eviction(context.clone());
+    let (readiness, preloader) = if config.preload.is_empty() {
+        (Readiness::ready(), None)
+    } else {
+        let readiness = Readiness::not_ready();
+        let preloader = preload::spawn(config.preload.clone(), context.clone(), readiness.clone());
+        (readiness, Some(preloader))
+    };
+
     let server = HttpServerBuilder::default()
+        .audit(audit)
+        .readiness(readiness)
+        .queue(queue)
         .config(config)
-        .context(Mutex::new(context))
+        .context(context)
         .build()?;
 
-    server.start().await
+    let result = server.start().await;
+    for task in watcher.into_iter().chain(preloader) {
+        task.abort();
+    }
+    idle_eviction.abort();
+    result
 }
@@ -0,0 +1,596 @@
+//! Endpoints that speak [Ollama's API](https://github.com/ollama/ollama/blob/main/docs/api.md),
+//! so Ollama clients can use djinn models.
+//!
+//! Model names are the names in [`Config::models`](crate::Config::models),
+//! with an optional `:latest` tag.
+//! `/api/embeddings` only accepts the configured embedder's name, see
+//! [`EmbeddingArchitecture::is_named`](djinn_core::embed::EmbeddingArchitecture::is_named).
+//! Ollama options that djinn doesn't support, like `num_ctx`, are ignored.
+
+use std::{
+    ops::DerefMut,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use djinn_core::lm::{
+    chat::ChatTemplate,
+    config::{ModelConfig, OutputFormat, RunConfig},
+    validate::validate_model_config,
+};
+use futures::{pin_mut, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::embed::embed_texts;
+use crate::error::{Error, ErrorResponse};
+use crate::registry::{lock_model, model_size};
+use crate::server::{Context, Json};
+
+pub const ROUTE_OLLAMA_GENERATE: &str = "/api/generate";
+pub const ROUTE_OLLAMA_CHAT: &str = "/api/chat";
+pub const ROUTE_OLLAMA_TAGS: &str = "/api/tags";
+pub const ROUTE_OLLAMA_SHOW: &str = "/api/show";
+pub const ROUTE_OLLAMA_EMBEDDINGS: &str = "/api/embeddings";
+
+/// The tag Ollama adds to model names
+const LATEST_TAG: &str = ":latest";
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+const fn default_stream() -> bool {
+    true
+}
+
+/// The subset of Ollama's model options that djinn supports
+#[derive(Deserialize, Debug, Default)]
+pub struct Options {
+    /// The number of tokens to generate, -1 or -2 to fill the context
+    num_predict: Option<i64>,
+    seed: Option<u64>,
+    temperature: Option<f64>,
+    top_k: Option<usize>,
+    top_p: Option<f64>,
+    min_p: Option<f64>,
+    typical_p: Option<f64>,
+    repeat_penalty: Option<f32>,
+    /// -1 to use the whole context
+    repeat_last_n: Option<i64>,
+    stop: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GenerateRequest {
+    model: String,
+    prompt: String,
+    #[serde(default)]
+    system: Option<String>,
+    /// Send the prompt as is, without the chat template
+    #[serde(default)]
+    raw: bool,
+    #[serde(default = "default_stream")]
+    stream: bool,
+    /// `"json"` or a JSON schema, both constrain the output to JSON
+    #[serde(default)]
+    format: Option<Value>,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Message {
+    role: String,
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(default = "default_stream")]
+    stream: bool,
+    #[serde(default)]
+    format: Option<Value>,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShowRequest {
+    /// Newer clients send `model`, older ones `name`
+    #[serde(alias = "name")]
+    model: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingsRequest {
+    model: String,
+    prompt: String,
+}
+
+/// The stats sent with the last message of a response
+#[derive(Serialize, Debug)]
+struct FinalStats {
+    done_reason: &'static str,
+    total_duration: u64,
+    load_duration: u64,
+    prompt_eval_count: usize,
+    prompt_eval_duration: u64,
+    eval_count: usize,
+    eval_duration: u64,
+}
+
+/// The response formats of `/api/generate` and `/api/chat`
+#[derive(Clone, Copy, Debug)]
+enum Endpoint {
+    Generate,
+    Chat,
+}
+
+impl Endpoint {
+    /// One message of a response
+    fn message(&self, model: &str, text: String, stats: Option<FinalStats>) -> Value {
+        let mut message = match self {
+            Endpoint::Generate => json!({
+                "model": model,
+                "created_at": rfc3339(SystemTime::now()),
+                "response": text,
+                "done": stats.is_some(),
+            }),
+            Endpoint::Chat => json!({
+                "model": model,
+                "created_at": rfc3339(SystemTime::now()),
+                "message": { "role": "assistant", "content": text },
+                "done": stats.is_some(),
+            }),
+        };
+        if let (Some(message), Ok(Value::Object(stats))) =
+            (message.as_object_mut(), serde_json::to_value(stats))
+        {
+            message.extend(stats);
+        }
+        message
+    }
+}
+
+/// A model run for an Ollama request
+struct Generation {
+    endpoint: Endpoint,
+    /// The model name as the client sent it
+    model: String,
+    prompt: Prompt,
+    options: Options,
+    format: Option<Value>,
+}
+
+/// How to build the prompt once the model's chat template is known
+enum Prompt {
+    Raw(String),
+    Generate {
+        system: Option<String>,
+        prompt: String,
+    },
+    Chat(Vec<Message>),
+}
+
+impl Prompt {
+    fn build(self, template: ChatTemplate) -> Result<String, Error> {
+        match self {
+            Prompt::Raw(prompt) => Ok(prompt),
+            Prompt::Generate { system, prompt } => Ok(system
+                .map(|system| template.system(&system))
+                .unwrap_or_default()
+                + &template.user_turn(&prompt)),
+            Prompt::Chat(messages) => chat_prompt(template, &messages),
+        }
+    }
+}
+
+/// Format a conversation that ends with a user message
+fn chat_prompt(template: ChatTemplate, messages: &[Message]) -> Result<String, Error> {
+    if !messages
+        .last()
+        .is_some_and(|message| message.role == "user")
+    {
+        return Err(Error::InvalidRequest(
+            "the last message must be from the user".to_string(),
+        ));
+    }
+    messages
+        .iter()
+        .map(|message| match message.role.as_str() {
+            "system" => Ok(template.system(&message.content)),
+            "user" => Ok(template.user_turn(&message.content)),
+            "assistant" => Ok(template.reply(&message.content)),
+            role => Err(Error::InvalidRequest(format!(
+                "unsupported message role {role}"
+            ))),
+        })
+        .collect()
+}
+
+/// The registry name of an Ollama model name
+fn model_name(name: &str) -> &str {
+    name.strip_suffix(LATEST_TAG).unwrap_or(name)
+}
+
+fn run_config(options: Options, format: Option<Value>, max_context_len: usize) -> RunConfig {
+    let defaults = RunConfig {
+        seed: None,
+        echo_prompt: false,
+        ..Default::default()
+    };
+    let fill_context = |n: i64| usize::try_from(n).unwrap_or(max_context_len);
+    RunConfig {
+        sample_len: options
+            .num_predict
+            .map_or(defaults.sample_len, fill_context),
+        seed: options.seed,
+        temperature: options.temperature.unwrap_or(defaults.temperature),
+        top_k: options.top_k,
+        top_p: options.top_p.or(defaults.top_p),
+        min_p: options.min_p,
+        typical_p: options.typical_p,
+        repeat_penalty: options.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+        repeat_last_n: options
+            .repeat_last_n
+            .map_or(defaults.repeat_last_n, fill_context),
+        stop: options.stop.unwrap_or_default(),
+        format: format
+            .filter(|format| format == "json" || format.is_object())
+            .map(|_format| OutputFormat::Json),
+        ..defaults
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+/// An error in Ollama's format, with djinn's error code
+fn error_response(error: Error) -> Response {
+    let ErrorResponse { code, message } = error.to_response();
+    (
+        error.status(),
+        axum::Json(json!({ "error": message, "code": code })),
+    )
+        .into_response()
+}
+
+/// Run a generation and send it as one JSON object,
+/// or as a stream of newline delimited JSON objects
+async fn respond(context: Arc<Mutex<Context>>, generation: Generation, stream: bool) -> Response {
+    let body = stream! {
+        let Generation { endpoint, model: model_tag, prompt, options, format } = generation;
+        let started = Instant::now();
+
+        let span = tracing::info_span!("ollama");
+        let mut lock = match lock_model(&context, Some(model_name(&model_tag))).instrument(span).await {
+            Ok(lock) => lock,
+            Err(error) => {
+                yield Err(error);
+                return;
+            }
+        };
+        tracing::info!("got model lock");
+        let context: &mut Context = lock.deref_mut();
+
+        let model = match context.models.get(Some(model_name(&model_tag))) {
+            Ok(model) => model,
+            Err(error) => {
+                yield Err(error);
+                return;
+            }
+        };
+        let load_duration = started.elapsed();
+
+        let prompt = match prompt.build(model.chat_template()) {
+            Ok(prompt) => prompt,
+            Err(error) => {
+                yield Err(error);
+                return;
+            }
+        };
+        let config = run_config(options, format, model.max_context_len());
+        let sample_len = config.sample_len;
+
+        let mut first_token = None;
+        {
+            let run_start = Instant::now();
+            let stream = model.run(prompt, config);
+            pin_mut!(stream);
+            while let Some(token) = stream.next().await {
+                match token {
+                    Ok(token) => {
+                        first_token.get_or_insert_with(|| run_start.elapsed());
+                        yield Ok(endpoint.message(&model_tag, token, None));
+                    }
+                    Err(error) => {
+                        yield Err(error.into());
+                        return;
+                    }
+                }
+            }
+        }
+
+        let total_duration = started.elapsed();
+        let eval_start = load_duration + first_token.unwrap_or_default();
+        let stats = model.stats();
+        let final_stats = FinalStats {
+            done_reason: if stats.generated_tokens >= sample_len { "length" } else { "stop" },
+            total_duration: nanos(total_duration),
+            load_duration: nanos(load_duration),
+            prompt_eval_count: stats.prompt_tokens - stats.cached_tokens,
+            prompt_eval_duration: nanos(first_token.unwrap_or_default()),
+            eval_count: stats.generated_tokens,
+            eval_duration: nanos(total_duration.saturating_sub(eval_start)),
+        };
+        yield Ok(endpoint.message(&model_tag, String::new(), Some(final_stats)));
+    };
+
+    if stream {
+        let lines = body.map(|message| {
+            let message = message.unwrap_or_else(|error| {
+                tracing::error!(%error, "error in Ollama response");
+                let ErrorResponse { code, message } = error.to_response();
+                json!({ "error": message, "code": code })
+            });
+            serde_json::to_string(&message).map(|line| line + "\n")
+        });
+        return (
+            [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+            Body::from_stream(lines),
+        )
+            .into_response();
+    }
+
+    // collect the text of the messages into the last one
+    let mut text = String::new();
+    let mut last = None;
+    pin_mut!(body);
+    while let Some(message) = body.next().await {
+        match message {
+            Ok(message) => {
+                let content = message
+                    .get("response")
+                    .or_else(|| message.pointer("/message/content"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                text.push_str(content);
+                last = Some(message);
+            }
+            Err(error) => return error_response(error),
+        }
+    }
+    let Some(mut message) = last else {
+        return error_response(Error::InvalidRequest("no response".to_string()));
+    };
+    if let Some(content) = message
+        .get_mut("response")
+        .or_else(|| message.pointer_mut("/message/content"))
+    {
+        *content = Value::String(text);
+    }
+    axum::Json(message).into_response()
+}
+
+#[instrument(skip(context))]
+pub async fn generate(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<GenerateRequest>,
+) -> Response {
+    let GenerateRequest {
+        model,
+        prompt,
+        system,
+        raw,
+        stream,
+        format,
+        options,
+    } = payload;
+    let prompt = if raw {
+        Prompt::Raw(prompt)
+    } else {
+        Prompt::Generate { system, prompt }
+    };
+    let generation = Generation {
+        endpoint: Endpoint::Generate,
+        model,
+        prompt,
+        options,
+        format,
+    };
+    respond(context, generation, stream).await
+}
+
+#[instrument(skip(context))]
+pub async fn chat(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<ChatRequest>,
+) -> Response {
+    let ChatRequest {
+        model,
+        messages,
+        stream,
+        format,
+        options,
+    } = payload;
+    let generation = Generation {
+        endpoint: Endpoint::Chat,
+        model,
+        prompt: Prompt::Chat(messages),
+        options,
+        format,
+    };
+    respond(context, generation, stream).await
+}
+
+/// The models that can be run
+#[instrument(skip(context))]
+pub async fn tags(State(context): State<Arc<Mutex<Context>>>) -> Json<Value> {
+    let status = context.lock().await.models.status();
+    let models: Vec<Value> = status
+        .iter()
+        .map(|model| {
+            let name = format!("{}{LATEST_TAG}", model.name);
+            let modified_at = std::fs::metadata(&model.config)
+                .and_then(|metadata| metadata.modified())
+                .map(rfc3339)
+                .unwrap_or_default();
+            let config = read_model_config(&model.config);
+            json!({
+                "name": name,
+                "model": name,
+                "modified_at": modified_at,
+                "size": config.as_ref().map(model_size).unwrap_or_default(),
+                "digest": "",
+                "details": {
+                    "format": config.as_ref().map(|config| {
+                        if config.variant.is_quantized() { "gguf" } else { "safetensors" }
+                    }),
+                    "family": config.as_ref().map(|config| format!("{:?}", config.variant).to_lowercase()),
+                },
+            })
+        })
+        .collect();
+    Json(json!({ "models": models }))
+}
+
+/// Information about a model, the modelfile is the model's djinn config
+#[instrument(skip(context))]
+pub async fn show(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<ShowRequest>,
+) -> Response {
+    let name = model_name(&payload.model);
+    let status = context.lock().await.models.status();
+    let Some(model) = status.iter().find(|model| &*model.name == name) else {
+        return error_response(Error::UnknownModel(name.into()));
+    };
+    let modelfile = std::fs::read_to_string(&model.config).unwrap_or_default();
+    axum::Json(json!({
+        "license": "",
+        "modelfile": modelfile,
+        "parameters": "",
+        "template": "",
+    }))
+    .into_response()
+}
+
+/// Embed a prompt with the configured embedder.
+/// The model can be named by the embedder's variant or Hugging Face repo
+#[instrument(skip(context, payload))]
+pub async fn embeddings(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<EmbeddingsRequest>,
+) -> Response {
+    let lock = context.lock().await;
+    let name = model_name(&payload.model);
+    let Some(embedder) = &lock.embedder else {
+        return error_response(Error::EmbedderNotConfigured);
+    };
+    if !embedder.variant().is_named(name) {
+        return error_response(Error::UnknownModel(name.into()));
+    }
+    match embed_texts(&lock, &[payload.prompt]) {
+        Ok(mut response) => {
+            axum::Json(json!({ "embedding": response.embeddings.pop() })).into_response()
+        }
+        Err(error) => error_response(error),
+    }
+}
+
+fn read_model_config(path: &Path) -> Option<ModelConfig> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    validate_model_config(&contents).ok()
+}
+
+/// Format a time as an RFC 3339 UTC timestamp, e.g. `2024-05-01T12:30:00Z`
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// The date of a number of days since 1970-01-01,
+/// from <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "2023-11-14T22:13:20Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_825_600)),
+            "2000-02-29T12:00:00Z"
+        );
+    }
+
+    #[test]
+    fn strips_the_latest_tag() {
+        assert_eq!(model_name("mistral:latest"), "mistral");
+        assert_eq!(model_name("mistral"), "mistral");
+    }
+
+    #[test]
+    fn formats_chat_messages() {
+        let message = |role: &str, content: &str| Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        };
+        let messages = [
+            message("user", "hi"),
+            message("assistant", "hello"),
+            message("user", "how are you?"),
+        ];
+
+        let prompt = chat_prompt(ChatTemplate::MISTRAL, &messages).unwrap();
+
+        assert_eq!(
+            prompt,
+            "[INST] hi [/INST]hello</s>[INST] how are you? [/INST]"
+        );
+        assert!(chat_prompt(ChatTemplate::MISTRAL, &messages[..2]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Load the models in [`Config::preload`] and warm them up when the server starts.
+//!
+//! The server accepts requests while the models load,
+//! and `/health-check` reports that it isn't ready until they're done.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use djinn_core::lm::config::RunConfig;
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::{
+    complete::generate,
+    error::{Error, Result},
+    registry::lock_model,
+    server::{Config, Context},
+};
+
+/// A short generation that runs each kernel once
+const WARMUP_PROMPT: &str = "Hello";
+const WARMUP_TOKENS: usize = 4;
+
+/// Whether the preloaded models are ready
+#[derive(Clone, Debug)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub fn ready() -> Self {
+        Readiness(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn not_ready() -> Self {
+        Readiness(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set_ready(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness::ready()
+    }
+}
+
+/// Fail before the server starts if a model to preload isn't configured
+pub(crate) fn check_names(config: &Config, models: &[String]) -> Result<()> {
+    let names = crate::registry::model_configs(config);
+    match models
+        .iter()
+        .find(|name| !names.contains_key(name.as_str()))
+    {
+        Some(name) => Err(Error::UnknownModel(name.as_str().into())),
+        None => Ok(()),
+    }
+}
+
+/// Load and warm up `models` one after the other in the background,
+/// then mark the server as ready.
+/// A model that fails is logged and loads again on its next request.
+pub(crate) fn spawn(
+    models: Vec<String>,
+    context: Arc<Mutex<Context>>,
+    readiness: Readiness,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let started = Instant::now();
+        for name in &models {
+            let model_started = Instant::now();
+            match warm_up(name, &context).await {
+                Ok(()) => tracing::info!(
+                    name,
+                    elapsed = ?model_started.elapsed(),
+                    "preloaded model"
+                ),
+                Err(error) => tracing::error!(name, %error, "unable to preload model"),
+            }
+        }
+        readiness.set_ready();
+        tracing::info!(?models, elapsed = ?started.elapsed(), "ready");
+    })
+}
+
+async fn warm_up(name: &str, context: &Mutex<Context>) -> Result<()> {
+    let mut lock = lock_model(context, Some(name)).await?;
+    let model = lock.models.get(Some(name))?;
+    let config = RunConfig {
+        sample_len: WARMUP_TOKENS,
+        seed: None,
+        echo_prompt: false,
+        ..Default::default()
+    };
+    generate(model, WARMUP_PROMPT.to_string(), config).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preloaded_models_must_be_configured() {
+        let mut config = Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into());
+        config
+            .models
+            .insert("phi".to_string(), "model/phi3.toml".into());
+
+        assert!(check_names(&config, &["default".to_string(), "phi".to_string()]).is_ok());
+        assert!(matches!(
+            check_names(&config, &["gemma".to_string()]),
+            Err(Error::UnknownModel(name)) if &*name == "gemma"
+        ));
+    }
+}
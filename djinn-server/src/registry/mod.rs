@@ -0,0 +1,613 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use djinn_core::{
+    hub::cache::{cache_dir, cached_repo},
+    lm::{
+        config::ModelConfig, mistral::create_new_context, model::ModelContext,
+        prefix_cache::PrefixCacheStats, validate::validate_model_config, ModelSource,
+    },
+};
+use serde::Serialize;
+use tokio::{
+    sync::{Mutex, MutexGuard},
+    task::JoinHandle,
+};
+use tracing::instrument;
+
+use crate::{
+    error::{Error, Result},
+    server::{Config, Context},
+};
+
+/// The name of the model loaded from [`Config::model_config`]
+pub const DEFAULT_MODEL_NAME: &str = "default";
+/// The [`Config::keep_alive_mins`] entry for models without their own
+pub const ALL_MODELS: &str = "*";
+/// How often idle models are looked for
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// A collection of named models that can be served.
+/// Models are loaded lazily on first use
+/// and the least recently used models are unloaded
+/// to stay within [`Config::max_loaded_models`] and [`Config::max_model_memory_mb`].
+/// Models are also unloaded when they've been idle for their [`Config::keep_alive_mins`].
+pub struct ModelRegistry {
+    default_model: Arc<str>,
+    configs: HashMap<Arc<str>, PathBuf>,
+    models: HashMap<Arc<str>, LoadedModel>,
+    limits: Limits,
+    keep_alive_mins: HashMap<String, u64>,
+    evictions: HashMap<Arc<str>, Evictions>,
+    /// Held while a model loads without the context lock
+    loading: Arc<Mutex<()>>,
+}
+
+/// How often a model was unloaded to free memory
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Evictions {
+    /// Unloaded after being idle for its keep alive
+    idle: u64,
+    /// Unloaded to make room for another model
+    capacity: u64,
+}
+
+/// How many models, and how much of their weights, can be loaded at once
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Limits {
+    max_models: Option<usize>,
+    max_bytes: Option<u64>,
+}
+
+impl Limits {
+    fn new(config: &Config) -> Self {
+        Limits {
+            max_models: config.max_loaded_models,
+            max_bytes: config
+                .max_model_memory_mb
+                .map(|mb| mb.saturating_mul(BYTES_PER_MB)),
+        }
+    }
+
+    fn exceeded(&self, models: usize, bytes: u64) -> bool {
+        self.max_models.is_some_and(|max| models > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+struct LoadedModel {
+    context: ModelContext,
+    last_used: Instant,
+    /// The size of the model's weight files
+    size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModelStatus {
+    pub(crate) name: Arc<str>,
+    pub(crate) config: PathBuf,
+    loaded: bool,
+    default: bool,
+    /// Only set for loaded models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix_cache: Option<PrefixCacheStats>,
+    /// How long the model stays loaded when it isn't used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive_mins: Option<u64>,
+    /// Seconds since the last request, only set for loaded models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_secs: Option<u64>,
+    evictions: Evictions,
+}
+
+impl ModelRegistry {
+    pub fn new(config: &Config) -> Self {
+        ModelRegistry {
+            default_model: DEFAULT_MODEL_NAME.into(),
+            configs: model_configs(config),
+            models: HashMap::new(),
+            limits: Limits::new(config),
+            keep_alive_mins: config.keep_alive_mins.clone(),
+            evictions: HashMap::new(),
+            loading: Arc::default(),
+        }
+    }
+
+    /// Use the models of a reloaded config.
+    /// Models that were removed or point to another config file are unloaded.
+    /// Returns the names of the models that were added, changed, or removed.
+    pub fn update(&mut self, config: &Config) -> Vec<Arc<str>> {
+        let configs = model_configs(config);
+        let mut changed: Vec<Arc<str>> = changed_models(&self.configs, &configs)
+            .map(|(name, _path)| name.clone())
+            .chain(
+                self.configs
+                    .keys()
+                    .filter(|name| !configs.contains_key(*name))
+                    .cloned(),
+            )
+            .collect();
+        changed.sort();
+        for name in &changed {
+            self.unload(name);
+        }
+
+        self.configs = configs;
+        self.limits = Limits::new(config);
+        self.keep_alive_mins = config.keep_alive_mins.clone();
+        self.evict(0, 0, None);
+        changed
+    }
+
+    /// The names of the models that are loaded from `path`
+    pub fn names_with_config(&self, path: &Path) -> Vec<Arc<str>> {
+        self.configs
+            .iter()
+            .filter(|(_name, config)| config.as_path() == path)
+            .map(|(name, _config)| name.clone())
+            .collect()
+    }
+
+    /// The name of a model, or the default model if no name is given
+    fn name(&self, name: Option<&str>) -> Arc<str> {
+        name.map(Into::into)
+            .unwrap_or_else(|| self.default_model.clone())
+    }
+
+    pub fn is_loaded(&self, name: Option<&str>) -> bool {
+        self.models.contains_key(&self.name(name))
+    }
+
+    /// Make room for a model that isn't loaded yet.
+    /// Returns `None` if it's already loaded.
+    #[instrument(skip(self))]
+    async fn reserve(&mut self, name: Option<&str>) -> Result<Option<PendingLoad>> {
+        let name = self.name(name);
+        if self.models.contains_key(&name) {
+            return Ok(None);
+        }
+        let path = self
+            .configs
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownModel(name.clone()))?;
+
+        let config = read_model_config(&path)
+            .await
+            .map_err(|source| Error::ModelLoad {
+                name: name.clone(),
+                source,
+            })?;
+        // weights that haven't been downloaded yet are measured after loading
+        self.evict(1, model_size(&config), None);
+
+        Ok(Some(PendingLoad { name, path, config }))
+    }
+
+    /// Add a model that was loaded for a [`PendingLoad`]
+    fn insert(&mut self, load: PendingLoad, context: ModelContext) -> Result<()> {
+        let PendingLoad { name, path, config } = load;
+        if self.configs.get(&name) != Some(&path) {
+            return Err(Error::ModelLoad {
+                name,
+                source: anyhow::anyhow!("the model's config changed while it was loading"),
+            });
+        }
+        let size = model_size(&config);
+        tracing::info!(%name, size_mb = size / BYTES_PER_MB, "model loaded");
+
+        self.models.insert(
+            name.clone(),
+            LoadedModel {
+                context,
+                last_used: Instant::now(),
+                size,
+            },
+        );
+        self.evict(0, 0, Some(&*name));
+        if self.limits.exceeded(self.models.len(), self.loaded_bytes()) {
+            tracing::warn!(
+                %name,
+                size_mb = size / BYTES_PER_MB,
+                limits = ?self.limits,
+                "the model is over the memory budget by itself"
+            );
+        }
+        Ok(())
+    }
+
+    /// Load a model if it isn't loaded yet, e.g. before the server starts.
+    /// Requests use [`lock_model`] instead, which doesn't hold the context lock while loading.
+    pub async fn load(&mut self, name: Option<&str>) -> Result<()> {
+        if let Some(load) = self.reserve(name).await? {
+            let context = load.run().await?;
+            self.insert(load, context)?;
+        }
+        Ok(())
+    }
+
+    /// Get a loaded model by name.
+    /// If no name is given, the default model is used.
+    /// Models are loaded by [`lock_model`] or [`ModelRegistry::load`].
+    pub fn get(&mut self, name: Option<&str>) -> Result<&mut ModelContext> {
+        let name = self.name(name);
+        let model = self.models.get_mut(&name).ok_or_else(|| {
+            if self.configs.contains_key(&name) {
+                Error::ModelLoad {
+                    name: name.clone(),
+                    source: anyhow::anyhow!("the model isn't loaded"),
+                }
+            } else {
+                Error::UnknownModel(name.clone())
+            }
+        })?;
+        model.last_used = Instant::now();
+
+        Ok(&mut model.context)
+    }
+
+    /// Drop the weights of a loaded model.
+    /// Returns true if the model was loaded.
+    pub fn unload(&mut self, name: &str) -> bool {
+        let unloaded = self.models.remove(name).is_some();
+        if unloaded {
+            tracing::info!(name, "model unloaded");
+        }
+        unloaded
+    }
+
+    /// Drop the weights of every loaded model.
+    /// Returns the names of the models that were loaded.
+    pub fn unload_all(&mut self) -> Vec<Arc<str>> {
+        let mut names: Vec<Arc<str>> = self.models.drain().map(|(name, _model)| name).collect();
+        names.sort();
+        tracing::info!(?names, "unloaded all models");
+        names
+    }
+
+    /// Unload the models that have been idle for longer than their keep alive.
+    /// Returns their names.
+    pub fn unload_idle(&mut self) -> Vec<Arc<str>> {
+        let mut idle: Vec<Arc<str>> = self
+            .models
+            .iter()
+            .filter(|(name, model)| {
+                self.keep_alive(name)
+                    .is_some_and(|keep_alive| model.last_used.elapsed() >= keep_alive)
+            })
+            .map(|(name, _model)| name.clone())
+            .collect();
+        idle.sort();
+        for name in &idle {
+            tracing::info!(%name, keep_alive = ?self.keep_alive(name), "unloading idle model");
+            self.unload(name);
+            self.evictions.entry(name.clone()).or_default().idle += 1;
+        }
+        idle
+    }
+
+    /// Mark the most recently used model as used now,
+    /// for when a request may have been running it until now
+    fn touch_last_used(&mut self) {
+        if let Some(model) = self.models.values_mut().max_by_key(|model| model.last_used) {
+            model.last_used = Instant::now();
+        }
+    }
+
+    /// How long `name` stays loaded when it isn't used.
+    /// Models without a keep alive stay loaded.
+    fn keep_alive(&self, name: &str) -> Option<Duration> {
+        self.keep_alive_mins
+            .get(name)
+            .or_else(|| self.keep_alive_mins.get(ALL_MODELS))
+            .map(|mins| Duration::from_secs(mins * 60))
+    }
+
+    pub fn status(&self) -> Vec<ModelStatus> {
+        let mut status: Vec<ModelStatus> = self
+            .configs
+            .iter()
+            .map(|(name, config)| {
+                let loaded = self.models.get(name);
+                ModelStatus {
+                    name: name.clone(),
+                    config: config.clone(),
+                    loaded: loaded.is_some(),
+                    default: *name == self.default_model,
+                    prefix_cache: loaded.map(|model| model.context.prefix_cache_stats()),
+                    keep_alive_mins: self
+                        .keep_alive(name)
+                        .map(|keep_alive| keep_alive.as_secs() / 60),
+                    idle_secs: loaded.map(|model| model.last_used.elapsed().as_secs()),
+                    evictions: self.evictions.get(name).copied().unwrap_or_default(),
+                }
+            })
+            .collect();
+        status.sort_by(|a, b| a.name.cmp(&b.name));
+        status
+    }
+
+    /// The total size of the loaded models' weight files
+    fn loaded_bytes(&self) -> u64 {
+        self.models.values().map(|model| model.size).sum()
+    }
+
+    /// Unload least recently used models, other than `keep`,
+    /// until `models` more models of `bytes` more weights fit in the limits
+    fn evict(&mut self, models: usize, bytes: u64, keep: Option<&str>) {
+        while self
+            .limits
+            .exceeded(self.models.len() + models, self.loaded_bytes() + bytes)
+        {
+            let Some(name) = self
+                .models
+                .iter()
+                .filter(|(name, _model)| Some(name.as_ref()) != keep)
+                .min_by_key(|(_name, model)| model.last_used)
+                .map(|(name, _model)| name.clone())
+            else {
+                break;
+            };
+            tracing::info!(
+                %name,
+                limits = ?self.limits,
+                loaded_mb = self.loaded_bytes() / BYTES_PER_MB,
+                "evicting least recently used model"
+            );
+            self.unload(&name);
+            self.evictions.entry(name).or_default().capacity += 1;
+        }
+    }
+}
+
+/// A model that has room made for it and can be loaded without the context lock
+struct PendingLoad {
+    name: Arc<str>,
+    path: PathBuf,
+    config: ModelConfig,
+}
+
+impl PendingLoad {
+    async fn run(&self) -> Result<ModelContext> {
+        create_new_context(&self.config)
+            .await
+            .map_err(|source| Error::ModelLoad {
+                name: self.name.clone(),
+                source,
+            })
+    }
+}
+
+/// Lock the context with the model `name` loaded
+pub(crate) async fn lock_model<'a>(
+    context: &'a Mutex<Context>,
+    name: Option<&str>,
+) -> Result<MutexGuard<'a, Context>> {
+    let lock = context.lock().await;
+    load_model(context, lock, name).await
+}
+
+/// Make sure the model `name` is loaded and return the lock again.
+/// `lock` is released while the weights load, so other requests can run meanwhile.
+pub(crate) async fn load_model<'a>(
+    context: &'a Mutex<Context>,
+    lock: MutexGuard<'a, Context>,
+    name: Option<&str>,
+) -> Result<MutexGuard<'a, Context>> {
+    if lock.models.is_loaded(name) {
+        return Ok(lock);
+    }
+    // one model loads at a time, so a model isn't loaded twice
+    let loading = lock.models.loading.clone();
+    drop(lock);
+    let _loading = loading.lock().await;
+
+    let mut lock = context.lock().await;
+    let Some(load) = lock.models.reserve(name).await? else {
+        // loaded by the request this one waited for
+        return Ok(lock);
+    };
+    drop(lock);
+
+    let model = load.run().await;
+    let mut lock = context.lock().await;
+    lock.models.insert(load, model?)?;
+    Ok(lock)
+}
+
+/// Unload idle models in the background until the task is aborted
+pub(crate) fn spawn_idle_eviction(context: Arc<Mutex<Context>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut lock = match context.try_lock() {
+                Ok(lock) => lock,
+                Err(_) => {
+                    let mut lock = context.lock().await;
+                    // the request that held the lock was running the last model it got
+                    lock.models.touch_last_used();
+                    lock
+                }
+            };
+            lock.models.unload_idle();
+        }
+    })
+}
+
+/// The config file of each model by name, the default model included
+pub(crate) fn model_configs(config: &Config) -> HashMap<Arc<str>, PathBuf> {
+    config
+        .models
+        .iter()
+        .map(|(name, path)| (Arc::from(name.as_str()), path.clone()))
+        .chain(std::iter::once((
+            DEFAULT_MODEL_NAME.into(),
+            config.model_config.clone(),
+        )))
+        .collect()
+}
+
+/// The most CPU threads any of the server's model configs asks for.
+/// Reads the configs synchronously, since it runs before the async runtime starts.
+/// Configs that can't be read are skipped, loading them reports the error
+pub fn cpu_threads(config: &Config) -> Option<NonZeroUsize> {
+    model_configs(config)
+        .values()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|contents| validate_model_config(&contents).ok())
+        .filter_map(|config| config.threads)
+        .max()
+}
+
+/// Models in `new` that aren't in `old` or have another config file
+pub(crate) fn changed_models<'a>(
+    old: &'a HashMap<Arc<str>, PathBuf>,
+    new: &'a HashMap<Arc<str>, PathBuf>,
+) -> impl Iterator<Item = (&'a Arc<str>, &'a PathBuf)> {
+    new.iter()
+        .filter(|(name, path)| old.get(*name) != Some(*path))
+}
+
+#[instrument]
+async fn read_model_config(path: &Path) -> anyhow::Result<ModelConfig> {
+    tracing::debug!("loading model config at {path:?}");
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(validate_model_config(&contents)?)
+}
+
+/// The size of a model's weights on disk, if they have been downloaded
+pub(crate) fn model_size(config: &ModelConfig) -> u64 {
+    match &config.model_source {
+        ModelSource::Files { weight_files, .. } => weight_files
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum(),
+        ModelSource::HuggingFaceHub { .. } => {
+            cached_repo(&cache_dir(), &config.variant.hf_repo_id())
+                .ok()
+                .flatten()
+                .map(|repo| repo.size)
+                .unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_report_changed_models() {
+        let mut config = Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into());
+        config
+            .models
+            .insert("phi".to_string(), "model/phi3.toml".into());
+        config
+            .models
+            .insert("gemma".to_string(), "model/gemma.toml".into());
+        let mut registry = ModelRegistry::new(&config);
+
+        config.models.remove("gemma");
+        config
+            .models
+            .insert("phi".to_string(), "model/local_phi3.toml".into());
+        config
+            .models
+            .insert("coder".to_string(), "model/starcoder.toml".into());
+        let changed = registry.update(&config);
+
+        assert_eq!(
+            changed,
+            vec![Arc::from("coder"), Arc::from("gemma"), Arc::from("phi")]
+        );
+        assert_eq!(
+            registry.names_with_config(Path::new("model/q_mistral.toml")),
+            vec![Arc::from(DEFAULT_MODEL_NAME)]
+        );
+    }
+
+    #[test]
+    fn cpu_threads_are_the_most_any_model_asks_for() {
+        let dir = std::env::temp_dir().join(format!("djinn-threads-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = |threads: Option<usize>| {
+            let threads = threads.map(|threads| format!("threads = {threads}\n"));
+            format!(
+                "variant = \"q_mistral\"\nflash_attn = false\n{}\n[model_source.hugging_face_hub]\nrevision = \"main\"\n",
+                threads.unwrap_or_default()
+            )
+        };
+        std::fs::write(dir.join("default.toml"), model(None)).unwrap();
+        std::fs::write(dir.join("small.toml"), model(Some(4))).unwrap();
+        std::fs::write(dir.join("large.toml"), model(Some(8))).unwrap();
+
+        let mut config = Config::new("[::1]:8080".parse().unwrap(), dir.join("default.toml"));
+        let without = cpu_threads(&config);
+        config
+            .models
+            .insert("small".to_string(), dir.join("small.toml"));
+        config
+            .models
+            .insert("large".to_string(), dir.join("large.toml"));
+        config
+            .models
+            .insert("missing".to_string(), dir.join("missing.toml"));
+        let with = cpu_threads(&config);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(without, None);
+        assert_eq!(with, NonZeroUsize::new(8));
+    }
+
+    #[test]
+    fn limits_models_and_memory() {
+        let mut config = Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into());
+        assert!(!Limits::new(&config).exceeded(100, u64::MAX));
+
+        config.max_loaded_models = Some(2);
+        config.max_model_memory_mb = Some(1024);
+        let limits = Limits::new(&config);
+        assert!(!limits.exceeded(2, 1024 * BYTES_PER_MB));
+        assert!(limits.exceeded(3, 0));
+        assert!(limits.exceeded(1, 1024 * BYTES_PER_MB + 1));
+    }
+
+    #[test]
+    fn keep_alive_falls_back_to_all_models() {
+        let mut config = Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into());
+        let registry = ModelRegistry::new(&config);
+        assert_eq!(registry.keep_alive("phi"), None);
+
+        config.keep_alive_mins.insert("phi".to_string(), 5);
+        config.keep_alive_mins.insert(ALL_MODELS.to_string(), 30);
+        let registry = ModelRegistry::new(&config);
+        assert_eq!(
+            registry.keep_alive("phi"),
+            Some(Duration::from_secs(5 * 60))
+        );
+        assert_eq!(
+            registry.keep_alive(DEFAULT_MODEL_NAME),
+            Some(Duration::from_secs(30 * 60))
+        );
+    }
+
+    #[test]
+    fn get_only_returns_loaded_models() {
+        let config = Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into());
+        let mut registry = ModelRegistry::new(&config);
+        assert!(!registry.is_loaded(None));
+        assert!(matches!(registry.get(None), Err(Error::ModelLoad { .. })));
+        assert!(matches!(
+            registry.get(Some("phi")),
+            Err(Error::UnknownModel(_))
+        ));
+    }
+}
@@ -0,0 +1,374 @@
+//! Reload the server config and the model configs when their files change.
+//!
+//! The log filter, the models, [`Config::max_loaded_models`], [`Config::max_model_memory_mb`],
+//! [`Config::keep_alive_mins`], [`Config::max_queued_requests`],
+//! and the image generation defaults are applied to the running server.
+//! A config that changes anything else, like the address or the audit log,
+//! is rejected and the server keeps running with the old one until it restarts.
+//! Models whose config changed are unloaded and load the new config on their next request.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use djinn_core::{
+    diffusion::{ImageGeneratorConfig, ImageOptions},
+    lm::validate::validate_model_config,
+};
+use serde_json::Value;
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::{
+    registry::{changed_models, model_configs},
+    server::{Config, Context, RequestQueue},
+};
+
+/// How often the files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Applies a log filter like `info,djinn_server=debug`,
+/// e.g. with a `tracing_subscriber` reload handle
+pub type SetLogFilter = Box<dyn Fn(&str) -> anyhow::Result<()> + Send + Sync>;
+
+/// The server config file to watch for changes
+pub struct Watch {
+    pub path: PathBuf,
+    pub set_log_filter: Option<SetLogFilter>,
+}
+
+/// Check the files for changes in the background until the task is aborted
+pub(crate) fn spawn(
+    watch: Watch,
+    config: Config,
+    context: Arc<Mutex<Context>>,
+    queue: RequestQueue,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut watcher = Watcher::new(watch, config, queue);
+        watcher.apply_initial_log_filter();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            watcher.poll(&context).await;
+        }
+    })
+}
+
+struct Watcher {
+    watch: Watch,
+    config: Config,
+    queue: RequestQueue,
+    /// When the server config and each model config were last modified
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Watcher {
+    fn new(watch: Watch, config: Config, queue: RequestQueue) -> Self {
+        let mut watcher = Watcher {
+            watch,
+            config,
+            queue,
+            modified: HashMap::new(),
+        };
+        watcher.modified = watcher.modified_times();
+        watcher
+    }
+
+    /// The server config and each model config with when they were last modified
+    fn modified_times(&self) -> HashMap<PathBuf, Option<SystemTime>> {
+        model_configs(&self.config)
+            .into_values()
+            .chain(std::iter::once(self.watch.path.clone()))
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect()
+    }
+
+    fn apply_initial_log_filter(&self) {
+        if let Some(filter) = &self.config.log_filter {
+            if let Err(error) = self.set_log_filter(filter) {
+                tracing::error!(%error, filter, "unable to set the log filter");
+            }
+        }
+    }
+
+    fn set_log_filter(&self, filter: &str) -> anyhow::Result<()> {
+        match &self.watch.set_log_filter {
+            Some(set_log_filter) => set_log_filter(filter),
+            None => anyhow::bail!("the log filter can't be changed while the server runs"),
+        }
+    }
+
+    async fn poll(&mut self, context: &Mutex<Context>) {
+        let changed: Vec<PathBuf> = self
+            .modified
+            .iter()
+            .filter(|(path, modified)| self::modified(path) != **modified)
+            .map(|(path, _modified)| path.clone())
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        if changed.contains(&self.watch.path) {
+            let path = self.watch.path.clone();
+            match self.reload_config(context).await {
+                Ok(()) => tracing::info!(?path, "reloaded the server config"),
+                Err(error) => tracing::error!(?path, %error, "rejected the server config"),
+            }
+        }
+        for path in changed.iter().filter(|path| **path != self.watch.path) {
+            match self.reload_model_config(path, context).await {
+                Ok(names) => {
+                    tracing::info!(?path, ?names, "reloading models on their next request")
+                }
+                Err(error) => tracing::error!(?path, %error, "rejected the model config"),
+            }
+        }
+
+        // remember the times of rejected files too so they're only reported once
+        self.modified = self.modified_times();
+    }
+
+    async fn reload_config(&mut self, context: &Mutex<Context>) -> anyhow::Result<()> {
+        let contents = tokio::fs::read_to_string(&self.watch.path).await?;
+        let config: Config = toml::from_str(&contents)?;
+
+        let incompatible = incompatible_changes(&self.config, &config)?;
+        if !incompatible.is_empty() {
+            anyhow::bail!("changing {} needs a restart", incompatible.join(", "));
+        }
+        let (old_models, new_models) = (model_configs(&self.config), model_configs(&config));
+        for (name, path) in changed_models(&old_models, &new_models) {
+            validate_file(path)
+                .await
+                .map_err(|error| anyhow::anyhow!("model {name}: {error}"))?;
+        }
+        let image_defaults = changed_image_defaults(&self.config, &config)?;
+        if let Some(defaults) = &image_defaults {
+            defaults
+                .validate()
+                .map_err(|error| anyhow::anyhow!("image_generator.defaults: {error}"))?;
+        }
+        if config.log_filter != self.config.log_filter {
+            if let Some(filter) = &config.log_filter {
+                self.set_log_filter(filter)?;
+                tracing::info!(filter, "changed the log filter");
+            }
+        }
+
+        let mut lock = context.lock().await;
+        let changed = lock.models.update(&config);
+        if !changed.is_empty() {
+            tracing::info!(models = ?changed, "changed models");
+        }
+        if let (Some(defaults), Some(generator)) = (image_defaults, &mut lock.image_generator) {
+            tracing::info!(?defaults, "changed the image generation defaults");
+            generator.set_defaults(defaults);
+        }
+        drop(lock);
+        if config.keep_alive_mins != self.config.keep_alive_mins {
+            tracing::info!(keep_alive_mins = ?config.keep_alive_mins, "changed keep alive");
+        }
+        if config.max_loaded_models != self.config.max_loaded_models {
+            tracing::info!(
+                max_loaded_models = ?config.max_loaded_models,
+                "changed the maximum number of loaded models"
+            );
+        }
+        if config.max_model_memory_mb != self.config.max_model_memory_mb {
+            tracing::info!(
+                max_model_memory_mb = ?config.max_model_memory_mb,
+                "changed the model memory budget"
+            );
+        }
+        if config.max_queued_requests != self.config.max_queued_requests {
+            self.queue.set_max(config.max_queued_requests);
+            tracing::info!(
+                max_queued_requests = ?self.queue.max(),
+                "changed the request queue limit"
+            );
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    /// Unload the models that use a model config file that changed.
+    /// Returns their names.
+    async fn reload_model_config(
+        &self,
+        path: &Path,
+        context: &Mutex<Context>,
+    ) -> anyhow::Result<Vec<Arc<str>>> {
+        validate_file(path).await?;
+        let mut lock = context.lock().await;
+        let names = lock.models.names_with_config(path);
+        for name in &names {
+            lock.models.unload(name);
+        }
+        Ok(names)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+async fn validate_file(path: &Path) -> anyhow::Result<()> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    validate_model_config(&contents)?;
+    Ok(())
+}
+
+/// Settings that are only read when the server starts.
+/// The image generator's defaults are left out, since they can change
+fn fixed_settings(config: &Config) -> anyhow::Result<[(&'static str, Value); 10]> {
+    let image_generator = config
+        .image_generator
+        .as_ref()
+        .map(|generator| ImageGeneratorConfig {
+            defaults: ImageOptions::default(),
+            ..generator.clone()
+        });
+    Ok([
+        ("socker_addr", serde_json::to_value(config.socker_addr)?),
+        ("grpc_addr", serde_json::to_value(config.grpc_addr)?),
+        ("detector", serde_json::to_value(&config.detector)?),
+        ("embedder", serde_json::to_value(&config.embedder)?),
+        ("transcriber", serde_json::to_value(&config.transcriber)?),
+        ("image_generator", serde_json::to_value(image_generator)?),
+        ("describer", serde_json::to_value(&config.describer)?),
+        ("audit", serde_json::to_value(&config.audit)?),
+        (
+            "shutdown_timeout_secs",
+            serde_json::to_value(config.shutdown_timeout_secs)?,
+        ),
+        ("preload", serde_json::to_value(&config.preload)?),
+    ])
+}
+
+/// The image generation defaults of `new`, if they aren't the same as in `old`
+fn changed_image_defaults(old: &Config, new: &Config) -> anyhow::Result<Option<ImageOptions>> {
+    let defaults = |config: &Config| {
+        config
+            .image_generator
+            .as_ref()
+            .map(|generator| generator.defaults.clone())
+    };
+    let (old, new) = (defaults(old), defaults(new));
+    if serde_json::to_value(&old)? == serde_json::to_value(&new)? {
+        return Ok(None);
+    }
+    Ok(new)
+}
+
+/// The names of the settings that changed but can't be applied without a restart
+fn incompatible_changes(old: &Config, new: &Config) -> anyhow::Result<Vec<&'static str>> {
+    Ok(fixed_settings(old)?
+        .into_iter()
+        .zip(fixed_settings(new)?)
+        .filter(|((_name, old), (_, new))| old != new)
+        .map(|((name, _old), _new)| name)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat::ChatSessions, registry::ModelRegistry};
+
+    fn config() -> Config {
+        Config::new("[::1]:8080".parse().unwrap(), "model/q_mistral.toml".into())
+    }
+
+    #[test]
+    fn only_fixed_settings_need_a_restart() {
+        let old = config();
+
+        let mut new = config();
+        new.max_loaded_models = Some(2);
+        new.max_model_memory_mb = Some(8192);
+        new.log_filter = Some("debug".to_string());
+        new.keep_alive_mins.insert("*".to_string(), 10);
+        new.max_queued_requests = Some(4);
+        new.models
+            .insert("phi".to_string(), "model/phi3.toml".into());
+        assert!(incompatible_changes(&old, &new).unwrap().is_empty());
+
+        new.socker_addr = "[::1]:9090".parse().unwrap();
+        new.shutdown_timeout_secs = 1;
+        assert_eq!(
+            incompatible_changes(&old, &new).unwrap(),
+            vec!["socker_addr", "shutdown_timeout_secs"]
+        );
+    }
+
+    fn image_generator(steps: usize) -> Option<ImageGeneratorConfig> {
+        let mut generator: ImageGeneratorConfig = toml::from_str("").unwrap();
+        generator.defaults.steps = steps;
+        Some(generator)
+    }
+
+    #[test]
+    fn image_defaults_change_without_a_restart() {
+        let mut old = config();
+        old.image_generator = image_generator(30);
+
+        let mut new = config();
+        new.image_generator = image_generator(20);
+        assert!(incompatible_changes(&old, &new).unwrap().is_empty());
+        assert_eq!(
+            changed_image_defaults(&old, &new)
+                .unwrap()
+                .map(|defaults| defaults.steps),
+            Some(20)
+        );
+        assert!(changed_image_defaults(&old, &old).unwrap().is_none());
+
+        new.image_generator = None;
+        assert_eq!(
+            incompatible_changes(&old, &new).unwrap(),
+            vec!["image_generator"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reloads_apply_the_queue_limit() {
+        let dir = std::env::temp_dir().join(format!("djinn-reload-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.toml");
+        let old = config();
+        let mut new = config();
+        new.max_queued_requests = Some(4);
+        std::fs::write(&path, toml::to_string(&new).unwrap()).unwrap();
+
+        let context = Mutex::new(Context {
+            models: ModelRegistry::new(&old),
+            sessions: ChatSessions::default(),
+            detector: None,
+            embedder: None,
+            transcriber: None,
+            image_generator: None,
+            describer: None,
+        });
+        let queue = RequestQueue::new(old.max_queued_requests);
+        let watch = Watch {
+            path,
+            set_log_filter: None,
+        };
+        let mut watcher = Watcher::new(watch, old, queue.clone());
+        let result = watcher.reload_config(&context).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert_eq!(queue.max(), Some(4));
+        assert_eq!(watcher.config.max_queued_requests, Some(4));
+    }
+}
@@ -0,0 +1,57 @@
+//! Track response bodies as they are sent
+
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    response::Response,
+};
+use http_body::Frame;
+
+/// Gets told how a response body is sent.
+/// It is dropped with the body, so it can also hold a guard.
+pub trait Track: Send + Unpin + 'static {
+    /// Called with the length of each chunk of the body
+    fn data(&mut self, _len: usize) {}
+    /// Called when the whole body has been sent
+    fn end(&mut self) {}
+}
+
+/// A body that reports its frames to a [`Track`].
+/// Unlike a data stream it keeps the trailers that gRPC responses end with.
+struct Tracked<T> {
+    body: Body,
+    tracker: T,
+}
+
+impl<T: Track> HttpBody for Tracked<T> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.body).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.tracker.data(data.len());
+                }
+            }
+            Some(Err(_)) => {}
+            None => this.tracker.end(),
+        }
+        Poll::Ready(frame)
+    }
+}
+
+/// Report the body of `response` to `tracker`
+pub fn track(response: Response, tracker: impl Track) -> Response {
+    let (parts, body) = response.into_parts();
+    Response::from_parts(parts, Body::new(Tracked { body, tracker }))
+}
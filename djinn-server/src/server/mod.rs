@@ -1,24 +1,66 @@
 use axum::{
-    extract::{FromRequest, MatchedPath},
+    extract::{connect_info::IntoMakeServiceWithConnectInfo, FromRequest, MatchedPath, State},
     handler::HandlerWithoutStateExt,
     http::{Request, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::{get, post, IntoMakeService},
+    routing::{delete, get, post},
     Router,
 };
 use derive_builder::Builder;
 use derive_new::new;
-use djinn_core::lm::model::ModelContext;
+use djinn_core::{
+    diffusion::{ImageGenerator, ImageGeneratorConfig},
+    embed::{EmbeddingConfig, EmbeddingContext},
+    llava::{Describer, DescriberConfig},
+    whisper::{TranscriberConfig, TranscriptionContext},
+    yolov8::{Detector, DetectorConfig},
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    fmt::Display, future::IntoFuture, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration,
+    collections::HashMap,
+    fmt::Display,
+    future::IntoFuture,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::sync::Mutex;
 use tower::ServiceExt;
 use tower_http::{services::ServeDir, trace::TraceLayer};
 use tracing::{instrument, Instrument, Level, Span};
 
-use crate::complete::ROUTE_COMPLETE;
+use crate::audit::{audit_requests, AuditConfig, AuditLog};
+use crate::chat::{ChatSessions, ROUTE_CHAT, ROUTE_CHAT_SESSION};
+use crate::complete::{ROUTE_COMPLETE, ROUTE_COMPLETE_BATCH, ROUTE_COMPLETE_STREAM};
+use crate::describe::ROUTE_DESCRIBE;
+use crate::detect::ROUTE_DETECT;
+use crate::embed::ROUTE_EMBED;
+use crate::imagine::ROUTE_IMAGINE;
+use crate::infill::ROUTE_INFILL;
+use crate::ollama::{
+    ROUTE_OLLAMA_CHAT, ROUTE_OLLAMA_EMBEDDINGS, ROUTE_OLLAMA_GENERATE, ROUTE_OLLAMA_SHOW,
+    ROUTE_OLLAMA_TAGS,
+};
+use crate::preload::Readiness;
+use crate::registry::{ModelRegistry, ModelStatus};
+use crate::tokenize::ROUTE_TOKENIZE;
+use crate::transcribe::ROUTE_TRANSCRIBE;
+
+use self::queue::limit_queue;
+pub(crate) use self::queue::RequestQueue;
+use self::shutdown::{count_requests, shutdown_signal, RequestCounter};
+
+pub(crate) mod body;
+mod queue;
+mod shutdown;
+
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+const fn default_shutdown_timeout_secs() -> u64 {
+    DEFAULT_SHUTDOWN_TIMEOUT_SECS
+}
 
 #[derive(FromRequest)]
 #[from_request(via(axum::Json), rejection(crate::error::Error))]
@@ -36,7 +78,80 @@ where
 #[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub socker_addr: SocketAddr,
+    /// The config of the default model
     pub model_config: PathBuf,
+    /// The maximum number of models to keep loaded at once.
+    /// The least recently used model is unloaded to make room for a new one.
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_loaded_models: Option<usize>,
+    /// The memory budget for loaded models in MiB,
+    /// measured by the size of their weight files.
+    /// The least recently used models are unloaded to keep the total under it.
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_model_memory_mb: Option<u64>,
+    /// Additional named model configs that can be requested by name
+    #[new(default)]
+    #[serde(default)]
+    pub models: HashMap<String, PathBuf>,
+    /// Enables the object detection endpoint
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detector: Option<DetectorConfig>,
+    /// Enables the embedding endpoint
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedder: Option<EmbeddingConfig>,
+    /// Enables the transcription endpoint
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcriber: Option<TranscriberConfig>,
+    /// Enables the image generation endpoint
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_generator: Option<ImageGeneratorConfig>,
+    /// Enables the image description endpoint
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub describer: Option<DescriberConfig>,
+    /// How long to wait for in flight requests to finish on shutdown
+    #[new(value = "DEFAULT_SHUTDOWN_TIMEOUT_SECS")]
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Serve the gRPC API on this address.
+    /// Needs the `grpc` feature
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc_addr: Option<SocketAddr>,
+    /// Enables the audit log
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditConfig>,
+    /// A log filter like `info,djinn_server=debug`.
+    /// Applied when the server runs from a config file that's watched for changes
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_filter: Option<String>,
+    /// The maximum number of requests for the models that can run or wait at once.
+    /// More are rejected with a `queue_full` error
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queued_requests: Option<usize>,
+    /// Models to load and warm up with a short generation when the server starts,
+    /// e.g. `["default", "phi"]`.
+    /// `/health-check` responds with 503 until they're ready.
+    /// If empty, the default model is loaded before the server starts listening
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preload: Vec<String>,
+    /// Unload models after they've been idle for this many minutes, by model name.
+    /// The `*` entry applies to models without their own.
+    /// Models without an entry stay loaded until [`Config::max_loaded_models`]
+    /// or [`Config::max_model_memory_mb`] evicts them
+    #[new(default)]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub keep_alive_mins: HashMap<String, u64>,
 }
 
 #[derive(Builder)]
@@ -45,32 +160,178 @@ pub struct HttpServer {
     config: Arc<Config>,
     #[builder(setter(into))]
     context: Arc<Mutex<Context>>,
+    #[builder(default)]
+    audit: Option<Arc<AuditLog>>,
+    #[builder(default)]
+    readiness: Readiness,
+    /// Shared with the config reload, which changes its limit
+    #[builder(default)]
+    queue: RequestQueue,
 }
 
 pub struct Context {
-    pub model: ModelContext,
+    pub models: ModelRegistry,
+    pub sessions: ChatSessions,
+    pub detector: Option<Detector>,
+    pub embedder: Option<EmbeddingContext>,
+    pub transcriber: Option<TranscriptionContext>,
+    pub image_generator: Option<ImageGenerator>,
+    pub describer: Option<Describer>,
+}
+
+impl Context {
+    /// Drop all model weights, freeing their memory.
+    /// Returns the names of the language models that were loaded.
+    fn unload(&mut self) -> Vec<Arc<str>> {
+        let models = self.models.unload_all();
+        self.sessions = ChatSessions::default();
+        self.detector = None;
+        self.embedder = None;
+        self.transcriber = None;
+        self.image_generator = None;
+        self.describer = None;
+        models
+    }
 }
 
-#[instrument]
-async fn health_check_handler() -> &'static str {
+#[instrument(skip(readiness))]
+async fn health_check_handler(State(readiness): State<Readiness>) -> (StatusCode, &'static str) {
     tracing::debug!("health checked");
-    "OK"
+    if readiness.is_ready() {
+        (StatusCode::OK, "OK")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "loading models")
+    }
+}
+
+#[instrument(skip(context))]
+async fn models_handler(State(context): State<Arc<Mutex<Context>>>) -> Json<Vec<ModelStatus>> {
+    let lock = context.lock().await;
+    Json(lock.models.status())
 }
 
 async fn not_found() -> (StatusCode, &'static str) {
     (StatusCode::NOT_FOUND, "Not found")
 }
 
-fn build_service(context: Arc<Mutex<Context>>) -> IntoMakeService<Router> {
-    let router = Router::new()
-        .route(
-            &ServiceRoutes::HealthCheck.to_string(),
-            get(health_check_handler),
-        )
+/// The middleware shared by the HTTP and gRPC APIs
+struct Layers {
+    requests: RequestCounter,
+    audit: Option<Arc<AuditLog>>,
+    queue: RequestQueue,
+}
+
+impl Layers {
+    /// Limit the queue of `model_routes`, which wait for the models,
+    /// and audit them and `routes`
+    fn route_layers<S>(&self, model_routes: Router<S>, routes: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let mut router = model_routes
+            .route_layer(middleware::from_fn_with_state(
+                self.queue.clone(),
+                limit_queue,
+            ))
+            .merge(routes);
+        if let Some(audit) = &self.audit {
+            router = router.route_layer(middleware::from_fn_with_state(
+                audit.clone(),
+                audit_requests,
+            ));
+        }
+        router
+    }
+
+    /// Count the requests of `router` for the shutdown
+    fn count_requests<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        router.layer(middleware::from_fn_with_state(
+            self.requests.clone(),
+            count_requests,
+        ))
+    }
+}
+
+fn build_service(
+    context: Arc<Mutex<Context>>,
+    layers: &Layers,
+    readiness: Readiness,
+) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    let model_routes = Router::new()
         .route(
             &ServiceRoutes::Complete.to_string(),
             post(crate::complete::complete),
         )
+        .route(
+            &ServiceRoutes::CompleteStream.to_string(),
+            post(crate::complete::complete_stream),
+        )
+        .route(
+            &ServiceRoutes::CompleteBatch.to_string(),
+            post(crate::complete::complete_batch),
+        )
+        .route(&ServiceRoutes::Chat.to_string(), post(crate::chat::chat))
+        .route(
+            &ServiceRoutes::ChatSession.to_string(),
+            delete(crate::chat::end_chat),
+        )
+        .route(
+            &ServiceRoutes::Detect.to_string(),
+            post(crate::detect::detect),
+        )
+        .route(&ServiceRoutes::Embed.to_string(), post(crate::embed::embed))
+        .route(
+            &ServiceRoutes::Transcribe.to_string(),
+            post(crate::transcribe::transcribe),
+        )
+        .route(
+            &ServiceRoutes::Imagine.to_string(),
+            post(crate::imagine::imagine),
+        )
+        .route(
+            &ServiceRoutes::Describe.to_string(),
+            post(crate::describe::describe),
+        )
+        .route(
+            &ServiceRoutes::Tokenize.to_string(),
+            post(crate::tokenize::tokenize),
+        )
+        .route(
+            &ServiceRoutes::Infill.to_string(),
+            post(crate::infill::infill),
+        )
+        .route(
+            &ServiceRoutes::OllamaGenerate.to_string(),
+            post(crate::ollama::generate),
+        )
+        .route(
+            &ServiceRoutes::OllamaChat.to_string(),
+            post(crate::ollama::chat),
+        )
+        .route(
+            &ServiceRoutes::OllamaTags.to_string(),
+            get(crate::ollama::tags),
+        )
+        .route(
+            &ServiceRoutes::OllamaShow.to_string(),
+            post(crate::ollama::show),
+        )
+        .route(
+            &ServiceRoutes::OllamaEmbeddings.to_string(),
+            post(crate::ollama::embeddings),
+        );
+    let routes = Router::new()
+        .route(
+            &ServiceRoutes::HealthCheck.to_string(),
+            get(health_check_handler).with_state(readiness),
+        )
+        .route(&ServiceRoutes::Models.to_string(), get(models_handler));
+
+    let router = layers
+        .route_layers(model_routes, routes)
         .fallback_service(
             ServeDir::new("./djinn-server/assets")
                 .not_found_service(not_found.into_service())
@@ -110,12 +371,31 @@ fn build_service(context: Arc<Mutex<Context>>) -> IntoMakeService<Router> {
         )
         .with_state(context);
 
-    router.into_make_service()
+    layers
+        .count_requests(router)
+        .into_make_service_with_connect_info::<SocketAddr>()
 }
 
 enum ServiceRoutes {
     HealthCheck,
     Complete,
+    CompleteStream,
+    CompleteBatch,
+    Chat,
+    ChatSession,
+    Models,
+    Detect,
+    Embed,
+    Transcribe,
+    Imagine,
+    Describe,
+    Tokenize,
+    Infill,
+    OllamaGenerate,
+    OllamaChat,
+    OllamaTags,
+    OllamaShow,
+    OllamaEmbeddings,
 }
 
 impl Display for ServiceRoutes {
@@ -123,6 +403,23 @@ impl Display for ServiceRoutes {
         match self {
             ServiceRoutes::HealthCheck => write!(f, "/health-check"),
             ServiceRoutes::Complete => write!(f, "{}", ROUTE_COMPLETE),
+            ServiceRoutes::CompleteStream => write!(f, "{}", ROUTE_COMPLETE_STREAM),
+            ServiceRoutes::CompleteBatch => write!(f, "{}", ROUTE_COMPLETE_BATCH),
+            ServiceRoutes::Chat => write!(f, "{}", ROUTE_CHAT),
+            ServiceRoutes::ChatSession => write!(f, "{}", ROUTE_CHAT_SESSION),
+            ServiceRoutes::Models => write!(f, "/models"),
+            ServiceRoutes::Detect => write!(f, "{}", ROUTE_DETECT),
+            ServiceRoutes::Embed => write!(f, "{}", ROUTE_EMBED),
+            ServiceRoutes::Transcribe => write!(f, "{}", ROUTE_TRANSCRIBE),
+            ServiceRoutes::Imagine => write!(f, "{}", ROUTE_IMAGINE),
+            ServiceRoutes::Describe => write!(f, "{}", ROUTE_DESCRIBE),
+            ServiceRoutes::Tokenize => write!(f, "{}", ROUTE_TOKENIZE),
+            ServiceRoutes::Infill => write!(f, "{}", ROUTE_INFILL),
+            ServiceRoutes::OllamaGenerate => write!(f, "{}", ROUTE_OLLAMA_GENERATE),
+            ServiceRoutes::OllamaChat => write!(f, "{}", ROUTE_OLLAMA_CHAT),
+            ServiceRoutes::OllamaTags => write!(f, "{}", ROUTE_OLLAMA_TAGS),
+            ServiceRoutes::OllamaShow => write!(f, "{}", ROUTE_OLLAMA_SHOW),
+            ServiceRoutes::OllamaEmbeddings => write!(f, "{}", ROUTE_OLLAMA_EMBEDDINGS),
         }
     }
 }
@@ -137,13 +434,108 @@ impl HttpServer {
         let server_span = tracing::span!(Level::INFO, "server span");
         tracing::info!("starting server on {socket_addr}");
 
-        axum::serve(listener, build_service(context))
-            .into_future()
-            .instrument(server_span)
-            .await?;
+        let started = Instant::now();
+
+        let layers = Layers {
+            requests: RequestCounter::default(),
+            audit: self.audit,
+            // shared by both APIs, since they wait for the same models
+            queue: self.queue,
+        };
+        let requests = layers.requests.clone();
+
+        #[cfg(feature = "grpc")]
+        let grpc = self.config.grpc_addr.map(|addr| {
+            let router = crate::grpc::router(context.clone(), self.readiness.clone());
+            let router = layers.count_requests(layers.route_layers(router, Router::new()));
+            tokio::spawn(async move {
+                if let Err(error) = crate::grpc::serve(addr, router, shutdown_signal()).await {
+                    tracing::error!(%error, "gRPC server failed");
+                }
+            })
+        });
+        #[cfg(not(feature = "grpc"))]
+        if self.config.grpc_addr.is_some() {
+            tracing::warn!("built without the grpc feature, not serving gRPC");
+        }
+
+        // the shutdown deadline, set when the signal arrives
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(None);
+        let serve = axum::serve(
+            listener,
+            build_service(context.clone(), &layers, self.readiness),
+        )
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            shutdown_tx.send_replace(Some(tokio::time::Instant::now() + timeout));
+        })
+        .into_future()
+        .instrument(server_span);
+
+        // stop waiting for in flight requests at the deadline
+        let in_flight = requests.clone();
+        let mut signaled = shutdown_rx.clone();
+        let drain_deadline = async move {
+            let Some(deadline) = signaled
+                .wait_for(Option::is_some)
+                .await
+                .ok()
+                .and_then(|deadline| *deadline)
+            else {
+                // the server stopped without a signal
+                return std::future::pending::<()>().await;
+            };
+            tracing::info!(
+                in_flight = in_flight.in_flight(),
+                "no longer accepting requests, waiting up to {timeout:?} for in flight requests"
+            );
+            tokio::time::sleep_until(deadline).await;
+        };
+
+        let mut drained = true;
+        tokio::select! {
+            result = serve => result?,
+            _ = drain_deadline => {
+                drained = false;
+                tracing::warn!(
+                    in_flight = requests.in_flight(),
+                    "timed out waiting for in flight requests"
+                );
+            }
+        }
 
         tracing::info!("HTTP server shutdown");
 
+        // the rest of the shutdown shares the drain's deadline,
+        // so the timeout bounds the whole shutdown
+        let deadline =
+            (*shutdown_rx.borrow()).unwrap_or_else(|| tokio::time::Instant::now() + timeout);
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = grpc {
+            if tokio::time::timeout_at(deadline, grpc).await.is_err() {
+                tracing::warn!("timed out waiting for gRPC requests");
+            }
+        }
+
+        let unloaded = match tokio::time::timeout_at(deadline, context.lock()).await {
+            Ok(mut lock) => Some(lock.unload()),
+            Err(_) => {
+                tracing::warn!("a request is still holding the models, not unloading them");
+                None
+            }
+        };
+
+        tracing::info!(
+            uptime = ?started.elapsed(),
+            handled_requests = requests.handled(),
+            aborted_requests = requests.in_flight(),
+            drained,
+            unloaded_models = ?unloaded,
+            "shutdown complete"
+        );
+
         Ok(())
     }
 }
@@ -0,0 +1,112 @@
+//! Reject requests for the models when too many are waiting
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::body::{track, Track};
+use crate::error::Error;
+
+/// Limits how many requests can run or wait for the models at once.
+/// The limit can change while the server runs, e.g. when its config is reloaded
+#[derive(Clone, Debug)]
+pub struct RequestQueue {
+    queued: Arc<AtomicUsize>,
+    /// The maximum, [`usize::MAX`] for no limit
+    max: Arc<AtomicUsize>,
+}
+
+impl RequestQueue {
+    pub fn new(max: Option<usize>) -> Self {
+        RequestQueue {
+            queued: Arc::default(),
+            max: Arc::new(AtomicUsize::new(max.unwrap_or(usize::MAX))),
+        }
+    }
+
+    /// The limit, if there is one
+    pub fn max(&self) -> Option<usize> {
+        let max = self.max.load(Ordering::Relaxed);
+        (max != usize::MAX).then_some(max)
+    }
+
+    /// Change the limit for the requests that come after.
+    /// Requests that are already over a lowered limit still finish
+    pub fn set_max(&self, max: Option<usize>) {
+        self.max.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Take a place in the queue, or return the limit if it's full
+    fn enter(&self) -> Result<Place, usize> {
+        let max = self.max.load(Ordering::Relaxed);
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < max).then_some(queued + 1)
+            })
+            .map(|_queued| Place(self.queued.clone()))
+            .map_err(|_queued| max)
+    }
+}
+
+/// Without a limit, like when the config doesn't set one
+impl Default for RequestQueue {
+    fn default() -> Self {
+        RequestQueue::new(None)
+    }
+}
+
+/// A request's place in the [`RequestQueue`], given up when it's dropped
+struct Place(Arc<AtomicUsize>);
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Middleware that responds with [`Error::QueueFull`] when the [`RequestQueue`] is full.
+/// A request keeps its place until its response body is sent, so streams count too.
+pub async fn limit_queue(
+    State(queue): State<RequestQueue>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let place = match queue.enter() {
+        Ok(place) => place,
+        Err(max) => {
+            tracing::warn!(max, "request queue is full");
+            return Error::QueueFull { max }.into_response();
+        }
+    };
+    track(next.run(request).await, place)
+}
+
+/// The place is given up when the body is dropped
+impl Track for Place {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_changes_apply_to_new_requests() {
+        let queue = RequestQueue::new(Some(2));
+        let first = queue.enter().unwrap();
+        let _second = queue.enter().unwrap();
+        assert_eq!(queue.enter().err(), Some(2));
+
+        queue.set_max(Some(1));
+        drop(first);
+        assert_eq!(queue.enter().err(), Some(1));
+
+        queue.set_max(None);
+        assert!(queue.enter().is_ok());
+    }
+}
@@ -0,0 +1,92 @@
+//! Graceful shutdown on SIGINT and SIGTERM
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use super::body::{track, Track};
+
+/// Counts requests so the shutdown summary can report them
+#[derive(Clone, Debug, Default)]
+pub struct RequestCounter {
+    in_flight: Arc<AtomicUsize>,
+    handled: Arc<AtomicU64>,
+}
+
+impl RequestCounter {
+    /// Requests that haven't finished sending their response body yet
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Requests whose response body was sent to the end
+    pub fn handled(&self) -> u64 {
+        self.handled.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in flight count when a request is done or dropped
+struct InFlight(RequestCounter);
+
+impl Track for InFlight {
+    fn end(&mut self) {
+        self.0.handled.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware that tracks requests in a [`RequestCounter`].
+/// A request stays in flight until its response body is done,
+/// so streaming responses count until they stop generating
+pub async fn count_requests(
+    State(counter): State<RequestCounter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    counter.in_flight.fetch_add(1, Ordering::Relaxed);
+    let in_flight = InFlight(counter);
+    // keep the guard alive until the body is done or dropped
+    track(next.run(request).await, in_flight)
+}
+
+/// Resolves when the process receives SIGINT (ctrl-c) or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            tracing::error!(%error, "unable to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(error) => {
+                tracing::error!(%error, "unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received ctrl-c"),
+        _ = terminate => tracing::info!("received SIGTERM"),
+    }
+}
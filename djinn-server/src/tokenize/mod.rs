@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use djinn_core::lm::tokens::encode;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::error::Result;
+use crate::registry::lock_model;
+use crate::server::{Context, Json};
+
+pub const ROUTE_TOKENIZE: &str = "/tokenize";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenizeRequest {
+    text: String,
+    /// The name of the model whose tokenizer is used.
+    /// The default model is used if none is given.
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenizeResponse {
+    /// The token IDs of the text as it's encoded in a prompt
+    tokens: Vec<u32>,
+    count: usize,
+    /// The most tokens the model can attend to, prompt and output together
+    max_context_len: usize,
+}
+
+/// Encode text with a model's tokenizer, e.g. to check that a prompt fits its context
+#[instrument(skip(context, payload))]
+pub async fn tokenize(
+    State(context): State<Arc<Mutex<Context>>>,
+    Json(payload): Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>> {
+    let span = tracing::info_span!("tokenize");
+    let mut lock = lock_model(&context, payload.model.as_deref())
+        .instrument(span)
+        .await?;
+    let model = lock.models.get(payload.model.as_deref())?;
+
+    let tokens = encode(model.tokenizer(), &payload.text)?;
+    Ok(Json(TokenizeResponse {
+        count: tokens.len(),
+        tokens,
+        max_context_len: model.max_context_len(),
+    }))
+}
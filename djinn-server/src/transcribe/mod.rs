@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::extract::{Multipart, State};
+use djinn_core::whisper::{decode_audio, TranscribeOptions, Transcription};
+use tokio::sync::Mutex;
+use tracing::{instrument, Instrument};
+
+use crate::detect::parse_field;
+use crate::error::{Error, Result};
+use crate::server::{Context, Json};
+
+pub const ROUTE_TRANSCRIBE: &str = "/transcribe";
+
+/// Multipart form field names accepted by [`transcribe`]
+const FIELD_AUDIO: &str = "audio";
+const FIELD_LANGUAGE: &str = "language";
+const FIELD_TRANSLATE: &str = "translate";
+
+#[derive(Debug)]
+struct TranscribeRequest {
+    /// Mono samples at Whisper's sample rate
+    samples: Vec<f32>,
+    options: TranscribeOptions,
+}
+
+/// Transcribe an audio file uploaded as multipart form data.
+///
+/// The `audio` field is required.
+/// `language` and `translate` are optional.
+#[instrument(skip(context, multipart))]
+pub async fn transcribe(
+    State(context): State<Arc<Mutex<Context>>>,
+    multipart: Multipart,
+) -> Result<Json<Transcription>> {
+    let request = read_request(multipart).await?;
+
+    let span = tracing::info_span!("transcribe");
+    let mut lock = context.lock().instrument(span).await;
+    tracing::info!("got transcriber lock");
+
+    let transcriber = lock
+        .transcriber
+        .as_mut()
+        .ok_or(Error::TranscriberNotConfigured)?;
+
+    transcriber
+        .check_options(&request.options)
+        .map_err(|error| Error::InvalidRequest(error.to_string()))?;
+    let transcription = transcriber
+        .transcribe(&request.samples, &request.options)
+        .map_err(Error::Transcription)?;
+    tracing::debug!(
+        segments = transcription.segments.len(),
+        language = ?transcription.language,
+        "transcribed audio"
+    );
+
+    Ok(Json(transcription))
+}
+
+async fn read_request(mut multipart: Multipart) -> Result<TranscribeRequest> {
+    let mut samples = None;
+    let mut options = TranscribeOptions::default();
+
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            FIELD_AUDIO => {
+                let bytes = field.bytes().await?;
+                // decoding is CPU bound and shouldn't hold up the runtime
+                let decoded = tokio::task::spawn_blocking(move || decode_audio(bytes))
+                    .await
+                    .map_err(|error| Error::Transcription(error.into()))?
+                    .map_err(|error| {
+                        Error::InvalidRequest(format!("unable to decode audio: {error}"))
+                    })?;
+                samples = Some(decoded);
+            }
+            FIELD_LANGUAGE => {
+                let language = field.text().await?.trim().to_string();
+                options.language = (!language.is_empty()).then_some(language);
+            }
+            FIELD_TRANSLATE => options.translate = parse_field(&name, &field.text().await?)?,
+            _ => tracing::warn!(name, "ignoring unknown field"),
+        }
+    }
+
+    let samples =
+        samples.ok_or_else(|| Error::InvalidRequest(format!("missing `{FIELD_AUDIO}` field")))?;
+
+    Ok(TranscribeRequest { samples, options })
+}
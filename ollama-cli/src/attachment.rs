@@ -0,0 +1,246 @@
+//! Local files and captured shell commands attached to chat prompts.
+//! Each attachment is sent in a fenced block before the prompt
+//! and the session records which prompt it was attached to.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, fs_ext::read_file_to_string, shell::Capture};
+
+/// Files are cut off after this many characters unless the config says otherwise
+pub const DEFAULT_MAX_CHARS: usize = 32_000;
+
+/// Where an attachment came from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    File(PathBuf),
+    /// A command captured by djinn's shell hook
+    Command {
+        command: String,
+        exit_code: i32,
+    },
+}
+
+/// A file or command output read for the next prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    source: Source,
+    contents: Arc<str>,
+    /// Characters in the whole file or output
+    chars: usize,
+}
+
+impl Attachment {
+    /// Read `path`, keeping at most `max_chars` characters of it
+    pub fn read(path: impl AsRef<Path>, max_chars: usize) -> Result<Self> {
+        let contents = read_file_to_string(&path)?;
+        Ok(Attachment::new(
+            Source::File(path.as_ref().into()),
+            &contents,
+            max_chars,
+        ))
+    }
+
+    /// The output of a captured command, keeping at most `max_chars` characters of it
+    pub fn command(capture: Capture, max_chars: usize) -> Self {
+        Attachment::new(
+            Source::Command {
+                command: capture.command,
+                exit_code: capture.exit_code,
+            },
+            &capture.output,
+            max_chars,
+        )
+    }
+
+    fn new(source: Source, contents: &str, max_chars: usize) -> Self {
+        let chars = contents.chars().count();
+        let contents = match contents.char_indices().nth(max_chars) {
+            Some((end, _)) => &contents[..end],
+            None => contents,
+        };
+        Attachment {
+            source,
+            contents: contents.into(),
+            chars,
+        }
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.contents.chars().count() < self.chars
+    }
+
+    /// The file's name, falling back to the whole path, or the command
+    pub fn name(&self) -> String {
+        match &self.source {
+            Source::File(path) => path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            Source::Command { command, .. } => format!("$ {command}"),
+        }
+    }
+
+    /// The contents in a fence under a header with the path or command
+    pub fn to_markdown(&self) -> String {
+        let (header, language) = match &self.source {
+            Source::File(path) => (
+                format!("File `{}`", path.display()),
+                path.extension()
+                    .map(|extension| extension.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            Source::Command { command, exit_code } => (
+                format!("Output of `{command}`, which exited with code {exit_code}"),
+                "console".to_string(),
+            ),
+        };
+        let header = if self.is_truncated() {
+            format!(
+                "{header}, the first {} of {} characters:",
+                self.contents.chars().count(),
+                self.chars
+            )
+        } else {
+            format!("{header}:")
+        };
+        format!("{header}\n{}", fenced(&language, &self.contents))
+    }
+
+    /// Record that the file was attached to the message at `message`
+    pub fn attached_to(&self, message: usize) -> AttachedFile {
+        AttachedFile {
+            message,
+            source: self.source.clone(),
+            chars: self.chars,
+            truncated: self.is_truncated(),
+        }
+    }
+}
+
+/// A file or command attached to a prompt in a saved session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachedFile {
+    /// The index of the prompt in the session's messages
+    pub message: usize,
+    pub source: Source,
+    /// Characters in the whole file or output
+    pub chars: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// `contents` in a fence that's longer than any backtick run in them
+pub fn fenced(language: &str, contents: &str) -> String {
+    let longest_run = contents
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let contents = contents.trim_end_matches('\n');
+    format!("{fence}{language}\n{contents}\n{fence}")
+}
+
+/// The prompt with the attached files before it
+pub fn inject(prompt: &str, attachments: &[Attachment]) -> String {
+    attachments
+        .iter()
+        .map(Attachment::to_markdown)
+        .chain(std::iter::once(prompt.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Forget the files of a removed message and move the later ones up
+pub fn remove_message(files: &mut Vec<AttachedFile>, index: usize) {
+    files.retain(|file| file.message != index);
+    for file in files.iter_mut().filter(|file| file.message > index) {
+        file.message -= 1;
+    }
+}
+
+/// Forget the files of the `count` oldest messages,
+/// which were replaced with a single summary
+pub fn replace_oldest(files: &mut Vec<AttachedFile>, count: usize) {
+    if count == 0 {
+        return;
+    }
+    files.retain(|file| file.message >= count);
+    for file in files.iter_mut() {
+        file.message -= count - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn attachments_are_fenced_before_the_prompt() {
+        let file = |path: &str| Source::File(path.into());
+        let code = Attachment::new(file("src/main.rs"), "fn main() {}\n", DEFAULT_MAX_CHARS);
+        let log = Attachment::new(file("run.log"), "ok ```\nfailed", 4);
+        assert!(!code.is_truncated());
+        assert!(log.is_truncated());
+
+        assert_eq!(
+            inject("why?", &[code, log]),
+            "File `src/main.rs`:\n```rs\nfn main() {}\n```\n\n\
+             File `run.log`, the first 4 of 13 characters:\n```log\nok `\n```\n\n\
+             why?"
+        );
+    }
+
+    #[test]
+    fn fences_are_longer_than_the_contents_backticks() {
+        let readme = Attachment::new(
+            Source::File("README.md".into()),
+            "```sh\njust\n```",
+            DEFAULT_MAX_CHARS,
+        );
+        assert!(readme.to_markdown().contains("\n````md\n"));
+    }
+
+    #[test]
+    fn commands_show_their_exit_code() {
+        let capture = Capture {
+            command: "cargo build".to_string(),
+            exit_code: 101,
+            output: "error[E0425]".to_string(),
+        };
+        assert_eq!(
+            Attachment::command(capture, DEFAULT_MAX_CHARS).to_markdown(),
+            "Output of `cargo build`, which exited with code 101:\n```console\nerror[E0425]\n```"
+        );
+    }
+
+    #[test]
+    fn files_follow_their_messages() {
+        let file = |message| AttachedFile {
+            message,
+            source: Source::File("a.rs".into()),
+            chars: 1,
+            truncated: false,
+        };
+        let mut files = vec![file(0), file(2), file(4)];
+
+        remove_message(&mut files, 2);
+        assert_eq!(files, vec![file(0), file(3)]);
+
+        replace_oldest(&mut files, 2);
+        assert_eq!(files, vec![file(2)]);
+    }
+}
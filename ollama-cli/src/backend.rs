@@ -0,0 +1,175 @@
+//! The servers that the TUI can run models on.
+//! Each server is a [`Backend`] and [`BackendKind`] selects one in the config.
+
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use futures::{stream::BoxStream, StreamExt as _};
+use ollama_rs::models::{LocalModel, ModelInfo};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt as _;
+use url::Url;
+
+use crate::{
+    config::Config,
+    djinn,
+    error::{Error, Result},
+    ollama::{
+        self,
+        chat::{ChatOptions, ChatRequest},
+        generate::Request,
+        running::RunningModel,
+        stats::GenerationStats,
+        tools::{ToolChatMessage, ToolRegistry},
+        ModelName,
+    },
+    openai,
+};
+
+/// A piece of a streamed response
+#[derive(Debug, Clone)]
+pub enum Chunk {
+    Token(Arc<str>),
+    /// Sent once the server reports timings, usually at the end of the stream
+    Stats(GenerationStats),
+}
+
+pub type TokenStream = BoxStream<'static, Result<Chunk>>;
+
+/// Which kind of server the TUI talks to
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum, strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Ollama,
+    Djinn,
+    /// Any server with an OpenAI compatible API
+    #[serde(rename = "openai")]
+    #[strum(serialize = "openai")]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Run a djinn model in the TUI process, needs the `local` feature
+    Local,
+}
+
+/// Connect to the server at `url` and check that it's answering.
+/// The local backend loads its model instead.
+pub async fn connect(
+    kind: BackendKind,
+    url: &Url,
+    config: &Config,
+) -> anyhow::Result<Arc<dyn Backend>> {
+    let backend: Arc<dyn Backend> = match kind {
+        BackendKind::Ollama => Arc::new(ollama::Client::new(url).await?),
+        BackendKind::Djinn => Arc::new(djinn::Client::new(url).await?),
+        BackendKind::OpenAi => Arc::new(openai::Client::new(url, config.openai.api_key()).await?),
+        #[cfg(feature = "local")]
+        BackendKind::Local => {
+            let path = config
+                .local
+                .model
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("set `local.model` to run a model locally"))?;
+            Arc::new(crate::local::Local::new(path).await?)
+        }
+        #[cfg(not(feature = "local"))]
+        BackendKind::Local => anyhow::bail!("running models locally needs the `local` feature"),
+    };
+    Ok(backend)
+}
+
+/// A server that can run models.
+/// Generation, chat, embeddings, and model listing are required,
+/// model management is optional and unsupported by default.
+#[async_trait]
+pub trait Backend: Debug + Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    /// Connect to another host of the same kind with the same credentials
+    async fn connect(&self, url: &Url) -> anyhow::Result<Arc<dyn Backend>>;
+
+    /// Check that the server is answering requests
+    async fn is_healthy(&self) -> bool;
+
+    async fn generate(&self, request: Request) -> Result<TokenStream>;
+
+    async fn chat(&self, request: ChatRequest) -> Result<TokenStream>;
+
+    async fn embed(&self, model: &ModelName, input: &str) -> Result<Vec<f32>>;
+
+    async fn list_models(&self) -> Result<Vec<LocalModel>>;
+
+    async fn model_info(&self, model: &ModelName) -> Result<ModelInfo>;
+
+    /// Send one round of a chat with tools.
+    /// The returned message may contain tool calls that need to be answered.
+    async fn chat_with_tools(
+        &self,
+        _model: &str,
+        _messages: &[ToolChatMessage],
+        _tools: &ToolRegistry,
+        _options: &ChatOptions,
+    ) -> Result<(ToolChatMessage, GenerationStats)> {
+        Err(self.unsupported("tool calls"))
+    }
+
+    /// List the models that are loaded into memory
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        Err(self.unsupported("listing running models"))
+    }
+
+    async fn unload_model(&self, _model: &ModelName) -> Result<()> {
+        Err(self.unsupported("unloading models"))
+    }
+
+    async fn copy_model(&self, _source: &ModelName, _destination: &ModelName) -> Result<()> {
+        Err(self.unsupported("copying models"))
+    }
+
+    /// Create (or replace) a model from a Modelfile.
+    /// The stream yields status updates until the model is created.
+    async fn create_model(
+        &self,
+        _name: &ModelName,
+        _modelfile: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        Err(self.unsupported("creating models"))
+    }
+
+    fn unsupported(&self, operation: &'static str) -> Error {
+        Error::Unsupported {
+            backend: self.kind(),
+            operation,
+        }
+    }
+}
+
+/// Write the generated tokens to stdout as they arrive
+pub async fn generate_stdout(backend: &dyn Backend, request: Request) -> anyhow::Result<()> {
+    let mut stream = backend.generate(request).await?;
+    let mut output_sink = tokio::io::stdout();
+    while let Some(chunk) = stream.next().await {
+        if let Chunk::Token(token) = chunk? {
+            output_sink.write_all(token.as_bytes()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate the whole response before returning it
+pub async fn generate_string(backend: &dyn Backend, request: Request) -> anyhow::Result<String> {
+    let mut stream = backend.generate(request).await?;
+    let mut response = String::new();
+    while let Some(chunk) = stream.next().await {
+        if let Chunk::Token(token) = chunk? {
+            response.push_str(&token);
+        }
+    }
+
+    Ok(response)
+}
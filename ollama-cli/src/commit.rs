@@ -0,0 +1,216 @@
+//! Write a Conventional Commits message for the staged changes with a model,
+//! then edit, approve, and commit it
+
+use std::{
+    io::{BufRead as _, Write as _},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    attachment::fenced,
+    backend::{self, Backend},
+    config::Defaults,
+    context::ContextSource,
+    ollama::{
+        generate::{GenerateParams, Request},
+        ModelName,
+    },
+};
+
+const SYSTEM_PROMPT: &str = "You write git commit messages in the Conventional Commits format. \
+    Answer with only the commit message: a summary line like `fix(parser): handle empty input` \
+    of at most 72 characters, a blank line, then a short body that explains why the change \
+    was made. Don't wrap the message in a code block.";
+/// Long diffs are cut off so they fit in the context window
+const DEFAULT_MAX_DIFF_CHARS: usize = 24_000;
+const TYPES: [&str; 11] = [
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// Write a commit message for the staged changes and commit them
+#[derive(Debug, clap::Args)]
+pub struct CommitArgs {
+    /// The model that writes the message, defaults to the config's default model
+    #[arg(long)]
+    model: Option<ModelName>,
+    /// Commit the first message without asking
+    #[arg(long, short)]
+    yes: bool,
+    /// Print the message without committing
+    #[arg(long, conflicts_with = "yes")]
+    dry_run: bool,
+    /// More to tell the model, e.g. why the change was made
+    #[arg(long)]
+    hint: Option<String>,
+    /// Cut the diff off after this many characters
+    #[arg(long, default_value_t = DEFAULT_MAX_DIFF_CHARS)]
+    max_diff_chars: usize,
+}
+
+enum Choice {
+    Commit,
+    Edit,
+    Regenerate,
+    Quit,
+}
+
+impl CommitArgs {
+    pub async fn run(self, backend: &dyn Backend, defaults: &Defaults) -> anyhow::Result<()> {
+        let model = self
+            .model
+            .clone()
+            .or_else(|| defaults.model.clone())
+            .unwrap_or_default();
+        let diff = ContextSource::GitStaged.collect()?;
+        if diff.trim().is_empty() {
+            anyhow::bail!("nothing is staged, add changes with `git add` first");
+        }
+        let prompt = self.prompt(&diff);
+
+        let mut message = self.generate(backend, &model, &prompt).await?;
+        loop {
+            println!("\n{message}\n");
+            if let Some(summary) = message.lines().next().filter(|line| !is_conventional(line)) {
+                eprintln!("warning: {summary:?} isn't a Conventional Commits summary");
+            }
+            if self.dry_run {
+                return Ok(());
+            }
+            let choice = if self.yes { Choice::Commit } else { ask()? };
+            match choice {
+                Choice::Commit => return git_commit(&message),
+                Choice::Edit => {
+                    message = edit::edit(&message)?.trim().to_string();
+                    if message.is_empty() {
+                        anyhow::bail!("the message is empty, not committing");
+                    }
+                }
+                Choice::Regenerate => message = self.generate(backend, &model, &prompt).await?,
+                Choice::Quit => return Ok(()),
+            }
+        }
+    }
+
+    fn prompt(&self, diff: &str) -> String {
+        let diff = match diff.char_indices().nth(self.max_diff_chars) {
+            Some((end, _)) => format!("{}\n[the rest of the diff was cut off]", &diff[..end]),
+            None => diff.to_string(),
+        };
+        let hint = self
+            .hint
+            .as_deref()
+            .map(|hint| format!("\n\nAbout the change: {hint}"))
+            .unwrap_or_default();
+        format!(
+            "Write a commit message for this staged diff:\n{}{hint}",
+            fenced("diff", &diff)
+        )
+    }
+
+    async fn generate(
+        &self,
+        backend: &dyn Backend,
+        model: &ModelName,
+        prompt: &str,
+    ) -> anyhow::Result<String> {
+        eprintln!("writing a commit message with {model}...");
+        let request = Request {
+            prompt: prompt.into(),
+            model: model.clone(),
+            params: GenerateParams {
+                system: Some(SYSTEM_PROMPT.to_string()),
+                ..Default::default()
+            },
+        };
+        let response = backend::generate_string(backend, request).await?;
+        Ok(clean(&response))
+    }
+}
+
+fn ask() -> anyhow::Result<Choice> {
+    loop {
+        eprint!("[c]ommit, [e]dit, [r]egenerate or [q]uit? ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(Choice::Quit);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "c" | "commit" => return Ok(Choice::Commit),
+            "e" | "edit" => return Ok(Choice::Edit),
+            "r" | "regenerate" => return Ok(Choice::Regenerate),
+            "q" | "quit" => return Ok(Choice::Quit),
+            _ => {}
+        }
+    }
+}
+
+fn git_commit(message: &str) -> anyhow::Result<()> {
+    let mut child = Command::new("git")
+        .args(["commit", "--file", "-"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin should be piped")
+        .write_all(message.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("`git commit` failed with {status}");
+    }
+    Ok(())
+}
+
+/// Trim the response and remove a code block around it
+fn clean(response: &str) -> String {
+    let response = response.trim();
+    let unfenced = response
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        // drop the fence's language
+        .and_then(|rest| rest.split_once('\n'))
+        .map(|(_, message)| message.trim());
+    unfenced.unwrap_or(response).to_string()
+}
+
+/// Whether `summary` looks like `type(scope)!: description`
+fn is_conventional(summary: &str) -> bool {
+    let Some((prefix, description)) = summary.split_once(": ") else {
+        return false;
+    };
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let kind = match prefix.split_once('(') {
+        Some((kind, scope)) => match scope.strip_suffix(')') {
+            Some(scope) if !scope.is_empty() => kind,
+            _ => return false,
+        },
+        None => prefix,
+    };
+    TYPES.contains(&kind) && !description.trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn code_blocks_are_removed() {
+        assert_eq!(
+            clean("```text\nfix: handle empty input\n\nbody\n```\n"),
+            "fix: handle empty input\n\nbody"
+        );
+        assert_eq!(clean(" feat: add commit "), "feat: add commit");
+    }
+
+    #[test]
+    fn recognizes_conventional_summaries() {
+        assert!(is_conventional("fix(parser): handle empty input"));
+        assert!(is_conventional("feat!: drop the old API"));
+        assert!(!is_conventional("fixed the parser"));
+        assert!(!is_conventional("wip: stuff"));
+        assert!(!is_conventional("fix(): nothing"));
+    }
+}
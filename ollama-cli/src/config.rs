@@ -1,13 +1,34 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use djinn_dirs::Dirs;
 use serde::{Deserialize, Serialize};
 
-use crate::{fs_ext::read_file_to_string, ollama::ModelHost, tui::event::EventDefinitions};
+use crate::{
+    backend::BackendKind,
+    context::ContextSource,
+    fs_ext::read_file_to_string,
+    ollama::{tools::ToolConfig, ModelHost, ModelName},
+    tui::event::EventDefinitions,
+};
 
 const APP_NAME: &str = "ollama_tui";
 const CONFIG_PATH_VAR: &str = "OLLAMA_TUI_CONFIG_PATH";
 const CONFIG_FILE_NAME: &str = "config.toml";
+/// Overlays the config for a project, looked for in the working directory and its parents
+const PROJECT_FILE_NAME: &str = ".djinn.toml";
 const LOG_FILE_NAME: &str = "tui.log";
+/// Written by the keymap editor and takes precedence over `keymap` in the config file
+const KEYMAP_FILE_NAME: &str = "keymap.toml";
+const SESSIONS_DIR_NAME: &str = "sessions";
+const OUTPUTS_DIR_NAME: &str = "outputs";
+const HISTORY_DIR_NAME: &str = "history";
+/// Prompt templates that extend the defaults
+const TEMPLATES_FILE_NAME: &str = "templates.toml";
+const DEFAULT_HOST_NAME: &str = "default";
+const OPENAI_API_KEY_VAR: &str = "OPENAI_API_KEY";
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Config {
@@ -15,14 +36,108 @@ pub struct Config {
     pub log_file: LogFile,
     #[serde(default)]
     pub host: ModelHost,
+    /// The kind of server that the hosts are running
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// Other servers that can be picked in the TUI
+    #[serde(default)]
+    pub hosts: Vec<NamedHost>,
     #[serde(default)]
     pub keymap: EventDefinitions,
+    /// Key bindings for editing text inputs
+    #[serde(default)]
+    pub editing: Editing,
+    /// Tools that models can call in the chat view
+    #[serde(default)]
+    pub tools: Vec<ToolConfig>,
+    #[serde(default)]
+    pub openai: OpenAiConfig,
+    #[serde(default)]
+    pub local: LocalConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    #[serde(default)]
+    pub defaults: Defaults,
 }
 
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
         get_config()
     }
+
+    /// The default host followed by the named hosts
+    pub fn all_hosts(&self) -> Vec<NamedHost> {
+        let default = NamedHost {
+            name: DEFAULT_HOST_NAME.to_string(),
+            url: self.host.clone(),
+        };
+        std::iter::once(default)
+            .filter(|default| !self.hosts.iter().any(|host| host.url == default.url))
+            .chain(self.hosts.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Editing {
+    /// Readline style bindings in edit mode
+    #[default]
+    Emacs,
+    /// Adds vi operators and visual mode from `default_vi_keymap.toml`
+    Vi,
+}
+
+/// Settings for the [`BackendKind::OpenAi`] backend
+#[derive(Debug, Deserialize, Default)]
+pub struct OpenAiConfig {
+    /// Read from `OPENAI_API_KEY` if it isn't set
+    api_key: Option<String>,
+}
+
+impl OpenAiConfig {
+    pub fn api_key(&self) -> Option<Arc<str>> {
+        self.api_key
+            .clone()
+            .or_else(|| std::env::var(OPENAI_API_KEY_VAR).ok())
+            .map(Into::into)
+    }
+}
+
+/// Settings for the [`BackendKind::Local`] backend
+#[derive(Debug, Deserialize, Default)]
+pub struct LocalConfig {
+    /// A djinn model config, like the ones in djinn's `configs` directory
+    pub model: Option<PathBuf>,
+}
+
+/// Settings for the chat view
+#[derive(Debug, Clone, Copy, Deserialize, Default)]
+pub struct ChatConfig {
+    /// Replace older messages with a summary from the model
+    /// when the conversation nearly fills the context window
+    #[serde(default)]
+    pub auto_summarize: bool,
+    /// Attached files are cut off after this many characters
+    pub max_attachment_chars: Option<usize>,
+}
+
+/// What prompts use when the CLI or TUI doesn't say otherwise,
+/// usually set for a project in `.djinn.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Defaults {
+    pub model: Option<ModelName>,
+    pub system: Option<String>,
+    /// Context providers run before prompts, e.g. `["git-status"]`
+    #[serde(default)]
+    pub context: Vec<ContextSource>,
+}
+
+/// A server with a name to show in the host picker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedHost {
+    pub name: String,
+    pub url: ModelHost,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,11 +167,127 @@ fn get_config() -> anyhow::Result<Config> {
     };
 
     let contents = read_file_to_string(path)?;
-    let config = toml::from_str(&contents)?;
+    let mut table: toml::Table = toml::from_str(&contents)?;
+    if let Some((path, project)) = load_project()? {
+        tracing::info!(?path, "using project config");
+        merge(&mut table, project);
+    }
+    let mut config: Config = table.try_into()?;
+
+    let keymap_path = keymap_path()?;
+    if keymap_path.exists() {
+        let contents = read_file_to_string(&keymap_path)?;
+        config.keymap = toml::from_str(&contents)?;
+    }
+    if config.editing == Editing::Vi {
+        let mut keymap = EventDefinitions::vi();
+        keymap.extend(std::mem::take(&mut config.keymap));
+        config.keymap = keymap;
+    }
 
     Ok(config)
 }
 
-fn base_dirs() -> anyhow::Result<xdg::BaseDirectories> {
-    Ok(xdg::BaseDirectories::with_prefix(APP_NAME)?)
+/// The nearest `.djinn.toml` in the working directory or its parents
+fn project_path() -> Option<PathBuf> {
+    let dir = std::env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// The project's config overlay and where it was found
+pub fn load_project() -> anyhow::Result<Option<(PathBuf, toml::Table)>> {
+    let Some(path) = project_path() else {
+        return Ok(None);
+    };
+    let contents = read_file_to_string(&path)?;
+    let table = toml::from_str(&contents)
+        .map_err(|error| anyhow::anyhow!("unable to parse {}: {error}", path.display()))?;
+    Ok(Some((path, table)))
+}
+
+/// Merge `overlay` into `base`. Tables are merged key by key, other values are replaced.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn keymap_path() -> anyhow::Result<PathBuf> {
+    Ok(base_dirs()?.place_config_file(KEYMAP_FILE_NAME)?)
+}
+
+/// Save the keymap so that it's loaded the next time the TUI starts
+pub fn save_keymap(keymap: &EventDefinitions) -> anyhow::Result<PathBuf> {
+    let path = keymap_path()?;
+    std::fs::write(&path, keymap.to_toml()?)?;
+    Ok(path)
+}
+
+/// Directory where chat sessions are saved
+pub fn sessions_dir() -> anyhow::Result<PathBuf> {
+    Ok(base_dirs()?.create_data_dir(SESSIONS_DIR_NAME)?)
+}
+
+/// Directory where generated output is saved by default
+pub fn outputs_dir() -> anyhow::Result<PathBuf> {
+    Ok(base_dirs()?.create_data_dir(OUTPUTS_DIR_NAME)?)
+}
+
+pub fn templates_path() -> anyhow::Result<PathBuf> {
+    Ok(base_dirs()?.place_config_file(TEMPLATES_FILE_NAME)?)
+}
+
+/// File where the prompts submitted in a view are saved
+pub fn history_path(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(base_dirs()?.place_state_file(format!("{HISTORY_DIR_NAME}/{name}.jsonl"))?)
+}
+
+fn base_dirs() -> anyhow::Result<Dirs> {
+    Ok(Dirs::new(APP_NAME)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn project_config_overlays_tables() {
+        let mut base: toml::Table = toml::from_str(
+            r#"
+            backend = "ollama"
+            [chat]
+            auto_summarize = true
+            [defaults]
+            model = "mistral"
+            context = ["git-status"]
+            "#,
+        )
+        .unwrap();
+        let project: toml::Table = toml::from_str(
+            r#"
+            [defaults]
+            model = "qwen2.5-coder"
+            context = ["git-diff"]
+            "#,
+        )
+        .unwrap();
+        merge(&mut base, project);
+
+        let config: Config = base.try_into().unwrap();
+        assert!(config.chat.auto_summarize);
+        assert_eq!(
+            config.defaults.model,
+            Some(ModelName("qwen2.5-coder".into()))
+        );
+        assert_eq!(config.defaults.context, vec![ContextSource::GitDiff]);
+    }
 }
@@ -0,0 +1,205 @@
+//! Context providers that put the output of a command before a prompt,
+//! from `--context` in the CLI or a `/git-diff` style command in the TUI inputs
+
+use std::{fmt::Display, process::Command, str::FromStr};
+
+use crate::{attachment::fenced, templates::Templates};
+
+const COMMAND_PREFIX: char = '/';
+const RANGE_PREFIX: &str = "git-range:";
+
+/// Where the context comes from
+#[derive(Debug, Clone, PartialEq, Eq, serde_with::DeserializeFromStr)]
+pub enum ContextSource {
+    /// The branch and changed files
+    GitStatus,
+    /// Changes that haven't been committed, staged or not
+    GitDiff,
+    /// Changes that are staged for the next commit
+    GitStaged,
+    /// The commits in a range like `main..HEAD` with their changes
+    GitRange(String),
+}
+
+impl ContextSource {
+    /// Names and descriptions for help text, the range is written `git-range:<range>`
+    pub const HELP: [(&'static str, &'static str); 4] = [
+        ("git-status", "the branch and changed files"),
+        ("git-diff", "uncommitted changes"),
+        ("git-staged", "staged changes"),
+        (
+            "git-range",
+            "the commits in a range, e.g. `/git-range main..HEAD`",
+        ),
+    ];
+
+    fn git_args(&self) -> Vec<&str> {
+        match self {
+            ContextSource::GitStatus => vec!["status", "--short", "--branch"],
+            ContextSource::GitDiff => vec!["diff", "--no-color", "HEAD"],
+            ContextSource::GitStaged => vec!["diff", "--no-color", "--cached"],
+            ContextSource::GitRange(range) => vec!["log", "--patch", "--no-color", range],
+        }
+    }
+
+    fn language(&self) -> &'static str {
+        match self {
+            ContextSource::GitStatus => "",
+            ContextSource::GitDiff | ContextSource::GitStaged | ContextSource::GitRange(_) => {
+                "diff"
+            }
+        }
+    }
+
+    /// Run the command in the current directory
+    pub fn collect(&self) -> anyhow::Result<String> {
+        let args = self.git_args();
+        let output = Command::new("git").args(&args).output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "`git {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// The output in a fence under a header with the command
+    pub fn to_markdown(&self) -> anyhow::Result<String> {
+        let output = self.collect()?;
+        let output = if output.trim().is_empty() {
+            "(no output)"
+        } else {
+            output.trim_end()
+        };
+        Ok(format!(
+            "Output of `git {}`:\n{}",
+            self.git_args().join(" "),
+            fenced(self.language(), output)
+        ))
+    }
+}
+
+impl Display for ContextSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContextSource::GitStatus => write!(f, "git-status"),
+            ContextSource::GitDiff => write!(f, "git-diff"),
+            ContextSource::GitStaged => write!(f, "git-staged"),
+            ContextSource::GitRange(range) => write!(f, "{RANGE_PREFIX}{range}"),
+        }
+    }
+}
+
+impl FromStr for ContextSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "git-status" => Ok(ContextSource::GitStatus),
+            "git-diff" => Ok(ContextSource::GitDiff),
+            "git-staged" => Ok(ContextSource::GitStaged),
+            _ => match s.strip_prefix(RANGE_PREFIX) {
+                Some(range) if !range.is_empty() => Ok(ContextSource::GitRange(range.to_string())),
+                _ => Err(format!(
+                    "unknown context {s:?}, expected git-status, git-diff, git-staged \
+                     or git-range:<range>"
+                )),
+            },
+        }
+    }
+}
+
+/// A context command at the start of `line` and the rest of the line.
+/// The range of `/git-range` is the word after it.
+pub fn parse_command(line: &str) -> Option<(ContextSource, &str)> {
+    let command = line.strip_prefix(COMMAND_PREFIX)?;
+    let (name, rest) = split_word(command);
+    let (source, rest) = match name {
+        "git-range" => {
+            let (range, rest) = split_word(rest);
+            (ContextSource::GitRange(range.to_string()), rest)
+        }
+        name => (name.parse().ok()?, rest),
+    };
+    match source {
+        ContextSource::GitRange(ref range) if range.is_empty() => None,
+        source => Some((source, rest)),
+    }
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    text.split_once(char::is_whitespace)
+        .map(|(word, rest)| (word, rest.trim_start()))
+        .unwrap_or((text, ""))
+}
+
+/// The contexts, each in its own block, followed by the prompt
+pub fn prepend(sources: &[ContextSource], prompt: &str) -> anyhow::Result<String> {
+    let mut blocks = sources
+        .iter()
+        .map(ContextSource::to_markdown)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if !prompt.trim().is_empty() {
+        blocks.push(prompt.to_string());
+    }
+    Ok(blocks.join("\n\n"))
+}
+
+/// Expand the context commands at the start of a line, like `/git-staged /commit-message`,
+/// then the template command after them
+pub fn expand(line: &str, templates: &Templates) -> anyhow::Result<String> {
+    let mut sources = Vec::new();
+    let mut rest = line;
+    while let Some((source, after)) = parse_command(rest) {
+        sources.push(source);
+        rest = after;
+    }
+    if sources.is_empty() {
+        return Ok(templates.expand(line).unwrap_or_else(|| line.to_string()));
+    }
+    let prompt = templates.expand(rest).unwrap_or_else(|| rest.to_string());
+    prepend(&sources, &prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parses_context_commands() {
+        assert_eq!(
+            parse_command("/git-diff review this"),
+            Some((ContextSource::GitDiff, "review this"))
+        );
+        assert_eq!(
+            parse_command("/git-range main..HEAD /summarize"),
+            Some((ContextSource::GitRange("main..HEAD".into()), "/summarize"))
+        );
+        assert_eq!(parse_command("/git-range"), None);
+        assert_eq!(parse_command("/summarize git-diff"), None);
+        assert_eq!(parse_command("git-diff"), None);
+    }
+
+    #[test]
+    fn cli_names_round_trip() {
+        for source in [
+            ContextSource::GitStatus,
+            ContextSource::GitStaged,
+            ContextSource::GitRange("v1.0..".into()),
+        ] {
+            assert_eq!(source.to_string().parse(), Ok(source));
+        }
+        assert!("git-range:".parse::<ContextSource>().is_err());
+    }
+
+    #[test]
+    fn lines_without_context_are_only_templated() {
+        let templates = Templates::default();
+        assert_eq!(expand("hi", &templates).unwrap(), "hi");
+    }
+}
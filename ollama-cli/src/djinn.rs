@@ -0,0 +1,314 @@
+//! A [`Backend`] for djinn-server.
+//! Completions are streamed from djinn's own `/complete/stream` route,
+//! chat and model listing use the routes it serves for Ollama clients.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::{pin_mut, StreamExt as _};
+use ollama_rs::models::{LocalModel, ModelInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::Instant;
+use url::Url;
+
+use crate::{
+    backend::{Backend, BackendKind, Chunk, TokenStream},
+    error::{Error, Result},
+    ollama::{
+        chat::ChatRequest,
+        generate::Request,
+        stats::{FinalData, GenerationStats},
+        ModelName,
+    },
+    sse::{lines, EventParser},
+};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server-sent event names used by `/complete/stream`
+const EVENT_TOKEN: &str = "token";
+const EVENT_ERROR: &str = "error";
+const EVENT_EOS: &str = "eos";
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: Url,
+}
+
+impl Client {
+    pub async fn new(address: &Url) -> anyhow::Result<Self> {
+        let client = Client {
+            http: reqwest::Client::new(),
+            url: address.clone(),
+        };
+
+        tracing::debug!("testing client connection");
+        client.health_check().await?;
+
+        Ok(client)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let url = self.url.join("health-check")?;
+        self.http
+            .get(url)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<reqwest::Response> {
+        let url = self.url.join(path)?;
+        let response = self.http.post(url).json(body).send().await?;
+        check_status(response).await
+    }
+}
+
+/// djinn's routes send `{"code": .., "message": ..}` on error
+/// and its Ollama routes send `{"code": .., "error": ..}`.
+/// Older servers don't send a code.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    #[serde(alias = "error")]
+    message: String,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+impl From<ErrorBody> for Error {
+    fn from(body: ErrorBody) -> Self {
+        match body.code {
+            Some(code) => Error::Server {
+                code,
+                message: body.message,
+            },
+            None => Error::Backend(body.message),
+        }
+    }
+}
+
+/// What a user can do about an error with djinn-server's `code`
+pub fn hint(code: &str) -> Option<&'static str> {
+    match code {
+        "model_not_loaded" => Some("check the model name and the server's model configs"),
+        "context_overflow" => Some("shorten the prompt or ask for fewer tokens"),
+        "queue_full" => Some("the server is busy, try again later"),
+        "backend_error" => Some("the server's logs have the details"),
+        _ => None,
+    }
+}
+
+/// Turn an error status into an error with the code and message from the server
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ErrorBody>().await {
+        Ok(body) => Err(body.into()),
+        Err(_) => Err(Error::Backend(status.to_string())),
+    }
+}
+
+/// The data of an `error` event, plain text on older servers
+fn event_error(data: String) -> Error {
+    match serde_json::from_str::<ErrorBody>(&data) {
+        Ok(body) => body.into(),
+        Err(_) => Error::Backend(data),
+    }
+}
+
+/// The data of the `eos` event
+#[derive(Debug, Deserialize)]
+struct StreamEnd {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    cached_tokens: u64,
+    #[serde(default)]
+    generated_tokens: u64,
+}
+
+impl StreamEnd {
+    fn stats(&self, started: Instant, first_token: Option<Instant>) -> GenerationStats {
+        GenerationStats::measured(
+            self.generated_tokens,
+            self.prompt_tokens.saturating_sub(self.cached_tokens),
+            started,
+            first_token,
+        )
+    }
+}
+
+/// A line of a streamed response from `/api/chat`
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    message: Option<ChatChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(flatten)]
+    final_data: FinalData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunkMessage {
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tags {
+    models: Vec<LocalModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[async_trait]
+impl Backend for Client {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Djinn
+    }
+
+    async fn connect(&self, url: &Url) -> anyhow::Result<Arc<dyn Backend>> {
+        Ok(Arc::new(Client::new(url).await?))
+    }
+
+    async fn is_healthy(&self) -> bool {
+        self.health_check()
+            .await
+            .inspect_err(|error| tracing::debug!(%error, "health check failed"))
+            .is_ok()
+    }
+
+    async fn generate(&self, request: Request) -> Result<TokenStream> {
+        let Request {
+            prompt,
+            model,
+            params,
+        } = request;
+        if params.system.is_some() || !params.images.is_empty() || params.format.is_some() {
+            return Err(self.unsupported("system prompts, images, or formats"));
+        }
+        if params.keep_alive.is_some() {
+            tracing::debug!("djinn doesn't support keep_alive, ignoring it");
+        }
+
+        // options are passed as djinn's run config, e.g. `sample_len=200`
+        let mut body: serde_json::Map<String, Value> = params.options.into_iter().collect();
+        body.insert("prompt".into(), prompt.to_string().into());
+        body.insert("model".into(), model.to_string().into());
+
+        let started = Instant::now();
+        let response = self.post("complete/stream", &Value::Object(body)).await?;
+
+        let stream = async_stream::try_stream! {
+            let lines = lines(response);
+            pin_mut!(lines);
+            let mut parser = EventParser::default();
+            let mut first_token = None;
+
+            while let Some(line) = lines.next().await {
+                let Some(event) = parser.line(&line?) else {
+                    continue;
+                };
+                match event.name.as_str() {
+                    EVENT_TOKEN => {
+                        first_token.get_or_insert_with(Instant::now);
+                        yield Chunk::Token(event.data.into());
+                    }
+                    EVENT_ERROR => Err(event_error(event.data))?,
+                    EVENT_EOS => {
+                        match serde_json::from_str::<StreamEnd>(&event.data) {
+                            Ok(end) => yield Chunk::Stats(end.stats(started, first_token)),
+                            Err(error) => tracing::warn!(%error, "unable to parse stats"),
+                        }
+                    }
+                    name => tracing::debug!(name, "ignoring unknown event"),
+                }
+            }
+        };
+
+        Ok(stream.boxed())
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<TokenStream> {
+        let messages: Vec<Value> = request
+            .context()
+            .map(|message| json!({ "role": message.role(), "content": message.content() }))
+            .chain(std::iter::once(
+                json!({ "role": "user", "content": request.prompt }),
+            ))
+            .collect();
+        let body = json!({
+            "model": request.model.to_string(),
+            "messages": messages,
+            "options": request.options,
+            "stream": true,
+        });
+
+        let response = self.post("api/chat", &body).await?;
+
+        let stream = async_stream::try_stream! {
+            let lines = lines(response);
+            pin_mut!(lines);
+
+            while let Some(line) = lines.next().await {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let chunk: ChatChunk = serde_json::from_str(&line)?;
+                if let Some(message) = chunk.error {
+                    Err(Error::from(ErrorBody { message, code: chunk.code }))?;
+                }
+                if chunk.done {
+                    yield Chunk::Stats(chunk.final_data.into());
+                }
+                if let Some(message) = chunk.message {
+                    yield Chunk::Token(message.content.into());
+                }
+            }
+        };
+
+        Ok(stream.boxed())
+    }
+
+    async fn embed(&self, _model: &ModelName, input: &str) -> Result<Vec<f32>> {
+        // djinn has a single embedding model
+        let response: EmbedResponse = self
+            .post("embed", &json!({ "input": input }))
+            .await?
+            .json()
+            .await?;
+        response
+            .embeddings
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Backend("no embeddings returned".into()))
+    }
+
+    async fn list_models(&self) -> Result<Vec<LocalModel>> {
+        let url = self.url.join("api/tags")?;
+        let response = check_status(self.http.get(url).send().await?).await?;
+        let tags: Tags = response.json().await?;
+        Ok(tags.models)
+    }
+
+    async fn model_info(&self, model: &ModelName) -> Result<ModelInfo> {
+        let response = self
+            .post("api/show", &json!({ "model": model.to_string() }))
+            .await?;
+        Ok(response.json().await?)
+    }
+}
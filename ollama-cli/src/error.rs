@@ -5,7 +5,7 @@ use ollama_rs::error::OllamaError;
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
 
-use crate::{lm::Response, tui::event::InputMode};
+use crate::{backend::BackendKind, lm::Response, tui::event::InputMode};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -20,6 +20,9 @@ pub enum Error {
     #[error("keymap should have all modes defined by default. missing {0}")]
     MissingKeymap(InputMode),
 
+    #[error("keymap does not serialize to the same bindings")]
+    KeymapRoundTrip,
+
     #[error("error indexing collection at index {index}: {msg}")]
     BadIndex { index: usize, msg: &'static str },
 
@@ -32,12 +35,24 @@ pub enum Error {
     #[error("unable to index instruction in Modelfile: {0}")]
     ModelfileIndex(usize),
 
-    #[error("missing instruction in Modelfile: {0}")]
-    ModelfileMissing(String),
-
     #[error(transparent)]
     OllamaRs(#[from] OllamaError),
 
+    #[error("invalid model options: {0}")]
+    GenerateOptions(serde_json::Error),
+
+    #[error("error parsing JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("unable to create model: {0}")]
+    CreateModel(String),
+
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+
     #[error("error sending Response over channel")]
     SendResponse(#[from] SendError<Response>),
 
@@ -47,6 +62,21 @@ pub enum Error {
     #[error("error Serializing TOML: {0}")]
     TomlSer(#[from] toml::ser::Error),
 
+    #[error("the {backend} backend doesn't support {operation}")]
+    Unsupported {
+        backend: BackendKind,
+        operation: &'static str,
+    },
+
+    #[error("backend error: {0}")]
+    Backend(String),
+
+    #[error(
+        "server error ({code}): {message}{}",
+        crate::djinn::hint(.code).map(|hint| format!(", {hint}")).unwrap_or_default()
+    )]
+    Server { code: String, message: String },
+
     #[error("got an unexpected response: {0:?}")]
     UnexpectedResponse(Response),
 }
@@ -2,20 +2,102 @@ use std::sync::Arc;
 
 use ollama_rs::models::{LocalModel, ModelInfo};
 
-use crate::ollama::{chat::ChatRequest, ModelName};
+use crate::ollama::{
+    chat::ChatRequest, embeddings::Embedding, generate::Request, running::RunningModel,
+    stats::GenerationStats, ModelHost, ModelName,
+};
 
 #[derive(Debug, Clone)]
 pub enum Response {
     Eos,
+    /// The current generation was stopped before it finished
+    Cancelled,
     Error(Arc<str>),
     Token(Arc<str>),
+    /// Sent before [`Response::Eos`] when the server reports timings
+    Stats(GenerationStats),
     LocalModels(Vec<LocalModel>),
     ModelInfo(ModelInfo),
+    Embedding(Embedding),
+    /// A status update from a long running operation like creating a model
+    Progress(Arc<str>),
+    ModelCreated(ModelName),
+    RunningModels(Vec<RunningModel>),
+    /// The model context is using a new host
+    Connected(ModelHost),
+    /// The host went down or came back
+    Connection(ConnectionState),
+    /// The oldest `replaced` messages of a chat were summarized
+    /// to make room in the context window
+    Summary {
+        replaced: usize,
+        summary: Arc<str>,
+    },
+    /// A response from one of the models in a [`Prompt::Compare`],
+    /// `index` is the model's position in the prompt
+    Compare {
+        index: usize,
+        response: Box<Response>,
+    },
+}
+
+/// Whether the host answered the last health check
+#[derive(Debug, Clone, Copy, Default, PartialEq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ConnectionState {
+    #[default]
+    Online,
+    Offline,
 }
 
 pub enum Prompt {
-    Generate(Arc<str>),
+    Generate(Request),
+    /// Run the same request on each of `models` at the same time
+    Compare {
+        models: Vec<ModelName>,
+        request: Request,
+    },
     Chat(ChatRequest),
     LocalModels,
     ModelInfo(ModelName),
+    Embed {
+        model: ModelName,
+        input: Arc<str>,
+    },
+    /// Stop the generation that is currently streaming
+    Cancel,
+    /// Create or replace a model from a Modelfile
+    CreateModel {
+        name: ModelName,
+        modelfile: String,
+    },
+    /// Connect to a different server
+    Connect(ModelHost),
+    /// List the models loaded on the server
+    RunningModels,
+    /// Unload a model from memory
+    Unload(ModelName),
+    /// Copy a model to a new name
+    CopyModel {
+        source: ModelName,
+        destination: ModelName,
+    },
+}
+
+impl Prompt {
+    /// The model that runs the prompt, if it runs one
+    pub fn model(&self) -> Option<&ModelName> {
+        match self {
+            Prompt::Generate(request) => Some(&request.model),
+            Prompt::Compare { models, .. } => models.first(),
+            Prompt::Chat(request) => Some(&request.model),
+            Prompt::Embed { model, .. } => Some(model),
+            _ => None,
+        }
+    }
+
+    /// Prompts that need the host are queued while it's offline
+    pub fn needs_connection(&self) -> bool {
+        !matches!(self, Prompt::Cancel | Prompt::Connect(_))
+    }
 }
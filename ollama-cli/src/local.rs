@@ -0,0 +1,295 @@
+//! A [`Backend`] that runs a djinn model inside the TUI process,
+//! so no server is needed.
+//! Only built with the `local` feature.
+
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use djinn_core::lm::{
+    chat::ChatTemplate,
+    config::{OutputFormat, RunConfig},
+    mistral::create_new_context,
+    model::ModelContext,
+    validate::validate_model_config,
+};
+use futures::{pin_mut, StreamExt as _};
+use ollama_rs::models::{LocalModel, ModelInfo};
+use serde_json::{json, Value};
+use tokio::{
+    sync::{mpsc::Sender, Mutex},
+    time::Instant,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use url::Url;
+
+use crate::{
+    backend::{Backend, BackendKind, Chunk, TokenStream},
+    error::{Error, Result},
+    ollama::{
+        chat::{ChatRequest, Message},
+        generate::{Format, Request},
+        stats::GenerationStats,
+        ModelName,
+    },
+};
+
+/// Tokens are buffered while the TUI catches up
+const TOKEN_BUFFER: usize = 32;
+
+/// A model loaded with djinn-core.
+/// Requests are run one at a time.
+pub struct Local {
+    name: ModelName,
+    config: PathBuf,
+    template: ChatTemplate,
+    context: Arc<Mutex<ModelContext>>,
+}
+
+impl Debug for Local {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Local")
+            .field("name", &self.name)
+            .field("config", &self.config)
+            .field("template", &self.template)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Local {
+    /// Load the model described by the config file at `path`.
+    /// The model is named after the file.
+    pub async fn new(path: &Path) -> anyhow::Result<Self> {
+        tracing::info!(?path, "loading local model");
+        let contents = tokio::fs::read_to_string(path).await?;
+        let model_config = validate_model_config(&contents)?;
+        let context = create_new_context(&model_config).await?;
+
+        let name = path
+            .file_stem()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+
+        Ok(Local {
+            name: ModelName(name.into()),
+            config: path.to_path_buf(),
+            template: context.chat_template(),
+            context: Arc::new(Mutex::new(context)),
+        })
+    }
+
+    fn check_model(&self, model: &ModelName) {
+        if model.0 != self.name.0 {
+            tracing::debug!(%model, local = %self.name, "running the local model instead");
+        }
+    }
+
+    /// Run the model on a background task so the stream doesn't borrow the model.
+    /// Dropping the stream stops the run.
+    fn run(&self, prompt: String, config: RunConfig) -> TokenStream {
+        let (sender, receiver) = tokio::sync::mpsc::channel(TOKEN_BUFFER);
+        let context = self.context.clone();
+        tokio::spawn(async move {
+            let mut context = context.lock_owned().await;
+            run_model(&mut context, prompt, config, sender).await;
+        });
+        ReceiverStream::new(receiver).boxed()
+    }
+}
+
+async fn run_model(
+    context: &mut ModelContext,
+    prompt: String,
+    config: RunConfig,
+    sender: Sender<Result<Chunk>>,
+) {
+    let started = Instant::now();
+    let mut first_token = None;
+    {
+        let stream = context.run(prompt, config);
+        pin_mut!(stream);
+
+        while let Some(token) = stream.next().await {
+            let chunk = match token {
+                Ok(token) => {
+                    first_token.get_or_insert_with(Instant::now);
+                    Ok(Chunk::Token(token.into()))
+                }
+                Err(error) => Err(Error::Backend(error.to_string())),
+            };
+            if sender.send(chunk).await.is_err() {
+                tracing::debug!("response stream dropped, stopping the model");
+                return;
+            }
+        }
+    }
+
+    let stats = context.stats();
+    let stats = GenerationStats::measured(
+        stats.generated_tokens as u64,
+        stats.prompt_tokens.saturating_sub(stats.cached_tokens) as u64,
+        started,
+        first_token,
+    );
+    // the receiver may be gone if the TUI cancelled at the last token
+    let _ = sender.send(Ok(Chunk::Stats(stats))).await;
+}
+
+/// Parse a run config from request options, e.g. `sample_len=200`.
+/// The prompt isn't echoed unless asked for.
+fn run_config(options: Vec<(String, Value)>) -> Result<RunConfig> {
+    let mut config = serde_json::Map::new();
+    config.insert("echo_prompt".into(), false.into());
+    config.extend(options);
+    serde_json::from_value(Value::Object(config)).map_err(Error::GenerateOptions)
+}
+
+/// Format a conversation with the model's template, ending where the reply starts
+fn chat_prompt(template: ChatTemplate, request: &ChatRequest) -> String {
+    request
+        .context()
+        .map(|message| match message {
+            Message::System(text) => template.system(&text),
+            Message::User(text) => template.user_turn(&text),
+            Message::Assistant(text) => template.reply(&text),
+        })
+        .chain(std::iter::once(template.user_turn(&request.prompt)))
+        .collect()
+}
+
+#[async_trait]
+impl Backend for Local {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Local
+    }
+
+    /// There's only one local model, so there's nothing to connect to
+    async fn connect(&self, _url: &Url) -> anyhow::Result<Arc<dyn Backend>> {
+        Err(self.unsupported("switching hosts").into())
+    }
+
+    async fn is_healthy(&self) -> bool {
+        true
+    }
+
+    async fn generate(&self, request: Request) -> Result<TokenStream> {
+        let Request {
+            prompt,
+            model,
+            params,
+        } = request;
+        self.check_model(&model);
+        if !params.images.is_empty() {
+            return Err(self.unsupported("images"));
+        }
+
+        let mut config = run_config(params.options)?;
+        if let Some(Format::Json) = params.format {
+            config.format = Some(OutputFormat::Json);
+        }
+        let prompt = match params.system {
+            Some(system) => self.template.system(&system) + &prompt,
+            None => prompt.to_string(),
+        };
+
+        Ok(self.run(prompt, config))
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<TokenStream> {
+        self.check_model(&request.model);
+        let options = &request.options;
+        let mut config = run_config(vec![])?;
+        if let Some(temperature) = options.temperature {
+            config.temperature = temperature.into();
+        }
+        if let Some(top_p) = options.top_p {
+            config.top_p = Some(top_p.into());
+        }
+        if let Some(repeat_penalty) = options.repeat_penalty {
+            config.repeat_penalty = repeat_penalty;
+        }
+        if let Some(seed) = options.seed {
+            config.seed = Some(u64::from(seed.unsigned_abs()));
+        }
+
+        let prompt = chat_prompt(self.template, &request);
+        Ok(self.run(prompt, config))
+    }
+
+    async fn embed(&self, _model: &ModelName, _input: &str) -> Result<Vec<f32>> {
+        Err(self.unsupported("embeddings"))
+    }
+
+    async fn list_models(&self) -> Result<Vec<LocalModel>> {
+        let modified_at: DateTime<Utc> = tokio::fs::metadata(&self.config)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+            .into();
+        // ollama-rs only builds its model types from JSON
+        let model = serde_json::from_value(json!({
+            "name": self.name.to_string(),
+            "modified_at": modified_at.to_rfc3339(),
+            "size": 0,
+        }))?;
+        Ok(vec![model])
+    }
+
+    /// The modelfile is the model's djinn config
+    async fn model_info(&self, model: &ModelName) -> Result<ModelInfo> {
+        self.check_model(model);
+        let modelfile = tokio::fs::read_to_string(&self.config)
+            .await
+            .map_err(|source| Error::ReadFile {
+                source,
+                path: self.config.clone(),
+            })?;
+        Ok(serde_json::from_value(json!({ "modelfile": modelfile }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn formats_chat_history() {
+        let request = ChatRequest {
+            prompt: "and now?".into(),
+            model: ModelName::default(),
+            system: Some("be brief".into()),
+            options: Default::default(),
+            history: vec![
+                Message::User("hi".into()),
+                Message::Assistant("hello".into()),
+            ],
+            summarize: None,
+        };
+
+        assert_eq!(
+            chat_prompt(ChatTemplate::MISTRAL, &request),
+            "be brief\n\n[INST] hi [/INST]hello</s>[INST] and now? [/INST]"
+        );
+    }
+
+    #[test]
+    fn options_override_the_echo_default() {
+        let config = run_config(vec![]).unwrap();
+        assert!(!config.echo_prompt);
+
+        let config = run_config(vec![
+            ("echo_prompt".to_string(), json!(true)),
+            ("sample_len".to_string(), json!(20)),
+        ])
+        .unwrap();
+        assert!(config.echo_prompt);
+        assert_eq!(config.sample_len, 20);
+    }
+}
@@ -1,23 +1,43 @@
-use std::{fs::File, path::Path};
+use std::{fs::File, io::stdout, path::Path};
 
+use backend::BackendKind;
 use clap::{Parser, Subcommand};
 use config::Config;
-use ollama::ModelHost;
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    ExecutableCommand as _,
+};
+use ollama::{ModelHost, ModelName};
 use tracing_subscriber::fmt::format::FmtSpan;
 use tui::AppContext;
 
+mod attachment;
+mod backend;
 pub mod bytes_size;
+mod commit;
 mod config;
+mod context;
+mod djinn;
 mod error;
 mod fs_ext;
 mod lm;
+#[cfg(feature = "local")]
+mod local;
 mod ollama;
+mod openai;
+mod session;
+mod shell;
+mod sse;
+mod templates;
 mod tui;
 
 #[derive(Parser)]
 pub struct Cli {
     #[arg(long)]
     host: Option<ModelHost>,
+    /// The kind of server at the host, overrides the config
+    #[arg(long)]
+    backend: Option<BackendKind>,
     #[command(subcommand)]
     mode: Mode,
 }
@@ -29,11 +49,31 @@ enum Mode {
         command: Command,
     },
     Tui,
+    /// Write a Conventional Commits message for the staged changes and commit them
+    Commit(commit::CommitArgs),
+    /// Manage saved chat sessions
+    Chat {
+        #[command(subcommand)]
+        command: ChatCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChatCommand {
+    /// Write a saved chat session as Markdown or JSON
+    Export(session::ExportArgs),
 }
 
 #[derive(Subcommand)]
 enum Command {
-    Generate(ollama::generate::Request),
+    Generate {
+        /// Put the output of a command before the prompt: git-status, git-diff,
+        /// git-staged or git-range:<range>. Can be repeated.
+        #[arg(long = "context")]
+        contexts: Vec<context::ContextSource>,
+        #[command(flatten)]
+        request: ollama::generate::Request,
+    },
     Embed(ollama::generate::Request),
 }
 
@@ -54,25 +94,55 @@ async fn main() -> anyhow::Result<()> {
     setup_tracing(&config.log_file)?;
 
     let host = args.host.as_ref().unwrap_or(&config.host);
-
-    let client = ollama::Client::new(host.url()).await?;
+    let backend_kind = args.backend.unwrap_or(config.backend);
 
     match args.mode {
-        Mode::OneShot { command } => match command {
-            Command::Generate(request) => {
-                client.generate_stdout(request).await?;
-            }
-            Command::Embed(request) => {
-                let embedding = client.embed(request).await?;
-                tracing::info!("{embedding:?}");
+        Mode::OneShot { command } => {
+            let backend = backend::connect(backend_kind, host.url(), &config).await?;
+            match command {
+                Command::Generate {
+                    contexts,
+                    mut request,
+                } => {
+                    let defaults = &config.defaults;
+                    if let Some(model) = defaults.model.as_ref() {
+                        // the positional model falls back to the default model
+                        if request.model == ModelName::default() {
+                            request.model = model.clone();
+                        }
+                    }
+                    if request.params.system.is_none() {
+                        request.params.system = defaults.system.clone();
+                    }
+                    let contexts: Vec<_> =
+                        defaults.context.iter().cloned().chain(contexts).collect();
+                    if !contexts.is_empty() {
+                        request.prompt = context::prepend(&contexts, &request.prompt)?.into();
+                    }
+                    backend::generate_stdout(backend.as_ref(), request).await?;
+                }
+                Command::Embed(request) => {
+                    let embedding = backend.embed(&request.model, &request.prompt).await?;
+                    tracing::info!("{embedding:?}");
+                }
             }
+        }
+        Mode::Commit(commit_args) => {
+            let backend = backend::connect(backend_kind, host.url(), &config).await?;
+            commit_args.run(backend.as_ref(), &config.defaults).await?;
+        }
+        Mode::Chat { command } => match command {
+            ChatCommand::Export(export_args) => export_args.run()?,
         },
         Mode::Tui => {
+            let backend = backend::connect(backend_kind, host.url(), &config).await?;
             color_eyre::install().expect("unable to install color_eyre");
             tracing::info!("starting TUI");
-            let app_context = AppContext::new(client, config);
+            let app_context = AppContext::new(backend, host.clone(), config);
             let terminal = ratatui::init();
+            stdout().execute(EnableMouseCapture)?;
             app_context.run(terminal).await?;
+            stdout().execute(DisableMouseCapture)?;
             ratatui::restore();
         }
     }
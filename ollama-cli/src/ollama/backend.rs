@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt as _};
+use ollama_rs::models::{LocalModel, ModelInfo};
+use url::Url;
+
+use super::{
+    chat::{ChatOptions, ChatRequest},
+    generate::Request,
+    running::RunningModel,
+    stats::{FinalData, GenerationStats},
+    tools::{ToolChatMessage, ToolRegistry},
+    Client, ModelName,
+};
+use crate::{
+    backend::{Backend, BackendKind, Chunk, TokenStream},
+    error::{Error, Result},
+};
+
+#[async_trait]
+impl Backend for Client {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Ollama
+    }
+
+    async fn connect(&self, url: &Url) -> anyhow::Result<Arc<dyn Backend>> {
+        Ok(Arc::new(Client::new(url).await?))
+    }
+
+    async fn is_healthy(&self) -> bool {
+        Client::is_healthy(self).await
+    }
+
+    async fn generate(&self, request: Request) -> Result<TokenStream> {
+        let stream = Client::generate(self, request).await?;
+        let stream = stream.flat_map(|responses| {
+            let chunks: Vec<Result<Chunk>> = match responses {
+                Ok(responses) => responses
+                    .into_iter()
+                    .flat_map(|response| {
+                        let stats = response.final_data.map(|data| {
+                            Chunk::Stats(GenerationStats::from(FinalData {
+                                eval_count: data.eval_count.into(),
+                                eval_duration: data.eval_duration,
+                                prompt_eval_count: data.prompt_eval_count.into(),
+                                total_duration: data.total_duration,
+                            }))
+                        });
+                        stats
+                            .into_iter()
+                            .chain(std::iter::once(Chunk::Token(response.response.into())))
+                            .map(Ok)
+                    })
+                    .collect(),
+                Err(error) => vec![Err(error.into())],
+            };
+            futures::stream::iter(chunks)
+        });
+        Ok(stream.boxed())
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<TokenStream> {
+        let stream = Client::chat(self, request).await?;
+        let stream = stream.flat_map(|response| {
+            let chunks: Vec<Result<Chunk>> = match response {
+                Ok(response) => {
+                    let stats = response.final_data.map(|data| {
+                        Chunk::Stats(GenerationStats::from(FinalData {
+                            eval_count: data.eval_count.into(),
+                            eval_duration: data.eval_duration,
+                            prompt_eval_count: data.prompt_eval_count.into(),
+                            total_duration: data.total_duration,
+                        }))
+                    });
+                    let token = response
+                        .message
+                        .map(|message| Chunk::Token(message.content.into()));
+                    stats.into_iter().chain(token).map(Ok).collect()
+                }
+                Err(()) => vec![Err(Error::Backend("error in response".into()))],
+            };
+            futures::stream::iter(chunks)
+        });
+        Ok(stream.boxed())
+    }
+
+    async fn embed(&self, model: &ModelName, input: &str) -> Result<Vec<f32>> {
+        let request = Request {
+            prompt: input.into(),
+            model: model.clone(),
+            params: Default::default(),
+        };
+        let mut embeddings = Client::embed(self, request)
+            .await
+            .map_err(|error| Error::Backend(error.to_string()))?;
+        if embeddings.is_empty() {
+            return Err(Error::Backend("no embeddings returned".into()));
+        }
+        Ok(embeddings.swap_remove(0))
+    }
+
+    async fn list_models(&self) -> Result<Vec<LocalModel>> {
+        self.list_local_models().await
+    }
+
+    async fn model_info(&self, model: &ModelName) -> Result<ModelInfo> {
+        Client::model_info(self, model.clone()).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        model: &str,
+        messages: &[ToolChatMessage],
+        tools: &ToolRegistry,
+        options: &ChatOptions,
+    ) -> Result<(ToolChatMessage, GenerationStats)> {
+        Client::chat_with_tools(self, model, messages, tools, options).await
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        Client::running_models(self).await
+    }
+
+    async fn unload_model(&self, model: &ModelName) -> Result<()> {
+        Client::unload_model(self, model).await
+    }
+
+    async fn copy_model(&self, source: &ModelName, destination: &ModelName) -> Result<()> {
+        Client::copy_model(self, source, destination).await
+    }
+
+    async fn create_model(
+        &self,
+        name: &ModelName,
+        modelfile: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        Client::create_model(self, name, modelfile).await
+    }
+}
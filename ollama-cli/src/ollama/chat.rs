@@ -1,18 +1,176 @@
-use std::sync::Arc;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher as _, Hasher as _},
+    str::FromStr,
+    sync::Arc,
+};
 
-use ollama_rs::generation::chat::{
-    request::ChatMessageRequest, ChatMessage, ChatMessageResponseStream,
+use ollama_rs::generation::{
+    chat::{request::ChatMessageRequest, ChatMessage, ChatMessageResponseStream},
+    options::GenerationOptions,
 };
 use serde::{Deserialize, Serialize};
 use strum::{EnumDiscriminants, EnumString};
 
 use super::{Client, ModelName};
+use crate::error::{Error, Result};
+
+/// Asks the model to summarize the history it's sent with
+const SUMMARY_PROMPT: &str = "Summarize our conversation so far in a short paragraph. \
+    Keep any facts, names and decisions that later messages may refer to.";
+/// Starts the message that stands in for the summarized messages
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:\n";
 
 #[derive(Debug, Clone)]
 pub struct ChatRequest {
     pub prompt: Arc<str>,
     pub model: ModelName,
+    /// Sent as the leading system message
+    pub system: Option<Arc<str>>,
+    pub options: ChatOptions,
+    /// The conversation before the prompt, oldest first
     pub history: Vec<Message>,
+    /// Replace this many of the oldest messages in the history
+    /// with a summary before answering
+    pub summarize: Option<usize>,
+}
+
+/// Model parameters that can be overridden for a chat session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    /// Responses to the same prompt and seed are the same
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+}
+
+impl ChatOptions {
+    pub const TEMPERATURE: &'static str = "temperature";
+    pub const TOP_P: &'static str = "top_p";
+    pub const NUM_CTX: &'static str = "num_ctx";
+    pub const REPEAT_PENALTY: &'static str = "repeat_penalty";
+    pub const SEED: &'static str = "seed";
+
+    pub fn is_empty(&self) -> bool {
+        *self == ChatOptions::default()
+    }
+
+    /// Each option by name with unset options left empty
+    pub fn fields(&self) -> [(&'static str, String); 5] {
+        fn show<T: ToString>(value: Option<T>) -> String {
+            value.map(|value| value.to_string()).unwrap_or_default()
+        }
+
+        [
+            (Self::TEMPERATURE, show(self.temperature)),
+            (Self::TOP_P, show(self.top_p)),
+            (Self::NUM_CTX, show(self.num_ctx)),
+            (Self::REPEAT_PENALTY, show(self.repeat_penalty)),
+            (Self::SEED, show(self.seed)),
+        ]
+    }
+
+    /// Parse options from the strings in [`Self::fields`]
+    pub fn from_fields<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> std::result::Result<Self, String> {
+        fn parse<T: FromStr>(name: &str, value: &str) -> std::result::Result<Option<T>, String>
+        where
+            T::Err: std::fmt::Display,
+        {
+            let value = value.trim();
+            if value.is_empty() {
+                return Ok(None);
+            }
+            value
+                .parse()
+                .map(Some)
+                .map_err(|error| format!("invalid {name} {value:?}: {error}"))
+        }
+
+        let mut options = ChatOptions::default();
+        for (name, value) in fields {
+            match name {
+                Self::TEMPERATURE => options.temperature = parse(name, value)?,
+                Self::TOP_P => options.top_p = parse(name, value)?,
+                Self::NUM_CTX => options.num_ctx = parse(name, value)?,
+                Self::REPEAT_PENALTY => options.repeat_penalty = parse(name, value)?,
+                Self::SEED => options.seed = parse(name, value)?,
+                _ => return Err(format!("unknown option {name}")),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The same options with a random seed, so the model answers differently
+    pub fn reseeded(&self) -> Self {
+        let random = RandomState::new().build_hasher().finish();
+        ChatOptions {
+            // Ollama takes a signed 32 bit seed, keep it positive
+            seed: Some((random >> 33) as i32),
+            ..self.clone()
+        }
+    }
+
+    pub fn to_generation_options(&self) -> Result<Option<GenerationOptions>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let value = serde_json::to_value(self).map_err(Error::GenerateOptions)?;
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(Error::GenerateOptions)
+    }
+}
+
+impl ChatRequest {
+    /// The system prompt, if there is one, followed by the history
+    pub fn context(&self) -> impl Iterator<Item = Message> + '_ {
+        self.system
+            .clone()
+            .map(Message::System)
+            .into_iter()
+            .chain(self.history.iter().cloned())
+    }
+
+    /// The number of messages that [`Self::summarize`] replaces
+    pub fn summarized_count(&self) -> usize {
+        self.summarize.unwrap_or_default().min(self.history.len())
+    }
+
+    /// A request for a summary of the oldest messages,
+    /// if this request should replace them with one
+    pub fn summary_request(&self) -> Option<ChatRequest> {
+        let count = self.summarized_count();
+        (count > 0).then(|| ChatRequest {
+            prompt: SUMMARY_PROMPT.into(),
+            model: self.model.clone(),
+            system: self.system.clone(),
+            options: self.options.clone(),
+            history: self.history[..count].to_vec(),
+            summarize: None,
+        })
+    }
+
+    /// Replace the oldest messages with their summary
+    pub fn with_summary(self, summary: &str) -> ChatRequest {
+        let count = self.summarized_count();
+        let history = std::iter::once(Message::summary(summary))
+            .chain(self.history.into_iter().skip(count))
+            .collect();
+        ChatRequest {
+            history,
+            summarize: None,
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Clone, strum::Display, EnumDiscriminants, Serialize, Deserialize)]
@@ -42,6 +200,11 @@ impl Message {
             Message::Assistant(arc) | Message::User(arc) | Message::System(arc) => arc.clone(),
         }
     }
+
+    /// Stands in for the messages that `summary` summarizes
+    pub fn summary(summary: &str) -> Message {
+        Message::System(format!("{SUMMARY_PREFIX}{}", summary.trim()).into())
+    }
 }
 
 impl<'a> From<(MessageRole, &'a str)> for Message {
@@ -58,13 +221,16 @@ impl<'a> From<(MessageRole, &'a str)> for Message {
 
 impl From<ChatRequest> for ChatMessageRequest {
     fn from(value: ChatRequest) -> Self {
-        let ChatRequest {
-            prompt,
-            model,
-            history,
-        } = value;
+        let context: Vec<Message> = value.context().collect();
+        let options = value
+            .options
+            .to_generation_options()
+            .inspect_err(|error| tracing::warn!(%error, "ignoring chat options"))
+            .ok()
+            .flatten();
+        let ChatRequest { prompt, model, .. } = value;
 
-        let messages: Vec<ChatMessage> = history
+        let messages: Vec<ChatMessage> = context
             .into_iter()
             .map(|message| match message {
                 Message::User(msg) => ChatMessage::user(msg.to_string()),
@@ -74,7 +240,11 @@ impl From<ChatRequest> for ChatMessageRequest {
             .chain(std::iter::once(ChatMessage::user(prompt.to_string())))
             .collect();
 
-        ChatMessageRequest::new(model.to_string(), messages)
+        let request = ChatMessageRequest::new(model.to_string(), messages);
+        match options {
+            Some(options) => request.options(options),
+            None => request,
+        }
     }
 }
 
@@ -87,3 +257,76 @@ impl Client {
         self.client.send_chat_messages_stream(request).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_round_trip_through_fields() {
+        let options = ChatOptions {
+            temperature: Some(0.5),
+            num_ctx: Some(4096),
+            ..Default::default()
+        };
+        let fields = options.fields();
+        let parsed =
+            ChatOptions::from_fields(fields.iter().map(|(name, value)| (*name, value.as_str())));
+
+        assert_eq!(parsed, Ok(options));
+    }
+
+    #[test]
+    fn invalid_option_names_the_field() {
+        let error = ChatOptions::from_fields([(ChatOptions::NUM_CTX, "lots")]).unwrap_err();
+        assert!(error.starts_with("invalid num_ctx"));
+    }
+
+    #[test]
+    fn summary_replaces_the_oldest_messages() {
+        let request = ChatRequest {
+            prompt: "and now?".into(),
+            model: ModelName::default(),
+            system: None,
+            options: Default::default(),
+            history: vec![
+                Message::User("hi".into()),
+                Message::Assistant("hello".into()),
+                Message::User("how are you?".into()),
+                Message::Assistant("fine".into()),
+            ],
+            summarize: Some(3),
+        };
+
+        let summary_request = request.summary_request().unwrap();
+        assert_eq!(summary_request.history.len(), 3);
+        assert_eq!(summary_request.summarize, None);
+
+        let request = request.with_summary("they said hi ");
+        assert_eq!(
+            request
+                .history
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "system: Summary of the earlier conversation:\nthey said hi",
+                "assistant: fine",
+            ]
+        );
+        assert!(request.summary_request().is_none());
+    }
+
+    #[test]
+    fn reseeding_keeps_the_other_options() {
+        let options = ChatOptions {
+            temperature: Some(0.5),
+            seed: Some(-1),
+            ..Default::default()
+        };
+        let reseeded = options.reseeded();
+
+        assert_eq!(reseeded.temperature, Some(0.5));
+        assert!(reseeded.seed.is_some_and(|seed| seed >= 0));
+    }
+}
@@ -0,0 +1,59 @@
+use futures::{stream::BoxStream, StreamExt as _};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Client, ModelName};
+use crate::error::{Error, Result};
+
+/// A line of progress from the create API
+#[derive(Debug, Deserialize)]
+struct CreateStatus {
+    status: Option<String>,
+    error: Option<String>,
+}
+
+impl Client {
+    /// Create (or replace) a model from a Modelfile.
+    /// The stream yields status updates until the model is created.
+    pub async fn create_model(
+        &self,
+        name: &ModelName,
+        modelfile: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let url = self.url.join("api/create")?;
+        let body = json!({
+            "name": name.to_string(),
+            "modelfile": modelfile,
+            "stream": true,
+        });
+
+        let response = self
+            .http
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(async_stream::try_stream! {
+            let mut bytes = response.bytes_stream();
+            let mut buffer: Vec<u8> = Vec::new();
+
+            while let Some(chunk) = bytes.next().await {
+                buffer.extend_from_slice(&chunk?);
+
+                while let Some(newline) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=newline).collect();
+                    let status: CreateStatus = serde_json::from_slice(&line)?;
+                    if let Some(error) = status.error {
+                        Err(Error::CreateModel(error))?;
+                    }
+                    if let Some(status) = status.status {
+                        yield status;
+                    }
+                }
+            }
+        }
+        .boxed())
+    }
+}
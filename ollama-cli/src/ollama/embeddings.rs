@@ -1,6 +1,16 @@
+use std::sync::Arc;
+
 use ollama_rs::generation::embeddings::request::GenerateEmbeddingsRequest;
 
-use super::{generate::Request, Client};
+use super::{generate::Request, Client, ModelName};
+
+/// An embedding vector along with the text it was generated from
+#[derive(Debug, Clone)]
+pub struct Embedding {
+    pub input: Arc<str>,
+    pub model: ModelName,
+    pub vector: Arc<[f32]>,
+}
 
 impl From<Request> for GenerateEmbeddingsRequest {
     fn from(value: Request) -> Self {
@@ -1,45 +1,228 @@
-use std::sync::Arc;
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 
-use clap::Parser;
-use futures::StreamExt;
-use ollama_rs::generation::completion::{request::GenerationRequest, GenerationResponseStream};
-use tokio::io::AsyncWriteExt as _;
+use base64::Engine as _;
+use clap::{Parser, ValueEnum};
+use ollama_rs::generation::{
+    completion::{request::GenerationRequest, GenerationResponseStream},
+    images::Image,
+    options::GenerationOptions,
+    parameters::{FormatType, KeepAlive, TimeUnit},
+};
+use serde_json::Value;
 
 use super::{Client, ModelName};
-use crate::error::Result;
+use crate::error::{Error, Result};
 
-#[derive(Parser)]
+#[derive(Parser, Clone, Debug)]
 pub struct Request {
     pub prompt: Arc<str>,
     #[arg(default_value_t)]
     pub model: ModelName,
+    #[command(flatten)]
+    pub params: GenerateParams,
+}
+
+/// Optional parameters to a generate request.
+/// The TUI parses these from the same flags as the CLI.
+#[derive(Parser, Clone, Debug, Default)]
+pub struct GenerateParams {
+    /// The system prompt.
+    #[arg(long)]
     pub system: Option<String>,
+    /// Images for multimodal models. Can be repeated.
+    #[arg(long = "image")]
+    pub images: Vec<PathBuf>,
+    /// Constrain the output format.
+    #[arg(long)]
+    pub format: Option<Format>,
+    /// How long the model stays loaded after the request, e.g. `5m`, `1h`, `30s`.
+    /// `0` unloads the model right away and `-1` keeps it loaded.
+    #[arg(long)]
+    pub keep_alive: Option<KeepAliveDuration>,
+    /// Model options like `temperature=0.2` or `stop=["\n"]`. Can be repeated.
+    #[arg(long = "option", value_parser = parse_option)]
+    pub options: Vec<(String, Value)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepAliveDuration {
+    Indefinitely,
+    UnloadOnCompletion,
+    Seconds(u64),
+    Minutes(u64),
+    Hours(u64),
 }
 
-impl From<Request> for GenerationRequest {
-    fn from(value: Request) -> Self {
-        let mut builder = GenerationRequest::new(value.model.to_string(), value.prompt.to_string());
-        builder.system = value.system;
+impl FromStr for KeepAliveDuration {
+    type Err = String;
 
-        builder
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "-1" => return Ok(KeepAliveDuration::Indefinitely),
+            "0" => return Ok(KeepAliveDuration::UnloadOnCompletion),
+            _ => {}
+        }
+
+        let split = s
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("missing unit in duration {s}, expected s, m, or h"))?;
+        let (time, unit) = s.split_at(split);
+        let time: u64 = time
+            .parse()
+            .map_err(|error| format!("invalid duration {s}: {error}"))?;
+
+        match unit {
+            "s" => Ok(KeepAliveDuration::Seconds(time)),
+            "m" => Ok(KeepAliveDuration::Minutes(time)),
+            "h" => Ok(KeepAliveDuration::Hours(time)),
+            _ => Err(format!("unknown unit {unit}, expected s, m, or h")),
+        }
+    }
+}
+
+impl From<KeepAliveDuration> for KeepAlive {
+    fn from(value: KeepAliveDuration) -> Self {
+        match value {
+            KeepAliveDuration::Indefinitely => KeepAlive::Indefinitely,
+            KeepAliveDuration::UnloadOnCompletion => KeepAlive::UnloadOnCompletion,
+            KeepAliveDuration::Seconds(time) => KeepAlive::Until {
+                time,
+                unit: TimeUnit::Seconds,
+            },
+            KeepAliveDuration::Minutes(time) => KeepAlive::Until {
+                time,
+                unit: TimeUnit::Minutes,
+            },
+            KeepAliveDuration::Hours(time) => KeepAlive::Until {
+                time,
+                unit: TimeUnit::Hours,
+            },
+        }
+    }
+}
+
+/// Parse `key=value` where the value is JSON if possible, otherwise a string
+fn parse_option(s: &str) -> std::result::Result<(String, Value), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got {s}"))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    Ok((key.trim().to_string(), value))
+}
+
+impl GenerateParams {
+    /// Parse parameters from a line of CLI flags,
+    /// e.g. `--system "be brief" --option temperature=0.2`
+    pub fn parse_line(line: &str) -> std::result::Result<Self, String> {
+        let words = shlex::split(line).ok_or_else(|| "unbalanced quotes".to_string())?;
+        GenerateParams::try_parse_from(std::iter::once("generate".to_string()).chain(words))
+            .map_err(|error| error.render().to_string())
+    }
+
+    fn model_options(&self) -> Result<Option<GenerationOptions>> {
+        if self.options.is_empty() {
+            return Ok(None);
+        }
+
+        let options: serde_json::Map<String, Value> = self.options.iter().cloned().collect();
+        let options =
+            serde_json::from_value(Value::Object(options)).map_err(Error::GenerateOptions)?;
+
+        Ok(Some(options))
+    }
+
+    async fn images(&self) -> Result<Vec<Image>> {
+        let mut images = Vec::with_capacity(self.images.len());
+        for path in &self.images {
+            let bytes = tokio::fs::read(path)
+                .await
+                .map_err(|source| Error::ReadFile {
+                    source,
+                    path: path.clone(),
+                })?;
+            let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+            images.push(Image::from_base64(&encoded));
+        }
+
+        Ok(images)
+    }
+}
+
+impl Request {
+    async fn into_generation_request(self) -> Result<GenerationRequest> {
+        let Request {
+            prompt,
+            model,
+            params,
+        } = self;
+
+        let mut request = GenerationRequest::new(model.to_string(), prompt.to_string());
+        request.system = params.system.clone();
+
+        let images = params.images().await?;
+        if !images.is_empty() {
+            request = request.images(images);
+        }
+        if let Some(Format::Json) = params.format {
+            request = request.format(FormatType::Json);
+        }
+        if let Some(keep_alive) = params.keep_alive {
+            request = request.keep_alive(keep_alive.into());
+        }
+        if let Some(options) = params.model_options()? {
+            request = request.options(options);
+        }
+
+        Ok(request)
     }
 }
 
 impl Client {
     pub async fn generate(&self, request: Request) -> Result<GenerationResponseStream> {
-        let request: GenerationRequest = request.into();
+        let request = request.into_generation_request().await?;
         Ok(self.client.generate_stream(request).await?)
     }
+}
 
-    pub async fn generate_stdout(&self, request: Request) -> anyhow::Result<()> {
-        let mut stream = self.generate(request).await?;
-        let mut output_sink = tokio::io::stdout();
-        while let Some(responses) = stream.next().await {
-            for response in responses? {
-                output_sink.write_all(response.response.as_bytes()).await?;
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("-1", KeepAliveDuration::Indefinitely)]
+    #[case("0", KeepAliveDuration::UnloadOnCompletion)]
+    #[case("30s", KeepAliveDuration::Seconds(30))]
+    #[case("5m", KeepAliveDuration::Minutes(5))]
+    #[case("2h", KeepAliveDuration::Hours(2))]
+    fn parse_keep_alive(#[case] input: &str, #[case] expected: KeepAliveDuration) {
+        assert_eq!(input.parse::<KeepAliveDuration>(), Ok(expected));
+    }
+
+    #[test]
+    fn parse_params_line() {
+        let params = GenerateParams::parse_line(
+            r#"--system "be brief" --format json --option temperature=0.2 --option stop=["\n"]"#,
+        )
+        .expect("should parse params");
 
-        Ok(())
+        assert_eq!(params.system.as_deref(), Some("be brief"));
+        assert_eq!(params.format, Some(Format::Json));
+        assert_eq!(
+            params.options,
+            vec![
+                ("temperature".to_string(), serde_json::json!(0.2)),
+                ("stop".to_string(), serde_json::json!(["\n"])),
+            ]
+        );
     }
 }
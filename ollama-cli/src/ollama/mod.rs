@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr, sync::Arc};
+use std::{fmt::Display, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use ollama_rs::{
@@ -10,17 +10,26 @@ use url::Url;
 
 use crate::error::Result;
 
+mod backend;
 pub mod chat;
+pub mod create;
 pub mod embeddings;
 pub mod generate;
+pub mod running;
+pub mod stats;
+pub mod tools;
 
 pub const DEFAULT_MODEL: &str = "mistral-nemo";
 pub const DEFAULT_DOMAIN: &str = "hoss";
 pub const DEFAULT_PORT: u16 = 11434;
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client {
     client: Ollama,
+    /// Used for API calls that ollama-rs doesn't support
+    http: reqwest::Client,
+    url: Url,
 }
 
 impl Client {
@@ -36,7 +45,26 @@ impl Client {
             tracing::debug!("model loaded: {model:?}");
         }
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            http: reqwest::Client::new(),
+            url: address.clone(),
+        })
+    }
+
+    /// Check that the server is answering requests
+    pub async fn is_healthy(&self) -> bool {
+        let Ok(url) = self.url.join("api/version") else {
+            return false;
+        };
+        self.http
+            .get(url)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .inspect_err(|error| tracing::debug!(%error, "health check failed"))
+            .is_ok()
     }
 
     pub async fn list_local_models(&self) -> Result<Vec<LocalModel>> {
@@ -46,9 +74,16 @@ impl Client {
     pub async fn model_info(&self, model_name: ModelName) -> Result<ModelInfo> {
         Ok(self.client.show_model_info(model_name.to_string()).await?)
     }
+
+    pub async fn copy_model(&self, source: &ModelName, destination: &ModelName) -> Result<()> {
+        Ok(self
+            .client
+            .copy_model(source.to_string(), destination.to_string())
+            .await?)
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, serde_with::DeserializeFromStr)]
 pub struct ModelName(pub Arc<str>);
 
 impl Default for ModelName {
@@ -71,7 +106,7 @@ impl Display for ModelName {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ModelHost(Url);
 
 impl ModelHost {
@@ -0,0 +1,58 @@
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Client, ModelName};
+use crate::error::Result;
+
+/// A model that is loaded into memory on the server
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunningModel {
+    pub name: String,
+    /// Total size in bytes
+    pub size: u64,
+    /// Bytes loaded into VRAM, the rest is in system RAM
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When the model will be unloaded as an RFC 3339 timestamp
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunningModels {
+    models: Vec<RunningModel>,
+}
+
+impl Client {
+    /// List the models that are loaded with the `ps` API
+    pub async fn running_models(&self) -> Result<Vec<RunningModel>> {
+        let url = self.url.join("api/ps")?;
+        let response: RunningModels = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.models)
+    }
+
+    /// Unload a model by sending an empty request with `keep_alive` set to 0
+    pub async fn unload_model(&self, model: &ModelName) -> Result<()> {
+        let url = self.url.join("api/generate")?;
+        let body = json!({
+            "model": model.to_string(),
+            "keep_alive": 0,
+        });
+
+        self.http
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
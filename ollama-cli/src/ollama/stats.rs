@@ -0,0 +1,111 @@
+use std::{fmt::Display, time::Duration};
+
+use tokio::time::Instant;
+
+use serde::Deserialize;
+
+/// Counts and timings from the final chunk of a response
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GenerationStats {
+    /// Tokens generated in the response
+    pub eval_count: u64,
+    pub eval_duration: Duration,
+    /// Tokens in the prompt that weren't cached
+    pub prompt_eval_count: u64,
+    pub total_duration: Duration,
+    /// Time from sending the request to the first token, measured by the client
+    pub time_to_first_token: Option<Duration>,
+}
+
+/// The fields that Ollama adds to the last chunk of a response.
+/// Durations are in nanoseconds.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct FinalData {
+    #[serde(default)]
+    pub eval_count: u64,
+    #[serde(default)]
+    pub eval_duration: u64,
+    #[serde(default)]
+    pub prompt_eval_count: u64,
+    #[serde(default)]
+    pub total_duration: u64,
+}
+
+impl From<FinalData> for GenerationStats {
+    fn from(data: FinalData) -> Self {
+        GenerationStats {
+            eval_count: data.eval_count,
+            eval_duration: Duration::from_nanos(data.eval_duration),
+            prompt_eval_count: data.prompt_eval_count,
+            total_duration: Duration::from_nanos(data.total_duration),
+            time_to_first_token: None,
+        }
+    }
+}
+
+impl GenerationStats {
+    /// Stats for servers that only report token counts.
+    /// Durations are measured by the client from when the request was sent.
+    pub fn measured(
+        eval_count: u64,
+        prompt_eval_count: u64,
+        started: Instant,
+        first_token: Option<Instant>,
+    ) -> Self {
+        GenerationStats {
+            eval_count,
+            eval_duration: first_token
+                .map(|first_token| first_token.elapsed())
+                .unwrap_or_default(),
+            prompt_eval_count,
+            total_duration: started.elapsed(),
+            time_to_first_token: None,
+        }
+    }
+
+    pub fn with_time_to_first_token(self, time_to_first_token: Option<Duration>) -> Self {
+        GenerationStats {
+            time_to_first_token,
+            ..self
+        }
+    }
+
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let seconds = self.eval_duration.as_secs_f64();
+        (seconds > 0.0).then(|| self.eval_count as f64 / seconds)
+    }
+}
+
+impl Display for GenerationStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tokens", self.eval_count)?;
+        if let Some(rate) = self.tokens_per_second() {
+            write!(f, ", {rate:.1} tok/s")?;
+        }
+        if let Some(ttft) = self.time_to_first_token {
+            write!(f, ", first token {}ms", ttft.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_includes_rate_and_first_token() {
+        let stats = GenerationStats::from(FinalData {
+            eval_count: 50,
+            eval_duration: 2_000_000_000,
+            prompt_eval_count: 10,
+            total_duration: 2_500_000_000,
+        })
+        .with_time_to_first_token(Some(Duration::from_millis(320)));
+
+        assert_eq!(
+            stats.to_string(),
+            "50 tokens, 25.0 tok/s, first token 320ms"
+        );
+    }
+}
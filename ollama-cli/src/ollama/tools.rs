@@ -0,0 +1,266 @@
+//! Tools that models can call during a chat.
+//!
+//! Tools are declared in the config file, either as a whitelisted local command
+//! or as one of the [`Builtin`] tools implemented here.
+//! ollama-rs doesn't support tools yet, so tool chats use the Ollama HTTP API directly.
+
+use std::{collections::HashMap, process::Stdio, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt as _;
+
+use super::{
+    chat::{ChatOptions, Message},
+    stats::{FinalData, GenerationStats},
+    Client,
+};
+use crate::error::Result;
+
+/// Commands that take longer than this are killed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolConfig {
+    /// A tool implemented by ollama-cli
+    Builtin { builtin: Builtin },
+    /// A local command that receives the call arguments as JSON on stdin
+    /// and returns the result on stdout
+    Command {
+        name: String,
+        description: String,
+        /// A JSON schema describing the arguments
+        parameters: Value,
+        command: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Builtin {
+    /// The current local date and time
+    CurrentTime,
+}
+
+impl Builtin {
+    fn definition(&self) -> ToolDefinition {
+        match self {
+            Builtin::CurrentTime => ToolDefinition {
+                name: "current_time".into(),
+                description: "Get the current local date and time".into(),
+                parameters: json!({ "type": "object", "properties": {} }),
+            },
+        }
+    }
+
+    fn call(&self, _arguments: &Value) -> anyhow::Result<String> {
+        match self {
+            Builtin::CurrentTime => Ok(chrono::Local::now().to_rfc2822()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum ToolHandler {
+    Builtin(Builtin),
+    Command(Arc<[String]>),
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ToolDefinition {
+    name: Arc<str>,
+    description: Arc<str>,
+    parameters: Value,
+}
+
+#[derive(Clone, Debug)]
+struct Tool {
+    definition: ToolDefinition,
+    handler: ToolHandler,
+}
+
+/// The tools available to chats
+#[derive(Clone, Debug, Default)]
+pub struct ToolRegistry {
+    tools: HashMap<Arc<str>, Tool>,
+}
+
+impl ToolRegistry {
+    pub fn new(configs: &[ToolConfig]) -> Self {
+        let tools = configs
+            .iter()
+            .map(|config| {
+                let tool = match config {
+                    ToolConfig::Builtin { builtin } => Tool {
+                        definition: builtin.definition(),
+                        handler: ToolHandler::Builtin(*builtin),
+                    },
+                    ToolConfig::Command {
+                        name,
+                        description,
+                        parameters,
+                        command,
+                    } => Tool {
+                        definition: ToolDefinition {
+                            name: name.as_str().into(),
+                            description: description.as_str().into(),
+                            parameters: parameters.clone(),
+                        },
+                        handler: ToolHandler::Command(command.clone().into()),
+                    },
+                };
+                (tool.definition.name.clone(), tool)
+            })
+            .collect();
+
+        ToolRegistry { tools }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    fn specs(&self) -> Vec<Value> {
+        self.tools
+            .values()
+            .map(|tool| json!({ "type": "function", "function": tool.definition }))
+            .collect()
+    }
+
+    /// Run a tool call from the model.
+    /// Errors are returned as text so the model can see what went wrong.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let FunctionCall { name, arguments } = &call.function;
+        tracing::info!(name, %arguments, "calling tool");
+
+        let result = match self.tools.get(name.as_str()) {
+            None => Err(anyhow::anyhow!("unknown tool: {name}")),
+            Some(Tool {
+                handler: ToolHandler::Builtin(builtin),
+                ..
+            }) => builtin.call(arguments),
+            Some(Tool {
+                handler: ToolHandler::Command(command),
+                ..
+            }) => run_command(command, arguments).await,
+        };
+
+        result.unwrap_or_else(|error| {
+            tracing::warn!(name, %error, "tool call failed");
+            format!("error: {error}")
+        })
+    }
+}
+
+async fn run_command(command: &[String], arguments: &Value) -> anyhow::Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("tool command is empty"))?;
+
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(arguments.to_string().as_bytes()).await?;
+    }
+
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output()).await??;
+    if !output.status.success() {
+        anyhow::bail!(
+            "command exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A chat message in the format of the Ollama API
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ToolChatMessage {
+    pub fn tool_result(content: String) -> Self {
+        ToolChatMessage {
+            role: "tool".into(),
+            content,
+            tool_calls: vec![],
+        }
+    }
+}
+
+impl From<&Message> for ToolChatMessage {
+    fn from(value: &Message) -> Self {
+        ToolChatMessage {
+            role: value.role().into(),
+            content: value.content().to_string(),
+            tool_calls: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub function: FunctionCall,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolChatResponse {
+    message: ToolChatMessage,
+    #[serde(flatten)]
+    final_data: FinalData,
+}
+
+impl Client {
+    /// Send one round of a chat with tools.
+    /// The returned message may contain tool calls that need to be answered.
+    /// Stats are for this round only.
+    pub async fn chat_with_tools(
+        &self,
+        model: &str,
+        messages: &[ToolChatMessage],
+        tools: &ToolRegistry,
+        options: &ChatOptions,
+    ) -> Result<(ToolChatMessage, GenerationStats)> {
+        let url = self.url.join("api/chat")?;
+        let body = json!({
+            "model": model,
+            "messages": messages,
+            "tools": tools.specs(),
+            "options": options,
+            // Ollama doesn't stream tool calls
+            "stream": false,
+        });
+
+        let response: ToolChatResponse = self
+            .http
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok((response.message, response.final_data.into()))
+    }
+}
@@ -0,0 +1,368 @@
+//! A [`Backend`] for servers with an OpenAI compatible API,
+//! including hosted models.
+//! The host is the base URL of the API, e.g. `https://api.openai.com/v1/`.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{pin_mut, StreamExt as _};
+use ollama_rs::models::{LocalModel, ModelInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::Instant;
+use url::Url;
+
+use crate::{
+    backend::{Backend, BackendKind, Chunk, TokenStream},
+    error::{Error, Result},
+    ollama::{chat::ChatRequest, generate::Request, stats::GenerationStats, ModelName},
+    sse::{lines, EventParser},
+};
+
+/// Sent as the data of the last event of a stream
+const DONE: &str = "[DONE]";
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    url: Url,
+    api_key: Option<Arc<str>>,
+}
+
+impl Client {
+    pub async fn new(address: &Url, api_key: Option<Arc<str>>) -> anyhow::Result<Self> {
+        let client = Client {
+            http: reqwest::Client::new(),
+            url: base_url(address),
+            api_key,
+        };
+
+        tracing::debug!("testing client connection");
+        client.models().await?;
+
+        Ok(client)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> Result<reqwest::RequestBuilder> {
+        let url = self.url.join(path)?;
+        let request = self.http.request(method, url);
+        Ok(match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<reqwest::Response> {
+        let response = self.request(reqwest::Method::GET, path)?.send().await?;
+        check_status(response).await
+    }
+
+    async fn post(&self, path: &str, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .request(reqwest::Method::POST, path)?
+            .json(body)
+            .send()
+            .await?;
+        check_status(response).await
+    }
+
+    async fn models(&self) -> Result<Vec<Model>> {
+        let models: Models = self.get("models").await?.json().await?;
+        Ok(models.data)
+    }
+
+    /// Send a streaming request and map each event with `chunk`
+    async fn stream(
+        &self,
+        path: &str,
+        body: Value,
+        chunk: fn(StreamChunk) -> Option<String>,
+    ) -> Result<TokenStream> {
+        let started = Instant::now();
+        let response = self.post(path, &body).await?;
+
+        let stream = async_stream::try_stream! {
+            let lines = lines(response);
+            pin_mut!(lines);
+            let mut parser = EventParser::default();
+            let mut first_token = None;
+
+            while let Some(line) = lines.next().await {
+                let Some(event) = parser.line(&line?) else {
+                    continue;
+                };
+                if event.data == DONE {
+                    break;
+                }
+                let mut data: StreamChunk = serde_json::from_str(&event.data)?;
+                if let Some(error) = data.error.take() {
+                    Err(Error::Backend(error.message))?;
+                }
+                if let Some(usage) = data.usage.take() {
+                    yield Chunk::Stats(usage.stats(started, first_token));
+                }
+                if let Some(token) = chunk(data) {
+                    first_token.get_or_insert_with(Instant::now);
+                    yield Chunk::Token(token.into());
+                }
+            }
+        };
+
+        Ok(stream.boxed())
+    }
+}
+
+/// Joining paths onto a URL replaces the last segment unless it ends with a slash
+fn base_url(address: &Url) -> Url {
+    let mut url = address.clone();
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    url
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+/// Turn an error status into an error with the message from the server
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = match response.json::<ErrorBody>().await {
+        Ok(body) => body.error.message,
+        Err(_) => status.to_string(),
+    };
+    Err(Error::Backend(message))
+}
+
+#[derive(Debug, Deserialize)]
+struct Models {
+    data: Vec<Model>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Model {
+    id: String,
+    /// Unix timestamp in seconds
+    #[serde(default)]
+    created: i64,
+    #[serde(default)]
+    owned_by: String,
+}
+
+impl Model {
+    /// The API doesn't report model sizes
+    fn to_local_model(&self) -> Result<LocalModel> {
+        let modified_at = chrono::DateTime::from_timestamp(self.created, 0)
+            .unwrap_or_default()
+            .to_rfc3339();
+        // ollama-rs only builds its model types from JSON
+        Ok(serde_json::from_value(json!({
+            "name": self.id,
+            "modified_at": modified_at,
+            "size": 0,
+        }))?)
+    }
+}
+
+/// An event from a streamed completion or chat completion
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    /// Only sent in the last chunk if `stream_options.include_usage` is set
+    #[serde(default)]
+    usage: Option<Usage>,
+    #[serde(default)]
+    error: Option<ErrorMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    /// Set by completions
+    #[serde(default)]
+    text: Option<String>,
+    /// Set by chat completions
+    #[serde(default)]
+    delta: Option<Delta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Usage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+impl Usage {
+    fn stats(&self, started: Instant, first_token: Option<Instant>) -> GenerationStats {
+        GenerationStats::measured(
+            self.completion_tokens,
+            self.prompt_tokens,
+            started,
+            first_token,
+        )
+    }
+}
+
+fn completion_text(chunk: StreamChunk) -> Option<String> {
+    chunk.choices.into_iter().next()?.text
+}
+
+fn chat_content(chunk: StreamChunk) -> Option<String> {
+    chunk.choices.into_iter().next()?.delta?.content
+}
+
+#[derive(Debug, Deserialize)]
+struct Embeddings {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+#[async_trait]
+impl Backend for Client {
+    fn kind(&self) -> BackendKind {
+        BackendKind::OpenAi
+    }
+
+    async fn connect(&self, url: &Url) -> anyhow::Result<Arc<dyn Backend>> {
+        Ok(Arc::new(Client::new(url, self.api_key.clone()).await?))
+    }
+
+    async fn is_healthy(&self) -> bool {
+        self.models()
+            .await
+            .inspect_err(|error| tracing::debug!(%error, "health check failed"))
+            .is_ok()
+    }
+
+    async fn generate(&self, request: Request) -> Result<TokenStream> {
+        let Request {
+            prompt,
+            model,
+            params,
+        } = request;
+        if params.system.is_some() || !params.images.is_empty() || params.format.is_some() {
+            return Err(self.unsupported("system prompts, images, or formats"));
+        }
+
+        // options are passed as request fields, e.g. `max_tokens=200`
+        let mut body: serde_json::Map<String, Value> = params.options.into_iter().collect();
+        body.insert("model".into(), model.to_string().into());
+        body.insert("prompt".into(), prompt.to_string().into());
+        body.insert("stream".into(), true.into());
+        body.insert("stream_options".into(), json!({ "include_usage": true }));
+
+        self.stream("completions", Value::Object(body), completion_text)
+            .await
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<TokenStream> {
+        let messages: Vec<Value> = request
+            .context()
+            .map(|message| json!({ "role": message.role(), "content": message.content() }))
+            .chain(std::iter::once(
+                json!({ "role": "user", "content": request.prompt }),
+            ))
+            .collect();
+        if request.options.num_ctx.is_some() || request.options.repeat_penalty.is_some() {
+            tracing::debug!("ignoring chat options that the OpenAI API doesn't support");
+        }
+
+        let mut body = json!({
+            "model": request.model.to_string(),
+            "messages": messages,
+            "stream": true,
+            "stream_options": { "include_usage": true },
+        });
+        if let Some(temperature) = request.options.temperature {
+            body["temperature"] = temperature.into();
+        }
+        if let Some(top_p) = request.options.top_p {
+            body["top_p"] = top_p.into();
+        }
+        if let Some(seed) = request.options.seed {
+            body["seed"] = seed.into();
+        }
+
+        self.stream("chat/completions", body, chat_content).await
+    }
+
+    async fn embed(&self, model: &ModelName, input: &str) -> Result<Vec<f32>> {
+        let body = json!({ "model": model.to_string(), "input": input });
+        let response: Embeddings = self.post("embeddings", &body).await?.json().await?;
+        response
+            .data
+            .into_iter()
+            .next()
+            .map(|data| data.embedding)
+            .ok_or_else(|| Error::Backend("no embeddings returned".into()))
+    }
+
+    async fn list_models(&self) -> Result<Vec<LocalModel>> {
+        self.models()
+            .await?
+            .iter()
+            .map(Model::to_local_model)
+            .collect()
+    }
+
+    /// The API only reports who owns a model, that's shown as the modelfile
+    async fn model_info(&self, model: &ModelName) -> Result<ModelInfo> {
+        let model: Model = self.get(&format!("models/{model}")).await?.json().await?;
+        Ok(serde_json::from_value(json!({
+            "modelfile": format!("# {}\n# owned by {}", model.id, model.owned_by),
+        }))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn base_url_ends_with_a_slash() {
+        let url: Url = "https://api.openai.com/v1".parse().unwrap();
+        let url = base_url(&url);
+
+        assert_eq!(
+            url.join("chat/completions").unwrap().as_str(),
+            "https://api.openai.com/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn parses_stream_chunks() {
+        let chunk: StreamChunk =
+            serde_json::from_str(r#"{"choices":[{"index":0,"delta":{"content":"hi"}}]}"#).unwrap();
+        assert_eq!(chat_content(chunk).as_deref(), Some("hi"));
+
+        let chunk: StreamChunk = serde_json::from_str(
+            r#"{"choices":[],"usage":{"prompt_tokens":5,"completion_tokens":7}}"#,
+        )
+        .unwrap();
+        assert_eq!(chunk.usage.map(|usage| usage.completion_tokens), Some(7));
+    }
+}
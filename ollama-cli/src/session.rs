@@ -0,0 +1,389 @@
+//! Chat sessions saved as JSON in the XDG data directory
+
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attachment::AttachedFile,
+    config::sessions_dir,
+    fs_ext::read_file_to_string,
+    ollama::chat::{ChatOptions, Message},
+};
+
+const SESSION_EXTENSION: &str = "json";
+const EXPORTS_DIR_NAME: &str = "exports";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub id: SessionId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<Arc<str>>,
+    #[serde(default, skip_serializing_if = "ChatOptions::is_empty")]
+    pub options: ChatOptions,
+    /// Oldest message first
+    pub messages: Vec<Message>,
+    /// Where this session was branched from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<BranchPoint>,
+    /// Files attached to the prompts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachedFile>,
+}
+
+/// A message in another session that a branch continues from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchPoint {
+    pub session: SessionId,
+    /// The index of the last message the sessions share
+    pub message: usize,
+}
+
+/// Sessions are identified by the local time they were started
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SessionId(String);
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Default for SessionId {
+    fn default() -> Self {
+        SessionId(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string())
+    }
+}
+
+impl SessionId {
+    /// A new ID with milliseconds,
+    /// so a branch made in the same second as its parent gets its own ID
+    pub fn branch() -> Self {
+        SessionId(
+            chrono::Local::now()
+                .format("%Y-%m-%d_%H-%M-%S-%3f")
+                .to_string(),
+        )
+    }
+}
+
+impl FromStr for SessionId {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SessionId(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Each message under a heading with its role
+    #[default]
+    Markdown,
+    /// The session as it's saved
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    /// Pick the format from a file extension, defaulting to Markdown
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => ExportFormat::Json,
+            _ => ExportFormat::Markdown,
+        }
+    }
+}
+
+impl Session {
+    pub fn path(&self) -> anyhow::Result<PathBuf> {
+        Ok(sessions_dir()?.join(format!("{}.{SESSION_EXTENSION}", self.id)))
+    }
+
+    pub fn save(&self) -> anyhow::Result<PathBuf> {
+        let path = self.path()?;
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// Default path for an export in the XDG data directory
+    pub fn export_path(&self, format: ExportFormat) -> anyhow::Result<PathBuf> {
+        let dir = sessions_dir()?.join(EXPORTS_DIR_NAME);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!("{}.{}", self.id, format.extension())))
+    }
+
+    /// A new session with the messages up to and including `message`
+    pub fn branch(&self, message: usize) -> Session {
+        Session {
+            id: SessionId::branch(),
+            system_prompt: self.system_prompt.clone(),
+            options: self.options.clone(),
+            messages: self.messages.iter().take(message + 1).cloned().collect(),
+            parent: Some(BranchPoint {
+                session: self.id.clone(),
+                message,
+            }),
+            attachments: self
+                .attachments
+                .iter()
+                .filter(|file| file.message <= message)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn export(&self, format: ExportFormat) -> anyhow::Result<String> {
+        match format {
+            ExportFormat::Markdown => Ok(self.to_markdown()),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        std::iter::once(format!("# Chat {}\n", self.id))
+            .chain(
+                self.system_prompt
+                    .iter()
+                    .map(|system_prompt| format!("## system\n\n{}\n", system_prompt.trim_end())),
+            )
+            .chain(self.messages.iter().map(|message| {
+                format!(
+                    "## {}\n\n{}\n",
+                    message.role(),
+                    message.content().trim_end()
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExportArgs {
+    /// The session to export, defaults to the most recent one
+    session: Option<SessionId>,
+    /// Defaults to the extension of `--output` or Markdown
+    #[arg(long)]
+    format: Option<ExportFormat>,
+    /// Write to a file instead of stdout
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+impl ExportArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let session = load(self.session.as_ref())?;
+        let format = self.format.unwrap_or_else(|| {
+            self.output
+                .as_deref()
+                .map(ExportFormat::from_path)
+                .unwrap_or_default()
+        });
+        let contents = session.export(format)?;
+
+        match self.output {
+            Some(path) => std::fs::write(path, contents)?,
+            None => println!("{contents}"),
+        }
+        Ok(())
+    }
+}
+
+/// Load a saved session by ID or the most recent one
+pub fn load(id: Option<&SessionId>) -> anyhow::Result<Session> {
+    let sessions = load_all()?;
+    match id {
+        Some(id) => sessions
+            .into_iter()
+            .find(|session| &session.id == id)
+            .ok_or_else(|| anyhow::anyhow!("no saved session {id}")),
+        None => sessions
+            .into_iter()
+            .next_back()
+            .ok_or_else(|| anyhow::anyhow!("no saved sessions")),
+    }
+}
+
+/// The files of the saved sessions, in no particular order
+fn session_paths() -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(sessions_dir()?)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == SESSION_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// The number of saved sessions, without loading them
+pub fn count() -> anyhow::Result<usize> {
+    Ok(session_paths()?.len())
+}
+
+/// Load every saved session, skipping files that can't be parsed
+pub fn load_all() -> anyhow::Result<Vec<Session>> {
+    let mut sessions = Vec::new();
+    for path in session_paths()? {
+        match read_file_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Ok(serde_json::from_str::<Session>(&contents)?))
+        {
+            Ok(session) => sessions.push(session),
+            Err(error) => tracing::warn!(?path, %error, "unable to load session"),
+        }
+    }
+    sessions.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(sessions)
+}
+
+/// The sessions in the same tree of branches as `id`,
+/// depth first from the root with the depth of each session
+pub fn tree<'a>(sessions: &'a [Session], id: &SessionId) -> Vec<(usize, &'a Session)> {
+    let find = |id: &SessionId| sessions.iter().find(|session| &session.id == id);
+
+    // stop at missing parents and cycles
+    let mut root = id;
+    let mut seen = vec![id];
+    while let Some(parent) = find(root).and_then(|session| session.parent.as_ref()) {
+        if find(&parent.session).is_none() || seen.contains(&&parent.session) {
+            break;
+        }
+        root = &parent.session;
+        seen.push(root);
+    }
+
+    let mut tree: Vec<(usize, &Session)> = Vec::new();
+    let mut stack: Vec<(usize, &Session)> = find(root).map(|root| (0, root)).into_iter().collect();
+    while let Some((depth, session)) = stack.pop() {
+        if tree.iter().any(|(_, visited)| visited.id == session.id) {
+            continue;
+        }
+        tree.push((depth, session));
+        // reversed so the oldest branch is visited first
+        stack.extend(
+            sessions
+                .iter()
+                .rev()
+                .filter(|child| {
+                    child
+                        .parent
+                        .as_ref()
+                        .is_some_and(|parent| parent.session == session.id)
+                })
+                .map(|child| (depth + 1, child)),
+        );
+    }
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_export_has_role_headings() {
+        let session = Session {
+            id: "2024-10-01_12-00-00".parse().unwrap(),
+            messages: vec![
+                Message::User("hi".into()),
+                Message::Assistant("hello!\n".into()),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            session.to_markdown(),
+            "# Chat 2024-10-01_12-00-00\n\n## user\n\nhi\n\n## assistant\n\nhello!\n"
+        );
+    }
+
+    #[test]
+    fn branches_share_the_messages_before_them() {
+        let session = Session {
+            id: "root".parse().unwrap(),
+            messages: vec![
+                Message::User("hi".into()),
+                Message::Assistant("hello!".into()),
+                Message::User("bye".into()),
+            ],
+            ..Default::default()
+        };
+        let branch = session.branch(1);
+
+        assert_eq!(branch.messages.len(), 2);
+        assert_eq!(
+            branch.parent,
+            Some(BranchPoint {
+                session: session.id.clone(),
+                message: 1,
+            })
+        );
+        assert_ne!(branch.id, session.id);
+    }
+
+    #[test]
+    fn tree_starts_at_the_root() {
+        let session = |id: &str, parent: Option<&str>| Session {
+            id: id.parse().unwrap(),
+            parent: parent.map(|parent| BranchPoint {
+                session: parent.parse().unwrap(),
+                message: 0,
+            }),
+            ..Default::default()
+        };
+        // sorted by ID like `load_all`
+        let sessions = [
+            session("a", None),
+            session("b", Some("a")),
+            session("c", Some("b")),
+            session("d", Some("a")),
+            session("e", None),
+        ];
+
+        let tree: Vec<_> = tree(&sessions, &"c".parse().unwrap())
+            .into_iter()
+            .map(|(depth, session)| (depth, session.id.to_string()))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (0, "a".to_string()),
+                (1, "b".to_string()),
+                (2, "c".to_string()),
+                (1, "d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("chat.json")),
+            ExportFormat::Json
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("chat.md")),
+            ExportFormat::Markdown
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("chat")),
+            ExportFormat::Markdown
+        );
+    }
+}
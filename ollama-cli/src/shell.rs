@@ -0,0 +1,44 @@
+//! Commands captured by djinn's shell hook, see `djinn watch-shell`.
+//! The hook saves them to a JSON lines file, newest last.
+
+use std::path::PathBuf;
+
+use djinn_dirs::Dirs;
+use serde::Deserialize;
+
+use crate::fs_ext::read_file_to_string;
+
+/// Overrides where captures are read from, like in djinn
+const CAPTURES_PATH_VAR: &str = "DJINN_SHELL_CAPTURES";
+/// In djinn's state directory
+const CAPTURES_FILE: &str = "shell.jsonl";
+
+/// A command run in a shell with the hook
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Capture {
+    pub command: String,
+    pub exit_code: i32,
+    /// The end of the output, stdout and stderr together
+    #[serde(default)]
+    pub output: String,
+}
+
+fn captures_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(CAPTURES_PATH_VAR) {
+        return Ok(path.into());
+    }
+    Ok(Dirs::djinn()?.state().join(CAPTURES_FILE))
+}
+
+/// The newest captured command, if the hook has captured any
+pub fn last_capture() -> anyhow::Result<Option<Capture>> {
+    let path = captures_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = read_file_to_string(&path)?;
+    Ok(contents
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str(line).ok()))
+}
@@ -0,0 +1,177 @@
+//! Prompt templates, expanded from `/<name> <input>` in the TUI inputs
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    config::{load_project, templates_path},
+    fs_ext::read_file_to_string,
+};
+
+const DEFAULTS: &str = include_str!("../default_templates.toml");
+const COMMAND_PREFIX: char = '/';
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Template {
+    #[serde(default)]
+    pub description: String,
+    /// Text with placeholders for the command's input
+    pub prompt: String,
+}
+
+/// Templates by name, the default templates along with the ones
+/// in the config directory and the project's `.djinn.toml`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Templates(BTreeMap<String, Template>);
+
+impl Default for Templates {
+    fn default() -> Self {
+        toml::from_str(DEFAULTS).expect("should be able to load default templates")
+    }
+}
+
+impl Templates {
+    /// Load the user's templates, then the project's, over the defaults,
+    /// skipping the ones that can't be read
+    pub fn load() -> Self {
+        let mut templates = Templates::default();
+        match load_user_templates() {
+            Ok(Some(user_templates)) => templates.0.extend(user_templates.0),
+            Ok(None) => {}
+            Err(error) => tracing::warn!(%error, "unable to load prompt templates"),
+        }
+        match load_project_templates() {
+            Ok(Some(project_templates)) => templates.0.extend(project_templates.0),
+            Ok(None) => {}
+            Err(error) => tracing::warn!(%error, "unable to load project templates"),
+        }
+        templates
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Template)> {
+        self.0.iter()
+    }
+
+    /// Expand a slash command like `/summarize some text`.
+    /// Returns `None` if `line` doesn't start with the name of a template.
+    pub fn expand(&self, line: &str) -> Option<String> {
+        let command = line.strip_prefix(COMMAND_PREFIX)?;
+        let (name, input) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        let template = self.0.get(name)?;
+        Some(fill(&template.prompt, input.trim()))
+    }
+}
+
+/// The line that starts a slash command for a template
+pub fn command(name: &str) -> String {
+    format!("{COMMAND_PREFIX}{name} ")
+}
+
+fn load_user_templates() -> anyhow::Result<Option<Templates>> {
+    let path = templates_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = read_file_to_string(&path)?;
+    Ok(Some(toml::from_str(&contents)?))
+}
+
+/// The `[templates]` table of the project's `.djinn.toml`
+fn load_project_templates() -> anyhow::Result<Option<Templates>> {
+    let Some((_, mut project)) = load_project()? else {
+        return Ok(None);
+    };
+    match project.remove("templates") {
+        Some(templates) => Ok(Some(templates.try_into()?)),
+        None => Ok(None),
+    }
+}
+
+/// Replace `{input}` with `input` and `{1}`, `{2}`, ... with its words.
+/// Unknown placeholders are left as they are.
+fn fill(prompt: &str, input: &str) -> String {
+    let words = shlex::split(input).unwrap_or_default();
+    let mut filled = String::with_capacity(prompt.len() + input.len());
+    let mut rest = prompt;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            filled.push_str(&rest[..1]);
+            rest = after;
+            continue;
+        }
+
+        let placeholder = rest
+            .strip_prefix('{')
+            .and_then(|after| after.split_once('}'));
+        let value = placeholder.and_then(|(name, _)| match name {
+            "input" => Some(input),
+            index => index
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index > 0)
+                .map(|index| words.get(index - 1).map(String::as_str).unwrap_or_default()),
+        });
+
+        match (value, placeholder) {
+            (Some(value), Some((_, after))) => {
+                filled.push_str(value);
+                rest = after;
+            }
+            _ => {
+                filled.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(
+            fill("{1} and {2}: {input} {{input}} {3}{unknown}", "'a b' c"),
+            "a b and c: 'a b' c {input} {unknown}"
+        );
+    }
+
+    #[test]
+    fn expands_known_commands() {
+        let templates: Templates = toml::from_str(
+            r#"
+            [shout]
+            prompt = "say {input}!"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            templates.expand("/shout hi there"),
+            Some("say hi there!".into())
+        );
+        assert_eq!(templates.expand("/shout"), Some("say !".into()));
+        assert_eq!(templates.expand("/whisper hi"), None);
+        assert_eq!(templates.expand("shout hi"), None);
+    }
+
+    #[test]
+    fn load_default_templates() {
+        let templates = Templates::default();
+        assert!(templates.expand("/summarize text").is_some());
+    }
+}
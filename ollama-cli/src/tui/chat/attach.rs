@@ -0,0 +1,103 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style},
+    widgets::{Block, Clear, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{
+    attachment::Attachment,
+    error::Result,
+    tui::{
+        event::{Action, MouseAction},
+        input::{InputView as _, TextInputEvent, TextInputViewModel},
+        popup::popup_area,
+        AppEvent,
+    },
+};
+
+const HELP: &str = "enter: attach, empty path: remove attachments, q: cancel";
+
+/// Popup that reads a file to attach to the next prompt
+#[derive(Debug, Clone)]
+pub struct AttachViewModel {
+    /// Files are cut off after this many characters
+    max_chars: usize,
+    path_input: TextInputViewModel,
+    error: Option<String>,
+    area: Rect,
+}
+
+impl AttachViewModel {
+    pub fn new(max_chars: usize) -> Self {
+        let mut path_input = TextInputViewModel::default();
+        if let Ok(dir) = std::env::current_dir() {
+            path_input.set_text(format!("{}/", dir.display()));
+        }
+        AttachViewModel {
+            max_chars,
+            path_input,
+            error: None,
+            area: Rect::default(),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match self.path_input.handle_action(action)? {
+            Some(TextInputEvent::Submit(path)) if path.trim().is_empty() => {
+                Ok(Some(AppEvent::Attach(None)))
+            }
+            Some(TextInputEvent::Submit(path)) => {
+                match Attachment::read(path.trim(), self.max_chars) {
+                    Ok(attachment) => Ok(Some(AppEvent::Attach(Some(attachment)))),
+                    Err(error) => {
+                        self.error = Some(error.to_string());
+                        // submitting clears the input
+                        self.path_input.set_text(path.to_string());
+                        Ok(None)
+                    }
+                }
+            }
+            Some(TextInputEvent::InputMode(input_mode)) => {
+                Ok(Some(AppEvent::InputMode(input_mode)))
+            }
+            Some(TextInputEvent::Quit) => Ok(Some(AppEvent::Deactivate)),
+            Some(TextInputEvent::Compose(_)) | None => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = AttachView)]
+pub impl<'a> Frame<'a> {
+    fn attach_popup(&mut self, parent: Rect, style: Style, view_model: &mut AttachViewModel) {
+        let area = popup_area(parent, 60, 30);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title("attach a file").style(style);
+        let [input_area, message_area] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(1)]).areas(block.inner(area));
+        self.render_widget(block, area);
+
+        self.input_view(input_area, style, &view_model.path_input);
+
+        let message = match &view_model.error {
+            Some(error) => Paragraph::new(error.as_str()).style(Style::default().fg(Color::Red)),
+            None => Paragraph::new(HELP).style(style),
+        };
+        self.render_widget(message.wrap(Wrap { trim: true }), message_area);
+    }
+}
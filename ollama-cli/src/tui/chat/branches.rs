@@ -0,0 +1,138 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Clear, List, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{
+    error::Result,
+    ollama::chat::Message,
+    session::{self, Session, SessionId},
+    tui::{
+        event::{Action, MouseAction},
+        popup::popup_area,
+        AppEvent,
+    },
+};
+
+const HELP: &str = "enter: open, q: close";
+
+/// Popup that picks a session from the tree of branches
+/// that the current session is in
+#[derive(Debug, Clone)]
+pub struct BranchesViewModel {
+    /// Sessions depth first with their depth in the tree
+    branches: Vec<(usize, Session)>,
+    /// The session that the chat view is showing
+    current: SessionId,
+    list_state: ListState,
+    area: Rect,
+}
+
+impl BranchesViewModel {
+    /// `current` is included even if it hasn't been saved
+    pub fn new(current: Session) -> Self {
+        let mut sessions = session::load_all()
+            .inspect_err(|error| tracing::error!(%error, "unable to load saved sessions"))
+            .unwrap_or_default();
+        sessions.retain(|session| session.id != current.id);
+        sessions.push(current.clone());
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let branches: Vec<(usize, Session)> = session::tree(&sessions, &current.id)
+            .into_iter()
+            .map(|(depth, session)| (depth, session.clone()))
+            .collect();
+        let selected = branches
+            .iter()
+            .position(|(_, session)| session.id == current.id);
+        BranchesViewModel {
+            branches,
+            current: current.id,
+            list_state: ListState::default().with_selected(selected),
+            area: Rect::default(),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => {
+                self.list_state.select_previous();
+                Ok(None)
+            }
+            Action::Down => {
+                self.list_state.select_next();
+                Ok(None)
+            }
+            Action::Enter => Ok(self
+                .list_state
+                .selected()
+                .and_then(|index| self.branches.get(index))
+                .map(|(_, session)| AppEvent::LoadSession(session.clone()))),
+            Action::Branches | Action::Quit | Action::Escape => Ok(Some(AppEvent::Deactivate)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            MouseAction::Click(_) => Ok(None),
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+/// The session's ID and its latest prompt
+fn label(session: &Session) -> String {
+    let prompt = session
+        .messages
+        .iter()
+        .rev()
+        .find_map(|message| match message {
+            Message::User(prompt) => prompt.lines().next().map(ToString::to_string),
+            _ => None,
+        })
+        .unwrap_or_default();
+    format!("{} ({}) {prompt}", session.id, session.messages.len())
+}
+
+#[extend::ext(name = BranchesView)]
+pub impl<'a> Frame<'a> {
+    fn branches_popup(&mut self, parent: Rect, style: Style, view_model: &mut BranchesViewModel) {
+        let area = popup_area(parent, 70, 50);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title("branches").style(style);
+        let [list_area, help_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(block.inner(area));
+        self.render_widget(block, area);
+
+        let branches = view_model.branches.iter().map(|(depth, session)| {
+            let marker = if session.id == view_model.current {
+                "* "
+            } else {
+                "  "
+            };
+            let indent = "  ".repeat(*depth);
+            Line::from(format!("{marker}{indent}{}", label(session)))
+        });
+        let list = List::from_iter(branches).style(style).highlight_style(
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White)),
+        );
+        self.render_stateful_widget(list, list_area, &mut view_model.list_state);
+        self.render_widget(Paragraph::new(HELP).style(style), help_area);
+    }
+}
@@ -0,0 +1,128 @@
+//! An estimate of how much of the model's context window the conversation fills.
+//! Responses are counted with the token counts the server reports,
+//! everything else is estimated from its length.
+
+use std::collections::HashMap;
+
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Block, Gauge},
+    Frame,
+};
+
+use crate::ollama::{chat::Message, stats::GenerationStats};
+
+/// Ollama's context length when `num_ctx` isn't set
+pub const DEFAULT_NUM_CTX: u64 = 2048;
+/// Warn when the conversation fills this much of the context
+const WARNING_RATIO: f64 = 0.8;
+/// A rough number of characters per token for English text
+const CHARS_PER_TOKEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContextUsage {
+    pub tokens: u64,
+    pub limit: u64,
+}
+
+impl ContextUsage {
+    /// `messages` are oldest first and `stats` are keyed by their index
+    pub fn estimate(
+        system: Option<&str>,
+        messages: &[Message],
+        stats: &HashMap<usize, GenerationStats>,
+        num_ctx: Option<u64>,
+    ) -> Self {
+        let estimated: u64 = system.map(estimate_tokens).unwrap_or_default()
+            + messages
+                .iter()
+                .enumerate()
+                .map(|(index, message)| match stats.get(&index) {
+                    Some(stats) => stats.eval_count,
+                    None => estimate_tokens(&message.content()),
+                })
+                .sum::<u64>();
+        // the last request's prompt was the whole conversation before its response
+        let reported = messages
+            .len()
+            .checked_sub(1)
+            .and_then(|newest| stats.get(&newest))
+            .map(|stats| stats.prompt_eval_count + stats.eval_count)
+            .unwrap_or_default();
+
+        ContextUsage {
+            tokens: estimated.max(reported),
+            limit: num_ctx.unwrap_or(DEFAULT_NUM_CTX).max(1),
+        }
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.tokens as f64 / self.limit as f64
+    }
+
+    /// Close enough to the limit that the server may soon drop older messages
+    pub fn is_near_limit(&self) -> bool {
+        self.ratio() >= WARNING_RATIO
+    }
+}
+
+fn estimate_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN) as u64
+}
+
+#[extend::ext(name = ContextGauge)]
+pub impl<'a> Frame<'a> {
+    fn context_gauge(&mut self, parent: Rect, style: Style, usage: ContextUsage) {
+        let color = if usage.tokens >= usage.limit {
+            Color::Red
+        } else if usage.is_near_limit() {
+            Color::Yellow
+        } else {
+            Color::Green
+        };
+        let mut block = Block::bordered().title("context").style(style);
+        if usage.is_near_limit() {
+            block = block.title_bottom("older messages will be truncated");
+        }
+
+        let gauge = Gauge::default()
+            .block(block)
+            .gauge_style(Style::default().fg(color))
+            .ratio(usage.ratio().min(1.0))
+            .label(format!("~{} / {} tokens", usage.tokens, usage.limit));
+        self.render_widget(gauge, parent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn responses_are_counted_with_reported_tokens() {
+        let messages = [
+            Message::User("12345678".into()),
+            Message::Assistant("a long answer that took few tokens".into()),
+        ];
+        let mut stats = HashMap::new();
+        stats.insert(
+            1,
+            GenerationStats {
+                eval_count: 3,
+                ..Default::default()
+            },
+        );
+
+        let usage = ContextUsage::estimate(Some("1234"), &messages, &stats, Some(10));
+        assert_eq!(usage.tokens, 1 + 2 + 3);
+        assert!(!usage.is_near_limit());
+
+        stats.get_mut(&1).unwrap().prompt_eval_count = 6;
+        let usage = ContextUsage::estimate(Some("1234"), &messages, &stats, Some(10));
+        assert_eq!(usage.tokens, 9);
+        assert!(usage.is_near_limit());
+    }
+}
@@ -1,44 +1,141 @@
 use std::sync::Arc;
 
 use ratatui::{
-    layout::{Constraint, Layout, Rect},
+    layout::{Constraint, Layout, Position, Rect},
     style::Style,
     Frame,
 };
 
+use search::{SearchView as _, SearchViewModel};
+
 use crate::{
+    attachment::{self, AttachedFile, Attachment},
+    config::Defaults,
+    context::ContextSource,
     error::Result,
     lm::{Prompt, Response},
-    ollama::chat::{ChatRequest, Message},
+    ollama::{
+        chat::{ChatOptions, ChatRequest, Message},
+        ModelName,
+    },
+    session::{self, BranchPoint, Session, SessionId},
+    templates::Templates,
 };
 
 use super::{
-    event::{Action, InputMode},
+    event::{Action, InputMode, MouseAction},
+    form::{FormEvent, FormView as _, FormViewModel},
     input::{InputView as _, TextInputEvent, TextInputViewModel},
     messages::{view::MessagesView as _, MessagesEvent, MessagesViewModel},
+    popup::AppFileData,
     AppEvent, StyleExt as _,
 };
 
-#[derive(Default, Clone, Debug)]
+mod attach;
+mod branches;
+mod context;
+mod search;
+
+pub use attach::{AttachView, AttachViewModel};
+pub use branches::{BranchesView, BranchesViewModel};
+use context::{ContextGauge as _, ContextUsage};
+
+/// Width of the model parameters pane
+const PARAMS_WIDTH: u16 = 24;
+/// Prompts are saved under the view's name
+const HISTORY_NAME: &str = "chat";
+/// The input grows with multi-line drafts up to this height
+const INPUT_HEIGHT: std::ops::RangeInclusive<u16> = 5..=12;
+/// The newest messages are kept as they are when older ones are summarized
+const KEEP_MESSAGES: usize = 4;
+
+#[derive(Clone, Debug)]
 pub struct ChatViewModel {
+    session_id: SessionId,
+    /// Where the session was branched from
+    parent: Option<BranchPoint>,
+    model: ModelName,
+    /// Sent as the leading system message of each request
+    system_prompt: Option<Arc<str>>,
+    system_input: TextInputViewModel,
+    /// Sent as Ollama options with each request
+    options: ChatOptions,
+    params: FormViewModel,
     text_input: TextInputViewModel,
+    /// The index of the prompt that the input replaces when it's submitted
+    editing: Option<usize>,
+    /// Files to send with the next prompt
+    attachments: Vec<Attachment>,
+    /// Files sent with earlier prompts
+    attached: Vec<AttachedFile>,
+    /// Summarize older messages when the context window fills up
+    auto_summarize: bool,
+    /// Put before the first prompt of a session
+    default_context: Vec<ContextSource>,
     messages: MessagesViewModel,
+    search: SearchViewModel,
+    /// Expanded from slash commands in the prompt input
+    templates: Templates,
     active_view: Option<Pane>,
     focused_view: Pane,
+    /// Areas from the last draw, used to hit test mouse events
+    system_area: Rect,
+    input_area: Rect,
+    search_area: Rect,
+    messages_area: Rect,
+    params_area: Rect,
+}
+
+impl Default for ChatViewModel {
+    fn default() -> Self {
+        let options = ChatOptions::default();
+        ChatViewModel {
+            session_id: Default::default(),
+            parent: None,
+            model: Default::default(),
+            system_prompt: None,
+            system_input: Default::default(),
+            params: FormViewModel::new(options.fields()),
+            options,
+            text_input: TextInputViewModel::with_history(HISTORY_NAME),
+            editing: None,
+            attachments: Vec::new(),
+            attached: Vec::new(),
+            auto_summarize: false,
+            default_context: Vec::new(),
+            messages: Default::default(),
+            search: Default::default(),
+            templates: Templates::load(),
+            active_view: None,
+            focused_view: Default::default(),
+            system_area: Default::default(),
+            input_area: Default::default(),
+            search_area: Default::default(),
+            messages_area: Default::default(),
+            params_area: Default::default(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub enum Pane {
+    System,
     #[default]
     Input,
     Messages,
+    Params,
+    /// Only reachable with [`Action::Search`]
+    Search,
 }
 
 impl Pane {
     fn next(self) -> Pane {
         match self {
+            Pane::System => Pane::Input,
             Pane::Input => Pane::Messages,
-            Pane::Messages => Pane::Input,
+            Pane::Messages => Pane::Params,
+            Pane::Params => Pane::System,
+            Pane::Search => Pane::Input,
         }
     }
 }
@@ -50,6 +147,15 @@ pub enum ChatEvent {
     NextView,
     InputMode(InputMode),
     Submit(Arc<str>),
+    Compose(String),
+    /// Edit a previous prompt in the input
+    EditMessage {
+        index: usize,
+        prompt: Arc<str>,
+    },
+    MessageDeleted {
+        index: usize,
+    },
     Quit,
 }
 
@@ -57,6 +163,8 @@ impl From<MessagesEvent> for ChatEvent {
     fn from(value: MessagesEvent) -> Self {
         match value {
             MessagesEvent::Quit => ChatEvent::Quit,
+            MessagesEvent::Edit { index, prompt } => ChatEvent::EditMessage { index, prompt },
+            MessagesEvent::Deleted { index } => ChatEvent::MessageDeleted { index },
         }
     }
 }
@@ -67,16 +175,197 @@ impl From<TextInputEvent> for ChatEvent {
             TextInputEvent::Submit(message) => ChatEvent::Submit(message),
             TextInputEvent::Quit => ChatEvent::Deactivate,
             TextInputEvent::InputMode(input_mode) => ChatEvent::InputMode(input_mode),
+            TextInputEvent::Compose(draft) => ChatEvent::Compose(draft),
         }
     }
 }
 
 impl ChatViewModel {
     pub fn handle_response(&mut self, response: Response) -> Result<()> {
-        self.messages.handle_response(response)
+        let finished = matches!(response, Response::Eos | Response::Cancelled);
+        if let Response::Summary { replaced, .. } = response {
+            attachment::replace_oldest(&mut self.attached, replaced);
+        }
+        self.messages.handle_response(response)?;
+        if finished {
+            self.save_session();
+        }
+        Ok(())
+    }
+
+    pub fn session(&self) -> Session {
+        Session {
+            id: self.session_id.clone(),
+            system_prompt: self.system_prompt.clone(),
+            options: self.options.clone(),
+            messages: self.messages.chronological(),
+            parent: self.parent.clone(),
+            attachments: self.attached.clone(),
+        }
+    }
+
+    /// Show a saved session, e.g. a branch picked from the tree
+    pub fn load_session(&mut self, session: Session) {
+        if !self.messages.is_stream_empty() {
+            tracing::warn!("unable to switch sessions while a response is streaming");
+            return;
+        }
+        self.session_id = session.id;
+        self.parent = session.parent;
+        self.editing = None;
+        self.system_prompt = session.system_prompt;
+        self.system_input
+            .set_text(self.system_prompt.as_deref().unwrap_or_default());
+        self.params = FormViewModel::new(session.options.fields());
+        self.options = session.options;
+        self.messages.set_messages(session.messages);
+        self.attached = session.attachments;
+    }
+
+    /// Send a file with the next prompt, or remove the files if there's none
+    pub fn attach(&mut self, attachment: Option<Attachment>) {
+        match attachment {
+            Some(attachment) => {
+                tracing::info!(source = ?attachment.source(), "attached");
+                self.attachments.push(attachment);
+            }
+            None => self.attachments.clear(),
+        }
+    }
+
+    pub fn set_auto_summarize(&mut self, auto_summarize: bool) {
+        self.auto_summarize = auto_summarize;
+    }
+
+    /// Use the model, system prompt and context from the config or the project.
+    /// The system prompt only fills in an empty one in a new session.
+    pub fn set_defaults(&mut self, defaults: &Defaults) {
+        self.model = defaults.model.clone().unwrap_or_default();
+        self.default_context = defaults.context.clone();
+        if let Some(system) = defaults.system.as_deref() {
+            if self.system_prompt.is_none() && self.messages.chronological().is_empty() {
+                self.system_prompt = Some(system.into());
+                self.system_input.set_text(system.to_string());
+            }
+        }
+    }
+
+    pub fn model(&self) -> &ModelName {
+        &self.model
+    }
+
+    fn context_usage(&self) -> ContextUsage {
+        ContextUsage::estimate(
+            self.system_prompt.as_deref(),
+            &self.messages.chronological(),
+            self.messages.stats(),
+            self.options.num_ctx,
+        )
+    }
+
+    /// Continue from the selected message, or the latest one, in a new session
+    fn branch(&mut self) {
+        let Some(message) = self
+            .messages
+            .selected_chronological()
+            .or_else(|| self.messages.chronological().len().checked_sub(1))
+        else {
+            return;
+        };
+        self.save_session();
+        let branch = self.session().branch(message);
+        self.load_session(branch);
+        self.save_session();
+    }
+
+    /// Answer the latest prompt again with a new seed
+    fn regenerate(&mut self) -> Option<AppEvent> {
+        let prompt = self.messages.pop_response()?;
+        Some(AppEvent::Submit(
+            self.chat_request(prompt, self.options.reseeded()),
+        ))
+    }
+
+    /// A request for `prompt` that's already at the end of the conversation
+    fn chat_request(&self, prompt: Arc<str>, options: ChatOptions) -> Prompt {
+        let history = self.messages.history();
+        let summarize = (self.auto_summarize && self.context_usage().is_near_limit())
+            .then(|| history.len().saturating_sub(KEEP_MESSAGES))
+            // a summary of a single message saves nothing
+            .filter(|count| *count >= 2);
+        Prompt::Chat(ChatRequest {
+            prompt,
+            model: self.model.clone(),
+            system: self.system_prompt.clone(),
+            options,
+            history,
+            summarize,
+        })
+    }
+
+    fn submit_system_prompt(&mut self, system_prompt: Arc<str>) {
+        self.system_prompt = (!system_prompt.trim().is_empty()).then_some(system_prompt.clone());
+        // keep the system prompt visible so it can be edited
+        self.system_input.set_text(system_prompt.to_string());
+        self.save_session();
+    }
+
+    fn submit_params(&mut self) {
+        match ChatOptions::from_fields(self.params.values()) {
+            Ok(options) => {
+                self.options = options;
+                self.params.set_error(None);
+                self.save_session();
+            }
+            Err(error) => self.params.set_error(Some(error)),
+        }
+    }
+
+    fn save_session(&self) {
+        match self.session().save() {
+            Ok(path) => tracing::debug!(?path, "saved session"),
+            Err(error) => tracing::error!(%error, "unable to save session"),
+        }
     }
 
     pub async fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        if action == Action::Stop {
+            return Ok(Some(AppEvent::Submit(Prompt::Cancel)));
+        }
+
+        if self.active_view != Some(Pane::Search) {
+            match action {
+                Action::Search => {
+                    self.active_view = Some(Pane::Search);
+                    return Ok(Some(AppEvent::InputMode(InputMode::Edit)));
+                }
+                Action::Export => {
+                    let data = AppFileData::Session(self.session());
+                    return Ok(Some(AppEvent::SaveFile(data)));
+                }
+                Action::Latest => {
+                    self.messages.handle_action(action);
+                    return Ok(None);
+                }
+                Action::Regenerate => return Ok(self.regenerate()),
+                Action::Branch => {
+                    self.branch();
+                    return Ok(None);
+                }
+                Action::NextMatch => {
+                    self.search.next();
+                    self.select_match();
+                    return Ok(None);
+                }
+                Action::PreviousMatch => {
+                    self.search.previous();
+                    self.select_match();
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+
         if let Some(active_view) = self.active_view {
             match active_view {
                 Pane::Input => {
@@ -97,6 +386,53 @@ impl ChatViewModel {
                         Ok(None)
                     }
                 }
+                Pane::System => match self.system_input.handle_action(action)? {
+                    Some(TextInputEvent::Submit(system_prompt)) => {
+                        self.submit_system_prompt(system_prompt);
+                        Ok(None)
+                    }
+                    Some(TextInputEvent::InputMode(input_mode)) => {
+                        Ok(Some(AppEvent::InputMode(input_mode)))
+                    }
+                    Some(TextInputEvent::Compose(draft)) => Ok(Some(AppEvent::Compose(draft))),
+                    Some(TextInputEvent::Quit) => {
+                        self.active_view = None;
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                },
+                Pane::Params => match self.params.handle_action(action)? {
+                    Some(FormEvent::Submit) => {
+                        self.submit_params();
+                        Ok(None)
+                    }
+                    Some(FormEvent::InputMode(input_mode)) => {
+                        Ok(Some(AppEvent::InputMode(input_mode)))
+                    }
+                    Some(FormEvent::Quit) => {
+                        self.active_view = None;
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                },
+                Pane::Search => match self.search.input.handle_action(action)? {
+                    Some(TextInputEvent::Submit(query)) => {
+                        self.run_search(query);
+                        self.active_view = None;
+                        self.focused_view = Pane::Messages;
+                        Ok(Some(AppEvent::InputMode(InputMode::Normal)))
+                    }
+                    Some(TextInputEvent::InputMode(input_mode)) => {
+                        Ok(Some(AppEvent::InputMode(input_mode)))
+                    }
+                    Some(TextInputEvent::Quit) => {
+                        self.search.clear();
+                        self.messages.set_highlight(None);
+                        self.active_view = None;
+                        Ok(None)
+                    }
+                    Some(TextInputEvent::Compose(_)) | None => Ok(None),
+                },
             }
         } else {
             let chat_event = match action {
@@ -115,6 +451,71 @@ impl ChatViewModel {
         }
     }
 
+    /// Search the current session along with the saved ones
+    fn run_search(&mut self, query: Arc<str>) {
+        let mut sessions = match session::load_all() {
+            Ok(sessions) => sessions,
+            Err(error) => {
+                tracing::error!(%error, "unable to load saved sessions");
+                Vec::new()
+            }
+        };
+        sessions.retain(|session| session.id != self.session_id);
+        sessions.push(self.session());
+
+        self.search.search(query, &sessions);
+        self.messages.set_highlight(self.search.query().cloned());
+        self.select_match();
+    }
+
+    /// Select the current match in the messages pane
+    /// if it's from this session
+    fn select_match(&mut self) {
+        if let Some(search_match) = self.search.selected() {
+            if search_match.session == self.session_id {
+                self.messages.select_chronological(search_match.index);
+            }
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        let Some(pane) = self.pane_at(mouse.position()) else {
+            return Ok(None);
+        };
+
+        match (mouse, pane) {
+            (MouseAction::Click(_), pane) => {
+                self.focused_view = pane;
+                self.active_view = Some(pane);
+            }
+            (MouseAction::ScrollUp(_), Pane::Messages) => {
+                self.messages.handle_action(Action::Up);
+            }
+            (MouseAction::ScrollDown(_), Pane::Messages) => {
+                self.messages.handle_action(Action::Down);
+            }
+            (MouseAction::ScrollUp(_) | MouseAction::ScrollDown(_), _) => {}
+        }
+
+        Ok(None)
+    }
+
+    fn pane_at(&self, position: Position) -> Option<Pane> {
+        if self.system_area.contains(position) {
+            Some(Pane::System)
+        } else if self.input_area.contains(position) {
+            Some(Pane::Input)
+        } else if self.search_area.contains(position) {
+            Some(Pane::Search)
+        } else if self.messages_area.contains(position) {
+            Some(Pane::Messages)
+        } else if self.params_area.contains(position) {
+            Some(Pane::Params)
+        } else {
+            None
+        }
+    }
+
     fn handle_chat_event(&mut self, event: ChatEvent) -> Option<AppEvent> {
         match event {
             ChatEvent::Activate(pane) => {
@@ -123,6 +524,7 @@ impl ChatViewModel {
             }
             ChatEvent::Deactivate => {
                 self.active_view = None;
+                self.editing = None;
                 None
             }
             ChatEvent::NextView => {
@@ -130,16 +532,74 @@ impl ChatViewModel {
                 None
             }
             ChatEvent::Submit(prompt) => {
+                let is_first = self.editing == Some(0) || self.messages.chronological().is_empty();
+                let contexts = if is_first {
+                    self.default_context.as_slice()
+                } else {
+                    &[]
+                };
+                let prompt = crate::context::expand(&prompt, &self.templates)
+                    .and_then(|expanded| crate::context::prepend(contexts, &expanded));
+                let prompt: Arc<str> = match prompt {
+                    Ok(prompt) => prompt.into(),
+                    Err(error) => {
+                        tracing::error!(%error, "unable to add context to the prompt");
+                        // submitting clears the input
+                        self.text_input.set_text(prompt.to_string());
+                        return None;
+                    }
+                };
+                if let Some(index) = self.editing.take() {
+                    self.messages.truncate_chronological(index);
+                    self.attached.retain(|file| file.message < index);
+                }
+                let index = self.messages.chronological().len();
+                let prompt: Arc<str> = if self.attachments.is_empty() {
+                    prompt
+                } else {
+                    attachment::inject(&prompt, &self.attachments).into()
+                };
+                self.attached.extend(
+                    self.attachments
+                        .drain(..)
+                        .map(|attachment| attachment.attached_to(index)),
+                );
                 self.messages.push_message(Message::User(prompt.clone()));
-                let prompt = Prompt::Chat(ChatRequest {
-                    prompt,
-                    model: Default::default(),
-                    history: self.messages.history(),
-                });
-                Some(AppEvent::Submit(prompt))
+                Some(AppEvent::Submit(
+                    self.chat_request(prompt, self.options.clone()),
+                ))
+            }
+            ChatEvent::EditMessage { index, prompt } => {
+                if !self.messages.is_stream_empty() {
+                    return None;
+                }
+                self.editing = Some(index);
+                self.text_input.set_text(prompt.to_string());
+                self.focused_view = Pane::Input;
+                self.active_view = Some(Pane::Input);
+                Some(AppEvent::InputMode(InputMode::Edit))
+            }
+            ChatEvent::MessageDeleted { index } => {
+                attachment::remove_message(&mut self.attached, index);
+                self.save_session();
+                None
             }
             ChatEvent::Quit => Some(AppEvent::Deactivate),
             ChatEvent::InputMode(input_mode) => Some(AppEvent::InputMode(input_mode)),
+            ChatEvent::Compose(draft) => Some(AppEvent::Compose(draft)),
+        }
+    }
+
+    /// Replace the draft in the active input, e.g. with text from an external editor.
+    /// The prompt input is activated if no other input is.
+    pub fn set_draft(&mut self, draft: &str) {
+        match self.active_view {
+            Some(Pane::System) => self.system_input.set_text(draft),
+            _ => {
+                self.focused_view = Pane::Input;
+                self.active_view = Some(Pane::Input);
+                self.text_input.set_text(draft);
+            }
         }
     }
 }
@@ -147,9 +607,60 @@ impl ChatViewModel {
 #[extend::ext(name = ChatView)]
 pub impl<'a> Frame<'a> {
     fn chat_view(&mut self, parent: Rect, style: Style, view_model: &mut ChatViewModel) {
-        let vertical = Layout::vertical([Constraint::Max(5), Constraint::Min(1)]);
+        let searching = view_model.active_view == Some(Pane::Search);
+        let search_constraint = if searching {
+            Constraint::Length(3)
+        } else if view_model.search.has_results() {
+            Constraint::Max(8)
+        } else {
+            Constraint::Length(0)
+        };
+        // rows plus the border
+        let input_rows = view_model
+            .text_input
+            .row_count(parent.width.saturating_sub(2));
+        let input_height = u16::try_from(input_rows + 2)
+            .unwrap_or(u16::MAX)
+            .clamp(*INPUT_HEIGHT.start(), *INPUT_HEIGHT.end());
+        let vertical = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Max(input_height),
+            search_constraint,
+            Constraint::Min(1),
+        ]);
 
-        let [input_area, messages_area] = vertical.areas(parent);
+        let [system_area, input_area, search_area, bottom_area] = vertical.areas(parent);
+        let [messages_area, side_area] =
+            Layout::horizontal([Constraint::Min(1), Constraint::Length(PARAMS_WIDTH)])
+                .areas(bottom_area);
+        let [params_area, context_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).areas(side_area);
+        view_model.params_area = params_area;
+        view_model.system_area = system_area;
+        view_model.input_area = input_area;
+        view_model.search_area = search_area;
+        view_model.messages_area = messages_area;
+
+        let system_style = if view_model.focused_view == Pane::System {
+            if let Some(Pane::System) = view_model.active_view {
+                Style::active()
+            } else {
+                Style::focused()
+            }
+        } else {
+            style
+        };
+        let system_title = if view_model.system_prompt.is_some() {
+            "system prompt"
+        } else {
+            "system prompt (unset)"
+        };
+        self.titled_input_view(
+            system_area,
+            system_style,
+            system_title,
+            &view_model.system_input,
+        );
 
         let input_style = if view_model.focused_view == Pane::Input {
             if let Some(Pane::Input) = view_model.active_view {
@@ -160,7 +671,34 @@ pub impl<'a> Frame<'a> {
         } else {
             style
         };
-        self.input_view(input_area, input_style, &view_model.text_input);
+        if view_model.editing.is_some() {
+            self.titled_input_view(
+                input_area,
+                input_style,
+                "edit prompt (replaces it and the messages after it)",
+                &view_model.text_input,
+            );
+        } else if !view_model.attachments.is_empty() {
+            let names: Vec<String> = view_model
+                .attachments
+                .iter()
+                .map(Attachment::name)
+                .collect();
+            self.titled_input_view(
+                input_area,
+                input_style,
+                &format!("attached: {}", names.join(", ")),
+                &view_model.text_input,
+            );
+        } else {
+            self.input_view(input_area, input_style, &view_model.text_input);
+        }
+
+        if searching {
+            self.input_view(search_area, Style::active(), &view_model.search.input);
+        } else if view_model.search.has_results() {
+            self.search_results(search_area, style, &mut view_model.search);
+        }
 
         let messages_style = if view_model.focused_view == Pane::Messages {
             if let Some(Pane::Messages) = view_model.active_view {
@@ -172,5 +710,23 @@ pub impl<'a> Frame<'a> {
             style
         };
         self.messages_view(messages_area, messages_style, &mut view_model.messages);
+
+        let params_active = view_model.active_view == Some(Pane::Params);
+        let params_style = if params_active {
+            Style::active()
+        } else if view_model.focused_view == Pane::Params {
+            Style::focused()
+        } else {
+            style
+        };
+        self.form_view(
+            params_area,
+            params_style,
+            "model parameters",
+            params_active,
+            &view_model.params,
+        );
+
+        self.context_gauge(context_area, style, view_model.context_usage());
     }
 }
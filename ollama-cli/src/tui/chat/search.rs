@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style, Stylize as _},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState},
+    Frame,
+};
+
+use crate::{
+    ollama::chat::Message,
+    session::{Session, SessionId},
+    tui::input::TextInputViewModel,
+};
+
+/// Characters of a matching message shown in the results list
+const SNIPPET_LEN: usize = 80;
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchViewModel {
+    pub input: TextInputViewModel,
+    query: Option<Arc<str>>,
+    matches: Vec<SearchMatch>,
+    list_state: ListState,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    pub session: SessionId,
+    /// Index into the session's messages, oldest first
+    pub index: usize,
+    pub message: Message,
+}
+
+impl SearchViewModel {
+    pub fn query(&self) -> Option<&Arc<str>> {
+        self.query.as_ref()
+    }
+
+    pub fn has_results(&self) -> bool {
+        self.query.is_some()
+    }
+
+    /// Search `sessions` and select the most recent match
+    pub fn search(&mut self, query: Arc<str>, sessions: &[Session]) {
+        if query.trim().is_empty() {
+            self.clear();
+            return;
+        }
+        self.matches = find_matches(&query, sessions);
+        self.list_state.select(self.matches.len().checked_sub(1));
+        self.query = Some(query);
+    }
+
+    pub fn clear(&mut self) {
+        self.query = None;
+        self.matches.clear();
+        self.list_state.select(None);
+    }
+
+    pub fn selected(&self) -> Option<&SearchMatch> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.matches.get(index))
+    }
+
+    pub fn next(&mut self) -> Option<&SearchMatch> {
+        self.step(1)
+    }
+
+    pub fn previous(&mut self) -> Option<&SearchMatch> {
+        self.step(self.matches.len().saturating_sub(1))
+    }
+
+    /// Move forward by `offset` wrapping around at the ends
+    fn step(&mut self, offset: usize) -> Option<&SearchMatch> {
+        if self.matches.is_empty() {
+            return None;
+        }
+        let index = self
+            .list_state
+            .selected()
+            .map_or(0, |index| (index + offset) % self.matches.len());
+        self.list_state.select(Some(index));
+        self.selected()
+    }
+}
+
+/// Case insensitive search through the messages of every session
+pub fn find_matches(query: &str, sessions: &[Session]) -> Vec<SearchMatch> {
+    let query = query.to_lowercase();
+    sessions
+        .iter()
+        .flat_map(|session| {
+            session
+                .messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| message.content().to_lowercase().contains(&query))
+                .map(|(index, message)| SearchMatch {
+                    session: session.id.clone(),
+                    index,
+                    message: message.clone(),
+                })
+        })
+        .collect()
+}
+
+#[extend::ext(name = SearchView)]
+pub impl<'a> Frame<'a> {
+    fn search_results(&mut self, parent: Rect, style: Style, view_model: &mut SearchViewModel) {
+        let items = view_model.matches.iter().map(|search_match| {
+            let content = search_match.message.content();
+            let snippet: String = content
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .chars()
+                .take(SNIPPET_LEN)
+                .collect();
+            ListItem::new(Line::from_iter([
+                Span::styled(format!("{} ", search_match.session), Style::default().dim()),
+                Span::styled(search_match.message.role(), Style::default().bold()),
+                Span::from(format!(": {snippet}")),
+            ]))
+        });
+
+        let position = view_model
+            .list_state
+            .selected()
+            .map_or(0, |index| index + 1);
+        let title = format!(
+            "search: {} ({position}/{})",
+            view_model.query.as_deref().unwrap_or_default(),
+            view_model.matches.len()
+        );
+
+        let list = List::new(items)
+            .block(Block::bordered().title(title).style(style))
+            .highlight_style(
+                Style::default()
+                    .bg(style.fg.unwrap_or(Color::Cyan))
+                    .fg(style.bg.unwrap_or(Color::Black)),
+            );
+
+        self.render_stateful_widget(list, parent, &mut view_model.list_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_are_case_insensitive_across_sessions() {
+        let sessions = [
+            Session {
+                messages: vec![
+                    Message::User("What is Rust?".into()),
+                    Message::Assistant("A systems language".into()),
+                ],
+                ..Default::default()
+            },
+            Session {
+                messages: vec![Message::User("rust vs go".into())],
+                ..Default::default()
+            },
+        ];
+
+        let matches = find_matches("RUST", &sessions);
+        let indexes: Vec<usize> = matches.iter().map(|m| m.index).collect();
+        assert_eq!(indexes, [0, 0]);
+    }
+
+    #[test]
+    fn navigation_wraps_around() {
+        let session = Session {
+            messages: vec![
+                Message::User("one".into()),
+                Message::User("two".into()),
+                Message::User("done".into()),
+            ],
+            ..Default::default()
+        };
+        let mut search = SearchViewModel::default();
+        search.search("one".into(), &[session]);
+
+        assert_eq!(search.selected().map(|m| m.index), Some(2));
+        assert_eq!(search.next().map(|m| m.index), Some(0));
+        assert_eq!(search.previous().map(|m| m.index), Some(2));
+    }
+}
@@ -0,0 +1,13 @@
+//! Copy text with the OSC 52 escape sequence.
+//! Most terminals pass it on to the system clipboard, even over SSH.
+
+use std::io::Write as _;
+
+use base64::Engine as _;
+
+pub fn copy(text: &str) -> std::io::Result<()> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{encoded}\x07")?;
+    stdout.flush()
+}
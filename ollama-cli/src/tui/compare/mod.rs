@@ -0,0 +1,496 @@
+//! Run the same prompt on several models side by side
+
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, List, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{
+    error::{Error, Result},
+    lm::{Prompt, Response},
+    ollama::{generate::Request, stats::GenerationStats, ModelName},
+};
+
+use super::{
+    event::Action,
+    input::{InputView as _, TextInputEvent, TextInputViewModel},
+    popup::{AppFileData, ViewerViewModel},
+    scroll::{FollowScroll, PAUSED_TITLE},
+    widgets_ext::RectExt as _,
+    AppEvent, StyleExt as _,
+};
+
+/// Prompts are saved under the view's name
+const HISTORY_NAME: &str = "compare";
+/// Width of the model list
+const MODELS_WIDTH: u16 = 28;
+/// A comparison needs at least this many models
+const MIN_MODELS: usize = 2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Pane {
+    #[default]
+    Input,
+    Models,
+    Outputs,
+}
+
+impl Pane {
+    fn next(self) -> Pane {
+        match self {
+            Pane::Input => Pane::Models,
+            Pane::Models => Pane::Outputs,
+            Pane::Outputs => Pane::Input,
+        }
+    }
+
+    fn previous(self) -> Pane {
+        match self {
+            Pane::Input => Pane::Outputs,
+            Pane::Models => Pane::Input,
+            Pane::Outputs => Pane::Models,
+        }
+    }
+}
+
+/// One model's output and timings
+#[derive(Debug, Clone)]
+struct Run {
+    model: ModelName,
+    output: String,
+    scroll: FollowScroll,
+    started: Instant,
+    /// Wall clock time until the stream ended
+    elapsed: Option<Duration>,
+    stats: Option<GenerationStats>,
+}
+
+impl Run {
+    fn new(model: ModelName) -> Self {
+        Run {
+            model,
+            output: String::new(),
+            scroll: FollowScroll::default(),
+            started: Instant::now(),
+            elapsed: None,
+            stats: None,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.elapsed.is_none()
+    }
+
+    fn finish(&mut self) {
+        self.elapsed.get_or_insert_with(|| self.started.elapsed());
+    }
+
+    fn handle_response(&mut self, response: Response) {
+        match response {
+            Response::Token(token) => self.output.push_str(&token),
+            Response::Stats(stats) => self.stats = Some(stats),
+            Response::Error(error) => {
+                self.output.push_str(&format!("\n[error: {error}]"));
+                self.finish();
+            }
+            Response::Eos => self.finish(),
+            _ => {}
+        }
+    }
+
+    /// The server's stats and the total time, or the time so far while streaming
+    fn summary(&self) -> String {
+        let total = self.elapsed.unwrap_or_else(|| self.started.elapsed());
+        let total = format!("{:.1}s", total.as_secs_f64());
+        match (&self.stats, self.is_running()) {
+            (_, true) => format!("running {total}"),
+            (Some(stats), false) => format!("{stats}, total {total}"),
+            (None, false) => format!("total {total}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CompareViewModel {
+    input: TextInputViewModel,
+    models: Vec<ModelName>,
+    model_state: ListState,
+    /// The models to compare in the order they were picked
+    checked: Vec<ModelName>,
+    runs: Vec<Run>,
+    /// The run that scrolls and opens in the viewer
+    selected_run: usize,
+    error: Option<Arc<str>>,
+    active_pane: Option<Pane>,
+    focused_pane: Pane,
+}
+
+impl Default for CompareViewModel {
+    fn default() -> Self {
+        CompareViewModel {
+            input: TextInputViewModel::with_history(HISTORY_NAME),
+            models: Vec::new(),
+            model_state: ListState::default(),
+            checked: Vec::new(),
+            runs: Vec::new(),
+            selected_run: 0,
+            error: None,
+            active_pane: None,
+            focused_pane: Default::default(),
+        }
+    }
+}
+
+impl CompareViewModel {
+    pub fn handle_response(&mut self, response: Response) -> Result<()> {
+        match response {
+            Response::LocalModels(local_models) => {
+                self.models = local_models
+                    .into_iter()
+                    .map(|model| ModelName(model.name.into()))
+                    .collect();
+                if self.model_state.selected().is_none() && !self.models.is_empty() {
+                    self.model_state.select(Some(0));
+                }
+            }
+            Response::Compare { index, response } => {
+                let run = self.runs.get_mut(index).ok_or(Error::BadIndex {
+                    index,
+                    msg: "no run for comparison response",
+                })?;
+                run.handle_response(*response);
+            }
+            Response::Cancelled => {
+                for run in self.runs.iter_mut().filter(|run| run.is_running()) {
+                    run.output.push_str("\n[cancelled]");
+                    run.finish();
+                }
+            }
+            Response::Error(error) => self.error = Some(error),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check or uncheck the model under the cursor
+    fn toggle_selected_model(&mut self) {
+        let Some(model) = self
+            .model_state
+            .selected()
+            .and_then(|index| self.models.get(index))
+        else {
+            return;
+        };
+        match self.checked.iter().position(|checked| checked == model) {
+            Some(position) => {
+                self.checked.remove(position);
+            }
+            None => self.checked.push(model.clone()),
+        }
+    }
+
+    fn submit_prompt(&mut self, prompt: Arc<str>) -> Option<AppEvent> {
+        if self.checked.len() < MIN_MODELS {
+            self.error = Some(format!("check at least {MIN_MODELS} models to compare").into());
+            return None;
+        }
+
+        self.error = None;
+        self.runs = self.checked.iter().cloned().map(Run::new).collect();
+        self.selected_run = 0;
+        Some(AppEvent::Submit(Prompt::Compare {
+            models: self.checked.clone(),
+            request: Request {
+                prompt,
+                model: ModelName::default(),
+                params: Default::default(),
+            },
+        }))
+    }
+
+    /// Every output with its stats as Markdown
+    fn report(&self) -> String {
+        let mut report = String::new();
+        for run in &self.runs {
+            let _ = write!(
+                report,
+                "## {}\n\n_{}_\n\n{}\n\n",
+                run.model,
+                run.summary(),
+                run.output.trim()
+            );
+        }
+        report
+    }
+
+    /// Replace the draft in the prompt input, e.g. with text from an external editor
+    pub fn set_draft(&mut self, draft: &str) {
+        self.focused_pane = Pane::Input;
+        self.active_pane = Some(Pane::Input);
+        self.input.set_text(draft);
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        if action == Action::Stop {
+            return Ok(Some(AppEvent::Submit(Prompt::Cancel)));
+        }
+
+        let Some(pane) = self.active_pane else {
+            return match action {
+                Action::Up => {
+                    self.focused_pane = self.focused_pane.previous();
+                    Ok(None)
+                }
+                Action::Down => {
+                    self.focused_pane = self.focused_pane.next();
+                    Ok(None)
+                }
+                Action::Refresh => Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
+                Action::Enter => {
+                    self.active_pane = Some(self.focused_pane);
+                    Ok(None)
+                }
+                Action::Quit => Ok(Some(AppEvent::Deactivate)),
+                _ => Ok(None),
+            };
+        };
+
+        match pane {
+            Pane::Input => match self.input.handle_action(action)? {
+                Some(TextInputEvent::InputMode(input_mode)) => {
+                    Ok(Some(AppEvent::InputMode(input_mode)))
+                }
+                Some(TextInputEvent::Submit(input)) => Ok(self.submit_prompt(input)),
+                Some(TextInputEvent::Compose(draft)) => Ok(Some(AppEvent::Compose(draft))),
+                Some(TextInputEvent::Quit) => {
+                    self.active_pane = None;
+                    Ok(None)
+                }
+                None => Ok(None),
+            },
+            Pane::Models => {
+                match action {
+                    Action::Up => self.model_state.select_previous(),
+                    Action::Down => self.model_state.select_next(),
+                    Action::Enter => self.toggle_selected_model(),
+                    Action::Refresh => return Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
+                    Action::Quit | Action::Escape => self.active_pane = None,
+                    _ => {}
+                }
+                Ok(None)
+            }
+            Pane::Outputs => match action {
+                Action::Left | Action::LeftWord => {
+                    self.selected_run = self.selected_run.saturating_sub(1);
+                    Ok(None)
+                }
+                Action::Right | Action::RightWord => {
+                    self.selected_run =
+                        (self.selected_run + 1).min(self.runs.len().saturating_sub(1));
+                    Ok(None)
+                }
+                Action::Export if !self.runs.is_empty() => {
+                    Ok(Some(AppEvent::SaveFile(AppFileData::Output(self.report()))))
+                }
+                Action::Quit | Action::Escape => {
+                    self.active_pane = None;
+                    Ok(None)
+                }
+                action => {
+                    let Some(run) = self.runs.get_mut(self.selected_run) else {
+                        return Ok(None);
+                    };
+                    match action {
+                        Action::Up => run.scroll.up(),
+                        Action::Down => run.scroll.down(),
+                        Action::Beginning => run.scroll.top(),
+                        Action::End | Action::Latest => run.scroll.latest(),
+                        Action::Enter => {
+                            return Ok(Some(AppEvent::ViewText(ViewerViewModel::new(
+                                &run.model,
+                                &run.output,
+                            ))))
+                        }
+                        _ => {}
+                    }
+                    Ok(None)
+                }
+            },
+        }
+    }
+}
+
+#[extend::ext(name = CompareView)]
+pub impl<'a> Frame<'a> {
+    fn compare_view(&mut self, parent: Rect, style: Style, view_model: &mut CompareViewModel) {
+        let [input_area, results_area] =
+            Layout::vertical([Constraint::Percentage(20), Constraint::Min(1)]).areas(parent);
+        let [models_area, outputs_area] =
+            Layout::horizontal([Constraint::Length(MODELS_WIDTH), Constraint::Min(1)])
+                .areas(results_area);
+
+        let pane_style = |pane: Pane| {
+            if view_model.active_pane == Some(pane) {
+                Style::active()
+            } else if view_model.focused_pane == pane {
+                Style::focused()
+            } else {
+                style
+            }
+        };
+
+        self.input_view(input_area, pane_style(Pane::Input), &view_model.input);
+
+        let models_style = pane_style(Pane::Models);
+        let models = List::from_iter(view_model.models.iter().map(|model| {
+            let check = if view_model.checked.contains(model) {
+                "x"
+            } else {
+                " "
+            };
+            format!("[{check}] {model}")
+        }))
+        .block(
+            Block::bordered()
+                .title("models")
+                .title_bottom(view_model.error.as_deref().unwrap_or_default()),
+        )
+        .style(models_style)
+        .highlight_style(
+            models_style
+                .fg(models_style.bg.unwrap_or(Color::Black))
+                .bg(models_style.fg.unwrap_or(Color::White)),
+        );
+        self.render_stateful_widget(models, models_area, &mut view_model.model_state);
+
+        let outputs_style = pane_style(Pane::Outputs);
+        if view_model.runs.is_empty() {
+            let message = Paragraph::new(format!(
+                "check at least {MIN_MODELS} models and enter a prompt to compare them"
+            ))
+            .style(outputs_style)
+            .block(Block::bordered().title("outputs"));
+            self.render_widget(message, outputs_area);
+            return;
+        }
+
+        let run_areas = Layout::horizontal(
+            view_model
+                .runs
+                .iter()
+                .map(|_| Constraint::Fill(1))
+                .collect::<Vec<_>>(),
+        )
+        .split(outputs_area);
+        let selected_run = view_model.selected_run;
+        for (index, (run, area)) in view_model.runs.iter_mut().zip(run_areas.iter()).enumerate() {
+            let run_style = if index == selected_run {
+                outputs_style
+            } else {
+                style
+            };
+
+            // wrapped here so the scroll offset can be clamped to the wrapped lines
+            let lines = area.wrap_inside(&run.output);
+            let offset = run
+                .scroll
+                .offset(lines.len(), area.height.saturating_sub(2));
+            let mut block = Block::bordered()
+                .title(run.model.to_string())
+                .title_bottom(run.summary());
+            if !run.scroll.is_following() {
+                block = block.title_bottom(PAUSED_TITLE);
+            }
+
+            let output = Paragraph::new(lines.join("\n"))
+                .style(run_style)
+                .scroll((offset, 0))
+                .block(block);
+            self.render_widget(output, *area);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn compare_view(models: &[&str]) -> CompareViewModel {
+        let mut view_model = CompareViewModel {
+            models: models
+                .iter()
+                .map(|model| ModelName((*model).into()))
+                .collect(),
+            ..Default::default()
+        };
+        view_model.model_state.select(Some(0));
+        view_model
+    }
+
+    #[test]
+    fn compares_checked_models_in_order() {
+        let mut view_model = compare_view(&["llama3", "mistral", "phi3"]);
+        assert!(view_model.submit_prompt("hi".into()).is_none());
+        assert!(view_model.error.is_some());
+
+        view_model.model_state.select(Some(2));
+        view_model.toggle_selected_model();
+        view_model.model_state.select(Some(1));
+        view_model.toggle_selected_model();
+        view_model.model_state.select(Some(0));
+        view_model.toggle_selected_model();
+        view_model.toggle_selected_model();
+
+        let Some(AppEvent::Submit(Prompt::Compare { models, request })) =
+            view_model.submit_prompt("hi".into())
+        else {
+            panic!("expected a comparison");
+        };
+        assert_eq!(
+            models.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["phi3", "mistral"]
+        );
+        assert_eq!(request.prompt.as_ref(), "hi");
+        assert_eq!(view_model.runs.len(), 2);
+    }
+
+    #[test]
+    fn responses_go_to_their_model() {
+        let mut view_model = compare_view(&["a", "b"]);
+        view_model.runs = vec![
+            Run::new(ModelName("a".into())),
+            Run::new(ModelName("b".into())),
+        ];
+        let tagged = |index, response| Response::Compare {
+            index,
+            response: Box::new(response),
+        };
+
+        for response in [
+            tagged(1, Response::Token("fast".into())),
+            tagged(0, Response::Token("slow".into())),
+            tagged(1, Response::Eos),
+        ] {
+            view_model.handle_response(response).unwrap();
+        }
+        assert!(view_model
+            .handle_response(tagged(2, Response::Eos))
+            .is_err());
+        view_model.handle_response(Response::Cancelled).unwrap();
+
+        assert_eq!(view_model.runs[0].output, "slow\n[cancelled]");
+        assert_eq!(view_model.runs[1].output, "fast");
+        assert!(view_model.runs.iter().all(|run| !run.is_running()));
+        assert!(view_model.runs[1].summary().starts_with("total "));
+    }
+}
@@ -0,0 +1,351 @@
+use std::sync::Arc;
+
+use itertools::Itertools as _;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::Text,
+    widgets::{Block, List, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{
+    error::{Error, Result},
+    lm::{Prompt, Response},
+    ollama::{embeddings::Embedding, ModelName},
+};
+
+use super::{
+    event::Action,
+    input::{InputView as _, TextInputEvent, TextInputViewModel},
+    AppEvent, StyleExt as _,
+};
+
+/// The number of nearest neighbors to show for the selected embedding
+const NEIGHBOR_COUNT: usize = 5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Pane {
+    #[default]
+    Input,
+    Models,
+    Embeddings,
+}
+
+impl Pane {
+    fn next(self) -> Pane {
+        match self {
+            Pane::Input => Pane::Models,
+            Pane::Models => Pane::Embeddings,
+            Pane::Embeddings => Pane::Input,
+        }
+    }
+
+    fn previous(self) -> Pane {
+        match self {
+            Pane::Input => Pane::Embeddings,
+            Pane::Models => Pane::Input,
+            Pane::Embeddings => Pane::Models,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EmbeddingsViewModel {
+    input: TextInputViewModel,
+    models: Vec<ModelName>,
+    model_state: ListState,
+    /// Texts that have been embedded in this session
+    embeddings: Vec<Embedding>,
+    embedding_state: ListState,
+    error: Option<Arc<str>>,
+    active_pane: Option<Pane>,
+    focused_pane: Pane,
+}
+
+impl EmbeddingsViewModel {
+    pub fn handle_response(&mut self, response: Response) -> Result<()> {
+        match response {
+            Response::LocalModels(local_models) => {
+                self.models = local_models
+                    .into_iter()
+                    .map(|model| ModelName(model.name.into()))
+                    .collect();
+                if self.model_state.selected().is_none() && !self.models.is_empty() {
+                    self.model_state.select(Some(0));
+                }
+                Ok(())
+            }
+            Response::Embedding(embedding) => {
+                self.error = None;
+                self.embeddings.push(embedding);
+                self.embedding_state.select(Some(self.embeddings.len() - 1));
+                Ok(())
+            }
+            Response::Error(error) => {
+                self.error = Some(error);
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        if let Some(pane) = self.active_pane {
+            match pane {
+                Pane::Input => {
+                    Ok(self
+                        .input
+                        .handle_action(action)?
+                        .and_then(|event| match event {
+                            TextInputEvent::InputMode(input_mode) => {
+                                Some(AppEvent::InputMode(input_mode))
+                            }
+                            TextInputEvent::Submit(input) => {
+                                Some(AppEvent::Submit(Prompt::Embed {
+                                    model: self.selected_model(),
+                                    input,
+                                }))
+                            }
+                            TextInputEvent::Compose(draft) => Some(AppEvent::Compose(draft)),
+                            TextInputEvent::Quit => {
+                                self.active_pane = None;
+                                None
+                            }
+                        }))
+                }
+                Pane::Models => match action {
+                    Action::Up => {
+                        self.model_state.select_previous();
+                        Ok(None)
+                    }
+                    Action::Down => {
+                        self.model_state.select_next();
+                        Ok(None)
+                    }
+                    Action::Refresh => Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
+                    Action::Quit | Action::Escape | Action::Enter => {
+                        self.active_pane = None;
+                        Ok(None)
+                    }
+                    _ => Ok(None),
+                },
+                Pane::Embeddings => match action {
+                    Action::Up => {
+                        self.embedding_state.select_previous();
+                        Ok(None)
+                    }
+                    Action::Down => {
+                        self.embedding_state.select_next();
+                        Ok(None)
+                    }
+                    Action::Quit | Action::Escape | Action::Enter => {
+                        self.active_pane = None;
+                        Ok(None)
+                    }
+                    _ => Ok(None),
+                },
+            }
+        } else {
+            match action {
+                Action::Up | Action::Left => {
+                    self.focused_pane = self.focused_pane.previous();
+                    Ok(None)
+                }
+                Action::Down | Action::Right => {
+                    self.focused_pane = self.focused_pane.next();
+                    Ok(None)
+                }
+                Action::Refresh => Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
+                Action::Enter => {
+                    self.active_pane = Some(self.focused_pane);
+                    Ok(None)
+                }
+                Action::Quit => Ok(Some(AppEvent::Deactivate)),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    /// Replace the input with text from an external editor
+    pub fn set_draft(&mut self, draft: &str) {
+        self.input.set_text(draft);
+    }
+
+    pub fn selected_model(&self) -> ModelName {
+        self.model_state
+            .selected()
+            .and_then(|index| self.models.get(index))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn selected_embedding(&self) -> Option<&Embedding> {
+        self.embedding_state
+            .selected()
+            .and_then(|index| self.embeddings.get(index))
+    }
+
+    /// The most similar embeddings to `embedding` by cosine similarity,
+    /// ignoring embeddings of different dimensions.
+    fn nearest_neighbors(&self, embedding: &Embedding) -> Vec<(f32, &Embedding)> {
+        self.embeddings
+            .iter()
+            .filter(|other| !std::ptr::eq(*other, embedding))
+            .filter_map(|other| {
+                cosine_similarity(&embedding.vector, &other.vector)
+                    .map(|similarity| (similarity, other))
+            })
+            .sorted_by(|(a, _), (b, _)| b.total_cmp(a))
+            .take(NEIGHBOR_COUNT)
+            .collect()
+    }
+
+    fn details(&self) -> String {
+        if let Some(ref error) = self.error {
+            return format!("error: {error}");
+        }
+
+        let Some(embedding) = self.selected_embedding() else {
+            return "enter some text to embed".to_string();
+        };
+
+        let stats = VectorStats::new(&embedding.vector);
+        let neighbors = self
+            .nearest_neighbors(embedding)
+            .into_iter()
+            .map(|(similarity, other)| format!("{similarity:.4}  {}", other.input))
+            .join("\n");
+
+        format!(
+            "model: {}\ndimensions: {}\nnorm: {:.4}\nmean: {:.4}\nstd dev: {:.4}\nmin: {:.4}\nmax: {:.4}\n\nnearest neighbors:\n{neighbors}",
+            embedding.model,
+            embedding.vector.len(),
+            stats.norm,
+            stats.mean,
+            stats.std_dev,
+            stats.min,
+            stats.max,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct VectorStats {
+    norm: f32,
+    mean: f32,
+    std_dev: f32,
+    min: f32,
+    max: f32,
+}
+
+impl VectorStats {
+    fn new(vector: &[f32]) -> Self {
+        let len = vector.len().max(1) as f32;
+        let mean = vector.iter().sum::<f32>() / len;
+        let variance = vector.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / len;
+
+        VectorStats {
+            norm: norm(vector),
+            mean,
+            std_dev: variance.sqrt(),
+            min: vector.iter().copied().fold(f32::INFINITY, f32::min),
+            max: vector.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+/// Returns `None` if the vectors have different dimensions or a zero norm
+fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let norms = norm(a) * norm(b);
+    if norms == 0. {
+        return None;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    Some(dot / norms)
+}
+
+#[extend::ext(name = EmbeddingsView)]
+pub impl<'a> Frame<'a> {
+    fn embeddings_view(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        view_model: &mut EmbeddingsViewModel,
+    ) {
+        let [input_area, results_area] =
+            Layout::vertical([Constraint::Max(5), Constraint::Min(1)]).areas(parent);
+        let [models_area, embeddings_area, details_area] = Layout::horizontal([
+            Constraint::Percentage(20),
+            Constraint::Percentage(30),
+            Constraint::Min(1),
+        ])
+        .areas(results_area);
+
+        let pane_style = |pane: Pane| {
+            if view_model.active_pane == Some(pane) {
+                Style::active()
+            } else if view_model.focused_pane == pane {
+                Style::focused()
+            } else {
+                style
+            }
+        };
+        let highlight_style = |style: Style| {
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White))
+        };
+
+        self.input_view(input_area, pane_style(Pane::Input), &view_model.input);
+
+        let models_style = pane_style(Pane::Models);
+        let models = List::from_iter(view_model.models.iter().map(|model| model.to_string()))
+            .block(Block::bordered().title("models"))
+            .style(models_style)
+            .highlight_style(highlight_style(models_style));
+        self.render_stateful_widget(models, models_area, &mut view_model.model_state);
+
+        let embeddings_style = pane_style(Pane::Embeddings);
+        let embeddings = List::from_iter(
+            view_model
+                .embeddings
+                .iter()
+                .map(|embedding| Text::from(embedding.input.to_string())),
+        )
+        .block(Block::bordered().title("embeddings"))
+        .style(embeddings_style)
+        .highlight_style(highlight_style(embeddings_style));
+        self.render_stateful_widget(embeddings, embeddings_area, &mut view_model.embedding_state);
+
+        let details = Paragraph::new(view_model.details())
+            .wrap(Wrap { trim: false })
+            .style(style)
+            .block(Block::bordered().title("details"));
+        self.render_widget(details, details_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosine_similarity_of_parallel_vectors_is_one() {
+        let similarity = cosine_similarity(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]).unwrap();
+        assert!((similarity - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosine_similarity_requires_matching_dimensions() {
+        assert_eq!(cosine_similarity(&[1.0, 2.0], &[1.0, 2.0, 3.0]), None);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 2.0]), None);
+    }
+}
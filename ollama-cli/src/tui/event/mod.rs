@@ -1,11 +1,18 @@
 use std::collections::HashMap;
 
-use crossterm::event::{Event, KeyCode, KeyEvent};
+use crossterm::event::{Event, KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
 use keymap::KeyMap;
+use ratatui::layout::Position;
 use serde::{Deserialize, Serialize};
 use strum::EnumIter;
 
+use crate::error::{Error, Result};
+
 const DEFAULTS: &str = include_str!("../../../default_keymap.toml");
+/// Added to the keymap when the config sets `editing = "vi"`
+const VI_DEFAULTS: &str = include_str!("../../../default_vi_keymap.toml");
+/// The table that holds per-view bindings
+const VIEWS_KEY: &str = "views";
 
 #[derive(Debug, PartialEq, Deserialize)]
 pub struct EventProcessor {
@@ -25,22 +32,32 @@ impl EventProcessor {
         self.input_mode = input_mode;
     }
 
-    pub fn process(&self, event: Event) -> Action {
+    /// Map an event to an [`Action`] using the bindings for `view`
+    /// and falling back to the global bindings
+    pub fn process(&self, event: Event, view: &str) -> Action {
         match event {
             Event::FocusGained
             | Event::FocusLost
             | Event::Mouse(_)
             | Event::Paste(_)
             | Event::Resize(_, _) => Action::Nop,
-            Event::Key(key_event) => self.process_key_event(key_event),
+            Event::Key(key_event) => self.process_key_event(key_event, view),
         }
     }
 
-    pub fn process_key_event(&self, event: KeyEvent) -> Action {
+    pub fn process_key_event(&self, event: KeyEvent, view: &str) -> Action {
+        let key = KeyMap::from(event);
         self.definitions
-            .0
-            .get(&self.input_mode)
-            .and_then(|map| map.0.get(&KeyMap::from(event)))
+            .views
+            .get(view)
+            .and_then(|modes| modes.get(&self.input_mode))
+            .and_then(|map| map.0.get(&key))
+            .or_else(|| {
+                self.definitions
+                    .global
+                    .get(&self.input_mode)
+                    .and_then(|map| map.0.get(&key))
+            })
             .copied()
             .unwrap_or(match event.code {
                 KeyCode::Char(c) => Action::Unhandled(c),
@@ -49,6 +66,35 @@ impl EventProcessor {
     }
 }
 
+/// Mouse input is handled by position
+/// instead of going through the keymap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Click(Position),
+    ScrollUp(Position),
+    ScrollDown(Position),
+}
+
+impl MouseAction {
+    pub fn from_event(event: MouseEvent) -> Option<Self> {
+        let position = Position::new(event.column, event.row);
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => Some(MouseAction::Click(position)),
+            MouseEventKind::ScrollUp => Some(MouseAction::ScrollUp(position)),
+            MouseEventKind::ScrollDown => Some(MouseAction::ScrollDown(position)),
+            _ => None,
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        match self {
+            MouseAction::Click(position)
+            | MouseAction::ScrollUp(position)
+            | MouseAction::ScrollDown(position) => *position,
+        }
+    }
+}
+
 #[derive(
     Default,
     Debug,
@@ -67,10 +113,100 @@ pub enum InputMode {
     #[default]
     Normal,
     Edit,
+    /// Selecting text in an input, like vi's visual mode
+    Visual,
 }
 
+pub type ModeMap = HashMap<InputMode, ActionMap>;
+
+/// Key bindings for each [`InputMode`].
+///
+/// Bindings in `[views.<view>.<mode>]` only apply to that view
+/// and take precedence over the global `[<mode>]` bindings.
 #[derive(Debug, Clone, PartialEq, Deserialize)]
-pub struct EventDefinitions(pub HashMap<InputMode, ActionMap>);
+pub struct EventDefinitions {
+    #[serde(default)]
+    pub views: HashMap<String, ModeMap>,
+    #[serde(flatten)]
+    pub global: ModeMap,
+}
+
+impl EventDefinitions {
+    /// Serialize to the same TOML format as [`DEFAULTS`].
+    /// The output is checked by parsing it back.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut table = mode_map_table(&self.global)?;
+
+        if !self.views.is_empty() {
+            let mut views = toml::Table::new();
+            for (view, modes) in &self.views {
+                views.insert(view.clone(), toml::Value::Table(mode_map_table(modes)?));
+            }
+            table.insert(VIEWS_KEY.to_string(), toml::Value::Table(views));
+        }
+
+        let contents = toml::to_string(&table)?;
+        let parsed: EventDefinitions = toml::from_str(&contents)?;
+        if &parsed != self {
+            return Err(Error::KeymapRoundTrip);
+        }
+
+        Ok(contents)
+    }
+
+    /// The vi bindings for text inputs
+    pub fn vi() -> Self {
+        toml::from_str(VI_DEFAULTS).expect("should be able to load vi keymaps")
+    }
+
+    /// Add the bindings from `other`, replacing any for the same keys
+    pub fn extend(&mut self, other: EventDefinitions) {
+        extend_mode_map(&mut self.global, other.global);
+        for (view, modes) in other.views {
+            extend_mode_map(self.views.entry(view).or_default(), modes);
+        }
+    }
+
+    /// The keys globally bound to an action in the given mode
+    pub fn keys_for(&self, mode: InputMode, action: Action) -> Vec<&KeyMap> {
+        self.global
+            .get(&mode)
+            .map(|action_map| {
+                action_map
+                    .0
+                    .iter()
+                    .filter(|(_key, bound)| **bound == action)
+                    .map(|(key, _action)| key)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn extend_mode_map(modes: &mut ModeMap, other: ModeMap) {
+    for (mode, action_map) in other {
+        modes.entry(mode).or_default().0.extend(action_map.0);
+    }
+}
+
+fn mode_map_table(modes: &ModeMap) -> Result<toml::Table> {
+    let mut table = toml::Table::new();
+    for (mode, action_map) in modes {
+        let mut bindings = toml::Table::new();
+        for (key, action) in &action_map.0 {
+            bindings.insert(key.to_string(), toml::Value::try_from(action)?);
+        }
+        table.insert(mode_key(*mode)?, toml::Value::Table(bindings));
+    }
+    Ok(table)
+}
+
+fn mode_key(mode: InputMode) -> Result<String> {
+    match toml::Value::try_from(mode)? {
+        toml::Value::String(key) => Ok(key),
+        _ => Err(Error::KeymapRoundTrip),
+    }
+}
 
 impl Default for EventDefinitions {
     fn default() -> Self {
@@ -93,10 +229,10 @@ impl From<Action> for ActionDefinition {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 pub struct ActionMap(pub HashMap<KeyMap, Action>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, strum::Display, EnumIter)]
 #[serde(rename_all = "snake_case")]
 pub enum Action {
     Beginning,
@@ -112,8 +248,38 @@ pub enum Action {
     Popup,
     Help,
     Enter,
+    Newline,
+    Compose,
+    Undo,
+    Redo,
+    DeleteWordBack,
+    DeleteToEnd,
+    DeleteToBeginning,
+    Yank,
+    YankPop,
+    Visual,
+    Delete,
+    Change,
+    DeleteChar,
+    Copy,
     Escape,
     Backspace,
+    Stop,
+    Search,
+    Export,
+    Create,
+    Duplicate,
+    Running,
+    Hosts,
+    Templates,
+    NextMatch,
+    PreviousMatch,
+    Latest,
+    Regenerate,
+    Branch,
+    Branches,
+    Attach,
+    LastCommand,
     Quit,
     #[serde(skip)]
     Unhandled(char),
@@ -122,10 +288,85 @@ pub enum Action {
 
 #[cfg(test)]
 mod tests {
+    use crossterm::event::KeyModifiers;
+
     use super::*;
 
     #[test]
     fn load_default_keymap() {
         let _event_definitions: EventDefinitions = toml::from_str(DEFAULTS).unwrap();
     }
+
+    #[test]
+    fn view_bindings_fall_back_to_global() {
+        let definitions: EventDefinitions = toml::from_str(
+            r#"
+            [normal]
+            r = "refresh"
+            q = "quit"
+
+            [views.chat.normal]
+            r = "stop"
+            "#,
+        )
+        .unwrap();
+        let processor = EventProcessor::new(definitions);
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+
+        assert_eq!(processor.process_key_event(key('r'), "chat"), Action::Stop);
+        assert_eq!(
+            processor.process_key_event(key('r'), "models"),
+            Action::Refresh
+        );
+        assert_eq!(processor.process_key_event(key('q'), "chat"), Action::Quit);
+    }
+
+    #[test]
+    fn vi_bindings_fill_in_the_keymap() {
+        let mut definitions = EventDefinitions::vi();
+        definitions.extend(EventDefinitions::default());
+        let mut processor = EventProcessor::new(definitions);
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+
+        assert_eq!(
+            processor.process_key_event(key('d'), "chat"),
+            Action::Delete
+        );
+        assert_eq!(
+            processor.process_key_event(key('d'), "models"),
+            Action::Duplicate
+        );
+        processor.input_mode(InputMode::Visual);
+        assert_eq!(processor.process_key_event(key('y'), "chat"), Action::Copy);
+    }
+
+    #[test]
+    fn mouse_events_map_to_positions() {
+        let event = |kind| MouseEvent {
+            kind,
+            column: 3,
+            row: 7,
+            modifiers: KeyModifiers::NONE,
+        };
+        let position = Position::new(3, 7);
+
+        assert_eq!(
+            MouseAction::from_event(event(MouseEventKind::Down(MouseButton::Left))),
+            Some(MouseAction::Click(position))
+        );
+        assert_eq!(
+            MouseAction::from_event(event(MouseEventKind::ScrollDown)),
+            Some(MouseAction::ScrollDown(position))
+        );
+        assert_eq!(MouseAction::from_event(event(MouseEventKind::Moved)), None);
+    }
+
+    #[test]
+    fn default_keymap_round_trip() {
+        let event_definitions = EventDefinitions::default();
+        let contents = event_definitions.to_toml().unwrap();
+        let parsed: EventDefinitions = toml::from_str(&contents).unwrap();
+
+        assert_eq!(parsed, event_definitions);
+    }
 }
@@ -0,0 +1,176 @@
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::error::Result;
+
+use super::{
+    event::{Action, InputMode},
+    input::{InputView as _, TextInputEvent, TextInputViewModel},
+    StyleExt as _,
+};
+
+/// Height of a bordered single line input
+const FIELD_HEIGHT: u16 = 3;
+/// Borders of the form and two lines for an error
+const EXTRA_HEIGHT: u16 = 4;
+
+/// Labeled text fields that are edited one at a time.
+/// Up and down move between fields
+/// and submitting a field is left to the owner to validate.
+#[derive(Debug, Clone, Default)]
+pub struct FormViewModel {
+    fields: Vec<FormField>,
+    selected: usize,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct FormField {
+    label: String,
+    input: TextInputViewModel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormEvent {
+    InputMode(InputMode),
+    /// The selected field was submitted
+    Submit,
+    Quit,
+}
+
+impl FormViewModel {
+    pub fn new<L: ToString, V: ToString>(fields: impl IntoIterator<Item = (L, V)>) -> Self {
+        let fields = fields
+            .into_iter()
+            .map(|(label, value)| {
+                let mut field = FormField {
+                    label: label.to_string(),
+                    input: TextInputViewModel::default(),
+                };
+                field.set_value(value.to_string());
+                field
+            })
+            .collect();
+
+        FormViewModel {
+            fields,
+            ..Default::default()
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|field| (field.label.as_str(), field.input.input.as_str()))
+    }
+
+    pub fn set_error(&mut self, error: Option<String>) {
+        self.error = error;
+    }
+
+    /// Select the next field.
+    /// Returns false if the last field was already selected.
+    pub fn next_field(&mut self) -> bool {
+        if self.selected + 1 < self.fields.len() {
+            self.selected += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The height needed to show every field and an error
+    pub fn height(&self) -> u16 {
+        let fields: u16 = self.fields.len().try_into().unwrap_or(u16::MAX);
+        fields
+            .saturating_mul(FIELD_HEIGHT)
+            .saturating_add(EXTRA_HEIGHT)
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<FormEvent>> {
+        match action {
+            Action::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                return Ok(None);
+            }
+            Action::Down => {
+                self.selected = (self.selected + 1).min(self.fields.len().saturating_sub(1));
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let Some(field) = self.fields.get_mut(self.selected) else {
+            return Ok(match action {
+                Action::Quit => Some(FormEvent::Quit),
+                _ => None,
+            });
+        };
+
+        match field.input.handle_action(action)? {
+            Some(TextInputEvent::Submit(value)) => {
+                // submitting clears the input
+                field.set_value(value.to_string());
+                Ok(Some(FormEvent::Submit))
+            }
+            Some(TextInputEvent::InputMode(input_mode)) => {
+                Ok(Some(FormEvent::InputMode(input_mode)))
+            }
+            Some(TextInputEvent::Quit) => Ok(Some(FormEvent::Quit)),
+            Some(TextInputEvent::Compose(_)) | None => Ok(None),
+        }
+    }
+}
+
+impl FormField {
+    fn set_value(&mut self, value: String) {
+        self.input.cursor_position = value.chars().count();
+        self.input.input = value;
+    }
+}
+
+#[extend::ext(name = FormView)]
+pub impl<'a> Frame<'a> {
+    /// Fields are drawn with `style`
+    /// except for the selected one which is drawn as active
+    /// if `active` is set
+    fn form_view(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        title: &str,
+        active: bool,
+        view_model: &FormViewModel,
+    ) {
+        let block = Block::bordered().title(title).style(style);
+        let inner = block.inner(parent);
+        self.render_widget(block, parent);
+
+        let constraints = view_model
+            .fields
+            .iter()
+            .map(|_| Constraint::Length(FIELD_HEIGHT))
+            .chain(std::iter::once(Constraint::Min(0)));
+        let areas = Layout::vertical(constraints).split(inner);
+
+        for (i, field) in view_model.fields.iter().enumerate() {
+            let field_style = if active && i == view_model.selected {
+                Style::active()
+            } else {
+                style
+            };
+            self.titled_input_view(areas[i], field_style, &field.label, &field.input);
+        }
+
+        if let Some(error) = &view_model.error {
+            let error = Paragraph::new(error.as_str())
+                .style(Style::default().fg(Color::Red))
+                .wrap(Wrap { trim: true });
+            self.render_widget(error, areas[view_model.fields.len()]);
+        }
+    }
+}
@@ -1,48 +1,107 @@
+use std::sync::Arc;
+
 use ratatui::{
-    layout::{Constraint, Layout, Rect},
+    layout::{Constraint, Layout, Position, Rect},
     style::Style,
-    widgets::{Block, Paragraph, Wrap},
+    widgets::{Block, Paragraph},
     Frame,
 };
 
+use settings::GenerateSettings;
+
 use crate::{
+    config::Defaults,
+    context::{self, ContextSource},
     error::Result,
     lm::{Prompt, Response},
+    ollama::{generate::Request, ModelName},
+    templates::Templates,
 };
 
 use super::{
-    event::Action,
+    event::{Action, MouseAction},
+    form::{FormEvent, FormView as _, FormViewModel},
     input::{InputView, TextInputEvent, TextInputViewModel},
+    popup::ViewerViewModel,
+    scroll::{FollowScroll, PAUSED_TITLE},
+    widgets_ext::RectExt as _,
     AppEvent, StyleExt as _,
 };
 
+mod settings;
+
+/// Prompts are saved under the view's name
+const HISTORY_NAME: &str = "generate";
+/// Width of the parameter sidebar
+const PARAMS_WIDTH: u16 = 32;
+
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 enum Pane {
     #[default]
     Input,
     Output,
+    Params,
 }
 
 impl Pane {
     fn next(self) -> Pane {
         match self {
             Pane::Input => Pane::Output,
-            Pane::Output => Pane::Input,
+            Pane::Output => Pane::Params,
+            Pane::Params => Pane::Input,
         }
     }
 
     fn previous(self) -> Pane {
-        self.next()
+        match self {
+            Pane::Input => Pane::Params,
+            Pane::Output => Pane::Input,
+            Pane::Params => Pane::Output,
+        }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct GenerateViewModel {
+    /// The model, temperature, system prompt and other CLI flags
+    params: FormViewModel,
+    settings: GenerateSettings,
     input: TextInputViewModel,
+    /// Run again with the current settings on refresh
+    last_prompt: Option<Arc<str>>,
     output: String,
-    scroll_state: u16,
+    output_scroll: FollowScroll,
+    /// Expanded from slash commands in the prompt input
+    templates: Templates,
+    /// Put before every prompt
+    default_context: Vec<ContextSource>,
     active_pane: Option<Pane>,
     focused_pane: Pane,
+    /// Areas from the last draw, used to hit test mouse events
+    params_area: Rect,
+    input_area: Rect,
+    output_area: Rect,
+}
+
+impl Default for GenerateViewModel {
+    fn default() -> Self {
+        let settings = GenerateSettings::default();
+        GenerateViewModel {
+            params: FormViewModel::new(settings.fields()),
+            settings,
+            input: TextInputViewModel::with_history(HISTORY_NAME),
+            last_prompt: None,
+            output: String::new(),
+            output_scroll: Default::default(),
+            templates: Templates::load(),
+            default_context: Vec::new(),
+            active_pane: None,
+            focused_pane: Default::default(),
+            params_area: Default::default(),
+            input_area: Default::default(),
+            output_area: Default::default(),
+        }
+    }
 }
 
 impl GenerateViewModel {
@@ -52,6 +111,9 @@ impl GenerateViewModel {
                 self.output.push_str(arc.as_ref());
             }
             Response::Eos => {}
+            Response::Cancelled => {
+                self.output.push_str("\n[cancelled]");
+            }
             Response::Error(arc) => {
                 self.output = arc.to_string();
             }
@@ -60,47 +122,150 @@ impl GenerateViewModel {
         Ok(())
     }
 
+    /// Use the model, system prompt and context from the config or the project
+    pub fn set_defaults(&mut self, defaults: &Defaults) {
+        if let Some(model) = &defaults.model {
+            self.settings.model = model.clone();
+        }
+        if let Some(system) = &defaults.system {
+            self.settings.params.system = Some(system.clone());
+        }
+        self.default_context = defaults.context.clone();
+        self.params = FormViewModel::new(self.settings.fields());
+    }
+
+    pub fn model(&self) -> &ModelName {
+        &self.settings.model
+    }
+
+    fn submit_params(&mut self) {
+        match GenerateSettings::from_fields(self.params.values()) {
+            Ok(settings) => {
+                self.settings = settings;
+                self.params.set_error(None);
+            }
+            Err(error) => self.params.set_error(Some(error)),
+        }
+    }
+
+    /// Run `prompt` with the current settings, replacing the output.
+    /// Context commands are run again each time.
+    fn submit_prompt(&mut self, prompt: Arc<str>) -> Option<AppEvent> {
+        self.last_prompt = Some(prompt.clone());
+        self.output.clear();
+        self.output_scroll.latest();
+        let prompt = context::expand(&prompt, &self.templates)
+            .and_then(|expanded| context::prepend(&self.default_context, &expanded));
+        match prompt {
+            Ok(prompt) => Some(AppEvent::Submit(Prompt::Generate(Request {
+                prompt: prompt.into(),
+                model: self.settings.model.clone(),
+                params: self.settings.params.clone(),
+            }))),
+            Err(error) => {
+                self.output = format!("unable to add context to the prompt: {error}");
+                None
+            }
+        }
+    }
+
+    /// Replace the draft in the prompt input, e.g. with text from an external editor
+    pub fn set_draft(&mut self, draft: &str) {
+        self.focused_pane = Pane::Input;
+        self.active_pane = Some(Pane::Input);
+        self.input.set_text(draft);
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        let Some(pane) = self.pane_at(mouse.position()) else {
+            return Ok(None);
+        };
+
+        match (mouse, pane) {
+            (MouseAction::Click(_), pane) => {
+                self.focused_pane = pane;
+                self.active_pane = Some(pane);
+            }
+            (MouseAction::ScrollUp(_), Pane::Output) => self.output_scroll.up(),
+            (MouseAction::ScrollDown(_), Pane::Output) => self.output_scroll.down(),
+            (MouseAction::ScrollUp(_) | MouseAction::ScrollDown(_), _) => {}
+        }
+
+        Ok(None)
+    }
+
+    fn pane_at(&self, position: Position) -> Option<Pane> {
+        [
+            (self.params_area, Pane::Params),
+            (self.input_area, Pane::Input),
+            (self.output_area, Pane::Output),
+        ]
+        .into_iter()
+        .find_map(|(area, pane)| area.contains(position).then_some(pane))
+    }
+
     pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        if action == Action::Stop {
+            return Ok(Some(AppEvent::Submit(Prompt::Cancel)));
+        }
+        if action == Action::Latest {
+            self.output_scroll.latest();
+            return Ok(None);
+        }
+
         if let Some(pane) = &self.active_pane {
             match pane {
-                Pane::Input => {
-                    Ok(self.input.handle_action(action)?.and_then(
-                        |input_action| match input_action {
-                            TextInputEvent::InputMode(input_mode) => {
-                                Some(AppEvent::InputMode(input_mode))
-                            }
-                            TextInputEvent::Submit(input) => {
-                                Some(AppEvent::Submit(Prompt::Generate(input)))
-                            }
-                            TextInputEvent::Quit => {
-                                self.active_pane = None;
-                                None
-                            }
-                        },
-                    ))
-                }
+                Pane::Params => match self.params.handle_action(action)? {
+                    Some(FormEvent::InputMode(input_mode)) => {
+                        Ok(Some(AppEvent::InputMode(input_mode)))
+                    }
+                    Some(FormEvent::Submit) => {
+                        self.submit_params();
+                        Ok(None)
+                    }
+                    Some(FormEvent::Quit) => {
+                        self.active_pane = None;
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                },
+                Pane::Input => match self.input.handle_action(action)? {
+                    Some(TextInputEvent::InputMode(input_mode)) => {
+                        Ok(Some(AppEvent::InputMode(input_mode)))
+                    }
+                    Some(TextInputEvent::Submit(input)) => Ok(self.submit_prompt(input)),
+                    Some(TextInputEvent::Compose(draft)) => Ok(Some(AppEvent::Compose(draft))),
+                    Some(TextInputEvent::Quit) => {
+                        self.active_pane = None;
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                },
                 Pane::Output => match action {
                     Action::Beginning => {
-                        self.scroll_state = 0;
+                        self.output_scroll.top();
                         Ok(None)
                     }
                     Action::End => {
-                        self.scroll_state = (self.output.lines().count() - 1) as u16;
+                        self.output_scroll.latest();
                         Ok(None)
                     }
                     Action::Up => {
-                        self.scroll_state = self.scroll_state.saturating_sub(1);
+                        self.output_scroll.up();
                         Ok(None)
                     }
                     Action::Down => {
-                        self.scroll_state = self.scroll_state.saturating_add(1);
+                        self.output_scroll.down();
                         Ok(None)
                     }
                     Action::Quit | Action::Escape => {
                         self.active_pane = None;
                         Ok(None)
                     }
-                    Action::Enter => todo!(),
+                    Action::Enter => Ok(Some(AppEvent::ViewText(ViewerViewModel::new(
+                        "output",
+                        &self.output,
+                    )))),
                     _ => Ok(None),
                 },
             }
@@ -114,7 +279,10 @@ impl GenerateViewModel {
                     self.focused_pane = self.focused_pane.next();
                     Ok(None)
                 }
-                Action::Refresh => todo!(),
+                Action::Refresh => Ok(self
+                    .last_prompt
+                    .clone()
+                    .and_then(|prompt| self.submit_prompt(prompt))),
                 Action::Enter => {
                     self.active_pane = Some(self.focused_pane);
                     Ok(None)
@@ -128,10 +296,31 @@ impl GenerateViewModel {
 
 #[extend::ext(name = GenerateView)]
 pub impl<'a> Frame<'a> {
-    fn generate_view(&mut self, parent: Rect, style: Style, view_model: &GenerateViewModel) {
-        let vertical = Layout::vertical([Constraint::Percentage(20), Constraint::Min(1)]);
+    fn generate_view(&mut self, parent: Rect, style: Style, view_model: &mut GenerateViewModel) {
+        let [main_area, params_area] =
+            Layout::horizontal([Constraint::Min(1), Constraint::Length(PARAMS_WIDTH)])
+                .areas(parent);
+        let [input_area, output_area] =
+            Layout::vertical([Constraint::Percentage(20), Constraint::Min(1)]).areas(main_area);
+        view_model.params_area = params_area;
+        view_model.input_area = input_area;
+        view_model.output_area = output_area;
 
-        let [input_area, output_area] = vertical.areas(parent);
+        let params_active = view_model.active_pane == Some(Pane::Params);
+        let params_style = if params_active {
+            Style::active()
+        } else if view_model.focused_pane == Pane::Params {
+            Style::focused()
+        } else {
+            style
+        };
+        self.form_view(
+            params_area,
+            params_style,
+            "parameters",
+            params_active,
+            &view_model.params,
+        );
 
         let input_style = if let Some(Pane::Input) = view_model.active_pane {
             Style::active()
@@ -151,10 +340,20 @@ pub impl<'a> Frame<'a> {
             style
         };
 
-        let output = Paragraph::new(view_model.output.as_str())
+        // wrapped here so the scroll offset can be clamped to the wrapped lines
+        let lines = output_area.wrap_inside(&view_model.output);
+        let offset = view_model
+            .output_scroll
+            .offset(lines.len(), output_area.height.saturating_sub(2));
+        let mut block = Block::bordered();
+        if !view_model.output_scroll.is_following() {
+            block = block.title_bottom(PAUSED_TITLE);
+        }
+
+        let output = Paragraph::new(lines.join("\n"))
             .style(output_style)
-            .wrap(Wrap { trim: true })
-            .block(Block::bordered());
+            .scroll((offset, 0))
+            .block(block);
 
         self.render_widget(output, output_area);
     }
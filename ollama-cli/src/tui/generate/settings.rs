@@ -0,0 +1,117 @@
+//! The fields of the generate view's parameter sidebar
+
+use serde_json::Value;
+
+use crate::ollama::{generate::GenerateParams, ModelName};
+
+const MODEL: &str = "model";
+const TEMPERATURE: &str = "temperature";
+const SYSTEM: &str = "system";
+/// Any other CLI flags, e.g. `--image ./cat.png --format json`
+const FLAGS: &str = "flags";
+
+/// The model and parameters to run prompts with
+#[derive(Debug, Clone, Default)]
+pub struct GenerateSettings {
+    pub model: ModelName,
+    pub params: GenerateParams,
+}
+
+impl GenerateSettings {
+    /// Labels and initial values for a form
+    pub fn fields(&self) -> [(&'static str, String); 4] {
+        [
+            (MODEL, self.model.to_string()),
+            (TEMPERATURE, String::new()),
+            (SYSTEM, self.params.system.clone().unwrap_or_default()),
+            (FLAGS, String::new()),
+        ]
+    }
+
+    /// Parse settings from the strings in [`Self::fields`].
+    /// The system and temperature fields take precedence over the flags.
+    pub fn from_fields<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> std::result::Result<Self, String> {
+        let mut model = None;
+        let mut temperature = None;
+        let mut system = None;
+        let mut flags = "";
+        for (name, value) in fields {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match name {
+                MODEL => {
+                    model = Some(
+                        value
+                            .parse::<ModelName>()
+                            .map_err(|error| format!("invalid model {value:?}: {error}"))?,
+                    )
+                }
+                TEMPERATURE => {
+                    temperature = Some(
+                        value
+                            .parse::<f32>()
+                            .map_err(|error| format!("invalid temperature {value:?}: {error}"))?,
+                    )
+                }
+                SYSTEM => system = Some(value.to_string()),
+                FLAGS => flags = value,
+                _ => return Err(format!("unknown setting {name}")),
+            }
+        }
+
+        let mut params = GenerateParams::parse_line(flags)?;
+        if let Some(system) = system {
+            params.system = Some(system);
+        }
+        if let Some(temperature) = temperature {
+            params.options.retain(|(name, _)| name != TEMPERATURE);
+            params
+                .options
+                .push((TEMPERATURE.to_string(), Value::from(temperature)));
+        }
+
+        Ok(GenerateSettings {
+            model: model.unwrap_or_default(),
+            params,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn fields_override_flags() {
+        let settings = GenerateSettings::from_fields([
+            (MODEL, "mistral"),
+            (TEMPERATURE, "0.5"),
+            (SYSTEM, ""),
+            (
+                FLAGS,
+                "--system 'be brief' --option temperature=1 --format json",
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(settings.model.to_string(), "mistral");
+        assert_eq!(settings.params.system.as_deref(), Some("be brief"));
+        assert_eq!(
+            settings.params.options,
+            vec![(TEMPERATURE.to_string(), Value::from(0.5f32))]
+        );
+        assert!(settings.params.format.is_some());
+    }
+
+    #[test]
+    fn invalid_fields_are_errors() {
+        assert!(GenerateSettings::from_fields([(TEMPERATURE, "warm")]).is_err());
+        assert!(GenerateSettings::from_fields([(FLAGS, "--nope")]).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Clear, List, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{config::NamedHost, error::Result, ollama::ModelHost};
+
+use super::{
+    event::{Action, MouseAction},
+    popup::popup_area,
+    AppEvent,
+};
+
+const HELP: &str = "enter: connect, q: close";
+
+/// Popup that picks which Ollama server to connect to
+#[derive(Debug, Clone)]
+pub struct HostsViewModel {
+    hosts: Vec<NamedHost>,
+    /// The host that the model context is connected to
+    current: ModelHost,
+    list_state: ListState,
+    area: Rect,
+}
+
+impl HostsViewModel {
+    pub fn new(hosts: Vec<NamedHost>, current: ModelHost) -> Self {
+        let selected = hosts
+            .iter()
+            .position(|host| host.url == current)
+            .unwrap_or_default();
+        HostsViewModel {
+            hosts,
+            current,
+            list_state: ListState::default().with_selected(Some(selected)),
+            area: Rect::default(),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => {
+                self.list_state.select_previous();
+                Ok(None)
+            }
+            Action::Down => {
+                self.list_state.select_next();
+                Ok(None)
+            }
+            Action::Enter => Ok(self
+                .list_state
+                .selected()
+                .and_then(|index| self.hosts.get(index))
+                .map(|host| AppEvent::Connect(host.url.clone()))),
+            Action::Hosts | Action::Quit | Action::Escape => Ok(Some(AppEvent::Deactivate)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            MouseAction::Click(_) => Ok(None),
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = HostsView)]
+pub impl<'a> Frame<'a> {
+    fn hosts_popup(&mut self, parent: Rect, style: Style, view_model: &mut HostsViewModel) {
+        let area = popup_area(parent, 50, 40);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title("hosts").style(style);
+        let [list_area, help_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(block.inner(area));
+        self.render_widget(block, area);
+
+        let hosts = view_model.hosts.iter().map(|host| {
+            let marker = if host.url == view_model.current {
+                "* "
+            } else {
+                "  "
+            };
+            Line::from(format!("{marker}{}: {}", host.name, host.url))
+        });
+        let list = List::from_iter(hosts).style(style).highlight_style(
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White)),
+        );
+        self.render_stateful_widget(list, list_area, &mut view_model.list_state);
+        self.render_widget(Paragraph::new(HELP).style(style), help_area);
+    }
+}
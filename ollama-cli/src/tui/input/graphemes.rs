@@ -0,0 +1,68 @@
+//! Cursor positions are character indices kept on grapheme cluster boundaries,
+//! so an emoji with a modifier or a letter with a combining accent moves and deletes as one.
+
+use unicode_segmentation::UnicodeSegmentation as _;
+
+/// The byte index of the character at `cursor`, or the end of `input`
+pub fn byte_index(input: &str, cursor: usize) -> usize {
+    input
+        .char_indices()
+        .nth(cursor)
+        .map(|(index, _)| index)
+        .unwrap_or(input.len())
+}
+
+/// The character index of the grapheme before `cursor`
+pub fn previous_boundary(input: &str, cursor: usize) -> usize {
+    let before = &input[..byte_index(input, cursor)];
+    before
+        .graphemes(true)
+        .next_back()
+        .map(|grapheme| cursor - grapheme.chars().count())
+        .unwrap_or(0)
+}
+
+/// The character index after the grapheme at `cursor`
+pub fn next_boundary(input: &str, cursor: usize) -> usize {
+    let after = &input[byte_index(input, cursor)..];
+    after
+        .graphemes(true)
+        .next()
+        .map(|grapheme| cursor + grapheme.chars().count())
+        .unwrap_or(cursor)
+}
+
+/// The grapheme at `cursor`, if it isn't at the end
+pub fn grapheme_at(input: &str, cursor: usize) -> Option<&str> {
+    input[byte_index(input, cursor)..].graphemes(true).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn steps_over_clusters() {
+        // e with a combining accent, a thumbs up with a skin tone, then x
+        let input = "e\u{301}\u{1f44d}\u{1f3fd}x";
+        assert_eq!(next_boundary(input, 0), 2);
+        assert_eq!(next_boundary(input, 2), 4);
+        assert_eq!(next_boundary(input, 4), 5);
+        assert_eq!(next_boundary(input, 5), 5);
+        assert_eq!(previous_boundary(input, 4), 2);
+        assert_eq!(previous_boundary(input, 2), 0);
+        assert_eq!(previous_boundary(input, 0), 0);
+        assert_eq!(grapheme_at(input, 2), Some("\u{1f44d}\u{1f3fd}"));
+        assert_eq!(grapheme_at(input, 5), None);
+    }
+
+    #[test]
+    fn indexes_wide_characters() {
+        let input = "日本語";
+        assert_eq!(byte_index(input, 1), 3);
+        assert_eq!(byte_index(input, 3), input.len());
+        assert_eq!(grapheme_at(input, 1), Some("本"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Prompts submitted from an input, recalled with up and down
+
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{config::history_path, fs_ext::read_file_to_string};
+
+/// Older prompts are dropped when the history is loaded
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Debug, Clone, Default)]
+pub struct PromptHistory {
+    /// Oldest first
+    entries: Vec<Arc<str>>,
+    /// The recalled entry, if any
+    position: Option<usize>,
+    /// The input from before the first recall, restored after the newest entry
+    draft: String,
+    /// Entries are appended here as JSON strings, one per line.
+    /// History that isn't persisted is kept until the TUI quits.
+    path: Option<PathBuf>,
+}
+
+impl PromptHistory {
+    /// Load the history saved for `name`, e.g. the view's name
+    pub fn load(name: &str) -> Self {
+        let path = match history_path(name) {
+            Ok(path) => path,
+            Err(error) => {
+                tracing::warn!(%error, name, "unable to find prompt history");
+                return PromptHistory::default();
+            }
+        };
+
+        let mut entries = if path.exists() {
+            match read_file_to_string(&path) {
+                Ok(contents) => parse_entries(&contents),
+                Err(error) => {
+                    tracing::warn!(%error, "unable to read prompt history");
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            if let Err(error) = rewrite(&path, &entries) {
+                tracing::warn!(%error, "unable to trim prompt history");
+            }
+        }
+
+        PromptHistory {
+            entries,
+            path: Some(path),
+            ..Default::default()
+        }
+    }
+
+    /// Add a submitted prompt unless it's blank or repeats the last one
+    pub fn push(&mut self, entry: Arc<str>) {
+        self.position = None;
+        self.draft.clear();
+        if entry.trim().is_empty() || self.entries.last() == Some(&entry) {
+            return;
+        }
+
+        if let Some(path) = &self.path {
+            if let Err(error) = append(path, &entry) {
+                tracing::warn!(%error, "unable to save prompt history");
+            }
+        }
+        self.entries.push(entry);
+    }
+
+    /// Recall the entry before the current one.
+    /// `input` is kept as the draft when recall starts.
+    pub fn previous(&mut self, input: &str) -> Option<&str> {
+        let position = match self.position {
+            Some(position) => position.saturating_sub(1),
+            None => {
+                self.draft = input.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+        self.position = Some(position);
+        self.entries.get(position).map(AsRef::as_ref)
+    }
+
+    /// Recall the entry after the current one, or the draft after the newest entry
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            self.entries.get(position).map(AsRef::as_ref)
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+/// Lines that aren't JSON strings are skipped
+fn parse_entries(contents: &str) -> Vec<Arc<str>> {
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<String>(line).ok())
+        .map(Into::into)
+        .collect()
+}
+
+fn append(path: &Path, entry: &str) -> anyhow::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+fn rewrite(path: &Path, entries: &[Arc<str>]) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn history(entries: &[&str]) -> PromptHistory {
+        PromptHistory {
+            entries: entries.iter().map(|entry| (*entry).into()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recall_returns_to_the_draft() {
+        let mut history = history(&["first", "second"]);
+
+        assert_eq!(history.previous("draft"), Some("second"));
+        assert_eq!(history.previous("second"), Some("first"));
+        assert_eq!(history.previous("first"), Some("first"));
+        assert_eq!(history.next(), Some("second"));
+        assert_eq!(history.next(), Some("draft"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn blank_and_repeated_prompts_are_skipped() {
+        let mut history = history(&["first"]);
+        history.push("first".into());
+        history.push("  ".into());
+        history.push("second".into());
+
+        assert_eq!(
+            history.entries,
+            vec!["first".into(), "second".into()] as Vec<Arc<str>>
+        );
+    }
+
+    #[test]
+    fn entries_can_span_lines() {
+        let entries = parse_entries("\"one\\ntwo\"\nnot json\n\"three\"\n");
+
+        assert_eq!(
+            entries,
+            vec!["one\ntwo".into(), "three".into()] as Vec<Arc<str>>
+        );
+    }
+}
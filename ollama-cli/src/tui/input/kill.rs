@@ -0,0 +1,125 @@
+//! Readline style kills, text deleted by a kill can be yanked back.
+//! Positions are character indices like the input's cursor.
+
+use std::collections::VecDeque;
+
+/// Older kills are dropped past this many
+const MAX_ENTRIES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Backward,
+    Forward,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KillRing {
+    /// Newest first
+    entries: VecDeque<String>,
+    /// The entry that was yanked last
+    yank_index: usize,
+}
+
+impl KillRing {
+    /// Save killed text.
+    /// Consecutive kills are joined into one entry, in the order the text was in.
+    pub fn kill(&mut self, text: String, direction: Direction, consecutive: bool) {
+        if text.is_empty() {
+            return;
+        }
+        match (self.entries.front_mut(), consecutive) {
+            (Some(last), true) => match direction {
+                Direction::Backward => last.insert_str(0, &text),
+                Direction::Forward => last.push_str(&text),
+            },
+            _ => {
+                self.entries.push_front(text);
+                self.entries.truncate(MAX_ENTRIES);
+            }
+        }
+    }
+
+    /// The newest kill
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// The kill before the one that was yanked last, wrapping around to the newest
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.entries.len();
+        self.entries.get(self.yank_index).map(String::as_str)
+    }
+}
+
+/// The start of the word before `cursor`, skipping whitespace before the cursor first
+pub fn word_start(input: &str, cursor: usize) -> usize {
+    let before: Vec<char> = input.chars().take(cursor).collect();
+    let mut start = before.len();
+    while start > 0 && before[start - 1].is_whitespace() {
+        start -= 1;
+    }
+    while start > 0 && !before[start - 1].is_whitespace() {
+        start -= 1;
+    }
+    start
+}
+
+/// The start of the line the cursor is on
+pub fn line_start(input: &str, cursor: usize) -> usize {
+    input
+        .chars()
+        .take(cursor)
+        .enumerate()
+        .filter(|(_, c)| *c == '\n')
+        .last()
+        .map(|(newline, _)| newline + 1)
+        .unwrap_or(0)
+}
+
+/// The end of the line the cursor is on.
+/// At the end of a line the newline is included, like readline's `kill-line` in Emacs.
+pub fn line_end(input: &str, cursor: usize) -> usize {
+    let mut after = input.chars().skip(cursor).peekable();
+    if after.peek() == Some(&'\n') {
+        return cursor + 1;
+    }
+    cursor + after.take_while(|c| *c != '\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn finds_boundaries() {
+        let input = "one two  \nthree four";
+        // after "two  "
+        assert_eq!(word_start(input, 9), 4);
+        assert_eq!(word_start(input, 2), 0);
+        // after "thr"
+        assert_eq!(line_start(input, 13), 10);
+        assert_eq!(line_start(input, 3), 0);
+        assert_eq!(line_end(input, 4), 9);
+        assert_eq!(line_end(input, 9), 10);
+        assert_eq!(line_end(input, 13), input.chars().count());
+    }
+
+    #[test]
+    fn consecutive_kills_are_joined() {
+        let mut ring = KillRing::default();
+        ring.kill("two".into(), Direction::Backward, false);
+        ring.kill("one ".into(), Direction::Backward, true);
+        ring.kill(" three".into(), Direction::Forward, true);
+        ring.kill("four".into(), Direction::Forward, false);
+
+        assert_eq!(ring.yank(), Some("four"));
+        assert_eq!(ring.yank_pop(), Some("one two three"));
+        assert_eq!(ring.yank_pop(), Some("four"));
+    }
+}
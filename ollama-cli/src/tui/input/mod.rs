@@ -1,4 +1,4 @@
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, cell::Cell, sync::Arc};
 
 use ratatui::{
     layout::Rect,
@@ -7,31 +7,110 @@ use ratatui::{
     widgets::{Block, Paragraph},
     Frame,
 };
+use unicode_segmentation::UnicodeSegmentation as _;
+use unicode_width::UnicodeWidthStr as _;
 
 use crate::error::Result;
 
 use super::{
     event::{Action, InputMode},
-    widgets_ext::RectExt,
+    scroll::keep_visible,
 };
 
+use graphemes::{byte_index, grapheme_at, next_boundary, previous_boundary};
+use history::PromptHistory;
+use kill::{line_end, line_start, word_start, Direction, KillRing};
+use undo::{Edit, Snapshot, UndoStack};
+use vi::{inner_word, line_content, whole_line, Operator, Pending};
+use wrap::{cursor_row, wrap};
+
+mod graphemes;
+mod history;
+mod kill;
+mod undo;
+mod vi;
+mod wrap;
+
 #[derive(Default, Debug, Clone)]
 pub struct TextInputViewModel {
     pub input: String,
     pub cursor_position: usize,
+    /// Submitted input, recalled with up and down
+    history: PromptHistory,
+    undo: UndoStack,
+    kill_ring: KillRing,
+    /// Whether the last action was a kill, consecutive kills are yanked together
+    killing: bool,
+    /// The range of the last yank, replaced by [`Action::YankPop`]
+    yanked: Option<(usize, usize)>,
+    /// A vi operator waiting for a motion
+    pending: Option<Pending>,
+    /// Where the selection started in vi's visual mode
+    visual: Option<usize>,
+    /// The first row drawn, kept between draws
+    /// so the view only scrolls when the cursor leaves it
+    scroll: Cell<usize>,
 }
 
 #[derive(Debug, Clone)]
 pub enum TextInputEvent {
     InputMode(InputMode),
     Submit(Arc<str>),
+    /// Edit the draft in an external editor
+    Compose(String),
     Quit,
 }
 
 impl TextInputViewModel {
+    /// An input that saves what's submitted under `name`
+    /// so it can be recalled the next time the TUI runs
+    pub fn with_history(name: &str) -> Self {
+        TextInputViewModel {
+            history: PromptHistory::load(name),
+            ..Default::default()
+        }
+    }
+
     pub fn handle_action(&mut self, action: Action) -> Result<Option<TextInputEvent>> {
+        if matches!(
+            action,
+            Action::Right
+                | Action::Left
+                | Action::Beginning
+                | Action::End
+                | Action::RightWord
+                | Action::LeftWord
+        ) {
+            self.undo.break_step();
+        }
+        let consecutive_kill = std::mem::take(&mut self.killing);
+        let yanked = self.yanked.take();
+        if let Some(pending) = self.pending.take() {
+            return Ok(self.complete_operator(pending, action));
+        }
+
         match action {
             Action::Edit => return Ok(Some(TextInputEvent::InputMode(InputMode::Edit))),
+            Action::Visual => {
+                self.visual = Some(self.cursor_position);
+                return Ok(Some(TextInputEvent::InputMode(InputMode::Visual)));
+            }
+            Action::Delete | Action::Change => {
+                let operator = if action == Action::Delete {
+                    Operator::Delete
+                } else {
+                    Operator::Change
+                };
+                match self.selection() {
+                    Some((start, end)) => return Ok(Some(self.operate(operator, start, end))),
+                    None => self.pending = Some(Pending::Motion(operator)),
+                }
+            }
+            Action::Copy => return Ok(self.copy_selection()),
+            Action::DeleteChar => {
+                let end = next_boundary(&self.input, self.cursor_position);
+                self.kill(self.cursor_position, end, false);
+            }
             Action::Quit => return Ok(Some(TextInputEvent::Quit)),
             Action::Right => self.move_cursor_right(),
             Action::Left => self.move_cursor_left(),
@@ -40,24 +119,232 @@ impl TextInputViewModel {
             Action::RightWord => self.move_cursor_word(),
             Action::LeftWord => self.move_cursor_back(),
             Action::Enter => return Ok(Some(self.submit_message())),
+            Action::Newline => self.enter_char('\n'),
+            Action::Compose => return Ok(Some(TextInputEvent::Compose(self.input.clone()))),
             Action::Escape => {
+                self.visual = None;
                 return Ok(Some(TextInputEvent::InputMode(InputMode::Normal)));
             }
+            Action::Up => self.recall_previous(),
+            Action::Down => self.recall_next(),
             Action::Backspace => self.delete_char(),
-            Action::Unhandled(to_insert) => self.enter_char(to_insert),
+            Action::DeleteWordBack => self.kill(
+                word_start(&self.input, self.cursor_position),
+                self.cursor_position,
+                consecutive_kill,
+            ),
+            Action::DeleteToEnd => self.kill(
+                self.cursor_position,
+                line_end(&self.input, self.cursor_position),
+                consecutive_kill,
+            ),
+            Action::DeleteToBeginning => self.kill(
+                line_start(&self.input, self.cursor_position),
+                self.cursor_position,
+                consecutive_kill,
+            ),
+            Action::Yank => self.yank(),
+            Action::YankPop => self.yank_pop(yanked),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::Unhandled(to_insert) if self.visual.is_none() => self.enter_char(to_insert),
             _ => {}
         }
         Ok(None)
     }
 
+    /// Replace the input, e.g. with a draft from an external editor
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.undo.record(Edit::Replace, self.snapshot());
+        self.input = text.into();
+        self.move_cursor_to_end();
+    }
+
+    /// The number of rows when wrapped to `width` columns
+    pub fn row_count(&self, width: u16) -> usize {
+        wrap(&self.input, width.into()).len()
+    }
+
     fn submit_message(&mut self) -> TextInputEvent {
         let message: Arc<str> = self.input.clone().into();
+        self.history.push(message.clone());
+        self.undo.clear();
         self.input.clear();
         self.reset_cursor();
 
         TextInputEvent::Submit(message)
     }
 
+    /// The characters selected in visual mode, including the grapheme under the cursor
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.visual?;
+        let start = anchor.min(self.cursor_position);
+        let end = next_boundary(&self.input, anchor.max(self.cursor_position));
+        Some((start, end))
+    }
+
+    /// Finish a vi command like `dw`, `dd` or `ciw`.
+    /// Anything that doesn't complete the command cancels it.
+    fn complete_operator(&mut self, pending: Pending, action: Action) -> Option<TextInputEvent> {
+        let range = match (pending, action) {
+            (Pending::Motion(Operator::Delete), Action::Delete) => {
+                whole_line(&self.input, self.cursor_position)
+            }
+            (Pending::Motion(Operator::Change), Action::Change) => {
+                line_content(&self.input, self.cursor_position)
+            }
+            (Pending::Motion(operator), Action::Edit) => {
+                self.pending = Some(Pending::Inner(operator));
+                return None;
+            }
+            (Pending::Motion(_), motion) => {
+                let target = self.motion_target(motion)?;
+                (
+                    self.cursor_position.min(target),
+                    self.cursor_position.max(target),
+                )
+            }
+            (Pending::Inner(_), Action::RightWord) => inner_word(&self.input, self.cursor_position),
+            (Pending::Inner(_), _) => return None,
+        };
+        let operator = match pending {
+            Pending::Motion(operator) | Pending::Inner(operator) => operator,
+        };
+        Some(self.operate(operator, range.0, range.1))
+    }
+
+    /// Where a motion after an operator goes
+    fn motion_target(&self, motion: Action) -> Option<usize> {
+        let target = match motion {
+            Action::Left => previous_boundary(&self.input, self.cursor_position),
+            Action::Right => next_boundary(&self.input, self.cursor_position),
+            Action::Beginning => 0,
+            Action::End => self.input.chars().count(),
+            Action::RightWord => move_cursor_word(&self.input, self.cursor_position),
+            Action::LeftWord => word_start(&self.input, self.cursor_position),
+            _ => return None,
+        };
+        Some(self.clamp_cursor(target))
+    }
+
+    /// Delete the characters from `start` to `end` into the kill ring,
+    /// changing them starts inserting
+    fn operate(&mut self, operator: Operator, start: usize, end: usize) -> TextInputEvent {
+        self.visual = None;
+        self.kill(start, end, false);
+        self.move_cursor_to(start);
+        match operator {
+            Operator::Delete => TextInputEvent::InputMode(InputMode::Normal),
+            Operator::Change => TextInputEvent::InputMode(InputMode::Edit),
+        }
+    }
+
+    /// Save the selection to the kill ring without deleting it
+    fn copy_selection(&mut self) -> Option<TextInputEvent> {
+        let (start, end) = self.selection()?;
+        let text = self.input.chars().skip(start).take(end - start).collect();
+        self.kill_ring.kill(text, Direction::Forward, false);
+        self.visual = None;
+        self.move_cursor_to(start);
+        Some(TextInputEvent::InputMode(InputMode::Normal))
+    }
+
+    /// Delete the characters from `start` to `end` into the kill ring
+    fn kill(&mut self, start: usize, end: usize, consecutive: bool) {
+        if start == end {
+            self.killing = consecutive;
+            return;
+        }
+        let direction = if end <= self.cursor_position {
+            Direction::Backward
+        } else {
+            Direction::Forward
+        };
+        let killed = self.replace_range(start, end, "");
+        self.kill_ring.kill(killed, direction, consecutive);
+        self.killing = true;
+    }
+
+    fn yank(&mut self) {
+        if let Some(text) = self.kill_ring.yank() {
+            let text = text.to_string();
+            self.insert_yank(self.cursor_position, self.cursor_position, &text);
+        }
+    }
+
+    /// Replace the last yank with an older kill
+    fn yank_pop(&mut self, yanked: Option<(usize, usize)>) {
+        let Some((start, end)) = yanked else {
+            return;
+        };
+        match self.kill_ring.yank_pop() {
+            Some(text) => {
+                let text = text.to_string();
+                self.insert_yank(start, end, &text);
+            }
+            None => self.yanked = Some((start, end)),
+        }
+    }
+
+    fn insert_yank(&mut self, start: usize, end: usize, text: &str) {
+        self.replace_range(start, end, text);
+        self.yanked = Some((start, self.cursor_position));
+    }
+
+    /// Replace the characters from `start` to `end`, leaving the cursor after `text`.
+    /// Returns the replaced text.
+    fn replace_range(&mut self, start: usize, end: usize, text: &str) -> String {
+        self.undo.record(Edit::Replace, self.snapshot());
+        let replaced = self.input.chars().skip(start).take(end - start).collect();
+        self.input = self
+            .input
+            .chars()
+            .take(start)
+            .chain(text.chars())
+            .chain(self.input.chars().skip(end))
+            .collect();
+        self.move_cursor_to(start + text.chars().count());
+        replaced
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            input: self.input.clone(),
+            cursor_position: self.cursor_position,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.input = snapshot.input;
+        self.move_cursor_to(snapshot.cursor_position);
+    }
+
+    fn undo(&mut self) {
+        if let Some(snapshot) = self.undo.undo(self.snapshot()) {
+            self.restore(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(snapshot) = self.undo.redo(self.snapshot()) {
+            self.restore(snapshot);
+        }
+    }
+
+    fn recall_previous(&mut self) {
+        if let Some(entry) = self.history.previous(&self.input) {
+            let entry = entry.to_string();
+            self.set_text(entry);
+        }
+    }
+
+    fn recall_next(&mut self) {
+        if let Some(entry) = self.history.next() {
+            let entry = entry.to_string();
+            self.set_text(entry);
+        }
+    }
+
     fn reset_cursor(&mut self) {
         self.cursor_position = 0;
     }
@@ -67,13 +354,11 @@ impl TextInputViewModel {
     }
 
     fn move_cursor_left(&mut self) {
-        let cursor_moved_left = self.cursor_position.saturating_sub(1);
-        self.cursor_position = self.clamp_cursor(cursor_moved_left);
+        self.move_cursor_to(previous_boundary(&self.input, self.cursor_position));
     }
 
     fn move_cursor_right(&mut self) {
-        let cursor_moved_right = self.cursor_position.saturating_add(1);
-        self.cursor_position = self.clamp_cursor(cursor_moved_right);
+        self.move_cursor_to(next_boundary(&self.input, self.cursor_position));
     }
 
     fn move_cursor_word(&mut self) {
@@ -96,55 +381,56 @@ impl TextInputViewModel {
         self.move_cursor_to(self.input.chars().count());
     }
 
-    fn byte_index(&self) -> usize {
-        self.input
-            .char_indices()
-            .map(|(i, _)| i)
-            .nth(self.cursor_position)
-            .unwrap_or(self.input.len())
-    }
-
+    /// Typing a combining character joins the grapheme before the cursor
     fn enter_char(&mut self, new_char: char) {
-        let index = self.byte_index();
+        self.undo.record(Edit::Insert(new_char), self.snapshot());
+        let index = byte_index(&self.input, self.cursor_position);
         self.input.insert(index, new_char);
-        self.move_cursor_right();
+        self.move_cursor_to(self.cursor_position + 1);
     }
 
+    /// Delete the grapheme before the cursor
     fn delete_char(&mut self) {
-        let is_not_cursor_leftmost = self.cursor_position != 0;
-
-        if is_not_cursor_leftmost {
-            let current_index = self.cursor_position;
-            let from_left_to_current_index = current_index - 1;
+        let start = previous_boundary(&self.input, self.cursor_position);
+        if start == self.cursor_position {
+            return;
+        }
+        if let Some(deleted) = self.input.chars().nth(start) {
+            self.undo.record(Edit::Delete(deleted), self.snapshot());
+        }
 
-            let before_char_to_delete = self.input.chars().take(from_left_to_current_index);
-            let after_char_to_delete = self.input.chars().skip(current_index);
+        let before_char_to_delete = self.input.chars().take(start);
+        let after_char_to_delete = self.input.chars().skip(self.cursor_position);
 
-            self.input = before_char_to_delete.chain(after_char_to_delete).collect();
-            self.move_cursor_left();
-        }
+        self.input = before_char_to_delete.chain(after_char_to_delete).collect();
+        self.move_cursor_to(start);
     }
 }
 
 fn move_cursor_word(input: &str, cursor_position: usize) -> usize {
-    let (before, after) = input.split_at(cursor_position);
-    after
-        .find(' ')
-        .map(|space_pos| before.len() + space_pos + 1)
-        .unwrap_or(input.len())
+    input
+        .chars()
+        .skip(cursor_position)
+        .position(|c| c == ' ')
+        .map(|space_pos| cursor_position + space_pos + 1)
+        .unwrap_or(input.chars().count())
 }
 
 fn move_cursor_back(input: &str, cursor_position: usize) -> usize {
-    let (before, _after) = input.split_at(cursor_position);
-    before
-        .rfind(' ')
-        .map(|space_pos| space_pos - 1)
+    input
+        .chars()
+        .take(cursor_position)
+        .collect::<Vec<_>>()
+        .iter()
+        .rposition(|c| *c == ' ')
+        .map(|space_pos| space_pos.saturating_sub(1))
         .unwrap_or(0)
 }
 
 #[derive(Debug, PartialEq, Clone)]
 struct CursorLine<'a> {
-    cursor_char: char,
+    /// The grapheme under the cursor, a space at the end of the line
+    cursor: Cow<'a, str>,
     left: Cow<'a, str>,
     right: Cow<'a, str>,
 }
@@ -153,126 +439,161 @@ struct CursorLine<'a> {
 impl<T: AsRef<str>> T {
     /// Single out the cursor in a line of text.
     /// This function is meant to locate the cursor
-    /// and the character under it
+    /// and the grapheme under it
     /// for highlighting.
+    /// `cursor_position` is a character index.
     fn single_out(&self, cursor_position: usize) -> CursorLine<'_> {
-        if let Some((left, right)) = self.as_ref().split_at_checked(cursor_position) {
-            if let Some((cursor, right)) = right.split_at_checked(1) {
-                CursorLine {
-                    cursor_char: cursor.chars().nth(0).unwrap_or(' '),
-                    left: left.into(),
-                    right: right.into(),
-                }
-            } else {
-                CursorLine {
-                    cursor_char: right.chars().nth(0).unwrap_or(' '),
-                    left: left.into(),
-                    right: "".into(),
-                }
-            }
-        } else {
-            CursorLine {
-                cursor_char: ' ',
-                left: "".into(),
+        let line = self.as_ref();
+        let index = byte_index(line, cursor_position);
+        let (left, right) = line.split_at(index);
+        match grapheme_at(line, cursor_position) {
+            Some(cursor) => CursorLine {
+                cursor: cursor.into(),
+                left: left.into(),
+                right: right[cursor.len()..].into(),
+            },
+            None => CursorLine {
+                cursor: " ".into(),
+                left: left.into(),
                 right: "".into(),
-            }
+            },
         }
     }
 }
 
+/// A wrapped line of the input, `start` is where it is in the input
 #[derive(Debug, Clone)]
 enum EditLine<'a> {
-    Normal(Cow<'a, str>),
+    Normal {
+        string: Cow<'a, str>,
+        start: usize,
+    },
     WithCursor {
         string: Cow<'a, str>,
+        start: usize,
         cursor_position: usize,
     },
 }
 
-impl<'a> From<Cow<'a, str>> for EditLine<'a> {
-    fn from(value: Cow<'a, str>) -> Self {
-        EditLine::Normal(value)
-    }
+/// The characters from `start` to `end`
+fn char_slice(input: &str, start: usize, end: usize) -> &str {
+    &input[byte_index(input, start)..byte_index(input, end)]
 }
 
-#[derive(Debug)]
-struct EditLinesBuilder<'a> {
-    consumed_chars: usize,
-    lines: Vec<EditLine<'a>>,
+/// The graphemes at the start of `text` that fit in `width` columns
+fn fit_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    let end = text
+        .grapheme_indices(true)
+        .find(|(_, grapheme)| {
+            used += grapheme.width();
+            used > width
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(text.len());
+    &text[..end]
 }
 
-impl<'a> EditLinesBuilder<'a> {
-    fn new<TLine: AsRef<str>>(lines: &[TLine]) -> Self {
-        EditLinesBuilder {
-            consumed_chars: 0,
-            lines: Vec::with_capacity(lines.len()),
+/// Wrap the input inside the border of `parent_view`.
+/// A cursor past the edge of a full row is drawn on the next row,
+/// or on the last column if it's on whitespace hanging past the edge.
+fn parse_edit_lines(input: &str, cursor_position: usize, parent_view: Rect) -> Vec<EditLine<'_>> {
+    let width = usize::from(parent_view.width.saturating_sub(2)).max(1);
+    let rows = wrap(input, width);
+    let cursor_row = cursor_row(&rows, cursor_position);
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+
+    for (index, row) in rows.iter().enumerate() {
+        let string = char_slice(input, row.start, row.end);
+        if index != cursor_row {
+            lines.push(EditLine::Normal {
+                string: string.into(),
+                start: row.start,
+            });
+            continue;
         }
-    }
 
-    fn build(self) -> Vec<EditLine<'a>> {
-        self.lines
+        let cursor_position = cursor_position - row.start;
+        let column = char_slice(string, 0, cursor_position).width();
+        if column < width {
+            lines.push(EditLine::WithCursor {
+                string: string.into(),
+                start: row.start,
+                cursor_position,
+            });
+        } else if row.start + cursor_position >= row.end {
+            lines.push(EditLine::Normal {
+                string: string.into(),
+                start: row.start,
+            });
+            lines.push(EditLine::WithCursor {
+                string: "".into(),
+                start: row.end,
+                cursor_position: 0,
+            });
+        } else {
+            let visible = fit_width(string, width - 1);
+            lines.push(EditLine::WithCursor {
+                string: visible.into(),
+                start: row.start,
+                cursor_position: visible.chars().count(),
+            });
+        }
     }
-}
-
-fn parse_edit_lines(input: &str, cursor_position: usize, parent_view: Rect) -> Vec<EditLine<'_>> {
-    let lines = parent_view.wrap_inside(input);
-    let builder = EditLinesBuilder::new(&lines);
     lines
-        .into_iter()
-        .fold(builder, |acc, line| {
-            let EditLinesBuilder {
-                consumed_chars,
-                lines: mut lines_builder,
-            } = acc;
-
-            let previous_consumed = consumed_chars;
-            let consumed_chars = consumed_chars + line.len() + 1;
-
-            let line = if cursor_position >= previous_consumed && cursor_position < consumed_chars {
-                let cursor_position = cursor_position - previous_consumed;
-                tracing::debug!(
-                    previous_consumed,
-                    consumed_chars,
-                    line.len = line.len(),
-                    cursor_position,
-                    input.len = input.len(),
-                );
-                EditLine::WithCursor {
-                    string: line,
-                    cursor_position,
-                }
-            } else {
-                EditLine::Normal(line)
-            };
-
-            lines_builder.push(line);
+}
 
-            EditLinesBuilder {
-                consumed_chars,
-                lines: lines_builder,
-            }
-        })
-        .build()
+/// Split `text` at `start` in the input into spans,
+/// highlighting the part that's in `selection`
+fn select_spans(text: &str, start: usize, selection: Option<(usize, usize)>) -> Vec<Span<'static>> {
+    let Some((selection_start, selection_end)) = selection else {
+        return vec![Span::from(text.to_string())];
+    };
+    let len = text.chars().count();
+    let selection_start = selection_start.saturating_sub(start).min(len);
+    let selection_end = selection_end.saturating_sub(start).min(len);
+    let style = Style::default().bg(Color::Blue);
+
+    let before: String = text.chars().take(selection_start).collect();
+    let selected: String = text
+        .chars()
+        .skip(selection_start)
+        .take(selection_end - selection_start)
+        .collect();
+    let after: String = text.chars().skip(selection_end).collect();
+    [
+        Span::from(before),
+        Span::from(selected).style(style),
+        Span::from(after),
+    ]
+    .into_iter()
+    .filter(|span| !span.content.is_empty())
+    .collect()
 }
 
 #[extend::ext]
 impl Vec<EditLine<'_>> {
-    fn render(&self) -> Vec<Line> {
+    fn render(&self, selection: Option<(usize, usize)>) -> Vec<Line> {
         let style = Style::default().bg(Color::White).fg(Color::Black);
 
         self.iter()
             .map(|line| match line {
-                EditLine::Normal(line) => Line::from(line.to_string()),
+                EditLine::Normal { string, start } => {
+                    Line::from(select_spans(string, *start, selection))
+                }
                 EditLine::WithCursor {
                     string,
+                    start,
                     cursor_position,
                 } => {
                     let cursor_line = string.single_out(*cursor_position);
-                    Line::from(vec![
-                        Span::from(cursor_line.left.to_string()),
-                        Span::from(cursor_line.cursor_char.to_string()).style(style),
-                        Span::from(cursor_line.right.to_string()),
-                    ])
+                    let right_start = start
+                        + cursor_line.left.chars().count()
+                        + cursor_line.cursor.chars().count();
+                    let mut spans = select_spans(&cursor_line.left, *start, selection);
+                    spans.push(Span::from(cursor_line.cursor.to_string()).style(style));
+                    spans.extend(select_spans(&cursor_line.right, right_start, selection));
+                    Line::from(spans)
                 }
             })
             .collect()
@@ -282,27 +603,45 @@ impl Vec<EditLine<'_>> {
 #[extend::ext(name = InputView)]
 pub impl<'a> Frame<'a> {
     fn input_view(&mut self, parent: Rect, style: Style, view_model: &TextInputViewModel) {
-        let width = parent.width;
-        let cursor_position: u16 = view_model
-            .cursor_position
-            .try_into()
-            .inspect_err(|err| tracing::warn!(%err, "unable to convert cursor position to u16"))
-            .unwrap_or(0);
-
-        let y = cursor_position / width;
+        let title = format!("cursor position: {}", view_model.cursor_position);
+        self.titled_input_view(parent, style, &title, view_model);
+    }
 
+    fn titled_input_view(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        title: &str,
+        view_model: &TextInputViewModel,
+    ) {
         let lines = parse_edit_lines(
             view_model.input.as_str(),
             view_model.cursor_position,
             parent,
         );
 
-        let input = Paragraph::new(lines.render())
+        // only scroll when the cursor's line would leave the view
+        let cursor_line = lines
+            .iter()
+            .position(|line| matches!(line, EditLine::WithCursor { .. }))
+            .unwrap_or(lines.len().saturating_sub(1));
+        let inner_height = usize::from(parent.height.saturating_sub(2));
+        let scroll = keep_visible(
+            view_model.scroll.get(),
+            cursor_line,
+            lines.len(),
+            inner_height,
+        );
+        view_model.scroll.set(scroll);
+        let y: u16 = scroll
+            .try_into()
+            .inspect_err(|err| tracing::warn!(%err, "unable to convert scroll offset to u16"))
+            .unwrap_or(0);
+
+        let input = Paragraph::new(lines.render(view_model.selection()))
             .scroll((y, 0))
             .style(style)
-            .block(
-                Block::bordered().title(format!("cursor position: {}", view_model.cursor_position)),
-            );
+            .block(Block::bordered().title(title));
 
         self.render_widget(input, parent);
     }
@@ -318,7 +657,7 @@ mod tests {
         let cursor_position: usize = 4; // t
 
         let expected = CursorLine {
-            cursor_char: 't',
+            cursor: "t".into(),
             left: "let ".into(),
             right: "here be light!".into(),
         };
@@ -334,7 +673,7 @@ mod tests {
         let cursor_position: usize = 0;
 
         let expected = CursorLine {
-            cursor_char: ' ',
+            cursor: " ".into(),
             left: "".into(),
             right: "".into(),
         };
@@ -350,7 +689,7 @@ mod tests {
         let cursor_position: usize = 5;
 
         let expected = CursorLine {
-            cursor_char: ' ',
+            cursor: " ".into(),
             left: line.into(),
             right: "".into(),
         };
@@ -360,6 +699,171 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn cursor_after_a_newline_is_on_the_next_line() {
+        let lines = parse_edit_lines("ab\ncd", 3, Rect::new(0, 0, 20, 5));
+
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(
+            lines[1],
+            EditLine::WithCursor {
+                cursor_position: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_split_cursor_wide_and_combined() {
+        let line = "日本語";
+        let expected = CursorLine {
+            cursor: "本".into(),
+            left: "日".into(),
+            right: "語".into(),
+        };
+        assert_eq!(line.single_out(1), expected);
+
+        let line = "cafe\u{301}!";
+        let expected = CursorLine {
+            cursor: "e\u{301}".into(),
+            left: "caf".into(),
+            right: "!".into(),
+        };
+        assert_eq!(line.single_out(3), expected);
+    }
+
+    #[test]
+    fn wide_characters_wrap_by_width() {
+        // 3 double width characters fit inside the border
+        let lines = parse_edit_lines("日本語日本語", 4, Rect::new(0, 0, 8, 5));
+
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(
+            lines[1],
+            EditLine::WithCursor {
+                start: 3,
+                cursor_position: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn cursor_after_a_full_row_gets_its_own_row() {
+        let lines = parse_edit_lines("abcd\nef", 4, Rect::new(0, 0, 6, 5));
+
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(
+            lines[1],
+            EditLine::WithCursor {
+                start: 4,
+                cursor_position: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn cursor_moves_and_deletes_graphemes() {
+        let mut input = TextInputViewModel::default();
+        input.set_text("a\u{1f44d}\u{1f3fd}b");
+        input.handle_action(Action::Left).unwrap();
+        input.handle_action(Action::Left).unwrap();
+        assert_eq!(input.cursor_position, 1);
+
+        input.handle_action(Action::Right).unwrap();
+        input.handle_action(Action::Backspace).unwrap();
+        assert_eq!(input.input, "ab");
+        assert_eq!(input.cursor_position, 1);
+
+        input.handle_action(Action::Unhandled('e')).unwrap();
+        input.handle_action(Action::Unhandled('\u{301}')).unwrap();
+        input.handle_action(Action::Left).unwrap();
+        assert_eq!(input.cursor_position, 1);
+    }
+
+    #[test]
+    fn undo_restores_deleted_text() {
+        let mut input = TextInputViewModel::default();
+        for c in "hi there".chars() {
+            input.handle_action(Action::Unhandled(c)).unwrap();
+        }
+        for _ in 0..5 {
+            input.handle_action(Action::Backspace).unwrap();
+        }
+        assert_eq!(input.input, "hi ");
+
+        input.handle_action(Action::Undo).unwrap();
+        assert_eq!(input.input, "hi there");
+        assert_eq!(input.cursor_position, 8);
+
+        input.handle_action(Action::Redo).unwrap();
+        assert_eq!(input.input, "hi ");
+    }
+
+    #[test]
+    fn yank_restores_killed_words() {
+        let mut input = TextInputViewModel::default();
+        input.set_text("one two three");
+        input.handle_action(Action::DeleteWordBack).unwrap();
+        input.handle_action(Action::DeleteWordBack).unwrap();
+        assert_eq!(input.input, "one ");
+
+        // moving the cursor starts a new kill
+        input.handle_action(Action::End).unwrap();
+        input.handle_action(Action::DeleteToBeginning).unwrap();
+        assert_eq!(input.input, "");
+
+        input.handle_action(Action::Yank).unwrap();
+        assert_eq!(input.input, "one ");
+        input.handle_action(Action::YankPop).unwrap();
+        assert_eq!(input.input, "two three");
+        assert_eq!(input.cursor_position, 9);
+    }
+
+    #[test]
+    fn vi_operators_delete_and_change() {
+        let mut input = TextInputViewModel::default();
+        input.set_text("one two\nthree four");
+        input.handle_action(Action::Beginning).unwrap();
+        input.handle_action(Action::Delete).unwrap();
+        input.handle_action(Action::RightWord).unwrap();
+        assert_eq!(input.input, "two\nthree four");
+
+        input.handle_action(Action::Delete).unwrap();
+        input.handle_action(Action::Delete).unwrap();
+        assert_eq!(input.input, "three four");
+
+        input.handle_action(Action::End).unwrap();
+        input.handle_action(Action::Change).unwrap();
+        input.handle_action(Action::Edit).unwrap();
+        let event = input.handle_action(Action::RightWord).unwrap();
+        assert!(matches!(
+            event,
+            Some(TextInputEvent::InputMode(InputMode::Edit))
+        ));
+        assert_eq!(input.input, "three ");
+
+        input.handle_action(Action::Yank).unwrap();
+        assert_eq!(input.input, "three four");
+    }
+
+    #[test]
+    fn visual_mode_deletes_the_selection() {
+        let mut input = TextInputViewModel::default();
+        input.set_text("hello world");
+        input.handle_action(Action::Beginning).unwrap();
+        input.handle_action(Action::Visual).unwrap();
+        input.handle_action(Action::Right).unwrap();
+        assert_eq!(input.selection(), Some((0, 2)));
+        // typing doesn't replace the selection
+        input.handle_action(Action::Unhandled('z')).unwrap();
+
+        input.handle_action(Action::Delete).unwrap();
+        assert_eq!(input.input, "llo world");
+        assert_eq!(input.selection(), None);
+    }
+
     #[test]
     fn test_move_word() {
         let line = "hello, world! i am here.";
@@ -0,0 +1,147 @@
+//! Undo and redo for a text input
+
+/// Older edits are dropped past this many undo steps
+const MAX_STEPS: usize = 100;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub input: String,
+    pub cursor_position: usize,
+}
+
+/// What an edit did, typing and deleting are undone a word at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Insert(char),
+    Delete(char),
+    /// Replacing some or all of the input, always its own step
+    Replace,
+}
+
+impl Edit {
+    /// Whether this edit continues the step of the `last` one
+    fn coalesces_with(self, last: Edit) -> bool {
+        // a word and the whitespace typed or deleted after it are one step
+        match (last, self) {
+            (Edit::Insert(last), Edit::Insert(next)) | (Edit::Delete(last), Edit::Delete(next)) => {
+                !last.is_whitespace() || next.is_whitespace()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UndoStack {
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The last edit, unless the cursor has moved since
+    last_edit: Option<Edit>,
+}
+
+impl UndoStack {
+    /// Record the input from before `edit`, unless the edit continues the last step
+    pub fn record(&mut self, edit: Edit, before: Snapshot) {
+        let coalesce = self
+            .last_edit
+            .is_some_and(|last_edit| edit.coalesces_with(last_edit));
+        if !coalesce {
+            self.undo.push(before);
+            if self.undo.len() > MAX_STEPS {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+        self.last_edit = Some(edit);
+    }
+
+    /// Start a new step with the next edit, e.g. after the cursor moves
+    pub fn break_step(&mut self) {
+        self.last_edit = None;
+    }
+
+    /// Returns the input to restore, `current` is kept for redo
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.undo.pop()?;
+        self.redo.push(current);
+        self.last_edit = None;
+        Some(snapshot)
+    }
+
+    /// Returns the input to restore, `current` is kept for undo
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.redo.pop()?;
+        self.undo.push(current);
+        self.last_edit = None;
+        Some(snapshot)
+    }
+
+    pub fn clear(&mut self) {
+        *self = UndoStack::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn snapshot(input: &str) -> Snapshot {
+        Snapshot {
+            input: input.to_string(),
+            cursor_position: input.chars().count(),
+        }
+    }
+
+    /// Type `text` after `start`, recording each character
+    fn type_text(stack: &mut UndoStack, start: &str, text: &str) -> String {
+        let mut input = start.to_string();
+        for c in text.chars() {
+            stack.record(Edit::Insert(c), snapshot(&input));
+            input.push(c);
+        }
+        input
+    }
+
+    #[test]
+    fn typing_is_undone_a_word_at_a_time() {
+        let mut stack = UndoStack::default();
+        let input = type_text(&mut stack, "", "hello big world");
+
+        let input = stack.undo(snapshot(&input)).unwrap();
+        assert_eq!(input.input, "hello big ");
+        let input = stack.undo(input).unwrap();
+        assert_eq!(input.input, "hello ");
+
+        let input = stack.redo(input).unwrap();
+        assert_eq!(input.input, "hello big ");
+    }
+
+    #[test]
+    fn backspacing_is_undone_a_word_at_a_time() {
+        let mut stack = UndoStack::default();
+        let mut input = "one two three".to_string();
+        while let Some(c) = input.pop() {
+            stack.record(Edit::Delete(c), snapshot(&format!("{input}{c}")));
+        }
+
+        let input = stack.undo(snapshot("")).unwrap();
+        assert_eq!(input.input, "one");
+        let input = stack.undo(input).unwrap();
+        assert_eq!(input.input, "one two");
+        let input = stack.undo(input).unwrap();
+        assert_eq!(input.input, "one two three");
+        assert_eq!(stack.undo(input), None);
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut stack = UndoStack::default();
+        let input = type_text(&mut stack, "", "a");
+        let input = stack.undo(snapshot(&input)).unwrap();
+        type_text(&mut stack, &input.input, "b");
+
+        assert_eq!(stack.redo(snapshot("b")), None);
+    }
+}
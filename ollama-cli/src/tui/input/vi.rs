@@ -0,0 +1,114 @@
+//! Vi style operators and text objects, bound when the config sets `editing = "vi"`.
+//! Positions are character indices like the input's cursor.
+
+use super::kill::line_start;
+
+/// What to do with the text an operator covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    /// Delete and start inserting
+    Change,
+}
+
+/// An operator waiting for the rest of its command, e.g. the `d` in `dw`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pending {
+    Motion(Operator),
+    /// `i` after an operator, waiting for a text object like the `w` in `ciw`
+    Inner(Operator),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Newline,
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+impl From<char> for CharClass {
+    fn from(c: char) -> Self {
+        if c == '\n' {
+            CharClass::Newline
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+/// The word under the cursor, or the whitespace it's on, like vi's `iw`.
+/// A cursor past the end is on the last character.
+pub fn inner_word(input: &str, cursor: usize) -> (usize, usize) {
+    let chars: Vec<char> = input.chars().collect();
+    let Some(last) = chars.len().checked_sub(1) else {
+        return (0, 0);
+    };
+    let at = cursor.min(last);
+    let class = CharClass::from(chars[at]);
+    if class == CharClass::Newline {
+        return (at, at);
+    }
+
+    let mut start = at;
+    while start > 0 && CharClass::from(chars[start - 1]) == class {
+        start -= 1;
+    }
+    let mut end = at + 1;
+    while end < chars.len() && CharClass::from(chars[end]) == class {
+        end += 1;
+    }
+    (start, end)
+}
+
+/// The line the cursor is on with its newline, like vi's `dd`.
+/// The last line takes the newline before it instead.
+pub fn whole_line(input: &str, cursor: usize) -> (usize, usize) {
+    let start = line_start(input, cursor);
+    let len = input.chars().count();
+    match input.chars().skip(start).position(|c| c == '\n') {
+        Some(newline) => (start, start + newline + 1),
+        None => (start.saturating_sub(1), len),
+    }
+}
+
+/// The line the cursor is on without its newline, like vi's `cc`
+pub fn line_content(input: &str, cursor: usize) -> (usize, usize) {
+    let start = line_start(input, cursor);
+    let end = start + input.chars().skip(start).take_while(|c| *c != '\n').count();
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn finds_inner_words() {
+        let input = "say foo_bar, now";
+        // on the 'b'
+        assert_eq!(inner_word(input, 8), (4, 11));
+        // on the comma
+        assert_eq!(inner_word(input, 11), (11, 12));
+        // on the space after "say"
+        assert_eq!(inner_word(input, 3), (3, 4));
+        // past the end
+        assert_eq!(inner_word(input, 16), (13, 16));
+        assert_eq!(inner_word("", 0), (0, 0));
+    }
+
+    #[test]
+    fn finds_whole_lines() {
+        let input = "one\ntwo\nthree";
+        assert_eq!(whole_line(input, 5), (4, 8));
+        assert_eq!(whole_line(input, 10), (7, 13));
+        assert_eq!(whole_line("only", 2), (0, 4));
+        assert_eq!(line_content(input, 5), (4, 7));
+    }
+}
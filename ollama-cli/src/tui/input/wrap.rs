@@ -0,0 +1,138 @@
+//! Word wrapping for the input that keeps track of where each row is in the input,
+//! so the cursor and selection can be drawn on the right row.
+//! Widths are display columns, so wide glyphs take two.
+
+use unicode_segmentation::UnicodeSegmentation as _;
+use unicode_width::UnicodeWidthStr as _;
+
+/// A row of wrapped input, `start` and `end` are character indices into the input.
+/// Whitespace at a soft break stays at the end of the row before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Wrap `input` into rows of at most `width` columns.
+/// Rows break after whitespace if they can and inside a word if they can't.
+pub fn wrap(input: &str, width: usize) -> Vec<Row> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut offset = 0;
+
+    for line in input.split('\n') {
+        let mut row_start = offset;
+        let mut row_width = 0;
+        // where the row could break and the width of the row up to there
+        let mut break_at: Option<(usize, usize)> = None;
+        let mut position = offset;
+        let mut after_whitespace = false;
+
+        for grapheme in line.graphemes(true) {
+            let is_whitespace = grapheme.chars().all(char::is_whitespace);
+            let grapheme_width = grapheme.width();
+
+            // whitespace hangs past the edge instead of starting a row
+            if !is_whitespace {
+                if after_whitespace {
+                    break_at = Some((position, row_width));
+                }
+                if row_width + grapheme_width > width && position > row_start {
+                    if let Some((at, at_width)) = break_at.take().filter(|(at, _)| *at > row_start)
+                    {
+                        rows.push(Row {
+                            start: row_start,
+                            end: at,
+                        });
+                        row_start = at;
+                        row_width -= at_width;
+                    }
+                    // the word doesn't fit on a row of its own
+                    if row_width + grapheme_width > width && position > row_start {
+                        rows.push(Row {
+                            start: row_start,
+                            end: position,
+                        });
+                        row_start = position;
+                        row_width = 0;
+                    }
+                }
+            }
+
+            row_width += grapheme_width;
+            position += grapheme.chars().count();
+            after_whitespace = is_whitespace;
+        }
+
+        rows.push(Row {
+            start: row_start,
+            end: position,
+        });
+        // skip the newline
+        offset = position + 1;
+    }
+
+    rows
+}
+
+/// The row the cursor is drawn on.
+/// A cursor at a soft break is drawn at the start of the next row.
+pub fn cursor_row(rows: &[Row], cursor: usize) -> usize {
+    rows.iter()
+        .rposition(|row| row.start <= cursor)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn row_text(input: &str, rows: &[Row]) -> Vec<String> {
+        rows.iter()
+            .map(|row| {
+                input
+                    .chars()
+                    .skip(row.start)
+                    .take(row.end - row.start)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_word_boundaries() {
+        let input = "the quick brown fox\n\njumps";
+        let rows = wrap(input, 10);
+
+        assert_eq!(
+            row_text(input, &rows),
+            vec!["the quick ", "brown fox", "", "jumps"]
+        );
+        assert_eq!(rows[3].start, 21);
+    }
+
+    #[test]
+    fn breaks_long_words_and_wide_glyphs() {
+        let input = "abcdefgh 日本語日本語";
+        let rows = wrap(input, 5);
+
+        assert_eq!(
+            row_text(input, &rows),
+            vec!["abcde", "fgh ", "日本", "語日", "本語"]
+        );
+    }
+
+    #[test]
+    fn cursor_at_a_soft_break_starts_the_next_row() {
+        let input = "hello world\nend";
+        let rows = wrap(input, 8);
+
+        assert_eq!(cursor_row(&rows, 3), 0);
+        assert_eq!(cursor_row(&rows, 6), 1);
+        // the end of a hard line stays on its row
+        assert_eq!(cursor_row(&rows, 11), 1);
+        assert_eq!(cursor_row(&rows, 12), 2);
+    }
+}
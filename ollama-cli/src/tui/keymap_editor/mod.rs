@@ -0,0 +1,172 @@
+use keymap::KeyMap;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::Text,
+    widgets::{Block, List, ListState, Paragraph, Wrap},
+    Frame,
+};
+use strum::IntoEnumIterator as _;
+
+use crate::error::Result;
+
+use super::{
+    event::{Action, EventDefinitions, InputMode},
+    AppEvent,
+};
+
+/// Edit key bindings for each [`InputMode`].
+///
+/// Pressing enter on an action waits for the next key press
+/// and binds it to the action, or unbinds it if it's already bound to the action.
+#[derive(Clone, Debug, Default)]
+pub struct KeymapViewModel {
+    definitions: EventDefinitions,
+    rows: Vec<(InputMode, Action)>,
+    list_state: ListState,
+    /// The next key press is bound to the selected action
+    capturing: bool,
+    message: Option<String>,
+}
+
+impl KeymapViewModel {
+    pub fn load(&mut self, definitions: EventDefinitions) {
+        self.definitions = definitions;
+        self.rows = InputMode::iter()
+            .flat_map(|mode| {
+                Action::iter()
+                    .filter(|action| !matches!(action, Action::Unhandled(_) | Action::Nop))
+                    .map(move |action| (mode, action))
+            })
+            .collect();
+        if self.list_state.selected().is_none() {
+            self.list_state.select_first();
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    fn selected(&self) -> Option<(InputMode, Action)> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.rows.get(index))
+            .copied()
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => self.list_state.select_previous(),
+            Action::Down => self.list_state.select_next(),
+            Action::Enter => {
+                if let Some((mode, action)) = self.selected() {
+                    self.capturing = true;
+                    self.message = Some(format!(
+                        "press a key to bind or unbind {action} in {mode} mode"
+                    ));
+                }
+            }
+            Action::Refresh => {
+                self.load(EventDefinitions::default());
+                self.message = Some("reset to the default keymap".to_string());
+                return Ok(Some(AppEvent::UpdateKeymap(self.definitions.clone())));
+            }
+            Action::Quit => return Ok(Some(AppEvent::Deactivate)),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Bind the key to the selected action
+    pub fn handle_key(&mut self, key: KeyMap) -> Option<AppEvent> {
+        self.capturing = false;
+        let (mode, action) = self.selected()?;
+
+        match self.bind(mode, action, key) {
+            Ok(message) => {
+                self.message = Some(message);
+                Some(AppEvent::UpdateKeymap(self.definitions.clone()))
+            }
+            Err(message) => {
+                self.message = Some(message);
+                None
+            }
+        }
+    }
+
+    /// Returns a description of the change or a conflict
+    fn bind(
+        &mut self,
+        mode: InputMode,
+        action: Action,
+        key: KeyMap,
+    ) -> std::result::Result<String, String> {
+        let is_last_quit = action == Action::Quit
+            && mode == InputMode::Normal
+            && self.definitions.keys_for(mode, action).len() == 1;
+
+        let action_map = self.definitions.global.entry(mode).or_default();
+        match action_map.0.get(&key).copied() {
+            Some(bound) if bound == action => {
+                if is_last_quit {
+                    return Err(format!("{key} is the only key that quits"));
+                }
+                action_map.0.remove(&key);
+                Ok(format!("unbound {key} from {action} in {mode} mode"))
+            }
+            Some(bound) => Err(format!("{key} is already bound to {bound} in {mode} mode")),
+            None => {
+                let message = format!("bound {key} to {action} in {mode} mode");
+                action_map.0.insert(key, action);
+                Ok(message)
+            }
+        }
+    }
+}
+
+#[extend::ext(name = KeymapView)]
+pub impl<'a> Frame<'a> {
+    fn keymap_view(&mut self, parent: Rect, style: Style, view_model: &mut KeymapViewModel) {
+        let vertical = Layout::vertical([Constraint::Min(1), Constraint::Length(3)]);
+        let [list_area, message_area] = vertical.areas(parent);
+
+        let rows = view_model.rows.iter().map(|(mode, action)| {
+            let keys = view_model
+                .definitions
+                .keys_for(*mode, *action)
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Text::from(format!(
+                "{:<8} {:<12} {keys}",
+                mode.to_string(),
+                action.to_string()
+            ))
+        });
+
+        let list = List::new(rows)
+            .style(style)
+            .highlight_style(
+                style
+                    .fg(style.bg.unwrap_or(Color::Black))
+                    .bg(style.fg.unwrap_or(Color::White)),
+            )
+            .block(Block::bordered().title("keymap (enter: bind/unbind, r: reset)"));
+
+        self.render_stateful_widget(list, list_area, &mut view_model.list_state);
+
+        let message_style = if view_model.capturing {
+            Style::default().fg(Color::Cyan)
+        } else {
+            style
+        };
+        let message = Paragraph::new(view_model.message.as_deref().unwrap_or_default())
+            .style(message_style)
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered());
+
+        self.render_widget(message, message_area);
+    }
+}
@@ -0,0 +1,260 @@
+//! Render markdown from model output as styled [`Line`]s.
+//!
+//! Only a subset is supported:
+//! fenced code blocks (highlighted with [`syntect`]),
+//! headings, list items, `**bold**`, `*italic*`, and `inline code`.
+
+use std::sync::LazyLock;
+
+use ratatui::{
+    style::{Color, Modifier, Style, Stylize as _},
+    text::{Line, Span},
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+};
+
+const FENCE: &str = "```";
+const BULLET: &str = "• ";
+const THEME_NAME: &str = "base16-ocean.dark";
+
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_nonewlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove(THEME_NAME)
+        .expect("default themes should include base16-ocean.dark")
+});
+
+/// Render `content` as lines no wider than `width`.
+/// Code blocks are not wrapped so they keep their indentation.
+pub fn render(content: &str, width: u16) -> Vec<Line<'static>> {
+    let width = usize::from(width.max(1));
+    let mut lines = Vec::new();
+    let mut code_block: Option<HighlightLines<'static>> = None;
+
+    for line in content.lines() {
+        if let Some(info) = line.trim_start().strip_prefix(FENCE) {
+            code_block = match code_block {
+                Some(_) => None,
+                None => Some(HighlightLines::new(find_syntax(info), &THEME)),
+            };
+            lines.push(Line::from(line.to_string()).dim());
+            continue;
+        }
+
+        match &mut code_block {
+            Some(highlighter) => lines.push(highlight_line(highlighter, line)),
+            None => lines.extend(render_prose(line, width)),
+        }
+    }
+
+    lines
+}
+
+/// Find a syntax from the info string of a fence, e.g. "rust,ignore"
+fn find_syntax(info: &str) -> &'static SyntaxReference {
+    let token = info
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    SYNTAX_SET
+        .find_syntax_by_token(token)
+        .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text())
+}
+
+fn highlight_line(highlighter: &mut HighlightLines<'static>, line: &str) -> Line<'static> {
+    match highlighter.highlight_line(line, &SYNTAX_SET) {
+        Ok(ranges) => Line::from_iter(ranges.into_iter().map(|(style, text)| {
+            let color = style.foreground;
+            Span::styled(
+                text.to_string(),
+                Style::default().fg(Color::Rgb(color.r, color.g, color.b)),
+            )
+        })),
+        Err(error) => {
+            tracing::warn!(%error, "unable to highlight line");
+            Line::from(line.to_string())
+        }
+    }
+}
+
+fn render_prose(line: &str, width: usize) -> Vec<Line<'static>> {
+    if let Some(heading) = heading(line) {
+        return textwrap::wrap(heading, width)
+            .into_iter()
+            .map(|text| Line::from(text.to_string()).bold())
+            .collect();
+    }
+
+    let (prefix, text) = list_item(line);
+    let indent = " ".repeat(prefix.chars().count());
+    let text_width = width.saturating_sub(indent.len()).max(1);
+
+    let mut state = InlineState::default();
+    textwrap::wrap(text, text_width)
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let prefix = if i == 0 {
+                prefix.clone()
+            } else {
+                indent.clone()
+            };
+            std::iter::once(Span::from(prefix))
+                .chain(state.spans(&text))
+                .collect()
+        })
+        .collect()
+}
+
+fn heading(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    (text.len() < line.len() && text.starts_with(' ')).then(|| text.trim())
+}
+
+/// Split a list item into its marker and text,
+/// replacing unordered markers with a bullet
+fn list_item(line: &str) -> (String, &str) {
+    let text = line.trim_start();
+    let leading = &line[..line.len() - text.len()];
+
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(rest) = text.strip_prefix(marker) {
+            return (format!("{leading}{BULLET}"), rest);
+        }
+    }
+
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        if let Some(rest) = text[digits..].strip_prefix(". ") {
+            return (format!("{leading}{}. ", &text[..digits]), rest);
+        }
+    }
+
+    (leading.to_string(), text)
+}
+
+/// Inline emphasis that can continue across wrapped lines
+#[derive(Debug, Default)]
+struct InlineState {
+    bold: bool,
+    italic: bool,
+    code: bool,
+}
+
+impl InlineState {
+    fn style(&self) -> Style {
+        if self.code {
+            return Style::default().fg(Color::Yellow);
+        }
+        let mut style = Style::default();
+        if self.bold {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        if self.italic {
+            style = style.add_modifier(Modifier::ITALIC);
+        }
+        style
+    }
+
+    fn spans(&mut self, text: &str) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+        let mut current = String::new();
+        let chars: Vec<char> = text.chars().collect();
+
+        let mut flush = |current: &mut String, style: Style| {
+            if !current.is_empty() {
+                spans.push(Span::styled(std::mem::take(current), style));
+            }
+        };
+
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            let previous = i.checked_sub(1).map(|i| chars[i]);
+
+            if c == '`' {
+                flush(&mut current, self.style());
+                self.code = !self.code;
+            } else if self.code {
+                current.push(c);
+            } else if c == '*' && next == Some('*') {
+                flush(&mut current, self.style());
+                self.bold = !self.bold;
+                i += 1;
+            } else if c == '*' && self.is_italic_marker(previous, next) {
+                flush(&mut current, self.style());
+                self.italic = !self.italic;
+            } else {
+                current.push(c);
+            }
+            i += 1;
+        }
+        flush(&mut current, self.style());
+
+        spans
+    }
+
+    /// `*` opens emphasis before a word and closes it after one,
+    /// so `2 * 3` is left alone
+    fn is_italic_marker(&self, previous: Option<char>, next: Option<char>) -> bool {
+        if self.italic {
+            previous.is_some_and(|c| !c.is_whitespace())
+        } else {
+            next.is_some_and(|c| !c.is_whitespace())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &Line) -> String {
+        line.spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect()
+    }
+
+    #[test]
+    fn inline_emphasis_is_styled() {
+        let spans = InlineState::default().spans("a **bold** and *italic* `x * y`");
+
+        let bold = spans.iter().find(|span| span.content == "bold").unwrap();
+        assert!(bold.style.add_modifier.contains(Modifier::BOLD));
+
+        let italic = spans.iter().find(|span| span.content == "italic").unwrap();
+        assert!(italic.style.add_modifier.contains(Modifier::ITALIC));
+
+        let code = spans.iter().find(|span| span.content == "x * y").unwrap();
+        assert_eq!(code.style.fg, Some(Color::Yellow));
+    }
+
+    #[test]
+    fn multiplication_is_not_italic() {
+        let spans = InlineState::default().spans("2 * 3 * 4");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].content, "2 * 3 * 4");
+    }
+
+    #[test]
+    fn list_items_use_bullets() {
+        let lines = render("- one\n  * two\n3. three", 80);
+        let lines: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(lines, ["• one", "  • two", "3. three"]);
+    }
+
+    #[test]
+    fn code_blocks_are_not_wrapped() {
+        let code = "fn main() { println!(\"a long line of code\"); }";
+        let content = format!("```rust\n{code}\n```\nafter");
+        let lines = render(&content, 10);
+        let lines: Vec<String> = lines.iter().map(text).collect();
+        assert_eq!(lines, ["```rust", code, "```", "after"]);
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
 use ratatui::{
     style::{Style, Stylize},
@@ -8,7 +11,7 @@ use ratatui::{
 use crate::{
     error::{Error, Result},
     lm::Response,
-    ollama::chat::Message,
+    ollama::{chat::Message, stats::GenerationStats},
 };
 
 use super::event::Action;
@@ -24,11 +27,26 @@ pub struct MessagesViewModel {
     /// of the conversation.
     messages: VecDeque<Message>,
     state: state::MessagesState,
+    /// Text to highlight, e.g. a search query
+    highlight: Option<Arc<str>>,
+    /// Stats for the response that is streaming
+    stream_stats: Option<GenerationStats>,
+    /// Stats of responses by their index in [`Self::chronological`]
+    stats: HashMap<usize, GenerationStats>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum MessagesEvent {
     Quit,
+    /// Edit a prompt and submit it in place of the prompt and everything after it
+    Edit {
+        index: usize,
+        prompt: Arc<str>,
+    },
+    /// A message was removed from the history
+    Deleted {
+        index: usize,
+    },
 }
 
 impl From<Message> for Text<'_> {
@@ -44,21 +62,40 @@ impl From<Message> for Text<'_> {
 }
 
 impl MessagesViewModel {
-    /// Get a list of text lines that are wrapped
-    /// around the given `view_width`.
-    fn get_message_list(&self) -> Vec<Message> {
-        std::iter::once(Message::Assistant(self.model_stream.clone().into()))
-            .chain(self.messages.clone())
+    /// Get the streaming response followed by the history, newest first,
+    /// along with the stats of each response.
+    fn get_message_list(&self) -> Vec<(Message, Option<GenerationStats>)> {
+        let len = self.messages.len();
+        std::iter::once((Message::Assistant(self.model_stream.clone().into()), None))
+            .chain(
+                self.messages
+                    .iter()
+                    .enumerate()
+                    .map(|(i, message)| (message.clone(), self.stats.get(&(len - 1 - i)).copied())),
+            )
             .collect()
     }
 
     pub fn handle_response(&mut self, response: Response) -> Result<()> {
         match response {
-            Response::ModelInfo(_) | Response::LocalModels(_) => {
-                return Err(Error::UnexpectedResponse(response))
+            Response::ModelInfo(_)
+            | Response::LocalModels(_)
+            | Response::Embedding(_)
+            | Response::Progress(_)
+            | Response::ModelCreated(_)
+            | Response::RunningModels(_)
+            | Response::Connected(_)
+            | Response::Connection(_)
+            | Response::Compare { .. } => return Err(Error::UnexpectedResponse(response)),
+            Response::Stats(stats) => self.stream_stats = Some(stats),
+            Response::Summary { replaced, summary } => {
+                self.replace_oldest(replaced, Message::summary(&summary))
             }
-            Response::Eos => {
+            Response::Eos | Response::Cancelled => {
                 let message = Message::Assistant(self.model_stream.clone().into());
+                if let Some(stats) = self.stream_stats.take() {
+                    self.stats.insert(self.messages.len(), stats);
+                }
                 self.push_message(message);
                 self.clear_stream();
             }
@@ -94,29 +131,259 @@ impl MessagesViewModel {
                 self.state.select_previous();
                 None
             }
+            Action::Latest => {
+                self.state.latest();
+                None
+            }
             Action::Enter => {
                 if self.state.selected().is_some() {
                     // TODO: enter fullscreen
                 }
                 None
             }
+            Action::Edit => {
+                let index = self.selected_chronological()?;
+                match self.messages.get(self.messages.len() - 1 - index)? {
+                    Message::User(prompt) => Some(MessagesEvent::Edit {
+                        index,
+                        prompt: prompt.clone(),
+                    }),
+                    _ => None,
+                }
+            }
+            Action::Delete | Action::DeleteChar => {
+                let index = self.selected_chronological()?;
+                self.remove_chronological(index)
+                    .then_some(MessagesEvent::Deleted { index })
+            }
             _ => None,
         }
     }
 
+    /// Remove a message by its index in [`Self::chronological`].
+    /// The history can't change while a response is streaming.
+    pub fn remove_chronological(&mut self, index: usize) -> bool {
+        if !self.is_stream_empty() || index >= self.messages.len() {
+            return false;
+        }
+        self.messages.remove(self.messages.len() - 1 - index);
+        self.stats = self
+            .stats
+            .drain()
+            .filter(|(stats_index, _)| *stats_index != index)
+            .map(|(stats_index, stats)| {
+                let stats_index = if stats_index > index {
+                    stats_index - 1
+                } else {
+                    stats_index
+                };
+                (stats_index, stats)
+            })
+            .collect();
+        true
+    }
+
+    /// Replace the `count` oldest messages with `message`, e.g. a summary of them
+    pub fn replace_oldest(&mut self, count: usize, message: Message) {
+        let count = count.min(self.messages.len());
+        if count == 0 {
+            return;
+        }
+        self.messages.truncate(self.messages.len() - count);
+        self.messages.push_back(message);
+        self.stats = self
+            .stats
+            .drain()
+            .filter(|(index, _)| *index >= count)
+            .map(|(index, stats)| (index + 1 - count, stats))
+            .collect();
+    }
+
+    /// Keep the messages before `index` in [`Self::chronological`]
+    pub fn truncate_chronological(&mut self, index: usize) {
+        let removed = self.messages.len().saturating_sub(index);
+        self.messages.drain(..removed);
+        self.stats.retain(|stats_index, _| *stats_index < index);
+        self.state.latest();
+    }
+
     pub fn push_message(&mut self, message: Message) {
         self.messages.push_front(message);
+        self.state.message_pushed();
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.state.is_following()
     }
 
+    /// The conversation before the newest prompt, oldest first
     pub fn history(&self) -> Vec<Message> {
-        self.messages.clone().into()
+        self.messages.iter().skip(1).rev().cloned().collect()
+    }
+
+    /// Messages with the oldest first
+    pub fn chronological(&self) -> Vec<Message> {
+        self.messages.iter().rev().cloned().collect()
+    }
+
+    /// Replace the conversation, e.g. with a saved session, oldest message first
+    pub fn set_messages(&mut self, messages: Vec<Message>) {
+        self.messages = messages.into_iter().rev().collect();
+        self.stats.clear();
+        self.clear_stream();
+        self.state.latest();
+    }
+
+    /// Remove the newest response so it can be generated again.
+    /// Returns the prompt it answered, or `None` if the newest messages
+    /// aren't a prompt and its finished response.
+    pub fn pop_response(&mut self) -> Option<Arc<str>> {
+        if !self.is_stream_empty() {
+            return None;
+        }
+        let (Some(Message::Assistant(_)), Some(Message::User(prompt))) =
+            (self.messages.front(), self.messages.get(1))
+        else {
+            return None;
+        };
+        let prompt = prompt.clone();
+        self.stats.remove(&(self.messages.len() - 1));
+        self.messages.pop_front();
+        Some(prompt)
+    }
+
+    /// The index in [`Self::chronological`] of the selected message
+    pub fn selected_chronological(&self) -> Option<usize> {
+        // the first row is the streaming response
+        let row = self.state.list_state.selected().filter(|row| *row > 0)?;
+        self.messages.len().checked_sub(row)
+    }
+
+    /// Stats of responses by their index in [`Self::chronological`]
+    pub fn stats(&self) -> &HashMap<usize, GenerationStats> {
+        &self.stats
     }
 
-    fn is_stream_empty(&self) -> bool {
+    /// Select a message by its index in [`Self::chronological`]
+    pub fn select_chronological(&mut self, index: usize) {
+        if let Some(newest_first) = self.messages.len().checked_sub(index + 1) {
+            // the first row is the streaming response
+            self.state.select(Some(newest_first + 1));
+        }
+    }
+
+    pub fn set_highlight(&mut self, highlight: Option<Arc<str>>) {
+        self.highlight = highlight;
+    }
+
+    pub fn is_stream_empty(&self) -> bool {
         self.model_stream.is_empty()
     }
 
     fn clear_stream(&mut self) {
         self.model_stream.clear();
+        self.stream_stats = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn regenerating_removes_the_response() {
+        let mut messages = MessagesViewModel::default();
+        messages.set_messages(vec![
+            Message::User("hi".into()),
+            Message::Assistant("hello".into()),
+        ]);
+        assert_eq!(messages.pop_response().as_deref(), Some("hi"));
+        assert_eq!(messages.chronological().len(), 1);
+        // the prompt has no response to remove
+        assert_eq!(messages.pop_response(), None);
+    }
+
+    #[test]
+    fn deleting_a_message_keeps_the_stats_of_the_others() {
+        let mut messages = MessagesViewModel::default();
+        messages.set_messages(vec![
+            Message::User("one".into()),
+            Message::Assistant("two".into()),
+            Message::User("three".into()),
+            Message::Assistant("four".into()),
+        ]);
+        let stats = |eval_count| GenerationStats {
+            eval_count,
+            ..Default::default()
+        };
+        messages.stats.insert(1, stats(2));
+        messages.stats.insert(3, stats(4));
+
+        assert!(messages.remove_chronological(1));
+        assert!(!messages.remove_chronological(3));
+        assert_eq!(
+            messages
+                .chronological()
+                .iter()
+                .map(|message| message.content().to_string())
+                .collect::<Vec<_>>(),
+            vec!["one", "three", "four"]
+        );
+        assert_eq!(messages.stats, HashMap::from([(2, stats(4))]));
+
+        messages.truncate_chronological(1);
+        assert_eq!(messages.chronological().len(), 1);
+        assert!(messages.stats.is_empty());
+    }
+
+    #[test]
+    fn summary_replaces_the_oldest_messages() {
+        let mut messages = MessagesViewModel::default();
+        messages.set_messages(vec![
+            Message::User("one".into()),
+            Message::Assistant("two".into()),
+            Message::User("three".into()),
+            Message::Assistant("four".into()),
+            Message::User("five".into()),
+        ]);
+        messages.stats.insert(3, GenerationStats::default());
+
+        messages
+            .handle_response(Response::Summary {
+                replaced: 3,
+                summary: "counting".into(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            messages
+                .history()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "system: Summary of the earlier conversation:\ncounting",
+                "assistant: four"
+            ]
+        );
+        assert!(messages.stats.contains_key(&1));
+    }
+
+    #[test]
+    fn selection_maps_to_chronological_index() {
+        let mut messages = MessagesViewModel::default();
+        messages.set_messages(vec![
+            Message::User("one".into()),
+            Message::Assistant("two".into()),
+            Message::User("three".into()),
+        ]);
+        assert_eq!(messages.selected_chronological(), None);
+
+        for index in 0..3 {
+            messages.select_chronological(index);
+            assert_eq!(messages.selected_chronological(), Some(index));
+        }
     }
 }
@@ -21,4 +21,45 @@ impl MessagesState {
     pub fn selected(&mut self) -> Option<usize> {
         self.list_state.selected()
     }
+
+    /// Keep the selected message selected when a newer one is pushed in front of it.
+    /// The first row is the streaming response, so it stays selected to follow the stream.
+    pub fn message_pushed(&mut self) {
+        if let Some(selected) = self.list_state.selected().filter(|selected| *selected > 0) {
+            self.list_state.select(Some(selected + 1));
+        }
+    }
+
+    /// Show the newest messages and follow the stream
+    pub fn latest(&mut self) {
+        self.list_state.select(None);
+        *self.list_state.offset_mut() = 0;
+    }
+
+    /// New messages are shown unless an older message is selected
+    pub fn is_following(&self) -> bool {
+        self.list_state.selected().unwrap_or_default() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn selection_stays_on_the_same_message() {
+        let mut state = MessagesState::default();
+        state.message_pushed();
+        assert_eq!(state.selected(), None);
+
+        state.select(Some(2));
+        state.message_pushed();
+        assert_eq!(state.selected(), Some(3));
+        assert!(!state.is_following());
+
+        state.latest();
+        assert!(state.is_following());
+    }
 }
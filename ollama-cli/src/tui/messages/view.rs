@@ -1,14 +1,15 @@
-use std::{borrow::Cow, sync::Arc};
-
 use ratatui::{
     layout::Rect,
-    style::{Color, Style, Stylize as _},
+    style::{Color, Modifier, Style, Stylize as _},
     text::{Line, Span, Text},
     widgets::{Block, List, ListItem},
     Frame,
 };
 
-use crate::ollama::chat::Message;
+use crate::{
+    ollama::{chat::Message, stats::GenerationStats},
+    tui::{markdown, scroll::PAUSED_TITLE},
+};
 
 use super::MessagesViewModel;
 
@@ -25,7 +26,7 @@ struct MessageViewBuilder {
 #[derive(Debug, Clone)]
 pub struct MessageContent {
     role: &'static str,
-    content: Vec<Arc<str>>,
+    content: Vec<Line<'static>>,
 }
 
 impl MessageContent {
@@ -54,9 +55,9 @@ impl MessageViewBuilder {
         }
     }
 
-    fn make_row(&mut self, message: &Message) -> MessageContent {
+    fn make_row(&mut self, message: &Message, stats: Option<&GenerationStats>) -> MessageContent {
         if self.remaining_lines > 0 {
-            let content = self.make_message_content(message);
+            let content = self.make_message_content(message, stats);
 
             let consumed_lines = self.consumed_lines + content.height();
             debug_assert!(self.remaining_lines >= content.height());
@@ -73,56 +74,103 @@ impl MessageViewBuilder {
         }
     }
 
-    fn make_message_content(&self, message: &Message) -> MessageContent {
+    fn make_message_content(
+        &self,
+        message: &Message,
+        stats: Option<&GenerationStats>,
+    ) -> MessageContent {
         let role = message.role();
         let content = message.content();
         tracing::info!(role, %content, "creating message row");
 
-        let content_lines = fit_content(&content, self.message_cell_width, self.remaining_lines);
-
-        let content: Vec<Arc<str>> = content_lines
-            .into_iter()
-            .map(|line: Cow<'_, str>| line.into())
-            .collect();
+        // stats take a line at the end if there's room for some content too
+        let stats = stats.filter(|_| self.remaining_lines > 1);
+        let height = self.remaining_lines - u16::from(stats.is_some());
+        let mut content = fit_content(&content, self.message_cell_width, height);
+        if let Some(stats) = stats {
+            content.push(Line::from(format!("({stats})")).dim());
+        }
 
         MessageContent { role, content }
     }
 }
 
-fn fit_content(content: &str, width: u16, height: u16) -> Vec<Cow<'_, str>> {
-    let mut content_lines: Vec<Cow<'_, str>> = textwrap::wrap(content, usize::from(width))
+fn fit_content(content: &str, width: u16, height: u16) -> Vec<Line<'static>> {
+    let mut content_lines: Vec<Line<'static>> = markdown::render(content, width)
         .into_iter()
         .take(height.into())
         .collect();
 
     if (height as usize) <= content_lines.len() {
         content_lines.pop();
-        content_lines.push(ELLIPSIS.into());
+        content_lines.push(Line::from(ELLIPSIS));
     }
 
     content_lines
 }
 
-fn fit_messages(messages: &[Message], max_height: u16, message_cell_width: u16) -> Vec<ListItem> {
+/// Reverse the style of each case insensitive occurrence of `query`
+fn highlight_matches(mut line: Line<'static>, query: &str) -> Line<'static> {
+    if query.is_empty() {
+        return line;
+    }
+    let query = query.to_ascii_lowercase();
+
+    line.spans = std::mem::take(&mut line.spans)
+        .into_iter()
+        .flat_map(|span| {
+            let content = span.content.to_string();
+            let mut pieces = Vec::new();
+            let mut start = 0;
+            for (index, _) in content.to_ascii_lowercase().match_indices(&query) {
+                if index > start {
+                    pieces.push(Span::styled(content[start..index].to_string(), span.style));
+                }
+                let end = index + query.len();
+                pieces.push(Span::styled(
+                    content[index..end].to_string(),
+                    span.style.add_modifier(Modifier::REVERSED),
+                ));
+                start = end;
+            }
+            if start < content.len() {
+                pieces.push(Span::styled(content[start..].to_string(), span.style));
+            }
+            pieces
+        })
+        .collect();
+
+    line
+}
+
+fn fit_messages(
+    messages: &[(Message, Option<GenerationStats>)],
+    max_height: u16,
+    message_cell_width: u16,
+    highlight: Option<&str>,
+) -> Vec<ListItem<'static>> {
     messages
         .iter()
         .scan(
             MessageViewBuilder::new(max_height, message_cell_width),
-            move |builder, message| Some(builder.make_row(message)),
+            move |builder, (message, stats)| Some(builder.make_row(message, stats.as_ref())),
         )
         .map(|content| {
             let MessageContent { role, content } = content;
 
             let mut content = content.into_iter();
-            let first = Line::from_iter([
-                Span::from(role).bold(),
-                Span::from(": "),
-                Span::from(content.next().unwrap_or_default().to_string()),
-            ]);
-
-            let rest = content.map(|line: Arc<str>| Line::from(line.to_string()));
+            let first = Line::from_iter(
+                [Span::from(role).bold(), Span::from(": ")]
+                    .into_iter()
+                    .chain(content.next().unwrap_or_default().spans),
+            );
 
-            let lines = std::iter::once(first).chain(rest);
+            let lines = std::iter::once(first)
+                .chain(content)
+                .map(|line| match highlight {
+                    Some(query) => highlight_matches(line, query),
+                    None => line,
+                });
 
             ListItem::from(Text::from_iter(lines))
         })
@@ -138,19 +186,27 @@ pub impl<'a> Frame<'a> {
         let max_height = parent.height - 2;
         let message_cell_width = parent.width - 3 - role_cell_width;
 
-        let messages = fit_messages(&messages, max_height, message_cell_width);
+        let messages = fit_messages(
+            &messages,
+            max_height,
+            message_cell_width,
+            view_model.highlight.as_deref(),
+        );
 
         tracing::info!(rows.len = messages.len());
 
         // let widths = [Constraint::Length(10), Constraint::Max(message_cell_width)];
 
-        let table = List::new(messages)
-            .block(Block::bordered().style(style))
-            .highlight_style(
-                Style::default()
-                    .bg(style.fg.unwrap_or(Color::Cyan))
-                    .fg(style.bg.unwrap_or(Color::Black)),
-            );
+        let mut block = Block::bordered().style(style);
+        if !view_model.is_following() {
+            block = block.title_bottom(PAUSED_TITLE);
+        }
+
+        let table = List::new(messages).block(block).highlight_style(
+            Style::default()
+                .bg(style.fg.unwrap_or(Color::Cyan))
+                .fg(style.bg.unwrap_or(Color::Black)),
+        );
         self.render_stateful_widget(table, parent, &mut view_model.state.list_state);
     }
 }
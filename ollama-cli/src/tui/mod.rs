@@ -1,51 +1,97 @@
-use std::{io::stdout, time::Duration};
+use std::{io::stdout, sync::Arc, time::Duration};
 
-use chat::ChatViewModel;
+use chat::{AttachView as _, AttachViewModel, BranchesView as _, BranchesViewModel, ChatViewModel};
+use compare::{CompareView as _, CompareViewModel};
 use crossterm::ExecutableCommand as _;
-use event::{Action, EventProcessor, InputMode};
+use embeddings::{EmbeddingsView, EmbeddingsViewModel};
+use event::{Action, EventDefinitions, EventProcessor, InputMode, MouseAction};
 use futures::StreamExt as _;
 use generate::{GenerateView, GenerateViewModel};
+use hosts::{HostsView as _, HostsViewModel};
+use keymap_editor::{KeymapView, KeymapViewModel};
 use model_context::ModelContext;
-use models::{ModelsView, ModelsViewModel};
-use nav::{NavView, NavViewModel};
+use models::{
+    instruction::{InstructionFormView as _, InstructionFormViewModel},
+    model_name::{ModelNameView as _, ModelNameViewModel},
+    running::{RunningModelsView as _, RunningModelsViewModel},
+    ModelsView, ModelsViewModel,
+};
+use nav::{NavStatus, NavView, NavViewModel};
 use ollama_rs::models::ModelInfo;
-use popup::{PopupView, PopupViewModel};
+use popup::{
+    AppFileData, Popup, PopupView, PopupViewModel, SaveFileView, SaveFileViewModel, ViewerView,
+    ViewerViewModel,
+};
 use ratatui::{
     crossterm::event::Event,
+    layout::{Constraint, Layout},
     style::{Color, Style},
     DefaultTerminal, Frame,
 };
-use strum::VariantNames;
+use status_bar::{Status, StatusBarView as _, StatusBarViewModel};
+use strum::{IntoStaticStr, VariantNames};
+use templates::{TemplatesView as _, TemplatesViewModel};
 
 use crate::{
-    config::Config,
+    attachment::{self, Attachment},
+    backend::{Backend, BackendKind},
+    config::{save_keymap, Config},
     error::Result,
-    lm::{Prompt, Response},
-    ollama,
+    lm::{ConnectionState, Prompt, Response},
+    ollama::{tools::ToolRegistry, ModelHost, ModelName},
+    session::Session,
+    shell,
+    templates::Templates,
     tui::chat::ChatView as _,
 };
 
 pub mod chat;
+mod clipboard;
+pub mod compare;
+pub mod embeddings;
 pub mod event;
+mod form;
 pub mod generate;
+mod hosts;
 pub mod input;
+pub mod keymap_editor;
+mod markdown;
 pub mod messages;
 mod model_context;
 pub mod models;
 mod nav;
 mod popup;
+mod scroll;
+mod status_bar;
+mod templates;
 mod widgets_ext;
 
+/// Drafts are edited as Markdown so editors highlight them
+const DRAFT_SUFFIX: &str = ".md";
+
+/// How often the nav view's stats are refreshed
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(10);
+
 pub struct AppContext {
     model_context: ModelContext,
     event_processor: EventProcessor,
-    popup: Option<PopupViewModel>,
+    popup: Option<Popup>,
     view: View,
     config: Config,
+    /// The host that the model context is connected to
+    host: ModelHost,
+    connection: ConnectionState,
+    status_bar: StatusBarViewModel,
+    /// The model of the last prompt that ran one, shown in the nav view
+    last_model: Option<ModelName>,
+    backend: BackendKind,
+    /// The chat session from the last time the chat view was open,
+    /// resumed with its scroll position when the view is opened again
+    chat: Option<ChatViewModel>,
 }
 
 #[derive(Clone, Debug, strum::EnumString, strum::EnumDiscriminants)]
-#[strum_discriminants(derive(VariantNames))]
+#[strum_discriminants(derive(VariantNames, IntoStaticStr))]
 #[strum_discriminants(name(ViewName))]
 #[strum_discriminants(strum(serialize_all = "lowercase"))]
 #[strum(serialize_all = "lowercase")]
@@ -53,6 +99,9 @@ pub enum View {
     Models(ModelsViewModel),
     Chat(ChatViewModel),
     Generate(GenerateViewModel),
+    Compare(CompareViewModel),
+    Embeddings(EmbeddingsViewModel),
+    Keymap(KeymapViewModel),
     Nav(NavViewModel),
 }
 
@@ -68,7 +117,10 @@ impl View {
             View::Chat(ref mut chat_view_model) => chat_view_model.handle_response(response),
             View::Models(ref mut models_view_model) => models_view_model.handle_response(response),
             View::Generate(ref mut view_model) => view_model.handle_response(response),
-            View::Nav(_nav_view_model) => Ok(()),
+            View::Compare(ref mut view_model) => view_model.handle_response(response),
+            View::Embeddings(ref mut view_model) => view_model.handle_response(response),
+            View::Keymap(_keymap_view_model) => Ok(()),
+            View::Nav(view_model) => view_model.handle_response(response),
         };
 
         if let Err(error) = result {
@@ -79,6 +131,40 @@ impl View {
         }
     }
 
+    /// The model that requests from this view are sent to
+    pub fn model(&self) -> Option<ModelName> {
+        match self {
+            View::Chat(chat_view_model) => Some(chat_view_model.model().clone()),
+            View::Generate(generate_view_model) => Some(generate_view_model.model().clone()),
+            View::Embeddings(embeddings_view_model) => Some(embeddings_view_model.selected_model()),
+            View::Models(models_view_model) => models_view_model.selected_model().cloned(),
+            View::Compare(_) | View::Keymap(_) | View::Nav(_) => None,
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match self {
+            View::Chat(chat_view_model) => chat_view_model.handle_mouse(mouse),
+            View::Models(models_view_model) => models_view_model.handle_mouse(mouse),
+            View::Generate(generate_view_model) => generate_view_model.handle_mouse(mouse),
+            View::Nav(nav_view_model) => nav_view_model.handle_mouse(mouse),
+            View::Compare(_) | View::Embeddings(_) | View::Keymap(_) => Ok(None),
+        }
+    }
+
+    /// Put a draft from an external editor in the active input
+    pub fn set_draft(&mut self, draft: &str) {
+        match self {
+            View::Chat(chat_view_model) => chat_view_model.set_draft(draft),
+            View::Generate(generate_view_model) => generate_view_model.set_draft(draft),
+            View::Compare(compare_view_model) => compare_view_model.set_draft(draft),
+            View::Embeddings(embeddings_view_model) => embeddings_view_model.set_draft(draft),
+            View::Models(_) | View::Keymap(_) | View::Nav(_) => {
+                tracing::warn!("no input to put the draft in")
+            }
+        }
+    }
+
     pub async fn init(&mut self) -> Result<Option<AppEvent>> {
         match self {
             View::Chat(_chat_view_model) => Ok(None),
@@ -87,6 +173,10 @@ impl View {
             }
             View::Nav(_nav_view_model) => Ok(None),
             View::Generate(_generate_view_model) => Ok(None),
+            View::Compare(_) | View::Embeddings(_) => {
+                Ok(Some(AppEvent::Submit(Prompt::LocalModels)))
+            }
+            View::Keymap(_keymap_view_model) => Ok(None),
         }
     }
 }
@@ -103,39 +193,101 @@ impl Style {
 }
 
 impl AppContext {
-    pub fn new(client: ollama::Client, config: Config) -> Self {
+    pub fn new(backend: Arc<dyn Backend>, host: ModelHost, config: Config) -> Self {
+        let backend_kind = backend.kind();
         Self {
-            model_context: ModelContext::spawn(client),
+            model_context: ModelContext::spawn(backend, ToolRegistry::new(&config.tools)),
             event_processor: EventProcessor::new(config.keymap.clone()),
             popup: None,
             view: Default::default(),
             config,
+            host,
+            connection: ConnectionState::default(),
+            status_bar: StatusBarViewModel::default(),
+            last_model: None,
+            backend: backend_kind,
+            chat: None,
         }
     }
 
     fn draw(&mut self, frame: &mut Frame) {
+        let [view_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
         match &mut self.view {
             View::Chat(ref mut chat_view_model) => {
-                frame.chat_view(frame.area(), Style::default(), chat_view_model);
+                frame.chat_view(view_area, Style::default(), chat_view_model);
             }
             View::Models(models_view_model) => {
-                frame.models_view(frame.area(), Style::default(), models_view_model)
-            }
-            View::Nav(nav_view_model) => {
-                frame.nav_view(frame.area(), Style::active(), nav_view_model)
+                frame.models_view(view_area, Style::default(), models_view_model)
             }
+            View::Nav(nav_view_model) => frame.nav_view(
+                view_area,
+                Style::active(),
+                NavStatus {
+                    host: &self.host,
+                    backend: self.backend,
+                    connection: self.connection,
+                    last_model: self.last_model.as_ref(),
+                },
+                nav_view_model,
+            ),
             View::Generate(generate_view_model) => {
-                frame.generate_view(frame.area(), Style::default(), generate_view_model)
+                frame.generate_view(view_area, Style::default(), generate_view_model)
+            }
+            View::Compare(compare_view_model) => {
+                frame.compare_view(view_area, Style::default(), compare_view_model)
+            }
+            View::Embeddings(embeddings_view_model) => {
+                frame.embeddings_view(view_area, Style::default(), embeddings_view_model)
+            }
+            View::Keymap(keymap_view_model) => {
+                frame.keymap_view(view_area, Style::default(), keymap_view_model)
             }
         }
-        if let Some(ref mut popup) = self.popup {
-            frame.popup(frame.area(), Style::active(), popup);
+
+        let status = Status {
+            view: ViewName::from(&self.view).into(),
+            input_mode: self.event_processor.input_mode,
+            model: self.view.model(),
+            host: &self.host,
+            connection: self.connection,
+        };
+        frame.status_bar(status_area, Style::default(), &status, &self.status_bar);
+
+        match &mut self.popup {
+            Some(Popup::Text(popup) | Popup::Progress(popup)) => {
+                frame.popup(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::SaveFile(popup)) => {
+                frame.save_file_popup(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::Instruction(popup)) => {
+                frame.instruction_form(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::Hosts(popup)) => frame.hosts_popup(frame.area(), Style::active(), popup),
+            Some(Popup::Templates(popup)) => {
+                frame.templates_popup(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::Viewer(popup)) => frame.viewer(frame.area(), Style::default(), popup),
+            Some(Popup::Branches(popup)) => {
+                frame.branches_popup(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::Attach(popup)) => frame.attach_popup(frame.area(), Style::active(), popup),
+            Some(Popup::Running(popup)) => {
+                frame.running_models(frame.area(), Style::active(), popup)
+            }
+            Some(Popup::ModelName(popup)) => {
+                frame.model_name_form(frame.area(), Style::active(), popup)
+            }
+            None => {}
         }
     }
 
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> anyhow::Result<()> {
         let period = Duration::from_secs_f32(1.0 / 15.0);
         let mut interval = tokio::time::interval(period);
+        let mut dashboard_refresh = tokio::time::interval(DASHBOARD_REFRESH);
         let mut events = ratatui::crossterm::event::EventStream::new();
         loop {
             tokio::select! {
@@ -149,9 +301,58 @@ impl AppContext {
                     }
                 },
                 Some(response) = self.model_context.response_receiver.recv() => {
-                    self.view.handle_response(response);
+                    self.handle_response(response).await;
+                }
+                _ = dashboard_refresh.tick() => self.refresh_dashboard().await,
+            }
+        }
+    }
+
+    /// Responses from long running tasks go to the progress popup,
+    /// the running models popup takes its list and any errors while it's open,
+    /// and everything else goes to the view
+    async fn handle_response(&mut self, response: Response) {
+        self.status_bar.observe(&response);
+
+        if let Response::Connection(connection) = response {
+            self.connection = connection;
+            return;
+        }
+
+        if let Response::Connected(host) = response {
+            self.host = host;
+            self.connection = ConnectionState::Online;
+            if let View::Models(_) | View::Compare(_) | View::Embeddings(_) = self.view {
+                self.submit_message(Prompt::LocalModels).await;
+            }
+            self.refresh_dashboard().await;
+            return;
+        }
+
+        let popup = match &mut self.popup {
+            Some(Popup::Progress(popup)) => popup,
+            Some(Popup::Running(popup))
+                if matches!(response, Response::RunningModels(_) | Response::Error(_)) =>
+            {
+                if let Err(error) = popup.handle_response(response) {
+                    tracing::error!(%error, "error handling response");
                 }
+                return;
+            }
+            _ => {
+                self.view.handle_response(response);
+                return;
+            }
+        };
+
+        match response {
+            Response::Progress(status) => popup.push_line(&status),
+            Response::ModelCreated(name) => {
+                popup.push_line(&format!("created {name}"));
+                self.submit_message(Prompt::LocalModels).await;
             }
+            Response::Error(error) => popup.push_line(&format!("error: {error}")),
+            response => self.view.handle_response(response),
         }
     }
 
@@ -171,9 +372,60 @@ impl AppContext {
                 self.edit_model_file(terminal, model_info)?;
                 Ok(true)
             }
+            AppEvent::LoadSession(session) => {
+                self.popup = None;
+                if let View::Chat(chat_view_model) = &mut self.view {
+                    chat_view_model.load_session(session);
+                }
+                Ok(true)
+            }
+            AppEvent::Attach(attachment) => {
+                self.popup = None;
+                self.event_processor.input_mode(InputMode::Normal);
+                if let View::Chat(chat_view_model) = &mut self.view {
+                    chat_view_model.attach(attachment);
+                }
+                Ok(true)
+            }
+            AppEvent::SetDraft(draft) => {
+                self.popup = None;
+                self.view.set_draft(&draft);
+                self.event_processor.input_mode(InputMode::Edit);
+                Ok(true)
+            }
+            AppEvent::Compose(draft) => {
+                match edit_text(terminal, &draft, DRAFT_SUFFIX) {
+                    // editors usually end the file with a newline
+                    Ok(draft) => self.view.set_draft(draft.trim_end()),
+                    Err(error) => {
+                        tracing::error!(%error, "unable to edit draft");
+                        self.popup = Some(Popup::Text(PopupViewModel::new(
+                            "unable to edit draft",
+                            error.to_string(),
+                        )));
+                    }
+                }
+                Ok(true)
+            }
             AppEvent::Quit => Ok(false),
             AppEvent::Activate(view) => {
-                self.view = view;
+                self.view = match (view, self.chat.take()) {
+                    (View::Chat(_), Some(chat_view_model)) => View::Chat(chat_view_model),
+                    (view, _) => view,
+                };
+                if let View::Keymap(keymap_view_model) = &mut self.view {
+                    keymap_view_model.load(self.event_processor.definitions.clone());
+                }
+                match &mut self.view {
+                    View::Chat(chat_view_model) => {
+                        chat_view_model.set_auto_summarize(self.config.chat.auto_summarize);
+                        chat_view_model.set_defaults(&self.config.defaults);
+                    }
+                    View::Generate(generate_view_model) => {
+                        generate_view_model.set_defaults(&self.config.defaults);
+                    }
+                    _ => {}
+                }
                 if let Some(event) = self.view.init().await? {
                     // necessary because of async recursion
                     Box::pin(self.handle_event(terminal, event)).await
@@ -184,30 +436,141 @@ impl AppContext {
             AppEvent::Deactivate => {
                 if self.popup.is_some() {
                     self.popup = None;
+                    self.event_processor.input_mode(InputMode::Normal);
                 } else {
-                    self.view = View::Nav(Default::default());
+                    if let View::Chat(chat_view_model) =
+                        std::mem::replace(&mut self.view, View::Nav(Default::default()))
+                    {
+                        self.chat = Some(chat_view_model);
+                    }
+                    self.refresh_dashboard().await;
+                }
+                Ok(true)
+            }
+            AppEvent::EditModelInstruction(form) => {
+                self.popup = Some(form.into());
+                Ok(true)
+            }
+            AppEvent::EditModelName(form) => {
+                self.popup = Some(form.into());
+                Ok(true)
+            }
+            AppEvent::CreateModel { name, modelfile } => {
+                self.popup = Some(Popup::Progress(PopupViewModel::new(
+                    format!("creating {name}"),
+                    "",
+                )));
+                self.event_processor.input_mode(InputMode::Normal);
+                self.submit_message(Prompt::CreateModel { name, modelfile })
+                    .await;
+                Ok(true)
+            }
+            AppEvent::Connect(host) => {
+                self.popup = None;
+                self.submit_message(Prompt::Connect(host)).await;
+                Ok(true)
+            }
+            AppEvent::CopyModel {
+                source,
+                destination,
+            } => {
+                self.popup = Some(Popup::Progress(PopupViewModel::new(
+                    format!("copying {source} to {destination}"),
+                    "",
+                )));
+                self.event_processor.input_mode(InputMode::Normal);
+                self.submit_message(Prompt::CopyModel {
+                    source,
+                    destination,
+                })
+                .await;
+                Ok(true)
+            }
+            AppEvent::UpdateModelfile(source) => {
+                if let View::Models(models_view_model) = &mut self.view {
+                    if let Err(error) = models_view_model.update_modelfile(&source) {
+                        tracing::error!(%error, "unable to update Modelfile");
+                    }
                 }
+                self.popup = None;
+                self.event_processor.input_mode(InputMode::Normal);
+                Ok(true)
+            }
+            AppEvent::SaveFile(data) => {
+                self.popup = Some(SaveFileViewModel::new(data).into());
+                Ok(true)
+            }
+            AppEvent::ViewText(viewer) => {
+                self.popup = Some(viewer.into());
                 Ok(true)
             }
             AppEvent::InputMode(input_mode) => {
                 self.event_processor.input_mode(input_mode);
                 Ok(true)
             }
+            AppEvent::UpdateKeymap(definitions) => {
+                match save_keymap(&definitions) {
+                    Ok(path) => tracing::info!(?path, "saved keymap"),
+                    Err(error) => tracing::error!(%error, "unable to save keymap"),
+                }
+                self.event_processor.definitions = definitions;
+                Ok(true)
+            }
         }
     }
 
     async fn handle_input(&mut self, event: Event) -> anyhow::Result<Option<AppEvent>> {
-        let action = self.event_processor.process(event);
+        if let (View::Keymap(keymap_view_model), Event::Key(key_event)) = (&mut self.view, &event) {
+            if keymap_view_model.is_capturing() {
+                return Ok(keymap_view_model.handle_key((*key_event).into()));
+            }
+        }
+
+        if let Event::Mouse(mouse_event) = event {
+            let Some(mouse) = MouseAction::from_event(mouse_event) else {
+                return Ok(None);
+            };
+            return if let Some(ref mut popup) = self.popup {
+                Ok(popup.handle_mouse(mouse)?)
+            } else {
+                Ok(self.view.handle_mouse(mouse)?)
+            };
+        }
+
+        let view_name: &'static str = ViewName::from(&self.view).into();
+        let action = self.event_processor.process(event, view_name);
 
         if let Some(ref mut popup) = self.popup {
             return Ok(popup.handle_action(action)?);
         }
 
         if action == Action::Popup {
-            self.popup = Some(PopupViewModel::log_popup(&self.config.log_file)?);
+            self.popup = Some(PopupViewModel::log_popup(&self.config.log_file)?.into());
+            Ok(None)
+        } else if action == Action::Hosts {
+            self.popup =
+                Some(HostsViewModel::new(self.config.all_hosts(), self.host.clone()).into());
+            Ok(None)
+        } else if action == Action::Running {
+            self.popup = Some(RunningModelsViewModel::default().into());
+            self.submit_message(Prompt::RunningModels).await;
+            Ok(None)
+        } else if action == Action::Templates
+            && matches!(self.view, View::Chat(_) | View::Generate(_))
+        {
+            self.popup = Some(TemplatesViewModel::new(&Templates::load()).into());
+            Ok(None)
+        } else if let (Action::Branches, View::Chat(chat_view_model)) = (action, &self.view) {
+            self.popup = Some(BranchesViewModel::new(chat_view_model.session()).into());
+            Ok(None)
+        } else if let (Action::Attach, View::Chat(_)) = (action, &self.view) {
+            self.popup = Some(AttachViewModel::new(self.max_attachment_chars()).into());
+            Ok(None)
+        } else if let (Action::LastCommand, View::Chat(_)) = (action, &self.view) {
+            self.attach_last_command();
             Ok(None)
         } else if action == Action::Help {
-            self.popup = Some(PopupViewModel::keymap_popup(&self.event_processor));
+            self.popup = Some(PopupViewModel::keymap_popup(&self.event_processor).into());
             Ok(None)
         } else {
             let app_event = match &mut self.view {
@@ -217,6 +580,11 @@ impl AppContext {
                 View::Models(models_view_model) => models_view_model.handle_event(action).await?,
                 View::Nav(nav_view_model) => nav_view_model.handle_action(action)?,
                 View::Generate(generate_view_model) => generate_view_model.handle_action(action)?,
+                View::Compare(compare_view_model) => compare_view_model.handle_action(action)?,
+                View::Embeddings(embeddings_view_model) => {
+                    embeddings_view_model.handle_action(action)?
+                }
+                View::Keymap(keymap_view_model) => keymap_view_model.handle_action(action)?,
             };
             Ok(app_event)
         }
@@ -228,21 +596,58 @@ impl AppContext {
         terminal: &mut DefaultTerminal,
         model_info: ModelInfo,
     ) -> anyhow::Result<()> {
-        stdout().execute(crossterm::terminal::LeaveAlternateScreen)?;
-        crossterm::terminal::disable_raw_mode()?;
+        let _edited_modelfile = edit_text(terminal, &model_info.modelfile, ".tmpl")?;
+        Ok(())
+    }
 
-        let mut edit_options = edit::Builder::default();
-        let edit_options = edit_options.suffix(".tmpl");
+    fn max_attachment_chars(&self) -> usize {
+        self.config
+            .chat
+            .max_attachment_chars
+            .unwrap_or(attachment::DEFAULT_MAX_CHARS)
+    }
 
-        let _edited_modelfile = edit::edit_with_builder(model_info.modelfile, edit_options)?;
+    /// Attach the last command captured by djinn's shell hook to the next chat prompt
+    fn attach_last_command(&mut self) {
+        let max_chars = self.max_attachment_chars();
+        let View::Chat(chat_view_model) = &mut self.view else {
+            return;
+        };
+        match shell::last_capture() {
+            Ok(Some(capture)) => {
+                chat_view_model.attach(Some(Attachment::command(capture, max_chars)))
+            }
+            Ok(None) => {
+                self.popup = Some(Popup::Text(PopupViewModel::new(
+                    "last command",
+                    "no captured commands, load the hook with `djinn watch-shell install`",
+                )))
+            }
+            Err(error) => tracing::error!(%error, "unable to read captured commands"),
+        }
+    }
 
-        stdout().execute(crossterm::terminal::EnterAlternateScreen)?;
-        crossterm::terminal::enable_raw_mode()?;
-        terminal.clear()?;
-        Ok(())
+    /// Ask the host for the nav view's stats.
+    /// Prompts aren't sent while the host is offline since they'd be queued.
+    async fn refresh_dashboard(&mut self) {
+        let View::Nav(nav_view_model) = &mut self.view else {
+            return;
+        };
+        nav_view_model.refresh();
+        if self.popup.is_some() || self.connection == ConnectionState::Offline {
+            return;
+        }
+        self.submit_message(Prompt::LocalModels).await;
+        // only Ollama reports which models are loaded
+        if self.backend == BackendKind::Ollama {
+            self.submit_message(Prompt::RunningModels).await;
+        }
     }
 
     async fn submit_message(&mut self, prompt: Prompt) {
+        if let Some(model) = prompt.model() {
+            self.last_model = Some(model.clone());
+        }
         self.model_context
             .prompt_sender
             .send(prompt)
@@ -257,5 +662,57 @@ pub enum AppEvent {
     Submit(Prompt),
     EditSystemPrompt(ModelInfo),
     InputMode(InputMode),
+    /// Use and save the edited keymap
+    UpdateKeymap(EventDefinitions),
+    /// Ask where to save the data with a popup
+    SaveFile(AppFileData),
+    /// Show text full screen with a popup
+    ViewText(ViewerViewModel),
+    /// Edit a single Modelfile instruction with a popup form
+    EditModelInstruction(InstructionFormViewModel),
+    /// Replace the Modelfile in the models view
+    UpdateModelfile(String),
+    /// Ask for a model name with a popup form
+    EditModelName(ModelNameViewModel),
+    /// Create a model and show its progress in a popup
+    CreateModel {
+        name: ModelName,
+        modelfile: String,
+    },
+    /// Switch the model context to another host
+    Connect(ModelHost),
+    /// Copy a model and show the result in a popup
+    CopyModel {
+        source: ModelName,
+        destination: ModelName,
+    },
+    /// Edit the draft of the active input in `$EDITOR`
+    Compose(String),
+    /// Start editing the prompt input with the given text
+    SetDraft(String),
+    /// Show a saved session in the chat view
+    LoadSession(Session),
+    /// Attach a file to the next prompt in the chat view,
+    /// or remove the pending files if there's none
+    Attach(Option<Attachment>),
     Quit,
 }
+
+/// Edit `text` in `$EDITOR` with the TUI suspended.
+/// `suffix` is the temporary file's extension, e.g. `.md`.
+fn edit_text(terminal: &mut DefaultTerminal, text: &str, suffix: &str) -> anyhow::Result<String> {
+    stdout().execute(crossterm::event::DisableMouseCapture)?;
+    stdout().execute(crossterm::terminal::LeaveAlternateScreen)?;
+    crossterm::terminal::disable_raw_mode()?;
+
+    let mut edit_options = edit::Builder::default();
+    let edit_options = edit_options.suffix(suffix);
+    // restore the TUI even if the editor fails
+    let edited = edit::edit_with_builder(text, edit_options);
+
+    stdout().execute(crossterm::terminal::EnterAlternateScreen)?;
+    stdout().execute(crossterm::event::EnableMouseCapture)?;
+    crossterm::terminal::enable_raw_mode()?;
+    terminal.clear()?;
+    Ok(edited?)
+}
@@ -1,18 +1,29 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
 
 use futures::StreamExt;
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
+    time::Instant,
 };
 use tracing::instrument;
 
 use crate::{
-    error::Result,
-    lm::{Prompt, Response},
-    ollama::{self, chat::ChatRequest, generate::Request, ModelName},
+    backend::{Backend, Chunk, TokenStream},
+    error::{Error, Result},
+    lm::{ConnectionState, Prompt, Response},
+    ollama::{
+        chat::ChatRequest,
+        embeddings::Embedding,
+        generate::Request,
+        tools::{ToolChatMessage, ToolRegistry},
+        ModelName,
+    },
 };
 
+/// The maximum number of tool call rounds before the chat gives up
+const MAX_TOOL_ROUNDS: usize = 5;
+
 #[derive(Debug)]
 pub struct ModelContext {
     _handle: JoinHandle<Result<()>>,
@@ -21,24 +32,43 @@ pub struct ModelContext {
 }
 
 impl ModelContext {
-    pub fn spawn(client: ollama::Client) -> ModelContext {
+    pub fn spawn(backend: Arc<dyn Backend>, tools: ToolRegistry) -> ModelContext {
         let (prompt_sender, mut prompt_receiver): (Sender<Prompt>, Receiver<Prompt>) =
             tokio::sync::mpsc::channel(5);
         let (response_sender, response_receiver) = tokio::sync::mpsc::channel(20);
 
         let context = ModeContext {
-            client,
+            backend,
             response_sender,
+            tools: Arc::new(tools),
         };
 
         let handle: JoinHandle<Result<()>> = tokio::spawn(async move {
-            while let Some(prompt) = prompt_receiver.recv().await {
-                match prompt {
-                    Prompt::Generate(string) => context.handle_generate_mode(string).await?,
-                    Prompt::Chat(request) => context.handle_chat_mode(request).await?,
-                    Prompt::LocalModels => context.load_local_models().await?,
-                    Prompt::ModelInfo(model_info) => context.get_model_info(model_info).await?,
+            let mut worker = Worker {
+                context,
+                generation: None,
+                pending: VecDeque::new(),
+                queue: VecDeque::new(),
+                health: Health::default(),
+            };
+
+            loop {
+                tokio::select! {
+                    prompt = prompt_receiver.recv() => {
+                        let Some(prompt) = prompt else {
+                            break;
+                        };
+                        worker.submit(prompt).await?;
+                    }
+                    () = finished(&mut worker.generation) => {
+                        worker.generation = None;
+                    }
+                    _ = tokio::time::sleep_until(worker.health.next_check) => {
+                        worker.check_health().await?;
+                    }
                 }
+                worker.drain_queue().await?;
+                worker.start_next();
             }
 
             Ok(())
@@ -52,98 +82,554 @@ impl ModelContext {
     }
 }
 
+/// Handles prompts in order and queues them while the host is offline
+struct Worker {
+    context: ModeContext,
+    /// The generation that is currently streaming, if any
+    generation: Option<JoinHandle<()>>,
+    /// Generations waiting for the current one to finish,
+    /// so that responses aren't interleaved
+    pending: VecDeque<Generation>,
+    /// Prompts received while the host was offline
+    queue: VecDeque<Prompt>,
+    health: Health,
+}
+
+impl Worker {
+    async fn submit(&mut self, prompt: Prompt) -> Result<()> {
+        if !self.health.online && prompt.needs_connection() {
+            tracing::debug!(
+                queued = self.queue.len() + 1,
+                "host is offline, queueing prompt"
+            );
+            self.queue.push_back(prompt);
+            return Ok(());
+        }
+
+        match self.handle_prompt(prompt).await {
+            Ok(()) => Ok(()),
+            Err(Error::SendResponse(error)) => Err(error.into()),
+            Err(error) => {
+                tracing::error!(%error, "error handling prompt");
+                self.context
+                    .response_sender
+                    .send(Response::Error(error.to_string().into()))
+                    .await?;
+                // the host may have gone away
+                self.check_health().await
+            }
+        }
+    }
+
+    async fn check_health(&mut self) -> Result<()> {
+        let online = self.context.backend.is_healthy().await;
+        self.set_online(online).await
+    }
+
+    async fn set_online(&mut self, online: bool) -> Result<()> {
+        if self.health.update(online) {
+            let state = if online {
+                ConnectionState::Online
+            } else {
+                ConnectionState::Offline
+            };
+            tracing::info!(%state, "host connection changed");
+            self.context
+                .response_sender
+                .send(Response::Connection(state))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the prompts that were queued while the host was offline
+    async fn drain_queue(&mut self) -> Result<()> {
+        while self.health.online {
+            let Some(prompt) = self.queue.pop_front() else {
+                break;
+            };
+            self.submit(prompt).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_prompt(&mut self, prompt: Prompt) -> Result<()> {
+        match prompt {
+            Prompt::Generate(request) => self.pending.push_back(Generation::Generate(request)),
+            Prompt::Compare { models, request } => self
+                .pending
+                .push_back(Generation::Compare { models, request }),
+            Prompt::Chat(request) => self.pending.push_back(Generation::Chat(request)),
+            Prompt::Cancel => self.cancel().await?,
+            Prompt::LocalModels => self.context.load_local_models().await?,
+            Prompt::ModelInfo(model_info) => self.context.get_model_info(model_info).await?,
+            Prompt::Embed { model, input } => self.context.embed(model, input).await?,
+            Prompt::Connect(host) => match self.context.backend.connect(host.url()).await {
+                Ok(backend) => {
+                    self.cancel().await?;
+                    self.context.backend = backend;
+                    tracing::info!(%host, "connected to host");
+                    self.context
+                        .response_sender
+                        .send(Response::Connected(host))
+                        .await?;
+                    self.set_online(true).await?;
+                }
+                Err(error) => {
+                    tracing::warn!(%error, %host, "unable to connect to host");
+                    self.context
+                        .response_sender
+                        .send(Response::Error(
+                            format!("unable to connect to {host}: {error}").into(),
+                        ))
+                        .await?;
+                }
+            },
+            Prompt::RunningModels => self.context.load_running_models().await?,
+            Prompt::Unload(model) => self.context.unload_model(model).await?,
+            Prompt::CopyModel {
+                source,
+                destination,
+            } => self.context.copy_model(source, destination).await?,
+            Prompt::CreateModel { name, modelfile } => {
+                let context = self.context.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = context.create_model(name, modelfile).await {
+                        context.report_error(error, "error creating model").await;
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the next pending generation once the current one is done.
+    /// Generations run in their own task so that the worker keeps handling prompts,
+    /// like [`Prompt::Cancel`], while they stream.
+    fn start_next(&mut self) {
+        if self.generation.is_some() {
+            return;
+        }
+        let Some(generation) = self.pending.pop_front() else {
+            return;
+        };
+        let context = self.context.clone();
+        self.generation = Some(tokio::spawn(async move {
+            let result = match generation {
+                Generation::Generate(request) => context.handle_generate_mode(request).await,
+                Generation::Compare { models, request } => {
+                    context.handle_compare(models, request).await
+                }
+                Generation::Chat(request) => context.handle_chat_mode(request).await,
+            };
+            if let Err(error) = result {
+                context
+                    .report_error(error, "error generating response")
+                    .await;
+            }
+        }));
+    }
+
+    /// Stop the current generation if it's still streaming
+    /// and drop the ones waiting to start
+    async fn cancel(&mut self) -> Result<()> {
+        let dropped = self.pending.len();
+        self.pending.clear();
+        let running = match self.generation.take() {
+            Some(generation) if !generation.is_finished() => {
+                generation.abort();
+                true
+            }
+            _ => false,
+        };
+        if running || dropped > 0 {
+            tracing::info!(dropped, "generation cancelled");
+            self.context
+                .response_sender
+                .send(Response::Cancelled)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Tracks whether the host is reachable and when to check again.
+/// Checks back off exponentially while the host is offline.
 #[derive(Debug)]
+struct Health {
+    online: bool,
+    backoff: Duration,
+    next_check: Instant,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            online: true,
+            backoff: MIN_BACKOFF,
+            next_check: Instant::now() + HEALTH_INTERVAL,
+        }
+    }
+}
+
+impl Health {
+    /// Returns true if the state changed
+    fn update(&mut self, online: bool) -> bool {
+        let changed = self.online != online;
+        self.online = online;
+        if online {
+            self.backoff = MIN_BACKOFF;
+            self.next_check = Instant::now() + HEALTH_INTERVAL;
+        } else {
+            self.next_check = Instant::now() + self.backoff;
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+        changed
+    }
+}
+
+/// A prompt that streams a response
+enum Generation {
+    Generate(Request),
+    Compare {
+        models: Vec<ModelName>,
+        request: Request,
+    },
+    Chat(ChatRequest),
+}
+
+/// Wait for the current generation to finish, or forever if there isn't one
+async fn finished(generation: &mut Option<JoinHandle<()>>) {
+    match generation {
+        Some(generation) => {
+            if let Err(error) = generation.await {
+                tracing::warn!(%error, "generation did not finish");
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ModeContext {
-    pub client: ollama::Client,
+    pub backend: Arc<dyn Backend>,
     pub response_sender: Sender<Response>,
+    pub tools: Arc<ToolRegistry>,
 }
 
 impl ModeContext {
+    /// Log an error from a spawned task and show it in the TUI
+    async fn report_error(&self, error: Error, message: &str) {
+        tracing::error!(%error, "{message}");
+        if let Err(error) = self
+            .response_sender
+            .send(Response::Error(error.to_string().into()))
+            .await
+        {
+            tracing::warn!(%error, "unable to send error to the TUI");
+        }
+    }
+
     async fn load_local_models(&self) -> Result<()> {
-        let local_models = self.client.list_local_models().await?;
+        let local_models = self.backend.list_models().await?;
         self.response_sender
             .send(Response::LocalModels(local_models))
             .await?;
         Ok(())
     }
 
+    async fn load_running_models(&self) -> Result<()> {
+        let response = match self.backend.running_models().await {
+            Ok(models) => Response::RunningModels(models),
+            Err(error) => Response::Error(error.to_string().into()),
+        };
+        self.response_sender.send(response).await?;
+        Ok(())
+    }
+
+    async fn unload_model(&self, model: ModelName) -> Result<()> {
+        if let Err(error) = self.backend.unload_model(&model).await {
+            self.response_sender
+                .send(Response::Error(error.to_string().into()))
+                .await?;
+            return Ok(());
+        }
+        tracing::info!(%model, "unloaded model");
+        self.load_running_models().await
+    }
+
     async fn get_model_info(&self, model_name: ModelName) -> Result<()> {
-        let model_info = self.client.model_info(model_name).await?;
+        let model_info = self.backend.model_info(&model_name).await?;
         self.response_sender
             .send(Response::ModelInfo(model_info))
             .await?;
         Ok(())
     }
 
-    async fn handle_generate_mode(&self, prompt: Arc<str>) -> Result<()> {
-        let result = self
-            .client
-            .generate(Request {
-                prompt,
-                model: Default::default(),
-                system: None,
-            })
-            .await;
-
-        match result {
-            Ok(mut stream) => {
-                while let Some(responses) = stream.next().await {
-                    match responses {
-                        Ok(responses) => {
-                            for response in responses {
-                                self.response_sender
-                                    .send(Response::Token(response.response.into()))
-                                    .await?
-                            }
-                        }
-                        Err(error) => {
-                            self.response_sender
-                                .send(Response::Error(error.to_string().into()))
-                                .await?;
-                        }
-                    }
-                }
-                self.response_sender.send(Response::Eos).await?;
-            }
+    async fn create_model(&self, name: ModelName, modelfile: String) -> Result<()> {
+        let mut stream = match self.backend.create_model(&name, &modelfile).await {
+            Ok(stream) => stream,
             Err(error) => {
                 self.response_sender
                     .send(Response::Error(error.to_string().into()))
                     .await?;
+                return Ok(());
+            }
+        };
+
+        while let Some(status) = stream.next().await {
+            match status {
+                Ok(status) => {
+                    self.response_sender
+                        .send(Response::Progress(status.into()))
+                        .await?
+                }
+                Err(error) => {
+                    self.response_sender
+                        .send(Response::Error(error.to_string().into()))
+                        .await?;
+                    return Ok(());
+                }
             }
         }
+
+        self.response_sender
+            .send(Response::ModelCreated(name))
+            .await?;
+        Ok(())
+    }
+
+    async fn copy_model(&self, source: ModelName, destination: ModelName) -> Result<()> {
+        let response = match self.backend.copy_model(&source, &destination).await {
+            Ok(()) => Response::ModelCreated(destination),
+            Err(error) => Response::Error(error.to_string().into()),
+        };
+        self.response_sender.send(response).await?;
+        Ok(())
+    }
+
+    async fn embed(&self, model: ModelName, input: Arc<str>) -> Result<()> {
+        let response = match self.backend.embed(&model, &input).await {
+            Ok(vector) => Response::Embedding(Embedding {
+                input,
+                model,
+                vector: vector.into(),
+            }),
+            Err(error) => Response::Error(error.to_string().into()),
+        };
+
+        self.response_sender.send(response).await?;
+        Ok(())
+    }
+
+    async fn handle_generate_mode(&self, request: Request) -> Result<()> {
+        let started = Instant::now();
+        let result = self.backend.generate(request).await;
+        self.send_stream(started, result).await
+    }
+
+    /// Generate with every model at once,
+    /// tagging each response with the index of its model
+    async fn handle_compare(&self, models: Vec<ModelName>, request: Request) -> Result<()> {
+        let runs = models.into_iter().enumerate().map(|(index, model)| {
+            let request = Request {
+                model,
+                ..request.clone()
+            };
+            async move {
+                let started = Instant::now();
+                let result = self.backend.generate(request).await;
+                self.forward_stream(started, result, |response| Response::Compare {
+                    index,
+                    response: Box::new(response),
+                })
+                .await
+            }
+        });
+
+        futures::future::try_join_all(runs).await?;
         Ok(())
     }
 
     #[instrument]
     async fn handle_chat_mode(&self, prompt: ChatRequest) -> Result<()> {
-        let result = self.client.chat(prompt).await;
-
-        match result {
-            Ok(mut stream) => {
-                while let Some(responses) = stream.next().await {
-                    match responses {
-                        Ok(response) => {
-                            if let Some(response) = response.message {
-                                self.response_sender
-                                    .send(Response::Token(response.content.into()))
-                                    .await?
-                            }
-                        }
-                        Err(()) => {
-                            self.response_sender
-                                .send(Response::Error("error in response".into()))
-                                .await?;
-                        }
-                    }
+        let prompt = self.summarize(prompt).await?;
+        if !self.tools.is_empty() {
+            return self.handle_tool_chat(prompt).await;
+        }
+
+        let started = Instant::now();
+        let result = self.backend.chat(prompt).await;
+        self.send_stream(started, result).await
+    }
+
+    /// Replace the oldest messages with a summary if the request asks for one.
+    /// The whole history is sent if the model can't summarize it.
+    async fn summarize(&self, request: ChatRequest) -> Result<ChatRequest> {
+        let Some(summary_request) = request.summary_request() else {
+            return Ok(request);
+        };
+
+        match self.collect_chat(summary_request).await {
+            Ok(summary) => {
+                self.response_sender
+                    .send(Response::Summary {
+                        replaced: request.summarized_count(),
+                        summary: summary.as_str().into(),
+                    })
+                    .await?;
+                Ok(request.with_summary(&summary))
+            }
+            Err(error) => {
+                tracing::warn!(%error, "unable to summarize the chat history");
+                Ok(ChatRequest {
+                    summarize: None,
+                    ..request
+                })
+            }
+        }
+    }
+
+    /// Chat without streaming the response to the TUI
+    async fn collect_chat(&self, request: ChatRequest) -> Result<String> {
+        let mut stream = self.backend.chat(request).await?;
+        let mut response = String::new();
+        while let Some(chunk) = stream.next().await {
+            if let Chunk::Token(token) = chunk? {
+                response.push_str(&token);
+            }
+        }
+        Ok(response)
+    }
+
+    /// Forward a streamed response to the TUI
+    async fn send_stream(&self, started: Instant, result: Result<TokenStream>) -> Result<()> {
+        self.forward_stream(started, result, |response| response)
+            .await
+    }
+
+    /// Forward a streamed response to the TUI, wrapping each response with `wrap`
+    async fn forward_stream(
+        &self,
+        started: Instant,
+        result: Result<TokenStream>,
+        wrap: impl Fn(Response) -> Response,
+    ) -> Result<()> {
+        let mut stream = match result {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.response_sender
+                    .send(wrap(Response::Error(error.to_string().into())))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let mut first_token = None;
+        while let Some(chunk) = stream.next().await {
+            let response = match chunk {
+                Ok(Chunk::Token(token)) => {
+                    first_token.get_or_insert_with(|| started.elapsed());
+                    Response::Token(token)
+                }
+                Ok(Chunk::Stats(stats)) => {
+                    first_token.get_or_insert_with(|| started.elapsed());
+                    Response::Stats(stats.with_time_to_first_token(first_token))
+                }
+                Err(error) => Response::Error(error.to_string().into()),
+            };
+            self.response_sender.send(wrap(response)).await?;
+        }
+        self.response_sender.send(wrap(Response::Eos)).await?;
+        Ok(())
+    }
+
+    /// Chat with tools available to the model.
+    /// Tool calls are run and their results are sent back to the model
+    /// until it responds without calling any tools.
+    #[instrument(skip(self))]
+    async fn handle_tool_chat(&self, request: ChatRequest) -> Result<()> {
+        let model = request.model.to_string();
+        let mut messages: Vec<ToolChatMessage> = request
+            .context()
+            .map(|message| ToolChatMessage::from(&message))
+            .chain(std::iter::once(ToolChatMessage {
+                role: "user".into(),
+                content: request.prompt.to_string(),
+                tool_calls: vec![],
+            }))
+            .collect();
+
+        let started = Instant::now();
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let (message, stats) = match self
+                .backend
+                .chat_with_tools(&model, &messages, &self.tools, &request.options)
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    self.response_sender
+                        .send(Response::Error(error.to_string().into()))
+                        .await?;
+                    return Ok(());
                 }
+            };
+
+            if message.tool_calls.is_empty() {
+                // the answer isn't streamed so the first token is the whole answer
+                let stats = stats.with_time_to_first_token(Some(started.elapsed()));
+                self.response_sender.send(Response::Stats(stats)).await?;
+                self.response_sender
+                    .send(Response::Token(message.content.into()))
+                    .await?;
                 self.response_sender.send(Response::Eos).await?;
+                return Ok(());
             }
-            Err(error) => {
+
+            let calls = message.tool_calls.clone();
+            messages.push(message);
+            for call in calls {
                 self.response_sender
-                    .send(Response::Error(error.to_string().into()))
+                    .send(Response::Token(
+                        format!("[tool: {}]\n", call.function.name).into(),
+                    ))
                     .await?;
+                let result = self.tools.call(&call).await;
+                messages.push(ToolChatMessage::tool_result(result));
             }
         }
+
+        self.response_sender
+            .send(Response::Error(
+                format!("gave up after {MAX_TOOL_ROUNDS} rounds of tool calls").into(),
+            ))
+            .await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_checks_back_off_while_offline() {
+        let mut health = Health::default();
+
+        assert!(health.update(false));
+        assert_eq!(health.backoff, MIN_BACKOFF * 2);
+        for _ in 0..10 {
+            assert!(!health.update(false));
+        }
+        assert_eq!(health.backoff, MAX_BACKOFF);
+
+        assert!(health.update(true));
+        assert_eq!(health.backoff, MIN_BACKOFF);
+    }
+}
@@ -0,0 +1,398 @@
+//! Instructions of a Modelfile as they're written in its source
+//! so that they can be edited one at a time with a form.
+
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use itertools::Itertools as _;
+use modelfile::Modelfile;
+use ratatui::{
+    layout::{Position, Rect},
+    style::Style,
+    widgets::Clear,
+    Frame,
+};
+
+use crate::{
+    error::Result,
+    tui::{
+        event::{Action, MouseAction},
+        form::{FormEvent, FormView as _, FormViewModel},
+        AppEvent,
+    },
+};
+
+const TRIPLE_QUOTE: &str = r#"""""#;
+/// The file name prefix of blobs in Ollama's model directory
+const BLOB_PREFIX: &str = "sha256-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
+pub enum Keyword {
+    From,
+    Parameter,
+    Template,
+    System,
+    Adapter,
+    License,
+    Message,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub keyword: Keyword,
+    pub args: String,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.keyword, self.args)
+    }
+}
+
+impl Instruction {
+    /// The first line of the instruction
+    pub fn summary(&self) -> String {
+        self.to_string()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Form fields for each argument.
+    /// Newlines are escaped so that text fits in a single line input.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        match self.keyword {
+            Keyword::Parameter => {
+                let (name, value) = split_first_word(&self.args);
+                vec![("name", name.to_string()), ("value", value.to_string())]
+            }
+            Keyword::Message => {
+                let (role, content) = split_first_word(&self.args);
+                vec![
+                    ("role", role.to_string()),
+                    ("content", escape(unquote(content))),
+                ]
+            }
+            Keyword::From | Keyword::Adapter => vec![("path", self.args.clone())],
+            Keyword::Template | Keyword::System | Keyword::License => {
+                vec![("text", escape(unquote(&self.args)))]
+            }
+        }
+    }
+
+    /// Build an instruction from the values of [`Self::fields`]
+    pub fn from_fields<'a>(
+        keyword: Keyword,
+        values: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        let values: HashMap<&str, &str> = values.into_iter().collect();
+        let value = |name: &str| values.get(name).copied().unwrap_or_default().trim();
+
+        let args = match keyword {
+            Keyword::Parameter => format!("{} {}", value("name"), value("value")),
+            Keyword::Message => format!("{} {}", value("role"), quote(&unescape(value("content")))),
+            Keyword::From | Keyword::Adapter => value("path").to_string(),
+            Keyword::Template | Keyword::System | Keyword::License => {
+                quote(&unescape(value("text")))
+            }
+        };
+
+        Instruction { keyword, args }
+    }
+}
+
+fn split_first_word(args: &str) -> (&str, &str) {
+    let args = args.trim();
+    args.split_once(char::is_whitespace)
+        .map(|(first, rest)| (first, rest.trim()))
+        .unwrap_or((args, ""))
+}
+
+fn unquote(text: &str) -> &str {
+    let text = text.trim();
+    text.strip_prefix(TRIPLE_QUOTE)
+        .and_then(|text| text.strip_suffix(TRIPLE_QUOTE))
+        .or_else(|| {
+            text.strip_prefix('"')
+                .and_then(|text| text.strip_suffix('"'))
+        })
+        .unwrap_or(text)
+}
+
+fn quote(text: &str) -> String {
+    if text.contains('\n') || text.contains('"') {
+        format!("{TRIPLE_QUOTE}{text}{TRIPLE_QUOTE}")
+    } else {
+        text.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n")
+}
+
+/// Split a Modelfile into its instructions, skipping comments
+pub fn split_instructions(source: &str) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = Vec::new();
+    let mut in_quotes = false;
+
+    for line in source.lines() {
+        if in_quotes {
+            if let Some(instruction) = instructions.last_mut() {
+                instruction.args.push('\n');
+                instruction.args.push_str(line);
+            }
+            in_quotes = !line.contains(TRIPLE_QUOTE);
+            continue;
+        }
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, args) = split_first_word(line);
+        match keyword.parse::<Keyword>() {
+            Ok(keyword) => {
+                in_quotes = args.matches(TRIPLE_QUOTE).count() == 1;
+                instructions.push(Instruction {
+                    keyword,
+                    args: args.to_string(),
+                });
+            }
+            Err(_) => tracing::warn!(line, "skipping unknown Modelfile instruction"),
+        }
+    }
+
+    instructions
+}
+
+pub fn join_instructions(instructions: &[Instruction]) -> String {
+    instructions.iter().join("\n") + "\n"
+}
+
+/// Check that local GGUF files and blobs in FROM and ADAPTER instructions
+/// have a valid header, and that only ADAPTER points to an adapter.
+/// Model names and files that aren't on this machine, e.g. on a remote server, are skipped.
+pub fn check_gguf_references(instructions: &[Instruction]) -> std::result::Result<(), String> {
+    for instruction in instructions {
+        if !matches!(instruction.keyword, Keyword::From | Keyword::Adapter) {
+            continue;
+        }
+        let path = Path::new(instruction.args.trim());
+        let is_gguf = path.extension().is_some_and(|ext| ext == "gguf")
+            || path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(BLOB_PREFIX));
+        if !is_gguf || !path.is_file() {
+            continue;
+        }
+
+        let header = djinn_gguf::Header::open(path)
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+        match instruction.keyword {
+            Keyword::From if header.is_adapter() => {
+                return Err(format!(
+                    "{} is an adapter, use ADAPTER instead of FROM",
+                    path.display()
+                ))
+            }
+            Keyword::Adapter if header.file_type().is_some() && !header.is_adapter() => {
+                return Err(format!("{} is a model, not an adapter", path.display()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Popup form that edits or adds a single instruction.
+/// The whole Modelfile is parsed before the edit is accepted.
+#[derive(Debug, Clone)]
+pub struct InstructionFormViewModel {
+    /// The instruction being edited or `None` to add one
+    index: Option<usize>,
+    keyword: Keyword,
+    instructions: Vec<Instruction>,
+    form: FormViewModel,
+    area: Rect,
+}
+
+impl InstructionFormViewModel {
+    pub fn edit(instructions: Vec<Instruction>, index: usize) -> Option<Self> {
+        let instruction = instructions.get(index)?;
+        Some(InstructionFormViewModel {
+            index: Some(index),
+            keyword: instruction.keyword,
+            form: FormViewModel::new(instruction.fields()),
+            instructions,
+            area: Rect::default(),
+        })
+    }
+
+    pub fn add(instructions: Vec<Instruction>, keyword: Keyword) -> Self {
+        let empty = Instruction {
+            keyword,
+            args: String::new(),
+        };
+        InstructionFormViewModel {
+            index: None,
+            keyword,
+            form: FormViewModel::new(empty.fields()),
+            instructions,
+            area: Rect::default(),
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.index {
+            Some(_) => format!("edit {}", self.keyword),
+            None => format!("add {}", self.keyword),
+        }
+    }
+
+    /// The Modelfile with this edit applied if it parses
+    fn apply(&self) -> std::result::Result<String, String> {
+        let instruction = Instruction::from_fields(self.keyword, self.form.values());
+        let mut instructions = self.instructions.clone();
+        match self.index {
+            Some(index) => instructions[index] = instruction,
+            None => instructions.push(instruction),
+        }
+
+        let source = join_instructions(&instructions);
+        source
+            .parse::<Modelfile>()
+            .map_err(|error| error.to_string())?;
+        check_gguf_references(&instructions)?;
+        Ok(source)
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match self.form.handle_action(action)? {
+            Some(FormEvent::Submit) => {
+                if self.form.next_field() {
+                    return Ok(None);
+                }
+                match self.apply() {
+                    Ok(source) => Ok(Some(AppEvent::UpdateModelfile(source))),
+                    Err(error) => {
+                        self.form.set_error(Some(error));
+                        Ok(None)
+                    }
+                }
+            }
+            Some(FormEvent::InputMode(input_mode)) => Ok(Some(AppEvent::InputMode(input_mode))),
+            Some(FormEvent::Quit) => Ok(Some(AppEvent::Deactivate)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = InstructionFormView)]
+pub impl<'a> Frame<'a> {
+    fn instruction_form(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        view_model: &mut InstructionFormViewModel,
+    ) {
+        let width = parent.width * 3 / 5;
+        let height = view_model.form.height().min(parent.height);
+        let area = Rect {
+            x: parent.x + (parent.width - width) / 2,
+            y: parent.y + (parent.height - height) / 2,
+            width,
+            height,
+        };
+        view_model.area = area;
+
+        self.render_widget(Clear, area);
+        let title = view_model.title();
+        self.form_view(area, style, &title, true, &view_model.form);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODELFILE: &str = r#"# Modelfile generated by "ollama show"
+FROM /models/blobs/sha256-1234
+TEMPLATE """{{ .System }}
+{{ .Prompt }}"""
+PARAMETER stop "<|eot_id|>"
+MESSAGE user hello
+"#;
+
+    #[test]
+    fn split_keeps_multiline_arguments() {
+        let instructions = split_instructions(MODELFILE);
+        let keywords: Vec<Keyword> = instructions.iter().map(|i| i.keyword).collect();
+
+        assert_eq!(
+            keywords,
+            [
+                Keyword::From,
+                Keyword::Template,
+                Keyword::Parameter,
+                Keyword::Message
+            ]
+        );
+        assert_eq!(
+            instructions[1].args,
+            "\"\"\"{{ .System }}\n{{ .Prompt }}\"\"\""
+        );
+    }
+
+    #[test]
+    fn gguf_references_are_checked() {
+        let dir = std::env::temp_dir().join(format!("ollama-cli-gguf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let broken = dir.join("broken.gguf");
+        std::fs::write(&broken, "not a model").unwrap();
+
+        let from = |path: &str| Instruction {
+            keyword: Keyword::From,
+            args: path.to_string(),
+        };
+        let checked = check_gguf_references(&[from(&broken.display().to_string())]);
+        let remote = check_gguf_references(&split_instructions(MODELFILE));
+        let named = check_gguf_references(&[from("llama3.2")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(checked.unwrap_err().contains("not a GGUF file"));
+        assert!(remote.is_ok());
+        assert!(named.is_ok());
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        for instruction in split_instructions(MODELFILE) {
+            let fields = instruction.fields();
+            let rebuilt = Instruction::from_fields(
+                instruction.keyword,
+                fields.iter().map(|(name, value)| (*name, value.as_str())),
+            );
+            assert_eq!(rebuilt, instruction);
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use instruction::InstructionFormViewModel;
 use model_info::{ModelInfoView, ModelInfoViewModel};
 use model_list::{ModelListView, ModelListViewModel};
+use model_name::{ModelNameAction, ModelNameViewModel};
 use modelfile::{ModelfileView, ModelfileViewModel};
 use ollama_rs::models::ModelInfo;
 use ratatui::{
-    layout::{Constraint, Layout, Rect},
+    layout::{Constraint, Layout, Position, Rect},
     style::Style,
     Frame,
 };
@@ -14,19 +16,31 @@ use crate::{
     ollama::ModelName,
 };
 
-use super::{event::Action, AppEvent, StyleExt};
+use super::{
+    event::{Action, MouseAction},
+    AppEvent, StyleExt,
+};
 
+pub mod instruction;
 mod model_info;
 mod model_list;
+pub mod model_name;
 mod modelfile;
+pub mod running;
 
 #[derive(Clone, Debug, Default)]
 pub struct ModelsViewModel {
     model_list: ModelListViewModel,
     model_info: ModelInfoViewModel,
     modelfile: ModelfileViewModel,
+    /// The model whose info and Modelfile are shown
+    selected_model: Option<ModelName>,
     active_pane: Option<Pane>,
     focused_pane: Pane,
+    /// Areas from the last draw, used to hit test mouse events
+    model_list_area: Rect,
+    model_info_area: Rect,
+    modelfile_area: Rect,
 }
 
 impl ModelsViewModel {
@@ -41,6 +55,55 @@ impl ModelsViewModel {
         }
     }
 
+    pub fn selected_model(&self) -> Option<&ModelName> {
+        self.selected_model.as_ref()
+    }
+
+    /// Show a Modelfile edited with [`InstructionFormViewModel`]
+    pub fn update_modelfile(&mut self, source: &str) -> Result<()> {
+        self.modelfile.load(source)
+    }
+
+    /// Ask for a name to create a model from the Modelfile
+    fn create_model(&self) -> Option<AppEvent> {
+        let modelfile = self.modelfile.source()?;
+        Some(AppEvent::EditModelName(ModelNameViewModel::new(
+            ModelNameAction::Create { modelfile },
+            self.selected_model.as_ref(),
+        )))
+    }
+
+    /// Ask for a name to copy the model to
+    fn duplicate_model(&self, source: Option<ModelName>) -> Option<AppEvent> {
+        let source = source?;
+        Some(AppEvent::EditModelName(ModelNameViewModel::new(
+            ModelNameAction::Copy {
+                source: source.clone(),
+            },
+            Some(&source),
+        )))
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        if let MouseAction::Click(position) = mouse {
+            if let Some(pane) = self.pane_at(position) {
+                self.focused_pane = pane;
+                self.active_pane = Some(pane);
+            }
+        }
+        Ok(None)
+    }
+
+    fn pane_at(&self, position: Position) -> Option<Pane> {
+        [
+            (self.model_list_area, Pane::ModelList),
+            (self.model_info_area, Pane::ModelInfo),
+            (self.modelfile_area, Pane::Modelfile),
+        ]
+        .into_iter()
+        .find_map(|(area, pane)| area.contains(position).then_some(pane))
+    }
+
     pub async fn handle_event(&mut self, action: Action) -> Result<Option<AppEvent>> {
         if let Some(pane) = &self.active_pane {
             let model_event = match pane {
@@ -57,11 +120,17 @@ impl ModelsViewModel {
                     }
                     ModelEvent::Refresh => Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
                     ModelEvent::GetInfo(model_name) => {
+                        self.selected_model = Some(model_name.clone());
                         Ok(Some(AppEvent::Submit(Prompt::ModelInfo(model_name))))
                     }
                     ModelEvent::EditInfo(model_info) => {
                         Ok(Some(AppEvent::EditSystemPrompt(model_info)))
                     }
+                    ModelEvent::EditInstruction(form) => {
+                        Ok(Some(AppEvent::EditModelInstruction(form)))
+                    }
+                    ModelEvent::Create => Ok(self.create_model()),
+                    ModelEvent::Duplicate(model_name) => Ok(self.duplicate_model(Some(model_name))),
                 }
             } else {
                 Ok(None)
@@ -77,6 +146,8 @@ impl ModelsViewModel {
                     Ok(None)
                 }
                 Action::Refresh => Ok(Some(AppEvent::Submit(Prompt::LocalModels))),
+                Action::Create => Ok(self.create_model()),
+                Action::Duplicate => Ok(self.duplicate_model(self.selected_model.clone())),
                 Action::Enter => {
                     self.active_pane = Some(self.focused_pane);
                     Ok(None)
@@ -91,7 +162,12 @@ impl ModelsViewModel {
 #[derive(Clone, Debug)]
 pub enum ModelEvent {
     Deactivate,
+    /// Create a model from the edited Modelfile
+    Create,
+    /// Copy a model to a new name
+    Duplicate(ModelName),
     EditInfo(ModelInfo),
+    EditInstruction(InstructionFormViewModel),
     GetInfo(ModelName),
     Refresh,
 }
@@ -132,6 +208,9 @@ pub impl<'a> Frame<'a> {
         ]);
 
         let [model_list_area, model_info_area, modelfile_area] = vertical.areas(parent);
+        view_model.model_list_area = model_list_area;
+        view_model.model_info_area = model_info_area;
+        view_model.modelfile_area = modelfile_area;
 
         let model_list_style = if let Some(Pane::ModelList) = view_model.active_pane {
             Style::active()
@@ -35,22 +35,21 @@ impl ModelListViewModel {
         Ok(())
     }
 
+    fn selected_name(&self) -> Option<ModelName> {
+        let model = self
+            .widget_state
+            .selected()
+            .and_then(|index| self.models.get(index))?;
+        let name: Arc<str> = model.name.clone().into();
+        Some(ModelName(name))
+    }
+
     pub async fn handle_event(&mut self, action: Action) -> Result<Option<ModelEvent>> {
         match action {
             Action::Refresh => Ok(Some(ModelEvent::Refresh)),
             Action::Quit => Ok(Some(ModelEvent::Deactivate)),
-            Action::Enter => {
-                if let Some(model) = self
-                    .widget_state
-                    .selected()
-                    .and_then(|index| self.models.get(index))
-                {
-                    let name: Arc<str> = model.name.clone().into();
-                    Ok(Some(ModelEvent::GetInfo(ModelName(name))))
-                } else {
-                    Ok(None)
-                }
-            }
+            Action::Enter => Ok(self.selected_name().map(ModelEvent::GetInfo)),
+            Action::Duplicate => Ok(self.selected_name().map(ModelEvent::Duplicate)),
             Action::Down => {
                 self.widget_state.select_next();
                 Ok(None)
@@ -77,11 +76,13 @@ pub impl<'a> Frame<'a> {
             .map(|info| {
                 let name = Span::from(info.name.as_str());
                 let size = Span::from(info.size.fit_to_bytesize());
-                let last_modified: DateTime<Utc> = info
+                // not every backend knows when a model was modified
+                let last_modified = info
                     .modified_at
-                    .parse()
-                    .expect("could not parse datetime from ollama");
-                let last_modified = Span::from(last_modified.format("%a %v %T").to_string());
+                    .parse::<DateTime<Utc>>()
+                    .map(|time| time.format("%a %v %T").to_string())
+                    .unwrap_or_default();
+                let last_modified = Span::from(last_modified);
                 Row::from_iter([name, size, last_modified])
             })
             .collect();
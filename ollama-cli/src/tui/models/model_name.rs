@@ -0,0 +1,117 @@
+use ratatui::{
+    layout::{Position, Rect},
+    style::Style,
+    widgets::Clear,
+    Frame,
+};
+
+use crate::{
+    error::Result,
+    ollama::ModelName,
+    tui::{
+        event::{Action, MouseAction},
+        form::{FormEvent, FormView as _, FormViewModel},
+        AppEvent,
+    },
+};
+
+const NAME_LABEL: &str = "name";
+
+/// What to do with the model name once it's submitted
+#[derive(Debug, Clone)]
+pub enum ModelNameAction {
+    /// Create a model from the Modelfile
+    Create { modelfile: String },
+    /// Copy an existing model
+    Copy { source: ModelName },
+}
+
+/// Popup form that asks for the name of a model
+#[derive(Debug, Clone)]
+pub struct ModelNameViewModel {
+    action: ModelNameAction,
+    form: FormViewModel,
+    area: Rect,
+}
+
+impl ModelNameViewModel {
+    pub fn new(action: ModelNameAction, name: Option<&ModelName>) -> Self {
+        let name = name.map(ToString::to_string).unwrap_or_default();
+        ModelNameViewModel {
+            action,
+            form: FormViewModel::new([(NAME_LABEL, name)]),
+            area: Rect::default(),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self.action {
+            ModelNameAction::Create { .. } => "create model",
+            ModelNameAction::Copy { .. } => "duplicate model",
+        }
+    }
+
+    fn submit(&mut self) -> Option<AppEvent> {
+        let name = self
+            .form
+            .values()
+            .find_map(|(label, value)| (label == NAME_LABEL).then_some(value.trim()))
+            .unwrap_or_default();
+        if name.is_empty() {
+            self.form.set_error(Some("a model name is required".into()));
+            return None;
+        }
+
+        let name = ModelName(name.into());
+        match &self.action {
+            ModelNameAction::Create { modelfile } => Some(AppEvent::CreateModel {
+                name,
+                modelfile: modelfile.clone(),
+            }),
+            ModelNameAction::Copy { source } => Some(AppEvent::CopyModel {
+                source: source.clone(),
+                destination: name,
+            }),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match self.form.handle_action(action)? {
+            Some(FormEvent::Submit) => Ok(self.submit()),
+            Some(FormEvent::InputMode(input_mode)) => Ok(Some(AppEvent::InputMode(input_mode))),
+            Some(FormEvent::Quit) => Ok(Some(AppEvent::Deactivate)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = ModelNameView)]
+pub impl<'a> Frame<'a> {
+    fn model_name_form(&mut self, parent: Rect, style: Style, view_model: &mut ModelNameViewModel) {
+        let width = parent.width * 3 / 5;
+        let height = view_model.form.height().min(parent.height);
+        let area = Rect {
+            x: parent.x + (parent.width - width) / 2,
+            y: parent.y + (parent.height - height) / 2,
+            width,
+            height,
+        };
+        view_model.area = area;
+
+        self.render_widget(Clear, area);
+        self.form_view(area, style, view_model.title(), true, &view_model.form);
+    }
+}
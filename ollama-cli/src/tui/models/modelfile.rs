@@ -1,6 +1,3 @@
-use std::collections::HashMap;
-
-use modelfile::Modelfile;
 use ratatui::{
     layout::{Constraint, Layout, Rect},
     style::{Color, Style},
@@ -11,144 +8,111 @@ use ratatui::{
 use crate::error::{Error, Result};
 use crate::{lm::Response, tui::event::Action};
 
-use super::ModelEvent;
+use super::{
+    instruction::{
+        join_instructions, split_instructions, Instruction, InstructionFormViewModel, Keyword,
+    },
+    ModelEvent,
+};
+
+/// The last row of the list adds a message
+const ADD_MESSAGE: &str = "+ MESSAGE";
+const ADD_MESSAGE_HELP: &str = "press enter to add a MESSAGE to the Modelfile";
 
 #[derive(Debug, Clone, Default)]
 pub struct ModelfileViewModel {
-    modelfile: Option<Modelfile>,
-    model_info: HashMap<String, toml::Value>,
-    selected: Option<usize>,
-    instructions: Vec<String>,
-    details: Option<toml::Value>,
+    /// The instructions as they're written in the Modelfile
+    instructions: Vec<Instruction>,
     list_state: ListState,
-    active_panel: Option<Panel>,
 }
 
 impl ModelfileViewModel {
-    pub fn load(&mut self, modelfile: Modelfile) -> Result<()> {
-        let string = toml::to_string(&modelfile)?;
-        let map: HashMap<String, toml::Value> = toml::from_str(&string)?;
-
-        self.modelfile = Some(modelfile);
-        self.model_info = map;
-        let mut instructions: Vec<String> = self.model_info.keys().cloned().collect();
-        instructions.sort();
-        self.instructions = instructions;
+    /// Load a Modelfile if it can be parsed
+    pub fn load(&mut self, source: &str) -> Result<()> {
+        let _validated: modelfile::Modelfile = source.parse()?;
+        self.instructions = split_instructions(source);
+        if self.list_state.selected().is_none() {
+            self.list_state.select_first();
+        }
         Ok(())
     }
 
+    /// The Modelfile as it would be sent to Ollama
+    pub fn source(&self) -> Option<String> {
+        (!self.instructions.is_empty()).then(|| join_instructions(&self.instructions))
+    }
+
     pub fn handle_response(&mut self, response: Response) -> Result<()> {
         let Response::ModelInfo(model_info) = response else {
             return Ok(());
         };
-        let modelfile: Modelfile = model_info.modelfile.parse()?;
-        self.load(modelfile)
+        self.load(&model_info.modelfile)
     }
 
     pub fn handle_action(&mut self, action: Action) -> Result<Option<ModelEvent>> {
         match action {
             Action::Quit => Ok(Some(ModelEvent::Deactivate)),
             Action::Up => {
-                self.prev();
-                self.update_details()?;
+                self.list_state.select_previous();
                 Ok(None)
             }
             Action::Down => {
-                self.next();
-                self.update_details()?;
+                self.list_state.select_next();
                 Ok(None)
             }
-            Action::Enter => {
-                if let Some(_selected) = self.selected {
-                    self.active_panel = Some(Panel::Details);
-                    Ok(None)
+            Action::Enter | Action::Edit => {
+                let Some(selected) = self.list_state.selected() else {
+                    return Ok(None);
+                };
+                let form = if selected >= self.instructions.len() {
+                    InstructionFormViewModel::add(self.instructions.clone(), Keyword::Message)
                 } else {
-                    Ok(None)
-                }
-            }
-            Action::Left => todo!(),
-            Action::Right => todo!(),
-            Action::LeftWord => todo!(),
-            Action::RightWord => todo!(),
-            Action::Refresh => todo!(),
-            _ => todo!(),
-        }
-    }
-
-    fn next(&mut self) {
-        if let Some(selected) = self.selected {
-            if selected == self.instructions.len() - 1 {
-                self.selected = Some(0);
-            } else {
-                self.selected = Some(selected + 1);
-            }
-        } else {
-            self.selected = Some(0);
-        }
-    }
-
-    fn prev(&mut self) {
-        if let Some(selected) = self.selected {
-            if selected == 0 {
-                self.selected = Some(self.instructions.len() - 1);
-            } else {
-                self.selected = Some(selected - 1);
+                    InstructionFormViewModel::edit(self.instructions.clone(), selected)
+                        .ok_or(Error::ModelfileIndex(selected))?
+                };
+                Ok(Some(ModelEvent::EditInstruction(form)))
             }
-        } else {
-            self.selected = Some(self.instructions.len() - 1);
+            Action::Create => Ok(Some(ModelEvent::Create)),
+            _ => Ok(None),
         }
     }
 
-    fn update_details(&mut self) -> Result<()> {
-        tracing::info!(offset = self.list_state.selected(), "updating offset");
-        if let Some(selected) = self.selected {
-            let instruction = self
+    fn details(&self) -> String {
+        match self.list_state.selected() {
+            Some(selected) => self
                 .instructions
                 .get(selected)
-                .ok_or(Error::ModelfileIndex(selected))?;
-            let details = self
-                .model_info
-                .get(instruction)
-                .cloned()
-                .ok_or(Error::ModelfileMissing(instruction.to_string()))?;
-            self.details = Some(details);
-            self.list_state.select(Some(selected));
+                .map(ToString::to_string)
+                .unwrap_or_else(|| ADD_MESSAGE_HELP.to_string()),
+            None => String::new(),
         }
-        Ok(())
     }
 }
 
-#[derive(Default, Debug, Clone, Copy)]
-enum Panel {
-    #[default]
-    Instruction,
-    Details,
-}
-
 #[extend::ext(name = ModelfileView)]
 pub impl<'a> Frame<'a> {
     fn modelfile(&mut self, parent: Rect, style: Style, view_model: &mut ModelfileViewModel) {
         let [instruction_panel, detail_panel] =
             Layout::horizontal([Constraint::Min(15), Constraint::Min(2)]).areas(parent);
 
-        let instructions = List::from_iter(view_model.instructions.iter().map(|s| s.as_str()))
-            .block(Block::bordered())
-            .style(style)
-            .highlight_style(
-                style
-                    .fg(style.bg.unwrap_or(Color::Black))
-                    .bg(style.fg.unwrap_or(Color::White)),
-            );
-
-        let details = Paragraph::new(
+        let instructions = List::from_iter(
             view_model
-                .details
-                .as_ref()
-                .map(|v| v.to_string())
-                .unwrap_or_default(),
+                .instructions
+                .iter()
+                .map(Instruction::summary)
+                .chain(std::iter::once(ADD_MESSAGE.to_string())),
         )
-        .wrap(Wrap { trim: false })
-        .block(Block::bordered());
+        .block(Block::bordered())
+        .style(style)
+        .highlight_style(
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White)),
+        );
+
+        let details = Paragraph::new(view_model.details())
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered());
 
         self.render_stateful_widget(instructions, instruction_panel, &mut view_model.list_state);
         self.render_widget(details, detail_panel);
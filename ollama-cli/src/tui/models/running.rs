@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style},
+    text::Span,
+    widgets::{Block, Clear, Paragraph, Row, Table, TableState},
+    Frame,
+};
+
+use crate::{
+    error::{Error, Result},
+    lm::{Prompt, Response},
+    ollama::{running::RunningModel, ModelName},
+    tui::{
+        event::{Action, MouseAction},
+        popup::popup_area,
+        AppEvent,
+    },
+};
+
+use super::u64Ext as _;
+
+const HELP: &str = "s: unload, r: refresh, q: close";
+
+/// Popup listing the models loaded on the server
+#[derive(Debug, Clone, Default)]
+pub struct RunningModelsViewModel {
+    models: Vec<RunningModel>,
+    table_state: TableState,
+    error: Option<Arc<str>>,
+    area: Rect,
+}
+
+impl RunningModelsViewModel {
+    pub fn handle_response(&mut self, response: Response) -> Result<()> {
+        match response {
+            Response::RunningModels(models) => {
+                self.error = None;
+                self.models = models;
+                if self.models.is_empty() {
+                    self.table_state.select(None);
+                } else if self.table_state.selected().is_none() {
+                    self.table_state.select_first();
+                }
+                Ok(())
+            }
+            Response::Error(error) => {
+                self.error = Some(error);
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    fn selected_name(&self) -> Option<ModelName> {
+        let model = self
+            .table_state
+            .selected()
+            .and_then(|index| self.models.get(index))?;
+        Some(ModelName(model.name.as_str().into()))
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => {
+                self.table_state.select_previous();
+                Ok(None)
+            }
+            Action::Down => {
+                self.table_state.select_next();
+                Ok(None)
+            }
+            Action::Stop => Ok(self
+                .selected_name()
+                .map(|name| AppEvent::Submit(Prompt::Unload(name)))),
+            Action::Refresh => Ok(Some(AppEvent::Submit(Prompt::RunningModels))),
+            Action::Running | Action::Quit | Action::Escape => Ok(Some(AppEvent::Deactivate)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            MouseAction::Click(_) => Ok(None),
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+/// Show when the model expires in local time or the raw value if it can't be parsed
+fn format_expiry(expires_at: &str) -> String {
+    DateTime::parse_from_rfc3339(expires_at)
+        .map(|time| time.with_timezone(&Local).format("%T").to_string())
+        .unwrap_or_else(|_| expires_at.to_string())
+}
+
+#[extend::ext(name = RunningModelsView)]
+pub impl<'a> Frame<'a> {
+    fn running_models(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        view_model: &mut RunningModelsViewModel,
+    ) {
+        let area = popup_area(parent, 70, 50);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title("running models").style(style);
+        let [table_area, message_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(block.inner(area));
+        self.render_widget(block, area);
+
+        let rows = view_model.models.iter().map(|model| {
+            Row::new([
+                Span::from(model.name.as_str()),
+                Span::from(model.size_vram.fit_to_bytesize()),
+                Span::from(model.size.saturating_sub(model.size_vram).fit_to_bytesize()),
+                Span::from(format_expiry(&model.expires_at)),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(["name", "vram", "ram", "expires"]))
+        .style(style)
+        .highlight_style(
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White)),
+        );
+        self.render_stateful_widget(table, table_area, &mut view_model.table_state);
+
+        let message = match &view_model.error {
+            Some(error) => Paragraph::new(error.as_ref()).style(Style::default().fg(Color::Red)),
+            None => Paragraph::new(HELP).style(style),
+        };
+        self.render_widget(message, message_area);
+    }
+}
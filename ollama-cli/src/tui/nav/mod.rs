@@ -1,21 +1,47 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Local};
 use ratatui::{
-    layout::{Alignment, Rect},
+    layout::{Alignment, Constraint, Layout, Position, Rect},
     style::{Color, Style, Stylize as _},
-    text::Text,
-    widgets::{Block, List, ListState, Padding},
+    text::{Line, Span, Text},
+    widgets::{Block, List, ListState, Padding, Row, Table},
     Frame,
 };
 use strum::VariantNames;
 
-use super::{event::Action, AppEvent, ViewName};
-use crate::error::{Error, Result};
+use super::{
+    event::{Action, MouseAction},
+    AppEvent, ViewName,
+};
+use crate::{
+    backend::BackendKind,
+    bytes_size::u64Ext as _,
+    error::{Error, Result},
+    lm::{ConnectionState, Response},
+    ollama::{ModelHost, ModelName},
+    session,
+};
 
 #[derive(Clone, Debug)]
 pub struct NavViewModel {
     views: Arc<[&'static str]>,
     list_state: ListState,
+    /// Inner area of the list from the last draw, used to hit test mouse events
+    list_area: Rect,
+    dashboard: Dashboard,
+}
+
+/// Stats about the host and the TUI, refreshed while the nav view is open.
+/// Stats that haven't been fetched yet are empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Dashboard {
+    local_models: Option<usize>,
+    /// The size of all the local models as reported by the host
+    model_bytes: Option<u64>,
+    running_models: Option<usize>,
+    saved_sessions: Option<usize>,
+    refreshed_at: Option<DateTime<Local>>,
 }
 
 impl Default for NavViewModel {
@@ -28,11 +54,63 @@ impl Default for NavViewModel {
             .copied()
             .collect();
 
-        Self { views, list_state }
+        Self {
+            views,
+            list_state,
+            list_area: Rect::default(),
+            dashboard: Dashboard::default(),
+        }
     }
 }
 
 impl NavViewModel {
+    /// Count the saved sessions,
+    /// the host's stats arrive as responses to the refresh prompts
+    pub fn refresh(&mut self) {
+        self.dashboard.saved_sessions = session::count()
+            .inspect_err(|error| tracing::warn!(%error, "unable to count saved sessions"))
+            .ok();
+    }
+
+    /// Errors are ignored since some backends can't report every stat
+    pub fn handle_response(&mut self, response: Response) -> Result<()> {
+        match response {
+            Response::LocalModels(models) => {
+                self.dashboard.local_models = Some(models.len());
+                self.dashboard.model_bytes = Some(models.iter().map(|model| model.size).sum());
+                self.dashboard.refreshed_at = Some(Local::now());
+            }
+            Response::RunningModels(models) => {
+                self.dashboard.running_models = Some(models.len());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) => match self.index_at(position) {
+                Some(index) => {
+                    self.list_state.select(Some(index));
+                    self.handle_action(Action::Enter)
+                }
+                None => Ok(None),
+            },
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    /// Each entry is a single line
+    fn index_at(&self, position: Position) -> Option<usize> {
+        if !self.list_area.contains(position) {
+            return None;
+        }
+        let index = self.list_state.offset() + usize::from(position.y - self.list_area.y);
+        (index < self.views.len()).then_some(index)
+    }
+
     pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
         match action {
             Action::Up => {
@@ -62,9 +140,90 @@ impl NavViewModel {
     }
 }
 
+fn connection_line(host: &ModelHost, connection: ConnectionState) -> Line<'static> {
+    let color = match connection {
+        ConnectionState::Online => Color::Green,
+        ConnectionState::Offline => Color::Red,
+    };
+    Line::from(vec![
+        Span::from(format!(" {host} ")),
+        Span::styled(format!("{connection} "), Style::default().fg(color)),
+    ])
+}
+
+/// A stat that hasn't been fetched yet
+const UNKNOWN: &str = "-";
+
+fn show<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+/// What the app knows about the host, shown next to the dashboard's stats
+pub struct NavStatus<'a> {
+    pub host: &'a ModelHost,
+    pub backend: BackendKind,
+    pub connection: ConnectionState,
+    pub last_model: Option<&'a ModelName>,
+}
+
+impl Dashboard {
+    fn rows(&self, status: &NavStatus) -> Vec<[String; 2]> {
+        vec![
+            ["host".into(), status.host.to_string()],
+            ["backend".into(), status.backend.to_string()],
+            ["status".into(), status.connection.to_string()],
+            ["models".into(), show(self.local_models)],
+            [
+                "disk usage".into(),
+                show(self.model_bytes.map(|bytes| bytes.fit_to_bytesize())),
+            ],
+            ["loaded".into(), show(self.running_models)],
+            ["saved sessions".into(), show(self.saved_sessions)],
+            ["last model".into(), show(status.last_model)],
+            [
+                "updated".into(),
+                show(self.refreshed_at.map(|time| time.format("%T"))),
+            ],
+        ]
+    }
+}
+
 #[extend::ext(name = NavView)]
 pub impl<'a> Frame<'a> {
-    fn nav_view(&mut self, parent: Rect, style: Style, view_model: &mut NavViewModel) {
+    fn nav_view(
+        &mut self,
+        parent: Rect,
+        style: Style,
+        status: NavStatus,
+        view_model: &mut NavViewModel,
+    ) {
+        let block = Block::bordered()
+            .title("control panel")
+            .title_style(Style::default().bold().underlined().italic())
+            .title_alignment(Alignment::Center)
+            .title_bottom(connection_line(status.host, status.connection).right_aligned());
+        let [list_area, dashboard_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(block.inner(parent));
+        self.render_widget(block, parent);
+
+        let dashboard = Table::new(
+            view_model
+                .dashboard
+                .rows(&status)
+                .into_iter()
+                .map(|[name, value]| Row::new([Span::from(name).bold(), Span::from(value)])),
+            [Constraint::Length(16), Constraint::Fill(1)],
+        )
+        .style(style)
+        .block(Block::new().padding(Padding::uniform(1)));
+        self.render_widget(dashboard, dashboard_area);
+
+        let block = Block::new().padding(Padding::proportional(2));
+        view_model.list_area = block.inner(list_area);
+
         let list = List::from_iter(
             view_model
                 .views
@@ -78,14 +237,32 @@ pub impl<'a> Frame<'a> {
                 .fg(style.bg.unwrap_or(Color::Black))
                 .bg(style.fg.unwrap_or(Color::White)),
         )
-        .block(
-            Block::bordered()
-                .padding(Padding::proportional(5))
-                .title("Ollama control panel")
-                .title_style(Style::default().bold().underlined().italic())
-                .title_alignment(Alignment::Center),
-        );
-
-        self.render_stateful_widget_ref(list, parent, &mut view_model.list_state);
+        .block(block);
+
+        self.render_stateful_widget_ref(list, list_area, &mut view_model.list_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn dashboard_sums_model_sizes() {
+        let models = serde_json::from_value(serde_json::json!([
+            { "name": "a", "modified_at": "2024-10-01T00:00:00Z", "size": 1024 },
+            { "name": "b", "modified_at": "2024-10-01T00:00:00Z", "size": 2048 },
+        ]))
+        .unwrap();
+        let mut view_model = NavViewModel::default();
+        view_model
+            .handle_response(Response::LocalModels(models))
+            .unwrap();
+
+        assert_eq!(view_model.dashboard.local_models, Some(2));
+        assert_eq!(view_model.dashboard.model_bytes, Some(3072));
+        assert_eq!(view_model.dashboard.running_models, None);
     }
 }
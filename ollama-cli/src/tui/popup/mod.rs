@@ -0,0 +1,336 @@
+use std::{path::Path, sync::Arc};
+
+use itertools::Itertools;
+use ratatui::{
+    layout::{Constraint, Flex, Layout, Position, Rect},
+    style::Style,
+    widgets::{Block, Clear, Padding, Paragraph, Wrap},
+    Frame,
+};
+use strum::IntoEnumIterator as _;
+
+use crate::{
+    error::{Error, Result},
+    fs_ext::read_file_to_string,
+    tui::event::InputMode,
+};
+
+use super::{
+    chat::{AttachViewModel, BranchesViewModel},
+    event::{Action, EventProcessor, MouseAction},
+    hosts::HostsViewModel,
+    models::{
+        instruction::InstructionFormViewModel, model_name::ModelNameViewModel,
+        running::RunningModelsViewModel,
+    },
+    templates::TemplatesViewModel,
+    AppEvent,
+};
+
+pub mod save_file;
+pub mod viewer;
+
+pub use save_file::{AppFileData, SaveFileView, SaveFileViewModel};
+pub use viewer::{ViewerView, ViewerViewModel};
+
+/// Popups drawn over the current view
+/// that take all input until they're closed
+#[derive(Debug, Clone)]
+pub enum Popup {
+    Text(PopupViewModel),
+    SaveFile(SaveFileViewModel),
+    Instruction(InstructionFormViewModel),
+    ModelName(ModelNameViewModel),
+    /// Status updates from a long running task like creating a model
+    Progress(PopupViewModel),
+    Running(RunningModelsViewModel),
+    Hosts(HostsViewModel),
+    Templates(TemplatesViewModel),
+    /// Full screen output with save and copy actions
+    Viewer(ViewerViewModel),
+    Branches(BranchesViewModel),
+    Attach(AttachViewModel),
+}
+
+impl Popup {
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match self {
+            Popup::Text(view_model) => view_model.handle_action(action),
+            Popup::SaveFile(view_model) => view_model.handle_action(action),
+            Popup::Instruction(view_model) => view_model.handle_action(action),
+            Popup::ModelName(view_model) => view_model.handle_action(action),
+            Popup::Progress(view_model) => view_model.handle_action(action),
+            Popup::Running(view_model) => view_model.handle_action(action),
+            Popup::Hosts(view_model) => view_model.handle_action(action),
+            Popup::Templates(view_model) => view_model.handle_action(action),
+            Popup::Viewer(view_model) => view_model.handle_action(action),
+            Popup::Branches(view_model) => view_model.handle_action(action),
+            Popup::Attach(view_model) => view_model.handle_action(action),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match self {
+            Popup::Text(view_model) => view_model.handle_mouse(mouse),
+            Popup::SaveFile(view_model) => view_model.handle_mouse(mouse),
+            Popup::Instruction(view_model) => view_model.handle_mouse(mouse),
+            Popup::ModelName(view_model) => view_model.handle_mouse(mouse),
+            Popup::Progress(view_model) => view_model.handle_mouse(mouse),
+            Popup::Running(view_model) => view_model.handle_mouse(mouse),
+            Popup::Hosts(view_model) => view_model.handle_mouse(mouse),
+            Popup::Templates(view_model) => view_model.handle_mouse(mouse),
+            Popup::Viewer(view_model) => view_model.handle_mouse(mouse),
+            Popup::Branches(view_model) => view_model.handle_mouse(mouse),
+            Popup::Attach(view_model) => view_model.handle_mouse(mouse),
+        }
+    }
+}
+
+impl From<PopupViewModel> for Popup {
+    fn from(value: PopupViewModel) -> Self {
+        Popup::Text(value)
+    }
+}
+
+impl From<InstructionFormViewModel> for Popup {
+    fn from(value: InstructionFormViewModel) -> Self {
+        Popup::Instruction(value)
+    }
+}
+
+impl From<ModelNameViewModel> for Popup {
+    fn from(value: ModelNameViewModel) -> Self {
+        Popup::ModelName(value)
+    }
+}
+
+impl From<RunningModelsViewModel> for Popup {
+    fn from(value: RunningModelsViewModel) -> Self {
+        Popup::Running(value)
+    }
+}
+
+impl From<HostsViewModel> for Popup {
+    fn from(value: HostsViewModel) -> Self {
+        Popup::Hosts(value)
+    }
+}
+
+impl From<TemplatesViewModel> for Popup {
+    fn from(value: TemplatesViewModel) -> Self {
+        Popup::Templates(value)
+    }
+}
+
+impl From<BranchesViewModel> for Popup {
+    fn from(value: BranchesViewModel) -> Self {
+        Popup::Branches(value)
+    }
+}
+
+impl From<AttachViewModel> for Popup {
+    fn from(value: AttachViewModel) -> Self {
+        Popup::Attach(value)
+    }
+}
+
+impl From<ViewerViewModel> for Popup {
+    fn from(value: ViewerViewModel) -> Self {
+        Popup::Viewer(value)
+    }
+}
+
+impl From<SaveFileViewModel> for Popup {
+    fn from(value: SaveFileViewModel) -> Self {
+        Popup::SaveFile(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PopupViewModel {
+    title: String,
+    content: PopupContent,
+    scroll_offset: u16,
+    /// Area from the last draw, clicking outside of it closes the popup
+    area: Rect,
+}
+
+#[derive(Debug, Clone)]
+pub struct PopupContent {
+    columns: Arc<[String]>,
+}
+
+impl<S: AsRef<str>> From<S> for PopupContent {
+    fn from(value: S) -> Self {
+        PopupContent {
+            columns: [value.as_ref().to_string()].into(),
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for PopupContent {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        let columns: Arc<[String]> = iter.into_iter().map(|s| s.as_ref().into()).collect();
+
+        Self { columns }
+    }
+}
+
+impl PopupContent {
+    pub fn line_count(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|column| column.lines().count())
+            .max()
+            .expect("should have a non-zero number of columns")
+    }
+
+    /// Append a line to the last column
+    pub fn push_line(&mut self, line: &str) {
+        let mut columns = self.columns.to_vec();
+        match columns.last_mut() {
+            Some(column) if !column.is_empty() => {
+                column.push('\n');
+                column.push_str(line);
+            }
+            Some(column) => column.push_str(line),
+            None => columns.push(line.to_string()),
+        }
+        self.columns = columns.into();
+    }
+}
+
+impl PopupViewModel {
+    pub fn new(title: impl ToString, content: impl Into<PopupContent>) -> Self {
+        PopupViewModel {
+            title: title.to_string(),
+            content: content.into(),
+            scroll_offset: 0,
+            area: Rect::default(),
+        }
+    }
+
+    pub fn log_popup(log_file: impl AsRef<Path>) -> Result<Self> {
+        let logs = read_file_to_string(log_file)?;
+
+        let content = logs.lines().rev().take(10).join("\n");
+
+        Ok(PopupViewModel::new("logs".to_string(), content))
+    }
+
+    pub fn keymap_popup(event_processor: &EventProcessor) -> Self {
+        let keymaps = &event_processor.definitions.global;
+        let keymap_help: PopupContent = InputMode::iter()
+            .map(|mode| {
+                let keymap = keymaps
+                    .get(&mode)
+                    .ok_or(Error::MissingKeymap(mode))
+                    .expect("should be able to get the keymap");
+
+                std::iter::once(mode.to_string())
+                    .chain(
+                        keymap
+                            .0
+                            .iter()
+                            .map(|(key, action)| format!("{key}: {action}")),
+                    )
+                    .join("\n")
+            })
+            .collect();
+
+        PopupViewModel::new("help", keymap_help)
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        self.content.push_line(line);
+    }
+
+    fn max_scroll(&self) -> u16 {
+        (self.content.line_count())
+            .try_into()
+            .expect("should be able to fit popup content into u16")
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            MouseAction::Click(_) => Ok(None),
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => {
+                self.scroll_offset = self.scroll_offset.saturating_sub(1);
+                Ok(None)
+            }
+            Action::Down => {
+                self.scroll_offset = self.scroll_offset.saturating_add(1);
+                if self.scroll_offset > self.max_scroll() {
+                    self.scroll_offset = self.max_scroll();
+                }
+                Ok(None)
+            }
+            Action::Beginning => {
+                self.scroll_offset = 0;
+                Ok(None)
+            }
+            Action::End => {
+                self.scroll_offset = self.max_scroll();
+                Ok(None)
+            }
+            Action::Popup | Action::Help | Action::Quit | Action::Enter | Action::Escape => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+pub fn popup_area(parent: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let vertical = Layout::vertical([Constraint::Percentage(percent_y)]).flex(Flex::Center);
+    let horizontal = Layout::horizontal([Constraint::Percentage(percent_x)]).flex(Flex::Center);
+
+    let [area] = vertical.areas(parent);
+    let [area] = horizontal.areas(area);
+
+    area
+}
+
+#[extend::ext(name = PopupView)]
+pub impl<'a> Frame<'a> {
+    fn popup(&mut self, parent: Rect, style: Style, view_model: &mut PopupViewModel) {
+        let area = popup_area(parent, 60, 60);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title(view_model.title.as_str());
+
+        let layout = Layout::horizontal(
+            view_model
+                .content
+                .columns
+                .iter()
+                .map(|_| Constraint::Fill(1)),
+        );
+
+        for (i, column_area) in layout.split(area).iter().enumerate() {
+            let content = Paragraph::new(view_model.content.columns[i].as_str())
+                .wrap(Wrap { trim: true })
+                .scroll((view_model.scroll_offset, 0))
+                .block(Block::new().padding(Padding::proportional(2)))
+                .style(style);
+            self.render_widget(content, *column_area);
+        }
+
+        self.render_widget(block, area);
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style},
+    widgets::{Block, Clear, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{
+    config::outputs_dir,
+    error::Result,
+    session::{ExportFormat, Session},
+    tui::{
+        event::{Action, MouseAction},
+        input::{InputView as _, TextInputEvent, TextInputViewModel},
+        AppEvent,
+    },
+};
+
+use super::popup_area;
+
+const HELP: &str = "enter: save, q: cancel";
+
+/// Data that can be written to a file from the save popup
+#[derive(Debug, Clone)]
+pub enum AppFileData {
+    /// Exported as Markdown or as JSON if the path ends in `.json`
+    Session(Session),
+    /// Generated text, saved as is
+    Output(String),
+}
+
+impl AppFileData {
+    fn title(&self) -> &'static str {
+        match self {
+            AppFileData::Session(_) => "export chat (.md or .json)",
+            AppFileData::Output(_) => "save output",
+        }
+    }
+
+    fn default_path(&self) -> anyhow::Result<PathBuf> {
+        match self {
+            AppFileData::Session(session) => session.export_path(ExportFormat::default()),
+            AppFileData::Output(_) => {
+                let name = chrono::Local::now().format("generate_%Y-%m-%d_%H-%M-%S.md");
+                Ok(outputs_dir()?.join(name.to_string()))
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = match self {
+            AppFileData::Session(session) => session.export(ExportFormat::from_path(path))?,
+            AppFileData::Output(text) => text.clone(),
+        };
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SaveFileViewModel {
+    data: AppFileData,
+    path_input: TextInputViewModel,
+    error: Option<String>,
+    area: Rect,
+}
+
+impl SaveFileViewModel {
+    pub fn new(data: AppFileData) -> Self {
+        let path = data
+            .default_path()
+            .inspect_err(|error| tracing::warn!(%error, "unable to get default path"))
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let mut path_input = TextInputViewModel::default();
+        path_input.set_text(path);
+
+        SaveFileViewModel {
+            data,
+            path_input,
+            error: None,
+            area: Rect::default(),
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match self.path_input.handle_action(action)? {
+            Some(TextInputEvent::Submit(path)) => {
+                let path = PathBuf::from(path.as_ref());
+                match self.data.save(&path) {
+                    Ok(()) => {
+                        tracing::info!(?path, "saved file");
+                        Ok(Some(AppEvent::Deactivate))
+                    }
+                    Err(error) => {
+                        self.error = Some(error.to_string());
+                        // submitting clears the input
+                        self.path_input.input = path.display().to_string();
+                        self.path_input.cursor_position = self.path_input.input.chars().count();
+                        Ok(None)
+                    }
+                }
+            }
+            Some(TextInputEvent::InputMode(input_mode)) => {
+                Ok(Some(AppEvent::InputMode(input_mode)))
+            }
+            Some(TextInputEvent::Quit) => Ok(Some(AppEvent::Deactivate)),
+            Some(TextInputEvent::Compose(_)) | None => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = SaveFileView)]
+pub impl<'a> Frame<'a> {
+    fn save_file_popup(&mut self, parent: Rect, style: Style, view_model: &mut SaveFileViewModel) {
+        let area = popup_area(parent, 60, 30);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered()
+            .title(view_model.data.title())
+            .style(style);
+        let [input_area, message_area] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(1)]).areas(block.inner(area));
+        self.render_widget(block, area);
+
+        self.input_view(input_area, style, &view_model.path_input);
+
+        let message = match &view_model.error {
+            Some(error) => Paragraph::new(error.as_str()).style(Style::default().fg(Color::Red)),
+            None => Paragraph::new(HELP).style(style),
+        };
+        self.render_widget(message.wrap(Wrap { trim: true }), message_area);
+    }
+}
@@ -0,0 +1,101 @@
+use ratatui::{
+    layout::Rect,
+    style::Style,
+    widgets::{Block, Clear, Paragraph},
+    Frame,
+};
+
+use crate::{
+    error::Result,
+    tui::{
+        clipboard,
+        event::{Action, MouseAction},
+        scroll::FollowScroll,
+        widgets_ext::RectExt as _,
+        AppEvent,
+    },
+};
+
+use super::AppFileData;
+
+const HELP: &str = "e: save, y: copy, q: close";
+
+/// Full screen text, e.g. a generated response, that can be saved or copied
+#[derive(Debug, Clone)]
+pub struct ViewerViewModel {
+    title: String,
+    text: String,
+    scroll: FollowScroll,
+    /// The result of the last copy
+    message: Option<String>,
+}
+
+impl ViewerViewModel {
+    pub fn new(title: impl ToString, text: impl ToString) -> Self {
+        let mut scroll = FollowScroll::default();
+        scroll.top();
+        ViewerViewModel {
+            title: title.to_string(),
+            text: text.to_string(),
+            scroll,
+            message: None,
+        }
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => self.scroll.up(),
+            Action::Down => self.scroll.down(),
+            Action::Beginning => self.scroll.top(),
+            Action::End => self.scroll.latest(),
+            Action::Export => {
+                return Ok(Some(AppEvent::SaveFile(AppFileData::Output(
+                    self.text.clone(),
+                ))))
+            }
+            Action::Copy => {
+                self.message = Some(match clipboard::copy(&self.text) {
+                    Ok(()) => "copied".to_string(),
+                    Err(error) => format!("unable to copy: {error}"),
+                });
+            }
+            Action::Quit | Action::Escape | Action::Enter => return Ok(Some(AppEvent::Deactivate)),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::ScrollUp(_) => self.scroll.up(),
+            MouseAction::ScrollDown(_) => self.scroll.down(),
+            MouseAction::Click(_) => {}
+        }
+        Ok(None)
+    }
+}
+
+#[extend::ext(name = ViewerView)]
+pub impl<'a> Frame<'a> {
+    fn viewer(&mut self, parent: Rect, style: Style, view_model: &mut ViewerViewModel) {
+        self.render_widget(Clear, parent);
+
+        let lines = parent.wrap_inside(&view_model.text);
+        let offset = view_model
+            .scroll
+            .offset(lines.len(), parent.height.saturating_sub(2));
+        let help = match &view_model.message {
+            Some(message) => format!("{message} | {HELP}"),
+            None => HELP.to_string(),
+        };
+        let block = Block::bordered()
+            .title(view_model.title.as_str())
+            .title_bottom(help);
+
+        let text = Paragraph::new(lines.join("\n"))
+            .style(style)
+            .scroll((offset, 0))
+            .block(block);
+        self.render_widget(text, parent);
+    }
+}
@@ -0,0 +1,125 @@
+//! Scrolling for output that grows while it's shown, e.g. a streaming response
+
+/// Shown under output that isn't following new content
+pub const PAUSED_TITLE: &str = "scrolled back, jump to latest to follow";
+
+/// A vertical scroll offset that follows the end of the content
+/// until it's scrolled away from the bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FollowScroll {
+    offset: u16,
+    follow: bool,
+    /// The largest offset from the last draw
+    max_offset: u16,
+}
+
+impl Default for FollowScroll {
+    fn default() -> Self {
+        FollowScroll {
+            offset: 0,
+            follow: true,
+            max_offset: 0,
+        }
+    }
+}
+
+impl FollowScroll {
+    pub fn up(&mut self) {
+        self.offset = self.offset.min(self.max_offset).saturating_sub(1);
+        self.follow = false;
+    }
+
+    /// Scrolling back to the bottom follows the content again
+    pub fn down(&mut self) {
+        self.offset = self.offset.saturating_add(1).min(self.max_offset);
+        self.follow = self.offset == self.max_offset;
+    }
+
+    pub fn top(&mut self) {
+        self.offset = 0;
+        self.follow = false;
+    }
+
+    /// Jump to the end of the content and follow it
+    pub fn latest(&mut self) {
+        self.offset = self.max_offset;
+        self.follow = true;
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.follow
+    }
+
+    /// The offset to draw with for content of `content_height` lines
+    /// in a viewport of `viewport_height` lines
+    pub fn offset(&mut self, content_height: usize, viewport_height: u16) -> u16 {
+        let max_offset = content_height.saturating_sub(viewport_height.into());
+        self.max_offset = max_offset.try_into().unwrap_or(u16::MAX);
+        if self.follow {
+            self.offset = self.max_offset;
+        } else {
+            self.offset = self.offset.min(self.max_offset);
+        }
+        self.offset
+    }
+}
+
+/// The smallest change to `offset` that keeps `line` in a viewport of `height` lines,
+/// without scrolling past the end of `content_height` lines
+pub fn keep_visible(offset: usize, line: usize, content_height: usize, height: usize) -> usize {
+    let height = height.max(1);
+    let offset = offset.min(content_height.saturating_sub(height));
+    if line < offset {
+        line
+    } else if line >= offset + height {
+        line + 1 - height
+    } else {
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn follows_until_scrolled_up() {
+        let mut scroll = FollowScroll::default();
+        assert_eq!(scroll.offset(30, 10), 20);
+        assert_eq!(scroll.offset(31, 10), 21);
+
+        scroll.up();
+        assert_eq!(scroll.offset(40, 10), 20);
+        assert!(!scroll.is_following());
+
+        scroll.latest();
+        assert_eq!(scroll.offset(41, 10), 31);
+    }
+
+    #[test]
+    fn scrolling_to_the_bottom_follows_again() {
+        let mut scroll = FollowScroll::default();
+        scroll.offset(12, 10);
+        scroll.up();
+        scroll.up();
+        scroll.down();
+        assert!(!scroll.is_following());
+        scroll.down();
+        assert!(scroll.is_following());
+        assert_eq!(scroll.offset(15, 10), 5);
+    }
+
+    #[test]
+    fn keeps_a_line_visible_without_jumping() {
+        // moving down within the view doesn't scroll
+        assert_eq!(keep_visible(0, 3, 20, 5), 0);
+        assert_eq!(keep_visible(0, 5, 20, 5), 1);
+        // moving back up only scrolls at the top
+        assert_eq!(keep_visible(4, 6, 20, 5), 4);
+        assert_eq!(keep_visible(4, 2, 20, 5), 2);
+        // the content shrank
+        assert_eq!(keep_visible(10, 3, 6, 5), 1);
+    }
+}
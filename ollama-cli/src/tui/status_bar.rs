@@ -0,0 +1,149 @@
+use std::time::Instant;
+
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    Frame,
+};
+
+use crate::{
+    lm::{ConnectionState, Response},
+    ollama::{ModelHost, ModelName},
+};
+
+use super::event::InputMode;
+
+/// Tracks streaming responses so the status bar can show tokens/sec
+#[derive(Debug, Default)]
+pub struct StatusBarViewModel {
+    stream: Option<TokenStream>,
+    /// Tokens/sec of the last finished stream
+    last_rate: Option<f64>,
+}
+
+#[derive(Debug)]
+struct TokenStream {
+    started: Instant,
+    latest: Instant,
+    tokens: usize,
+}
+
+impl TokenStream {
+    fn rate(&self) -> Option<f64> {
+        let elapsed = self.latest.duration_since(self.started).as_secs_f64();
+        (elapsed > 0.0).then(|| self.tokens as f64 / elapsed)
+    }
+}
+
+impl StatusBarViewModel {
+    pub fn observe(&mut self, response: &Response) {
+        self.observe_at(response, Instant::now())
+    }
+
+    fn observe_at(&mut self, response: &Response, now: Instant) {
+        match response {
+            Response::Token(_) => {
+                let stream = self.stream.get_or_insert(TokenStream {
+                    started: now,
+                    latest: now,
+                    tokens: 0,
+                });
+                stream.latest = now;
+                stream.tokens += 1;
+            }
+            Response::Eos | Response::Cancelled | Response::Error(_) => {
+                if let Some(stream) = self.stream.take() {
+                    self.last_rate = stream.rate();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn rate(&self) -> Option<f64> {
+        self.stream
+            .as_ref()
+            .and_then(TokenStream::rate)
+            .or(self.last_rate)
+    }
+}
+
+/// Everything the status bar shows that's owned by the app
+#[derive(Debug)]
+pub struct Status<'a> {
+    pub view: &'static str,
+    pub input_mode: InputMode,
+    pub model: Option<ModelName>,
+    pub host: &'a ModelHost,
+    pub connection: ConnectionState,
+}
+
+#[extend::ext(name = StatusBarView)]
+pub impl<'a> Frame<'a> {
+    fn status_bar(
+        &mut self,
+        area: Rect,
+        style: Style,
+        status: &Status,
+        view_model: &StatusBarViewModel,
+    ) {
+        let connection_color = match status.connection {
+            ConnectionState::Online => Color::Green,
+            ConnectionState::Offline => Color::Red,
+        };
+        let stream = if view_model.is_streaming() {
+            "streaming"
+        } else {
+            "idle"
+        };
+        let rate = view_model
+            .rate()
+            .map(|rate| format!(" {rate:.1} tok/s"))
+            .unwrap_or_default();
+
+        let mut spans = vec![
+            Span::from(format!(" {} ", status.view)).style(style.fg(Color::Black).bg(Color::Cyan)),
+            Span::from(format!(" {} ", status.input_mode)),
+        ];
+        if let Some(model) = &status.model {
+            spans.push(Span::from(format!("| {model} ")));
+        }
+        spans.extend([
+            Span::from(format!("| {} ", status.host)),
+            Span::from(format!("{} ", status.connection)).style(style.fg(connection_color)),
+            Span::from(format!("| {stream}{rate}")),
+        ]);
+
+        self.render_widget(Line::from(spans).style(style), area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn rate_is_kept_after_stream_ends() {
+        let mut status = StatusBarViewModel::default();
+        let start = Instant::now();
+
+        for i in 0..=10 {
+            status.observe_at(
+                &Response::Token("a".into()),
+                start + Duration::from_millis(i * 100),
+            );
+        }
+        assert!(status.is_streaming());
+
+        status.observe_at(&Response::Eos, start + Duration::from_secs(2));
+        assert!(!status.is_streaming());
+        assert_eq!(status.rate(), Some(11.0));
+    }
+}
@@ -0,0 +1,137 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Style, Stylize as _},
+    text::{Line, Text},
+    widgets::{Block, Clear, List, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{
+    context::ContextSource,
+    error::Result,
+    templates::{self, Template, Templates},
+};
+
+use super::{
+    event::{Action, MouseAction},
+    popup::popup_area,
+    AppEvent,
+};
+
+const HELP: &str = "enter: use, q: close";
+
+/// Popup that picks a prompt template or context command
+/// and starts its slash command in the input
+#[derive(Debug, Clone)]
+pub struct TemplatesViewModel {
+    templates: Vec<(String, Template)>,
+    list_state: ListState,
+    area: Rect,
+}
+
+impl TemplatesViewModel {
+    pub fn new(templates: &Templates) -> Self {
+        TemplatesViewModel {
+            templates: templates
+                .iter()
+                .map(|(name, template)| (name.clone(), template.clone()))
+                .chain(ContextSource::HELP.map(|(name, description)| {
+                    let template = Template {
+                        description: format!("context: {description}"),
+                        prompt: "the command's output, then the rest of the line".to_string(),
+                    };
+                    (name.to_string(), template)
+                }))
+                .collect(),
+            list_state: ListState::default().with_selected(Some(0)),
+            area: Rect::default(),
+        }
+    }
+
+    fn selected(&self) -> Option<&(String, Template)> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.templates.get(index))
+    }
+
+    pub fn handle_action(&mut self, action: Action) -> Result<Option<AppEvent>> {
+        match action {
+            Action::Up => {
+                self.list_state.select_previous();
+                Ok(None)
+            }
+            Action::Down => {
+                self.list_state.select_next();
+                Ok(None)
+            }
+            Action::Enter => Ok(self
+                .selected()
+                .map(|(name, _template)| AppEvent::SetDraft(templates::command(name)))),
+            Action::Templates | Action::Quit | Action::Escape => Ok(Some(AppEvent::Deactivate)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn handle_mouse(&mut self, mouse: MouseAction) -> Result<Option<AppEvent>> {
+        match mouse {
+            MouseAction::Click(position) if !self.contains(position) => {
+                Ok(Some(AppEvent::Deactivate))
+            }
+            MouseAction::Click(_) => Ok(None),
+            MouseAction::ScrollUp(_) => self.handle_action(Action::Up),
+            MouseAction::ScrollDown(_) => self.handle_action(Action::Down),
+        }
+    }
+
+    fn contains(&self, position: Position) -> bool {
+        self.area.contains(position)
+    }
+}
+
+#[extend::ext(name = TemplatesView)]
+pub impl<'a> Frame<'a> {
+    fn templates_popup(&mut self, parent: Rect, style: Style, view_model: &mut TemplatesViewModel) {
+        let area = popup_area(parent, 70, 60);
+        view_model.area = area;
+        self.render_widget(Clear, area);
+
+        let block = Block::bordered().title("prompt templates").style(style);
+        let [main_area, help_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(block.inner(area));
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main_area);
+        self.render_widget(block, area);
+
+        let names = view_model
+            .templates
+            .iter()
+            .map(|(name, _template)| Line::from(templates::command(name)));
+        let list = List::from_iter(names).style(style).highlight_style(
+            style
+                .fg(style.bg.unwrap_or(Color::Black))
+                .bg(style.fg.unwrap_or(Color::White)),
+        );
+        self.render_stateful_widget(list, list_area, &mut view_model.list_state);
+
+        if let Some((_name, template)) = view_model.selected() {
+            let preview = Text::from_iter(
+                std::iter::once(Line::from(template.description.clone()).bold())
+                    .chain(std::iter::once(Line::default()))
+                    .chain(
+                        template
+                            .prompt
+                            .lines()
+                            .map(|line| Line::from(line.to_string())),
+                    ),
+            );
+            let preview = Paragraph::new(preview)
+                .style(style)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title("preview"));
+            self.render_widget(preview, preview_area);
+        }
+
+        self.render_widget(Paragraph::new(HELP).style(style), help_area);
+    }
+}